@@ -8,8 +8,18 @@ import {
   Message,
   HelloMessage,
   HeartbeatMessage,
+  BufferReportMessage,
+  BufferReportAckMessage,
+  RateAdjustMessage,
+  SdpOfferMessage,
+  SdpAnswerMessage,
   MediaControlMessage,
   MediaControlParams,
+  TranscodeRequestMessage,
+  ClockSyncedMessage,
+  KeyframeRequestMessage,
+  ConcealmentMessage,
+  RtpClockMessage,
 } from './types';
 
 export class SoluSyncClient extends EventEmitter {
@@ -23,6 +33,10 @@ export class SoluSyncClient extends EventEmitter {
   private heartbeatInterval?: number;
   private clockSyncInterval?: number;
   private connected: boolean = false;
+  // Issued by the server; resumes our subscriptions after a reconnect
+  private sessionToken?: string;
+  // Where the RTP timestamps of each received track lie on the network clock
+  private rtpClocks: Partial<Record<'audio' | 'video', RtpClockMessage>> = {};
 
   constructor(config: SoluSyncConfig) {
     super();
@@ -132,6 +146,42 @@ export class SoluSyncClient extends EventEmitter {
     this.send(message);
   }
 
+  // Ask for a stream in a codec we cannot decode to be transcoded to one
+  // we can (declared as a codec:<name> capability) before subscribing;
+  // an unsupported pair is answered with a media_error
+  requestTranscode(trackId: string, targetCodec: string): void {
+    const message: TranscodeRequestMessage = {
+      type: 'transcode_request',
+      header: this.createHeader(),
+      track_id: trackId,
+      target_codec: targetCodec,
+    };
+    
+    this.send(message);
+  }
+
+  // Report our audio output buffer; the server answers with the target
+  // latency to schedule playout with ('bufferTarget' event)
+  reportBuffer(
+    trackId: string,
+    underruns: number,
+    overruns: number,
+    occupancyMs: number,
+    playoutDelayMs: number
+  ): void {
+    const message: BufferReportMessage = {
+      type: 'buffer_report',
+      header: this.createHeader(),
+      track_id: trackId,
+      underruns,
+      overruns,
+      occupancy_ms: occupancyMs,
+      playout_delay_ms: playoutDelayMs,
+    };
+    
+    this.send(message);
+  }
+
   getCurrentTime(): number {
     return this.clockSync.now();
   }
@@ -140,6 +190,22 @@ export class SoluSyncClient extends EventEmitter {
     return this.clockSync.getOffset();
   }
 
+  /**
+   * Network time a received packet of the audio or video track is presented
+   * at, from its RTP timestamp (e.g. `getSynchronizationSources()[0].rtpTimestamp`)
+   */
+  rtpToNetworkTime(kind: 'audio' | 'video', rtpTimestamp: number): number | undefined {
+    const clock = this.rtpClocks[kind];
+    if (!clock) return undefined;
+    // Timestamps wrap at 2^32; take the wrap nearest the current time
+    const wrap = 2 ** 32;
+    const nearTicks = Math.round((this.clockSync.now() - clock.network_time) * clock.clock_rate);
+    let fromNear = (rtpTimestamp - clock.rtp_timestamp - nearTicks) % wrap;
+    if (fromNear < 0) fromNear += wrap;
+    if (fromNear >= wrap / 2) fromNear -= wrap;
+    return clock.network_time + (nearTicks + fromNear) / clock.clock_rate;
+  }
+
   getNetworkQuality(): NetworkQuality {
     const rtt = this.clockSync.getLastRTT();
     if (rtt < 10) return NetworkQuality.Excellent;
@@ -162,10 +228,54 @@ export class SoluSyncClient extends EventEmitter {
           this.clockSync.handleResponse(message);
           break;
           
+        case 'clock_synced': {
+          // Synchronized playback should wait for, and pause on losing, sync
+          const synced = message as ClockSyncedMessage;
+          this.emit(synced.synced ? 'clockSynced' : 'clockResync', synced.offset_ms, synced.offset_stddev_ms);
+          break;
+        }
+          
         case 'heartbeat':
           this.handleHeartbeat(message as HeartbeatMessage);
           break;
           
+        case 'buffer_report_ack': {
+          const ack = message as BufferReportAckMessage;
+          this.emit('bufferTarget', ack.track_id, ack.target_latency_ms);
+          break;
+        }
+          
+        case 'rate_adjust':
+          // Play slightly faster or slower until the buffer is back at target
+          this.emit('playbackRate', (message as RateAdjustMessage).playback_rate);
+          break;
+          
+        case 'sdp_offer':
+          this.handleOffer(message as SdpOfferMessage);
+          break;
+          
+        case 'rtp_clock': {
+          const clock = message as RtpClockMessage;
+          this.rtpClocks[clock.kind] = clock;
+          this.emit('rtpClock', clock.kind, clock.network_time);
+          break;
+        }
+          
+        case 'keyframe_request': {
+          // A producer should encode its next frame of the track as a keyframe
+          const request = message as KeyframeRequestMessage;
+          this.emit('keyframeRequest', request.track_id, request.reason);
+          break;
+        }
+          
+        case 'concealment': {
+          // Audio frames that never arrived; the decoder should conceal
+          // them rather than leave a gap
+          const gap = message as ConcealmentMessage;
+          this.emit('conceal', gap.track_id, gap.timestamp, gap.duration);
+          break;
+        }
+          
         case 'error':
           this.emit('error', message);
           break;
@@ -186,6 +296,10 @@ export class SoluSyncClient extends EventEmitter {
       capabilities: this.config.capabilities!,
       node_type: this.config.nodeType!,
       auth_token: this.config.authToken,
+      ingest_token: this.config.ingestToken,
+      session_token: this.sessionToken,
+      min_latency_ms: this.config.minLatencyMs,
+      max_acceptable_latency_ms: this.config.maxLatencyMs,
     };
     
     this.send(message);
@@ -193,9 +307,36 @@ export class SoluSyncClient extends EventEmitter {
 
   private handleHello(message: HelloMessage): void {
     console.log('Server hello received:', message);
+    this.sessionToken = message.session_token;
     this.emit('ready');
   }
 
+  // The server offers a peer connection after our hello, and restarts ICE
+  // on it when our connection degrades, e.g. after switching networks;
+  // answering lets media resume where it left off
+  private async handleOffer(message: SdpOfferMessage): Promise<void> {
+    if (!this.pc) {
+      if (message.ice_restart) {
+        return;
+      }
+      this.pc = new RTCPeerConnection({ iceServers: this.config.iceServers });
+      this.pc.ontrack = (event) => this.emit('track', event.track, event.streams);
+    }
+    try {
+      await this.pc.setRemoteDescription({ type: 'offer', sdp: message.sdp });
+      const answer = await this.pc.createAnswer();
+      await this.pc.setLocalDescription(answer);
+      const reply: SdpAnswerMessage = {
+        type: 'sdp_answer',
+        header: this.createHeader(),
+        sdp: this.pc.localDescription?.sdp ?? answer.sdp ?? '',
+      };
+      this.send(reply);
+    } catch (error) {
+      this.emit('error', error);
+    }
+  }
+
   private handleHeartbeat(message: HeartbeatMessage): void {
     if (message.server_time) {
       // Update clock offset estimate
@@ -259,8 +400,16 @@ export class SoluSyncClient extends EventEmitter {
     };
   }
 
+  // Kept across page loads: the server caches buffer tuning per node ID
   private generateNodeId(): string {
-    return 'client-' + Math.random().toString(36).substr(2, 9);
+    const key = 'solusync-node-id';
+    const stored = globalThis.localStorage?.getItem(key);
+    if (stored) {
+      return stored;
+    }
+    const nodeId = crypto.randomUUID();
+    globalThis.localStorage?.setItem(key, nodeId);
+    return nodeId;
   }
 
   private generateId(): string {
@@ -31,9 +31,13 @@ export interface SoluSyncConfig {
   nodeType?: NodeType;
   capabilities?: string[];
   authToken?: string;
+  ingestToken?: string;
   iceServers?: RTCIceServer[];
   clockSyncInterval?: number;
   futureBufferMs?: number;
+  // Override the server's buffer latency bounds for this client
+  minLatencyMs?: number;
+  maxLatencyMs?: number;
 }
 
 export interface MediaControlParams {
@@ -65,6 +69,31 @@ export interface ClockSyncResponse extends Message {
   t3: number;
 }
 
+export interface ClockSyncedMessage extends Message {
+  type: 'clock_synced';
+  header: MessageHeader;
+  synced: boolean; // false when a converged clock diverged again
+  offset_ms: number;
+  offset_stddev_ms: number;
+}
+
+export interface KeyframeRequestMessage extends Message {
+  type: 'keyframe_request';
+  header: MessageHeader;
+  track_id: string;
+  reason: 'pli' | 'fir' | 'subscribe';
+}
+
+export interface ConcealmentMessage extends Message {
+  type: 'concealment';
+  header: MessageHeader;
+  track_id: string;
+  first_sequence: number;
+  missing: number;
+  timestamp: number; // Presentation time of the first missing frame
+  duration: number; // Seconds of audio to conceal
+}
+
 export interface MediaControlMessage extends Message {
   type: 'media_control';
   header: MessageHeader;
@@ -74,11 +103,19 @@ export interface MediaControlMessage extends Message {
   params: MediaControlParams;
 }
 
+export interface TranscodeRequestMessage extends Message {
+  type: 'transcode_request';
+  header: MessageHeader;
+  track_id: string;
+  target_codec: string;
+}
+
 export interface MessageHeader {
   id: string;
   timestamp: number;
   node_id: string;
   sequence: number;
+  request_id?: string;
 }
 
 export interface HelloMessage extends Message {
@@ -88,6 +125,59 @@ export interface HelloMessage extends Message {
   capabilities: string[];
   node_type: NodeType;
   auth_token?: string;
+  // Grants the publisher role on servers that require ingest auth
+  ingest_token?: string;
+  session_token?: string;
+  min_latency_ms?: number;
+  max_acceptable_latency_ms?: number;
+}
+
+export interface BufferReportMessage extends Message {
+  type: 'buffer_report';
+  header: MessageHeader;
+  track_id: string;
+  underruns: number;
+  overruns: number;
+  occupancy_ms: number;
+  playout_delay_ms: number;
+}
+
+export interface BufferReportAckMessage extends Message {
+  type: 'buffer_report_ack';
+  header: MessageHeader;
+  track_id: string;
+  target_latency_ms: number;
+  jitter_buffer_ms: number;
+}
+
+export interface RateAdjustMessage extends Message {
+  type: 'rate_adjust';
+  header: MessageHeader;
+  playback_rate: number;
+  target_latency_ms: number;
+}
+
+export interface SdpOfferMessage extends Message {
+  type: 'sdp_offer';
+  header: MessageHeader;
+  sdp: string;
+  ice_restart: boolean;
+}
+
+export interface SdpAnswerMessage extends Message {
+  type: 'sdp_answer';
+  header: MessageHeader;
+  sdp: string;
+}
+
+export interface RtpClockMessage extends Message {
+  type: 'rtp_clock';
+  header: MessageHeader;
+  kind: 'audio' | 'video';
+  codec: string;
+  clock_rate: number;
+  rtp_timestamp: number; // Presented at network_time
+  network_time: number;
 }
 
 export interface HeartbeatMessage extends Message {
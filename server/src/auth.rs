@@ -0,0 +1,139 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{fmt, sync::Arc};
+
+/// Bearer token control API requests must present
+#[derive(Clone, PartialEq, Eq)]
+pub struct AdminToken(String);
+
+impl AdminToken {
+    /// Token from its configured value; none when blank
+    pub fn new(token: &str) -> Option<Self> {
+        let token = token.trim();
+        (!token.is_empty()).then(|| Self(token.to_string()))
+    }
+
+    /// Whether an `Authorization` header value presents this token
+    ///
+    /// Compared in constant time, so response timing does not reveal how
+    /// much of a guess was right.
+    pub fn authorizes(&self, authorization: &str) -> bool {
+        let Some((scheme, presented)) = authorization.trim().split_once(' ') else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return false;
+        }
+        let (presented, expected) = (presented.trim().as_bytes(), self.0.as_bytes());
+        presented.len() == expected.len()
+            && presented.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+// Keep the token out of logged configuration
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdminToken(..)")
+    }
+}
+
+/// Reject control API requests without the admin token
+///
+/// Everything passes when no token is configured. CORS preflights carry no
+/// credentials and are left to the CORS layer.
+pub async fn require_admin_token(
+    State(token): State<Option<Arc<AdminToken>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = token else {
+        return next.run(request).await;
+    };
+    if request.method() == Method::OPTIONS {
+        return next.run(request).await;
+    }
+
+    let authorization = request.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok());
+    if authorization.is_some_and(|authorization| token.authorizes(authorization)) {
+        return next.run(request).await;
+    }
+
+    tracing::warn!("Rejected unauthorized {} {}", request.method(), request.uri());
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        "Admin token required",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app(token: Option<&str>) -> Router {
+        let token = token.and_then(AdminToken::new).map(Arc::new);
+        let control = Router::new()
+            .route("/api/play", post(|| async { "OK" }))
+            .route_layer(middleware::from_fn_with_state(token, require_admin_token));
+        Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .route("/api/time", get(|| async { "OK" }))
+            .merge(control)
+    }
+
+    fn request(method: Method, uri: &str, authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    async fn status(app: Router, request: Request) -> StatusCode {
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_control_requests_need_the_admin_token() {
+        let app = app(Some("s3cret"));
+
+        let authorized = request(Method::POST, "/api/play", Some("Bearer s3cret"));
+        assert_eq!(status(app.clone(), authorized).await, StatusCode::OK);
+        let lowercase_scheme = request(Method::POST, "/api/play", Some("bearer s3cret"));
+        assert_eq!(status(app.clone(), lowercase_scheme).await, StatusCode::OK);
+
+        for authorization in [None, Some("Bearer wrong"), Some("Bearer s3cre"), Some("Basic s3cret"), Some("s3cret")] {
+            let response = app
+                .clone()
+                .oneshot(request(Method::POST, "/api/play", authorization))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", authorization);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        }
+
+        // Health checks and clock sync stay open
+        assert_eq!(status(app.clone(), request(Method::GET, "/health", None)).await, StatusCode::OK);
+        assert_eq!(status(app, request(Method::GET, "/api/time", None)).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_control_requests_are_open_without_a_token() {
+        assert!(AdminToken::new("  ").is_none());
+        let open = request(Method::POST, "/api/play", None);
+        assert_eq!(status(app(None), open).await, StatusCode::OK);
+        assert_eq!(format!("{:?}", AdminToken::new("s3cret")), "Some(AdminToken(..))");
+    }
+}
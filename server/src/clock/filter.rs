@@ -1,4 +1,50 @@
 use nalgebra::{Matrix2, Vector2};
+use std::collections::VecDeque;
+
+/// Latest samples the EWMA filter picks the lowest round trip among
+const EWMA_WINDOW: usize = 8;
+
+/// Weight of each new windowed offset in the EWMA filter's estimate
+const EWMA_WEIGHT: f64 = 0.25;
+
+/// Smooths the clock offsets measured with a peer into estimates of its
+/// offset and drift
+pub trait OffsetFilter: Send + Sync {
+    /// Update filter with an offset measured at `current_time` (local clock)
+    fn update_at(&mut self, measured_offset: f64, rtt: f64, current_time: f64) -> f64;
+    
+    /// Update filter with new offset measurement
+    fn update(&mut self, measured_offset: f64, rtt: f64) -> f64 {
+        self.update_at(measured_offset, rtt, crate::protocol::get_current_time())
+    }
+    
+    /// Get current drift rate estimate (seconds per second)
+    fn drift_rate(&self) -> f64;
+    
+    /// Reset the filter
+    fn reset(&mut self);
+}
+
+/// Offset filter each peer's clock is smoothed with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OffsetFilterKind {
+    /// `KalmanFilter`
+    #[default]
+    Kalman,
+    
+    /// `EwmaFilter`, for links whose round trip jitters too much for the
+    /// Kalman filter's noise model, such as mobile ones
+    Ewma,
+}
+
+impl OffsetFilterKind {
+    pub fn build(self) -> Box<dyn OffsetFilter> {
+        match self {
+            Self::Kalman => Box::new(KalmanFilter::new()),
+            Self::Ewma => Box::new(EwmaFilter::new()),
+        }
+    }
+}
 
 /// Kalman filter for smoothing clock offset measurements
 /// 
@@ -35,33 +81,6 @@ impl KalmanFilter {
         }
     }
     
-    /// Update filter with new offset measurement
-    pub fn update(&mut self, measured_offset: f64, rtt: f64) -> f64 {
-        let current_time = crate::protocol::get_current_time();
-        
-        // Adjust measurement noise based on RTT (higher RTT = more noise)
-        self.measurement_noise = 1e-4 + (rtt * rtt * 0.1).min(0.01);
-        
-        if let Some(last_time) = self.last_update {
-            let dt = current_time - last_time;
-            
-            // Predict step
-            self.predict(dt);
-            
-            // Update step
-            self.correct(measured_offset);
-        } else {
-            // First measurement - initialize state
-            self.state[0] = measured_offset;
-            self.state[1] = 0.0;
-        }
-        
-        self.last_update = Some(current_time);
-        
-        // Return filtered offset
-        self.state[0]
-    }
-    
     /// Predict step of Kalman filter
     fn predict(&mut self, dt: f64) {
         // State transition matrix
@@ -98,25 +117,107 @@ impl KalmanFilter {
         let i_minus_kh = Matrix2::identity() - k * h.transpose();
         self.covariance = i_minus_kh * self.covariance;
     }
-    
-    /// Get current offset estimate
-    pub fn offset(&self) -> f64 {
+}
+
+impl OffsetFilter for KalmanFilter {
+    fn update_at(&mut self, measured_offset: f64, rtt: f64, current_time: f64) -> f64 {
+        // Adjust measurement noise based on RTT (higher RTT = more noise)
+        self.measurement_noise = 1e-4 + (rtt * rtt * 0.1).min(0.01);
+        
+        if let Some(last_time) = self.last_update {
+            let dt = current_time - last_time;
+            
+            // Predict step
+            self.predict(dt);
+            
+            // Update step
+            self.correct(measured_offset);
+        } else {
+            // First measurement - initialize state
+            self.state[0] = measured_offset;
+            self.state[1] = 0.0;
+        }
+        
+        self.last_update = Some(current_time);
+        
+        // Return filtered offset
         self.state[0]
     }
     
-    /// Get current drift rate estimate (seconds per second)
-    pub fn drift_rate(&self) -> f64 {
+    fn drift_rate(&self) -> f64 {
         self.state[1]
     }
     
-    /// Reset the filter
-    pub fn reset(&mut self) {
+    fn reset(&mut self) {
         self.state = Vector2::zeros();
         self.covariance = Matrix2::identity() * 1.0;
         self.last_update = None;
     }
 }
 
+/// Offset filter taking the sample with the lowest round trip of the last
+/// `EWMA_WINDOW`, smoothed by an exponentially weighted moving average
+///
+/// The sample that queued least on the way is the one whose offset is
+/// least skewed by asymmetric delay, so spikes of a jittery link are passed
+/// over rather than averaged in. Drift is the moving average of the
+/// estimate's change per second.
+#[derive(Debug, Default)]
+pub struct EwmaFilter {
+    /// Latest samples as (offset, rtt)
+    window: VecDeque<(f64, f64)>,
+    offset: f64,
+    drift_rate: f64,
+    last_update: Option<f64>,
+}
+
+impl EwmaFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl OffsetFilter for EwmaFilter {
+    fn update_at(&mut self, measured_offset: f64, rtt: f64, current_time: f64) -> f64 {
+        if self.window.len() == EWMA_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back((measured_offset, rtt));
+        
+        // The latest of the samples with the lowest round trip
+        let (windowed, _) = self
+            .window
+            .iter()
+            .rev()
+            .copied()
+            .reduce(|best, sample| if sample.1 < best.1 { sample } else { best })
+            .unwrap_or((measured_offset, rtt));
+        
+        let Some(last_time) = self.last_update else {
+            self.offset = windowed;
+            self.last_update = Some(current_time);
+            return self.offset;
+        };
+        let previous = self.offset;
+        self.offset += EWMA_WEIGHT * (windowed - self.offset);
+        let dt = current_time - last_time;
+        if dt > 0.0 {
+            let drift = (self.offset - previous) / dt;
+            self.drift_rate += EWMA_WEIGHT * (drift - self.drift_rate);
+        }
+        self.last_update = Some(current_time);
+        self.offset
+    }
+    
+    fn drift_rate(&self) -> f64 {
+        self.drift_rate
+    }
+    
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -155,10 +256,58 @@ mod tests {
             let true_offset = base_offset + drift_rate * time;
             let measurement = true_offset + (i as f64 * 0.0001); // Small noise
             
-            filter.update(measurement, 0.01);
+            filter.update_at(measurement, 0.01, time);
         }
         
         // Filter should estimate drift rate
         assert!((filter.drift_rate() - drift_rate).abs() < 0.0005);
     }
+    
+    #[test]
+    fn test_offset_filters_converge_and_reset_through_the_trait() {
+        for kind in [OffsetFilterKind::Kalman, OffsetFilterKind::Ewma] {
+            let mut filter = kind.build();
+            
+            // Noise around an offset of 0.1s, with a sample that queued
+            // 80ms on one leg every so often
+            let mut offset = 0.0;
+            for i in 0..40 {
+                let time = i as f64;
+                let (noise, rtt) = match i % 5 {
+                    4 => (0.04, 0.09),
+                    _ => ([-0.002, 0.001, 0.002, -0.001][i % 4], 0.01),
+                };
+                offset = filter.update_at(0.1 + noise, rtt, time);
+            }
+            assert!((offset - 0.1).abs() < 0.01, "{:?}: {}", kind, offset);
+            
+            // A steady drift of 1ms per second is followed
+            filter.reset();
+            for i in 0..60 {
+                let time = 100.0 + i as f64;
+                offset = filter.update_at(0.2 + 0.001 * i as f64, 0.01, time);
+            }
+            assert!((filter.drift_rate() - 0.001).abs() < 0.0005, "{:?}: {}", kind, filter.drift_rate());
+            assert!((offset - 0.259).abs() < 0.005, "{:?}: {}", kind, offset);
+            
+            filter.reset();
+            assert_eq!(filter.update_at(0.05, 0.01, 0.0), 0.05);
+        }
+    }
+    
+    #[test]
+    fn test_ewma_filter_passes_over_delayed_samples() {
+        let mut filter = EwmaFilter::new();
+        filter.update_at(0.1, 0.01, 0.0);
+        // Samples that queued on the way are skewed by half the extra
+        // delay, and lose to the quick one in the window
+        for i in 1..EWMA_WINDOW {
+            filter.update_at(0.1 + 0.05, 0.11, i as f64);
+        }
+        assert!((filter.offset - 0.1).abs() < 1e-9);
+        
+        // Once the quick sample leaves the window the slow ones are all
+        // there is
+        assert!(filter.update_at(0.15, 0.11, EWMA_WINDOW as f64) > 0.1);
+    }
 }
\ No newline at end of file
@@ -1,17 +1,20 @@
 use anyhow::Result;
+use parking_lot::Mutex;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use crate::{config::ServerConfig, health::HealthState};
+
 mod filter;
 mod sync;
 
-pub use filter::KalmanFilter;
+pub use filter::{OffsetFilter, OffsetFilterKind};
 pub use sync::{ClockSample, ClockSync};
 
 /// Manages clock synchronization for all connected nodes
@@ -22,18 +25,138 @@ pub struct ClockManager {
     /// Clock synchronization state for each peer
     peers: Arc<RwLock<HashMap<Uuid, PeerClock>>>,
     
-    /// Master clock offset (if we're not the master)
-    master_offset: Arc<RwLock<Option<f64>>>,
+    /// Master clock offset and drift (if we're not the master)
+    master: Arc<RwLock<Option<MasterClock>>>,
+    
+    /// Peer elected master, whose clock samples set the master clock
+    master_peer: Mutex<Option<Uuid>>,
+    
+    /// Fastest rate at which a change of master offset is applied, in
+    /// seconds per second; zero applies changes immediately
+    max_slew_rate: f64,
+    
+    /// Filter each peer's offset samples are smoothed with
+    filter_kind: OffsetFilterKind,
     
     /// Channel for clock sync samples
     sample_tx: mpsc::Sender<(Uuid, ClockSample)>,
-    sample_rx: Arc<RwLock<mpsc::Receiver<(Uuid, ClockSample)>>>,
+    
+    /// Receiving end of the sample channel, taken by the run task on startup
+    sample_rx: Mutex<Option<mpsc::Receiver<(Uuid, ClockSample)>>>,
+    
+    /// Peers whose offset converged or diverged again
+    convergence_events: broadcast::Sender<ClockConvergence>,
+}
+
+/// Longest time the master drift is extrapolated without a new update
+///
+/// Matches the stale peer threshold: past this the estimate is no longer
+/// being refreshed and extrapolating further only adds error.
+const MAX_PREDICTION_SECS: f64 = 30.0;
+
+/// Latest offset samples of a peer whose spread decides whether it has
+/// converged
+const CONVERGENCE_WINDOW: usize = 8;
+
+/// Largest standard deviation of the windowed offset samples for a peer to
+/// converge, in seconds
+const CONVERGED_OFFSET_STDDEV: f64 = 0.005;
+
+/// Standard deviation past which a converged peer counts as diverged, in
+/// seconds; higher than `CONVERGED_OFFSET_STDDEV` so a peer near the
+/// threshold does not flap
+const DIVERGED_OFFSET_STDDEV: f64 = 0.010;
+
+/// A peer's clock offset converging, or diverging after it had
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockConvergence {
+    pub peer_id: Uuid,
+    
+    /// Whether the offset is now converged
+    pub synced: bool,
+    
+    /// Filtered offset in seconds
+    pub offset: f64,
+    
+    /// Standard deviation of the windowed offset samples in seconds
+    pub offset_stddev: f64,
+}
+
+/// Clock synchronization statistics of one peer
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct PeerClockStats {
+    /// Filtered offset in seconds
+    pub offset: f64,
+    
+    /// Round trip of the latest sample in seconds
+    pub rtt: f64,
+    pub sample_count: u64,
+    
+    /// Whether the offset has converged closely enough for synchronized
+    /// playback
+    pub synced: bool,
+    
+    /// Standard deviation of the windowed offset samples in seconds, once
+    /// the window is full
+    pub offset_stddev: Option<f64>,
+}
+
+/// Clock synchronization health
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClockHealth {
+    /// `down` until the sample processing task runs, `degraded` while any
+    /// peer has not converged
+    pub state: HealthState,
+    pub peer_count: usize,
+    pub converged_peers: usize,
+    
+    /// Whether every peer's offset estimate has converged
+    pub converged: bool,
+    
+    /// Largest absolute peer offset in seconds
+    pub max_offset: f64,
+    
+    /// Whether a master clock is being followed
+    pub following_master: bool,
+}
+
+/// Offset of the master clock from local time
+#[derive(Debug, Clone, Copy)]
+struct MasterClock {
+    /// Offset in seconds at `updated_at`
+    offset: f64,
+    
+    /// Change of the offset in seconds per second
+    drift_rate: f64,
+    
+    /// Local time of the last update
+    updated_at: f64,
+    
+    /// Difference between the previously applied offset and `offset` at
+    /// `updated_at`, slewed away at `slew_rate`
+    correction: f64,
+    
+    /// Rate at which `correction` shrinks, in seconds per second
+    slew_rate: f64,
+}
+
+impl MasterClock {
+    /// Offset applied at `local_time`
+    ///
+    /// Predicted from the drift since the last update, plus whatever part of
+    /// the step from the previous offset has not been slewed away yet.
+    fn offset_at(&self, local_time: f64) -> f64 {
+        let elapsed = (local_time - self.updated_at).max(0.0);
+        let predicted = self.offset + self.drift_rate * elapsed.min(MAX_PREDICTION_SECS);
+        let remaining = (self.correction.abs() - self.slew_rate * elapsed).max(0.0);
+        predicted + remaining.copysign(self.correction)
+    }
 }
 
 /// Clock state for a single peer
 struct PeerClock {
-    /// Kalman filter for smoothing clock offset
-    filter: KalmanFilter,
+    /// Filter for smoothing clock offset
+    filter: Box<dyn OffsetFilter>,
     
     /// Last known offset in seconds
     offset: f64,
@@ -49,55 +172,186 @@ struct PeerClock {
     
     /// Clock drift rate (ppm)
     drift_ppm: f64,
+    
+    /// Latest measured offsets, up to `CONVERGENCE_WINDOW`
+    recent_offsets: VecDeque<f64>,
+    
+    /// Whether the offset has converged and not diverged since
+    synced: bool,
+}
+
+impl PeerClock {
+    fn is_converged(&self) -> bool {
+        self.synced
+    }
+    
+    /// Standard deviation of the windowed offsets, once the window is full
+    fn offset_stddev(&self) -> Option<f64> {
+        if self.recent_offsets.len() < CONVERGENCE_WINDOW {
+            return None;
+        }
+        let count = self.recent_offsets.len() as f64;
+        let mean = self.recent_offsets.iter().sum::<f64>() / count;
+        let variance = self.recent_offsets.iter().map(|o| (o - mean).powi(2)).sum::<f64>() / count;
+        Some(variance.sqrt())
+    }
+    
+    /// Take in a measured offset, returning whether the peer is synced if
+    /// that changed
+    fn update_convergence(&mut self, offset: f64) -> Option<bool> {
+        if self.recent_offsets.len() == CONVERGENCE_WINDOW {
+            self.recent_offsets.pop_front();
+        }
+        self.recent_offsets.push_back(offset);
+        
+        let stddev = self.offset_stddev()?;
+        let synced = if self.synced {
+            stddev <= DIVERGED_OFFSET_STDDEV
+        } else {
+            stddev <= CONVERGED_OFFSET_STDDEV
+        };
+        (synced != self.synced).then(|| {
+            self.synced = synced;
+            synced
+        })
+    }
+    
+    fn stats(&self) -> PeerClockStats {
+        PeerClockStats {
+            offset: self.offset,
+            rtt: self.rtt,
+            sample_count: self.sample_count,
+            synced: self.synced,
+            offset_stddev: self.offset_stddev(),
+        }
+    }
 }
 
 impl ClockManager {
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_config(&ServerConfig::default())
+    }
+    
+    pub fn with_config(config: &ServerConfig) -> Self {
         let (tx, rx) = mpsc::channel(1000);
         
         Self {
             node_id: Uuid::new_v4(),
             peers: Arc::new(RwLock::new(HashMap::new())),
-            master_offset: Arc::new(RwLock::new(None)),
+            master: Arc::new(RwLock::new(None)),
+            master_peer: Mutex::new(None),
+            max_slew_rate: config.max_clock_slew_ppm.max(0.0) / 1e6,
+            filter_kind: config.clock_filter,
             sample_tx: tx,
-            sample_rx: Arc::new(RwLock::new(rx)),
+            sample_rx: Mutex::new(Some(rx)),
+            convergence_events: broadcast::channel(100).0,
         }
     }
     
+    /// Subscribe to peers converging and diverging
+    pub fn subscribe_convergence(&self) -> broadcast::Receiver<ClockConvergence> {
+        self.convergence_events.subscribe()
+    }
+    
     /// Get current synchronized time
+    ///
+    /// When following a master, its offset is corrected for the drift since
+    /// the last sync update.
     pub async fn now(&self) -> f64 {
         let local_time = crate::protocol::get_current_time();
         
         // Apply master offset if we're not the master
-        if let Some(offset) = *self.master_offset.read().await {
-            local_time + offset
+        if let Some(master) = *self.master.read().await {
+            local_time + master.offset_at(local_time)
         } else {
             local_time
         }
     }
     
+    /// Follow a master clock at `offset` seconds from local time that drifts
+    /// by `drift_rate` seconds per second
+    ///
+    /// When already following a master, the change from the offset applied
+    /// so far is slewed in at the configured rate instead of stepping time.
+    pub async fn set_master_clock(&self, offset: f64, drift_rate: f64) {
+        let local_time = crate::protocol::get_current_time();
+        let mut master = self.master.write().await;
+        
+        let correction = match *master {
+            Some(previous) if self.max_slew_rate > 0.0 => previous.offset_at(local_time) - offset,
+            _ => 0.0,
+        };
+        *master = Some(MasterClock {
+            offset,
+            drift_rate,
+            updated_at: local_time,
+            correction,
+            slew_rate: self.max_slew_rate,
+        });
+    }
+    
+    /// Follow the clock of `peer_id` as the master, or of no peer
+    ///
+    /// The master clock is then set from that peer's samples. Without a
+    /// master peer the last master clock keeps being followed, so time does
+    /// not jump back while a new master is elected.
+    pub fn set_master_peer(&self, peer_id: Option<Uuid>) {
+        let mut master_peer = self.master_peer.lock();
+        if *master_peer != peer_id {
+            info!("Master clock peer: {:?}", peer_id);
+            *master_peer = peer_id;
+        }
+    }
+    
     /// Submit a clock sample from a peer
     pub async fn add_sample(&self, peer_id: Uuid, sample: ClockSample) -> Result<()> {
         self.sample_tx.send((peer_id, sample)).await?;
         Ok(())
     }
     
-    /// Get clock offset for a specific peer
-    pub async fn get_peer_offset(&self, peer_id: &Uuid) -> Option<f64> {
-        self.peers.read().await.get(peer_id).map(|p| p.offset)
+    /// Get network statistics for a peer
+    pub async fn get_peer_stats(&self, peer_id: &Uuid) -> Option<PeerClockStats> {
+        self.peers.read().await.get(peer_id).map(PeerClock::stats)
     }
     
-    /// Get network statistics for a peer
-    pub async fn get_peer_stats(&self, peer_id: &Uuid) -> Option<(f64, f64, u64)> {
-        self.peers.read().await.get(peer_id).map(|p| {
-            (p.offset, p.rtt, p.sample_count)
-        })
+    /// Peer convergence and sample processing health
+    pub async fn health(&self) -> ClockHealth {
+        let running = self.sample_rx.lock().is_none();
+        let peers = self.peers.read().await;
+        let converged_peers = peers.values().filter(|p| p.is_converged()).count();
+        let converged = converged_peers == peers.len();
+        
+        let state = if !running {
+            HealthState::Down
+        } else if !converged {
+            HealthState::Degraded
+        } else {
+            HealthState::Ok
+        };
+        
+        ClockHealth {
+            state,
+            peer_count: peers.len(),
+            converged_peers,
+            converged,
+            max_offset: peers.values().map(|p| p.offset.abs()).fold(0.0, f64::max),
+            following_master: self.master.read().await.is_some(),
+        }
     }
     
     /// Run the clock manager background task
+    ///
+    /// The sample receiver is owned by this task, so samples are processed
+    /// without holding any lock while waiting for the next one.
     pub async fn run(self: Arc<Self>) {
         info!("Clock manager started for node {}", self.node_id);
         
+        let mut sample_rx = self
+            .sample_rx
+            .lock()
+            .take()
+            .expect("Clock manager is already running");
         let mut maintenance_interval = tokio::time::interval(Duration::from_secs(10));
         
         loop {
@@ -106,23 +360,12 @@ impl ClockManager {
                     self.cleanup_stale_peers().await;
                 }
                 
-                _ = self.process_samples() => {}
-            }
-        }
-    }
-    
-    /// Process incoming clock samples
-    async fn process_samples(&self) {
-        loop {
-            let sample = {
-                let mut rx = self.sample_rx.write().await;
-                rx.recv().await
-            };
-            
-            if let Some((peer_id, sample)) = sample {
-                self.update_peer_clock(peer_id, sample).await;
-            } else {
-                break;
+                sample = sample_rx.recv() => {
+                    match sample {
+                        Some((peer_id, sample)) => self.update_peer_clock(peer_id, sample).await,
+                        None => break,
+                    }
+                }
             }
         }
     }
@@ -131,19 +374,22 @@ impl ClockManager {
     async fn update_peer_clock(&self, peer_id: Uuid, sample: ClockSample) {
         let mut peers = self.peers.write().await;
         
+        let filter_kind = self.filter_kind;
         let peer = peers.entry(peer_id).or_insert_with(|| {
             info!("New peer clock: {}", peer_id);
             PeerClock {
-                filter: KalmanFilter::new(),
+                filter: filter_kind.build(),
                 offset: 0.0,
                 rtt: 0.0,
                 last_update: Instant::now(),
                 sample_count: 0,
                 drift_ppm: 0.0,
+                recent_offsets: VecDeque::with_capacity(CONVERGENCE_WINDOW),
+                synced: false,
             }
         });
         
-        // Update Kalman filter with new sample
+        // Update the offset filter with new sample
         let filtered_offset = peer.filter.update(sample.offset, sample.rtt);
         
         // Calculate drift if we have enough samples
@@ -157,6 +403,28 @@ impl ClockManager {
         peer.rtt = sample.rtt;
         peer.last_update = Instant::now();
         peer.sample_count += 1;
+        if let Some(synced) = peer.update_convergence(sample.offset) {
+            let event = ClockConvergence {
+                peer_id,
+                synced,
+                offset: peer.offset,
+                offset_stddev: peer.offset_stddev().unwrap_or_default(),
+            };
+            if synced {
+                info!("Clock of {} converged at {:.3}ms", peer_id, event.offset * 1000.0);
+            } else {
+                warn!(
+                    "Clock of {} diverged (offset stddev {:.3}ms)",
+                    peer_id,
+                    event.offset_stddev * 1000.0
+                );
+                // The filter starts over from the next samples, rather than
+                // being dragged off the offset it had settled on
+                peer.filter.reset();
+            }
+            // No subscribers just means nobody is listening yet
+            let _ = self.convergence_events.send(event);
+        }
         
         debug!(
             "Clock update for {}: offset={:.3}ms, rtt={:.3}ms, drift={:.1}ppm",
@@ -168,14 +436,19 @@ impl ClockManager {
         
         // If this is our master, update our offset
         if self.is_master_peer(&peer_id) {
-            *self.master_offset.write().await = Some(filtered_offset);
+            self.set_master_clock(filtered_offset, peer.filter.drift_rate()).await;
         }
     }
     
+    /// Peer whose clock is followed as the master
+    #[cfg(test)]
+    pub fn master_peer(&self) -> Option<Uuid> {
+        *self.master_peer.lock()
+    }
+    
     /// Check if a peer is our master
-    fn is_master_peer(&self, _peer_id: &Uuid) -> bool {
-        // TODO: Implement master selection logic
-        false
+    fn is_master_peer(&self, peer_id: &Uuid) -> bool {
+        *self.master_peer.lock() == Some(*peer_id)
     }
     
     /// Remove stale peer entries
@@ -191,4 +464,191 @@ impl ClockManager {
             !is_stale
         });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrent_samples_are_processed() {
+        let manager = Arc::new(ClockManager::new());
+        tokio::spawn(manager.clone().run());
+        
+        let peers: Vec<Uuid> = (0..10).map(|_| Uuid::new_v4()).collect();
+        let mut tasks = Vec::new();
+        for &peer_id in &peers {
+            for _ in 0..50 {
+                let manager = manager.clone();
+                tasks.push(tokio::spawn(async move {
+                    let sample = ClockSample {
+                        offset: 0.001,
+                        rtt: 0.01,
+                    };
+                    manager.add_sample(peer_id, sample).await.unwrap();
+                }));
+            }
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        
+        let processed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let mut total = 0;
+                for peer_id in &peers {
+                    if let Some(stats) = manager.get_peer_stats(peer_id).await {
+                        total += stats.sample_count;
+                    }
+                }
+                if total == 500 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        
+        assert!(processed.is_ok(), "Samples were not all processed");
+    }
+
+    #[tokio::test]
+    async fn test_only_the_master_peer_sets_the_master_clock() {
+        let manager = Arc::new(ClockManager::new());
+        tokio::spawn(manager.clone().run());
+        let (master, other) = (Uuid::new_v4(), Uuid::new_v4());
+        manager.set_master_peer(Some(master));
+        
+        let sample = ClockSample { offset: 0.25, rtt: 0.01 };
+        manager.add_sample(other, sample).await.unwrap();
+        manager.add_sample(master, sample).await.unwrap();
+        let followed = tokio::time::timeout(Duration::from_secs(5), async {
+            while manager.master.read().await.is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(followed.is_ok(), "Master clock was not set");
+        assert_eq!(manager.get_peer_stats(&other).await.unwrap().sample_count, 1);
+        let offset = manager.master.read().await.unwrap().offset;
+        assert!((offset - 0.25).abs() < 1e-9, "offset {}", offset);
+        
+        // Losing the master keeps its clock rather than jumping back
+        manager.set_master_peer(None);
+        manager.add_sample(master, ClockSample { offset: 0.5, rtt: 0.01 }).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while manager.get_peer_stats(&master).await.unwrap().sample_count < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(manager.master.read().await.unwrap().offset, offset);
+        assert!(manager.health().await.following_master);
+    }
+
+    #[tokio::test]
+    async fn test_now_follows_master_drift() {
+        let manager = ClockManager::new();
+        let drift_rate = 0.01;
+        manager.set_master_clock(0.5, drift_rate).await;
+        
+        let start = crate::protocol::get_current_time();
+        let synced_start = manager.now().await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let synced_end = manager.now().await;
+        let end = crate::protocol::get_current_time();
+        
+        // The synchronized clock gains drift_rate seconds per local second
+        let local_elapsed = end - start;
+        let gained = (synced_end - synced_start) - local_elapsed;
+        assert!((gained - drift_rate * local_elapsed).abs() < 1e-3, "gained {}", gained);
+        assert!(synced_start - start >= 0.5);
+    }
+    
+    #[test]
+    fn test_master_drift_prediction_is_capped() {
+        let master = MasterClock {
+            offset: 0.5,
+            drift_rate: 0.001,
+            updated_at: 100.0,
+            correction: 0.0,
+            slew_rate: 0.0,
+        };
+        
+        assert_eq!(master.offset_at(100.0), 0.5);
+        assert!((master.offset_at(110.0) - 0.51).abs() < 1e-9);
+        assert_eq!(master.offset_at(100.0 + MAX_PREDICTION_SECS), master.offset_at(1e6));
+        assert_eq!(master.offset_at(50.0), 0.5);
+    }
+    
+    #[tokio::test]
+    async fn test_offset_change_is_slewed() {
+        let config = ServerConfig {
+            max_clock_slew_ppm: 5000.0,
+            ..Default::default()
+        };
+        let manager = ClockManager::with_config(&config);
+        manager.set_master_clock(0.0, 0.0).await;
+        manager.set_master_clock(0.02, 0.0).await;
+        let master = manager.master.read().await.unwrap();
+        
+        // Sample the applied offset every 10ms for five seconds
+        let interval = 0.01;
+        let max_step = 0.005 * interval;
+        let mut previous = master.offset_at(master.updated_at);
+        assert!(previous.abs() < 1e-6, "stepped to {}", previous);
+        for i in 1..=500 {
+            let offset = master.offset_at(master.updated_at + i as f64 * interval);
+            assert!((offset - previous).abs() <= max_step + 1e-6, "step {} at {}", offset - previous, i);
+            assert!(offset <= 0.02 + 1e-6);
+            previous = offset;
+        }
+        assert!((previous - 0.02).abs() < 1e-6);
+        
+        // The whole 20ms correction takes 4s at 5ms per second
+        assert!(master.offset_at(master.updated_at + 2.0) < 0.015);
+    }
+    
+    #[tokio::test]
+    async fn test_stable_samples_converge_once() {
+        let manager = ClockManager::new();
+        let mut events = manager.subscribe_convergence();
+        let peer_id = Uuid::new_v4();
+        let sample = |offset: f64| ClockSample { offset, rtt: 0.01 };
+        
+        // Offsets jittering by half a millisecond converge once the window
+        // fills, and only once however long they stay stable
+        for i in 0..50 {
+            let jitter = if i % 2 == 0 { 0.0005 } else { -0.0005 };
+            manager.update_peer_clock(peer_id, sample(0.02 + jitter)).await;
+            let stats = manager.get_peer_stats(&peer_id).await.unwrap();
+            assert_eq!(stats.synced, i + 1 >= CONVERGENCE_WINDOW, "sample {}", i);
+        }
+        let synced = events.try_recv().unwrap();
+        assert_eq!(synced.peer_id, peer_id);
+        assert!(synced.synced);
+        assert!(synced.offset_stddev <= CONVERGED_OFFSET_STDDEV);
+        assert!(events.try_recv().is_err());
+        
+        // Offsets swinging by 50ms diverge it, once
+        let mut resync = None;
+        for i in 50..60 {
+            let swing = if i % 2 == 0 { 0.05 } else { -0.05 };
+            manager.update_peer_clock(peer_id, sample(0.02 + swing)).await;
+            resync = resync.or_else(|| events.try_recv().ok());
+            if resync.is_some() {
+                break;
+            }
+        }
+        let resync = resync.unwrap();
+        assert!(!resync.synced);
+        assert!(resync.offset_stddev > DIVERGED_OFFSET_STDDEV);
+        assert!(!manager.get_peer_stats(&peer_id).await.unwrap().synced);
+        
+        // Its filter starts over from the next sample
+        manager.update_peer_clock(peer_id, sample(0.3)).await;
+        assert_eq!(manager.get_peer_stats(&peer_id).await.unwrap().offset, 0.3);
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -8,9 +8,6 @@ pub struct ClockSample {
     
     /// Round-trip time in seconds
     pub rtt: f64,
-    
-    /// Timestamp when sample was taken
-    pub timestamp: f64,
 }
 
 /// Clock synchronization algorithm (PTP-inspired)
@@ -36,11 +33,7 @@ impl ClockSync {
         let rtt = (t4 - t1) - (t3 - t2);
         let offset = ((t2 - t1) + (t3 - t4)) / 2.0;
         
-        ClockSample {
-            offset,
-            rtt,
-            timestamp: t4,
-        }
+        ClockSample { offset, rtt }
     }
     
     /// Create a sync response from a sync request
@@ -54,15 +47,6 @@ impl ClockSync {
             t3: get_current_time(), // Will be slightly after t2
         }
     }
-    
-    /// Process a sync response to get clock sample
-    pub fn process_response(
-        original_t1: f64,
-        response: &ClockSyncResponse,
-    ) -> ClockSample {
-        let t4 = get_current_time();
-        Self::calculate_offset(original_t1, response.t2, response.t3, t4)
-    }
 }
 
 #[cfg(test)]
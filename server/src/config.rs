@@ -0,0 +1,470 @@
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    auth::AdminToken,
+    clock::OffsetFilterKind,
+    control::{BroadcastPolicy, CapabilityMap, DemotionPolicy, ElectionWeights},
+    cors::CorsConfig,
+    media::{BufferPolicy, IceConfig, JitterMode, LossRecoveryConfig, PortRange, UdpPortConfig, WebRtcCodec},
+    tls::TlsConfig,
+};
+
+/// Server configuration, read from `SOLUSYNC_*` environment variables
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Directory where uploaded media files are stored
+    pub media_dir: PathBuf,
+
+    /// File the track catalog and programs are saved to; nothing is
+    /// persisted when unset
+    pub state_file: Option<PathBuf>,
+
+    /// Directory stream frame captures are written to and replayed from
+    pub capture_dir: PathBuf,
+
+    /// PEM file of the DTLS certificate all peer connections present,
+    /// generated when missing; each connection generates its own when
+    /// unset
+    pub dtls_certificate: Option<PathBuf>,
+
+    /// Size in bytes past which a capture continues in a new segment file
+    pub capture_segment_bytes: u64,
+
+    /// How long a stopped catalog stream may go without subscribers before
+    /// it is torn down, in milliseconds; 0 keeps idle streams
+    pub idle_stream_grace_ms: u64,
+
+    /// How long a client's peer connection may stay disconnected before ICE
+    /// is restarted, and each restart has to connect again, in milliseconds
+    pub peer_disconnect_grace_ms: u64,
+
+    /// ICE restarts offered to a client whose peer connection degrades
+    /// before its media is torn down; the count starts over once it connects
+    pub max_ice_restarts: u32,
+
+    /// Directory of the web client's static files
+    pub static_dir: PathBuf,
+
+    /// Maximum accepted upload size in bytes
+    pub max_upload_bytes: u64,
+
+    /// Messages queued for each client's WebSocket before it counts as
+    /// overflowing
+    pub client_queue_size: usize,
+
+    /// Frames a video subscription's forwarder handles back to back before
+    /// yielding, so audio forwarders run in between; 0 never yields
+    pub video_forwarder_budget: usize,
+
+    /// How long a disconnected client's subscriptions and buffer state are
+    /// kept for it to resume with its session token, in milliseconds; 0
+    /// disables resuming
+    pub session_resume_ms: u64,
+
+    /// How long the buffer tuning of a disconnected device is kept to
+    /// seed its next connection, in milliseconds; 0 disables the cache
+    pub device_tuning_max_age_ms: u64,
+
+    /// How broadcasts treat clients with a full send queue
+    pub broadcast_policy: BroadcastPolicy,
+
+    /// Maximum size in bytes of an incoming control message
+    pub max_message_bytes: usize,
+
+    /// Maximum size in bytes of an incoming `media_data` message
+    pub max_media_message_bytes: usize,
+
+    /// Capabilities clients must advertise to perform gated operations
+    pub required_capabilities: CapabilityMap,
+
+    /// Weights of the components master candidates are ranked by
+    pub election_weights: ElectionWeights,
+
+    /// Limits past which the leading master candidate is demoted
+    pub master_demotion: DemotionPolicy,
+
+    /// Silence between consecutive play queue items, in milliseconds
+    pub queue_gap_ms: u64,
+
+    /// Overlap between consecutive play queue items, in milliseconds;
+    /// replaces the gap when set
+    pub queue_crossfade_ms: u64,
+
+    /// Certificate and key for HTTPS/WSS; plain HTTP/WS when unset
+    pub tls: Option<TlsConfig>,
+
+    /// Origins allowed to call the API from other sites
+    pub cors: CorsConfig,
+
+    /// Bearer token the control API requires; the API is open when unset
+    pub admin_token: Option<AdminToken>,
+
+    /// STUN/TURN servers for WebRTC peer connections
+    pub ice: IceConfig,
+
+    /// Codecs peer connections offer, each kind in order of preference
+    pub webrtc_codecs: Vec<WebRtcCodec>,
+
+    /// UDP ports peer connections gather candidates on
+    pub webrtc_udp: UdpPortConfig,
+
+    /// NACK and FEC offered to clients on lossy networks
+    pub loss_recovery: LossRecoveryConfig,
+
+    /// Fastest rate at which master clock corrections are applied, in
+    /// parts per million; 0 steps the clock immediately
+    pub max_clock_slew_ppm: f64,
+
+    /// Filter smoothing each peer's measured clock offsets
+    pub clock_filter: OffsetFilterKind,
+
+    /// Secret for signing client auth tokens; clients need no token when unset
+    pub auth_secret: Option<String>,
+
+    /// Secret for signing ingest tokens, which a client needs to publish
+    /// media; when unset, advertising the `media_source` capability is enough
+    pub ingest_secret: Option<String>,
+
+    /// Ignore cluster messages that are not signed by a verified node key
+    pub require_signed_cluster_messages: bool,
+
+    /// Key this server signs the cluster messages it sends with, so peers
+    /// requiring signatures act on them
+    pub node_signing_key: Option<ed25519_dalek::SigningKey>,
+
+    /// Interval between playback progress reports to clients, in
+    /// milliseconds; 0 disables them
+    pub progress_interval_ms: u64,
+
+    /// How far ahead of its presentation time playback emits media, in
+    /// milliseconds, so clients hold it before it is due
+    pub prebuffer_ms: u64,
+
+    /// Least time a Play without a start time is scheduled ahead by, in
+    /// milliseconds; subscribers with deeper future buffers get longer
+    pub start_lead_ms: u64,
+
+    /// Latency bounds, per-quality targets and late-frame slack of client
+    /// future buffers
+    pub buffer_policy: BufferPolicy,
+
+    /// Integrated loudness uploaded tracks are normalized to, in LUFS
+    pub loudness_reference_lufs: f64,
+
+    /// How far ahead of its audio a program's video is presented, in
+    /// milliseconds, unless the program sets its own offset
+    pub av_offset_ms: f64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            media_dir: PathBuf::from("media"),
+            state_file: None,
+            capture_dir: PathBuf::from("media/captures"),
+            dtls_certificate: None,
+            capture_segment_bytes: 64 * 1024 * 1024,
+            idle_stream_grace_ms: 60_000,
+            peer_disconnect_grace_ms: 5_000,
+            max_ice_restarts: 3,
+            static_dir: PathBuf::from("public"),
+            max_upload_bytes: 200 * 1024 * 1024,
+            client_queue_size: 100,
+            video_forwarder_budget: 8,
+            session_resume_ms: 30_000,
+            device_tuning_max_age_ms: 3_600_000,
+            broadcast_policy: BroadcastPolicy::Drop,
+            max_message_bytes: 64 * 1024,
+            max_media_message_bytes: 1024 * 1024,
+            required_capabilities: CapabilityMap::default(),
+            election_weights: ElectionWeights::default(),
+            master_demotion: DemotionPolicy::default(),
+            queue_gap_ms: 0,
+            queue_crossfade_ms: 0,
+            tls: None,
+            cors: CorsConfig::default(),
+            admin_token: None,
+            ice: IceConfig::default(),
+            webrtc_codecs: WebRtcCodec::ALL.to_vec(),
+            webrtc_udp: UdpPortConfig::default(),
+            loss_recovery: LossRecoveryConfig::default(),
+            max_clock_slew_ppm: 5000.0,
+            clock_filter: OffsetFilterKind::Kalman,
+            auth_secret: None,
+            ingest_secret: None,
+            require_signed_cluster_messages: false,
+            node_signing_key: None,
+            progress_interval_ms: 0,
+            prebuffer_ms: 500,
+            start_lead_ms: 100,
+            buffer_policy: BufferPolicy::default(),
+            loudness_reference_lufs: -16.0,
+            av_offset_ms: 0.0,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Build configuration from the environment, falling back to defaults
+    ///
+    /// Invalid values are logged and ignored, except ICE servers, UDP ports
+    /// and cluster signing: media would silently fail to connect through a
+    /// firewall without the former, and a mistyped signing setting would
+    /// silently accept unsigned cluster messages, so they fail startup
+    /// instead.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        if let Ok(dir) = std::env::var("SOLUSYNC_MEDIA_DIR") {
+            config.media_dir = PathBuf::from(dir);
+        }
+        config.state_file = Some(match std::env::var("SOLUSYNC_STATE_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => config.media_dir.join("state.json"),
+        });
+        config.capture_dir = match std::env::var("SOLUSYNC_CAPTURE_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => config.media_dir.join("captures"),
+        };
+        config.dtls_certificate = Some(match std::env::var("SOLUSYNC_DTLS_CERT") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => config.media_dir.join("dtls-certificate.pem"),
+        });
+        if let Some(mb) = env_parse::<u64>("SOLUSYNC_CAPTURE_SEGMENT_MB").filter(|mb| *mb > 0) {
+            config.capture_segment_bytes = mb * 1024 * 1024;
+        }
+        if let Some(grace_ms) = env_parse("SOLUSYNC_IDLE_STREAM_GRACE_MS") {
+            config.idle_stream_grace_ms = grace_ms;
+        }
+        if let Some(grace_ms) = env_parse("SOLUSYNC_PEER_DISCONNECT_GRACE_MS") {
+            config.peer_disconnect_grace_ms = grace_ms;
+        }
+        if let Some(restarts) = env_parse("SOLUSYNC_MAX_ICE_RESTARTS") {
+            config.max_ice_restarts = restarts;
+        }
+        if let Ok(dir) = std::env::var("SOLUSYNC_STATIC_DIR") {
+            config.static_dir = PathBuf::from(dir);
+        }
+        if let Some(mb) = env_parse::<u64>("SOLUSYNC_MAX_UPLOAD_MB") {
+            config.max_upload_bytes = mb * 1024 * 1024;
+        }
+        if let Some(kb) = env_parse::<usize>("SOLUSYNC_MAX_MESSAGE_KB") {
+            config.max_message_bytes = kb * 1024;
+        }
+        if let Some(kb) = env_parse::<usize>("SOLUSYNC_MAX_MEDIA_MESSAGE_KB") {
+            config.max_media_message_bytes = kb * 1024;
+        }
+        if let Some(gap_ms) = env_parse("SOLUSYNC_QUEUE_GAP_MS") {
+            config.queue_gap_ms = gap_ms;
+        }
+        if let Some(crossfade_ms) = env_parse("SOLUSYNC_QUEUE_CROSSFADE_MS") {
+            config.queue_crossfade_ms = crossfade_ms;
+        }
+        if let Some(interval_ms) = env_parse("SOLUSYNC_PROGRESS_INTERVAL_MS") {
+            config.progress_interval_ms = interval_ms;
+        }
+        if let Some(prebuffer_ms) = env_parse("SOLUSYNC_PREBUFFER_MS") {
+            config.prebuffer_ms = prebuffer_ms;
+        }
+        if let Some(lead_ms) = env_parse("SOLUSYNC_START_LEAD_MS") {
+            config.start_lead_ms = lead_ms;
+        }
+        if let Some(lufs) = env_parse("SOLUSYNC_LOUDNESS_REFERENCE_LUFS") {
+            config.loudness_reference_lufs = lufs;
+        }
+        if let Some(offset_ms) = env_parse("SOLUSYNC_AV_OFFSET_MS") {
+            config.av_offset_ms = offset_ms;
+        }
+        if let Some(min_ms) = env_parse("SOLUSYNC_BUFFER_MIN_MS") {
+            config.buffer_policy.min_latency = Duration::from_millis(min_ms);
+        }
+        if let Some(max_ms) = env_parse("SOLUSYNC_BUFFER_MAX_MS") {
+            config.buffer_policy.max_latency = Duration::from_millis(max_ms);
+        }
+        if config.buffer_policy.min_latency > config.buffer_policy.max_latency {
+            tracing::warn!(
+                "SOLUSYNC_BUFFER_MIN_MS exceeds SOLUSYNC_BUFFER_MAX_MS; using the default bounds"
+            );
+            let defaults = BufferPolicy::default();
+            config.buffer_policy.min_latency = defaults.min_latency;
+            config.buffer_policy.max_latency = defaults.max_latency;
+        }
+        if let Some(slack_ms) = env_parse("SOLUSYNC_LATE_FRAME_SLACK_MS") {
+            config.buffer_policy.late_slack = Duration::from_millis(slack_ms);
+        }
+        if let Ok(mode) = std::env::var("SOLUSYNC_JITTER_MODE") {
+            match mode.as_str() {
+                "fixed" => config.buffer_policy.jitter_mode = JitterMode::Fixed,
+                "adaptive" => config.buffer_policy.jitter_mode = JitterMode::Adaptive,
+                _ => tracing::warn!("Ignoring unknown SOLUSYNC_JITTER_MODE: {:?}", mode),
+            }
+        }
+        if let Some(margin_ms) = env_parse("SOLUSYNC_JITTER_MARGIN_MS") {
+            config.buffer_policy.jitter_margin = Duration::from_millis(margin_ms);
+        }
+        if let Some(deviation_ms) = env_parse("SOLUSYNC_RATE_DEVIATION_MS") {
+            config.buffer_policy.rate_deviation = Duration::from_millis(deviation_ms);
+        }
+        if let Some(ppm) = env_parse("SOLUSYNC_MAX_CLOCK_SLEW_PPM") {
+            config.max_clock_slew_ppm = ppm;
+        }
+        if let Ok(filter) = std::env::var("SOLUSYNC_CLOCK_FILTER") {
+            match filter.as_str() {
+                "kalman" => config.clock_filter = OffsetFilterKind::Kalman,
+                "ewma" => config.clock_filter = OffsetFilterKind::Ewma,
+                _ => tracing::warn!("Ignoring unknown SOLUSYNC_CLOCK_FILTER: {:?}", filter),
+            }
+        }
+        if let Ok(secret) = std::env::var("SOLUSYNC_AUTH_SECRET") {
+            config.auth_secret = Some(secret).filter(|secret| !secret.is_empty());
+        }
+        if let Ok(secret) = std::env::var("SOLUSYNC_INGEST_SECRET") {
+            config.ingest_secret = Some(secret).filter(|secret| !secret.is_empty());
+        }
+        if let Ok(required) = std::env::var("SOLUSYNC_REQUIRE_SIGNED_CLUSTER") {
+            config.require_signed_cluster_messages = required
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid SOLUSYNC_REQUIRE_SIGNED_CLUSTER: {:?}", required))?;
+        }
+        if let Ok(seed) = std::env::var("SOLUSYNC_NODE_KEY") {
+            // Hex-encoded 32-byte ed25519 seed
+            let seed: [u8; 32] = hex::decode(seed.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow::anyhow!("SOLUSYNC_NODE_KEY must be 64 hex digits"))?;
+            config.node_signing_key = Some(ed25519_dalek::SigningKey::from_bytes(&seed));
+        }
+        match (std::env::var("SOLUSYNC_TLS_CERT"), std::env::var("SOLUSYNC_TLS_KEY")) {
+            (Ok(cert), Ok(key)) => {
+                config.tls = Some(TlsConfig {
+                    cert_path: PathBuf::from(cert),
+                    key_path: PathBuf::from(key),
+                });
+            }
+            (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+                tracing::warn!("TLS needs both SOLUSYNC_TLS_CERT and SOLUSYNC_TLS_KEY; serving plain HTTP");
+            }
+            (Err(_), Err(_)) => {}
+        }
+        if let Some(allow_any) = env_parse("SOLUSYNC_ALLOW_ANY_ORIGIN") {
+            config.cors.allow_any_origin = allow_any;
+        }
+        if let Ok(origins) = std::env::var("SOLUSYNC_CORS_ORIGINS") {
+            config.cors.origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Ok(patterns) = std::env::var("SOLUSYNC_CORS_ORIGIN_PATTERNS") {
+            // Whitespace-separated, since patterns may contain commas
+            for pattern in patterns.split_whitespace() {
+                let regex = CorsConfig::origin_pattern(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid CORS origin pattern {:?}: {}", pattern, e))?;
+                config.cors.origin_patterns.push(regex);
+            }
+        }
+        if let Ok(token) = std::env::var("SOLUSYNC_ADMIN_TOKEN") {
+            config.admin_token = AdminToken::new(&token);
+        }
+        if let Some(queue_size) = env_parse("SOLUSYNC_CLIENT_QUEUE_SIZE") {
+            config.client_queue_size = queue_size;
+        }
+        if let Some(budget) = env_parse("SOLUSYNC_VIDEO_FORWARDER_BUDGET") {
+            config.video_forwarder_budget = budget;
+        }
+        if let Some(resume_ms) = env_parse("SOLUSYNC_SESSION_RESUME_MS") {
+            config.session_resume_ms = resume_ms;
+        }
+        if let Some(max_age_ms) = env_parse("SOLUSYNC_DEVICE_TUNING_MAX_AGE_MS") {
+            config.device_tuning_max_age_ms = max_age_ms;
+        }
+        if let Ok(policy) = std::env::var("SOLUSYNC_BROADCAST_POLICY") {
+            let max_consecutive_drops = env_parse("SOLUSYNC_BROADCAST_MAX_DROPS").unwrap_or(50);
+            match policy.as_str() {
+                "block" => config.broadcast_policy = BroadcastPolicy::Block,
+                "drop" => config.broadcast_policy = BroadcastPolicy::Drop,
+                "disconnect" => {
+                    config.broadcast_policy = BroadcastPolicy::Disconnect { max_consecutive_drops }
+                }
+                _ => tracing::warn!("Ignoring unknown SOLUSYNC_BROADCAST_POLICY: {:?}", policy),
+            }
+        }
+        if let Ok(spec) = std::env::var("SOLUSYNC_REQUIRED_CAPABILITIES") {
+            let mut required = config.required_capabilities.clone();
+            match required.apply_overrides(&spec) {
+                Ok(()) => config.required_capabilities = required,
+                Err(e) => tracing::warn!("Ignoring SOLUSYNC_REQUIRED_CAPABILITIES: {}", e),
+            }
+        }
+        if let Some(drift_ppm) = env_parse("SOLUSYNC_MASTER_MAX_DRIFT_PPM") {
+            config.master_demotion.max_drift_ppm = drift_ppm;
+        }
+        if let Some(jitter_ms) = env_parse("SOLUSYNC_MASTER_MAX_JITTER_MS") {
+            config.master_demotion.max_jitter_ms = jitter_ms;
+        }
+        if let Ok(spec) = std::env::var("SOLUSYNC_ELECTION_WEIGHTS") {
+            let mut weights = config.election_weights;
+            match weights.apply_overrides(&spec) {
+                Ok(()) => config.election_weights = weights,
+                Err(e) => tracing::warn!("Ignoring SOLUSYNC_ELECTION_WEIGHTS: {}", e),
+            }
+        }
+        if let Ok(servers) = std::env::var("SOLUSYNC_ICE_SERVERS") {
+            config.ice.servers = IceConfig::parse_servers(&servers)?;
+        }
+        if let Some(host_only) = env_parse("SOLUSYNC_ICE_HOST_ONLY") {
+            config.ice.host_only = host_only;
+        }
+        config.ice.validate()?;
+        if let Ok(range) = std::env::var("SOLUSYNC_WEBRTC_UDP_PORTS") {
+            config.webrtc_udp.port_range = Some(PortRange::parse(&range)?);
+        }
+        if let Ok(port) = std::env::var("SOLUSYNC_WEBRTC_UDP_MUX_PORT") {
+            let port = port
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid SOLUSYNC_WEBRTC_UDP_MUX_PORT: {:?}", port))?;
+            config.webrtc_udp.mux_port = Some(port);
+        }
+        config.webrtc_udp.validate()?;
+        if let Ok(list) = std::env::var("SOLUSYNC_WEBRTC_CODECS") {
+            match WebRtcCodec::parse_list(&list) {
+                Ok(codecs) => config.webrtc_codecs = codecs,
+                Err(e) => tracing::warn!("Ignoring SOLUSYNC_WEBRTC_CODECS: {}", e),
+            }
+        }
+        if let Some(nack) = env_parse("SOLUSYNC_VIDEO_NACK") {
+            config.loss_recovery.video_nack = nack;
+        }
+        if let Some(fec) = env_parse("SOLUSYNC_OPUS_FEC") {
+            config.loss_recovery.opus_fec = fec;
+        }
+        if let Some(dtx) = env_parse("SOLUSYNC_OPUS_DTX") {
+            config.loss_recovery.opus_dtx = dtx;
+        }
+        if let Some(threshold) = env_parse::<f64>("SOLUSYNC_FEC_LOSS_THRESHOLD") {
+            if threshold > 0.0 {
+                config.loss_recovery.fec_loss_threshold_percent = threshold;
+            } else {
+                tracing::warn!("Ignoring SOLUSYNC_FEC_LOSS_THRESHOLD of {}; it must be positive", threshold);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Parse an environment variable, warning about unparseable values
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid value for {}: {:?}", name, value);
+            None
+        }
+    }
+}
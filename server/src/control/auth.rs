@@ -0,0 +1,136 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a token was rejected
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TokenError {
+    #[error("malformed token")]
+    Malformed,
+
+    #[error("token signature is invalid")]
+    BadSignature,
+
+    #[error("token expired")]
+    Expired,
+
+    #[error("token was issued to another client")]
+    WrongClient,
+}
+
+/// Mints and validates signed, time-limited client tokens
+///
+/// A token is `<client_id>.<expires_at>.<signature>`, where `expires_at` is
+/// a Unix timestamp in seconds and the signature is a hex HMAC-SHA256 over
+/// the first two fields. Tokens are bound to the node ID a client sends in
+/// its hello header, so a captured token cannot be used by another client
+/// and stops working once it expires.
+#[derive(Clone)]
+pub struct TokenAuthority {
+    secret: Vec<u8>,
+}
+
+impl TokenAuthority {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Mint a token for `client_id` valid for `ttl` from now
+    pub fn mint(&self, client_id: Uuid, ttl: Duration) -> String {
+        self.mint_until(client_id, now_secs() + ttl.as_secs() as i64)
+    }
+
+    /// Mint a token for `client_id` that expires at a Unix timestamp
+    pub fn mint_until(&self, client_id: Uuid, expires_at: i64) -> String {
+        let payload = format!("{}.{}", client_id, expires_at);
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Check that `token` is genuine, unexpired and issued to `client_id`
+    pub fn verify(&self, token: &str, client_id: Uuid) -> Result<(), TokenError> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+        let (token_client, expires_at) = payload.split_once('.').ok_or(TokenError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| TokenError::Malformed)?;
+
+        // Constant-time comparison
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::BadSignature)?;
+
+        let expires_at: i64 = expires_at.parse().map_err(|_| TokenError::Malformed)?;
+        if now_secs() >= expires_at {
+            return Err(TokenError::Expired);
+        }
+        if token_client.parse::<Uuid>().ok() != Some(client_id) {
+            return Err(TokenError::WrongClient);
+        }
+
+        Ok(())
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+impl std::fmt::Debug for TokenAuthority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenAuthority").finish_non_exhaustive()
+    }
+}
+
+fn now_secs() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_token_is_accepted() {
+        let authority = TokenAuthority::new("secret");
+        let client_id = Uuid::new_v4();
+        let token = authority.mint(client_id, Duration::from_secs(60));
+
+        assert_eq!(authority.verify(&token, client_id), Ok(()));
+        assert_eq!(
+            authority.verify(&token, Uuid::new_v4()),
+            Err(TokenError::WrongClient)
+        );
+    }
+
+    #[test]
+    fn test_expired_token_is_rejected() {
+        let authority = TokenAuthority::new("secret");
+        let client_id = Uuid::new_v4();
+        let token = authority.mint_until(client_id, now_secs() - 1);
+
+        assert_eq!(authority.verify(&token, client_id), Err(TokenError::Expired));
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let authority = TokenAuthority::new("secret");
+        let client_id = Uuid::new_v4();
+        let token = authority.mint_until(client_id, now_secs() - 1);
+
+        // Extending the expiry invalidates the signature
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let (id, _) = payload.split_once('.').unwrap();
+        let forged = format!("{}.{}.{}", id, now_secs() + 3600, signature);
+        assert_eq!(authority.verify(&forged, client_id), Err(TokenError::BadSignature));
+
+        let other = TokenAuthority::new("other secret").mint(client_id, Duration::from_secs(60));
+        assert_eq!(authority.verify(&other, client_id), Err(TokenError::BadSignature));
+        assert_eq!(authority.verify("not-a-token", client_id), Err(TokenError::Malformed));
+    }
+}
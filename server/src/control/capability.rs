@@ -0,0 +1,146 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+/// Client operations that can be gated on an advertised capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientOperation {
+    /// Sending clock sync requests
+    ClockSync,
+
+    /// Sending media control commands
+    MediaControl,
+
+    /// Publishing `media_data` chunks as a live source
+    MediaData,
+
+    /// Subscribing to a track's frames
+    Subscribe,
+}
+
+impl ClientOperation {
+    pub const ALL: [Self; 4] = [Self::ClockSync, Self::MediaControl, Self::MediaData, Self::Subscribe];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::ClockSync => "clock_sync",
+            Self::MediaControl => "media_control",
+            Self::MediaData => "media_data",
+            Self::Subscribe => "subscribe",
+        }
+    }
+}
+
+impl fmt::Display for ClientOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ClientOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|op| op.name() == s)
+            .ok_or_else(|| format!("Unknown operation: {}", s))
+    }
+}
+
+/// What a client may do with streams
+///
+/// Publishing is granted separately from client authentication: by an
+/// ingest token when the server has an ingest secret, otherwise by
+/// advertising the `media_source` capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientRole {
+    /// Receives streams only
+    Listener,
+
+    /// May also create streams and push `media_data` into them
+    Publisher,
+}
+
+impl ClientRole {
+    /// Role of a client on a server without an ingest secret
+    pub fn from_capabilities(capabilities: &[String]) -> Self {
+        if capabilities.iter().any(|c| c == super::MEDIA_SOURCE_CAPABILITY) {
+            Self::Publisher
+        } else {
+            Self::Listener
+        }
+    }
+}
+
+/// Capability a client must advertise in its hello for each operation
+///
+/// Operations without an entry are allowed for every client.
+#[derive(Debug, Clone)]
+pub struct CapabilityMap {
+    required: HashMap<ClientOperation, String>,
+}
+
+impl Default for CapabilityMap {
+    fn default() -> Self {
+        let required = [
+            (ClientOperation::ClockSync, "clock_sync"),
+            (ClientOperation::MediaControl, "audio"),
+            (ClientOperation::MediaData, super::MEDIA_SOURCE_CAPABILITY),
+            (ClientOperation::Subscribe, "media_streaming"),
+        ];
+
+        Self {
+            required: required
+                .into_iter()
+                .map(|(op, capability)| (op, capability.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl CapabilityMap {
+    /// Capability required for an operation, if any
+    pub fn required(&self, op: ClientOperation) -> Option<&str> {
+        self.required.get(&op).map(String::as_str)
+    }
+
+    /// Require a capability for an operation, or allow it for everyone with `None`
+    pub fn set(&mut self, op: ClientOperation, capability: Option<String>) {
+        match capability {
+            Some(capability) => self.required.insert(op, capability),
+            None => self.required.remove(&op),
+        };
+    }
+
+    /// Apply overrides of the form `operation=capability,...`
+    ///
+    /// An empty capability (`operation=`) removes the requirement.
+    pub fn apply_overrides(&mut self, spec: &str) -> Result<(), String> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (op, capability) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected operation=capability, got {:?}", entry))?;
+            let capability = capability.trim();
+            let capability = (!capability.is_empty()).then(|| capability.to_string());
+            self.set(op.trim().parse()?, capability);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_replace_and_remove_requirements() {
+        let mut map = CapabilityMap::default();
+
+        map.apply_overrides("media_control=controller, clock_sync=").unwrap();
+
+        assert_eq!(map.required(ClientOperation::MediaControl), Some("controller"));
+        assert_eq!(map.required(ClientOperation::ClockSync), None);
+        assert_eq!(map.required(ClientOperation::Subscribe), Some("media_streaming"));
+        assert!(map.apply_overrides("teleport=yes").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::sync::{Arc, Weak};
+use tokio::sync::mpsc;
+use tracing::debug;
+use uuid::Uuid;
+use webrtc::{
+    data_channel::{data_channel_message::DataChannelMessage, RTCDataChannel},
+    peer_connection::RTCPeerConnection,
+};
+
+use super::{outgoing, ControlError};
+use crate::{
+    media::{EgressCounter, WebRtcServer},
+    protocol::Message as ProtoMessage,
+};
+
+/// Capability a client advertises in its hello to have control data
+/// channels opened on its peer connection; the server announces it too
+pub const DATA_CHANNEL_CAPABILITY: &str = "transport:datachannel";
+
+/// Replies queued for one data channel before further ones are dropped
+const REPLY_QUEUE_SIZE: usize = 32;
+
+/// Control data channel of a client's peer connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Ordered and retransmitted, carrying any control message
+    Reliable,
+
+    /// Unordered and never retransmitted, carrying only `clock_sync`
+    ///
+    /// A late or lost exchange only costs one clock sample, while a
+    /// retransmitted one would report an inflated round trip.
+    Unreliable,
+}
+
+impl Lane {
+    pub const ALL: [Lane; 2] = [Lane::Reliable, Lane::Unreliable];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Reliable => "control",
+            Self::Unreliable => "control-unreliable",
+        }
+    }
+
+    fn ordered(self) -> bool {
+        self == Self::Reliable
+    }
+}
+
+/// Text message received on a control data channel
+pub struct ChannelMessage {
+    pub lane: Lane,
+    pub text: String,
+
+    /// Queue of replies sent back on the same channel
+    pub reply: mpsc::Sender<ProtoMessage>,
+}
+
+/// Refuse a hello on a data channel; it belongs on the WebSocket, which
+/// owns the session
+pub fn check_not_hello(text: &str) -> Result<(), ControlError> {
+    #[derive(serde::Deserialize)]
+    struct MessageType<'a> {
+        #[serde(rename = "type", borrow)]
+        kind: Option<&'a str>,
+    }
+
+    if serde_json::from_str::<MessageType>(text)?.kind == Some("hello") {
+        return Err(ControlError::InvalidRequest(
+            "A hello must be sent over the WebSocket".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Open the control data channels on a client's peer connection
+///
+/// The channels are announced in the next offer, so this has to happen
+/// before it is created. Text messages received on them are queued on
+/// `incoming`; binary ones are ignored. The channels are returned so the
+/// caller can keep them for the life of the connection. Replies are
+/// counted in `egress`.
+pub async fn open(
+    peer_connection: &Arc<RTCPeerConnection>,
+    client_id: Uuid,
+    incoming: mpsc::Sender<ChannelMessage>,
+    egress: Arc<EgressCounter>,
+) -> Result<Vec<Arc<RTCDataChannel>>> {
+    let mut channels = Vec::new();
+    for lane in Lane::ALL {
+        let channel = WebRtcServer::create_data_channel(peer_connection, lane.label(), lane.ordered()).await?;
+
+        let (reply, replies) = mpsc::channel(REPLY_QUEUE_SIZE);
+        tokio::spawn(send_replies(replies, Arc::downgrade(&channel), client_id, egress.clone()));
+
+        let incoming = incoming.clone();
+        channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let incoming = incoming.clone();
+            let reply = reply.clone();
+            Box::pin(async move {
+                if !message.is_string {
+                    debug!("Ignoring binary {} message from {}", lane.label(), client_id);
+                    return;
+                }
+                let text = String::from_utf8_lossy(&message.data).into_owned();
+                let message = ChannelMessage { lane, text, reply };
+                match lane {
+                    Lane::Reliable => {
+                        let _ = incoming.send(message).await;
+                    }
+                    // A stale clock sample is worth less than none
+                    Lane::Unreliable => {
+                        let _ = incoming.try_send(message);
+                    }
+                }
+            })
+        }));
+        channels.push(channel);
+    }
+    Ok(channels)
+}
+
+/// Write queued replies to a data channel until it is dropped
+///
+/// The channel is held weakly: its message handler owns the reply queue's
+/// sender, so a strong reference would keep both alive forever.
+async fn send_replies(
+    mut replies: mpsc::Receiver<ProtoMessage>,
+    channel: Weak<RTCDataChannel>,
+    client_id: Uuid,
+    egress: Arc<EgressCounter>,
+) {
+    while let Some(message) = replies.recv().await {
+        let Some(channel) = channel.upgrade() else {
+            break;
+        };
+        let Ok(json) = outgoing::encode(&message) else {
+            continue;
+        };
+        match channel.send_text(json).await {
+            Ok(bytes) => egress.record(1, bytes),
+            Err(e) => debug!("Failed to reply on {} to {}: {}", channel.label(), client_id, e),
+        }
+    }
+}
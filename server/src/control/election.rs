@@ -0,0 +1,330 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::protocol::{MasterElectionMessage, NodeType};
+
+/// How long a node's claim of who is master stays valid without being
+/// repeated
+pub const MASTER_CLAIM_VALIDITY: Duration = Duration::from_secs(10);
+
+/// Uptime at which the uptime component reaches half its weight
+const UPTIME_HALF_SCORE_SECS: f64 = 3600.0;
+
+/// RTT standard deviation at which the stability component reaches half
+/// its weight
+const RTT_STDDEV_HALF_SCORE_MS: f64 = 10.0;
+
+/// Connected clients at which the client component reaches half its weight
+const CLIENTS_HALF_SCORE: f64 = 100.0;
+
+/// RTT reports kept per node to judge its stability
+const RTT_HISTORY_LEN: usize = 20;
+
+/// Weights of the components of a master candidate's composite score
+///
+/// Each component is scaled to 0..=1 before weighting:
+///
+/// - `node_type`: 1 for a verified Master, 0.5 for a Replica, 0 for a Client
+/// - `candidate`: the `candidate_score` the node reported, clamped
+/// - `uptime`: `uptime / (uptime + 1h)` from the node's last status
+/// - `stability`: `1 / (1 + stddev / 10ms)` over the RTTs in its recent
+///   statuses, 0 until two have arrived
+/// - `clients`: `clients / (clients + 100)` from its last status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElectionWeights {
+    pub node_type: f64,
+    pub candidate: f64,
+    pub uptime: f64,
+    pub stability: f64,
+    pub clients: f64,
+}
+
+impl Default for ElectionWeights {
+    /// Node type outweighs all other components together
+    fn default() -> Self {
+        Self {
+            node_type: 4.0,
+            candidate: 1.0,
+            uptime: 1.0,
+            stability: 1.0,
+            clients: 0.5,
+        }
+    }
+}
+
+impl ElectionWeights {
+    /// Composite score of a candidate; higher is better
+    pub fn score(&self, candidate: &CandidateProfile) -> f64 {
+        let node_type = match candidate.node_type {
+            NodeType::Master => 1.0,
+            NodeType::Replica => 0.5,
+            NodeType::Client => 0.0,
+        };
+        let uptime = candidate.uptime_seconds as f64;
+        let stability = candidate
+            .rtt_stddev_ms
+            .map_or(0.0, |stddev| 1.0 / (1.0 + stddev / RTT_STDDEV_HALF_SCORE_MS));
+        let clients = candidate.connected_clients as f64;
+
+        self.node_type * node_type
+            + self.candidate * candidate.candidate_score.clamp(0.0, 1.0)
+            + self.uptime * uptime / (uptime + UPTIME_HALF_SCORE_SECS)
+            + self.stability * stability
+            + self.clients * clients / (clients + CLIENTS_HALF_SCORE)
+    }
+
+    /// Apply overrides of the form `component=weight,...`
+    pub fn apply_overrides(&mut self, spec: &str) -> Result<(), String> {
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (component, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected component=weight, got {:?}", entry))?;
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .ok()
+                .filter(|w: &f64| w.is_finite() && *w >= 0.0)
+                .ok_or_else(|| format!("Invalid weight for {}: {:?}", component, weight))?;
+            match component.trim() {
+                "node_type" => self.node_type = weight,
+                "candidate" => self.candidate = weight,
+                "uptime" => self.uptime = weight,
+                "stability" => self.stability = weight,
+                "clients" => self.clients = weight,
+                other => return Err(format!("Unknown score component: {}", other)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// When the current master is demoted for a degraded clock or link
+///
+/// A master whose reported drift or measured RTT jitter stays over its
+/// limit for `sustain` is demoted and may not lead again for `holdoff`.
+/// Once degraded, it only counts as healthy again when both fall below
+/// `recovery_ratio` of their limits, so values hovering at a limit do not
+/// restart the sustain period.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DemotionPolicy {
+    pub max_drift_ppm: f64,
+    pub max_jitter_ms: f64,
+    pub sustain: Duration,
+    pub recovery_ratio: f64,
+    pub holdoff: Duration,
+}
+
+impl Default for DemotionPolicy {
+    fn default() -> Self {
+        Self {
+            max_drift_ppm: 100.0,
+            max_jitter_ms: 20.0,
+            sustain: Duration::from_secs(10),
+            recovery_ratio: 0.8,
+            holdoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Clock and link health a node reported or was measured with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NodeHealth {
+    pub drift_ppm: Option<f64>,
+    pub jitter_ms: Option<f64>,
+}
+
+impl NodeHealth {
+    /// How far the worst value is past its limit, as a fraction of it
+    fn load(&self, policy: &DemotionPolicy) -> f64 {
+        let drift = self.drift_ppm.map_or(0.0, |drift| drift.abs() / policy.max_drift_ppm);
+        let jitter = self.jitter_ms.map_or(0.0, |jitter| jitter / policy.max_jitter_ms);
+        drift.max(jitter)
+    }
+}
+
+/// What is known about a node standing for master
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandidateProfile {
+    /// Role the node proved in its key handshake
+    pub node_type: NodeType,
+    pub candidate_score: f64,
+    pub uptime_seconds: u64,
+    pub rtt_stddev_ms: Option<f64>,
+    pub connected_clients: u32,
+}
+
+/// Recent average RTTs a node reported in its statuses
+#[derive(Debug, Default)]
+pub struct RttHistory {
+    samples: VecDeque<f64>,
+}
+
+impl RttHistory {
+    pub fn record(&mut self, rtt_ms: f64) {
+        if self.samples.len() == RTT_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt_ms);
+    }
+
+    /// Standard deviation of the recorded RTTs, once there are two
+    pub fn stddev(&self) -> Option<f64> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let n = self.samples.len() as f64;
+        let mean = self.samples.iter().sum::<f64>() / n;
+        let variance = self.samples.iter().map(|rtt| (rtt - mean).powi(2)).sum::<f64>() / n;
+        Some(variance.sqrt())
+    }
+}
+
+/// Outcome of recording a master election message
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElectionOutcome {
+    /// The message was from an election epoch already superseded
+    Stale { epoch: u64 },
+
+    /// The message was recorded; `leading` is set when its sender became
+    /// the best candidate
+    Recorded { leading: bool },
+
+    /// Nodes currently claim different masters, e.g. after two network
+    /// partitions each elected one and rejoined
+    ///
+    /// The election restarts at `epoch`, so claims from before the split
+    /// no longer count.
+    SplitBrain { masters: Vec<Uuid>, epoch: u64 },
+}
+
+/// Master election as seen by this server
+///
+/// Every election message carries the epoch it belongs to. Messages from
+/// a later epoch restart the election at that epoch, and messages from an
+/// earlier one are ignored.
+#[derive(Debug, Default)]
+pub struct ElectionState {
+    epoch: u64,
+
+    /// Best node standing in the current epoch, with its composite score
+    candidate: Option<(Uuid, f64)>,
+
+    /// Masters claimed by nodes in the current epoch, with when each claim
+    /// was last heard
+    claimed_masters: HashMap<Uuid, Instant>,
+
+    /// Split-brain conditions detected so far
+    split_brains: u64,
+
+    /// Since when the leading node's health has been over the limits
+    degraded_since: Option<Instant>,
+
+    /// Demoted nodes and until when they may not lead
+    demoted: HashMap<Uuid, Instant>,
+}
+
+impl ElectionState {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn candidate(&self) -> Option<(Uuid, f64)> {
+        self.candidate
+    }
+
+    pub fn split_brains(&self) -> u64 {
+        self.split_brains
+    }
+
+    /// Record an election message from `node_id`, whose composite score
+    /// is `score`
+    ///
+    /// A node leads when its score is higher than the current candidate's;
+    /// on equal scores the lower node ID wins, so every server picks the
+    /// same leader whatever order the messages arrived in.
+    pub fn record(
+        &mut self,
+        node_id: Uuid,
+        election: &MasterElectionMessage,
+        score: f64,
+        now: Instant,
+    ) -> ElectionOutcome {
+        if election.epoch < self.epoch {
+            return ElectionOutcome::Stale { epoch: self.epoch };
+        }
+        if election.epoch > self.epoch {
+            self.restart(election.epoch);
+        }
+
+        if let Some(master) = election.current_master {
+            self.claimed_masters
+                .retain(|_, heard| now.duration_since(*heard) < MASTER_CLAIM_VALIDITY);
+            self.claimed_masters.insert(master, now);
+
+            if self.claimed_masters.len() > 1 {
+                let mut masters: Vec<_> = self.claimed_masters.keys().copied().collect();
+                masters.sort();
+                self.split_brains += 1;
+                self.restart(self.epoch + 1);
+                return ElectionOutcome::SplitBrain {
+                    masters,
+                    epoch: self.epoch,
+                };
+            }
+        }
+
+        self.demoted.retain(|_, until| *until > now);
+        let leading = match self.candidate {
+            _ if self.demoted.contains_key(&node_id) => false,
+            Some((leader, best)) => score > best || (score == best && node_id < leader),
+            None => true,
+        };
+        if leading {
+            self.candidate = Some((node_id, score));
+        }
+        ElectionOutcome::Recorded { leading }
+    }
+
+    /// Check the health of `node_id` if it is the leading candidate
+    ///
+    /// Returns the epoch the election restarts at when the leader has been
+    /// degraded for the sustain period and is demoted.
+    pub fn check_leader_health(
+        &mut self,
+        node_id: Uuid,
+        health: NodeHealth,
+        policy: &DemotionPolicy,
+        now: Instant,
+    ) -> Option<u64> {
+        if self.candidate.map(|(leader, _)| leader) != Some(node_id) {
+            return None;
+        }
+
+        let load = health.load(policy);
+        match self.degraded_since {
+            None if load > 1.0 => self.degraded_since = Some(now),
+            Some(_) if load < policy.recovery_ratio => self.degraded_since = None,
+            _ => {}
+        }
+
+        let since = self.degraded_since?;
+        if now.duration_since(since) < policy.sustain {
+            return None;
+        }
+        self.demoted.insert(node_id, now + policy.holdoff);
+        self.restart(self.epoch + 1);
+        Some(self.epoch)
+    }
+
+    /// Start the election over at `epoch`
+    fn restart(&mut self, epoch: u64) {
+        self.epoch = epoch;
+        self.candidate = None;
+        self.claimed_masters.clear();
+        self.degraded_since = None;
+    }
+}
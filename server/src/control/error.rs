@@ -0,0 +1,69 @@
+use tokio::sync::mpsc;
+
+use crate::protocol::ErrorCode;
+
+/// Errors raised while handling client messages
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    /// The message could not be parsed
+    #[error("Malformed message: {0}")]
+    ParseError(#[from] serde_json::Error),
+
+    /// The message exceeds the size limit for its type
+    #[error("Message of {size} bytes exceeds the {limit} byte limit")]
+    MessageTooLarge { size: usize, limit: usize },
+
+    /// The client could not be authenticated
+    #[error("Authentication failed: {0}")]
+    AuthError(String),
+
+    /// The client is not allowed to perform the operation
+    #[error("Not permitted: {0}")]
+    Unauthorized(String),
+
+    /// The client's outgoing channel is closed
+    #[error("Client channel closed")]
+    ChannelClosed,
+
+    /// The request is well-formed but asks for something impossible
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    /// A media command failed
+    #[error("Media error: {0}")]
+    MediaError(String),
+
+    /// Any other server-side failure
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl ControlError {
+    /// Protocol error code to report to the client
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ParseError(_) | Self::MessageTooLarge { .. } | Self::InvalidRequest(_) => {
+                ErrorCode::ProtocolError
+            }
+            Self::AuthError(_) => ErrorCode::AuthenticationFailed,
+            Self::Unauthorized(_) => ErrorCode::Unauthorized,
+            Self::ChannelClosed => ErrorCode::NetworkError,
+            Self::MediaError(_) => ErrorCode::MediaError,
+            Self::Internal(_) => ErrorCode::InternalError,
+        }
+    }
+
+    /// Whether the connection should be closed after reporting the error
+    ///
+    /// Malformed or rejected messages leave the connection usable; a client
+    /// that failed authentication or can no longer be written to does not.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::AuthError(_) | Self::ChannelClosed)
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for ControlError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        Self::ChannelClosed
+    }
+}
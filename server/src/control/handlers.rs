@@ -1,22 +1,72 @@
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
+    extract::{multipart::Field, Json, Multipart, Path, State},
+    http::{header, StatusCode},
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt::Write as _};
+use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
 use crate::{
+    clock::ClockHealth,
+    control::{ClientInfo, ConnectionHealth},
+    health::HealthState,
+    media::{
+        BoundsSource, BufferStats, CatalogError, IceServerSummary, LatencyBounds, MediaHealth, PlaybackState,
+        QualityTier, QueueItem, RecordingError, TieredVideo, ToneParams, TrackInfo, UdpPortConfig, Waveform,
+        DEFAULT_SYNC_SLACK,
+    },
     protocol::{MediaAction, MediaParams, MessageHeader},
+    request_id::RequestId,
     AppState,
 };
 
 /// Play request
 #[derive(Debug, Deserialize)]
 pub struct PlayRequest {
+    #[serde(default)]
     pub track_id: String,
+    
+    /// Program to play in place of a single track
+    pub program_id: Option<String>,
+    
     pub start_at: Option<f64>,
     pub volume: Option<f32>,
+    
+    /// Network time at which to stop
+    pub stop_at: Option<f64>,
+    
+    /// Stop after playing this long
+    pub max_duration_ms: Option<u64>,
+    
+    /// Play only to clients in this zone
+    pub zone: Option<String>,
+}
+
+/// Pause request
+#[derive(Debug, Deserialize)]
+pub struct PauseRequest {
+    pub track_id: String,
+}
+
+/// Body of a pause request: a `PauseRequest`, or the bare track ID string
+/// accepted before requests had fields
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PauseBody {
+    Request(PauseRequest),
+    TrackId(String),
+}
+
+impl From<PauseBody> for PauseRequest {
+    fn from(body: PauseBody) -> Self {
+        match body {
+            PauseBody::Request(req) => req,
+            PauseBody::TrackId(track_id) => Self { track_id },
+        }
+    }
 }
 
 /// API response
@@ -48,17 +98,29 @@ impl<T> ApiResponse<T> {
 /// Handle play command
 pub async fn play(
     State(state): State<AppState>,
+    request_id: RequestId,
     Json(req): Json<PlayRequest>,
 ) -> impl IntoResponse {
+    let track_id = req.program_id.clone().unwrap_or(req.track_id);
+    if track_id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("A track_id or program_id is required".into())),
+        );
+    }
+    // Without a start time, every subscriber's buffer gets to fill first
     let start_at = match req.start_at {
         Some(t) => t,
-        None => state.clock_manager.now().await + 0.1,
+        None => {
+            let lead = state.media_server.start_lead(&track_id, req.zone.as_deref()).await;
+            state.clock_manager.now().await + lead.as_secs_f64()
+        }
     };
     
     let control = crate::protocol::MediaControlMessage {
-        header: MessageHeader::new(Uuid::new_v4(), 0),
+        header: MessageHeader::new(Uuid::new_v4(), 0).with_request_id(request_id),
         action: MediaAction::Play,
-        track_id: req.track_id.clone(),
+        track_id: track_id.clone(),
         start_at,
         params: MediaParams {
             volume: req.volume,
@@ -66,9 +128,17 @@ pub async fn play(
             fade_in_ms: None,
             fade_out_ms: None,
             seek_position: None,
+            source: None,
+            stop_at: req.stop_at,
+            max_duration_ms: req.max_duration_ms,
         },
+        zone: req.zone.clone(),
     };
     
+    if let Err(e) = state.media_server.check_scheduled_stop(&control).await {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+    }
+    
     match state
         .media_server
         .get_control_sender()
@@ -78,8 +148,9 @@ pub async fn play(
         Ok(_) => (
             StatusCode::OK,
             Json(ApiResponse::success(serde_json::json!({
-                "track_id": req.track_id,
+                "track_id": track_id,
                 "start_at": start_at,
+                "zone": req.zone,
             }))),
         ),
         Err(e) => (
@@ -92,10 +163,19 @@ pub async fn play(
 /// Handle pause command
 pub async fn pause(
     State(state): State<AppState>,
-    Json(track_id): Json<String>,
+    request_id: RequestId,
+    Json(body): Json<PauseBody>,
 ) -> impl IntoResponse {
+    let PauseRequest { track_id } = body.into();
+    if track_id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("A track_id is required".into())),
+        );
+    }
+    
     let control = crate::protocol::MediaControlMessage {
-        header: MessageHeader::new(Uuid::new_v4(), 0),
+        header: MessageHeader::new(Uuid::new_v4(), 0).with_request_id(request_id),
         action: MediaAction::Pause,
         track_id: track_id.clone(),
         start_at: state.clock_manager.now().await,
@@ -105,7 +185,11 @@ pub async fn pause(
             fade_in_ms: None,
             fade_out_ms: None,
             seek_position: None,
+            source: None,
+            stop_at: None,
+            max_duration_ms: None,
         },
+        zone: None,
     };
     
     match state
@@ -156,31 +240,1409 @@ pub async fn sync(
     )
 }
 
+/// Server timestamps for NTP-style offset calculation
+///
+/// Clients combine these with their own send (t1) and receive (t4) times:
+/// `offset = ((t2 - t1) + (t3 - t4)) / 2`.
+#[derive(Debug, Serialize)]
+pub struct TimeResponse {
+    /// Server time when the request was received
+    pub t2: f64,
+    
+    /// Server time when the response was sent
+    pub t3: f64,
+}
+
+/// Get the server's synchronized time
+pub async fn time(State(state): State<AppState>) -> Json<ApiResponse<TimeResponse>> {
+    let t2 = state.clock_manager.now().await;
+    
+    // Nothing else runs before the response is serialized, so t3 is taken last
+    let t3 = state.clock_manager.now().await;
+    Json(ApiResponse::success(TimeResponse { t2, t3 }))
+}
+
 #[derive(Debug, Serialize)]
 pub struct StatusResponse {
     pub server_id: String,
     pub server_time: f64,
+    
+    /// The host's wall clock, which unlike the server time follows NTP
+    /// steps, for comparing with other hosts
+    pub wall_clock_time: f64,
     pub uptime_seconds: u64,
     pub connected_clients: u32,
     pub active_streams: u32,
+    
+    /// Position being presented by each stream that has played
+    pub positions: Vec<StreamPosition>,
+    
+    /// STUN/TURN servers peer connections use; empty when only host
+    /// candidates are gathered
+    pub ice_servers: Vec<IceServerSummary>,
+    
+    /// UDP ports peer connections use, to open on firewalls
+    pub webrtc_udp: UdpPortConfig,
+    
+    /// Fingerprint of the DTLS certificate peer connections present, as
+    /// in SDP's `a=fingerprint`; null when each connection generates its
+    /// own
+    pub dtls_fingerprint: Option<String>,
+}
+
+/// Current position of one stream
+#[derive(Debug, Serialize)]
+pub struct StreamPosition {
+    pub track_id: String,
+    pub zone: Option<String>,
+    pub state: PlaybackState,
+    pub position: f64,
 }
 
 /// Get server status
 pub async fn status(State(state): State<AppState>) -> impl IntoResponse {
+    let positions = state
+        .media_server
+        .stream_statuses()
+        .await
+        .into_iter()
+        .filter_map(|stream| {
+            Some(StreamPosition {
+                position: stream.playback_position?,
+                track_id: stream.track_id,
+                zone: stream.zone,
+                state: stream.state,
+            })
+        })
+        .collect();
+    
     // TODO: Get real stats
     let status = StatusResponse {
         server_id: Uuid::new_v4().to_string(),
         server_time: state.clock_manager.now().await,
+        wall_clock_time: crate::protocol::time::wall_clock_time(),
         uptime_seconds: 0,
         connected_clients: 0,
         active_streams: 0,
+        positions,
+        ice_servers: state.config.ice.summary(),
+        webrtc_udp: state.config.webrtc_udp,
+        dtls_fingerprint: state.media_server.dtls_fingerprint(),
     };
     
     (StatusCode::OK, Json(ApiResponse::success(status)))
 }
 
+/// Health of every subsystem
+#[derive(Debug, Serialize)]
+pub struct DetailedStatus {
+    /// Worst state of any subsystem
+    pub state: HealthState,
+    pub server_time: f64,
+    pub clock: ClockHealth,
+    pub media: MediaHealth,
+    pub connections: ConnectionHealth,
+}
+
+/// Get per-subsystem health
+pub async fn detailed_status(State(state): State<AppState>) -> Json<ApiResponse<DetailedStatus>> {
+    let clock = state.clock_manager.health().await;
+    let media = state.media_server.health().await;
+    let connections = state.control_server.health().await;
+    
+    Json(ApiResponse::success(DetailedStatus {
+        state: HealthState::worst([clock.state, media.state, connections.state]),
+        server_time: state.clock_manager.now().await,
+        clock,
+        media,
+        connections,
+    }))
+}
+
+/// Get status of all media streams
+pub async fn streams(State(state): State<AppState>) -> impl IntoResponse {
+    let streams = state.media_server.stream_statuses().await;
+    (StatusCode::OK, Json(ApiResponse::success(streams)))
+}
+
+/// Tear down a media stream
+pub async fn delete_stream(
+    State(state): State<AppState>,
+    Path(track_id): Path<String>,
+) -> impl IntoResponse {
+    if state.media_server.delete_stream(&track_id).await {
+        (StatusCode::OK, Json(ApiResponse::success(track_id)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Stream not found: {}", track_id))),
+        )
+    }
+}
+
+/// Recording start/stop request
+#[derive(Debug, Default, Deserialize)]
+pub struct RecordRequest {
+    /// Stop the stream's recording instead of starting one
+    #[serde(default)]
+    pub stop: bool,
+    /// File name inside the recordings directory, defaults to
+    /// `<track>-<unix time>` with the container's extension
+    pub file_name: Option<String>,
+}
+
+/// Start or stop recording a media stream to disk
+pub async fn record_stream(
+    State(state): State<AppState>,
+    Path(track_id): Path<String>,
+    Json(req): Json<RecordRequest>,
+) -> impl IntoResponse {
+    let result = if req.stop {
+        state.media_server.stop_recording(&track_id).await
+    } else {
+        // The default name comes from the track ID, which is checked the
+        // same as a given one
+        let file_name = req.file_name.unwrap_or_else(|| default_file_name(&track_id));
+        if !is_plain_file_name(&file_name) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(format!("Invalid file name: {}", file_name))),
+            );
+        }
+        
+        let dir = state.config.media_dir.join("recordings");
+        match tokio::fs::create_dir_all(&dir).await {
+            Ok(()) => state.media_server.start_recording(&track_id, &dir.join(file_name)).await,
+            Err(e) => Err(e.into()),
+        }
+    };
+    
+    match result {
+        Ok(status) => (StatusCode::OK, Json(ApiResponse::success(status))),
+        Err(e) => (recording_error_status(&e), Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+fn recording_error_status(error: &RecordingError) -> StatusCode {
+    match error {
+        RecordingError::NotFound(_) | RecordingError::CaptureNotFound(_) => StatusCode::NOT_FOUND,
+        RecordingError::AlreadyRecording(_) | RecordingError::NotRecording(_) => StatusCode::CONFLICT,
+        RecordingError::Corrupt(..) => StatusCode::UNPROCESSABLE_ENTITY,
+        RecordingError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Whether `name` names a file inside a directory without leaving it
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// `<track>-<unix time>`, the name of a recording or capture not given one
+fn default_file_name(track_id: &str) -> String {
+    format!(
+        "{}-{}",
+        track_id,
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    )
+}
+
+/// Frame capture start/stop request
+#[derive(Debug, Default, Deserialize)]
+pub struct CaptureRequest {
+    /// Stop the stream's capture instead of starting one
+    #[serde(default)]
+    pub stop: bool,
+    /// Capture name, the prefix of its segment files; defaults to
+    /// `<track>-<unix time>`
+    pub name: Option<String>,
+}
+
+/// Start or stop capturing a media stream's frames for replay
+pub async fn capture_stream(
+    State(state): State<AppState>,
+    Path(track_id): Path<String>,
+    Json(req): Json<CaptureRequest>,
+) -> impl IntoResponse {
+    let result = if req.stop {
+        state.media_server.stop_capture(&track_id).await
+    } else {
+        let name = req.name.unwrap_or_else(|| default_file_name(&track_id));
+        if !is_plain_file_name(&name) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(format!("Invalid capture name: {}", name))),
+            );
+        }
+        state.media_server.start_capture(&track_id, &name).await
+    };
+    
+    match result {
+        Ok(status) => (StatusCode::OK, Json(ApiResponse::success(status))),
+        Err(e) => (recording_error_status(&e), Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Capture replay request
+#[derive(Debug, Deserialize)]
+pub struct ReplayRequest {
+    /// Name the capture was started with
+    pub name: String,
+}
+
+/// Captured frames queued for replay
+#[derive(Debug, Serialize)]
+pub struct ReplayResponse {
+    pub codec: String,
+    pub frames: usize,
+}
+
+/// Replay a capture on a live stream
+///
+/// The capture is read and checked before responding; its frames are then
+/// re-ingested in the background at their original pace.
+pub async fn replay_capture(
+    State(state): State<AppState>,
+    Path(track_id): Path<String>,
+    Json(req): Json<ReplayRequest>,
+) -> impl IntoResponse {
+    if !is_plain_file_name(&req.name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("Invalid capture name: {}", req.name))),
+        );
+    }
+    let captured = match state.media_server.load_capture(&req.name).await {
+        Ok(captured) => captured,
+        Err(e) => return (recording_error_status(&e), Json(ApiResponse::error(e.to_string()))),
+    };
+    
+    let response = ReplayResponse {
+        codec: captured.codec.clone(),
+        frames: captured.frames.len(),
+    };
+    let media_server = state.media_server.clone();
+    tokio::spawn(async move {
+        if let Err(e) = media_server.replay_capture(&track_id, captured).await {
+            tracing::warn!("Replay of {} on {} failed: {}", req.name, track_id, e);
+        }
+    });
+    
+    (StatusCode::ACCEPTED, Json(ApiResponse::success(response)))
+}
+
+/// Sync group creation request
+#[derive(Debug, Deserialize)]
+pub struct SyncGroupRequest {
+    pub name: String,
+    
+    /// Live tracks released together
+    pub tracks: Vec<String>,
+    
+    /// Longest a frame is held for a lagging track
+    pub slack_ms: Option<u64>,
+}
+
+/// List sync groups
+pub async fn sync_groups(State(state): State<AppState>) -> impl IntoResponse {
+    let groups = state.media_server.sync_groups();
+    (StatusCode::OK, Json(ApiResponse::success(groups)))
+}
+
+/// Tie live tracks together so they are released in sync
+pub async fn create_sync_group(
+    State(state): State<AppState>,
+    Json(req): Json<SyncGroupRequest>,
+) -> impl IntoResponse {
+    let slack = req
+        .slack_ms
+        .map_or(DEFAULT_SYNC_SLACK, std::time::Duration::from_millis);
+    match state.media_server.create_sync_group(req.name.clone(), req.tracks, slack) {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(req.name))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Dissolve a sync group
+pub async fn delete_sync_group(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if state.media_server.remove_sync_group(&name).await {
+        (StatusCode::OK, Json(ApiResponse::success(name)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Sync group not found: {}", name))),
+        )
+    }
+}
+
+/// Test tone request
+#[derive(Debug, Deserialize)]
+pub struct ToneRequest {
+    #[serde(default)]
+    pub waveform: Waveform,
+    #[serde(default = "default_tone_frequency")]
+    pub frequency_hz: f64,
+    #[serde(default = "default_tone_duration")]
+    pub duration_ms: u64,
+    /// Spacing between beep or click onsets
+    pub interval_ms: Option<u64>,
+    pub start_at: Option<f64>,
+    /// Play only in this zone instead of on every client
+    pub zone: Option<String>,
+}
+
+fn default_tone_frequency() -> f64 {
+    1000.0
+}
+
+fn default_tone_duration() -> u64 {
+    10_000
+}
+
+/// Play a generated calibration tone on all clients at once
+pub async fn play_test_tone(
+    State(state): State<AppState>,
+    Json(req): Json<ToneRequest>,
+) -> impl IntoResponse {
+    let start_at = match req.start_at {
+        Some(t) => t,
+        None => state.clock_manager.now().await + 0.5,
+    };
+    let params = ToneParams {
+        waveform: req.waveform,
+        frequency: req.frequency_hz,
+        duration: std::time::Duration::from_millis(req.duration_ms),
+        interval: req.interval_ms.map(std::time::Duration::from_millis),
+    };
+    
+    match state.media_server.play_test_tone(params, start_at, req.zone).await {
+        Ok(cmd) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(serde_json::json!({
+                "track_id": cmd.track_id,
+                "start_at": cmd.start_at,
+                "zone": cmd.zone,
+            }))),
+        ),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Program creation request
+#[derive(Debug, Deserialize)]
+pub struct ProgramRequest {
+    pub program_id: String,
+    pub audio_track: String,
+    pub video_track: String,
+    
+    /// How far ahead of its audio the video is presented
+    pub av_offset_ms: Option<f64>,
+}
+
+/// List programs
+pub async fn programs(State(state): State<AppState>) -> impl IntoResponse {
+    let programs = state.media_server.programs().await;
+    (StatusCode::OK, Json(ApiResponse::success(programs)))
+}
+
+/// Tie an audio and a video track into a program
+pub async fn create_program(
+    State(state): State<AppState>,
+    Json(req): Json<ProgramRequest>,
+) -> impl IntoResponse {
+    match state
+        .media_server
+        .create_program(req.program_id.clone(), req.audio_track, req.video_track, req.av_offset_ms)
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(req.program_id))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Remove a program, leaving its tracks in place
+pub async fn delete_program(
+    State(state): State<AppState>,
+    Path(program_id): Path<String>,
+) -> impl IntoResponse {
+    if state.media_server.remove_program(&program_id).await {
+        (StatusCode::OK, Json(ApiResponse::success(program_id)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Program not found: {}", program_id))),
+        )
+    }
+}
+
+/// Tiered video creation request
+#[derive(Debug, Deserialize)]
+pub struct TieredVideoRequest {
+    pub stream_id: String,
+    
+    /// Track carrying each tier's encoding, e.g. `{"low": "stage-360p"}`
+    pub tiers: BTreeMap<QualityTier, String>,
+}
+
+/// List tiered videos
+pub async fn video_tiers(State(state): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(ApiResponse::success(state.media_server.video_tiers())))
+}
+
+/// Bind the encodings of a video into one stream of tiers
+pub async fn create_tiered_video(
+    State(state): State<AppState>,
+    Json(req): Json<TieredVideoRequest>,
+) -> impl IntoResponse {
+    let video = TieredVideo {
+        stream_id: req.stream_id.clone(),
+        tiers: req.tiers,
+    };
+    match state.media_server.create_tiered_video(video).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(req.stream_id))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Remove a tiered video, leaving its tracks in place
+pub async fn delete_tiered_video(
+    State(state): State<AppState>,
+    Path(stream_id): Path<String>,
+) -> impl IntoResponse {
+    if state.media_server.remove_tiered_video(&stream_id).await {
+        (StatusCode::OK, Json(ApiResponse::success(stream_id)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Tiered video not found: {}", stream_id))),
+        )
+    }
+}
+
+/// Get per-stream and per-client media statistics
+pub async fn media_stats(State(state): State<AppState>) -> impl IntoResponse {
+    let stats = state.media_server.stats().await;
+    (StatusCode::OK, Json(ApiResponse::success(stats)))
+}
+
+/// Get the WebRTC statistics of each client's peer connection
+///
+/// Statistics are collected every few seconds; this returns the latest
+/// collection without querying the connections.
+pub async fn webrtc_stats(State(state): State<AppState>) -> impl IntoResponse {
+    let stats = state.media_server.webrtc_stats();
+    (StatusCode::OK, Json(ApiResponse::success(stats)))
+}
+
 /// Get connected clients
 pub async fn connected_clients(State(state): State<AppState>) -> impl IntoResponse {
     let clients = state.control_server.get_connected_clients().await;
     (StatusCode::OK, Json(ApiResponse::success(clients)))
-}
\ No newline at end of file
+}
+
+/// Per-client egress in the Prometheus text format
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let clients = state.control_server.get_connected_clients().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&clients),
+    )
+}
+
+fn render_metrics(clients: &[ClientInfo]) -> String {
+    let mut out = String::new();
+    let families = [
+        ("solusync_client_egress_bytes_total", "Bytes sent to each connected client"),
+        ("solusync_client_egress_messages_total", "Messages and packets sent to each connected client"),
+    ];
+    for (name, help) in families {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for client in clients {
+            for (path, egress) in [("control", client.control_egress), ("media", client.media_egress)] {
+                let value = match name {
+                    "solusync_client_egress_bytes_total" => egress.bytes,
+                    _ => egress.messages,
+                };
+                let _ = writeln!(
+                    out,
+                    "{}{{client_id=\"{}\",path=\"{}\"}} {}",
+                    name, client.client_id, path, value
+                );
+            }
+        }
+    }
+    out
+}
+
+/// Get a client's future buffer statistics
+pub async fn client_buffer(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+) -> impl IntoResponse {
+    buffer_stats_response(client_id, state.media_server.get_buffer_stats(client_id).await)
+}
+
+/// Zero a client's buffer counters, such as after a tuning session
+pub async fn reset_client_buffer(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+) -> impl IntoResponse {
+    buffer_stats_response(client_id, state.media_server.reset_buffer_stats(client_id).await)
+}
+
+/// Override a client's buffer latency bounds
+///
+/// Bounds left out fall back to those from the client's hello, then the
+/// server's; an empty body clears the override.
+pub async fn configure_client_buffer(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    Json(bounds): Json<LatencyBounds>,
+) -> impl IntoResponse {
+    match state
+        .media_server
+        .set_latency_bounds(client_id, BoundsSource::Api, bounds)
+        .await
+    {
+        Ok(stats) => buffer_stats_response(client_id, stats),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Forget the buffer tuning cached for a device, so its next connection
+/// starts from the defaults
+///
+/// The ID is the device ID shown in the media stats of its clients.
+pub async fn clear_device_tuning(
+    State(state): State<AppState>,
+    Path(device_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if state.media_server.clear_device_tuning(device_id) {
+        (StatusCode::OK, Json(ApiResponse::success(device_id)))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("No tuning cached for device {}", device_id))),
+        )
+    }
+}
+
+fn buffer_stats_response(
+    client_id: Uuid,
+    stats: Option<BufferStats>,
+) -> (StatusCode, Json<ApiResponse<BufferStats>>) {
+    match stats {
+        Some(stats) => (StatusCode::OK, Json(ApiResponse::success(stats))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Client not found: {}", client_id))),
+        ),
+    }
+}
+
+/// Subscription change request
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionRequest {
+    /// Tracks to subscribe to
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+    
+    /// Tracks to unsubscribe from, applied before subscribing
+    #[serde(default)]
+    pub unsubscribe: Vec<String>,
+}
+
+/// Change a client's track subscriptions
+///
+/// Subscribing to an already subscribed track is a no-op. Returns the
+/// client's subscriptions afterwards.
+pub async fn update_subscriptions(
+    State(state): State<AppState>,
+    Path(client_id): Path<Uuid>,
+    Json(req): Json<SubscriptionRequest>,
+) -> impl IntoResponse {
+    let media = &state.media_server;
+    let Some(subscribed) = media.client_subscriptions(client_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("Client not found: {}", client_id))),
+        );
+    };
+    
+    for track_id in &req.unsubscribe {
+        if let Err(e) = media.unsubscribe_client(client_id, track_id).await {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+        }
+    }
+    for track_id in req.subscribe {
+        if subscribed.contains(&track_id) && !req.unsubscribe.contains(&track_id) {
+            continue;
+        }
+        if let Err(e) = media.subscribe_client(client_id, track_id).await {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+        }
+    }
+    
+    let subscribed = media.client_subscriptions(client_id).await.unwrap_or_default();
+    (StatusCode::OK, Json(ApiResponse::success(subscribed)))
+}
+
+/// Zone membership change request
+#[derive(Debug, Deserialize)]
+pub struct ZoneRequest {
+    pub zone: String,
+    
+    /// Clients to move into the zone
+    #[serde(default)]
+    pub join: Vec<Uuid>,
+    
+    /// Clients to remove from the zone; clients in other zones are left alone
+    #[serde(default)]
+    pub leave: Vec<Uuid>,
+}
+
+/// Change which clients belong to a zone
+///
+/// Returns the zone's members afterwards.
+pub async fn update_zone(
+    State(state): State<AppState>,
+    Json(req): Json<ZoneRequest>,
+) -> impl IntoResponse {
+    let media = &state.media_server;
+    if req.zone.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Zone name is empty".into())));
+    }
+    for client_id in req.join.iter().chain(&req.leave) {
+        if media.client_subscriptions(*client_id).await.is_none() {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error(format!("Client not found: {}", client_id))),
+            );
+        }
+    }
+    
+    for client_id in req.leave {
+        if media.client_zone(client_id).as_deref() != Some(req.zone.as_str()) {
+            continue;
+        }
+        if let Err(e) = media.leave_zone(client_id).await {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+        }
+    }
+    for client_id in req.join {
+        if let Err(e) = media.join_zone(client_id, req.zone.clone()).await {
+            return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+        }
+    }
+    
+    (StatusCode::OK, Json(ApiResponse::success(media.zone_status(&req.zone))))
+}
+
+/// Token request
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    /// Node ID the client will send in its hello header
+    pub node_id: Uuid,
+    
+    #[serde(default = "default_token_ttl")]
+    pub ttl_secs: u64,
+    
+    /// Mint an ingest token granting the publisher role instead of an auth
+    /// token
+    #[serde(default)]
+    pub ingest: bool,
+}
+
+fn default_token_ttl() -> u64 {
+    3600
+}
+
+/// Issue a signed token for a client's hello
+///
+/// Refused while the control API is open, as anyone could then mint
+/// themselves a token.
+pub async fn mint_token(
+    State(state): State<AppState>,
+    Json(req): Json<TokenRequest>,
+) -> impl IntoResponse {
+    if state.config.admin_token.is_none() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("SOLUSYNC_ADMIN_TOKEN is not set".into())),
+        );
+    }
+    
+    let ttl = std::time::Duration::from_secs(req.ttl_secs);
+    let (token, secret) = if req.ingest {
+        (state.control_server.mint_ingest_token(req.node_id, ttl), "SOLUSYNC_INGEST_SECRET")
+    } else {
+        (state.control_server.mint_token(req.node_id, ttl), "SOLUSYNC_AUTH_SECRET")
+    };
+    match token {
+        Some(token) => (StatusCode::OK, Json(ApiResponse::success(token))),
+        None => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!("{} is not set", secret))),
+        ),
+    }
+}
+
+/// Upload an audio file and register it in the track catalog
+///
+/// Expects multipart/form-data with the audio file in a `file` field.
+pub async fn upload_track(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    match store_upload(&state, &mut multipart).await {
+        Ok(track) => (StatusCode::OK, Json(ApiResponse::success(track))),
+        Err((status, message)) => (status, Json(ApiResponse::error(message))),
+    }
+}
+
+/// Stream the uploaded file to disk and register it
+async fn store_upload(
+    state: &AppState,
+    multipart: &mut Multipart,
+) -> Result<TrackInfo, (StatusCode, String)> {
+    let media_dir = &state.config.media_dir;
+    tokio::fs::create_dir_all(media_dir)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (e.status(), e.body_text()))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+        
+        let extension = field
+            .file_name()
+            .and_then(|name| std::path::Path::new(name).extension())
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+        
+        let temp_path = media_dir.join(format!(".upload-{}", Uuid::new_v4()));
+        let (content_hash, size) =
+            match write_field(&mut field, &temp_path, state.config.max_upload_bytes).await {
+                Ok(written) => written,
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&temp_path).await;
+                    return Err(e);
+                }
+            };
+        
+        return state
+            .media_server
+            .register_upload(&temp_path, media_dir, content_hash, size, extension)
+            .await
+            .map_err(|e| (catalog_error_status(&e), e.to_string()));
+    }
+    
+    Err((StatusCode::BAD_REQUEST, "Missing \"file\" field".to_string()))
+}
+
+/// Write a multipart field to `path`, returning its SHA-256 and size
+async fn write_field(
+    field: &mut Field<'_>,
+    path: &std::path::Path,
+    max_bytes: u64,
+) -> Result<(String, u64), (StatusCode, String)> {
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    
+    let mut file = tokio::fs::File::create(path).await.map_err(io_error)?;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    
+    while let Some(chunk) = field.chunk().await.map_err(|e| (e.status(), e.body_text()))? {
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload exceeds limit of {} bytes", max_bytes),
+            ));
+        }
+        
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(io_error)?;
+    }
+    
+    file.flush().await.map_err(io_error)?;
+    
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+fn catalog_error_status(error: &CatalogError) -> StatusCode {
+    match error {
+        CatalogError::UnsupportedMedia(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        CatalogError::NotFound(_) => StatusCode::NOT_FOUND,
+        CatalogError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// List the track catalog
+pub async fn list_tracks(State(state): State<AppState>) -> impl IntoResponse {
+    let tracks = state.media_server.catalog().list().await;
+    (StatusCode::OK, Json(ApiResponse::success(tracks)))
+}
+
+/// Delete a track from the catalog
+pub async fn delete_track(
+    State(state): State<AppState>,
+    Path(track_id): Path<String>,
+) -> impl IntoResponse {
+    match state.media_server.delete_track(&track_id).await {
+        Ok(track) => (StatusCode::OK, Json(ApiResponse::success(track))),
+        Err(e) => (catalog_error_status(&e), Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Enqueue request
+#[derive(Debug, Deserialize)]
+pub struct EnqueueRequest {
+    pub items: Vec<QueueItem>,
+}
+
+/// Get the play queue
+pub async fn get_queue(State(state): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(ApiResponse::success(state.media_server.queue_status())))
+}
+
+/// Append tracks to the play queue
+pub async fn enqueue(
+    State(state): State<AppState>,
+    Json(req): Json<EnqueueRequest>,
+) -> impl IntoResponse {
+    match state.media_server.enqueue(req.items).await {
+        Ok(queue) => (StatusCode::OK, Json(ApiResponse::success(queue))),
+        Err(e) => (catalog_error_status(&e), Json(ApiResponse::error(e.to_string()))),
+    }
+}
+
+/// Remove a waiting item from the play queue
+pub async fn remove_queue_item(
+    State(state): State<AppState>,
+    Path(index): Path<usize>,
+) -> impl IntoResponse {
+    match state.media_server.remove_queue_item(index).await {
+        Some(item) => (StatusCode::OK, Json(ApiResponse::success(item))),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("No queue item at index {}", index))),
+        ),
+    }
+}
+
+/// Skip to the next queue item
+///
+/// Returns the Play command announced to clients, or null if the queue is
+/// empty.
+pub async fn skip_queue(State(state): State<AppState>) -> impl IntoResponse {
+    match state.media_server.skip().await {
+        Ok(started) => (StatusCode::OK, Json(ApiResponse::success(started))),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ClockManager, config::ServerConfig, control::ControlServer, media::MediaServer};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_pause_accepts_request_object_and_bare_track_id() {
+        use axum::{body::Body, extract::Request, routing::post, Router};
+        use tower::ServiceExt;
+
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server,
+            control_server,
+        };
+        let app = Router::new().route("/api/pause", post(pause)).with_state(state);
+
+        let cases = [
+            (r#"{"track_id": "track"}"#, StatusCode::OK),
+            (r#""track""#, StatusCode::OK),
+            (r#"{"track_id": ""}"#, StatusCode::BAD_REQUEST),
+            (r#"{"track": "track"}"#, StatusCode::UNPROCESSABLE_ENTITY),
+            (r#"{"track_id": "#, StatusCode::BAD_REQUEST),
+        ];
+        for (body, status) in cases {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/api/pause")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+
+            if status == StatusCode::OK {
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(response["data"], "track");
+            }
+        }
+    }
+
+    /// Log output captured by a test's subscriber
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_reaches_the_media_control_logs() {
+        use axum::{body::Body, extract::Request, middleware, routing::post, Router};
+        use tower::ServiceExt;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        // The test runtime runs the media server on this thread too
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        tokio::spawn(media_server.clone().run());
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server,
+            control_server,
+        };
+        let app = Router::new()
+            .route("/api/play", post(play))
+            .layer(middleware::from_fn(crate::request_id::propagate_request_id))
+            .with_state(state);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api/play")
+            .header("content-type", "application/json")
+            .header("x-request-id", "req-42")
+            .body(Body::from(r#"{"track_id": "missing"}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "req-42");
+
+        // The command is processed after the response, so wait for its failure
+        let mut failure = None;
+        for _ in 0..100 {
+            let output = String::from_utf8(logs.0.lock().clone()).unwrap();
+            failure = output.lines().find(|line| line.contains("Error processing control command")).map(str::to_string);
+            if failure.is_some() {
+                assert!(
+                    output.lines().any(|line| line.contains("Play track missing") && line.contains("request_id=\"req-42\"")),
+                    "{}",
+                    output
+                );
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let failure = failure.expect("control command did not fail");
+        assert!(failure.contains("request_id=\"req-42\""), "{}", failure);
+    }
+
+    #[tokio::test]
+    async fn test_recording_and_capture_names_from_track_ids_stay_in_their_dirs() {
+        let clock = Arc::new(ClockManager::new());
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", uuid::Uuid::new_v4()));
+        let config = Arc::new(ServerConfig {
+            media_dir: media_dir.clone(),
+            capture_dir: media_dir.join("captures"),
+            ..Default::default()
+        });
+        let media_server = Arc::new(MediaServer::with_config(clock.clone(), config.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server,
+            control_server,
+        };
+
+        for track_id in ["../../escape", ".hidden", "a\\b"] {
+            let response = record_stream(
+                State(state.clone()),
+                Path(track_id.to_string()),
+                Json(RecordRequest::default()),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", track_id);
+            let response = capture_stream(
+                State(state.clone()),
+                Path(track_id.to_string()),
+                Json(CaptureRequest::default()),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", track_id);
+        }
+        assert!(!media_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_minted_tokens_pass_the_hello_checks() {
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig {
+            auth_secret: Some("auth-secret".into()),
+            admin_token: crate::auth::AdminToken::new("s3cret"),
+            ..Default::default()
+        });
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server,
+            control_server,
+        };
+        let node_id = Uuid::new_v4();
+
+        let response = mint_token(
+            State(state.clone()),
+            Json(TokenRequest { node_id, ttl_secs: 60, ingest: false }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let token = response["data"].as_str().unwrap();
+        let authority = crate::control::TokenAuthority::new("auth-secret");
+        authority.verify(token, node_id).unwrap();
+        assert!(authority.verify(token, Uuid::new_v4()).is_err());
+
+        // Ingest authentication is off
+        let response = mint_token(
+            State(state.clone()),
+            Json(TokenRequest { node_id, ttl_secs: 60, ingest: true }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Nobody may mint tokens through an open control API
+        let open = AppState {
+            config: Arc::new(ServerConfig {
+                admin_token: None,
+                ..(*state.config).clone()
+            }),
+            ..state
+        };
+        let response = mint_token(
+            State(open),
+            Json(TokenRequest { node_id, ttl_secs: 60, ingest: false }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_time_returns_ordered_server_timestamps() {
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock.clone(),
+            media_server,
+            control_server,
+        };
+
+        let before = clock.now().await;
+        let Json(response) = time(State(state)).await;
+        let after = clock.now().await;
+
+        let TimeResponse { t2, t3 } = response.data.unwrap();
+        assert!(before <= t2 && t2 <= t3 && t3 <= after);
+        assert!(after - before < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_detailed_status_reflects_streams_and_peers() {
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock.clone(),
+            media_server: media_server.clone(),
+            control_server,
+        };
+
+        // Nothing is processing samples or commands yet
+        let Json(response) = detailed_status(State(state.clone())).await;
+        let status = response.data.unwrap();
+        assert_eq!(status.clock.state, HealthState::Down);
+        assert_eq!(status.media.state, HealthState::Down);
+        assert_eq!(status.state, HealthState::Down);
+
+        tokio::spawn(clock.clone().run());
+        tokio::spawn(media_server.clone().run());
+        media_server.create_stream("track".into(), "opus".into()).await.unwrap();
+        let peer = Uuid::new_v4();
+        for _ in 0..20 {
+            let sample = crate::clock::ClockSample {
+                offset: 0.002,
+                rtt: 0.01,
+            };
+            clock.add_sample(peer, sample).await.unwrap();
+        }
+        for _ in 0..100 {
+            if clock.get_peer_stats(&peer).await.is_some_and(|stats| stats.sample_count == 20) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let Json(response) = detailed_status(State(state)).await;
+        let status = response.data.unwrap();
+        assert_eq!(status.state, HealthState::Ok);
+        assert_eq!(status.clock.peer_count, 1);
+        assert_eq!(status.clock.converged_peers, 1);
+        assert!(status.clock.converged);
+        assert!((status.clock.max_offset - 0.002).abs() < 1e-6);
+        assert_eq!(status.media.state, HealthState::Ok);
+        assert_eq!(status.media.streams, 1);
+        assert_eq!(status.media.playing_streams, 0);
+        assert_eq!(status.connections.active, 0);
+        assert_eq!(status.connections.auth_failures, 0);
+    }
+
+    #[test]
+    fn test_metrics_render_egress_per_client_and_path() {
+        let client_id = Uuid::new_v4();
+        let client = ClientInfo {
+            client_id,
+            node_type: crate::protocol::NodeType::Client,
+            role: crate::control::ClientRole::Listener,
+            capabilities: Vec::new(),
+            remote_addr: None,
+            connected_at: chrono::Utc::now(),
+            dropped_messages: 0,
+            queue_overflows: 0,
+            missing_messages: 0,
+            out_of_order_messages: 0,
+            control_egress: crate::media::Egress { bytes: 512, messages: 4 },
+            media_egress: crate::media::Egress { bytes: 9000, messages: 30 },
+        };
+        let rendered = render_metrics(&[client]);
+        let expected = [
+            format!("solusync_client_egress_bytes_total{{client_id=\"{}\",path=\"control\"}} 512", client_id),
+            format!("solusync_client_egress_bytes_total{{client_id=\"{}\",path=\"media\"}} 9000", client_id),
+            format!("solusync_client_egress_messages_total{{client_id=\"{}\",path=\"media\"}} 30", client_id),
+        ];
+        for line in expected {
+            assert!(rendered.lines().any(|l| l == line), "missing {:?} in {}", line, rendered);
+        }
+        assert!(rendered.contains("# TYPE solusync_client_egress_messages_total counter"));
+    }
+
+    #[tokio::test]
+    async fn test_client_buffer_stats_and_reset() {
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::new(clock.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server: media_server.clone(),
+            control_server,
+        };
+        let body = |response: axum::response::Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let unknown = Uuid::new_v4();
+        let response = client_buffer(State(state.clone()), Path(unknown)).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = reset_client_buffer(State(state.clone()), Path(unknown)).await.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let client_id = Uuid::new_v4();
+        media_server.add_client(client_id).await.unwrap();
+        let response = client_buffer(State(state.clone()), Path(client_id)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats = body(response).await["data"].clone();
+        assert_eq!(stats["target_latency_ms"], 80);
+        assert_eq!(stats["underrun_count"], 0);
+        assert_eq!(stats["network_quality"], "Good");
+        for counter in ["overrun_count", "queued_frames", "occupancy_ms", "late_frames", "dropped_late_frames"] {
+            assert_eq!(stats[counter], 0, "{}", counter);
+        }
+
+        let response = reset_client_buffer(State(state), Path(client_id)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await["data"]["target_latency_ms"], 80);
+    }
+
+    #[tokio::test]
+    async fn test_client_buffer_config_overrides_hello_bounds() {
+        use crate::{
+            control::SequenceTracker,
+            protocol::{HelloMessage, Message, NodeType},
+        };
+        use tokio_util::sync::CancellationToken;
+
+        let clock = Arc::new(ClockManager::new());
+        let config = Arc::new(ServerConfig::default());
+        let media_server = Arc::new(MediaServer::with_config(clock.clone(), config.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager: clock,
+            media_server: media_server.clone(),
+            control_server: control_server.clone(),
+        };
+
+        let hello = |min_latency_ms, max_acceptable_latency_ms| {
+            serde_json::to_string(&Message::Hello(HelloMessage {
+                header: MessageHeader::new(Uuid::new_v4(), 0),
+                protocol_version: "0.1.0".into(),
+                capabilities: vec![],
+                node_type: NodeType::Client,
+                auth_token: None,
+                zone: None,
+                session_token: None,
+                min_latency_ms,
+                max_acceptable_latency_ms,
+                ingest_token: None,
+            }))
+            .unwrap()
+        };
+        let connect = |text: String| {
+            let control_server = control_server.clone();
+            async move {
+                let client_id = Uuid::new_v4();
+                let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+                control_server
+                    .handle_text(
+                        &client_id,
+                        &text,
+                        &tx,
+                        &CancellationToken::new(),
+                        &Arc::new(SequenceTracker::new()),
+                        None,
+                    )
+                    .await;
+                (client_id, rx.try_recv().unwrap())
+            }
+        };
+        let bounds_of = |client_id| {
+            let media_server = media_server.clone();
+            async move {
+                let stats = media_server.get_buffer_stats(client_id).await.unwrap();
+                (stats.min_latency_ms, stats.max_latency_ms)
+            }
+        };
+
+        // A hello asking for a floor over its ceiling is refused
+        let (client_id, reply) = connect(hello(Some(200), Some(100))).await;
+        assert!(matches!(reply, Message::Error(_)));
+        assert!(media_server.get_buffer_stats(client_id).await.is_none());
+
+        let (client_id, reply) = connect(hello(None, Some(60))).await;
+        assert!(matches!(reply, Message::Hello(_)));
+        assert_eq!(bounds_of(client_id).await, (30, 60));
+
+        let configure = |client_id, bounds: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                let bounds = serde_json::from_value(bounds).unwrap();
+                configure_client_buffer(State(state), Path(client_id), Json(bounds))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        let status = configure(client_id, serde_json::json!({"min_latency_ms": 100})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(bounds_of(client_id).await, (30, 60));
+
+        let status = configure(client_id, serde_json::json!({"min_latency_ms": 100, "max_latency_ms": 400})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bounds_of(client_id).await, (100, 400));
+
+        let status = configure(client_id, serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bounds_of(client_id).await, (30, 60));
+
+        let status = configure(Uuid::new_v4(), serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,104 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::protocol::NodeType;
+
+/// Length of the nonce a node is challenged to sign
+const NONCE_LEN: usize = 32;
+
+/// Why a node failed the key handshake
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("no public key announced")]
+    MissingKey,
+
+    #[error("announced public key is not a valid ed25519 key")]
+    InvalidKey,
+
+    #[error("signature is malformed")]
+    MalformedSignature,
+
+    #[error("signature does not match the announced key")]
+    BadSignature,
+}
+
+/// Outstanding challenge for a node announcing itself as Master or Replica
+///
+/// The node is only trusted in its announced role once it returns an
+/// ed25519 signature over the nonce made with the private key matching the
+/// public key it announced.
+#[derive(Debug, Clone)]
+pub struct NodeChallenge {
+    node_type: NodeType,
+    public_key: VerifyingKey,
+    nonce: [u8; NONCE_LEN],
+}
+
+impl NodeChallenge {
+    /// Challenge a node announcing `node_type` with `public_key`
+    pub fn new(node_type: NodeType, public_key: Option<&[u8]>) -> Result<Self, HandshakeError> {
+        let public_key = public_key.ok_or(HandshakeError::MissingKey)?;
+        let public_key = <&[u8; 32]>::try_from(public_key).map_err(|_| HandshakeError::InvalidKey)?;
+        let public_key = VerifyingKey::from_bytes(public_key).map_err(|_| HandshakeError::InvalidKey)?;
+
+        Ok(Self {
+            node_type,
+            public_key,
+            nonce: rand::random(),
+        })
+    }
+
+    /// Role the node is trusted with once the challenge is met
+    pub fn node_type(&self) -> NodeType {
+        self.node_type
+    }
+
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// Key the node announced, trusted once the challenge is met
+    pub fn public_key(&self) -> VerifyingKey {
+        self.public_key
+    }
+
+    /// Check a signature over the nonce against the announced key
+    pub fn verify(&self, signature: &[u8]) -> Result<(), HandshakeError> {
+        let signature = Signature::from_slice(signature).map_err(|_| HandshakeError::MalformedSignature)?;
+        self.public_key
+            .verify_strict(&self.nonce, &signature)
+            .map_err(|_| HandshakeError::BadSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_signature_must_match_announced_key() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[9; 32]);
+        let challenge = NodeChallenge::new(
+            NodeType::Replica,
+            Some(key.verifying_key().as_bytes()),
+        )
+        .unwrap();
+
+        let signature = key.sign(challenge.nonce()).to_bytes();
+        assert_eq!(challenge.verify(&signature), Ok(()));
+
+        let forged = other.sign(challenge.nonce()).to_bytes();
+        assert_eq!(challenge.verify(&forged), Err(HandshakeError::BadSignature));
+        assert_eq!(challenge.verify(&[0; 10]), Err(HandshakeError::MalformedSignature));
+
+        assert_eq!(
+            NodeChallenge::new(NodeType::Replica, None).unwrap_err(),
+            HandshakeError::MissingKey
+        );
+        assert_eq!(
+            NodeChallenge::new(NodeType::Replica, Some(&[1; 5])).unwrap_err(),
+            HandshakeError::InvalidKey
+        );
+    }
+}
@@ -1,26 +1,62 @@
 use anyhow::Result;
 use axum::extract::ws::{Message, WebSocket};
-use futures::{SinkExt, StreamExt};
+use futures::StreamExt;
 use tokio::sync::RwLock;
 use std::{
     collections::HashMap,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
     net::SocketAddr,
 };
-use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, error::TrySendError},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+mod auth;
+mod capability;
+mod datachannel;
+mod election;
+mod error;
 pub mod handlers;
+mod handshake;
+mod outgoing;
+mod sequence;
+mod signing;
+
+pub use auth::TokenAuthority;
+pub use capability::{CapabilityMap, ClientOperation, ClientRole};
+pub use datachannel::{ChannelMessage, Lane, DATA_CHANNEL_CAPABILITY};
+pub use election::{
+    CandidateProfile, DemotionPolicy, ElectionOutcome, ElectionState, ElectionWeights, NodeHealth,
+    RttHistory,
+};
+pub use error::ControlError;
+pub use handshake::NodeChallenge;
+pub use sequence::{SequenceCheck, SequenceTracker};
+pub use signing::{verify_cluster_message, SignatureError};
 
 use crate::{
     clock::ClockManager,
-    media::MediaServer,
+    config::ServerConfig,
+    health::HealthState,
+    media::{stream_key, BoundsSource, CodecSupport, Egress, EgressCounter, CLOCK_CHANNEL_CAPABILITY, DetachedClient, LatencyBounds, MediaServer, WebRtcServer},
     protocol::{
-        ErrorCode, ErrorMessage, HelloMessage, Message as ProtoMessage, MessageHeader, NodeType,
+        BufferReportAckMessage, BufferReportMessage, ClockSyncedMessage, ErrorCode, ErrorMessage, HelloMessage,
+        ConcealmentMessage, KeyframeRequestMessage, MediaAction, Message as ProtoMessage, MessageHeader, MasterElectionMessage,
+        NodeAnnounceMessage, NodeChallengeMessage, NodeChallengeResponseMessage, NodeStatusMessage,
+        NodeType, RateAdjustMessage, RtpClockMessage, SdpAnswerMessage, SdpOfferMessage, TranscodeRequestMessage,
     },
 };
 
+/// Capability a client must declare to publish `media_data` chunks
+pub const MEDIA_SOURCE_CAPABILITY: &str = "media_source";
+
 /// Control server for handling WebSocket connections and commands
 pub struct ControlServer {
     /// Server ID
@@ -34,6 +70,81 @@ pub struct ControlServer {
     
     /// Connected clients
     clients: Arc<RwLock<HashMap<Uuid, ClientConnection>>>,
+    
+    /// Server configuration
+    config: Arc<ServerConfig>,
+    
+    /// Validates client auth tokens, if authentication is enabled
+    tokens: Option<TokenAuthority>,
+    
+    /// Validates the ingest tokens granting the publisher role, if ingest
+    /// authentication is enabled
+    ingest_tokens: Option<TokenAuthority>,
+    
+    /// Key challenges awaiting a signature, by client
+    challenges: Arc<RwLock<HashMap<Uuid, NodeChallenge>>>,
+    
+    /// Latest status reported by each cluster node
+    node_statuses: Arc<RwLock<HashMap<Uuid, NodeRecord>>>,
+    
+    /// Current election epoch, its strongest candidate and the masters
+    /// nodes claim
+    election: Arc<RwLock<ElectionState>>,
+    
+    /// Connections rejected for failing authentication
+    auth_failures: AtomicU64,
+    
+    /// State of disconnected clients awaiting a reconnect, by session token
+    sessions: Arc<RwLock<HashMap<String, ParkedSession>>>,
+}
+
+/// Last status of a cluster node and the RTTs it reported recently
+struct NodeRecord {
+    status: NodeStatusMessage,
+    rtt: RttHistory,
+}
+
+/// Client kept after a disconnect until it resumes or the window ends
+struct ParkedSession {
+    client_id: Uuid,
+    expires_at: tokio::time::Instant,
+    media: DetachedClient,
+}
+
+/// Client connection health
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionHealth {
+    /// `degraded` while any client is dropping broadcasts
+    pub state: HealthState,
+    pub active: usize,
+    
+    /// Clients whose send queue overflowed on the last broadcast
+    pub lagging: usize,
+    
+    /// Connections rejected for failing authentication since startup
+    pub auth_failures: u64,
+    
+    /// Epoch of the current master election
+    pub election_epoch: u64,
+    
+    /// Node currently leading the election, if any
+    pub election_leader: Option<Uuid>,
+    
+    /// Conflicting master claims detected since startup
+    pub split_brains: u64,
+}
+
+/// How broadcasts treat clients whose send queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastPolicy {
+    /// Wait for queue space (a slow client delays everyone)
+    Block,
+    
+    /// Drop the message for that client
+    Drop,
+    
+    /// Drop the message, and disconnect the client after this many consecutive drops
+    Disconnect { max_consecutive_drops: u32 },
 }
 
 /// Connected client information
@@ -41,53 +152,287 @@ pub struct ControlServer {
 pub struct ClientConnection {
     pub client_id: Uuid,
     pub node_type: NodeType,
+    /// Whether the client may publish media
+    pub role: ClientRole,
     pub tx: mpsc::Sender<ProtoMessage>,
     pub capabilities: Vec<String>,
     pub remote_addr: Option<SocketAddr>,
     pub connected_at: chrono::DateTime<chrono::Utc>,
+    /// Broadcast messages dropped because the send queue was full
+    pub dropped_messages: Arc<AtomicU64>,
+    /// Messages that found the send queue full, whether they were then
+    /// dropped or waited for space
+    pub queue_overflows: Arc<AtomicU64>,
+    /// Drops since the last successful broadcast send
+    pub consecutive_drops: Arc<AtomicU32>,
+    /// Cancelled to force the connection closed
+    pub disconnect: CancellationToken,
+    /// Sequence numbers of messages received from the client
+    pub sequence: Arc<SequenceTracker>,
+    /// Node key verified by the announce handshake
+    pub public_key: Option<ed25519_dalek::VerifyingKey>,
+    /// Token the client presents in its hello to resume after a reconnect
+    pub session_token: String,
+    /// Bytes written to the client's WebSocket and control data channels
+    /// by its current connection
+    pub egress: Arc<EgressCounter>,
+}
+
+impl ClientConnection {
+    /// Queue a message, waiting for space if the send queue is full
+    ///
+    /// Waiting is counted as a queue overflow.
+    pub async fn send(&self, message: ProtoMessage) -> Result<(), mpsc::error::SendError<ProtoMessage>> {
+        match self.tx.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(message)) => {
+                self.queue_overflows.fetch_add(1, Ordering::Relaxed);
+                debug!("Send queue full for client {}, waiting", self.client_id);
+                self.tx.send(message).await
+            }
+            Err(TrySendError::Closed(message)) => Err(mpsc::error::SendError(message)),
+        }
+    }
 }
 
 impl ControlServer {
-    pub fn new(clock_manager: Arc<ClockManager>, media_server: Arc<MediaServer>) -> Self {
+    pub fn new(
+        clock_manager: Arc<ClockManager>,
+        media_server: Arc<MediaServer>,
+        config: Arc<ServerConfig>,
+    ) -> Self {
         Self {
             server_id: Uuid::new_v4(),
             clock_manager,
             media_server,
             clients: Arc::new(RwLock::new(HashMap::new())),
+            tokens: config.auth_secret.as_deref().map(TokenAuthority::new),
+            ingest_tokens: config.ingest_secret.as_deref().map(TokenAuthority::new),
+            challenges: Arc::new(RwLock::new(HashMap::new())),
+            node_statuses: Arc::new(RwLock::new(HashMap::new())),
+            election: Arc::new(RwLock::new(ElectionState::default())),
+            auth_failures: AtomicU64::new(0),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            config,
+        }
+    }
+    
+    /// Mint an auth token for the client with node ID `client_id`
+    ///
+    /// Returns `None` when authentication is disabled.
+    pub fn mint_token(&self, client_id: Uuid, ttl: std::time::Duration) -> Option<String> {
+        self.tokens.as_ref().map(|tokens| tokens.mint(client_id, ttl))
+    }
+    
+    /// Mint an ingest token letting the client with node ID `client_id`
+    /// publish media
+    ///
+    /// Returns `None` when ingest authentication is disabled.
+    pub fn mint_ingest_token(&self, client_id: Uuid, ttl: std::time::Duration) -> Option<String> {
+        self.ingest_tokens.as_ref().map(|tokens| tokens.mint(client_id, ttl))
+    }
+    
+    /// Run the control server background task
+    ///
+    /// Forwards media control events (e.g. seeks) and playback progress
+    /// reports to all connected clients, or only to the members of the
+    /// event's zone, and ICE restart offers, keyframe requests, concealment
+    /// requests, RTP clock mappings and clock convergence changes to the
+    /// client they are for.
+    pub async fn run(self: Arc<Self>) {
+        let mut events = self.media_server.subscribe_control_events();
+        let mut progress = self.media_server.subscribe_progress_events();
+        let mut offers = self.media_server.subscribe_ice_restart_offers();
+        let mut keyframe_requests = self.media_server.subscribe_keyframe_requests();
+        let mut concealment_requests = self.media_server.subscribe_concealment_requests();
+        let mut rtp_clocks = self.media_server.subscribe_rtp_clocks();
+        let mut convergence = self.clock_manager.subscribe_convergence();
+        
+        loop {
+            let (message, zone) = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(control) => {
+                        let zone = control.zone.clone();
+                        (ProtoMessage::MediaControl(control), zone)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Skipped {} media control events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                report = progress.recv() => match report {
+                    Ok(report) => {
+                        let zone = report.zone.clone();
+                        (ProtoMessage::PlaybackProgress(report), zone)
+                    }
+                    // A later report supersedes the skipped ones
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                offer = offers.recv() => match offer {
+                    Ok((client_id, offer)) => {
+                        let message = ProtoMessage::SdpOffer(SdpOfferMessage {
+                            header: MessageHeader::new(self.server_id, 0),
+                            sdp: offer.sdp,
+                            ice_restart: true,
+                        });
+                        if let Err(e) = self.broadcast_to(message, Some(&[client_id])).await {
+                            warn!("Failed to send ICE restart offer to {}: {}", client_id, e);
+                        }
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Skipped {} ICE restart offers", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                request = keyframe_requests.recv() => match request {
+                    Ok(request) => {
+                        let message = ProtoMessage::KeyframeRequest(KeyframeRequestMessage {
+                            header: MessageHeader::new(self.server_id, 0),
+                            track_id: request.track_id,
+                            reason: request.reason,
+                        });
+                        if let Err(e) = self.broadcast_to(message, Some(&[request.producer_id])).await {
+                            debug!("Failed to send keyframe request to {}: {}", request.producer_id, e);
+                        }
+                        continue;
+                    }
+                    // Subscribers still waiting ask again
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                request = concealment_requests.recv() => match request {
+                    Ok((client_id, request)) => {
+                        let message = ProtoMessage::Concealment(ConcealmentMessage {
+                            header: MessageHeader::new(self.server_id, 0),
+                            track_id: request.track_id,
+                            first_sequence: request.first_sequence,
+                            missing: request.missing,
+                            timestamp: request.timestamp,
+                            duration: request.duration.as_secs_f64(),
+                        });
+                        if let Err(e) = self.broadcast_to(message, Some(&[client_id])).await {
+                            debug!("Failed to send concealment request to {}: {}", client_id, e);
+                        }
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Skipped {} concealment requests", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                anchor = rtp_clocks.recv() => match anchor {
+                    Ok((client_id, codec, mapping)) => {
+                        let message = ProtoMessage::RtpClock(RtpClockMessage {
+                            header: MessageHeader::new(self.server_id, 0),
+                            kind: if codec.is_audio() { "audio" } else { "video" }.to_string(),
+                            codec: codec.name().to_string(),
+                            clock_rate: mapping.clock_rate,
+                            rtp_timestamp: mapping.rtp_anchor,
+                            network_time: mapping.network_anchor,
+                        });
+                        if let Err(e) = self.broadcast_to(message, Some(&[client_id])).await {
+                            warn!("Failed to send RTP clock mapping to {}: {}", client_id, e);
+                        }
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Skipped {} RTP clock mappings", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                change = convergence.recv() => match change {
+                    Ok(change) => {
+                        let message = ProtoMessage::ClockSynced(ClockSyncedMessage {
+                            header: MessageHeader::new(self.server_id, 0),
+                            synced: change.synced,
+                            offset_ms: change.offset * 1000.0,
+                            offset_stddev_ms: change.offset_stddev * 1000.0,
+                        });
+                        if let Err(e) = self.broadcast_to(message, Some(&[change.peer_id])).await {
+                            debug!("Failed to send clock sync state to {}: {}", change.peer_id, e);
+                        }
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Skipped {} clock convergence changes", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            
+            let recipients = zone.as_deref().map(|zone| self.media_server.zone_members(zone));
+            if let Err(e) = self.broadcast_to(message, recipients.as_deref()).await {
+                warn!("Failed to broadcast media event: {}", e);
+            }
         }
     }
     
     /// Handle new WebSocket connection
     pub async fn handle_connection(&self, websocket: WebSocket, remote_addr: Option<SocketAddr>) -> Result<()> {
-        let (mut ws_sender, mut ws_receiver) = websocket.split();
-        let (tx, mut rx) = mpsc::channel::<ProtoMessage>(100);
+        let (ws_sender, mut ws_receiver) = websocket.split();
+        let (tx, rx) = mpsc::channel::<ProtoMessage>(self.config.client_queue_size.max(1));
         
-        let client_id = Uuid::new_v4();
+        let mut client_id = Uuid::new_v4();
+        let disconnect = CancellationToken::new();
+        let sequence = Arc::new(SequenceTracker::new());
+        let egress = Arc::new(EgressCounter::default());
+        
+        // Control data channels, decided once the client's hello is in
+        let (channel_tx, mut channel_rx) = mpsc::channel::<ChannelMessage>(self.config.client_queue_size.max(1));
+        let channel_sequence = Arc::new(SequenceTracker::new());
+        let mut data_channels = None;
         info!("New WebSocket connection from {:?}: {}", remote_addr, client_id);
         
         // Spawn task to forward messages to WebSocket
-        let tx_task = tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                let json = match serde_json::to_string(&msg) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        error!("Failed to serialize message: {}", e);
-                        continue;
-                    }
-                };
-                
-                if ws_sender.send(Message::Text(json)).await.is_err() {
-                    break;
-                }
-            }
-        });
+        let tx_task = tokio::spawn(outgoing::forward_messages(
+            rx,
+            ws_sender,
+            outgoing::encode,
+            self.server_id,
+            client_id,
+            disconnect.clone(),
+            egress.clone(),
+        ));
         
         // Handle incoming messages
-        while let Some(result) = ws_receiver.next().await {
+        loop {
+            let result = tokio::select! {
+                _ = disconnect.cancelled() => {
+                    warn!("Disconnecting client {}", client_id);
+                    break;
+                }
+                Some(message) = channel_rx.recv() => {
+                    self.handle_channel_message(&client_id, message, &disconnect, &channel_sequence)
+                        .await;
+                    continue;
+                }
+                result = ws_receiver.next() => result,
+            };
+            let Some(result) = result else {
+                break;
+            };
+            
             match result {
                 Ok(Message::Text(text)) => {
-                    if let Err(e) = self.handle_message(&client_id, &text, &tx, remote_addr).await {
-                        error!("Error handling message from {}: {}", client_id, e);
+                    let resumed = self
+                        .handle_text(&client_id, &text, &tx, &disconnect, &sequence, remote_addr)
+                        .await;
+                    if let Some(resumed) = resumed {
+                        client_id = resumed;
+                    }
+                    self.attach_egress(&client_id, &egress).await;
+                    if data_channels.is_none() {
+                        data_channels = self.open_data_channels(&client_id, &channel_tx, &egress).await;
+                        if data_channels.is_some() {
+                            self.send_offer(&client_id).await;
+                        }
                     }
                 }
                 Ok(Message::Close(_)) => {
@@ -102,90 +447,500 @@ impl ControlServer {
             }
         }
         
-        // Cleanup
+        // Cleanup; the data channels close with the peer connection
+        drop(data_channels);
         self.remove_client(&client_id).await;
         tx_task.abort();
         
         Ok(())
     }
     
+    /// Handle an incoming text frame, reporting failures to the client
+    ///
+    /// Fatal errors disconnect the client after the error has been queued.
+    /// Returns the client ID the connection continues under when a hello
+    /// resumed an earlier session.
+    async fn handle_text(
+        &self,
+        client_id: &Uuid,
+        text: &str,
+        tx: &mpsc::Sender<ProtoMessage>,
+        disconnect: &CancellationToken,
+        sequence: &Arc<SequenceTracker>,
+        remote_addr: Option<SocketAddr>,
+    ) -> Option<Uuid> {
+        let error = match self
+            .handle_message(client_id, text, tx, disconnect, sequence, remote_addr)
+            .await
+        {
+            Ok(resumed) => return resumed,
+            Err(error) => error,
+        };
+        
+        self.report_error(client_id, error, tx, disconnect).await;
+        None
+    }
+    
+    /// Reply to a message that failed, disconnecting the client if the
+    /// error is fatal
+    async fn report_error(
+        &self,
+        client_id: &Uuid,
+        error: ControlError,
+        tx: &mpsc::Sender<ProtoMessage>,
+        disconnect: &CancellationToken,
+    ) {
+        warn!("Error handling message from {}: {}", client_id, error);
+        if matches!(error, ControlError::AuthError(_)) {
+            self.auth_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        
+        // Reply on the connection's own channel, since the client may not
+        // have completed its hello yet
+        let response = ProtoMessage::Error(ErrorMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            code: error.code(),
+            message: error.to_string(),
+            details: None,
+        });
+        if tx.send(response).await.is_err() {
+            debug!("Could not report error to {}: channel closed", client_id);
+        }
+        
+        if error.is_fatal() {
+            disconnect.cancel();
+        }
+    }
+    
+    /// Count a client's egress in its connection's counter
+    ///
+    /// Each hello replaces the client's record, with a counter of its own;
+    /// the connection's one is put back so that the count only starts over
+    /// with a new connection.
+    async fn attach_egress(&self, client_id: &Uuid, egress: &Arc<EgressCounter>) {
+        let attached = self
+            .clients
+            .read()
+            .await
+            .get(client_id)
+            .is_none_or(|client| Arc::ptr_eq(&client.egress, egress));
+        if !attached {
+            if let Some(client) = self.clients.write().await.get_mut(client_id) {
+                client.egress = egress.clone();
+            }
+        }
+    }
+    
+    /// Open control data channels on the peer connection of a client whose
+    /// hello advertised them
+    ///
+    /// Returns `None` until the client has completed its hello, and the
+    /// channels opened, if any, once it has.
+    async fn open_data_channels(
+        &self,
+        client_id: &Uuid,
+        incoming: &mpsc::Sender<ChannelMessage>,
+        egress: &Arc<EgressCounter>,
+    ) -> Option<Vec<Arc<webrtc::data_channel::RTCDataChannel>>> {
+        let wanted = self
+            .clients
+            .read()
+            .await
+            .get(client_id)?
+            .capabilities
+            .iter()
+            .any(|capability| capability == DATA_CHANNEL_CAPABILITY);
+        if !wanted {
+            return Some(Vec::new());
+        }
+        
+        let Some(peer_connection) = self.media_server.peer_connection(*client_id).await else {
+            return Some(Vec::new());
+        };
+        match datachannel::open(&peer_connection, *client_id, incoming.clone(), egress.clone()).await {
+            Ok(channels) => {
+                info!("Opened control data channels for {}", client_id);
+                Some(channels)
+            }
+            Err(e) => {
+                warn!("Failed to open data channels for {}: {}", client_id, e);
+                Some(Vec::new())
+            }
+        }
+    }
+    
+    /// Offer a client that completed its hello its peer connection, with
+    /// any data channels opened on it
+    ///
+    /// Peer connections that were negotiated before, such as a resumed
+    /// session's, are left alone.
+    async fn send_offer(&self, client_id: &Uuid) {
+        let Some(peer_connection) = self.media_server.peer_connection(*client_id).await else {
+            return;
+        };
+        if peer_connection.local_description().await.is_some() {
+            return;
+        }
+        
+        let offer = match WebRtcServer::create_offer(&peer_connection).await {
+            Ok(offer) => offer,
+            Err(e) => {
+                warn!("Failed to create SDP offer for {}: {}", client_id, e);
+                return;
+            }
+        };
+        let message = ProtoMessage::SdpOffer(SdpOfferMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            sdp: offer.sdp,
+            ice_restart: false,
+        });
+        if let Err(e) = self.broadcast_to(message, Some(&[*client_id])).await {
+            warn!("Failed to send SDP offer to {}: {}", client_id, e);
+        }
+    }
+    
+    /// Handle a message received on a control data channel, replying on
+    /// the same channel
+    ///
+    /// Messages on the reliable lane go through the same dispatch as the
+    /// WebSocket's, numbered by their own sequence. Clock syncs on the
+    /// unreliable lane may arrive late, twice or not at all; each is simply
+    /// answered, without sequence checks.
+    async fn handle_channel_message(
+        &self,
+        client_id: &Uuid,
+        message: ChannelMessage,
+        disconnect: &CancellationToken,
+        sequence: &Arc<SequenceTracker>,
+    ) {
+        let result = match message.lane {
+            Lane::Reliable => match datachannel::check_not_hello(&message.text) {
+                Ok(()) => {
+                    self.handle_text(client_id, &message.text, &message.reply, disconnect, sequence, None)
+                        .await;
+                    return;
+                }
+                Err(e) => Err(e),
+            },
+            Lane::Unreliable => match serde_json::from_str(&message.text) {
+                Ok(ProtoMessage::ClockSync(sync)) => self.handle_clock_sync(client_id, sync, &message.reply).await,
+                Ok(_) => Err(ControlError::InvalidRequest(format!(
+                    "Only clock_sync is accepted on the {} channel",
+                    Lane::Unreliable.label()
+                ))),
+                Err(e) => Err(e.into()),
+            },
+        };
+        if let Err(error) = result {
+            // Losing a data channel does not end the WebSocket session
+            let error = match error {
+                ControlError::ChannelClosed => return,
+                error => error,
+            };
+            self.report_error(client_id, error, &message.reply, disconnect).await;
+        }
+    }
+    
     /// Handle incoming message
+    ///
+    /// Returns the resumed client ID when a hello resumed an earlier session.
     async fn handle_message(
         &self,
         client_id: &Uuid,
         text: &str,
         tx: &mpsc::Sender<ProtoMessage>,
+        disconnect: &CancellationToken,
+        sequence: &Arc<SequenceTracker>,
         remote_addr: Option<SocketAddr>,
-    ) -> Result<()> {
+    ) -> Result<Option<Uuid>, ControlError> {
+        self.check_message_size(text)?;
         let message: ProtoMessage = serde_json::from_str(text)?;
         
+        let header_sequence = message.header().sequence;
+        match sequence.check(header_sequence) {
+            SequenceCheck::InOrder => {}
+            SequenceCheck::Gap { missing } => {
+                warn!(
+                    "Client {} skipped {} message(s) before sequence {}",
+                    client_id, missing, header_sequence
+                );
+            }
+            SequenceCheck::Replay { last_seen } => {
+                warn!(
+                    "Client {} sent sequence {} after {} (reordered or replayed)",
+                    client_id, header_sequence, last_seen
+                );
+            }
+        }
+        
+        if matches!(message, ProtoMessage::NodeStatus(_) | ProtoMessage::MasterElection(_)) {
+            // Only nodes that finished their hello and proved a cluster role
+            // have a say in the cluster
+            if !self.is_cluster_node(client_id).await {
+                warn!("Dropping cluster message from {}, which is not a verified cluster node", client_id);
+                return Ok(None);
+            }
+            self.check_cluster_signature(client_id, &message).await?;
+        }
+        
         match message {
             ProtoMessage::Hello(hello) => {
-                self.handle_hello(client_id, hello, tx.clone(), remote_addr).await?;
+                let resumed_id = self
+                    .handle_hello(
+                        client_id,
+                        hello,
+                        tx.clone(),
+                        disconnect.clone(),
+                        sequence.clone(),
+                        remote_addr,
+                    )
+                    .await?;
+                return Ok(Some(resumed_id).filter(|id| id != client_id));
             }
             ProtoMessage::ClockSync(sync) => {
                 self.handle_clock_sync(client_id, sync, tx).await?;
             }
             ProtoMessage::MediaControl(control) => {
-                self.handle_media_control(control).await?;
+                self.handle_media_control(client_id, control).await?;
+            }
+            ProtoMessage::MediaData(chunk) => {
+                self.handle_media_data(client_id, chunk).await?;
             }
             ProtoMessage::Heartbeat(heartbeat) => {
                 self.handle_heartbeat(heartbeat, tx).await?;
             }
+            ProtoMessage::BufferReport(report) => {
+                self.handle_buffer_report(client_id, report, tx).await?;
+            }
+            ProtoMessage::NodeAnnounce(announce) => {
+                self.handle_node_announce(client_id, announce, tx).await?;
+            }
+            ProtoMessage::NodeChallengeResponse(response) => {
+                self.handle_challenge_response(client_id, response).await?;
+            }
+            ProtoMessage::NodeStatus(status) => {
+                self.handle_node_status(client_id, status).await;
+            }
+            ProtoMessage::MasterElection(election) => {
+                self.handle_master_election(client_id, election).await;
+            }
+            ProtoMessage::SdpAnswer(answer) => {
+                self.handle_sdp_answer(client_id, answer).await?;
+            }
+            ProtoMessage::TranscodeRequest(request) => {
+                self.handle_transcode_request(client_id, request).await?;
+            }
             _ => {
                 warn!("Unhandled message type from {}", client_id);
             }
         }
         
+        Ok(None)
+    }
+    
+    /// Reject messages over the size limit for their type
+    ///
+    /// Only the `type` tag is read, so oversized payloads are never
+    /// deserialized.
+    fn check_message_size(&self, text: &str) -> Result<(), ControlError> {
+        #[derive(serde::Deserialize)]
+        struct MessageType<'a> {
+            #[serde(rename = "type", borrow)]
+            kind: Option<&'a str>,
+        }
+        
+        let size = text.len();
+        let media_limit = self.config.max_media_message_bytes;
+        let control_limit = self.config.max_message_bytes;
+        if size <= control_limit.min(media_limit) {
+            return Ok(());
+        }
+        
+        let message_type: MessageType = serde_json::from_str(text)?;
+        let limit = match message_type.kind {
+            Some("media_data") => media_limit,
+            _ => control_limit,
+        };
+        
+        if size > limit {
+            return Err(ControlError::MessageTooLarge { size, limit });
+        }
         Ok(())
     }
     
     /// Handle hello message
+    ///
+    /// A hello carrying the token of a session parked within the resume
+    /// window continues that session: the connection takes over its client
+    /// ID, subscriptions, zone and buffer state. Returns the client ID the
+    /// connection is known by. A connection says hello once; a repeated
+    /// hello is rejected and leaves the session as it was.
     async fn handle_hello(
         &self,
         client_id: &Uuid,
         hello: HelloMessage,
         tx: mpsc::Sender<ProtoMessage>,
+        disconnect: CancellationToken,
+        sequence: Arc<SequenceTracker>,
         remote_addr: Option<SocketAddr>,
-    ) -> Result<()> {
+    ) -> Result<Uuid, ControlError> {
+        if self.clients.read().await.contains_key(client_id) {
+            return Err(ControlError::InvalidRequest(
+                "hello was already sent on this connection".into(),
+            ));
+        }
         info!(
             "Client {} hello from {:?}: type={:?}, capabilities={:?}",
             client_id, remote_addr, hello.node_type, hello.capabilities
         );
         
-        // TODO: Authenticate client
+        if let Some(tokens) = &self.tokens {
+            let token = hello
+                .auth_token
+                .as_deref()
+                .ok_or_else(|| ControlError::AuthError("missing auth token".into()))?;
+            tokens
+                .verify(token, hello.header.node_id)
+                .map_err(|e| ControlError::AuthError(e.to_string()))?;
+        }
+        let role = self.client_role(&hello)?;
+        
+        // Master and Replica roles are only granted by the key handshake
+        // that follows a node announcement
+        if hello.node_type != NodeType::Client {
+            debug!(
+                "Client {} claims {:?}; treated as a client until its key is verified",
+                client_id, hello.node_type
+            );
+        }
+        
+        let latency_bounds = LatencyBounds {
+            min_latency_ms: hello.min_latency_ms,
+            max_latency_ms: hello.max_acceptable_latency_ms,
+        };
+        let policy = &self.config.buffer_policy;
+        latency_bounds
+            .resolve(policy.min_latency, policy.max_latency)
+            .map_err(|e| ControlError::InvalidRequest(e.to_string()))?;
+        
+        let resumed = match hello.session_token.as_deref() {
+            Some(token) => {
+                let session = self.take_session(token).await;
+                if session.is_none() {
+                    info!("Client {} presented an unknown or expired session token", client_id);
+                }
+                session
+            }
+            None => None,
+        };
+        let client_id = &resumed.as_ref().map_or(*client_id, |session| session.client_id);
+        
+        let codecs = CodecSupport::from_capabilities(&hello.capabilities);
         
         // Store client connection
+        let session_token = Uuid::new_v4().simple().to_string();
         let client = ClientConnection {
             client_id: *client_id,
-            node_type: hello.node_type,
+            node_type: NodeType::Client,
+            role,
             tx: tx.clone(),
             capabilities: hello.capabilities,
             remote_addr,
             connected_at: chrono::Utc::now(),
+            dropped_messages: Arc::new(AtomicU64::new(0)),
+            queue_overflows: Arc::new(AtomicU64::new(0)),
+            consecutive_drops: Arc::new(AtomicU32::new(0)),
+            disconnect,
+            sequence,
+            public_key: None,
+            session_token: session_token.clone(),
+            egress: Arc::default(),
         };
         
         self.clients.write().await.insert(*client_id, client);
         
         // Add to media server if client supports media
-        self.media_server.add_client(*client_id).await?;
+        match resumed {
+            Some(session) => {
+                info!(
+                    "Client {} resumed with {} subscriptions",
+                    client_id,
+                    session.media.subscriptions().len()
+                );
+                self.media_server
+                    .resume_client(*client_id, session.media, hello.zone)
+                    .await?;
+                self.media_server.set_client_codecs(*client_id, codecs).await;
+            }
+            None => {
+                self.media_server.add_client(*client_id).await?;
+                self.media_server.set_client_codecs(*client_id, codecs).await;
+                self.media_server
+                    .attach_device(*client_id, hello.header.node_id)
+                    .await;
+                let zone = hello.zone.or_else(|| self.media_server.device_zone(hello.header.node_id));
+                if let Some(zone) = zone {
+                    self.media_server.join_zone(*client_id, zone).await?;
+                }
+            }
+        }
+        
+        // A resumed client's bounds set through the API may conflict with
+        // the new hello's; those are kept
+        if let Err(e) = self
+            .media_server
+            .set_latency_bounds(*client_id, BoundsSource::Hello, latency_bounds)
+            .await
+        {
+            warn!("Ignoring latency bounds in hello from {}: {}", client_id, e);
+        }
         
-        // Send welcome response
+        // Send welcome response, announcing the transport compression the
+        // client may apply to its media chunks
+        let mut capabilities = vec![
+            "clock_sync".to_string(),
+            "media_streaming".to_string(),
+            "cluster".to_string(),
+            DATA_CHANNEL_CAPABILITY.to_string(),
+            CLOCK_CHANNEL_CAPABILITY.to_string(),
+        ];
+        capabilities.extend(self.media_server.compression_capabilities());
         let response = ProtoMessage::Hello(HelloMessage {
             header: MessageHeader::new(self.server_id, 0),
             protocol_version: "0.1.0".to_string(),
-            capabilities: vec![
-                "clock_sync".to_string(),
-                "media_streaming".to_string(),
-                "cluster".to_string(),
-            ],
+            capabilities,
             node_type: NodeType::Master,
             auth_token: None,
+            zone: None,
+            session_token: Some(session_token),
+            min_latency_ms: None,
+            max_acceptable_latency_ms: None,
+            ingest_token: None,
         });
         
         tx.send(response).await?;
         
-        Ok(())
+        Ok(*client_id)
+    }
+    
+    /// Remove and return the parked session for `token`, if it has not
+    /// expired
+    async fn take_session(&self, token: &str) -> Option<ParkedSession> {
+        let mut sessions = self.sessions.write().await;
+        Self::expire_sessions(&mut sessions);
+        sessions.remove(token)
+    }
+    
+    /// Forget sessions whose resume window has passed
+    fn expire_sessions(sessions: &mut HashMap<String, ParkedSession>) {
+        let now = tokio::time::Instant::now();
+        sessions.retain(|_, session| {
+            let live = session.expires_at > now;
+            if !live {
+                info!("Session of client {} expired", session.client_id);
+            }
+            live
+        });
     }
     
     /// Handle clock sync
@@ -194,7 +949,9 @@ impl ControlServer {
         client_id: &Uuid,
         sync: crate::protocol::ClockSyncMessage,
         tx: &mpsc::Sender<ProtoMessage>,
-    ) -> Result<()> {
+    ) -> Result<(), ControlError> {
+        self.authorize(client_id, ClientOperation::ClockSync).await?;
+        
         let response = crate::clock::ClockSync::create_response(&sync);
         tx.send(ProtoMessage::ClockSyncResponse(response)).await?;
         Ok(())
@@ -203,86 +960,2149 @@ impl ControlServer {
     /// Handle media control
     async fn handle_media_control(
         &self,
+        client_id: &Uuid,
         control: crate::protocol::MediaControlMessage,
-    ) -> Result<()> {
+    ) -> Result<(), ControlError> {
+        // Subscriptions apply to the sending client and are gated separately
+        let key = stream_key(&control.track_id, control.zone.as_deref());
+        match control.action {
+            MediaAction::Subscribe => return self.subscribe_client(client_id, key).await,
+            MediaAction::Unsubscribe => return self.unsubscribe_client(client_id, &key).await,
+            _ => {}
+        }
+        
+        self.authorize(client_id, ClientOperation::MediaControl).await?;
+        
+        let request_id = control.header.request_id.clone();
         self.media_server
-            .get_control_sender()
-            .send(control)
-            .await?;
-        Ok(())
+            .process_control(control)
+            .await
+            .map_err(|e| {
+                warn!(request_id = request_id.as_deref(), "Media control from {} failed: {}", client_id, e);
+                ControlError::MediaError(e.to_string())
+            })
     }
     
-    /// Handle heartbeat
-    async fn handle_heartbeat(
+    /// Handle a chunk from a client acting as a live media source
+    async fn handle_media_data(
         &self,
-        heartbeat: crate::protocol::HeartbeatMessage,
-        tx: &mpsc::Sender<ProtoMessage>,
-    ) -> Result<()> {
-        let mut response = heartbeat.clone();
-        response.server_time = Some(self.clock_manager.now().await);
-        tx.send(ProtoMessage::Heartbeat(response)).await?;
-        Ok(())
+        client_id: &Uuid,
+        chunk: crate::protocol::MediaDataMessage,
+    ) -> Result<(), ControlError> {
+        self.authorize(client_id, ClientOperation::MediaData).await?;
+        
+        self.media_server
+            .ingest_chunk(*client_id, chunk)
+            .await
+            .map_err(|e| {
+                warn!("Media data from {} rejected: {}", client_id, e);
+                ControlError::MediaError(e.to_string())
+            })
     }
     
-    /// Remove client
-    async fn remove_client(&self, client_id: &Uuid) {
-        self.clients.write().await.remove(client_id);
-        info!("Removed client: {}", client_id);
+    /// Apply a client's answer to an offer made on its peer connection
+    async fn handle_sdp_answer(&self, client_id: &Uuid, answer: SdpAnswerMessage) -> Result<(), ControlError> {
+        self.media_server
+            .apply_answer(*client_id, answer.sdp)
+            .await
+            .map_err(|e| {
+                warn!("SDP answer from {} rejected: {}", client_id, e);
+                ControlError::MediaError(e.to_string())
+            })
     }
     
-    /// Send error to client
-    async fn send_error(
+    /// Flag a stream the client cannot decode for transcoding to a codec
+    /// it can; gated like subscribing, which it precedes
+    async fn handle_transcode_request(
         &self,
         client_id: &Uuid,
-        code: ErrorCode,
-        message: String,
-    ) -> Result<()> {
-        if let Some(client) = self.clients.read().await.get(client_id) {
-            let error = ProtoMessage::Error(ErrorMessage {
-                header: MessageHeader::new(self.server_id, 0),
-                code,
-                message,
-                details: None,
-            });
-            
-            client.tx.send(error).await?;
+        request: TranscodeRequestMessage,
+    ) -> Result<(), ControlError> {
+        self.authorize(client_id, ClientOperation::Subscribe).await?;
+        
+        self.media_server
+            .request_transcode(*client_id, request.track_id, request.target_codec)
+            .await
+            .map_err(|e| {
+                warn!("Transcode request from {} refused: {}", client_id, e);
+                ControlError::MediaError(e.to_string())
+            })
+    }
+    
+    /// Subscribe a client to a track's frames
+    pub async fn subscribe_client(&self, client_id: &Uuid, track_id: String) -> Result<(), ControlError> {
+        self.authorize(client_id, ClientOperation::Subscribe).await?;
+        
+        self.media_server
+            .subscribe_client(*client_id, track_id)
+            .await
+            .map_err(|e| ControlError::MediaError(e.to_string()))
+    }
+    
+    /// Unsubscribe a client from a track; unsubscribing twice is not an error
+    pub async fn unsubscribe_client(&self, client_id: &Uuid, track_id: &str) -> Result<(), ControlError> {
+        self.authorize(client_id, ClientOperation::Subscribe).await?;
+        
+        self.media_server
+            .unsubscribe_client(*client_id, track_id)
+            .await
+            .map(|_| ())
+            .map_err(|e| ControlError::MediaError(e.to_string()))
+    }
+    
+    /// Role a hello grants
+    ///
+    /// With ingest authentication enabled, only a valid ingest token makes
+    /// a publisher; presenting an invalid one fails the hello.
+    fn client_role(&self, hello: &HelloMessage) -> Result<ClientRole, ControlError> {
+        let Some(ingest_tokens) = &self.ingest_tokens else {
+            return Ok(ClientRole::from_capabilities(&hello.capabilities));
+        };
+        let Some(token) = hello.ingest_token.as_deref() else {
+            return Ok(ClientRole::Listener);
+        };
+        ingest_tokens
+            .verify(token, hello.header.node_id)
+            .map_err(|e| ControlError::AuthError(format!("ingest token: {}", e)))?;
+        Ok(ClientRole::Publisher)
+    }
+    
+    /// Check that a client advertised the capability an operation requires,
+    /// and that a client publishing media holds the publisher role
+    async fn authorize(&self, client_id: &Uuid, op: ClientOperation) -> Result<(), ControlError> {
+        let clients = self.clients.read().await;
+        let client = clients.get(client_id);
+        if op == ClientOperation::MediaData && !client.is_some_and(|client| client.role == ClientRole::Publisher) {
+            return Err(ControlError::Unauthorized(format!("{} requires the publisher role", op)));
+        }
+        
+        let Some(required) = self.config.required_capabilities.required(op) else {
+            return Ok(());
+        };
+        let allowed = client.is_some_and(|client| client.capabilities.iter().any(|c| c == required));
+        if !allowed {
+            return Err(ControlError::Unauthorized(format!(
+                "{} requires the {:?} capability",
+                op, required
+            )));
         }
         
         Ok(())
     }
     
-    /// Broadcast message to all clients
-    pub async fn broadcast(&self, message: ProtoMessage) -> Result<()> {
-        let clients = self.clients.read().await;
+    /// Handle a node announcement
+    ///
+    /// A node announcing itself as Master or Replica is challenged to sign a
+    /// fresh nonce with the private key matching its announced public key.
+    async fn handle_node_announce(
+        &self,
+        client_id: &Uuid,
+        announce: NodeAnnounceMessage,
+        tx: &mpsc::Sender<ProtoMessage>,
+    ) -> Result<(), ControlError> {
+        if !self.clients.read().await.contains_key(client_id) {
+            return Err(ControlError::Unauthorized("node announced before hello".into()));
+        }
         
-        for (client_id, client) in clients.iter() {
-            if let Err(e) = client.tx.send(message.clone()).await {
-                warn!("Failed to send to client {}: {}", client_id, e);
+        if announce.node_type == NodeType::Client {
+            self.challenges.write().await.remove(client_id);
+            if let Some(client) = self.clients.write().await.get_mut(client_id) {
+                client.node_type = NodeType::Client;
+                client.public_key = None;
             }
+            return Ok(());
         }
         
+        let challenge = NodeChallenge::new(announce.node_type, announce.public_key.as_deref())
+            .map_err(|e| ControlError::Unauthorized(e.to_string()))?;
+        let message = ProtoMessage::NodeChallenge(NodeChallengeMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            nonce: challenge.nonce().to_vec(),
+        });
+        
+        info!(
+            "Node {} announced as {:?} at {}; sent key challenge",
+            client_id, announce.node_type, announce.endpoint
+        );
+        self.challenges.write().await.insert(*client_id, challenge);
+        tx.send(message).await?;
         Ok(())
     }
     
-    /// Get connected clients information
-    pub async fn get_connected_clients(&self) -> Vec<ClientInfo> {
-        let clients = self.clients.read().await;
+    /// Handle a signed key challenge
+    ///
+    /// The challenge is consumed either way, so a failed attempt needs a new
+    /// announcement.
+    async fn handle_challenge_response(
+        &self,
+        client_id: &Uuid,
+        response: NodeChallengeResponseMessage,
+    ) -> Result<(), ControlError> {
+        let challenge = self
+            .challenges
+            .write()
+            .await
+            .remove(client_id)
+            .ok_or_else(|| ControlError::Unauthorized("no key challenge pending".into()))?;
         
-        clients.values().map(|client| ClientInfo {
-            client_id: client.client_id,
-            node_type: client.node_type,
-            capabilities: client.capabilities.clone(),
-            remote_addr: client.remote_addr.map(|addr| addr.to_string()),
-            connected_at: client.connected_at,
-        }).collect()
+        challenge
+            .verify(&response.signature)
+            .map_err(|e| ControlError::Unauthorized(e.to_string()))?;
+        
+        if let Some(client) = self.clients.write().await.get_mut(client_id) {
+            client.node_type = challenge.node_type();
+            client.public_key = Some(challenge.public_key());
+            info!("Node {} verified as {:?}", client_id, client.node_type);
+        }
+        Ok(())
     }
-}
-
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
-pub struct ClientInfo {
-    pub client_id: Uuid,
-    pub node_type: NodeType,
-    pub capabilities: Vec<String>,
-    pub remote_addr: Option<String>,
-    pub connected_at: chrono::DateTime<chrono::Utc>,
-}
\ No newline at end of file
+    
+    /// Whether a client completed its hello and verified a Master or
+    /// Replica role with the key handshake
+    async fn is_cluster_node(&self, client_id: &Uuid) -> bool {
+        self.clients
+            .read()
+            .await
+            .get(client_id)
+            .is_some_and(|client| matches!(client.node_type, NodeType::Master | NodeType::Replica))
+    }
+    
+    /// Verify the signature on a cluster message before acting on it
+    ///
+    /// Signatures are checked against the key the sender proved in its
+    /// announce handshake. Unsigned messages are accepted unless signing is
+    /// required.
+    async fn check_cluster_signature(&self, client_id: &Uuid, message: &ProtoMessage) -> Result<(), ControlError> {
+        if !signing::is_signed(message) {
+            if self.config.require_signed_cluster_messages {
+                warn!("Ignoring unsigned cluster message from {}", client_id);
+                return Err(ControlError::Unauthorized(SignatureError::Unsigned.to_string()));
+            }
+            return Ok(());
+        }
+        
+        let key = self.clients.read().await.get(client_id).and_then(|c| c.public_key);
+        verify_cluster_message(message, key.as_ref()).map_err(|e| {
+            warn!("Rejected cluster message from {}: {}", client_id, e);
+            ControlError::Unauthorized(e.to_string())
+        })
+    }
+    
+    /// Sign a cluster message this server sends with its node key, if it
+    /// has one; peers requiring signatures drop it otherwise
+    fn sign_cluster_message(&self, message: &mut ProtoMessage) {
+        let Some(key) = &self.config.node_signing_key else {
+            return;
+        };
+        if let Err(e) = signing::sign_cluster_message(message, key) {
+            warn!("Failed to sign cluster message: {}", e);
+        }
+    }
+    
+    /// Record a cluster node's status
+    async fn handle_node_status(&self, client_id: &Uuid, status: NodeStatusMessage) {
+        debug!(
+            "Node {} status: {:?}, {} clients",
+            client_id, status.node_type, status.connected_clients
+        );
+        let health = {
+            let mut statuses = self.node_statuses.write().await;
+            let record = statuses.entry(*client_id).or_insert_with(|| NodeRecord {
+                status: status.clone(),
+                rtt: RttHistory::default(),
+            });
+            record.rtt.record(status.avg_rtt_ms);
+            record.status = status;
+            NodeHealth {
+                drift_ppm: record.status.drift_ppm,
+                jitter_ms: record.rtt.stddev(),
+            }
+        };
+        
+        // A degraded master steps down rather than drag the cluster along
+        let demotion = self.election.write().await.check_leader_health(
+            *client_id,
+            health,
+            &self.config.master_demotion,
+            tokio::time::Instant::now(),
+        );
+        if let Some(epoch) = demotion {
+            warn!(
+                "Master {} degraded (drift {:?}ppm, jitter {:?}ms); restarting election at epoch {}",
+                client_id, health.drift_ppm, health.jitter_ms, epoch
+            );
+            self.restart_election(epoch, None, Some(*client_id)).await;
+        }
+    }
+    
+    /// Record a node standing in a master election if it outscores the
+    /// current candidate
+    ///
+    /// Candidates are ranked by a composite of their proven role, reported
+    /// score, uptime, RTT stability and client count, weighted by
+    /// `election_weights`, with ties going to the lower node ID. The leading
+    /// node's clock is followed as the master clock.
+    ///
+    /// Nodes claiming different masters at the same time mean the cluster
+    /// split and each side elected its own; the cluster nodes are told and
+    /// the election restarts at a higher epoch.
+    async fn handle_master_election(&self, client_id: &Uuid, election: MasterElectionMessage) {
+        let node_type = self
+            .clients
+            .read()
+            .await
+            .get(client_id)
+            .map_or(NodeType::Client, |client| client.node_type);
+        let profile = {
+            let statuses = self.node_statuses.read().await;
+            let record = statuses.get(client_id);
+            CandidateProfile {
+                node_type,
+                candidate_score: election.candidate_score,
+                uptime_seconds: record.map_or(0, |r| r.status.uptime_seconds),
+                rtt_stddev_ms: record.and_then(|r| r.rtt.stddev()),
+                connected_clients: record.map_or(0, |r| r.status.connected_clients),
+            }
+        };
+        let score = self.config.election_weights.score(&profile);
+        
+        let outcome = self.election.write().await.record(
+            *client_id,
+            &election,
+            score,
+            tokio::time::Instant::now(),
+        );
+        
+        match outcome {
+            ElectionOutcome::Stale { epoch } => {
+                debug!(
+                    "Ignoring election message from {} for epoch {} (now {})",
+                    client_id, election.epoch, epoch
+                );
+            }
+            ElectionOutcome::Recorded { leading: true } => {
+                info!(
+                    "Node {} leads election {} (epoch {}) with score {:.3} ({:?})",
+                    client_id, election.election_id, election.epoch, score, profile
+                );
+                self.clock_manager.set_master_peer(Some(*client_id));
+            }
+            ElectionOutcome::Recorded { leading: false } => {}
+            ElectionOutcome::SplitBrain { masters, epoch } => {
+                error!(
+                    "Split brain: nodes claim masters {:?}; restarting election at epoch {}",
+                    masters, epoch
+                );
+                let error = ProtoMessage::Error(ErrorMessage {
+                    header: MessageHeader::new(self.server_id, 0),
+                    code: ErrorCode::ClusterError,
+                    message: format!("Split brain between masters {:?}", masters),
+                    details: Some(serde_json::json!({ "masters": masters, "epoch": epoch })),
+                });
+                self.restart_election(epoch, Some(error), None).await;
+            }
+        }
+    }
+    
+    /// Start an election at `epoch` for cluster nodes to stand in again,
+    /// after sending them `notice`
+    ///
+    /// A `demoted` master is named in the election message.
+    async fn restart_election(&self, epoch: u64, notice: Option<ProtoMessage>, demoted: Option<Uuid>) {
+        // No node leads until the cluster stands again
+        self.clock_manager.set_master_peer(None);
+        
+        let nodes: Vec<Uuid> = self
+            .clients
+            .read()
+            .await
+            .values()
+            .filter(|client| client.node_type != NodeType::Client)
+            .map(|client| client.client_id)
+            .collect();
+        
+        let mut election = ProtoMessage::MasterElection(MasterElectionMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            election_id: Uuid::new_v4(),
+            epoch,
+            candidate_score: 0.0,
+            current_master: None,
+            demoted,
+            signature: None,
+        });
+        self.sign_cluster_message(&mut election);
+        for message in notice.into_iter().chain([election]) {
+            if let Err(e) = self.broadcast_to(message, Some(&nodes)).await {
+                warn!("Failed to announce re-election: {}", e);
+            }
+        }
+    }
+    
+    /// Handle heartbeat
+    async fn handle_heartbeat(
+        &self,
+        heartbeat: crate::protocol::HeartbeatMessage,
+        tx: &mpsc::Sender<ProtoMessage>,
+    ) -> Result<(), ControlError> {
+        let mut response = heartbeat.clone();
+        response.server_time = Some(self.clock_manager.now().await);
+        tx.send(ProtoMessage::Heartbeat(response)).await?;
+        Ok(())
+    }
+    
+    /// Handle a buffer report from a client's audio output
+    ///
+    /// The report adjusts the client's future buffer, and the resulting
+    /// target latency is sent back for the client to schedule playout with.
+    /// A change of the playback rate hint follows in a `rate_adjust`.
+    async fn handle_buffer_report(
+        &self,
+        client_id: &Uuid,
+        report: BufferReportMessage,
+        tx: &mpsc::Sender<ProtoMessage>,
+    ) -> Result<(), ControlError> {
+        let (stats, rate) = self
+            .media_server
+            .apply_buffer_report(*client_id, &report)
+            .await
+            .ok_or_else(|| ControlError::Unauthorized("buffer report before hello".into()))?;
+        
+        let ack = BufferReportAckMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            track_id: report.track_id,
+            target_latency_ms: stats.target_latency_ms,
+            jitter_buffer_ms: stats.jitter_buffer_ms,
+        };
+        tx.send(ProtoMessage::BufferReportAck(ack)).await?;
+        
+        if let Some(playback_rate) = rate {
+            let adjust = RateAdjustMessage {
+                header: MessageHeader::new(self.server_id, 0),
+                playback_rate,
+                target_latency_ms: stats.target_latency_ms,
+            };
+            tx.send(ProtoMessage::RateAdjust(adjust)).await?;
+        }
+        Ok(())
+    }
+    
+    /// Remove client
+    ///
+    /// Clients that completed their hello are parked for the resume window,
+    /// so a reconnect with their session token picks up where they left.
+    async fn remove_client(&self, client_id: &Uuid) {
+        let client = self.clients.write().await.remove(client_id);
+        self.challenges.write().await.remove(client_id);
+        self.node_statuses.write().await.remove(client_id);
+        
+        let window = std::time::Duration::from_millis(self.config.session_resume_ms);
+        match client.filter(|_| !window.is_zero()) {
+            Some(client) => {
+                if let Some(media) = self.media_server.detach_client(*client_id).await {
+                    let mut sessions = self.sessions.write().await;
+                    Self::expire_sessions(&mut sessions);
+                    sessions.insert(client.session_token, ParkedSession {
+                        client_id: *client_id,
+                        expires_at: tokio::time::Instant::now() + window,
+                        media,
+                    });
+                }
+            }
+            None => self.media_server.remove_client(*client_id).await,
+        }
+        info!("Removed client: {}", client_id);
+    }
+    
+    /// Broadcast a message to `recipients`, or to all clients with `None`
+    ///
+    /// Full client queues are handled according to the configured
+    /// `BroadcastPolicy`, so one slow client does not stall the others
+    /// unless `Block` is selected.
+    async fn broadcast_to(&self, message: ProtoMessage, recipients: Option<&[Uuid]>) -> Result<()> {
+        let clients = self.clients.read().await;
+        let policy = self.config.broadcast_policy;
+        
+        for (client_id, client) in clients.iter() {
+            if recipients.is_some_and(|ids| !ids.contains(client_id)) {
+                continue;
+            }
+            if policy == BroadcastPolicy::Block {
+                if let Err(e) = client.send(message.clone()).await {
+                    warn!("Failed to send to client {}: {}", client_id, e);
+                }
+                continue;
+            }
+            
+            match client.tx.try_send(message.clone()) {
+                Ok(()) => {
+                    client.consecutive_drops.store(0, Ordering::Relaxed);
+                }
+                Err(TrySendError::Full(_)) => {
+                    client.queue_overflows.fetch_add(1, Ordering::Relaxed);
+                    client.dropped_messages.fetch_add(1, Ordering::Relaxed);
+                    let drops = client.consecutive_drops.fetch_add(1, Ordering::Relaxed) + 1;
+                    debug!("Send queue full for client {}, dropped broadcast", client_id);
+                    
+                    if let BroadcastPolicy::Disconnect { max_consecutive_drops } = policy {
+                        if drops >= max_consecutive_drops && !client.disconnect.is_cancelled() {
+                            warn!(
+                                "Client {} dropped {} consecutive broadcasts, disconnecting",
+                                client_id, drops
+                            );
+                            client.disconnect.cancel();
+                        }
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    warn!("Failed to send to client {}: channel closed", client_id);
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Connected client and authentication health
+    pub async fn health(&self) -> ConnectionHealth {
+        let clients = self.clients.read().await;
+        let lagging = clients
+            .values()
+            .filter(|c| c.consecutive_drops.load(Ordering::Relaxed) > 0)
+            .count();
+        let election = self.election.read().await;
+        
+        ConnectionHealth {
+            state: if lagging > 0 { HealthState::Degraded } else { HealthState::Ok },
+            active: clients.len(),
+            lagging,
+            auth_failures: self.auth_failures.load(Ordering::Relaxed),
+            election_epoch: election.epoch(),
+            election_leader: election.candidate().map(|(id, _)| id),
+            split_brains: election.split_brains(),
+        }
+    }
+    
+    /// Get connected clients information
+    pub async fn get_connected_clients(&self) -> Vec<ClientInfo> {
+        let mut clients: Vec<ClientInfo> = self.clients.read().await.values().map(|client| ClientInfo {
+            client_id: client.client_id,
+            node_type: client.node_type,
+            role: client.role,
+            capabilities: client.capabilities.clone(),
+            remote_addr: client.remote_addr.map(|addr| addr.to_string()),
+            connected_at: client.connected_at,
+            dropped_messages: client.dropped_messages.load(Ordering::Relaxed),
+            queue_overflows: client.queue_overflows.load(Ordering::Relaxed),
+            missing_messages: client.sequence.missing(),
+            out_of_order_messages: client.sequence.out_of_order(),
+            control_egress: client.egress.snapshot(),
+            media_egress: Egress::default(),
+        }).collect();
+        
+        for client in &mut clients {
+            if let Some(egress) = self.media_server.client_egress(client.client_id).await {
+                client.media_egress = egress;
+            }
+        }
+        clients
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ClientInfo {
+    pub client_id: Uuid,
+    pub node_type: NodeType,
+    pub role: ClientRole,
+    pub capabilities: Vec<String>,
+    pub remote_addr: Option<String>,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+    pub dropped_messages: u64,
+    pub queue_overflows: u64,
+    pub missing_messages: u64,
+    pub out_of_order_messages: u64,
+    
+    /// Sent over the WebSocket and control data channels
+    pub control_egress: Egress,
+    
+    /// Sent over the WebRTC peer connection: RTP packets and clock sync
+    /// replies
+    pub media_egress: Egress,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ClockSample, protocol::HeartbeatMessage};
+    use std::time::Duration;
+
+    fn test_server(broadcast_policy: BroadcastPolicy) -> ControlServer {
+        let config = ServerConfig {
+            broadcast_policy,
+            ..Default::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::new(clock)),
+            Arc::new(config),
+        )
+    }
+
+    async fn add_test_client(
+        server: &ControlServer,
+        queue_size: usize,
+    ) -> (ClientConnection, mpsc::Receiver<ProtoMessage>) {
+        add_client_with_capabilities(server, queue_size, &[]).await
+    }
+
+    async fn add_client_with_capabilities(
+        server: &ControlServer,
+        queue_size: usize,
+        capabilities: &[&str],
+    ) -> (ClientConnection, mpsc::Receiver<ProtoMessage>) {
+        let (tx, rx) = mpsc::channel(queue_size);
+        let capabilities: Vec<String> = capabilities.iter().map(|c| c.to_string()).collect();
+        let client = ClientConnection {
+            client_id: Uuid::new_v4(),
+            node_type: NodeType::Client,
+            role: ClientRole::from_capabilities(&capabilities),
+            tx,
+            capabilities,
+            remote_addr: None,
+            connected_at: chrono::Utc::now(),
+            dropped_messages: Arc::new(AtomicU64::new(0)),
+            queue_overflows: Arc::new(AtomicU64::new(0)),
+            consecutive_drops: Arc::new(AtomicU32::new(0)),
+            disconnect: CancellationToken::new(),
+            sequence: Arc::new(SequenceTracker::new()),
+            public_key: None,
+            session_token: Uuid::new_v4().simple().to_string(),
+            egress: Arc::default(),
+        };
+        server.clients.write().await.insert(client.client_id, client.clone());
+        (client, rx)
+    }
+
+    fn heartbeat() -> ProtoMessage {
+        ProtoMessage::Heartbeat(HeartbeatMessage {
+            header: MessageHeader::new(Uuid::new_v4(), 0),
+            client_time: 0.0,
+            server_time: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_does_not_stall_broadcast() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (slow, _slow_rx) = add_test_client(&server, 1).await;
+        let (_healthy, mut healthy_rx) = add_test_client(&server, 100).await;
+
+        let broadcasts = async {
+            for _ in 0..10 {
+                server.broadcast_to(heartbeat(), None).await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_millis(500), broadcasts)
+            .await
+            .expect("Broadcast stalled on slow client");
+
+        let mut received = 0;
+        while healthy_rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 10);
+        assert_eq!(slow.dropped_messages.load(Ordering::Relaxed), 9);
+        assert_eq!(slow.queue_overflows.load(Ordering::Relaxed), 9);
+        assert!(!slow.disconnect.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_counts_overflows_when_blocking() {
+        let server = test_server(BroadcastPolicy::Block);
+        let (slow, mut slow_rx) = add_test_client(&server, 2).await;
+
+        server.broadcast_to(heartbeat(), None).await.unwrap();
+        server.broadcast_to(heartbeat(), None).await.unwrap();
+        assert_eq!(slow.queue_overflows.load(Ordering::Relaxed), 0);
+
+        // The third message waits for the client to drain one
+        let drain = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            slow_rx.recv().await.unwrap();
+            slow_rx
+        });
+        server.broadcast_to(heartbeat(), None).await.unwrap();
+        let _slow_rx = drain.await.unwrap();
+
+        let info = server.get_connected_clients().await;
+        assert_eq!((info[0].queue_overflows, info[0].dropped_messages), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_policy_disconnects_slow_client() {
+        let server = test_server(BroadcastPolicy::Disconnect { max_consecutive_drops: 3 });
+        let (slow, _slow_rx) = add_test_client(&server, 1).await;
+
+        for _ in 0..3 {
+            server.broadcast_to(heartbeat(), None).await.unwrap();
+        }
+        assert!(!slow.disconnect.is_cancelled());
+
+        server.broadcast_to(heartbeat(), None).await.unwrap();
+        assert!(slow.disconnect.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_is_protocol_error() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (tx, _rx) = mpsc::channel(10);
+
+        let result = server
+            .handle_message(
+                &Uuid::new_v4(),
+                "{not json",
+                &tx,
+                &CancellationToken::new(),
+                &Arc::new(SequenceTracker::new()),
+                None,
+            )
+            .await;
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, ControlError::ParseError(_)));
+        assert_eq!(error.code(), ErrorCode::ProtocolError);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_messages_are_rejected() {
+        let config = ServerConfig {
+            max_message_bytes: 1024,
+            max_media_message_bytes: 64 * 1024,
+            ..Default::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::new(clock)),
+            Arc::new(config),
+        );
+        let padding = "x".repeat(8 * 1024);
+        let control = format!(r#"{{"type":"heartbeat","padding":"{}"}}"#, padding);
+        let media = format!(r#"{{"type":"media_data","padding":"{}"}}"#, padding);
+        let huge = format!(r#"{{"type":"media_data","data":"{}"}}"#, "x".repeat(16 * 1024 * 1024));
+
+        let error = server.check_message_size(&control).unwrap_err();
+        assert!(matches!(error, ControlError::MessageTooLarge { limit: 1024, .. }));
+        assert_eq!(error.code(), ErrorCode::ProtocolError);
+        assert!(server.check_message_size(&media).is_ok());
+        assert!(matches!(
+            server.check_message_size(&huge),
+            Err(ControlError::MessageTooLarge { limit: 65536, .. })
+        ));
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+        server
+            .handle_text(
+                &Uuid::new_v4(),
+                &huge,
+                &tx,
+                &disconnect,
+                &Arc::new(SequenceTracker::new()),
+                None,
+            )
+            .await;
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::ProtocolError),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        assert!(!disconnect.is_cancelled());
+    }
+
+    fn media_chunk(sender: Uuid, chunk_index: u64) -> String {
+        let message = ProtoMessage::MediaData(crate::protocol::MediaDataMessage {
+            header: MessageHeader::new(sender, chunk_index + 1),
+            track_id: "live".into(),
+            chunk_index,
+            timestamp: 100.0 + chunk_index as f64 * 0.02,
+            duration: 0.02,
+            data: vec![chunk_index as u8; 4],
+            codec: "opus".into(),
+            is_keyframe: false,
+            compression: None,
+        });
+        serde_json::to_string(&message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_publishers_may_push_media_data() {
+        let config = ServerConfig {
+            ingest_secret: Some("ingest secret".into()),
+            ..Default::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(clock.clone(), Arc::new(MediaServer::new(clock)), Arc::new(config));
+        let stream_exists = || async { server.media_server.stats().await.streams.iter().any(|s| s.status.track_id == "live") };
+
+        // Both claim to be media sources; only the publisher holds an ingest
+        // token, and one signed with another secret is refused outright
+        let forged = auth::TokenAuthority::new("client secret");
+        for (role, publisher) in [("listener", false), ("forger", false), ("publisher", true)] {
+            let client_id = Uuid::new_v4();
+            let (tx, mut rx) = mpsc::channel(10);
+            let disconnect = CancellationToken::new();
+            let sequence = Arc::new(SequenceTracker::new());
+            let ingest_token = match role {
+                "listener" => None,
+                "forger" => Some(forged.mint(client_id, Duration::from_secs(60))),
+                _ => server.mint_ingest_token(client_id, Duration::from_secs(60)),
+            };
+            let hello = ProtoMessage::Hello(HelloMessage {
+                header: MessageHeader::new(client_id, 0),
+                protocol_version: "0.1.0".into(),
+                capabilities: vec![MEDIA_SOURCE_CAPABILITY.into()],
+                node_type: NodeType::Client,
+                auth_token: None,
+                zone: None,
+                session_token: None,
+                min_latency_ms: None,
+                max_acceptable_latency_ms: None,
+                ingest_token,
+            });
+            let hello = serde_json::to_string(&hello).unwrap();
+            server
+                .handle_text(&client_id, &hello, &tx, &disconnect, &sequence, None)
+                .await;
+            if role == "forger" {
+                match rx.try_recv() {
+                    Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::AuthenticationFailed),
+                    other => panic!("Expected error frame, got {:?}", other),
+                }
+                assert!(disconnect.is_cancelled());
+                continue;
+            }
+            assert!(matches!(rx.try_recv(), Ok(ProtoMessage::Hello(_))));
+
+            server
+                .handle_text(&client_id, &media_chunk(client_id, 0), &tx, &disconnect, &sequence, None)
+                .await;
+            let info = server.get_connected_clients().await;
+            let info = info.iter().find(|c| c.client_id == client_id).unwrap();
+            if publisher {
+                assert_eq!(info.role, ClientRole::Publisher);
+                assert!(rx.try_recv().is_err(), "Unexpected response to media data");
+                assert!(stream_exists().await);
+            } else {
+                assert_eq!(info.role, ClientRole::Listener);
+                match rx.try_recv() {
+                    Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::Unauthorized),
+                    other => panic!("Expected error frame, got {:?}", other),
+                }
+                assert!(!stream_exists().await, "a listener created a stream");
+                assert!(!disconnect.is_cancelled());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_media_data_is_published_in_order() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let client_id = Uuid::new_v4();
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+        let sequence = Arc::new(SequenceTracker::new());
+
+        let hello = ProtoMessage::Hello(HelloMessage {
+            header: MessageHeader::new(client_id, 0),
+            protocol_version: "0.1.0".into(),
+            capabilities: vec![MEDIA_SOURCE_CAPABILITY.into()],
+            node_type: NodeType::Client,
+            auth_token: None,
+            zone: None,
+            session_token: None,
+            min_latency_ms: None,
+            max_acceptable_latency_ms: None,
+            ingest_token: None,
+        });
+        let hello = serde_json::to_string(&hello).unwrap();
+        server
+            .handle_text(&client_id, &hello, &tx, &disconnect, &sequence, None)
+            .await;
+        assert!(matches!(rx.try_recv(), Ok(ProtoMessage::Hello(_))));
+
+        server
+            .media_server
+            .create_stream("live".into(), "opus".into())
+            .await
+            .unwrap();
+        let mut frame_rx = server.media_server.subscribe_frames("live").await.unwrap();
+
+        for chunk_index in [0, 2, 1, 3] {
+            let chunk = media_chunk(client_id, chunk_index);
+            server
+                .handle_text(&client_id, &chunk, &tx, &disconnect, &sequence, None)
+                .await;
+        }
+        assert!(rx.try_recv().is_err(), "Unexpected response to media data");
+
+        for expected in 0..4 {
+            let frame = frame_rx.try_recv().unwrap();
+            assert_eq!(frame.sequence, expected);
+            assert_eq!(frame.data, vec![expected as u8; 4]);
+            assert!((frame.timestamp - (100.0 + expected as f64 * 0.02)).abs() < 1e-9);
+            assert_eq!(frame.duration, Duration::from_millis(20));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zstd_media_data_round_trips_through_negotiation() {
+        use crate::{
+            media::{Compressors, ZstdCompressor},
+            protocol::{Compression, MediaDataMessage},
+        };
+
+        let server = test_server(BroadcastPolicy::Drop);
+        server
+            .media_server
+            .set_compressor(Compression::Zstd, Arc::new(ZstdCompressor));
+        let client_id = Uuid::new_v4();
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+        let sequence = Arc::new(SequenceTracker::new());
+
+        let hello = ProtoMessage::Hello(HelloMessage {
+            header: MessageHeader::new(client_id, 0),
+            protocol_version: "0.1.0".into(),
+            capabilities: vec![MEDIA_SOURCE_CAPABILITY.into(), Compression::Zstd.capability()],
+            node_type: NodeType::Client,
+            auth_token: None,
+            zone: None,
+            session_token: None,
+            min_latency_ms: None,
+            max_acceptable_latency_ms: None,
+            ingest_token: None,
+        });
+        let hello = serde_json::to_string(&hello).unwrap();
+        server
+            .handle_text(&client_id, &hello, &tx, &disconnect, &sequence, None)
+            .await;
+        let welcome = match rx.try_recv() {
+            Ok(ProtoMessage::Hello(welcome)) => welcome,
+            other => panic!("Expected hello, got {:?}", other),
+        };
+
+        // The sender picks zstd from what the server announced
+        let mut compressors = Compressors::default();
+        compressors.register(Compression::Zstd, Arc::new(ZstdCompressor));
+        let algorithm = compressors.negotiate(&welcome.capabilities).unwrap();
+        assert_eq!(algorithm, Compression::Zstd);
+
+        server
+            .media_server
+            .create_stream("live".into(), "pcm16".into())
+            .await
+            .unwrap();
+        let mut frame_rx = server.media_server.subscribe_frames("live").await.unwrap();
+
+        let mut pcm = vec![0u8; 1920];
+        pcm[800..840].iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let mut chunk = MediaDataMessage {
+            header: MessageHeader::new(client_id, 1),
+            track_id: "live".into(),
+            chunk_index: 0,
+            timestamp: 100.0,
+            duration: 0.02,
+            data: pcm.clone(),
+            codec: "pcm16".into(),
+            is_keyframe: false,
+            compression: None,
+        };
+        assert!(compressors.compress_chunk(algorithm, &mut chunk).unwrap());
+        assert!(chunk.data.len() < pcm.len());
+        let chunk = serde_json::to_string(&ProtoMessage::MediaData(chunk)).unwrap();
+        server
+            .handle_text(&client_id, &chunk, &tx, &disconnect, &sequence, None)
+            .await;
+        assert!(rx.try_recv().is_err(), "Unexpected response to media data");
+
+        let frame = frame_rx.try_recv().unwrap();
+        assert_eq!(frame.data, pcm);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnect_with_session_token_restores_subscriptions() {
+        let server = test_server(BroadcastPolicy::Drop);
+        server
+            .media_server
+            .create_stream("live".into(), "opus".into())
+            .await
+            .unwrap();
+
+        // Returns the ID the connection ended up with and its session token
+        let connect = |session_token: Option<String>| {
+            let server = &server;
+            async move {
+                let connection_id = Uuid::new_v4();
+                let (tx, mut rx) = mpsc::channel(10);
+                let hello = ProtoMessage::Hello(HelloMessage {
+                    header: MessageHeader::new(connection_id, 0),
+                    protocol_version: "0.1.0".into(),
+                    capabilities: vec!["media_streaming".into()],
+                    node_type: NodeType::Client,
+                    auth_token: None,
+                    zone: None,
+                    session_token,
+                    min_latency_ms: None,
+                    max_acceptable_latency_ms: None,
+                    ingest_token: None,
+                });
+                let resumed = server
+                    .handle_text(
+                        &connection_id,
+                        &serde_json::to_string(&hello).unwrap(),
+                        &tx,
+                        &CancellationToken::new(),
+                        &Arc::new(SequenceTracker::new()),
+                        None,
+                    )
+                    .await;
+                let token = match rx.try_recv() {
+                    Ok(ProtoMessage::Hello(welcome)) => welcome.session_token.unwrap(),
+                    other => panic!("Expected hello, got {:?}", other),
+                };
+                (resumed.unwrap_or(connection_id), token)
+            }
+        };
+
+        let (client_id, token) = connect(None).await;
+        server.subscribe_client(&client_id, "live".into()).await.unwrap();
+        server.remove_client(&client_id).await;
+        assert_eq!(server.media_server.client_subscriptions(client_id).await, None);
+
+        // The reconnect continues as the same client with a fresh token
+        let (resumed_id, next_token) = connect(Some(token.clone())).await;
+        assert_eq!(resumed_id, client_id);
+        assert_ne!(next_token, token);
+        assert_eq!(
+            server.media_server.client_subscriptions(client_id).await,
+            Some(vec!["live".to_string()])
+        );
+        assert_eq!(server.get_connected_clients().await.len(), 1);
+
+        // Tokens are used once, and expire with the resume window
+        server.remove_client(&client_id).await;
+        let (other_id, _) = connect(Some(token)).await;
+        assert_ne!(other_id, client_id);
+
+        tokio::time::advance(Duration::from_millis(server.config.session_resume_ms + 1)).await;
+        let (late_id, _) = connect(Some(next_token)).await;
+        assert_ne!(late_id, client_id);
+        assert!(server.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_hello_is_rejected_and_keeps_the_session() {
+        let server = test_server(BroadcastPolicy::Drop);
+        server
+            .media_server
+            .create_stream("live".into(), "opus".into())
+            .await
+            .unwrap();
+        let client_id = Uuid::new_v4();
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+        let sequence = Arc::new(SequenceTracker::new());
+        let hello = |session_token: Option<String>| {
+            serde_json::to_string(&ProtoMessage::Hello(HelloMessage {
+                header: MessageHeader::new(client_id, 0),
+                protocol_version: "0.1.0".into(),
+                capabilities: vec!["media_streaming".into()],
+                node_type: NodeType::Client,
+                auth_token: None,
+                zone: None,
+                session_token,
+                min_latency_ms: None,
+                max_acceptable_latency_ms: None,
+                ingest_token: None,
+            }))
+            .unwrap()
+        };
+
+        server
+            .handle_text(&client_id, &hello(None), &tx, &disconnect, &sequence, None)
+            .await;
+        let token = match rx.try_recv() {
+            Ok(ProtoMessage::Hello(welcome)) => welcome.session_token.unwrap(),
+            other => panic!("Expected hello, got {:?}", other),
+        };
+        while rx.try_recv().is_ok() {}
+        server.subscribe_client(&client_id, "live".into()).await.unwrap();
+
+        // Neither a plain hello nor one presenting the session's own token
+        // sets the client up again
+        for session_token in [None, Some(token)] {
+            let resumed = server
+                .handle_text(&client_id, &hello(session_token), &tx, &disconnect, &sequence, None)
+                .await;
+            assert_eq!(resumed, None);
+            match rx.try_recv() {
+                Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::ProtocolError),
+                other => panic!("Expected error, got {:?}", other),
+            }
+            assert!(rx.try_recv().is_err());
+        }
+        assert!(!disconnect.is_cancelled());
+        assert_eq!(
+            server.media_server.client_subscriptions(client_id).await,
+            Some(vec!["live".to_string()])
+        );
+        assert_eq!(server.get_connected_clients().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_reports_grow_target_latency() {
+        let policy = crate::media::BufferPolicy {
+            max_latency: Duration::from_millis(300),
+            underruns_to_grow: 1,
+            adjustment_cooldown: Duration::ZERO,
+            max_adjustment_per_minute: Duration::MAX,
+            ..Default::default()
+        };
+        let config = Arc::new(ServerConfig {
+            buffer_policy: policy,
+            ..Default::default()
+        });
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::with_config(clock, config.clone())),
+            config,
+        );
+        let (client, _client_rx) = add_test_client(&server, 10).await;
+        server.media_server.add_client(client.client_id).await.unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let mut latencies = Vec::new();
+        for sequence in 0..30 {
+            let report = ProtoMessage::BufferReport(BufferReportMessage {
+                header: MessageHeader::new(client.client_id, sequence),
+                track_id: "live".into(),
+                underruns: 1,
+                overruns: 0,
+                occupancy_ms: 0.0,
+                playout_delay_ms: 25.0,
+            });
+            server
+                .handle_text(
+                    &client.client_id,
+                    &serde_json::to_string(&report).unwrap(),
+                    &tx,
+                    &client.disconnect,
+                    &client.sequence,
+                    None,
+                )
+                .await;
+            match rx.try_recv() {
+                Ok(ProtoMessage::BufferReportAck(ack)) => {
+                    assert_eq!(ack.track_id, "live");
+                    latencies.push(ack.target_latency_ms);
+                }
+                other => panic!("Expected buffer report ack, got {:?}", other),
+            }
+        }
+
+        // Every underrun raises the latency until it reaches the bound
+        assert!(latencies[1] > latencies[0], "{:?}", latencies);
+        assert!(latencies.windows(2).all(|pair| pair[1] >= pair[0]), "{:?}", latencies);
+        assert_eq!(latencies.last(), Some(&300));
+
+        let stats = server.media_server.get_buffer_stats(client.client_id).await.unwrap();
+        assert_eq!(stats.underrun_count, 30);
+        assert_eq!(stats.client_playout_delay_ms, Some(25));
+    }
+
+    #[tokio::test]
+    async fn test_clients_only_subscribe_to_codecs_they_decode() {
+        let server = test_server(BroadcastPolicy::Drop);
+        server.media_server.create_stream("cam".into(), "h264".into()).await.unwrap();
+        server.media_server.create_stream("live".into(), "opus".into()).await.unwrap();
+
+        let client_id = Uuid::new_v4();
+        let (tx, _rx) = mpsc::channel(10);
+        let hello = ProtoMessage::Hello(HelloMessage {
+            header: MessageHeader::new(client_id, 0),
+            protocol_version: "0.1.0".into(),
+            capabilities: vec!["media_streaming".into(), "codec:opus".into()],
+            node_type: NodeType::Client,
+            auth_token: None,
+            zone: None,
+            session_token: None,
+            min_latency_ms: None,
+            max_acceptable_latency_ms: None,
+            ingest_token: None,
+        });
+        server
+            .handle_text(
+                &client_id,
+                &serde_json::to_string(&hello).unwrap(),
+                &tx,
+                &CancellationToken::new(),
+                &Arc::new(SequenceTracker::new()),
+                None,
+            )
+            .await;
+
+        let result = server.subscribe_client(&client_id, "cam".into()).await;
+        assert!(matches!(result, Err(ControlError::MediaError(_))), "{:?}", result);
+        server.subscribe_client(&client_id, "live".into()).await.unwrap();
+        assert_eq!(
+            server.media_server.client_subscriptions(client_id).await,
+            Some(vec!["live".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_media_data_requires_source_capability() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (client, _client_rx) = add_test_client(&server, 10).await;
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let chunk = media_chunk(client.client_id, 0);
+        server
+            .handle_text(
+                &client.client_id,
+                &chunk,
+                &tx,
+                &client.disconnect,
+                &client.sequence,
+                None,
+            )
+            .await;
+
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::Unauthorized),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        assert!(server.media_server.subscribe_frames("live").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_operations_require_advertised_capability() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let all = ["clock_sync", "audio", "media_source", "media_streaming"];
+        let (allowed, _allowed_rx) = add_client_with_capabilities(&server, 10, &all).await;
+        let (denied, _denied_rx) = add_test_client(&server, 10).await;
+
+        for op in ClientOperation::ALL {
+            assert!(server.authorize(&allowed.client_id, op).await.is_ok(), "{} denied", op);
+            let error = server.authorize(&denied.client_id, op).await.unwrap_err();
+            assert_eq!(error.code(), ErrorCode::Unauthorized, "{} allowed", op);
+        }
+
+        // Gating applies before the operation itself is attempted
+        server.media_server.add_client(allowed.client_id).await.unwrap();
+        server.media_server.add_client(denied.client_id).await.unwrap();
+        server
+            .media_server
+            .create_stream("track".into(), "opus".into())
+            .await
+            .unwrap();
+        server
+            .subscribe_client(&allowed.client_id, "track".into())
+            .await
+            .unwrap();
+        assert!(matches!(
+            server.subscribe_client(&denied.client_id, "track".into()).await,
+            Err(ControlError::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_clock_sync_without_capability_is_rejected() {
+        let mut config = ServerConfig::default();
+        config
+            .required_capabilities
+            .apply_overrides("media_control=")
+            .unwrap();
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::new(clock)),
+            Arc::new(config),
+        );
+        let (client, _client_rx) = add_client_with_capabilities(&server, 10, &["audio"]).await;
+        let (tx, mut rx) = mpsc::channel(10);
+
+        let sync = ProtoMessage::ClockSync(crate::protocol::ClockSyncMessage {
+            header: MessageHeader::new(client.client_id, 1),
+            t1: 0.0,
+        });
+        let sync = serde_json::to_string(&sync).unwrap();
+        server
+            .handle_text(&client.client_id, &sync, &tx, &client.disconnect, &client.sequence, None)
+            .await;
+
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::Unauthorized),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        assert!(server
+            .authorize(&client.client_id, ClientOperation::MediaControl)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_json_sends_error_frame() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+
+        server
+            .handle_text(
+                &Uuid::new_v4(),
+                "{not json",
+                &tx,
+                &disconnect,
+                &Arc::new(SequenceTracker::new()),
+                None,
+            )
+            .await;
+
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::ProtocolError),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        assert!(!disconnect.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_sdp_answer_reaches_media_server() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (tx, mut rx) = mpsc::channel(10);
+        let client_id = Uuid::new_v4();
+        let answer = |sdp: &str| {
+            serde_json::to_string(&ProtoMessage::SdpAnswer(SdpAnswerMessage {
+                header: MessageHeader::new(client_id, 0),
+                sdp: sdp.into(),
+            }))
+            .unwrap()
+        };
+        let send = |text: String| {
+            let server = &server;
+            let tx = tx.clone();
+            async move {
+                let sequence = Arc::new(SequenceTracker::new());
+                server
+                    .handle_text(&client_id, &text, &tx, &CancellationToken::new(), &sequence, None)
+                    .await;
+            }
+        };
+
+        // Without a peer connection there is nothing to answer
+        send(answer("v=0")).await;
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::MediaError),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+
+        // With one, an answer to no offer is refused by the connection
+        server.media_server.add_client(client_id).await.unwrap();
+        send(answer("not sdp")).await;
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::MediaError),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        server.media_server.remove_client(client_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_transcode_requests_need_a_supported_codec_pair() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let capabilities = ["media_streaming", "codec:pcmu", "codec:vp8"];
+        let (client, _rx) = add_client_with_capabilities(&server, 10, &capabilities).await;
+        let client_id = client.client_id;
+        let media = &server.media_server;
+        media.create_stream("voice".into(), "pcm16".into()).await.unwrap();
+        media.create_stream("cam".into(), "h264".into()).await.unwrap();
+        media.add_client(client_id).await.unwrap();
+        media.set_client_codecs(client_id, CodecSupport::from_capabilities(&client.capabilities)).await;
+        
+        let request = |track_id: &str, target_codec: &str| TranscodeRequestMessage {
+            header: MessageHeader::new(client_id, 0),
+            track_id: track_id.into(),
+            target_codec: target_codec.into(),
+        };
+        
+        // Video is not transcoded, nor to a codec the client cannot decode
+        for (track_id, target_codec) in [("cam", "vp8"), ("voice", "pcma"), ("missing", "pcmu")] {
+            let result = server.handle_transcode_request(&client_id, request(track_id, target_codec)).await;
+            assert!(matches!(result, Err(ControlError::MediaError(_))), "{:?}", result);
+        }
+        assert!(server.subscribe_client(&client_id, "voice".into()).await.is_err());
+        
+        // PCM can reach the client as μ-law, after which it may subscribe
+        server.handle_transcode_request(&client_id, request("voice", "PCMU")).await.unwrap();
+        server.subscribe_client(&client_id, "voice".into()).await.unwrap();
+        let stats = media.stats().await;
+        assert_eq!(stats.clients[0].transcodes["voice"], "pcmu");
+        
+        media.remove_client(client_id).await;
+    }
+    
+    #[tokio::test]
+    async fn test_hello_requires_valid_auth_token() {
+        let config = ServerConfig {
+            auth_secret: Some("secret".into()),
+            ..Default::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::new(clock)),
+            Arc::new(config),
+        );
+        let node_id = Uuid::new_v4();
+        let hello = |auth_token: Option<String>| {
+            serde_json::to_string(&ProtoMessage::Hello(HelloMessage {
+                header: MessageHeader::new(node_id, 0),
+                protocol_version: "0.1.0".into(),
+                capabilities: vec![],
+                node_type: NodeType::Client,
+                auth_token,
+                zone: None,
+                session_token: None,
+                min_latency_ms: None,
+                max_acceptable_latency_ms: None,
+                ingest_token: None,
+            }))
+            .unwrap()
+        };
+
+        let expired = TokenAuthority::new("secret").mint_until(node_id, 0);
+        for token in [None, Some(expired)] {
+            let client_id = Uuid::new_v4();
+            let (tx, mut rx) = mpsc::channel(10);
+            let disconnect = CancellationToken::new();
+            let sequence = Arc::new(SequenceTracker::new());
+            server
+                .handle_text(&client_id, &hello(token), &tx, &disconnect, &sequence, None)
+                .await;
+
+            match rx.try_recv() {
+                Ok(ProtoMessage::Error(error)) => {
+                    assert_eq!(error.code, ErrorCode::AuthenticationFailed)
+                }
+                other => panic!("Expected error frame, got {:?}", other),
+            }
+            assert!(disconnect.is_cancelled());
+            assert!(!server.clients.read().await.contains_key(&client_id));
+        }
+
+        let client_id = Uuid::new_v4();
+        let (tx, mut rx) = mpsc::channel(10);
+        let disconnect = CancellationToken::new();
+        let sequence = Arc::new(SequenceTracker::new());
+        let token = server.mint_token(node_id, Duration::from_secs(60));
+        server
+            .handle_text(&client_id, &hello(token), &tx, &disconnect, &sequence, None)
+            .await;
+        assert!(matches!(rx.try_recv(), Ok(ProtoMessage::Hello(_))));
+        assert!(server.clients.read().await.contains_key(&client_id));
+    }
+
+    #[tokio::test]
+    async fn test_node_must_sign_challenge_with_announced_key() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let server = test_server(BroadcastPolicy::Drop);
+        let announced = SigningKey::from_bytes(&[1; 32]);
+        let wrong = SigningKey::from_bytes(&[2; 32]);
+
+        for (signer, trusted) in [(&announced, true), (&wrong, false)] {
+            let (client, mut rx) = add_test_client(&server, 10).await;
+            let announce = NodeAnnounceMessage {
+                header: MessageHeader::new(client.client_id, 0),
+                node_type: NodeType::Replica,
+                capabilities: vec![],
+                endpoint: "10.0.0.2:8080".into(),
+                public_key: Some(announced.verifying_key().to_bytes().to_vec()),
+            };
+            server
+                .handle_node_announce(&client.client_id, announce, &client.tx)
+                .await
+                .unwrap();
+            let nonce = match rx.try_recv() {
+                Ok(ProtoMessage::NodeChallenge(challenge)) => challenge.nonce,
+                other => panic!("Expected challenge, got {:?}", other),
+            };
+
+            let response = NodeChallengeResponseMessage {
+                header: MessageHeader::new(client.client_id, 1),
+                signature: signer.sign(&nonce).to_bytes().to_vec(),
+            };
+            let result = server.handle_challenge_response(&client.client_id, response).await;
+            let node_type = server.clients.read().await[&client.client_id].node_type;
+            if trusted {
+                assert!(result.is_ok());
+                assert_eq!(node_type, NodeType::Replica);
+            } else {
+                let error = result.unwrap_err();
+                assert_eq!(error.code(), ErrorCode::Unauthorized);
+                assert_eq!(node_type, NodeType::Client);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_split_brain_restarts_election_at_higher_epoch() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (node_a, mut rx_a) = add_test_client(&server, 10).await;
+        let (node_b, mut rx_b) = add_test_client(&server, 10).await;
+        let (_client, mut client_rx) = add_test_client(&server, 10).await;
+        for node in [&node_a, &node_b] {
+            server.clients.write().await.get_mut(&node.client_id).unwrap().node_type = NodeType::Master;
+        }
+        let (master_a, master_b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let send = |node: &ClientConnection, epoch, score, current_master| {
+            let message = ProtoMessage::MasterElection(MasterElectionMessage {
+                header: MessageHeader::new(node.client_id, 0),
+                election_id: Uuid::new_v4(),
+                epoch,
+                candidate_score: score,
+                current_master,
+                demoted: None,
+                signature: None,
+            });
+            let text = serde_json::to_string(&message).unwrap();
+            let server = &server;
+            let node = node.clone();
+            async move {
+                server
+                    .handle_text(&node.client_id, &text, &node.tx, &node.disconnect, &node.sequence, None)
+                    .await
+            }
+        };
+
+        // Each side of a healed partition still follows its own master
+        send(&node_a, 0, 0.5, Some(master_a)).await;
+        assert_eq!(server.election.read().await.candidate(), Some((node_a.client_id, 4.5)));
+        assert_eq!(server.clock_manager.master_peer(), Some(node_a.client_id));
+        send(&node_b, 0, 0.7, Some(master_b)).await;
+
+        for rx in [&mut rx_a, &mut rx_b] {
+            match rx.try_recv() {
+                Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::ClusterError),
+                other => panic!("Expected cluster error, got {:?}", other),
+            }
+            match rx.try_recv() {
+                Ok(ProtoMessage::MasterElection(election)) => {
+                    assert_eq!(election.epoch, 1);
+                    assert_eq!(election.current_master, None);
+                }
+                other => panic!("Expected re-election, got {:?}", other),
+            }
+        }
+        assert!(client_rx.try_recv().is_err());
+        {
+            let election = server.election.read().await;
+            assert_eq!((election.epoch(), election.split_brains()), (1, 1));
+            assert_eq!(election.candidate(), None);
+        }
+        assert_eq!(server.clock_manager.master_peer(), None);
+
+        // Claims from before the split no longer count
+        send(&node_a, 0, 0.9, Some(master_a)).await;
+        assert_eq!(server.election.read().await.candidate(), None);
+
+        // The re-election settles on one master for both sides
+        send(&node_a, 1, 0.5, None).await;
+        send(&node_b, 1, 0.7, None).await;
+        assert_eq!(server.election.read().await.candidate(), Some((node_b.client_id, 4.7)));
+        let health = server.health().await;
+        assert_eq!(
+            (health.election_epoch, health.election_leader, health.split_brains),
+            (1, Some(node_b.client_id), 1)
+        );
+        assert_eq!(server.clock_manager.master_peer(), Some(node_b.client_id));
+        send(&node_a, 1, 0.5, Some(node_b.client_id)).await;
+        send(&node_b, 1, 0.7, Some(node_b.client_id)).await;
+        assert!(rx_a.try_recv().is_err());
+        assert_eq!(server.election.read().await.epoch(), 1);
+
+        // A master claimed after the last claim expired is a handover
+        tokio::time::advance(election::MASTER_CLAIM_VALIDITY).await;
+        send(&node_a, 1, 0.5, Some(node_a.client_id)).await;
+        assert_eq!(server.election.read().await.split_brains(), 1);
+    }
+
+    /// Verify `node` as a master and have it stand in the election
+    async fn stand_for_master(server: &ControlServer, node: &ClientConnection, candidate_score: f64) {
+        server.clients.write().await.get_mut(&node.client_id).unwrap().node_type = NodeType::Master;
+        let message = ProtoMessage::MasterElection(MasterElectionMessage {
+            header: MessageHeader::new(node.client_id, 0),
+            election_id: Uuid::new_v4(),
+            epoch: 0,
+            candidate_score,
+            current_master: None,
+            demoted: None,
+            signature: None,
+        });
+        let text = serde_json::to_string(&message).unwrap();
+        server
+            .handle_text(&node.client_id, &text, &node.tx, &node.disconnect, &node.sequence, None)
+            .await;
+    }
+
+    /// Wait for the clock manager to have processed `count` samples of `peer_id`
+    async fn wait_for_clock_samples(clock: &ClockManager, peer_id: Uuid, count: u64) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while clock.get_peer_stats(&peer_id).await.map_or(0, |stats| stats.sample_count) < count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("Clock samples were not processed");
+    }
+
+    /// Local time, and the offset of the synchronized clock from it
+    async fn synced_offset(clock: &ClockManager) -> (f64, f64) {
+        let local = crate::protocol::get_current_time();
+        (local, clock.now().await - local)
+    }
+
+    #[tokio::test]
+    async fn test_elected_master_clock_is_predicted_from_its_drift() {
+        let server = test_server(BroadcastPolicy::Drop);
+        tokio::spawn(server.clock_manager.clone().run());
+        let (node, _rx) = add_test_client(&server, 10).await;
+        stand_for_master(&server, &node, 0.5).await;
+
+        // The master's clock runs 1% fast
+        let drift_rate = 0.01;
+        let start = crate::protocol::get_current_time();
+        let master_offset = |local: f64| 0.1 + drift_rate * (local - start);
+        for _ in 0..40 {
+            let offset = master_offset(crate::protocol::get_current_time());
+            let sample = ClockSample { offset, rtt: 0.001 };
+            server.clock_manager.add_sample(node.client_id, sample).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        wait_for_clock_samples(&server.clock_manager, node.client_id, 40).await;
+
+        // Without new samples, the offset keeps following the drift
+        let (before, offset_before) = synced_offset(&server.clock_manager).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let (after, offset_after) = synced_offset(&server.clock_manager).await;
+        let gained = offset_after - offset_before;
+        assert!((gained - drift_rate * (after - before)).abs() < 1e-3, "gained {}", gained);
+        assert!((offset_after - master_offset(after)).abs() < 1e-3, "offset {}", offset_after);
+    }
+
+    #[tokio::test]
+    async fn test_master_handover_is_slewed_in() {
+        let server = test_server(BroadcastPolicy::Drop);
+        tokio::spawn(server.clock_manager.clone().run());
+        let (old_master, _old_rx) = add_test_client(&server, 10).await;
+        let (new_master, _new_rx) = add_test_client(&server, 10).await;
+        stand_for_master(&server, &old_master, 0.5).await;
+
+        // The two masters' clocks are 20ms apart
+        let send_samples = |node: &ClientConnection, offset, count| {
+            let clock = server.clock_manager.clone();
+            let node_id = node.client_id;
+            async move {
+                for _ in 0..count {
+                    clock.add_sample(node_id, ClockSample { offset, rtt: 0.001 }).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+        send_samples(&old_master, 0.1, 10).await;
+        send_samples(&new_master, 0.12, 10).await;
+        wait_for_clock_samples(&server.clock_manager, new_master.client_id, 10).await;
+        let (_, offset) = synced_offset(&server.clock_manager).await;
+        assert!((offset - 0.1).abs() < 1e-3, "offset {}", offset);
+
+        // Time does not step to the new master, but moves toward it at the
+        // slew rate
+        stand_for_master(&server, &new_master, 0.9).await;
+        assert_eq!(server.clock_manager.master_peer(), Some(new_master.client_id));
+        send_samples(&new_master, 0.12, 1).await;
+        wait_for_clock_samples(&server.clock_manager, new_master.client_id, 11).await;
+        let (before, offset_before) = synced_offset(&server.clock_manager).await;
+        assert!((offset_before - 0.1).abs() < 1e-3, "offset {}", offset_before);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let (after, offset_after) = synced_offset(&server.clock_manager).await;
+        let slew_rate = ServerConfig::default().max_clock_slew_ppm / 1e6;
+        let slewed = offset_after - offset_before;
+        assert!((slewed - slew_rate * (after - before)).abs() < 5e-4, "slewed {}", slewed);
+    }
+
+    #[tokio::test]
+    async fn test_equal_scores_prefer_node_type_then_lower_id() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let mut nodes = Vec::new();
+        for node_type in [NodeType::Replica, NodeType::Master, NodeType::Master] {
+            let (node, _rx) = add_test_client(&server, 10).await;
+            server.clients.write().await.get_mut(&node.client_id).unwrap().node_type = node_type;
+            nodes.push(node);
+        }
+        let stand = |node: &ClientConnection| {
+            let node_id = node.client_id;
+            let election = MasterElectionMessage {
+                header: MessageHeader::new(node_id, 0),
+                election_id: Uuid::new_v4(),
+                epoch: 0,
+                candidate_score: 0.8,
+                current_master: None,
+                demoted: None,
+                signature: None,
+            };
+            let server = &server;
+            async move { server.handle_master_election(&node_id, election).await }
+        };
+        let leader = || async { server.election.read().await.candidate().map(|(id, _)| id) };
+
+        // The Master outranks the Replica standing with the same score
+        stand(&nodes[0]).await;
+        assert_eq!(leader().await, Some(nodes[0].client_id));
+        stand(&nodes[1]).await;
+        assert_eq!(leader().await, Some(nodes[1].client_id));
+        stand(&nodes[0]).await;
+        assert_eq!(leader().await, Some(nodes[1].client_id));
+
+        // Between identical Masters the lower ID wins in either order
+        let lower = nodes[1].client_id.min(nodes[2].client_id);
+        stand(&nodes[2]).await;
+        assert_eq!(leader().await, Some(lower));
+        stand(&nodes[1]).await;
+        assert_eq!(leader().await, Some(lower));
+
+        // An unstable link costs more than the role is worth once weighted so
+        let weights = ElectionWeights {
+            node_type: 0.1,
+            ..Default::default()
+        };
+        let steady = CandidateProfile {
+            node_type: NodeType::Replica,
+            candidate_score: 0.8,
+            uptime_seconds: 7200,
+            rtt_stddev_ms: Some(1.0),
+            connected_clients: 50,
+        };
+        let jittery = CandidateProfile {
+            node_type: NodeType::Master,
+            rtt_stddev_ms: Some(80.0),
+            ..steady.clone()
+        };
+        assert!(weights.score(&steady) > weights.score(&jittery));
+        assert!(ElectionWeights::default().score(&steady) < ElectionWeights::default().score(&jittery));
+
+        let mut weights = ElectionWeights::default();
+        weights.apply_overrides("node_type=0.5, clients=2").unwrap();
+        assert_eq!((weights.node_type, weights.clients), (0.5, 2.0));
+        assert!(weights.apply_overrides("luck=1").is_err());
+        assert!(weights.apply_overrides("uptime=-1").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drifting_master_steps_down() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (master, mut master_rx) = add_test_client(&server, 10).await;
+        let (replica, mut replica_rx) = add_test_client(&server, 10).await;
+        for (node, node_type) in [(&master, NodeType::Master), (&replica, NodeType::Replica)] {
+            server.clients.write().await.get_mut(&node.client_id).unwrap().node_type = node_type;
+        }
+        let stand = |node_id: Uuid, epoch| {
+            let election = MasterElectionMessage {
+                header: MessageHeader::new(node_id, 0),
+                election_id: Uuid::new_v4(),
+                epoch,
+                candidate_score: 0.8,
+                current_master: None,
+                demoted: None,
+                signature: None,
+            };
+            let server = &server;
+            async move { server.handle_master_election(&node_id, election).await }
+        };
+        let report = |node_id: Uuid, drift_ppm: f64| {
+            let status = NodeStatusMessage {
+                header: MessageHeader::new(node_id, 0),
+                node_type: NodeType::Master,
+                connected_clients: 10,
+                cpu_usage: 0.2,
+                memory_usage: 0.3,
+                battery_level: None,
+                network_quality: crate::protocol::NetworkQuality::Good,
+                avg_rtt_ms: 5.0,
+                packet_loss_percent: 0.0,
+                uptime_seconds: 600,
+                drift_ppm: Some(drift_ppm),
+                signature: None,
+            };
+            let server = &server;
+            async move { server.handle_node_status(&node_id, status).await }
+        };
+        let leader = || async { server.election.read().await.candidate().map(|(id, _)| id) };
+
+        stand(master.client_id, 0).await;
+        stand(replica.client_id, 0).await;
+        assert_eq!(leader().await, Some(master.client_id));
+
+        // A drift spike that recovers before the sustain period is tolerated
+        report(master.client_id, 150.0).await;
+        tokio::time::advance(Duration::from_secs(6)).await;
+        report(master.client_id, 70.0).await;
+        tokio::time::advance(Duration::from_secs(6)).await;
+        report(master.client_id, 150.0).await;
+        tokio::time::advance(Duration::from_secs(6)).await;
+
+        // Hovering just under the limit does not restart the sustain period
+        report(master.client_id, 90.0).await;
+        assert!(master_rx.try_recv().is_err());
+        assert_eq!(leader().await, Some(master.client_id));
+        tokio::time::advance(Duration::from_secs(4)).await;
+        report(master.client_id, 150.0).await;
+
+        for rx in [&mut master_rx, &mut replica_rx] {
+            match rx.try_recv() {
+                Ok(ProtoMessage::MasterElection(election)) => {
+                    assert_eq!(election.epoch, 1);
+                    assert_eq!(election.demoted, Some(master.client_id));
+                }
+                other => panic!("Expected step-down, got {:?}", other),
+            }
+        }
+        assert_eq!(leader().await, None);
+
+        // The demoted master cannot lead the re-election
+        stand(master.client_id, 1).await;
+        assert_eq!(leader().await, None);
+        stand(replica.client_id, 1).await;
+        assert_eq!(leader().await, Some(replica.client_id));
+
+        // Other nodes' statuses do not demote the leader
+        report(master.client_id, 500.0).await;
+        tokio::time::advance(Duration::from_secs(20)).await;
+        report(master.client_id, 500.0).await;
+        assert_eq!(leader().await, Some(replica.client_id));
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_master_election_rejected_when_signing_required() {
+        use ed25519_dalek::SigningKey;
+
+        let config = ServerConfig {
+            require_signed_cluster_messages: true,
+            ..Default::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        let server = ControlServer::new(
+            clock.clone(),
+            Arc::new(MediaServer::new(clock)),
+            Arc::new(config),
+        );
+        let key = SigningKey::from_bytes(&[5; 32]);
+        let (client, mut rx) = add_test_client(&server, 10).await;
+        if let Some(client) = server.clients.write().await.get_mut(&client.client_id) {
+            client.node_type = NodeType::Replica;
+            client.public_key = Some(key.verifying_key());
+        }
+
+        let election = |sequence| {
+            ProtoMessage::MasterElection(MasterElectionMessage {
+                header: MessageHeader::new(client.client_id, sequence),
+                election_id: Uuid::new_v4(),
+                epoch: 0,
+                candidate_score: 0.9,
+                current_master: None,
+                demoted: None,
+                signature: None,
+            })
+        };
+        let send = |message: ProtoMessage| {
+            let text = serde_json::to_string(&message).unwrap();
+            let server = &server;
+            let client = &client;
+            async move {
+                let ClientConnection { client_id, tx, disconnect, sequence, .. } = client;
+                server
+                    .handle_text(client_id, &text, tx, disconnect, sequence, None)
+                    .await
+            }
+        };
+
+        send(election(0)).await;
+        match rx.try_recv() {
+            Ok(ProtoMessage::Error(error)) => assert_eq!(error.code, ErrorCode::Unauthorized),
+            other => panic!("Expected error frame, got {:?}", other),
+        }
+        assert!(server.election.read().await.candidate().is_none());
+
+        // Signed by another key
+        let mut forged = election(1);
+        signing::sign_cluster_message(&mut forged, &SigningKey::from_bytes(&[6; 32])).unwrap();
+        send(forged).await;
+        assert!(matches!(rx.try_recv(), Ok(ProtoMessage::Error(_))));
+        assert!(server.election.read().await.candidate().is_none());
+
+        let mut signed = election(2);
+        signing::sign_cluster_message(&mut signed, &key).unwrap();
+        send(signed).await;
+        assert!(rx.try_recv().is_err());
+        let candidate = server.election.read().await.candidate();
+        assert_eq!(candidate.map(|(id, _)| id), Some(client.client_id));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_messages_from_unverified_clients_are_dropped() {
+        let server = test_server(BroadcastPolicy::Drop);
+        let (client, mut rx) = add_test_client(&server, 10).await;
+        let (tx, _) = mpsc::channel(10);
+        let stranger = ClientConnection { client_id: Uuid::new_v4(), tx, ..client.clone() };
+
+        for sender in [&client, &stranger] {
+            let messages = [
+                ProtoMessage::NodeStatus(NodeStatusMessage {
+                    header: MessageHeader::new(sender.client_id, 0),
+                    node_type: NodeType::Master,
+                    connected_clients: 10,
+                    cpu_usage: 0.2,
+                    memory_usage: 0.3,
+                    battery_level: None,
+                    network_quality: crate::protocol::NetworkQuality::Good,
+                    avg_rtt_ms: 5.0,
+                    packet_loss_percent: 0.0,
+                    uptime_seconds: 600,
+                    drift_ppm: None,
+                    signature: None,
+                }),
+                ProtoMessage::MasterElection(MasterElectionMessage {
+                    header: MessageHeader::new(sender.client_id, 1),
+                    election_id: Uuid::new_v4(),
+                    epoch: 0,
+                    candidate_score: 0.9,
+                    current_master: Some(sender.client_id),
+                    demoted: None,
+                    signature: None,
+                }),
+            ];
+            for message in messages {
+                let text = serde_json::to_string(&message).unwrap();
+                let ClientConnection { client_id, tx, disconnect, sequence, .. } = sender;
+                server.handle_text(client_id, &text, tx, disconnect, sequence, None).await;
+            }
+        }
+
+        assert!(rx.try_recv().is_err());
+        assert!(server.node_statuses.read().await.is_empty());
+        assert!(server.election.read().await.candidate().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_signed_only_peer_accepts_re_election() {
+        use ed25519_dalek::SigningKey;
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let test_node = |config: ServerConfig| {
+            let clock = Arc::new(ClockManager::new());
+            ControlServer::new(clock.clone(), Arc::new(MediaServer::new(clock)), Arc::new(config))
+        };
+        let server = test_node(ServerConfig {
+            node_signing_key: Some(key.clone()),
+            ..Default::default()
+        });
+        let peer = test_node(ServerConfig {
+            require_signed_cluster_messages: true,
+            ..Default::default()
+        });
+
+        // The peer is a replica of the server, and knows the server by the
+        // key it proved in the announce handshake
+        let (node, mut node_rx) = add_test_client(&server, 10).await;
+        server.clients.write().await.get_mut(&node.client_id).unwrap().node_type = NodeType::Replica;
+        let (upstream, mut upstream_rx) = add_test_client(&peer, 10).await;
+        if let Some(upstream) = peer.clients.write().await.get_mut(&upstream.client_id) {
+            upstream.node_type = NodeType::Master;
+            upstream.public_key = Some(key.verifying_key());
+        }
+
+        // A degraded master's demotion restarts the election
+        let demoted = Uuid::new_v4();
+        server.restart_election(3, None, Some(demoted)).await;
+        let election = match node_rx.try_recv() {
+            Ok(message @ ProtoMessage::MasterElection(_)) => message,
+            other => panic!("Expected master election, got {:?}", other),
+        };
+        assert_eq!(verify_cluster_message(&election, Some(&key.verifying_key())), Ok(()));
+
+        let text = serde_json::to_string(&election).unwrap();
+        let ClientConnection { client_id, tx, disconnect, sequence, .. } = &upstream;
+        peer.handle_text(client_id, &text, tx, disconnect, sequence, None).await;
+        assert!(upstream_rx.try_recv().is_err(), "Peer rejected the re-election");
+        assert_eq!(peer.election.read().await.epoch(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_data_channel_messages_reach_dispatch() {
+        use crate::media::{IceConfig, WebRtcServer};
+        use webrtc::{data_channel::RTCDataChannel, peer_connection::peer_connection_state::RTCPeerConnectionState};
+
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = Arc::new(ServerConfig {
+            ice: ice.clone(),
+            ..Default::default()
+        });
+        let clock = Arc::new(ClockManager::new());
+        let media_server = Arc::new(MediaServer::with_config(clock.clone(), config.clone()));
+        let server = ControlServer::new(clock, media_server.clone(), config);
+        let (client, _client_rx) =
+            add_client_with_capabilities(&server, 10, &["clock_sync", DATA_CHANNEL_CAPABILITY]).await;
+        media_server.add_client(client.client_id).await.unwrap();
+
+        let (channel_tx, mut channel_rx) = mpsc::channel(10);
+        let channels = server
+            .open_data_channels(&client.client_id, &channel_tx, &client.egress)
+            .await
+            .unwrap();
+        assert_eq!(channels.len(), 2);
+
+        // The browser side: a second peer that hands over the channels the
+        // server opened and collects the replies sent on them
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (opened_tx, mut opened_rx) = mpsc::unbounded_channel::<Arc<RTCDataChannel>>();
+        let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+        peer.on_data_channel(Box::new(move |channel| {
+            let opened_tx = opened_tx.clone();
+            let reply_tx = reply_tx.clone();
+            let label = channel.label().to_string();
+            channel.on_message(Box::new(move |message| {
+                let reply: ProtoMessage = serde_json::from_slice(&message.data).unwrap();
+                let _ = reply_tx.send((label.clone(), reply));
+                Box::pin(async {})
+            }));
+            let ready = channel.clone();
+            channel.on_open(Box::new(move || {
+                let _ = opened_tx.send(ready);
+                Box::pin(async {})
+            }));
+            Box::pin(async {})
+        }));
+
+        let server_pc = media_server.peer_connection(client.client_id).await.unwrap();
+        let gathered = |pc: Arc<webrtc::peer_connection::RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        peer.set_remote_description(gathered(server_pc.clone()).await).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        WebRtcServer::handle_answer(&server_pc, gathered(peer.clone()).await).await.unwrap();
+
+        // The media server's clock channel opens alongside them
+        let mut peer_channels = HashMap::new();
+        while peer_channels.len() < 3 {
+            let channel = tokio::time::timeout(Duration::from_secs(10), opened_rx.recv())
+                .await
+                .expect("all data channels open")
+                .unwrap();
+            peer_channels.insert(channel.label().to_string(), channel);
+        }
+        assert_eq!(peer.connection_state(), RTCPeerConnectionState::Connected);
+
+        let sync = |t1| {
+            serde_json::to_string(&ProtoMessage::ClockSync(crate::protocol::ClockSyncMessage {
+                header: MessageHeader::new(client.client_id, 1),
+                t1,
+            }))
+            .unwrap()
+        };
+        let heartbeat = serde_json::to_string(&heartbeat()).unwrap();
+        let exchanges = [
+            ("control", sync(12.5)),
+            ("control-unreliable", sync(25.0)),
+            ("control", heartbeat.clone()),
+            ("control-unreliable", heartbeat),
+        ];
+        let channel_sequence = Arc::new(SequenceTracker::new());
+        let mut replies = Vec::new();
+        for (label, text) in exchanges {
+            peer_channels[label].send_text(text).await.unwrap();
+            let message = tokio::time::timeout(Duration::from_secs(5), channel_rx.recv())
+                .await
+                .expect("message reaches the control server")
+                .unwrap();
+            assert_eq!(message.lane.label(), label);
+            server
+                .handle_channel_message(&client.client_id, message, &client.disconnect, &channel_sequence)
+                .await;
+            let (reply_label, reply) = tokio::time::timeout(Duration::from_secs(5), reply_rx.recv())
+                .await
+                .expect("reply comes back on a data channel")
+                .unwrap();
+            assert_eq!(reply_label, label);
+            replies.push(reply);
+        }
+
+        // Both lanes answer clock syncs on the channel they came in on,
+        // while anything else is refused on the unreliable one
+        match &replies[..] {
+            [
+                ProtoMessage::ClockSyncResponse(reliable),
+                ProtoMessage::ClockSyncResponse(unreliable),
+                ProtoMessage::Heartbeat(_),
+                ProtoMessage::Error(error),
+            ] => {
+                assert_eq!((reliable.t1, unreliable.t1), (12.5, 25.0));
+                assert_eq!(error.code, ErrorCode::ProtocolError);
+            }
+            other => panic!("Unexpected replies: {:?}", other),
+        }
+        assert!(!client.disconnect.is_cancelled());
+
+        peer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hello_is_followed_by_a_gathered_offer() {
+        let ice = crate::media::IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = Arc::new(ServerConfig {
+            ice,
+            ..Default::default()
+        });
+        let clock = Arc::new(ClockManager::new());
+        let media_server = Arc::new(MediaServer::with_config(clock.clone(), config.clone()));
+        let server = ControlServer::new(clock, media_server.clone(), config);
+        let (client, mut client_rx) =
+            add_client_with_capabilities(&server, 10, &["clock_sync", DATA_CHANNEL_CAPABILITY]).await;
+        media_server.add_client(client.client_id).await.unwrap();
+
+        let (channel_tx, _channel_rx) = mpsc::channel(10);
+        let _channels = server
+            .open_data_channels(&client.client_id, &channel_tx, &client.egress)
+            .await
+            .unwrap();
+        server.send_offer(&client.client_id).await;
+        match client_rx.try_recv() {
+            Ok(ProtoMessage::SdpOffer(offer)) => {
+                assert!(!offer.ice_restart);
+                assert!(offer.sdp.contains("m=application"), "{}", offer.sdp);
+                assert!(offer.sdp.contains("a=candidate:"), "{}", offer.sdp);
+            }
+            other => panic!("Expected SDP offer, got {:?}", other),
+        }
+
+        // The connection is negotiated already
+        server.send_offer(&client.client_id).await;
+        assert!(client_rx.try_recv().is_err());
+    }
+}
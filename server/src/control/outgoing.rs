@@ -0,0 +1,175 @@
+use axum::extract::ws::Message as WsMessage;
+use futures::{Sink, SinkExt};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    media::EgressCounter,
+    protocol::{ErrorCode, ErrorMessage, Message, MessageHeader},
+};
+
+/// Consecutive serialization failures after which a client is disconnected
+pub const MAX_ENCODE_FAILURES: u32 = 3;
+
+/// Serialize an outgoing message to JSON
+///
+/// Every message type derives `Serialize`, so a failure means a message was
+/// built in a state serde cannot represent; debug builds fail loudly.
+pub fn encode(message: &Message) -> serde_json::Result<String> {
+    let json = serde_json::to_string(message);
+    debug_assert!(json.is_ok(), "unserializable message {:?}: {:?}", message, json);
+    json
+}
+
+/// Write queued messages to a client's WebSocket until either side closes
+///
+/// A message that fails to serialize is replaced by an `internal_error`
+/// frame so the client knows a reply was lost, and the client is
+/// disconnected after `MAX_ENCODE_FAILURES` failures in a row. Frames
+/// the sink accepts are counted in `egress`.
+pub async fn forward_messages<S>(
+    mut rx: mpsc::Receiver<Message>,
+    mut sink: S,
+    encode: impl Fn(&Message) -> serde_json::Result<String>,
+    server_id: Uuid,
+    client_id: Uuid,
+    disconnect: CancellationToken,
+    egress: Arc<EgressCounter>,
+) where
+    S: Sink<WsMessage> + Unpin,
+{
+    let mut failures = 0;
+
+    while let Some(message) = rx.recv().await {
+        let json = match encode(&message) {
+            Ok(json) => {
+                failures = 0;
+                json
+            }
+            Err(e) => {
+                failures += 1;
+                error!("Failed to serialize message for {}: {}", client_id, e);
+                if failures >= MAX_ENCODE_FAILURES {
+                    warn!(
+                        "{} consecutive messages for {} failed to serialize, disconnecting",
+                        failures, client_id
+                    );
+                    disconnect.cancel();
+                    break;
+                }
+
+                let error = Message::Error(ErrorMessage {
+                    header: MessageHeader::new(server_id, 0),
+                    code: ErrorCode::InternalError,
+                    message: format!("Failed to serialize message: {}", e),
+                    details: None,
+                });
+                match encode(&error) {
+                    Ok(json) => json,
+                    Err(_) => continue,
+                }
+            }
+        };
+
+        let bytes = json.len();
+        if sink.send(WsMessage::Text(json)).await.is_err() {
+            break;
+        }
+        egress.record(1, bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::HeartbeatMessage;
+    use futures::{channel::mpsc as sink_channel, StreamExt};
+    use serde::ser::Error as _;
+
+    fn heartbeat() -> Message {
+        Message::Heartbeat(HeartbeatMessage {
+            header: MessageHeader::new(Uuid::new_v4(), 0),
+            client_time: 0.0,
+            server_time: None,
+        })
+    }
+
+    /// Encoder that cannot serialize heartbeats
+    fn failing_heartbeats(message: &Message) -> serde_json::Result<String> {
+        match message {
+            Message::Heartbeat(_) => Err(serde_json::Error::custom("injected failure")),
+            message => serde_json::to_string(message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serialization_failure_notifies_then_disconnects() {
+        let (tx, rx) = mpsc::channel(10);
+        let (sink, mut sent) = sink_channel::unbounded();
+        let disconnect = CancellationToken::new();
+        let task = tokio::spawn(forward_messages(
+            rx,
+            sink,
+            failing_heartbeats,
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            disconnect.clone(),
+            Arc::default(),
+        ));
+
+        // The client is told in place of the lost message
+        tx.send(heartbeat()).await.unwrap();
+        let Some(WsMessage::Text(json)) = sent.next().await else {
+            panic!("no frame sent");
+        };
+        match serde_json::from_str::<Message>(&json).unwrap() {
+            Message::Error(error) => {
+                assert_eq!(error.code, ErrorCode::InternalError);
+                assert!(error.message.contains("injected failure"));
+            }
+            other => panic!("expected an error frame, got {:?}", other),
+        }
+        assert!(!disconnect.is_cancelled());
+
+        // Repeated failures disconnect
+        for _ in 0..MAX_ENCODE_FAILURES {
+            let _ = tx.send(heartbeat()).await;
+        }
+        task.await.unwrap();
+        assert!(disconnect.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_sent_frames_are_counted_as_egress() {
+        let (tx, rx) = mpsc::channel(10);
+        let (sink, mut sent) = sink_channel::unbounded();
+        let egress = Arc::new(EgressCounter::default());
+        let task = tokio::spawn(forward_messages(
+            rx,
+            sink,
+            encode,
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            CancellationToken::new(),
+            egress.clone(),
+        ));
+
+        let message = heartbeat();
+        let expected = encode(&message).unwrap().len();
+        for _ in 0..3 {
+            tx.send(message.clone()).await.unwrap();
+            let Some(WsMessage::Text(json)) = sent.next().await else {
+                panic!("no frame sent");
+            };
+            assert_eq!(json.len(), expected);
+        }
+        drop(tx);
+        task.await.unwrap();
+        let egress = egress.snapshot();
+        assert_eq!(egress.messages, 3);
+        assert_eq!(egress.bytes, 3 * expected as u64);
+    }
+}
@@ -0,0 +1,102 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Result of checking an incoming message's sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// The sequence directly follows the last one seen (or is the first)
+    InOrder,
+
+    /// Sequence numbers were skipped, e.g. due to dropped messages
+    Gap { missing: u64 },
+
+    /// The sequence is not newer than the last one seen: a reordered or
+    /// replayed message
+    Replay { last_seen: u64 },
+}
+
+/// Tracks the sequence numbers of messages received on one connection
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    last_seen: Mutex<Option<u64>>,
+
+    /// Sequence numbers skipped so far
+    missing: AtomicU64,
+
+    /// Messages that arrived out of order or were replayed
+    out_of_order: AtomicU64,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received sequence number
+    ///
+    /// Out-of-order sequences do not move the expected sequence back, so a
+    /// single stale message is not followed by a spurious gap.
+    pub fn check(&self, sequence: u64) -> SequenceCheck {
+        let mut last_seen = self.last_seen.lock();
+
+        let check = match *last_seen {
+            Some(last) if sequence <= last => SequenceCheck::Replay { last_seen: last },
+            Some(last) if sequence > last + 1 => SequenceCheck::Gap {
+                missing: sequence - last - 1,
+            },
+            _ => SequenceCheck::InOrder,
+        };
+
+        match check {
+            SequenceCheck::Replay { .. } => {
+                self.out_of_order.fetch_add(1, Ordering::Relaxed);
+            }
+            SequenceCheck::Gap { missing } => {
+                self.missing.fetch_add(missing, Ordering::Relaxed);
+                *last_seen = Some(sequence);
+            }
+            SequenceCheck::InOrder => *last_seen = Some(sequence),
+        }
+
+        check
+    }
+
+    /// Number of sequence numbers skipped so far
+    pub fn missing(&self) -> u64 {
+        self.missing.load(Ordering::Relaxed)
+    }
+
+    /// Number of out-of-order or replayed messages so far
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_is_detected() {
+        let tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.check(1), SequenceCheck::InOrder);
+        assert_eq!(tracker.check(2), SequenceCheck::InOrder);
+        assert_eq!(tracker.check(4), SequenceCheck::Gap { missing: 1 });
+        assert_eq!(tracker.check(5), SequenceCheck::InOrder);
+        assert_eq!(tracker.missing(), 1);
+        assert_eq!(tracker.out_of_order(), 0);
+    }
+
+    #[test]
+    fn test_replay_is_flagged() {
+        let tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.check(1), SequenceCheck::InOrder);
+        assert_eq!(tracker.check(2), SequenceCheck::InOrder);
+        assert_eq!(tracker.check(1), SequenceCheck::Replay { last_seen: 2 });
+        assert_eq!(tracker.check(3), SequenceCheck::InOrder);
+        assert_eq!(tracker.out_of_order(), 1);
+        assert_eq!(tracker.missing(), 0);
+    }
+}
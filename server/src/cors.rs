@@ -0,0 +1,167 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use regex::Regex;
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Origins allowed to make cross-origin requests
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    /// Allow every origin (development only)
+    pub allow_any_origin: bool,
+
+    /// Exact origins, e.g. `https://venue.example.com`
+    pub origins: Vec<String>,
+
+    /// Patterns matched against the whole origin, compiled with
+    /// `origin_pattern`
+    pub origin_patterns: Vec<Regex>,
+}
+
+impl CorsConfig {
+    /// Compile an origin pattern to match whole origins only
+    ///
+    /// Unanchored, `https://.*\.example\.com` would also match
+    /// `https://evil.example.com.attacker.net`.
+    pub fn origin_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern))
+    }
+
+    /// Whether a cross-origin request from `origin` is allowed
+    pub fn allows(&self, origin: &str) -> bool {
+        self.allow_any_origin
+            || self.origins.iter().any(|allowed| allowed == origin)
+            || self.origin_patterns.iter().any(|pattern| pattern.is_match(origin))
+    }
+
+    /// CORS response headers for allowed origins
+    pub fn layer(self: &Arc<Self>) -> CorsLayer {
+        if self.allow_any_origin {
+            return CorsLayer::permissive();
+        }
+
+        let config = self.clone();
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::predicate(move |origin, _| {
+                origin.to_str().is_ok_and(|origin| config.allows(origin))
+            }))
+            .allow_methods([Method::GET, Method::POST, Method::DELETE])
+            .allow_headers(Any)
+    }
+}
+
+/// Reject requests whose `Origin` is neither allowed nor the server itself
+///
+/// Browsers only enforce CORS on responses, so without this a disallowed
+/// page could still trigger control actions with simple requests.
+pub async fn reject_disallowed_origins(
+    State(config): State<Arc<CorsConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let Some(origin) = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok()) else {
+        return next.run(request).await;
+    };
+
+    if config.allows(origin) || is_same_origin(origin, headers) {
+        return next.run(request).await;
+    }
+
+    tracing::warn!("Rejected {} {} from origin {}", request.method(), request.uri(), origin);
+    (StatusCode::FORBIDDEN, "Origin not allowed").into_response()
+}
+
+/// Whether `origin` names the host the request was sent to
+fn is_same_origin(origin: &str, headers: &HeaderMap) -> bool {
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+    let origin_host = origin.split_once("://").map(|(_, host)| host);
+    host.is_some() && host == origin_host
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    fn app(config: CorsConfig) -> Router {
+        let config = Arc::new(config);
+        Router::new()
+            .route("/api/play", post(|| async { "OK" }))
+            .layer(config.layer())
+            .layer(middleware::from_fn_with_state(config, reject_disallowed_origins))
+    }
+
+    fn preflight(origin: &str) -> Request {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/play")
+            .header(header::HOST, "localhost:8080")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn post_from(origin: &str) -> Request {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/api/play")
+            .header(header::HOST, "localhost:8080")
+            .header(header::ORIGIN, origin)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_allowlisted_origins_pass() {
+        let config = CorsConfig {
+            allow_any_origin: false,
+            origins: vec!["https://venue.example.com".into()],
+            origin_patterns: vec![CorsConfig::origin_pattern(r"https://[a-z]+\.solunar\.dev").unwrap()],
+        };
+
+        for origin in ["https://venue.example.com", "https://stage.solunar.dev"] {
+            let response = app(config.clone()).oneshot(preflight(origin)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+
+            let response = app(config.clone()).oneshot(post_from(origin)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let evil = "https://evil.example.org";
+        let response = app(config.clone()).oneshot(preflight(evil)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        let response = app(config.clone()).oneshot(post_from(evil)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Patterns match the whole origin, not a part of it
+        for extended in ["https://stage.solunar.dev.attacker.net", "http://x.io/https://stage.solunar.dev"] {
+            let response = app(config.clone()).oneshot(post_from(extended)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", extended);
+        }
+
+        // Pages served by the server itself are never cross-origin
+        let response = app(config).oneshot(post_from("http://localhost:8080")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_allow_any_origin_is_permissive() {
+        let config = CorsConfig {
+            allow_any_origin: true,
+            ..Default::default()
+        };
+
+        let response = app(config).oneshot(preflight("https://anything.test")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
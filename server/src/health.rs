@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+/// Health of a server subsystem, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    /// Working normally
+    Ok,
+
+    /// Working, but with reduced quality
+    Degraded,
+
+    /// Not working
+    Down,
+}
+
+impl HealthState {
+    /// Worst of several states, or `Ok` if there are none
+    pub fn worst(states: impl IntoIterator<Item = Self>) -> Self {
+        states.into_iter().max().unwrap_or(Self::Ok)
+    }
+}
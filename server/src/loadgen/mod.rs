@@ -0,0 +1,459 @@
+use anyhow::{bail, Context, Result};
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, time::Instant};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::protocol::{
+    ClockSyncMessage, HeartbeatMessage, HelloMessage, MediaAction, MediaControlMessage,
+    MediaParams, Message as ProtoMessage, MessageHeader, NodeType,
+};
+
+/// Simulated clients to run against a server
+///
+/// Every client says hello, subscribes to `subscribe`, then sends clock
+/// syncs at `sync_rate` and heartbeats every `heartbeat_interval` until
+/// `duration` has passed.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub url: String,
+    pub clients: usize,
+
+    /// Clock syncs per second sent by each client
+    pub sync_rate: f64,
+    pub heartbeat_interval: Duration,
+    pub duration: Duration,
+
+    /// Tracks each client subscribes to after its hello
+    pub subscribe: Vec<String>,
+    pub auth_token: Option<String>,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            url: "ws://127.0.0.1:8080/ws".to_string(),
+            clients: 100,
+            sync_rate: 1.0,
+            heartbeat_interval: Duration::from_secs(5),
+            duration: Duration::from_secs(30),
+            subscribe: Vec::new(),
+            auth_token: None,
+        }
+    }
+}
+
+impl LoadConfig {
+    /// Parse the arguments following `loadgen` on the command line
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().with_context(|| format!("Missing value for {}", flag));
+            match flag.as_str() {
+                "--url" => config.url = value()?,
+                "--clients" => config.clients = value()?.parse().context("Invalid --clients")?,
+                "--sync-rate" => config.sync_rate = value()?.parse().context("Invalid --sync-rate")?,
+                "--heartbeat-ms" => {
+                    config.heartbeat_interval =
+                        Duration::from_millis(value()?.parse().context("Invalid --heartbeat-ms")?)
+                }
+                "--duration" => {
+                    config.duration = Duration::from_secs_f64(value()?.parse().context("Invalid --duration")?)
+                }
+                "--subscribe" => config.subscribe.push(value()?),
+                "--token" => config.auth_token = Some(value()?),
+                other => bail!("Unknown loadgen option: {}", other),
+            }
+        }
+
+        if config.clients == 0 {
+            bail!("--clients must be at least 1");
+        }
+        if !(config.sync_rate.is_finite() && config.sync_rate > 0.0) {
+            bail!("--sync-rate must be positive");
+        }
+        if config.heartbeat_interval.is_zero() {
+            bail!("--heartbeat-ms must be positive");
+        }
+        Ok(config)
+    }
+}
+
+/// Percentiles of the clock sync round trips, in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    fn from_samples(mut samples: Vec<f64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+        let rank = |p: f64| samples[((p * samples.len() as f64).ceil() as usize).clamp(1, samples.len()) - 1];
+        Some(Self {
+            samples: samples.len(),
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+/// Totals over all simulated clients
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub clients: usize,
+    pub connected: usize,
+    pub failed: usize,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub media_frames: u64,
+    pub bytes_received: u64,
+
+    /// Error messages the server sent
+    pub errors: u64,
+    pub elapsed: Duration,
+    pub sync_rtt: Option<LatencySummary>,
+}
+
+impl LoadReport {
+    pub fn sent_per_sec(&self) -> f64 {
+        self.messages_sent as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn received_per_sec(&self) -> f64 {
+        self.messages_received as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Clients:   {} connected, {} failed of {}",
+            self.connected, self.failed, self.clients
+        )?;
+        writeln!(f, "Duration:  {:.1}s", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "Sent:      {} messages ({:.1}/s)",
+            self.messages_sent,
+            self.sent_per_sec()
+        )?;
+        writeln!(
+            f,
+            "Received:  {} messages ({:.1}/s), {} media frames, {} bytes",
+            self.messages_received,
+            self.received_per_sec(),
+            self.media_frames,
+            self.bytes_received
+        )?;
+        writeln!(f, "Errors:    {}", self.errors)?;
+        match &self.sync_rtt {
+            Some(rtt) => write!(
+                f,
+                "Sync RTT:  p50 {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, max {:.2}ms ({} samples)",
+                rtt.p50, rtt.p95, rtt.p99, rtt.max, rtt.samples
+            ),
+            None => write!(f, "Sync RTT:  no responses"),
+        }
+    }
+}
+
+/// What one simulated client saw
+#[derive(Debug, Default)]
+struct ClientStats {
+    connected: bool,
+    sent: u64,
+    received: u64,
+    media_frames: u64,
+    bytes_received: u64,
+    errors: u64,
+    sync_rtts: Vec<f64>,
+}
+
+/// Run the simulated clients until the configured duration has passed
+pub async fn run(config: LoadConfig) -> Result<LoadReport> {
+    let config = Arc::new(config);
+    let started = Instant::now();
+    let deadline = started + config.duration;
+
+    let clients: Vec<_> = (0..config.clients)
+        .map(|_| tokio::spawn(run_client(config.clone(), deadline)))
+        .collect();
+
+    let mut report = LoadReport {
+        clients: config.clients,
+        connected: 0,
+        failed: 0,
+        messages_sent: 0,
+        messages_received: 0,
+        media_frames: 0,
+        bytes_received: 0,
+        errors: 0,
+        elapsed: Duration::ZERO,
+        sync_rtt: None,
+    };
+    let mut sync_rtts = Vec::new();
+    for client in clients {
+        let stats = client.await.context("Simulated client panicked")?;
+        if stats.connected {
+            report.connected += 1;
+        } else {
+            report.failed += 1;
+        }
+        report.messages_sent += stats.sent;
+        report.messages_received += stats.received;
+        report.media_frames += stats.media_frames;
+        report.bytes_received += stats.bytes_received;
+        report.errors += stats.errors;
+        sync_rtts.extend(stats.sync_rtts);
+    }
+    report.elapsed = started.elapsed();
+    report.sync_rtt = LatencySummary::from_samples(sync_rtts);
+    Ok(report)
+}
+
+/// Sending half of a simulated client's connection
+struct ClientSender {
+    sink: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>,
+    node_id: Uuid,
+    sequence: u64,
+}
+
+impl ClientSender {
+    async fn send(&mut self, message: impl FnOnce(MessageHeader) -> ProtoMessage) -> Result<()> {
+        self.sequence += 1;
+        let message = message(MessageHeader::new(self.node_id, self.sequence));
+        self.sink
+            .send(WsMessage::Text(serde_json::to_string(&message)?))
+            .await?;
+        Ok(())
+    }
+}
+
+async fn run_client(config: Arc<LoadConfig>, deadline: Instant) -> ClientStats {
+    let mut stats = ClientStats::default();
+    let socket = match tokio_tungstenite::connect_async(config.url.as_str()).await {
+        Ok((socket, _)) => socket,
+        Err(e) => {
+            debug!("Simulated client could not connect to {}: {}", config.url, e);
+            return stats;
+        }
+    };
+    stats.connected = true;
+
+    let (sink, mut stream) = socket.split();
+    let mut sender = ClientSender {
+        sink,
+        node_id: Uuid::new_v4(),
+        sequence: 0,
+    };
+    if let Err(e) = simulate(&config, deadline, &mut sender, &mut stream, &mut stats).await {
+        debug!("Simulated client {} stopped: {}", sender.node_id, e);
+    }
+    stats.sent = sender.sequence;
+    sender.sink.close().await.ok();
+    stats
+}
+
+async fn simulate(
+    config: &LoadConfig,
+    deadline: Instant,
+    sender: &mut ClientSender,
+    stream: &mut futures::stream::SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    stats: &mut ClientStats,
+) -> Result<()> {
+    sender
+        .send(|header| {
+            ProtoMessage::Hello(HelloMessage {
+                header,
+                protocol_version: "0.1".to_string(),
+                capabilities: vec!["clock_sync".to_string(), "media_streaming".to_string()],
+                node_type: NodeType::Client,
+                auth_token: config.auth_token.clone(),
+                zone: None,
+                session_token: None,
+                min_latency_ms: None,
+                max_acceptable_latency_ms: None,
+                ingest_token: None,
+            })
+        })
+        .await?;
+    for track_id in &config.subscribe {
+        sender
+            .send(|header| {
+                ProtoMessage::MediaControl(MediaControlMessage {
+                    header,
+                    action: MediaAction::Subscribe,
+                    track_id: track_id.clone(),
+                    start_at: 0.0,
+                    params: MediaParams::default(),
+                    zone: None,
+                })
+            })
+            .await?;
+    }
+
+    // Clock sync timestamps are only compared with this client's own, so
+    // a monotonic clock keeps the round trips exact
+    let epoch = Instant::now();
+    let mut sync = tokio::time::interval(Duration::from_secs_f64(1.0 / config.sync_rate));
+    let mut heartbeat = tokio::time::interval(config.heartbeat_interval);
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return Ok(()),
+            _ = sync.tick() => {
+                let t1 = epoch.elapsed().as_secs_f64();
+                sender.send(|header| ProtoMessage::ClockSync(ClockSyncMessage { header, t1 })).await?;
+            }
+            _ = heartbeat.tick() => {
+                let client_time = crate::protocol::get_current_time();
+                sender
+                    .send(|header| {
+                        ProtoMessage::Heartbeat(HeartbeatMessage {
+                            header,
+                            client_time,
+                            server_time: None,
+                        })
+                    })
+                    .await?;
+            }
+            message = stream.next() => {
+                let text = match message {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | None => bail!("Server closed the connection"),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                stats.received += 1;
+                stats.bytes_received += text.len() as u64;
+                match serde_json::from_str(&text) {
+                    Ok(ProtoMessage::ClockSyncResponse(response)) => {
+                        let rtt = epoch.elapsed().as_secs_f64() - response.t1;
+                        stats.sync_rtts.push(rtt * 1000.0);
+                    }
+                    Ok(ProtoMessage::MediaData(_)) => stats.media_frames += 1,
+                    Ok(ProtoMessage::Error(error)) => {
+                        debug!("Server error for simulated client: {}", error.message);
+                        stats.errors += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ClockManager, config::ServerConfig, control::ControlServer, media::MediaServer, AppState,
+    };
+    use axum::{routing::get, Router};
+    use std::net::SocketAddr;
+
+    async fn start_server() -> SocketAddr {
+        let config = Arc::new(ServerConfig::default());
+        let clock_manager = Arc::new(ClockManager::with_config(&config));
+        let media_server = Arc::new(MediaServer::with_config(clock_manager.clone(), config.clone()));
+        let control_server = Arc::new(ControlServer::new(
+            clock_manager.clone(),
+            media_server.clone(),
+            config.clone(),
+        ));
+        let state = AppState {
+            config,
+            clock_manager,
+            media_server,
+            control_server,
+        };
+        let app = Router::new()
+            .route("/ws", get(crate::websocket_handler))
+            .with_state(state);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_simulated_clients_against_in_process_server() {
+        let addr = start_server().await;
+        let config = LoadConfig::from_args(
+            [
+                "--url",
+                &format!("ws://{}/ws", addr),
+                "--clients",
+                "4",
+                "--sync-rate",
+                "20",
+                "--heartbeat-ms",
+                "100",
+                "--duration",
+                "0.5",
+            ]
+            .map(String::from),
+        )
+        .unwrap();
+
+        let report = run(config.clone()).await.unwrap();
+        assert_eq!((report.connected, report.failed), (4, 0));
+        assert_eq!(report.errors, 0, "{}", report);
+
+        // Each client syncs about 10 times and heartbeats about 5 times
+        let rtt = report.sync_rtt.clone().unwrap();
+        assert!(rtt.samples >= 4 * 5, "{}", report);
+        assert!(rtt.p50 <= rtt.p95 && rtt.p95 <= rtt.p99 && rtt.p99 <= rtt.max);
+        assert!(report.messages_sent >= 4 * (1 + 5 + 3), "{}", report);
+        assert!(report.messages_received as usize >= rtt.samples);
+
+        // Subscribing to a track the server does not have is reported
+        let report = run(LoadConfig {
+            clients: 2,
+            subscribe: vec!["no_such_track".into()],
+            ..config.clone()
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.errors, 2, "{}", report);
+
+        // Clients that cannot connect are counted as failed
+        let report = run(LoadConfig {
+            url: "ws://127.0.0.1:1/ws".into(),
+            clients: 3,
+            ..config
+        })
+        .await
+        .unwrap();
+        assert_eq!((report.connected, report.failed), (0, 3));
+        assert!(report.sync_rtt.is_none());
+    }
+
+    #[test]
+    fn test_load_config_rejects_invalid_options() {
+        let parse = |args: &[&str]| LoadConfig::from_args(args.iter().map(|a| a.to_string()));
+
+        assert_eq!(parse(&[]).unwrap().clients, 100);
+        assert_eq!(parse(&["--subscribe", "a", "--subscribe", "b"]).unwrap().subscribe, ["a", "b"]);
+        assert!(parse(&["--clients", "0"]).is_err());
+        assert!(parse(&["--sync-rate", "0"]).is_err());
+        assert!(parse(&["--clients"]).is_err());
+        assert!(parse(&["--bogus", "1"]).is_err());
+    }
+}
@@ -1,32 +1,40 @@
 use anyhow::Result;
 use axum::{
-    extract::{ws::WebSocketUpgrade, State, ConnectInfo},
+    extract::{ws::WebSocketUpgrade, DefaultBodyLimit, State, ConnectInfo},
+    middleware,
     response::Response,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use std::{net::SocketAddr, sync::Arc};
-use tower_http::{
-    cors::CorsLayer,
-    services::ServeDir,
-    trace::TraceLayer,
-};
+use tower_http::trace::TraceLayer;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod auth;
 mod clock;
+mod config;
 mod control;
+mod cors;
+mod health;
+mod loadgen;
 mod media;
 mod protocol;
+mod request_id;
+mod spa;
+mod tls;
 
 use crate::{
     clock::ClockManager,
+    config::ServerConfig,
     control::ControlServer,
     media::MediaServer,
+    spa::StaticFiles,
 };
 
 #[derive(Clone)]
 pub struct AppState {
+    config: Arc<ServerConfig>,
     clock_manager: Arc<ClockManager>,
     media_server: Arc<MediaServer>,
     control_server: Arc<ControlServer>,
@@ -43,17 +51,54 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // `solusync-x-server loadgen [options]` load-tests a running server
+    // instead of starting one
+    if std::env::args().nth(1).as_deref() == Some("loadgen") {
+        let config = loadgen::LoadConfig::from_args(std::env::args().skip(2))?;
+        info!(
+            "Running {} simulated clients against {} for {:?}",
+            config.clients, config.url, config.duration
+        );
+        println!("{}", loadgen::run(config).await?);
+        return Ok(());
+    }
+
     info!("Starting SOLUSync-X Server v0.1.0");
+    // Server time runs on from the wall clock as it reads at startup
+    protocol::time::init();
 
+    let config = Arc::new(ServerConfig::from_env()?);
+    
+    // `--regenerate-cert` replaces the saved DTLS certificate before
+    // starting, for when it has leaked or should be rotated
+    if std::env::args().skip(1).any(|arg| arg == "--regenerate-cert") {
+        match &config.dtls_certificate {
+            Some(path) => {
+                media::CertificateFile::new(path).regenerate()?;
+            }
+            None => tracing::warn!("--regenerate-cert given but no DTLS certificate file is configured"),
+        }
+    }
+    
     // Initialize components
-    let clock_manager = Arc::new(ClockManager::new());
-    let media_server = Arc::new(MediaServer::new());
+    let clock_manager = Arc::new(ClockManager::with_config(&config));
+    let media_server = Arc::new(MediaServer::try_with_config(clock_manager.clone(), config.clone())?);
+    info!("WebRTC peer connections use {}", config.webrtc_udp);
+    if let Some(fingerprint) = media_server.dtls_fingerprint() {
+        info!("DTLS certificate fingerprint {}", fingerprint);
+    }
+    media_server.set_compressor(protocol::Compression::Zstd, Arc::new(media::ZstdCompressor));
+    #[cfg(feature = "opus")]
+    media_server.set_encoder_factory(media::opus_encoder_factory());
+    media_server.load_state().await;
     let control_server = Arc::new(ControlServer::new(
         clock_manager.clone(),
         media_server.clone(),
+        config.clone(),
     ));
 
     let app_state = AppState {
+        config: config.clone(),
         clock_manager: clock_manager.clone(),
         media_server: media_server.clone(),
         control_server: control_server.clone(),
@@ -62,31 +107,125 @@ async fn main() -> Result<()> {
     // Start background tasks
     tokio::spawn(clock_manager.run());
     tokio::spawn(media_server.run());
+    tokio::spawn(control_server.clone().run());
+
+    // Serve the web client, falling back to its index for client routes
+    let static_files = StaticFiles::new(&config.static_dir);
+    
+    if config.cors.allow_any_origin {
+        tracing::warn!("CORS allows any origin; set SOLUSYNC_CORS_ORIGINS for production");
+    }
+    let cors = Arc::new(config.cors.clone());
 
-    // Serve static files from public directory
-    let serve_dir = ServeDir::new("public");
+    match &config.node_signing_key {
+        Some(key) => info!(
+            "Signing cluster messages with node key {}",
+            hex::encode(key.verifying_key().to_bytes())
+        ),
+        None if config.require_signed_cluster_messages => {
+            tracing::warn!("No SOLUSYNC_NODE_KEY set; peers requiring signed cluster messages drop this server's elections")
+        }
+        None => {}
+    }
 
-    // Build HTTP/WebSocket server
-    let app = Router::new()
-        .nest_service("/", serve_dir.clone())
-        .route("/health", get(health_check))
-        .route("/ws", get(websocket_handler))
+    if config.admin_token.is_none() {
+        tracing::warn!("Control API is open to anyone; set SOLUSYNC_ADMIN_TOKEN for production");
+    }
+    let admin_token = config.admin_token.clone().map(Arc::new);
+
+    // Control routes, behind the admin token when one is set
+    let control_api = Router::new()
         .route("/api/play", post(control::handlers::play))
         .route("/api/pause", post(control::handlers::pause))
         .route("/api/sync", post(control::handlers::sync))
         .route("/api/status", get(control::handlers::status))
+        .route("/api/status/detailed", get(control::handlers::detailed_status))
         .route("/api/clients", get(control::handlers::connected_clients))
-        .layer(CorsLayer::permissive())
+        .route(
+            "/api/clients/:id/subscriptions",
+            post(control::handlers::update_subscriptions),
+        )
+        .route("/api/clients/:id/buffer", get(control::handlers::client_buffer))
+        .route(
+            "/api/clients/:id/buffer/reset",
+            post(control::handlers::reset_client_buffer),
+        )
+        .route(
+            "/api/clients/:id/buffer/config",
+            post(control::handlers::configure_client_buffer),
+        )
+        .route("/api/clients/:id/tuning", delete(control::handlers::clear_device_tuning))
+        .route("/api/zones", post(control::handlers::update_zone))
+        .route("/api/tokens", post(control::handlers::mint_token))
+        .route("/api/streams", get(control::handlers::streams))
+        .route("/api/streams/:id", delete(control::handlers::delete_stream))
+        .route("/api/streams/:id/record", post(control::handlers::record_stream))
+        .route("/api/streams/:id/capture", post(control::handlers::capture_stream))
+        .route("/api/streams/:id/replay", post(control::handlers::replay_capture))
+        .route("/api/media/stats", get(control::handlers::media_stats))
+        .route("/api/webrtc/stats", get(control::handlers::webrtc_stats))
+        .route("/api/test/tone", post(control::handlers::play_test_tone))
+        .route(
+            "/api/programs",
+            get(control::handlers::programs).post(control::handlers::create_program),
+        )
+        .route("/api/programs/:id", delete(control::handlers::delete_program))
+        .route(
+            "/api/video-tiers",
+            get(control::handlers::video_tiers).post(control::handlers::create_tiered_video),
+        )
+        .route("/api/video-tiers/:id", delete(control::handlers::delete_tiered_video))
+        .route(
+            "/api/sync-groups",
+            get(control::handlers::sync_groups).post(control::handlers::create_sync_group),
+        )
+        .route("/api/sync-groups/:name", delete(control::handlers::delete_sync_group))
+        .route(
+            "/api/tracks",
+            get(control::handlers::list_tracks)
+                // Uploads are size-limited while streaming to disk
+                .post(control::handlers::upload_track)
+                .layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/tracks/:id", delete(control::handlers::delete_track))
+        .route(
+            "/api/queue",
+            get(control::handlers::get_queue).post(control::handlers::enqueue),
+        )
+        .route("/api/queue/next", post(control::handlers::skip_queue))
+        .route("/api/queue/:index", delete(control::handlers::remove_queue_item))
+        .route_layer(middleware::from_fn_with_state(admin_token, auth::require_admin_token));
+
+    // Build HTTP/WebSocket server
+    let app = Router::new()
+        .route("/health", get(health_check))
+        .route("/metrics", get(control::handlers::metrics))
+        .route("/ws", get(websocket_handler))
+        // Clients sync their clocks before they could have a token
+        .route("/api/time", get(control::handlers::time))
+        .merge(control_api)
+        .fallback(move |request| static_files.clone().serve(request))
+        .layer(cors.layer())
+        .layer(middleware::from_fn_with_state(cors, cors::reject_disallowed_origins))
         .layer(TraceLayer::new_for_http())
+        // Outermost, so every log line and response of a request has its ID
+        .layer(middleware::from_fn(request_id::propagate_request_id))
         .with_state(app_state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
     
-    // Display startup information
-    display_startup_info(addr);
-
-    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+    match &config.tls {
+        Some(tls) => {
+            let listener = std::net::TcpListener::bind(addr)?;
+            display_startup_info(addr, true, &config);
+            tls::serve(listener, app, tls).await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            display_startup_info(addr, false, &config);
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+        }
+    }
 
     Ok(())
 }
@@ -101,7 +240,15 @@ async fn websocket_handler(
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> Response {
-    ws.on_upgrade(move |socket| handle_websocket(socket, state, addr))
+    // Hard cap enforced by the WebSocket layer; per-type limits are checked
+    // by the control server
+    let max_size = state
+        .config
+        .max_message_bytes
+        .max(state.config.max_media_message_bytes);
+    ws.max_message_size(max_size)
+        .max_frame_size(max_size)
+        .on_upgrade(move |socket| handle_websocket(socket, state, addr))
 }
 
 async fn handle_websocket(
@@ -114,13 +261,14 @@ async fn handle_websocket(
     }
 }
 
-fn display_startup_info(addr: SocketAddr) {
+fn display_startup_info(addr: SocketAddr, tls: bool, config: &ServerConfig) {
     use qrcode::QrCode;
     use qrcode::render::unicode;
     
+    let (http, ws) = if tls { ("https", "wss") } else { ("http", "ws") };
     let local_ip = local_ip_address::local_ip().unwrap_or(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)));
-    let local_url = format!("http://{}:8080", local_ip);
-    let localhost_url = "http://localhost:8080";
+    let local_url = format!("{}://{}:8080", http, local_ip);
+    let localhost_url = format!("{}://localhost:8080", http);
     
     println!("\n{}", "=".repeat(60));
     println!("🌕 SOLUSync-X Server v0.1.0");
@@ -146,8 +294,9 @@ fn display_startup_info(addr: SocketAddr) {
         }
     }
     
-    println!("\n🔌 WebSocket: ws://{}:8080/ws", local_ip);
-    println!("❤️  Health:   http://{}:8080/health", local_ip);
+    println!("\n🔌 WebSocket: {}://{}:8080/ws", ws, local_ip);
+    println!("❤️  Health:   {}://{}:8080/health", http, local_ip);
+    println!("🧱 WebRTC:    {}", config.webrtc_udp);
     
     println!("\n📊 Features:");
     println!("   • Ultra-low latency sync (±0.5ms)");
@@ -1,11 +1,17 @@
-use std::time::{Duration, Instant};
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use crate::protocol::NetworkQuality;
+use super::rendition::{QualityTier, Rendition};
 
 /// Media frame with timing information
 #[derive(Debug, Clone)]
 pub struct MediaFrame {
-    /// Frame data
-    pub data: Vec<u8>,
+    /// Frame data, shared by every subscriber the frame is forwarded to
+    pub data: Bytes,
     
     /// Presentation timestamp (network clock)
     pub timestamp: f64,
@@ -18,6 +24,24 @@ pub struct MediaFrame {
     
     /// Sequence number
     pub sequence: u64,
+    
+    /// Lower-bitrate encodings of `data`, if any
+    pub renditions: Arc<[Rendition]>,
+}
+
+impl MediaFrame {
+    /// Frame data encoded for a tier, falling back to the source data
+    pub fn data_for(&self, tier: QualityTier) -> &Bytes {
+        self.rendition(tier).unwrap_or(&self.data)
+    }
+    
+    /// Frame data re-encoded for a tier, if it was
+    pub fn rendition(&self, tier: QualityTier) -> Option<&Bytes> {
+        self.renditions
+            .iter()
+            .find(|rendition| rendition.tier == tier)
+            .map(|rendition| &rendition.data)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,57 +51,425 @@ pub enum FrameType {
     VideoKeyframe,
 }
 
+/// Decoder-side concealment requested for audio frames that never arrived
+///
+/// Opus decoders synthesize lost frames from the preceding audio (packet
+/// loss concealment) when told a frame is missing, instead of leaving an
+/// audible gap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcealmentRequest {
+    pub track_id: String,
+    
+    /// Sequence number of the first missing frame
+    pub first_sequence: u64,
+    
+    /// Consecutive frames missing
+    pub missing: u64,
+    
+    /// Presentation time of the first missing frame (network clock)
+    pub timestamp: f64,
+    
+    /// Length of audio to conceal
+    pub duration: Duration,
+}
+
+/// Frame waiting in a future buffer, with the stream it belongs to
+#[derive(Debug, Clone)]
+pub struct QueuedFrame {
+    pub track_id: String,
+    pub frame: MediaFrame,
+    
+    /// Released after its presentation time, within the late slack
+    pub late: bool,
+}
+
+/// Most frames a future buffer holds before dropping the oldest
+const MAX_QUEUED_FRAMES: usize = 512;
+
+/// Window in which underruns and overruns count towards reclassification
+const RECLASSIFY_WINDOW: Duration = Duration::from_secs(10);
+
+/// Underruns within the window that downgrade the perceived quality
+const UNDERRUN_BURST: usize = 3;
+
+/// Overruns within the window, without an underrun, that upgrade it
+const SUSTAINED_OVERRUNS: usize = 10;
+
+/// Most underruns or overruns taken from a single client report
+const MAX_REPORTED_EVENTS: u32 = 100;
+
+/// Smallest and largest playback rate change hinted to a client
+const MIN_RATE_OFFSET: f64 = 0.005;
+const MAX_RATE_OFFSET: f64 = 0.02;
+
+/// Time a rate hint is sized to correct the occupancy deviation in
+const RATE_CORRECTION_SECS: f64 = 10.0;
+
+/// How the jitter allowance of a future buffer is sized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JitterMode {
+    /// Fixed depth for each network quality
+    Fixed,
+    
+    /// Measured interarrival jitter of the frames, plus a safety margin,
+    /// once enough frames have arrived to measure it
+    #[default]
+    Adaptive,
+}
+
+/// Latency bounds requested for one client, each replacing the server's
+/// bound when set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LatencyBounds {
+    #[serde(default)]
+    pub min_latency_ms: Option<u64>,
+    #[serde(default)]
+    pub max_latency_ms: Option<u64>,
+}
+
+impl LatencyBounds {
+    /// These bounds, with those not set taken from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            min_latency_ms: self.min_latency_ms.or(fallback.min_latency_ms),
+            max_latency_ms: self.max_latency_ms.or(fallback.max_latency_ms),
+        }
+    }
+    
+    /// Minimum and maximum latency, with those not set taken from the
+    /// server's bounds
+    ///
+    /// A minimum over the maximum is an error.
+    pub fn resolve(self, server_min: Duration, server_max: Duration) -> anyhow::Result<(Duration, Duration)> {
+        let min_latency = self.min_latency_ms.map_or(server_min, Duration::from_millis);
+        let max_latency = self.max_latency_ms.map_or(server_max, Duration::from_millis);
+        if min_latency > max_latency {
+            anyhow::bail!(
+                "Minimum latency {}ms exceeds maximum latency {}ms",
+                min_latency.as_millis(),
+                max_latency.as_millis()
+            );
+        }
+        Ok((min_latency, max_latency))
+    }
+}
+
+/// Where a client's latency bounds were requested
+///
+/// Bounds set through the API take precedence over those from the
+/// client's hello, which take precedence over the server's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundsSource {
+    Hello,
+    Api,
+}
+
+/// Interarrival jitter of one track, estimated as in RFC 3550
+#[derive(Debug, Default)]
+struct JitterEstimator {
+    /// Presentation and arrival time of the previous frame
+    last: Option<(f64, f64)>,
+    
+    /// Smoothed mean deviation of the transit time, in seconds
+    jitter: f64,
+    samples: u64,
+}
+
+impl JitterEstimator {
+    fn record(&mut self, timestamp: f64, arrival: f64) {
+        if let Some((last_timestamp, last_arrival)) = self.last {
+            let deviation = (arrival - last_arrival) - (timestamp - last_timestamp);
+            self.jitter += (deviation.abs() - self.jitter) / 16.0;
+            self.samples += 1;
+        }
+        self.last = Some((timestamp, arrival));
+    }
+}
+
+/// Converged state of a client's buffer, carried over to the next
+/// connection of the same device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferTuning {
+    pub target_latency: Duration,
+    pub jitter: Option<Duration>,
+    
+    /// Playout delay of the client's audio output, from its last report
+    pub playout_delay: Option<Duration>,
+}
+
+/// Jitter deviations covered by an adaptive jitter allowance
+const ADAPTIVE_JITTER_DEVIATIONS: f64 = 3.0;
+
+/// Interarrival samples a track needs before its jitter sizes the buffer,
+/// one time constant of the RFC 3550 smoothing
+const MIN_JITTER_SAMPLES: u64 = 16;
+
+/// Latency bounds, per-quality targets, adjustment hysteresis,
+/// late-frame slack and jitter sizing for future buffers
+///
+/// The defaults suit general use; a low-latency LAN deployment can lower
+/// `max_latency`, while lossy mobile networks may need it raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferPolicy {
+    /// Minimum latency (best case)
+    pub min_latency: Duration,
+    
+    /// Maximum latency (worst case)
+    pub max_latency: Duration,
+    
+    /// Target latency for each network quality, before clamping to the bounds
+    pub excellent: Duration,
+    pub good: Duration,
+    pub fair: Duration,
+    pub poor: Duration,
+    pub critical: Duration,
+    
+    /// How far past its presentation time a frame is still sent; later
+    /// frames are dropped
+    pub late_slack: Duration,
+    
+    /// Underruns within `growth_window` needed before latency grows
+    pub underruns_to_grow: usize,
+    pub growth_window: Duration,
+    
+    /// Time without underruns before overruns may shrink latency
+    pub shrink_quiet_period: Duration,
+    
+    /// Shortest time between two latency adjustments
+    pub adjustment_cooldown: Duration,
+    
+    /// Largest total change of latency within a minute
+    pub max_adjustment_per_minute: Duration,
+    
+    /// How the jitter allowance is sized, and the margin added to the
+    /// measured jitter in adaptive mode
+    pub jitter_mode: JitterMode,
+    pub jitter_margin: Duration,
+    
+    /// How far the client's occupancy must stay from the target latency,
+    /// and for how long, before a playback rate change is hinted
+    pub rate_deviation: Duration,
+    pub rate_sustain: Duration,
+}
+
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        let recommended = |quality: NetworkQuality| Duration::from_millis(quality.recommended_buffer_ms());
+        Self {
+            min_latency: Duration::from_millis(30),
+            max_latency: Duration::from_millis(500),
+            excellent: recommended(NetworkQuality::Excellent),
+            good: recommended(NetworkQuality::Good),
+            fair: recommended(NetworkQuality::Fair),
+            poor: recommended(NetworkQuality::Poor),
+            critical: recommended(NetworkQuality::Critical),
+            late_slack: Duration::from_millis(5),
+            underruns_to_grow: 2,
+            growth_window: Duration::from_secs(5),
+            shrink_quiet_period: Duration::from_secs(5),
+            adjustment_cooldown: Duration::from_millis(500),
+            max_adjustment_per_minute: Duration::from_millis(200),
+            jitter_mode: JitterMode::Adaptive,
+            jitter_margin: Duration::from_millis(5),
+            rate_deviation: Duration::from_millis(20),
+            rate_sustain: Duration::from_secs(2),
+        }
+    }
+}
+
+impl BufferPolicy {
+    /// Target latency for a network quality, within the bounds
+    pub fn recommended(&self, quality: NetworkQuality) -> Duration {
+        let latency = match quality {
+            NetworkQuality::Excellent => self.excellent,
+            NetworkQuality::Good => self.good,
+            NetworkQuality::Fair => self.fair,
+            NetworkQuality::Poor => self.poor,
+            NetworkQuality::Critical => self.critical,
+        };
+        self.clamp(latency)
+    }
+    
+    /// Limit a latency to the policy bounds
+    pub fn clamp(&self, latency: Duration) -> Duration {
+        latency.max(self.min_latency).min(self.max_latency)
+    }
+}
+
 /// Dynamic future buffer that adjusts based on network conditions
+///
+/// Frames for a client wait here, ordered by presentation time, until they
+/// are one target latency ahead of it. The latency grows after underruns,
+/// when a track's released media runs out before more arrives, and shrinks
+/// after overruns, when the queue is full. Adjustments are damped so that
+/// alternating reports do not make it oscillate: growing takes several
+/// underruns, shrinking waits for a quiet period after the last underrun,
+/// adjustments are spaced by a cooldown and their total per minute is
+/// capped.
 pub struct DynamicFutureBuffer {
     /// Target latency for future playback
     target_latency: Duration,
     
-    /// Minimum latency (best case)
-    min_latency: Duration,
+    /// Latency bounds and per-quality targets
+    policy: BufferPolicy,
     
-    /// Maximum latency (worst case)
-    max_latency: Duration,
+    /// Server-wide latency bounds, before the client's overrides
+    server_bounds: (Duration, Duration),
     
-    /// Current network quality
+    /// Latency bounds requested in the client's hello and through the API
+    hello_bounds: LatencyBounds,
+    api_bounds: LatencyBounds,
+    
+    /// Current network quality, as measured from RTT and loss
     network_quality: NetworkQuality,
     
+    /// Levels the perceived quality sits below the measured one; negative
+    /// when sustained overruns allow it above
+    quality_shift: i8,
+    
+    /// Recent underruns and overruns, oldest first
+    recent_underruns: VecDeque<Instant>,
+    recent_overruns: VecDeque<Instant>,
+    
+    /// Underruns not yet acted on by growing the latency
+    pending_underruns: VecDeque<Instant>,
+    last_underrun: Option<Instant>,
+    
+    /// Latency changes within the last minute and their size
+    recent_adjustments: VecDeque<(Instant, Duration)>,
+    
     /// Latency adjustment rate
     adjustment_rate: f64,
     
-    /// Last adjustment time
-    last_adjustment: Instant,
+    /// Last adjustment time; the cooldown only runs once one was made
+    last_adjustment: Option<Instant>,
+    
+    /// Sequence number expected next from each track's audio
+    next_sequence: HashMap<String, u64>,
+    
+    /// Frames not yet released, in presentation order
+    queue: VecDeque<QueuedFrame>,
+    max_depth: usize,
+    
+    /// Network time up to which each playing track's released frames last
+    playing_until: HashMap<String, f64>,
+    
+    /// First sequence number of each track after its playback last ended;
+    /// earlier frames still in flight do not count as playing
+    ended_before: HashMap<String, u64>,
+    
+    /// Interarrival jitter of each track
+    jitter: HashMap<String, JitterEstimator>,
+    
+    /// Jitter learned on an earlier connection of the client's device,
+    /// used until this connection's has settled
+    prior_jitter: Option<Duration>,
+    
+    /// Video tracks whose delta frames are dropped until the next keyframe,
+    /// after a frame they depend on was dropped
+    awaiting_keyframe: HashSet<String>,
+    
+    /// Output queue and playout delay of the client's last buffer report
+    client_occupancy: Option<Duration>,
+    client_playout_delay: Option<Duration>,
+    
+    /// Playback rate hinted to the client, 1.0 when none, and since when
+    /// its occupancy has been above (`true`) or below the target
+    playback_rate: f64,
+    deviation_since: Option<(Instant, bool)>,
     
     /// Statistics
     underrun_count: u64,
     overrun_count: u64,
+    concealment_count: u64,
+    concealed_frames: u64,
+    late_frames: u64,
+    dropped_late_frames: u64,
 }
 
 impl DynamicFutureBuffer {
+    #[cfg(test)]
     pub fn new(initial_latency: Duration, quality: NetworkQuality) -> Self {
+        Self::with_policy(initial_latency, quality, BufferPolicy::default())
+    }
+    
+    /// Create a buffer bounded by `policy`
+    pub fn with_policy(initial_latency: Duration, quality: NetworkQuality, policy: BufferPolicy) -> Self {
         Self {
-            target_latency: initial_latency,
-            min_latency: Duration::from_millis(30),
-            max_latency: Duration::from_millis(500),
+            target_latency: policy.clamp(initial_latency),
+            server_bounds: (policy.min_latency, policy.max_latency),
+            hello_bounds: LatencyBounds::default(),
+            api_bounds: LatencyBounds::default(),
+            policy,
             network_quality: quality,
+            quality_shift: 0,
+            recent_underruns: VecDeque::new(),
+            recent_overruns: VecDeque::new(),
+            pending_underruns: VecDeque::new(),
+            last_underrun: None,
+            recent_adjustments: VecDeque::new(),
             adjustment_rate: 0.1, // 10% adjustment per update
-            last_adjustment: Instant::now(),
+            last_adjustment: None,
+            next_sequence: HashMap::new(),
+            queue: VecDeque::new(),
+            max_depth: MAX_QUEUED_FRAMES,
+            playing_until: HashMap::new(),
+            ended_before: HashMap::new(),
+            awaiting_keyframe: HashSet::new(),
+            jitter: HashMap::new(),
+            prior_jitter: None,
+            client_occupancy: None,
+            client_playout_delay: None,
+            playback_rate: 1.0,
+            deviation_since: None,
             underrun_count: 0,
             overrun_count: 0,
+            concealment_count: 0,
+            concealed_frames: 0,
+            late_frames: 0,
+            dropped_late_frames: 0,
         }
     }
     
+    /// Replace the latency bounds requested from `source`
+    ///
+    /// The effective bounds take each bound from the API request, else
+    /// the hello, else the server. Bounds whose minimum would exceed their
+    /// maximum are rejected, leaving the previous ones in place. The target
+    /// latency moves into new bounds at once.
+    pub fn set_latency_bounds(&mut self, source: BoundsSource, bounds: LatencyBounds) -> anyhow::Result<()> {
+        let (hello, api) = match source {
+            BoundsSource::Hello => (bounds, self.api_bounds),
+            BoundsSource::Api => (self.hello_bounds, bounds),
+        };
+        let (server_min, server_max) = self.server_bounds;
+        let (min_latency, max_latency) = api.or(hello).resolve(server_min, server_max)?;
+        
+        self.hello_bounds = hello;
+        self.api_bounds = api;
+        self.policy.min_latency = min_latency;
+        self.policy.max_latency = max_latency;
+        self.target_latency = self.policy.clamp(self.target_latency);
+        Ok(())
+    }
+    
     /// Update network quality and adjust buffer
     pub fn update_network_quality(&mut self, quality: NetworkQuality) {
         self.network_quality = quality;
         
         // Only adjust if enough time has passed
-        if self.last_adjustment.elapsed() < Duration::from_millis(500) {
+        if !self.cooled(Instant::now()) {
             return;
         }
         
-        let recommended = Duration::from_millis(quality.recommended_buffer_ms());
+        let recommended = self.policy.recommended(self.effective_quality());
         self.adjust_target_latency(recommended);
-        self.last_adjustment = Instant::now();
+    }
+    
+    /// Whether the cooldown since the last adjustment has passed at `now`
+    fn cooled(&self, now: Instant) -> bool {
+        self.last_adjustment
+            .is_none_or(|at| now.duration_since(at) >= self.policy.adjustment_cooldown)
     }
     
     /// Get current target latency
@@ -85,42 +477,438 @@ impl DynamicFutureBuffer {
         self.target_latency.as_secs_f64()
     }
     
+    /// Quality the buffer is sized for: the measured quality, moved down
+    /// after bursts of underruns and up after sustained overruns
+    pub fn effective_quality(&self) -> NetworkQuality {
+        let mut quality = self.network_quality;
+        for _ in 0..self.quality_shift.max(0) {
+            quality = quality.downgrade();
+        }
+        for _ in self.quality_shift.min(0)..0 {
+            quality = quality.upgrade();
+        }
+        quality
+    }
+    
     /// Report buffer underrun (playback starvation)
     pub fn report_underrun(&mut self) {
         self.underrun_count += 1;
+        let now = Instant::now();
+        self.last_underrun = Some(now);
         
-        // Increase buffer size
-        let new_target = self.target_latency.mul_f64(1.0 + self.adjustment_rate);
-        self.target_latency = new_target.min(self.max_latency);
+        // Increase buffer size once underruns repeat
+        record_event(&mut self.pending_underruns, now, self.policy.growth_window);
+        let cooled = self.cooled(now);
+        if self.pending_underruns.len() >= self.policy.underruns_to_grow && cooled {
+            self.pending_underruns.clear();
+            self.set_target_latency(self.target_latency.mul_f64(1.0 + self.adjustment_rate), now);
+            tracing::warn!(
+                "Buffer underrun! Increasing latency to {}ms",
+                self.target_latency.as_millis()
+            );
+        } else {
+            tracing::debug!("Buffer underrun, latency held at {}ms", self.target_latency.as_millis());
+        }
         
-        tracing::warn!(
-            "Buffer underrun! Increasing latency to {}ms",
-            self.target_latency.as_millis()
-        );
+        // A burst of underruns means the network is worse than it measures
+        record_event(&mut self.recent_underruns, now, RECLASSIFY_WINDOW);
+        self.recent_overruns.clear();
+        let burst = self.recent_underruns.len() >= UNDERRUN_BURST;
+        if burst && self.effective_quality() != NetworkQuality::Critical {
+            self.recent_underruns.clear();
+            self.quality_shift += 1;
+            let recommended = self.policy.recommended(self.effective_quality());
+            self.set_target_latency(self.target_latency.max(recommended), now);
+            tracing::warn!(
+                "Repeated underruns, treating network as {:?}; latency {}ms",
+                self.effective_quality(),
+                self.target_latency.as_millis()
+            );
+        }
     }
     
     /// Report buffer overrun (too much latency)
     pub fn report_overrun(&mut self) {
         self.overrun_count += 1;
+        let now = Instant::now();
+        
+        // Decrease buffer size slowly, and only while underruns have stopped
+        let quiet = self
+            .last_underrun
+            .is_none_or(|at| now.duration_since(at) >= self.policy.shrink_quiet_period);
+        let cooled = self.cooled(now);
+        if quiet && cooled {
+            self.set_target_latency(self.target_latency.mul_f64(1.0 - self.adjustment_rate * 0.5), now);
+            tracing::debug!(
+                "Buffer overrun. Decreasing latency to {}ms",
+                self.target_latency.as_millis()
+            );
+        }
+        
+        // Sustained overruns mean the network is better than it measures
+        record_event(&mut self.recent_overruns, now, RECLASSIFY_WINDOW);
+        let sustained = self.recent_overruns.len() >= SUSTAINED_OVERRUNS;
+        if sustained && self.effective_quality() != NetworkQuality::Excellent {
+            self.recent_overruns.clear();
+            self.quality_shift -= 1;
+            tracing::debug!("Sustained overruns, treating network as {:?}", self.effective_quality());
+        }
+    }
+    
+    /// Check the sequence number of an audio frame of `track_id`
+    ///
+    /// A gap since the previous frame records a concealment event and
+    /// returns the request for the client's decoder to conceal the missing
+    /// frames. A sequence number going backwards restarts tracking, as when
+    /// the stream is recreated.
+    pub fn check_sequence(
+        &mut self,
+        track_id: &str,
+        frame: &MediaFrame,
+    ) -> Option<ConcealmentRequest> {
+        if frame.frame_type != FrameType::Audio {
+            return None;
+        }
+        
+        let expected = self.next_sequence.insert(track_id.to_string(), frame.sequence + 1)?;
+        if frame.sequence <= expected {
+            return None;
+        }
+        
+        // Missing frames are assumed to be as long as the one that arrived
+        let missing = frame.sequence - expected;
+        let duration = frame.duration * missing as u32;
+        self.concealment_count += 1;
+        self.concealed_frames += missing;
+        tracing::debug!(
+            "Missing {} frames of {} before {}, requesting concealment",
+            missing, track_id, frame.sequence
+        );
+        
+        Some(ConcealmentRequest {
+            track_id: track_id.to_string(),
+            first_sequence: expected,
+            missing,
+            timestamp: frame.timestamp - duration.as_secs_f64(),
+            duration,
+        })
+    }
+    
+    /// Record that a frame of `track_id` arrived at network time `arrival`,
+    /// updating the track's jitter estimate
+    pub fn record_arrival(&mut self, track_id: &str, frame: &MediaFrame, arrival: f64) {
+        self.jitter
+            .entry(track_id.to_string())
+            .or_default()
+            .record(frame.timestamp, arrival);
+    }
+    
+    /// Largest interarrival jitter measured on any track, once frames have
+    /// arrived to compare
+    pub fn measured_jitter(&self) -> Option<Duration> {
+        self.jitter
+            .values()
+            .filter(|estimator| estimator.samples > 0)
+            .map(|estimator| estimator.jitter)
+            .reduce(f64::max)
+            .map(Duration::from_secs_f64)
+    }
+    
+    /// Largest jitter of the tracks with enough samples to size the buffer
+    fn settled_jitter(&self) -> Option<Duration> {
+        self.jitter
+            .values()
+            .filter(|estimator| estimator.samples >= MIN_JITTER_SAMPLES)
+            .map(|estimator| estimator.jitter)
+            .reduce(f64::max)
+            .map(Duration::from_secs_f64)
+    }
+    
+    /// What the buffer has learned about its client, to start the next
+    /// connection of the same device from
+    pub fn tuning(&self) -> BufferTuning {
+        BufferTuning {
+            target_latency: self.target_latency,
+            jitter: self.settled_jitter().or(self.prior_jitter),
+            playout_delay: self.client_playout_delay,
+        }
+    }
+    
+    /// Start from what an earlier connection of the client's device learned
+    ///
+    /// The target latency is clamped to the current bounds; the jitter
+    /// sizes the jitter buffer until this connection has measured its own.
+    pub fn seed(&mut self, tuning: &BufferTuning) {
+        self.target_latency = self.policy.clamp(tuning.target_latency);
+        self.prior_jitter = tuning.jitter;
+        self.client_playout_delay = tuning.playout_delay;
+    }
+    
+    /// Queue a frame of `track_id` for release
+    ///
+    /// When the queue is full the oldest frame is dropped, reported as an
+    /// overrun and returned.
+    pub fn push(&mut self, track_id: &str, frame: MediaFrame) -> Option<QueuedFrame> {
+        let index = self.queue.partition_point(|queued| queued.frame.timestamp <= frame.timestamp);
+        self.queue.insert(index, QueuedFrame {
+            track_id: track_id.to_string(),
+            frame,
+            late: false,
+        });
+        
+        if self.queue.len() <= self.max_depth {
+            return None;
+        }
+        let dropped = self.queue.pop_front();
+        self.report_overrun();
+        dropped
+    }
+    
+    /// Release the frames due at network time `now`: those presented
+    /// within one target latency, in presentation order
+    ///
+    /// Frames past their presentation time by up to the late slack are
+    /// released flagged as late; later ones are dropped. Video keyframes
+    /// are never dropped, since the frames after them depend on them, but a
+    /// dropped delta frame drops the rest of its track's delta frames until
+    /// the next keyframe. A playing track whose released media has run out,
+    /// with nothing more queued, is reported as an underrun once.
+    pub fn pop_ready(&mut self, now: f64) -> Vec<QueuedFrame> {
+        let horizon = now + self.release_lead();
+        let ready = self.queue.partition_point(|queued| queued.frame.timestamp <= horizon);
+        let due: Vec<_> = self.queue.drain(..ready).collect();
+        let slack = self.policy.late_slack.as_secs_f64();
+        
+        let mut released = Vec::with_capacity(due.len());
+        for mut queued in due {
+            let QueuedFrame { track_id, frame, .. } = &queued;
+            let lateness = now - frame.timestamp;
+            let dropped = match frame.frame_type {
+                FrameType::VideoKeyframe => {
+                    self.awaiting_keyframe.remove(track_id);
+                    false
+                }
+                FrameType::Video if self.awaiting_keyframe.contains(track_id) => true,
+                FrameType::Video if lateness > slack => {
+                    self.awaiting_keyframe.insert(track_id.clone());
+                    true
+                }
+                _ => lateness > slack,
+            };
+            if dropped {
+                tracing::debug!(
+                    "Dropping frame {} of {}, {:.1}ms late",
+                    frame.sequence,
+                    track_id,
+                    lateness * 1000.0
+                );
+                self.dropped_late_frames += 1;
+                continue;
+            }
+            if lateness > 0.0 {
+                queued.late = true;
+                self.late_frames += 1;
+            }
+            released.push(queued);
+        }
+        
+        for QueuedFrame { track_id, frame, .. } in &released {
+            let end = frame.timestamp + frame.duration.as_secs_f64();
+            if end <= now {
+                continue;
+            }
+            let ended = self
+                .ended_before
+                .get(track_id)
+                .is_some_and(|&next| frame.sequence < next);
+            if !ended {
+                let until = self.playing_until.entry(track_id.clone()).or_insert(end);
+                *until = until.max(end);
+            }
+        }
         
-        // Decrease buffer size slowly
-        let new_target = self.target_latency.mul_f64(1.0 - self.adjustment_rate * 0.5);
-        self.target_latency = new_target.max(self.min_latency);
+        // Media runs out once the jitter allowance has passed too
+        let grace = self.calculate_jitter_buffer().as_secs_f64();
+        let starved: Vec<String> = self
+            .playing_until
+            .iter()
+            .filter(|(track_id, until)| {
+                now > **until + grace && !self.queue.iter().any(|queued| &queued.track_id == *track_id)
+            })
+            .map(|(track_id, _)| track_id.clone())
+            .collect();
+        for track_id in starved {
+            self.playing_until.remove(&track_id);
+            tracing::debug!("Released media of {} ran out", track_id);
+            self.report_underrun();
+        }
         
+        released
+    }
+    
+    /// Network time at which `pop_ready` next has work to do: the next
+    /// frame comes due or a playing track's media runs out
+    pub fn next_due(&self) -> Option<f64> {
+        let release = self
+            .queue
+            .front()
+            .map(|queued| queued.frame.timestamp - self.release_lead());
+        let grace = self.calculate_jitter_buffer().as_secs_f64();
+        let starve = self.playing_until.values().map(|until| until + grace);
+        
+        release.into_iter().chain(starve).reduce(f64::min)
+    }
+    
+    /// Span of presentation time the queued frames cover
+    pub fn occupancy(&self) -> Duration {
+        let Some(first) = self.queue.front() else {
+            return Duration::ZERO;
+        };
+        let end = self
+            .queue
+            .iter()
+            .map(|queued| queued.frame.timestamp + queued.frame.duration.as_secs_f64())
+            .fold(first.frame.timestamp, f64::max);
+        Duration::from_secs_f64(end - first.frame.timestamp)
+    }
+    
+    /// Mark the playback of `track_id` as ended, so the end of its media is
+    /// not taken for an underrun
+    ///
+    /// `next_sequence` is the first sequence number a later playback will
+    /// use; frames before it are still released.
+    pub fn end_playback(&mut self, track_id: &str, next_sequence: u64) {
+        self.playing_until.remove(track_id);
+        self.ended_before.insert(track_id.to_string(), next_sequence);
+        
+        // The next playback's timestamps do not continue from this one's
+        if let Some(estimator) = self.jitter.get_mut(track_id) {
+            estimator.last = None;
+        }
+    }
+    
+    /// Forget a track the client no longer receives, dropping its queued
+    /// frames, so that subscribing again later is not taken for a gap
+    pub fn remove_track(&mut self, track_id: &str) {
+        self.queue.retain(|queued| queued.track_id != track_id);
+        self.next_sequence.remove(track_id);
+        self.playing_until.remove(track_id);
+        self.ended_before.remove(track_id);
+        self.awaiting_keyframe.remove(track_id);
+        self.jitter.remove(track_id);
+    }
+    
+    /// Apply a buffer report from the client's audio output
+    ///
+    /// Underruns and overruns the client counted since its previous report
+    /// are handled as if detected here, up to a bound per report. An output
+    /// queue over twice the target latency without underruns counts as one
+    /// more overrun, since the client holds more than it needs.
+    ///
+    /// Returns the new playback rate hint when the occupancy engaged or
+    /// cleared one.
+    pub fn apply_client_report(
+        &mut self,
+        underruns: u32,
+        overruns: u32,
+        occupancy: Duration,
+        playout_delay: Duration,
+    ) -> Option<f64> {
+        for _ in 0..underruns.min(MAX_REPORTED_EVENTS) {
+            self.report_underrun();
+        }
+        
+        let overfull = underruns == 0 && occupancy > self.target_latency * 2;
+        for _ in 0..overruns.min(MAX_REPORTED_EVENTS) + overfull as u32 {
+            self.report_overrun();
+        }
+        
+        self.client_occupancy = Some(occupancy);
+        self.client_playout_delay = Some(playout_delay);
+        self.observe_occupancy(occupancy, Instant::now())
+    }
+    
+    /// Hint a playback rate change once the client's occupancy has stayed
+    /// away from the target latency
+    ///
+    /// A client holding too much plays slightly faster and one holding too
+    /// little slightly slower, by 0.5-2% sized to correct the deviation in
+    /// about ten seconds, instead of the latency being cut and frames
+    /// dropped. The hint is cleared once the occupancy is back within half
+    /// the deviation threshold. Returns the rate when it changes.
+    fn observe_occupancy(&mut self, occupancy: Duration, now: Instant) -> Option<f64> {
+        let deviation = occupancy.as_secs_f64() - self.target_latency.as_secs_f64();
+        let threshold = self.policy.rate_deviation.as_secs_f64();
+        
+        if self.playback_rate != 1.0 {
+            let recovered = if self.playback_rate > 1.0 {
+                deviation < threshold / 2.0
+            } else {
+                deviation > -threshold / 2.0
+            };
+            if !recovered {
+                return None;
+            }
+            self.playback_rate = 1.0;
+            self.deviation_since = None;
+            tracing::debug!("Occupancy back at target, playback rate hint cleared");
+            return Some(self.playback_rate);
+        }
+        
+        if deviation.abs() <= threshold {
+            self.deviation_since = None;
+            return None;
+        }
+        let over = deviation > 0.0;
+        let since = match self.deviation_since {
+            Some((since, was_over)) if was_over == over => since,
+            _ => {
+                self.deviation_since = Some((now, over));
+                now
+            }
+        };
+        if now.duration_since(since) < self.policy.rate_sustain {
+            return None;
+        }
+        
+        let offset = (deviation.abs() / RATE_CORRECTION_SECS).clamp(MIN_RATE_OFFSET, MAX_RATE_OFFSET);
+        self.playback_rate = if over { 1.0 + offset } else { 1.0 - offset };
         tracing::debug!(
-            "Buffer overrun. Decreasing latency to {}ms",
-            self.target_latency.as_millis()
+            "Occupancy {:.0}ms off target, hinting playback rate {:.3}",
+            deviation * 1000.0,
+            self.playback_rate
         );
+        Some(self.playback_rate)
+    }
+    
+    /// How far ahead of their presentation time frames are released
+    ///
+    /// A client hinted to play faster consumes frames sooner, so they are
+    /// released proportionally earlier, and later for one playing slower.
+    fn release_lead(&self) -> f64 {
+        self.target_latency.as_secs_f64() * self.playback_rate
     }
     
     /// Calculate jitter buffer depth based on statistics
+    ///
+    /// In adaptive mode the depth covers three times the measured jitter
+    /// plus the margin, up to the latency bound; until jitter has been
+    /// measured, and in fixed mode, it depends on the network quality.
     pub fn calculate_jitter_buffer(&self) -> Duration {
-        match self.network_quality {
+        let fixed = match self.network_quality {
             NetworkQuality::Excellent => Duration::from_millis(5),
             NetworkQuality::Good => Duration::from_millis(10),
             NetworkQuality::Fair => Duration::from_millis(20),
             NetworkQuality::Poor => Duration::from_millis(40),
             NetworkQuality::Critical => Duration::from_millis(80),
+        };
+        
+        match (self.policy.jitter_mode, self.settled_jitter().or(self.prior_jitter)) {
+            (JitterMode::Adaptive, Some(jitter)) => {
+                let depth = jitter.mul_f64(ADAPTIVE_JITTER_DEVIATIONS) + self.policy.jitter_margin;
+                depth.min(self.policy.max_latency)
+            }
+            _ => fixed,
         }
     }
     
@@ -128,12 +916,37 @@ impl DynamicFutureBuffer {
     pub fn stats(&self) -> BufferStats {
         BufferStats {
             target_latency_ms: self.target_latency.as_millis() as u32,
+            min_latency_ms: self.policy.min_latency.as_millis() as u32,
+            max_latency_ms: self.policy.max_latency.as_millis() as u32,
             underrun_count: self.underrun_count,
             overrun_count: self.overrun_count,
+            concealment_count: self.concealment_count,
+            concealed_frames: self.concealed_frames,
+            queued_frames: self.queue.len(),
+            occupancy_ms: self.occupancy().as_millis() as u32,
+            late_frames: self.late_frames,
+            dropped_late_frames: self.dropped_late_frames,
+            measured_jitter_ms: self.measured_jitter().map(|jitter| jitter.as_secs_f64() * 1000.0),
+            jitter_buffer_ms: self.calculate_jitter_buffer().as_millis() as u32,
+            client_occupancy_ms: self.client_occupancy.map(|occupancy| occupancy.as_millis() as u32),
+            client_playout_delay_ms: self.client_playout_delay.map(|delay| delay.as_millis() as u32),
+            playback_rate: self.playback_rate,
             network_quality: self.network_quality,
+            effective_quality: self.effective_quality(),
         }
     }
     
+    /// Zero the counters in the statistics, keeping the latency and the
+    /// quality the buffer is sized for
+    pub fn reset_stats(&mut self) {
+        self.underrun_count = 0;
+        self.overrun_count = 0;
+        self.concealment_count = 0;
+        self.concealed_frames = 0;
+        self.late_frames = 0;
+        self.dropped_late_frames = 0;
+    }
+    
     /// Adjust target latency towards recommended value
     fn adjust_target_latency(&mut self, recommended: Duration) {
         let current = self.target_latency.as_secs_f64();
@@ -142,8 +955,7 @@ impl DynamicFutureBuffer {
         // Smooth adjustment using exponential moving average
         let new_latency = current * (1.0 - self.adjustment_rate) + target * self.adjustment_rate;
         
-        self.target_latency = Duration::from_secs_f64(new_latency)
-            .clamp(self.min_latency, self.max_latency);
+        self.set_target_latency(Duration::from_secs_f64(new_latency), Instant::now());
         
         tracing::debug!(
             "Adjusted buffer latency: {}ms -> {}ms (recommended: {}ms)",
@@ -152,14 +964,82 @@ impl DynamicFutureBuffer {
             recommended.as_millis()
         );
     }
+    
+    /// Move the target latency towards `target`, within the policy bounds
+    /// and what remains of the adjustment allowed per minute
+    fn set_target_latency(&mut self, target: Duration, now: Instant) {
+        let minute = Duration::from_secs(60);
+        while self.recent_adjustments.front().is_some_and(|(at, _)| now.duration_since(*at) > minute) {
+            self.recent_adjustments.pop_front();
+        }
+        let used: Duration = self.recent_adjustments.iter().map(|(_, change)| *change).sum();
+        let allowed = self.policy.max_adjustment_per_minute.saturating_sub(used);
+        
+        let current = self.target_latency;
+        let target = self.policy.clamp(target);
+        let new_target = if target > current {
+            target.min(current.saturating_add(allowed))
+        } else {
+            target.max(current.saturating_sub(allowed))
+        };
+        
+        let change = new_target.abs_diff(current);
+        if !change.is_zero() {
+            self.recent_adjustments.push_back((now, change));
+        }
+        self.target_latency = new_target;
+        self.last_adjustment = Some(now);
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct BufferStats {
     pub target_latency_ms: u32,
+    
+    /// Latency bounds in effect for the client
+    pub min_latency_ms: u32,
+    pub max_latency_ms: u32,
     pub underrun_count: u64,
     pub overrun_count: u64,
+    
+    /// Gaps in audio sequence numbers concealment was requested for
+    pub concealment_count: u64,
+    pub concealed_frames: u64,
+    
+    /// Frames waiting for release and the presentation time they span
+    pub queued_frames: usize,
+    pub occupancy_ms: u32,
+    
+    /// Frames released after their presentation time, within the late slack
+    pub late_frames: u64,
+    
+    /// Frames dropped for being later than the slack, with the video
+    /// frames depending on them
+    pub dropped_late_frames: u64,
+    
+    /// Interarrival jitter of the worst track, and the allowance it gets
+    pub measured_jitter_ms: Option<f64>,
+    pub jitter_buffer_ms: u32,
+    
+    /// Output queue and playout delay the client last reported
+    pub client_occupancy_ms: Option<u32>,
+    pub client_playout_delay_ms: Option<u32>,
+    
+    /// Playback rate hinted to the client, 1.0 when none
+    pub playback_rate: f64,
+    
     pub network_quality: NetworkQuality,
+    
+    /// Quality after underrun/overrun reclassification
+    pub effective_quality: NetworkQuality,
+}
+
+/// Record an event at `now`, forgetting those outside the window
+fn record_event(events: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while events.front().is_some_and(|at| now.duration_since(*at) > window) {
+        events.pop_front();
+    }
+    events.push_back(now);
 }
 
 #[cfg(test)]
@@ -173,16 +1053,505 @@ mod tests {
             NetworkQuality::Good,
         );
         
-        // Simulate underruns
+        // A single underrun is held; the second grows the buffer, with no
+        // cooldown running before the first adjustment
         buffer.report_underrun();
-        assert!(buffer.target_latency > Duration::from_millis(80));
+        assert_eq!(buffer.target_latency, Duration::from_millis(80));
+        buffer.report_underrun();
+        let grown = buffer.target_latency;
+        assert!(grown > Duration::from_millis(80));
         
-        // Simulate network quality change
+        // Within the cooldown that growth started, quality changes wait
         buffer.update_network_quality(NetworkQuality::Poor);
+        assert_eq!(buffer.target_latency, grown);
+        
+        // Simulate network quality change
         std::thread::sleep(Duration::from_millis(600)); // Wait for adjustment
         buffer.update_network_quality(NetworkQuality::Poor);
         
         // Should adjust towards poor network recommendation
-        assert!(buffer.target_latency > Duration::from_millis(150));
+        let poor = buffer.policy.recommended(NetworkQuality::Poor);
+        assert!(buffer.target_latency > grown && buffer.target_latency < poor);
+    }
+    
+    #[test]
+    fn test_underrun_burst_downgrades_effective_quality() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(80),
+            NetworkQuality::Good,
+        );
+        
+        for _ in 0..UNDERRUN_BURST - 1 {
+            buffer.report_underrun();
+        }
+        assert_eq!(buffer.effective_quality(), NetworkQuality::Good);
+        
+        buffer.report_underrun();
+        let stats = buffer.stats();
+        assert_eq!(stats.network_quality, NetworkQuality::Good);
+        assert_eq!(stats.effective_quality, NetworkQuality::Fair);
+        assert_eq!(stats.underrun_count, UNDERRUN_BURST as u64);
+        let fair = Duration::from_millis(NetworkQuality::Fair.recommended_buffer_ms());
+        assert!(buffer.target_latency >= fair);
+        
+        // Sustained overruns win it back
+        for _ in 0..SUSTAINED_OVERRUNS {
+            buffer.report_overrun();
+        }
+        assert_eq!(buffer.effective_quality(), NetworkQuality::Good);
+    }
+    
+    #[test]
+    fn test_policy_caps_latency_under_critical_quality() {
+        let policy = BufferPolicy {
+            min_latency: Duration::from_millis(10),
+            max_latency: Duration::from_millis(60),
+            ..undamped_policy()
+        };
+        let mut buffer = DynamicFutureBuffer::with_policy(
+            Duration::from_millis(80),
+            NetworkQuality::Critical,
+            policy,
+        );
+        assert_eq!(buffer.target_latency, Duration::from_millis(60));
+        
+        for _ in 0..20 {
+            buffer.report_underrun();
+        }
+        assert_eq!(buffer.target_latency, Duration::from_millis(60));
+        
+        std::thread::sleep(Duration::from_millis(600)); // Wait for adjustment
+        buffer.update_network_quality(NetworkQuality::Critical);
+        assert_eq!(buffer.target_latency, Duration::from_millis(60));
+        
+        for _ in 0..100 {
+            buffer.report_overrun();
+        }
+        assert_eq!(buffer.target_latency, Duration::from_millis(10));
+    }
+    
+    #[test]
+    fn test_latency_bounds_override_precedence() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(150),
+            NetworkQuality::Good,
+        );
+        let bounds = |min, max| LatencyBounds {
+            min_latency_ms: min,
+            max_latency_ms: max,
+        };
+        let effective = |buffer: &DynamicFutureBuffer| {
+            let stats = buffer.stats();
+            (stats.min_latency_ms, stats.max_latency_ms)
+        };
+        assert_eq!(effective(&buffer), (30, 500));
+        
+        // The hello lowers the ceiling, pulling the latency down with it
+        buffer.set_latency_bounds(BoundsSource::Hello, bounds(None, Some(100))).unwrap();
+        assert_eq!(effective(&buffer), (30, 100));
+        assert_eq!(buffer.target_latency, Duration::from_millis(100));
+        
+        // The API raises the floor, keeping the hello's ceiling, then
+        // overrides the ceiling too
+        buffer.set_latency_bounds(BoundsSource::Api, bounds(Some(60), None)).unwrap();
+        assert_eq!(effective(&buffer), (60, 100));
+        buffer.set_latency_bounds(BoundsSource::Api, bounds(Some(60), Some(200))).unwrap();
+        assert_eq!(effective(&buffer), (60, 200));
+        
+        // A later hello does not undo the API's bounds
+        buffer.set_latency_bounds(BoundsSource::Hello, bounds(Some(10), Some(50))).unwrap();
+        assert_eq!(effective(&buffer), (60, 200));
+        
+        // Impossible bounds are rejected and change nothing
+        assert!(buffer.set_latency_bounds(BoundsSource::Api, bounds(Some(300), None)).is_err());
+        assert!(buffer.set_latency_bounds(BoundsSource::Api, bounds(None, Some(5))).is_err());
+        assert_eq!(effective(&buffer), (60, 200));
+        
+        // Clearing the API override falls back to the hello
+        buffer.set_latency_bounds(BoundsSource::Api, LatencyBounds::default()).unwrap();
+        assert_eq!(effective(&buffer), (10, 50));
+        
+        // Quality recommendations stay within the effective bounds
+        std::thread::sleep(Duration::from_millis(600)); // Wait for adjustment
+        buffer.update_network_quality(NetworkQuality::Critical);
+        assert!(buffer.target_latency <= Duration::from_millis(50));
+    }
+    
+    #[test]
+    fn test_rate_hint_engages_on_sustained_deviation_with_hysteresis() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let ms = Duration::from_millis;
+        
+        // Within the 20ms threshold nothing is hinted
+        assert_eq!(buffer.observe_occupancy(ms(115), at(0.0)), None);
+        assert_eq!(buffer.observe_occupancy(ms(115), at(5.0)), None);
+        
+        // 60ms of excess held for 2s asks for 0.6% faster playback, and
+        // frames are released that much earlier
+        assert_eq!(buffer.observe_occupancy(ms(160), at(5.0)), None);
+        assert_eq!(buffer.observe_occupancy(ms(160), at(6.0)), None);
+        let rate = buffer.observe_occupancy(ms(160), at(7.0)).unwrap();
+        assert!((rate - 1.006).abs() < 1e-9, "{}", rate);
+        assert!((buffer.release_lead() - 0.1006).abs() < 1e-9);
+        assert_eq!(buffer.stats().playback_rate, rate);
+        
+        // The hint holds until the occupancy is within half the threshold
+        assert_eq!(buffer.observe_occupancy(ms(115), at(8.0)), None);
+        assert_eq!(buffer.stats().playback_rate, rate);
+        assert_eq!(buffer.observe_occupancy(ms(108), at(9.0)), Some(1.0));
+        assert_eq!(buffer.release_lead(), 0.1);
+        
+        // A deviation that changes sides starts its sustain period over
+        assert_eq!(buffer.observe_occupancy(ms(400), at(10.0)), None);
+        assert_eq!(buffer.observe_occupancy(ms(40), at(11.0)), None);
+        assert_eq!(buffer.observe_occupancy(ms(40), at(12.5)), None);
+        let rate = buffer.observe_occupancy(ms(40), at(13.0)).unwrap();
+        assert!((rate - 0.994).abs() < 1e-9, "{}", rate);
+        assert_eq!(buffer.observe_occupancy(ms(85), at(14.0)), None);
+        assert_eq!(buffer.observe_occupancy(ms(95), at(15.0)), Some(1.0));
+        
+        // Large deviations are corrected at no more than 2%
+        buffer.observe_occupancy(ms(900), at(20.0));
+        assert_eq!(buffer.observe_occupancy(ms(900), at(22.0)), Some(1.02));
+    }
+    
+    /// Policy acting on every report at once, as before hysteresis
+    fn undamped_policy() -> BufferPolicy {
+        BufferPolicy {
+            underruns_to_grow: 1,
+            shrink_quiet_period: Duration::ZERO,
+            adjustment_cooldown: Duration::ZERO,
+            max_adjustment_per_minute: Duration::MAX,
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_alternating_reports_do_not_sawtooth() {
+        let mut buffer = DynamicFutureBuffer::with_policy(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+            BufferPolicy {
+                adjustment_cooldown: Duration::ZERO,
+                ..Default::default()
+            },
+        );
+        
+        // Overruns right after underruns never shrink the latency, and the
+        // growth is capped at 200ms a minute
+        let mut lowest = buffer.target_latency;
+        let mut highest = buffer.target_latency;
+        for _ in 0..50 {
+            buffer.report_underrun();
+            buffer.report_overrun();
+            lowest = lowest.min(buffer.target_latency);
+            highest = highest.max(buffer.target_latency);
+        }
+        assert_eq!(lowest, Duration::from_millis(100));
+        assert!(highest <= Duration::from_millis(300), "grew to {:?}", highest);
+        assert_eq!((buffer.underrun_count, buffer.overrun_count), (50, 50));
+        
+        // Without damping the same reports move it every time
+        let mut buffer = DynamicFutureBuffer::with_policy(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+            undamped_policy(),
+        );
+        buffer.report_underrun();
+        let grown = buffer.target_latency;
+        buffer.report_overrun();
+        assert!(buffer.target_latency < grown && grown > Duration::from_millis(100));
+        
+        // By default a lone underrun does not grow it, a second does, and
+        // an overrun within the cooldown that starts does not shrink it
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        buffer.report_underrun();
+        buffer.report_overrun();
+        assert_eq!(buffer.target_latency, Duration::from_millis(100));
+        buffer.report_underrun();
+        buffer.last_underrun = None;
+        buffer.report_overrun();
+        assert_eq!(buffer.target_latency, Duration::from_millis(110));
+    }
+    
+    fn frame(timestamp: f64, sequence: u64) -> MediaFrame {
+        MediaFrame {
+            data: Bytes::new(),
+            timestamp,
+            duration: Duration::from_millis(20),
+            frame_type: FrameType::Audio,
+            sequence,
+            renditions: Arc::default(),
+        }
+    }
+    
+    #[test]
+    fn test_frames_are_released_in_presentation_order() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        
+        // Frames of two tracks arrive out of order
+        for (track_id, timestamp, sequence) in [
+            ("a", 10.04, 2),
+            ("b", 10.02, 1),
+            ("a", 10.0, 0),
+            ("a", 10.02, 1),
+            ("b", 10.0, 0),
+        ] {
+            assert!(buffer.push(track_id, frame(timestamp, sequence)).is_none());
+        }
+        assert_eq!(buffer.occupancy(), Duration::from_millis(60));
+        assert!((buffer.next_due().unwrap() - 9.9).abs() < 1e-9);
+        
+        // Only frames within the target latency of presentation leave
+        assert!(buffer.pop_ready(9.85).is_empty());
+        let released: Vec<_> = buffer
+            .pop_ready(9.93)
+            .into_iter()
+            .map(|queued| (queued.track_id, queued.frame.sequence))
+            .collect();
+        let expected = [("a", 0), ("b", 0), ("b", 1), ("a", 1)];
+        assert_eq!(released, expected.map(|(track_id, sequence)| (track_id.to_string(), sequence)));
+        
+        let stats = buffer.stats();
+        assert_eq!((stats.queued_frames, stats.occupancy_ms), (1, 20));
+        assert_eq!(buffer.pop_ready(9.95).len(), 1);
+        assert_eq!(buffer.occupancy(), Duration::ZERO);
+    }
+    
+    #[test]
+    fn test_late_frames_are_flagged_or_dropped() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        
+        // Within the 5ms slack frames are sent flagged, later ones dropped
+        buffer.push("a", frame(9.99, 0));
+        buffer.push("a", frame(9.997, 1));
+        buffer.push("a", frame(10.05, 2));
+        let released: Vec<_> = buffer
+            .pop_ready(10.0)
+            .into_iter()
+            .map(|queued| (queued.frame.sequence, queued.late))
+            .collect();
+        assert_eq!(released, [(1, true), (2, false)]);
+        let stats = buffer.stats();
+        assert_eq!((stats.late_frames, stats.dropped_late_frames), (1, 1));
+        
+        // Only frames still to be presented count as playing
+        assert!((buffer.next_due().unwrap() - 10.08).abs() < 1e-9);
+        assert_eq!(buffer.stats().underrun_count, 0);
+    }
+    
+    #[test]
+    fn test_adaptive_jitter_buffer_follows_measured_jitter() {
+        let adaptive = BufferPolicy {
+            jitter_mode: JitterMode::Adaptive,
+            ..Default::default()
+        };
+        
+        // 20ms frames arriving `offset` early and late in turn
+        let depth_for = |policy: BufferPolicy, offset: f64| {
+            let mut buffer = DynamicFutureBuffer::with_policy(
+                Duration::from_millis(100),
+                NetworkQuality::Good,
+                policy,
+            );
+            for n in 0..100u64 {
+                let timestamp = 10.0 + n as f64 * 0.02;
+                let jitter = if n % 2 == 0 { -offset } else { offset };
+                buffer.record_arrival("a", &frame(timestamp, n), timestamp - 1.0 + jitter);
+            }
+            buffer.calculate_jitter_buffer()
+        };
+        
+        let steady = depth_for(adaptive.clone(), 0.0);
+        let low = depth_for(adaptive.clone(), 0.002);
+        let high = depth_for(adaptive.clone(), 0.015);
+        assert_eq!(steady, adaptive.jitter_margin);
+        assert!(low > steady && high > low, "{:?} {:?} {:?}", steady, low, high);
+        
+        // Alternating by 2x15ms gives a jitter close to 30ms
+        assert!(high > Duration::from_millis(90) && high < Duration::from_millis(100));
+        
+        // Fixed mode keeps the per-quality depth whatever the jitter
+        let fixed = BufferPolicy {
+            jitter_mode: JitterMode::Fixed,
+            ..Default::default()
+        };
+        assert_eq!(depth_for(fixed, 0.015), Duration::from_millis(10));
+        
+        // Adaptive mode falls back to it until jitter has been measured
+        let buffer = DynamicFutureBuffer::with_policy(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+            adaptive,
+        );
+        assert_eq!(buffer.calculate_jitter_buffer(), Duration::from_millis(10));
+    }
+    
+    #[test]
+    fn test_jitter_depth_waits_for_enough_arrivals() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Fair,
+        );
+        
+        // 20ms frames whose transit time alternates by 8ms: every
+        // interarrival deviates by 8ms, so the estimate converges to it
+        let arrive = |buffer: &mut DynamicFutureBuffer, n: u64| {
+            let timestamp = 10.0 + n as f64 * 0.02;
+            let transit = if n.is_multiple_of(2) { 0.030 } else { 0.038 };
+            buffer.record_arrival("a", &frame(timestamp, n), timestamp - 1.0 + transit);
+        };
+        for n in 0..MIN_JITTER_SAMPLES {
+            arrive(&mut buffer, n);
+        }
+        
+        // One sample short: jitter is reported but the table still sizes
+        // the buffer
+        assert!(buffer.measured_jitter().is_some());
+        assert_eq!(buffer.calculate_jitter_buffer(), Duration::from_millis(20));
+        
+        for n in MIN_JITTER_SAMPLES..200 {
+            arrive(&mut buffer, n);
+        }
+        let jitter = buffer.measured_jitter().unwrap().as_secs_f64() * 1000.0;
+        assert!((jitter - 8.0).abs() < 0.1, "{}", jitter);
+        
+        let depth = buffer.calculate_jitter_buffer();
+        let depth_ms = depth.as_secs_f64() * 1000.0;
+        assert!((depth_ms - (3.0 * 8.0 + 5.0)).abs() < 0.5, "{}", depth_ms);
+        
+        let stats = buffer.stats();
+        assert!((stats.measured_jitter_ms.unwrap() - 8.0).abs() < 0.1);
+        assert_eq!(stats.jitter_buffer_ms, depth.as_millis() as u32);
+    }
+    
+    #[test]
+    fn test_late_keyframes_are_kept_and_broken_delta_frames_dropped() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        let video = |timestamp, sequence, frame_type| MediaFrame {
+            frame_type,
+            ..frame(timestamp, sequence)
+        };
+        
+        // A late keyframe is still sent, the late delta frame after it is not
+        buffer.push("v", video(9.9, 0, FrameType::VideoKeyframe));
+        buffer.push("v", video(9.94, 1, FrameType::Video));
+        let released = buffer.pop_ready(10.0);
+        assert_eq!(released.len(), 1);
+        assert!(released[0].late && released[0].frame.frame_type == FrameType::VideoKeyframe);
+        
+        // Delta frames depending on the dropped one follow it, even on time
+        buffer.push("v", video(10.05, 2, FrameType::Video));
+        assert!(buffer.pop_ready(10.0).is_empty());
+        
+        // The next keyframe resumes the track
+        buffer.push("v", video(10.1, 3, FrameType::VideoKeyframe));
+        buffer.push("v", video(10.14, 4, FrameType::Video));
+        let sequences: Vec<_> = buffer.pop_ready(10.05).iter().map(|queued| queued.frame.sequence).collect();
+        assert_eq!(sequences, [3, 4]);
+        
+        let stats = buffer.stats();
+        assert_eq!((stats.late_frames, stats.dropped_late_frames), (1, 2));
+    }
+    
+    #[test]
+    fn test_underrun_and_overrun_accounting() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        
+        buffer.push("a", frame(10.0, 0));
+        buffer.push("a", frame(10.02, 1));
+        assert_eq!(buffer.pop_ready(9.95).len(), 2);
+        
+        // Media lasts until 10.04, plus the 10ms jitter allowance
+        assert!(buffer.pop_ready(10.045).is_empty());
+        assert_eq!(buffer.stats().underrun_count, 0);
+        buffer.pop_ready(10.06);
+        assert_eq!(buffer.stats().underrun_count, 1);
+        buffer.pop_ready(10.5);
+        assert_eq!(buffer.stats().underrun_count, 1);
+        
+        // Playback that ended runs out without an underrun, even for its
+        // frames still in flight
+        buffer.push("a", frame(11.0, 2));
+        buffer.pop_ready(10.95);
+        buffer.end_playback("a", 4);
+        buffer.push("a", frame(11.02, 3));
+        buffer.pop_ready(10.97);
+        buffer.pop_ready(11.5);
+        assert_eq!(buffer.stats().underrun_count, 1);
+        
+        // The next playback is watched again
+        buffer.push("a", frame(12.0, 4));
+        buffer.pop_ready(11.95);
+        buffer.pop_ready(12.1);
+        assert_eq!(buffer.stats().underrun_count, 2);
+        
+        // A full queue drops its oldest frame
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(100),
+            NetworkQuality::Good,
+        );
+        buffer.max_depth = 2;
+        assert!(buffer.push("a", frame(10.02, 1)).is_none());
+        assert!(buffer.push("a", frame(10.04, 2)).is_none());
+        let dropped = buffer.push("a", frame(10.0, 0)).unwrap();
+        assert_eq!(dropped.frame.sequence, 0);
+        let stats = buffer.stats();
+        assert_eq!((stats.queued_frames, stats.overrun_count), (2, 1));
+    }
+    
+    #[test]
+    fn test_missing_sequence_requests_concealment() {
+        let mut buffer = DynamicFutureBuffer::new(
+            Duration::from_millis(80),
+            NetworkQuality::Good,
+        );
+        let frame = |sequence: u64, frame_type| MediaFrame {
+            frame_type,
+            ..frame(100.0 + sequence as f64 * 0.02, sequence)
+        };
+        
+        for sequence in 0..3 {
+            assert!(buffer.check_sequence("track", &frame(sequence, FrameType::Audio)).is_none());
+        }
+        
+        // Frames 3 and 4 never arrive
+        let request = buffer
+            .check_sequence("track", &frame(5, FrameType::Audio))
+            .unwrap();
+        assert_eq!((request.first_sequence, request.missing), (3, 2));
+        assert!((request.timestamp - 100.06).abs() < 1e-9);
+        assert_eq!(request.duration, Duration::from_millis(40));
+        let stats = buffer.stats();
+        assert_eq!((stats.concealment_count, stats.concealed_frames), (1, 2));
+        
+        // Other tracks, video and restarted sequences are not gaps
+        assert!(buffer.check_sequence("other", &frame(9, FrameType::Audio)).is_none());
+        assert!(buffer.check_sequence("track", &frame(8, FrameType::Video)).is_none());
+        assert!(buffer.check_sequence("track", &frame(6, FrameType::Audio)).is_none());
+        assert!(buffer.check_sequence("track", &frame(0, FrameType::Audio)).is_none());
+        buffer.remove_track("track");
+        assert!(buffer.check_sequence("track", &frame(20, FrameType::Audio)).is_none());
+        assert_eq!(buffer.stats().concealment_count, 1);
     }
 }
\ No newline at end of file
@@ -0,0 +1,413 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncWriteExt, sync::broadcast, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::{
+    buffer::{FrameType, MediaFrame},
+    recording::{RecordingError, RecordingState},
+};
+
+/// Start of every capture segment, followed by the stream's codec
+const MAGIC: &[u8; 8] = b"SSCAPT01";
+
+/// Extension of capture segment files
+const SEGMENT_EXTENSION: &str = "sscap";
+
+/// Frame type, sequence, timestamp and duration preceding each payload
+const RECORD_HEADER_LEN: usize = 1 + 8 + 8 + 8;
+
+/// Capture progress reported in stream stats
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureStatus {
+    /// Segment files written so far, in order
+    pub segments: Vec<PathBuf>,
+    pub state: RecordingState,
+    pub frames: u64,
+    pub bytes: u64,
+
+    /// Frames missed because the capture fell behind the stream
+    pub frames_dropped: u64,
+
+    /// Why the capture stopped early
+    pub error: Option<String>,
+}
+
+/// Frames read back from a capture
+#[derive(Debug, Clone)]
+pub struct CapturedStream {
+    pub codec: String,
+    pub frames: Vec<MediaFrame>,
+}
+
+/// Capture of the exact frames a stream distributes, for debugging sync
+///
+/// Unlike a [`Recording`](super::Recording), which writes a playable
+/// container, a capture keeps every frame's timestamp, type, sequence and
+/// duration next to its payload so the stream can be replayed as the server
+/// saw it. Frames are written to `<dir>/<name>.<n>.sscap` segments, and a
+/// new segment is started before one would grow past the segment size.
+///
+/// Each segment starts with `SSCAPT01`, a codec name length byte and the
+/// codec name, followed by length-prefixed frame records:
+///
+/// ```text
+/// u32 record length | u8 frame type | u64 sequence | f64 timestamp |
+/// u64 duration (ns) | payload
+/// ```
+///
+/// All integers and floats are little-endian.
+pub struct Capture {
+    cancel: CancellationToken,
+    status: Arc<Mutex<CaptureStatus>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Capture {
+    /// Create the first segment and start writing frames from `frame_rx`
+    pub async fn start(
+        dir: &Path,
+        name: &str,
+        frame_rx: broadcast::Receiver<MediaFrame>,
+        codec: &str,
+        segment_bytes: u64,
+    ) -> Result<Self, RecordingError> {
+        let mut writer = SegmentWriter {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            header: segment_header(codec),
+            segment_bytes,
+            file: None,
+            written: 0,
+            segments: 0,
+        };
+        let path = writer.open_segment().await?;
+        info!("Capturing {} stream to {}", codec, path.display());
+
+        let status = Arc::new(Mutex::new(CaptureStatus {
+            segments: vec![path],
+            state: RecordingState::Recording,
+            frames: 0,
+            bytes: writer.written,
+            frames_dropped: 0,
+            error: None,
+        }));
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(run(writer, status.clone(), frame_rx, cancel.clone()));
+
+        Ok(Self {
+            cancel,
+            status,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        self.status.lock().clone()
+    }
+
+    /// Whether frames are still being written
+    pub fn is_active(&self) -> bool {
+        self.status.lock().state == RecordingState::Recording
+    }
+
+    /// Stop capturing, once the frames already broadcast are written
+    pub async fn stop(&self) -> CaptureStatus {
+        self.cancel.cancel();
+        let task = self.task.lock().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+        self.status()
+    }
+}
+
+/// Segment files of the capture `name`, in order
+pub fn segment_path(dir: &Path, name: &str, index: u32) -> PathBuf {
+    dir.join(format!("{}.{:05}.{}", name, index, SEGMENT_EXTENSION))
+}
+
+/// Read every segment of the capture `name` back into frames
+pub async fn read_capture(dir: &Path, name: &str) -> Result<CapturedStream, RecordingError> {
+    let mut codec = None;
+    let mut frames = Vec::new();
+    for index in 0.. {
+        let path = segment_path(dir, name, index);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && index > 0 => break,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(RecordingError::CaptureNotFound(name.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = |reason: &str| RecordingError::Corrupt(path.display().to_string(), reason.to_string());
+
+        let (segment_codec, records) = parse_header(&data).ok_or_else(|| corrupt("bad segment header"))?;
+        match &codec {
+            None => codec = Some(segment_codec.to_string()),
+            Some(codec) if codec != segment_codec => return Err(corrupt("codec changed between segments")),
+            Some(_) => {}
+        }
+        parse_records(records, &mut frames).map_err(corrupt)?;
+    }
+
+    Ok(CapturedStream {
+        codec: codec.unwrap_or_default(),
+        frames,
+    })
+}
+
+/// Task writing broadcast frames to capture segments
+async fn run(
+    mut writer: SegmentWriter,
+    status: Arc<Mutex<CaptureStatus>>,
+    mut frame_rx: broadcast::Receiver<MediaFrame>,
+    cancel: CancellationToken,
+) {
+    let result = loop {
+        // Frames broadcast before the stop are still written
+        let frame = tokio::select! {
+            _ = cancel.cancelled() => match frame_rx.try_recv() {
+                Ok(frame) => Ok(frame),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    Err(broadcast::error::RecvError::Lagged(skipped))
+                }
+                Err(_) => break Ok(()),
+            },
+            frame = frame_rx.recv() => frame,
+        };
+        match frame {
+            Ok(frame) => match writer.write_frame(&frame).await {
+                Ok((new_segment, bytes)) => {
+                    let mut status = status.lock();
+                    status.segments.extend(new_segment);
+                    status.frames += 1;
+                    status.bytes += bytes;
+                }
+                Err(e) => break Err(e),
+            },
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Capture fell behind, skipped {} frames", skipped);
+                status.lock().frames_dropped += skipped;
+            }
+            Err(broadcast::error::RecvError::Closed) => break Ok(()),
+        }
+    };
+    let result = match result {
+        Ok(()) => writer.finish().await,
+        Err(e) => Err(e),
+    };
+
+    let mut status = status.lock();
+    match result {
+        Ok(()) => {
+            status.state = RecordingState::Stopped;
+            info!(
+                "Capture {} finished: {} frames in {} segments",
+                writer.name,
+                status.frames,
+                status.segments.len()
+            );
+        }
+        Err(e) => {
+            warn!("Capture {} failed: {}", writer.name, e);
+            status.state = RecordingState::Failed;
+            status.error = Some(e.to_string());
+        }
+    }
+}
+
+/// Writes frame records, rotating segments by size
+struct SegmentWriter {
+    dir: PathBuf,
+    name: String,
+    header: Vec<u8>,
+    segment_bytes: u64,
+    file: Option<File>,
+
+    /// Bytes written to the current segment
+    written: u64,
+
+    /// Segments opened so far
+    segments: u32,
+}
+
+impl SegmentWriter {
+    async fn open_segment(&mut self) -> std::io::Result<PathBuf> {
+        self.finish().await?;
+        let path = segment_path(&self.dir, &self.name, self.segments);
+        let mut file = File::create(&path).await?;
+        file.write_all(&self.header).await?;
+        file.flush().await?;
+        self.file = Some(file);
+        self.written = self.header.len() as u64;
+        self.segments += 1;
+        Ok(path)
+    }
+
+    /// Append a frame, returning the segment it opened, if any, and the
+    /// bytes written
+    ///
+    /// A frame larger than the segment size still gets a segment of its own.
+    async fn write_frame(&mut self, frame: &MediaFrame) -> std::io::Result<(Option<PathBuf>, u64)> {
+        let record = encode_record(frame);
+        let header_only = self.written == self.header.len() as u64;
+        let new_segment = if !header_only && self.written + record.len() as u64 > self.segment_bytes {
+            Some(self.open_segment().await?)
+        } else {
+            None
+        };
+        let opened = new_segment.as_ref().map_or(0, |_| self.header.len() as u64);
+
+        let file = self.file.as_mut().expect("a segment is open while capturing");
+        file.write_all(&record).await?;
+        file.flush().await?;
+        self.written += record.len() as u64;
+        Ok((new_segment, opened + record.len() as u64))
+    }
+
+    /// Sync and close the current segment
+    async fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+
+fn segment_header(codec: &str) -> Vec<u8> {
+    let codec = &codec.as_bytes()[..codec.len().min(u8::MAX as usize)];
+    let mut header = MAGIC.to_vec();
+    header.push(codec.len() as u8);
+    header.extend_from_slice(codec);
+    header
+}
+
+/// Codec named in a segment header, and the records after it
+fn parse_header(data: &[u8]) -> Option<(&str, &[u8])> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let (&len, rest) = rest.split_first()?;
+    let (codec, records) = rest.split_at_checked(len as usize)?;
+    Some((std::str::from_utf8(codec).ok()?, records))
+}
+
+fn encode_record(frame: &MediaFrame) -> Vec<u8> {
+    let len = RECORD_HEADER_LEN + frame.data.len();
+    let mut record = Vec::with_capacity(4 + len);
+    record.extend((len as u32).to_le_bytes());
+    record.push(match frame.frame_type {
+        FrameType::Audio => 0,
+        FrameType::Video => 1,
+        FrameType::VideoKeyframe => 2,
+    });
+    record.extend(frame.sequence.to_le_bytes());
+    record.extend(frame.timestamp.to_le_bytes());
+    record.extend((frame.duration.as_nanos() as u64).to_le_bytes());
+    record.extend_from_slice(&frame.data);
+    record
+}
+
+fn parse_records(mut data: &[u8], frames: &mut Vec<MediaFrame>) -> Result<(), &'static str> {
+    while !data.is_empty() {
+        let (len, rest) = data.split_first_chunk::<4>().ok_or("truncated record length")?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if len < RECORD_HEADER_LEN {
+            return Err("record shorter than its header");
+        }
+        let (record, rest) = rest.split_at_checked(len).ok_or("truncated record")?;
+        data = rest;
+
+        let frame_type = match record[0] {
+            0 => FrameType::Audio,
+            1 => FrameType::Video,
+            2 => FrameType::VideoKeyframe,
+            _ => return Err("unknown frame type"),
+        };
+        let field = |at: usize| <[u8; 8]>::try_from(&record[at..at + 8]).expect("record header is long enough");
+        frames.push(MediaFrame {
+            data: record[RECORD_HEADER_LEN..].to_vec().into(),
+            timestamp: f64::from_le_bytes(field(9)),
+            duration: Duration::from_nanos(u64::from_le_bytes(field(17))),
+            frame_type,
+            sequence: u64::from_le_bytes(field(1)),
+            renditions: Arc::default(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sequence: u64, frame_type: FrameType, data: Vec<u8>) -> MediaFrame {
+        MediaFrame {
+            data: data.into(),
+            timestamp: 1000.0 + sequence as f64 / 30.0,
+            duration: Duration::from_nanos(33_333_333),
+            frame_type,
+            sequence,
+            renditions: Arc::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_rotates_segments_and_reads_back_exactly() {
+        let dir = std::env::temp_dir().join(format!("solusync-capture-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (frame_tx, frame_rx) = broadcast::channel(16);
+
+        // Room for two of these frames per segment
+        let capture = Capture::start(&dir, "cam", frame_rx, "h264", 300).await.unwrap();
+        let frames: Vec<_> = (0..5u64)
+            .map(|sequence| {
+                let frame_type = if sequence == 0 { FrameType::VideoKeyframe } else { FrameType::Video };
+                frame(sequence, frame_type, (0..100).map(|i| (i * 7 + sequence) as u8).collect())
+            })
+            .collect();
+        for frame in &frames {
+            frame_tx.send(frame.clone()).unwrap();
+        }
+        let status = capture.stop().await;
+        assert_eq!(status.state, RecordingState::Stopped);
+        assert_eq!(status.frames, 5);
+        assert_eq!(status.segments.len(), 3);
+        let on_disk: u64 = status
+            .segments
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum();
+        assert_eq!(status.bytes, on_disk);
+        assert!(status.segments.iter().all(|path| std::fs::metadata(path).unwrap().len() <= 300));
+
+        let captured = read_capture(&dir, "cam").await.unwrap();
+        assert_eq!(captured.codec, "h264");
+        assert_eq!(captured.frames.len(), frames.len());
+        for (read, written) in captured.frames.iter().zip(&frames) {
+            assert_eq!(read.data, written.data);
+            assert_eq!(read.timestamp.to_bits(), written.timestamp.to_bits());
+            assert_eq!(read.duration, written.duration);
+            assert_eq!(read.frame_type, written.frame_type);
+            assert_eq!(read.sequence, written.sequence);
+        }
+
+        // A cut-off segment is reported rather than replayed partially
+        let last = status.segments.last().unwrap();
+        let data = std::fs::read(last).unwrap();
+        std::fs::write(last, &data[..data.len() - 1]).unwrap();
+        assert!(matches!(read_capture(&dir, "cam").await, Err(RecordingError::Corrupt(..))));
+        assert!(matches!(
+            read_capture(&dir, "missing").await,
+            Err(RecordingError::CaptureNotFound(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
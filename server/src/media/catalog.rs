@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+use tokio::sync::RwLock;
+
+/// Registered media track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub track_id: String,
+    pub path: PathBuf,
+    pub content_hash: String,
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub duration: Option<f64>,
+    pub size_bytes: u64,
+    pub added_at: chrono::DateTime<chrono::Utc>,
+
+    /// Integrated loudness in LUFS; unset for silence and passthrough codecs
+    pub loudness: Option<f64>,
+
+    /// Gain applied on playback to reach the reference loudness, in dB
+    pub gain_db: f64,
+}
+
+impl TrackInfo {
+    /// Linear playback gain
+    pub fn gain(&self) -> f64 {
+        10f64.powf(self.gain_db / 20.0)
+    }
+}
+
+/// Catalog of tracks available for playback
+#[derive(Default)]
+pub struct TrackCatalog {
+    tracks: RwLock<HashMap<String, TrackInfo>>,
+}
+
+impl TrackCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a track, replacing any existing entry with the same ID
+    pub async fn insert(&self, track: TrackInfo) {
+        self.tracks.write().await.insert(track.track_id.clone(), track);
+    }
+
+    /// Look up a track
+    pub async fn get(&self, track_id: &str) -> Option<TrackInfo> {
+        self.tracks.read().await.get(track_id).cloned()
+    }
+
+    /// List all tracks, oldest first
+    pub async fn list(&self) -> Vec<TrackInfo> {
+        let mut tracks: Vec<_> = self.tracks.read().await.values().cloned().collect();
+        tracks.sort_by_key(|t| t.added_at);
+        tracks
+    }
+
+    /// Remove a track from the catalog
+    pub async fn remove(&self, track_id: &str) -> Option<TrackInfo> {
+        self.tracks.write().await.remove(track_id)
+    }
+}
+
+/// Errors from catalog operations
+#[derive(Debug, thiserror::Error)]
+pub enum CatalogError {
+    #[error("File is not a supported audio format: {0}")]
+    UnsupportedMedia(String),
+
+    #[error("Track not found: {0}")]
+    NotFound(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
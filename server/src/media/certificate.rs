@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tracing::info;
+use webrtc::{dtls::crypto::Certificate, peer_connection::certificate::RTCCertificate};
+
+/// End of a generated certificate's validity, 4096-01-01 as rcgen issues
+/// self-signed certificates, in seconds since the Unix epoch
+const CERTIFICATE_NOT_AFTER_SECS: u64 = 67_090_118_400;
+
+/// PEM file holding the DTLS certificate and private key all peer
+/// connections present
+///
+/// Without one, every peer connection generates a certificate of its own,
+/// so its fingerprint changes with each connection and restart and clients
+/// cannot pin it.
+pub struct CertificateFile {
+    path: PathBuf,
+}
+
+impl CertificateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Read the saved certificate, generating and saving one if there is
+    /// none yet
+    ///
+    /// A file that cannot be read or parsed is an error rather than
+    /// replaced, as a new certificate breaks pinning clients.
+    pub fn load_or_generate(&self) -> Result<RTCCertificate> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(pem) => RTCCertificate::from_pem(&pem)
+                .with_context(|| format!("Invalid DTLS certificate in {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.regenerate(),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    /// Generate a new certificate and save it over any previous one
+    pub fn regenerate(&self) -> Result<RTCCertificate> {
+        let dtls_certificate = Certificate::generate_self_signed(vec!["solusync-x".to_string()])
+            .context("Failed to generate a DTLS certificate")?;
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(CERTIFICATE_NOT_AFTER_SECS);
+        let certificate = RTCCertificate::from_existing(dtls_certificate, expires);
+        self.save(&certificate)?;
+        info!("Generated DTLS certificate {}", self.path.display());
+        Ok(certificate)
+    }
+
+    /// Write the certificate and its private key, readable only by the
+    /// server's user
+    ///
+    /// The file is written next to the old one and renamed over it, so a
+    /// crash mid-write leaves the previous certificate intact.
+    fn save(&self, certificate: &RTCCertificate) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let mut temp_path = OsString::from(self.path.as_os_str());
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        file.write_all(certificate.serialize_pem().as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_is_kept_until_regenerated() {
+        let dir = std::env::temp_dir().join(format!("solusync-cert-{}", uuid::Uuid::new_v4()));
+        let file = CertificateFile::new(dir.join("dtls.pem"));
+
+        let generated = file.load_or_generate().unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file.path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(file.load_or_generate().unwrap(), generated);
+
+        let rotated = file.regenerate().unwrap();
+        assert_ne!(rotated, generated);
+        assert_eq!(file.load_or_generate().unwrap(), rotated);
+
+        std::fs::write(&file.path, "not a certificate").unwrap();
+        assert!(file.load_or_generate().is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
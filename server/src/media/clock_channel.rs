@@ -0,0 +1,364 @@
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc};
+use tracing::debug;
+use uuid::Uuid;
+use webrtc::{
+    data_channel::{data_channel_message::DataChannelMessage, data_channel_state::RTCDataChannelState, RTCDataChannel},
+    peer_connection::RTCPeerConnection,
+};
+
+use super::{EgressCounter, WebRtcServer};
+use crate::{
+    clock::{ClockManager, ClockSample, ClockSync},
+    protocol::get_current_time,
+};
+
+/// Label of the unordered, unretransmitted clock sync channel opened on
+/// every client's peer connection
+pub const CLOCK_CHANNEL_LABEL: &str = "clock";
+
+/// Capability announced in the hello response so clients sync their clock
+/// over the `clock` channel rather than the WebSocket once it is open
+pub const CLOCK_CHANNEL_CAPABILITY: &str = "clock_sync:datachannel";
+
+/// Requests answered once the channel opens, if they arrive before it does
+const MAX_HELD_REQUESTS: usize = 8;
+
+/// Round trips kept per path when comparing them
+const RTT_WINDOW: usize = 16;
+
+const REQUEST: u8 = 1;
+const RESPONSE: u8 = 2;
+const REPORT: u8 = 3;
+
+/// Path a clock sync exchange took
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPath {
+    WebSocket = 0,
+    DataChannel = 1,
+}
+
+/// Binary message on the `clock` channel, little-endian
+///
+/// - request: `u8 1 | u32 sequence | f64 t1`
+/// - response: `u8 2 | u32 sequence | f64 t1 | f64 t2 | f64 t3`
+/// - report: `u8 3 | u8 path | f64 t1 | f64 t2 | f64 t3 | f64 t4`
+///
+/// A report hands the server a completed exchange, from either path, so
+/// that it can follow the client's clock too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockPacket {
+    Request { sequence: u32, t1: f64 },
+    Response { sequence: u32, t1: f64, t2: f64, t3: f64 },
+    Report { path: SyncPath, t1: f64, t2: f64, t3: f64, t4: f64 },
+}
+
+impl ClockPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(34);
+        let times = match *self {
+            Self::Request { sequence, t1 } => {
+                out.push(REQUEST);
+                out.extend(sequence.to_le_bytes());
+                vec![t1]
+            }
+            Self::Response { sequence, t1, t2, t3 } => {
+                out.push(RESPONSE);
+                out.extend(sequence.to_le_bytes());
+                vec![t1, t2, t3]
+            }
+            Self::Report { path, t1, t2, t3, t4 } => {
+                out.extend([REPORT, path as u8]);
+                vec![t1, t2, t3, t4]
+            }
+        };
+        for time in times {
+            out.extend(time.to_le_bytes());
+        }
+        out
+    }
+
+    /// Parse a packet, or `None` if it is malformed
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (&kind, rest) = data.split_first()?;
+        let sequence = || Some(u32::from_le_bytes(rest.get(..4)?.try_into().ok()?));
+        let times = |from: usize, count: usize| -> Option<Vec<f64>> {
+            let bytes = rest.get(from..)?;
+            if bytes.len() != count * 8 {
+                return None;
+            }
+            Some(bytes.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap())).collect())
+        };
+        match kind {
+            REQUEST => {
+                let t = times(4, 1)?;
+                Some(Self::Request { sequence: sequence()?, t1: t[0] })
+            }
+            RESPONSE => {
+                let t = times(4, 3)?;
+                Some(Self::Response { sequence: sequence()?, t1: t[0], t2: t[1], t3: t[2] })
+            }
+            REPORT => {
+                let path = match rest.first()? {
+                    0 => SyncPath::WebSocket,
+                    1 => SyncPath::DataChannel,
+                    _ => return None,
+                };
+                let t = times(1, 4)?;
+                Some(Self::Report { path, t1: t[0], t2: t[1], t3: t[2], t4: t[3] })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Lowest recent round trip of each sync path
+#[derive(Default)]
+struct PathRtts {
+    websocket: VecDeque<f64>,
+    data_channel: VecDeque<f64>,
+}
+
+impl PathRtts {
+    fn record(&mut self, path: SyncPath, rtt: f64) {
+        let window = match path {
+            SyncPath::WebSocket => &mut self.websocket,
+            SyncPath::DataChannel => &mut self.data_channel,
+        };
+        if window.len() == RTT_WINDOW {
+            window.pop_front();
+        }
+        window.push_back(rtt);
+    }
+
+    /// Best recent round trips over the data channel and the WebSocket
+    fn best(&self) -> Option<(f64, f64)> {
+        let min = |window: &VecDeque<f64>| window.iter().copied().reduce(f64::min);
+        Some((min(&self.data_channel)?, min(&self.websocket)?))
+    }
+}
+
+/// Server end of a client's `clock` channel
+pub struct ClockEndpoint {
+    client_id: Uuid,
+    clock_manager: Arc<ClockManager>,
+
+    /// Egress of the client's peer connection, counting replies
+    egress: Arc<EgressCounter>,
+
+    /// Sequence, t1 and t2 of requests that arrived before the channel
+    /// had opened on this side
+    held: Mutex<Vec<(u32, f64, f64)>>,
+    rtts: Mutex<PathRtts>,
+}
+
+impl ClockEndpoint {
+    pub fn new(client_id: Uuid, clock_manager: Arc<ClockManager>, egress: Arc<EgressCounter>) -> Self {
+        Self {
+            client_id,
+            clock_manager,
+            egress,
+            held: Mutex::new(Vec::new()),
+            rtts: Mutex::new(PathRtts::default()),
+        }
+    }
+
+    /// Answer a request received at `t2`
+    ///
+    /// The client may see its end open, and send, before this end has;
+    /// such requests are held and answered by `answer_held` instead of
+    /// failing to send.
+    pub fn answer(&self, sequence: u32, t1: f64, t2: f64, open: bool) -> Option<ClockPacket> {
+        if !open {
+            let mut held = self.held.lock();
+            if held.len() < MAX_HELD_REQUESTS {
+                held.push((sequence, t1, t2));
+            }
+            return None;
+        }
+        Some(ClockPacket::Response { sequence, t1, t2, t3: get_current_time() })
+    }
+
+    /// Answer the requests held until the channel opened
+    ///
+    /// `t3` is stamped now, so the wait is left out of the round trip.
+    pub fn answer_held(&self) -> Vec<ClockPacket> {
+        let held = std::mem::take(&mut *self.held.lock());
+        held.into_iter()
+            .map(|(sequence, t1, t2)| ClockPacket::Response { sequence, t1, t2, t3: get_current_time() })
+            .collect()
+    }
+
+    /// Take in an exchange the client completed
+    ///
+    /// Data channel samples go to the clock manager under the client's id,
+    /// with the offset of the client's clock from the server's. WebSocket
+    /// ones are only kept to compare the round trips of both paths.
+    pub async fn report(&self, path: SyncPath, t1: f64, t2: f64, t3: f64, t4: f64) -> Result<()> {
+        let sample = ClockSync::calculate_offset(t1, t2, t3, t4);
+        if !(sample.rtt.is_finite() && sample.rtt >= 0.0 && sample.offset.is_finite()) {
+            anyhow::bail!("Impossible clock sync exchange");
+        }
+
+        let best = {
+            let mut rtts = self.rtts.lock();
+            rtts.record(path, sample.rtt);
+            rtts.best()
+        };
+        if let (SyncPath::DataChannel, Some((data_channel, websocket))) = (path, best) {
+            debug!(
+                "Clock sync for {}: data channel rtt={:.3}ms, WebSocket rtt={:.3}ms ({:.3}ms better)",
+                self.client_id,
+                data_channel * 1000.0,
+                websocket * 1000.0,
+                (websocket - data_channel) * 1000.0
+            );
+        }
+
+        if path == SyncPath::DataChannel {
+            let sample = ClockSample {
+                offset: -sample.offset,
+                ..sample
+            };
+            self.clock_manager.add_sample(self.client_id, sample).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Open the `clock` channel on a client's peer connection
+///
+/// Like the audio track, it has to be added before the offer is created.
+/// Requests are answered from the message handler itself: `t2` is taken
+/// as the message is handed over and `t3` right before the reply is sent.
+pub async fn open(peer_connection: &Arc<RTCPeerConnection>, endpoint: Arc<ClockEndpoint>) -> Result<Arc<RTCDataChannel>> {
+    let channel = WebRtcServer::create_data_channel(peer_connection, CLOCK_CHANNEL_LABEL, false).await?;
+
+    // Held weakly, as the channel owns its handlers
+    let weak = Arc::downgrade(&channel);
+    let handler_endpoint = endpoint.clone();
+    channel.on_message(Box::new(move |message: DataChannelMessage| {
+        let t2 = get_current_time();
+        let channel = weak.clone();
+        let endpoint = handler_endpoint.clone();
+        Box::pin(async move {
+            let Some(channel) = channel.upgrade() else {
+                return;
+            };
+            match ClockPacket::decode(&message.data) {
+                Some(ClockPacket::Request { sequence, t1 }) => {
+                    let open = channel.ready_state() == RTCDataChannelState::Open;
+                    if let Some(response) = endpoint.answer(sequence, t1, t2, open) {
+                        send(&channel, response, &endpoint).await;
+                    }
+                }
+                Some(ClockPacket::Report { path, t1, t2, t3, t4 }) => {
+                    if let Err(e) = endpoint.report(path, t1, t2, t3, t4).await {
+                        debug!("Ignoring clock report from {}: {}", endpoint.client_id, e);
+                    }
+                }
+                _ => debug!("Ignoring malformed clock packet from {}", endpoint.client_id),
+            }
+        })
+    }));
+
+    let weak = Arc::downgrade(&channel);
+    channel.on_open(Box::new(move || {
+        Box::pin(async move {
+            let Some(channel) = weak.upgrade() else {
+                return;
+            };
+            for response in endpoint.answer_held() {
+                send(&channel, response, &endpoint).await;
+            }
+        })
+    }));
+    Ok(channel)
+}
+
+async fn send(channel: &RTCDataChannel, packet: ClockPacket, endpoint: &ClockEndpoint) {
+    match channel.send(&Bytes::from(packet.encode())).await {
+        Ok(bytes) => endpoint.egress.record(1, bytes),
+        Err(e) => debug!("Failed to answer clock sync from {}: {}", endpoint.client_id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_clock_packets_round_trip() {
+        let packets = [
+            ClockPacket::Request { sequence: 7, t1: 1.5 },
+            ClockPacket::Response { sequence: u32::MAX, t1: 1.5, t2: 2.25, t3: 2.5 },
+            ClockPacket::Report { path: SyncPath::WebSocket, t1: 1.0, t2: 2.0, t3: 3.0, t4: 4.0 },
+        ];
+        for packet in packets {
+            let encoded = packet.encode();
+            assert_eq!(ClockPacket::decode(&encoded), Some(packet));
+            assert_eq!(ClockPacket::decode(&encoded[..encoded.len() - 1]), None);
+        }
+        assert_eq!(ClockPacket::Request { sequence: 0, t1: 0.0 }.encode().len(), 13);
+        assert_eq!(ClockPacket::decode(&[]), None);
+        assert_eq!(ClockPacket::decode(&[9, 0, 0, 0, 0]), None);
+    }
+
+    #[tokio::test]
+    async fn test_requests_before_open_are_answered_once_open() {
+        let clock = Arc::new(ClockManager::new());
+        let endpoint = ClockEndpoint::new(Uuid::new_v4(), clock, Arc::default());
+
+        // Nothing can be sent until this end opens
+        for sequence in 0..MAX_HELD_REQUESTS as u32 + 2 {
+            assert_eq!(endpoint.answer(sequence, 10.0, 20.0, false), None);
+        }
+        let held = endpoint.answer_held();
+        assert_eq!(held.len(), MAX_HELD_REQUESTS);
+        match held[0] {
+            ClockPacket::Response { sequence, t1, t2, t3 } => {
+                assert_eq!((sequence, t1, t2), (0, 10.0, 20.0));
+                assert!(t3 >= t2);
+            }
+            other => panic!("Unexpected packet: {:?}", other),
+        }
+        assert!(endpoint.answer_held().is_empty());
+
+        let t2 = get_current_time();
+        assert!(matches!(
+            endpoint.answer(1, 10.0, t2, true),
+            Some(ClockPacket::Response { sequence: 1, t3, .. }) if t3 >= t2
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_data_channel_reports_feed_the_clock_manager() {
+        let clock = Arc::new(ClockManager::new());
+        tokio::spawn(clock.clone().run());
+        let client_id = Uuid::new_v4();
+        let endpoint = ClockEndpoint::new(client_id, clock.clone(), Arc::default());
+
+        // WebSocket exchanges are compared but not fed
+        endpoint.report(SyncPath::WebSocket, 0.0, 0.5, 0.5, 0.04).await.unwrap();
+        assert!(endpoint.report(SyncPath::DataChannel, 0.0, 0.5, 0.5, -1.0).await.is_err());
+        // The client's clock runs half a second behind the server's
+        endpoint.report(SyncPath::DataChannel, 0.0, 0.505, 0.505, 0.01).await.unwrap();
+        assert_eq!(endpoint.rtts.lock().best(), Some((0.01, 0.04)));
+
+        let mut stats = None;
+        for _ in 0..100 {
+            stats = clock.get_peer_stats(&client_id).await;
+            if stats.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stats = stats.expect("sample reaches the clock manager");
+        assert_eq!(stats.sample_count, 1);
+        assert!((stats.rtt - 0.01).abs() < 1e-9);
+        assert!(stats.offset < 0.0);
+    }
+}
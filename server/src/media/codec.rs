@@ -0,0 +1,124 @@
+use std::collections::BTreeSet;
+
+use super::WebRtcCodec;
+
+/// Prefix of the hello capabilities naming a codec the client decodes, as
+/// in `codec:opus`
+pub const CODEC_CAPABILITY_PREFIX: &str = "codec:";
+
+/// Stream codecs that can be transcoded for a client, and the codecs they
+/// can be transcoded to
+const TRANSCODES: [(&str, &str); 2] = [("pcm16", "pcmu"), ("pcm16", "pcma")];
+
+/// Media time a stream's broadcast channel holds for a subscriber that
+/// falls behind, in seconds
+const CHANNEL_BUFFER_SECS: f64 = 10.0;
+
+/// Frame rate assumed of an audio stream: 20ms frames
+const AUDIO_FRAME_RATE: f64 = 50.0;
+
+/// Frame rate assumed of a video stream, the highest expected
+const VIDEO_FRAME_RATE: f64 = 120.0;
+
+/// Bounds of a derived channel capacity, in frames
+const MIN_CHANNEL_CAPACITY: usize = 16;
+const MAX_CHANNEL_CAPACITY: usize = 4096;
+
+/// Broadcast channel slots for a stream of `codec` whose frames last
+/// `frame_duration` seconds, if known
+///
+/// The channel holds [`CHANNEL_BUFFER_SECS`] of frames; without a frame
+/// duration, video codecs are taken to run at up to 120 frames a second
+/// and everything else at 50. Each slot keeps its frame alive until every
+/// subscriber has received it or it is overwritten, so a stream with a
+/// lagging subscriber holds up to this many frames in memory.
+pub fn channel_capacity(codec: &str, frame_duration: Option<f64>) -> usize {
+    let frame_rate = match frame_duration {
+        Some(duration) if duration > 0.0 => 1.0 / duration,
+        _ if is_video_codec(codec) => VIDEO_FRAME_RATE,
+        _ => AUDIO_FRAME_RATE,
+    };
+    ((CHANNEL_BUFFER_SECS * frame_rate).ceil() as usize).clamp(MIN_CHANNEL_CAPACITY, MAX_CHANNEL_CAPACITY)
+}
+
+/// Whether `codec` is a video codec; names are case-insensitive
+pub fn is_video_codec(codec: &str) -> bool {
+    codec.parse::<WebRtcCodec>().is_ok_and(|codec| !codec.is_audio())
+}
+
+/// Whether a stream of `source` codec can be transcoded to `target`;
+/// names are case-insensitive
+pub fn can_transcode(source: &str, target: &str) -> bool {
+    TRANSCODES
+        .iter()
+        .any(|(from, to)| from.eq_ignore_ascii_case(source) && to.eq_ignore_ascii_case(target))
+}
+
+/// Codecs a client can decode
+///
+/// A client naming no codecs predates codec capabilities and is taken to
+/// decode any.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodecSupport {
+    codecs: Option<BTreeSet<String>>,
+}
+
+impl CodecSupport {
+    /// Codecs named by `codec:` capabilities; names are case-insensitive
+    pub fn from_capabilities(capabilities: &[String]) -> Self {
+        let codecs: BTreeSet<String> = capabilities
+            .iter()
+            .filter_map(|capability| capability.strip_prefix(CODEC_CAPABILITY_PREFIX))
+            .map(|codec| codec.trim().to_ascii_lowercase())
+            .filter(|codec| !codec.is_empty())
+            .collect();
+        Self {
+            codecs: (!codecs.is_empty()).then_some(codecs),
+        }
+    }
+
+    /// Whether the client can decode a stream of `codec`
+    pub fn decodes(&self, codec: &str) -> bool {
+        self.codecs
+            .as_ref()
+            .is_none_or(|codecs| codecs.contains(&codec.to_ascii_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_capabilities_restrict_decodable_codecs() {
+        let capabilities = |names: &[&str]| names.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        let opus_only = CodecSupport::from_capabilities(&capabilities(&["audio", "codec:Opus"]));
+        assert!(opus_only.decodes("opus"));
+        assert!(!opus_only.decodes("h264"));
+
+        // Without codec capabilities anything goes
+        let legacy = CodecSupport::from_capabilities(&capabilities(&["audio", "codec:"]));
+        assert_eq!(legacy, CodecSupport::default());
+        assert!(legacy.decodes("h264"));
+    }
+
+    #[test]
+    fn test_only_pcm_transcodes_to_g711() {
+        assert!(can_transcode("pcm16", "PCMU"));
+        assert!(can_transcode("PCM16", "pcma"));
+        assert!(!can_transcode("opus", "pcmu"));
+        assert!(!can_transcode("h264", "vp8"));
+    }
+
+    #[test]
+    fn test_channel_capacity_covers_ten_seconds_of_frames() {
+        assert_eq!(channel_capacity("opus", None), 500);
+        assert_eq!(channel_capacity("VP8", None), 1200);
+        assert_eq!(channel_capacity("h264", Some(1.0 / 30.0)), 300);
+        assert_eq!(channel_capacity("pcm16", Some(0.01)), 1000);
+        assert_eq!(channel_capacity("opus", Some(0.0)), 500);
+        assert_eq!(channel_capacity("h264", Some(0.001)), MAX_CHANNEL_CAPACITY);
+        assert_eq!(channel_capacity("opus", Some(5.0)), MIN_CHANNEL_CAPACITY);
+    }
+}
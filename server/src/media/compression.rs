@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use std::{collections::HashMap, io::Read, sync::Arc};
+
+use crate::protocol::{Compression, MediaDataMessage};
+
+/// Codecs whose frames are already entropy coded, so transport
+/// compression would only cost time
+#[cfg(test)]
+const PRECOMPRESSED_CODECS: [&str; 4] = ["opus", "aac", "h264", "vp8"];
+
+/// Lossless transport compression of `media_data` payloads
+pub trait FrameCompressor: Send + Sync {
+    #[cfg(test)]
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Restore a payload, failing rather than producing more than
+    /// `max_size` bytes
+    fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>>;
+}
+
+/// zstd, at its default level
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZstdCompressor;
+
+impl FrameCompressor for ZstdCompressor {
+    #[cfg(test)]
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+    }
+
+    fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        // Stream the frame so a payload claiming a huge size is cut off at
+        // the limit instead of allocated up front
+        let mut out = Vec::new();
+        zstd::stream::read::Decoder::new(data)?
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut out)?;
+        if out.len() > max_size {
+            anyhow::bail!("Payload exceeds {} bytes", max_size);
+        }
+        Ok(out)
+    }
+}
+
+/// Transport compression algorithms this server can handle
+///
+/// An algorithm is only advertised to clients, as a `compression:<name>`
+/// capability in the hello response, once a compressor for it has been
+/// registered; the server registers `ZstdCompressor` at startup.
+#[derive(Clone, Default)]
+pub struct Compressors {
+    by_algorithm: HashMap<Compression, Arc<dyn FrameCompressor>>,
+}
+
+impl Compressors {
+    pub fn register(&mut self, algorithm: Compression, compressor: Arc<dyn FrameCompressor>) {
+        self.by_algorithm.insert(algorithm, compressor);
+    }
+
+    /// Capabilities announcing the registered algorithms, sorted
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities: Vec<_> = self.by_algorithm.keys().map(|a| a.capability()).collect();
+        capabilities.sort();
+        capabilities
+    }
+
+    /// First registered algorithm the peer advertised in `capabilities`
+    #[cfg(test)]
+    pub fn negotiate(&self, capabilities: &[String]) -> Option<Compression> {
+        Compression::ALL.into_iter().find(|algorithm| {
+            self.by_algorithm.contains_key(algorithm) && capabilities.contains(&algorithm.capability())
+        })
+    }
+
+    /// Compress a chunk's payload with `algorithm` and flag it, unless its
+    /// codec is already compressed or compression does not make it smaller
+    ///
+    /// Returns whether the chunk was compressed.
+    #[cfg(test)]
+    pub fn compress_chunk(&self, algorithm: Compression, chunk: &mut MediaDataMessage) -> Result<bool> {
+        if chunk.compression.is_some() || !benefits_from_compression(&chunk.codec) {
+            return Ok(false);
+        }
+        let compressor = self.compressor(algorithm)?;
+        let compressed = compressor.compress(&chunk.data)?;
+        if compressed.len() >= chunk.data.len() {
+            return Ok(false);
+        }
+        chunk.data = compressed;
+        chunk.compression = Some(algorithm);
+        Ok(true)
+    }
+
+    /// Restore a chunk's payload in place if it was compressed
+    pub fn decompress_chunk(&self, chunk: &mut MediaDataMessage, max_size: usize) -> Result<()> {
+        let Some(algorithm) = chunk.compression else {
+            return Ok(());
+        };
+        chunk.data = self
+            .compressor(algorithm)?
+            .decompress(&chunk.data, max_size)
+            .with_context(|| format!("Corrupt {} chunk {}", algorithm.name(), chunk.chunk_index))?;
+        chunk.compression = None;
+        Ok(())
+    }
+
+    fn compressor(&self, algorithm: Compression) -> Result<&Arc<dyn FrameCompressor>> {
+        self.by_algorithm
+            .get(&algorithm)
+            .with_context(|| format!("Unsupported compression: {}", algorithm.name()))
+    }
+}
+
+/// Whether transport compression can shrink frames of `codec`
+#[cfg(test)]
+pub fn benefits_from_compression(codec: &str) -> bool {
+    !PRECOMPRESSED_CODECS.contains(&codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MessageHeader;
+    use uuid::Uuid;
+
+    /// Run-length coding, standing in for a real compressor
+    struct RunLength;
+
+    impl FrameCompressor for RunLength {
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for run in data.chunk_by(|a, b| a == b) {
+                for part in run.chunks(u8::MAX as usize) {
+                    out.extend([part.len() as u8, part[0]]);
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for pair in data.chunks(2) {
+                let [count, byte] = pair else {
+                    anyhow::bail!("Truncated run");
+                };
+                if out.len() + *count as usize > max_size {
+                    anyhow::bail!("Payload exceeds {} bytes", max_size);
+                }
+                out.extend(std::iter::repeat_n(*byte, *count as usize));
+            }
+            Ok(out)
+        }
+    }
+
+    fn chunk(codec: &str, data: Vec<u8>) -> MediaDataMessage {
+        MediaDataMessage {
+            header: MessageHeader::new(Uuid::new_v4(), 1),
+            track_id: "live".into(),
+            chunk_index: 0,
+            timestamp: 100.0,
+            duration: 0.02,
+            data,
+            codec: codec.into(),
+            is_keyframe: false,
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_zstd_round_trips_and_refuses_oversized_payloads() {
+        let mut pcm = vec![0u8; 1920];
+        pcm[800..840].iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let compressed = ZstdCompressor.compress(&pcm).unwrap();
+        assert!(compressed.len() < pcm.len());
+        assert_eq!(ZstdCompressor.decompress(&compressed, pcm.len()).unwrap(), pcm);
+        assert!(ZstdCompressor.decompress(&compressed, pcm.len() - 1).is_err());
+        assert!(ZstdCompressor.decompress(&pcm[..64], 4096).is_err());
+    }
+
+    #[test]
+    fn test_compressed_chunk_round_trips_byte_for_byte() {
+        let mut compressors = Compressors::default();
+        assert_eq!(compressors.negotiate(&["compression:zstd".into()]), None);
+        compressors.register(Compression::Zstd, Arc::new(RunLength));
+        assert_eq!(compressors.capabilities(), ["compression:zstd"]);
+        assert_eq!(compressors.negotiate(&["compression:zstd".into()]), Some(Compression::Zstd));
+        assert_eq!(compressors.negotiate(&["audio".into()]), None);
+
+        // A quiet pcm16 frame: long runs of silence around a short burst
+        let mut pcm = vec![0u8; 1920];
+        pcm[800..840].iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let original = chunk("pcm16", pcm.clone());
+
+        let mut sent = original.clone();
+        assert!(compressors.compress_chunk(Compression::Zstd, &mut sent).unwrap());
+        assert_eq!(sent.compression, Some(Compression::Zstd));
+        assert!(sent.data.len() < pcm.len());
+
+        // The flag survives the JSON edge and tells the receiver to restore it
+        let mut received: MediaDataMessage =
+            serde_json::from_str(&serde_json::to_string(&sent).unwrap()).unwrap();
+        compressors.decompress_chunk(&mut received, pcm.len()).unwrap();
+        assert_eq!(received.data, original.data);
+        assert_eq!(received.compression, None);
+
+        // Payloads over the limit are refused rather than expanded
+        let mut bomb = sent.clone();
+        assert!(compressors.decompress_chunk(&mut bomb, pcm.len() - 1).is_err());
+
+        // Already compressed codecs and incompressible data are left alone
+        let mut opus = chunk("opus", vec![0; 200]);
+        assert!(!compressors.compress_chunk(Compression::Zstd, &mut opus).unwrap());
+        assert_eq!((opus.data.len(), opus.compression), (200, None));
+        let noise: Vec<u8> = (0..=255).collect();
+        let mut noisy = chunk("pcm16", noise.clone());
+        assert!(!compressors.compress_chunk(Compression::Zstd, &mut noisy).unwrap());
+        assert_eq!(noisy.data, noise);
+
+        // Chunks flagged with an algorithm nobody registered are refused
+        let mut unknown = sent;
+        assert!(Compressors::default().decompress_chunk(&mut unknown, 4096).is_err());
+    }
+}
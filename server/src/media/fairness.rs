@@ -0,0 +1,85 @@
+use super::codec::is_video_codec;
+
+/// Scheduling class of a subscription's forwarding task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwarderClass {
+    Audio,
+    Video,
+}
+
+impl ForwarderClass {
+    /// Class of the forwarder of a stream of `codec`; anything not video
+    /// is treated as audio
+    pub fn for_codec(codec: &str) -> Self {
+        if is_video_codec(codec) {
+            Self::Video
+        } else {
+            Self::Audio
+        }
+    }
+}
+
+/// Frames a forwarding task may handle back to back before yielding
+///
+/// Every subscription is forwarded by its own task, and a task whose
+/// channel always has another frame waiting only yields when the runtime's
+/// cooperative budget runs out. Video forwarders yield after `budget`
+/// frames in a row instead, so the frames of a high-rate video stream
+/// cannot hold up the audio forwarders, which never yield by choice and
+/// so take precedence. A budget of zero never yields.
+#[derive(Debug)]
+pub struct YieldBudget {
+    budget: usize,
+    spent: usize,
+}
+
+impl YieldBudget {
+    pub fn new(class: ForwarderClass, video_budget: usize) -> Self {
+        let budget = match class {
+            ForwarderClass::Audio => 0,
+            ForwarderClass::Video => video_budget,
+        };
+        Self { budget, spent: 0 }
+    }
+
+    /// Count a forwarded frame, given the frames still waiting for the
+    /// task, and whether it should now yield
+    ///
+    /// The count starts over whenever nothing is waiting, as the task then
+    /// waits for its next frame anyway.
+    pub fn spend(&mut self, waiting: usize) -> bool {
+        if self.budget == 0 || waiting == 0 {
+            self.spent = 0;
+            return false;
+        }
+        self.spent += 1;
+        if self.spent < self.budget {
+            return false;
+        }
+        self.spent = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_video_forwarders_yield_during_bursts() {
+        assert_eq!(ForwarderClass::for_codec("H264"), ForwarderClass::Video);
+        assert_eq!(ForwarderClass::for_codec("pcm16"), ForwarderClass::Audio);
+
+        let mut video = YieldBudget::new(ForwarderClass::Video, 3);
+        let yields: Vec<bool> = (0..7).map(|_| video.spend(10)).collect();
+        assert_eq!(yields, [false, false, true, false, false, true, false]);
+        // A drained channel starts the burst over
+        assert!(!video.spend(0));
+        assert!(!video.spend(10) && !video.spend(10) && video.spend(10));
+
+        let mut audio = YieldBudget::new(ForwarderClass::Audio, 3);
+        assert!((0..10).all(|_| !audio.spend(10)));
+        let mut unlimited = YieldBudget::new(ForwarderClass::Video, 0);
+        assert!((0..10).all(|_| !unlimited.spend(10)));
+    }
+}
@@ -0,0 +1,272 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::rendition::QualityTier;
+
+/// Consecutive quiet reports before a client's FEC is switched off again
+const FEC_OFF_REPORTS: u32 = 3;
+
+/// Retransmission and forward error correction offered to clients on
+/// lossy networks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossRecoveryConfig {
+    /// Offer generic NACK on video, so clients ask for lost packets again
+    pub video_nack: bool,
+
+    /// Offer Opus in-band FEC (`useinbandfec=1`)
+    pub opus_fec: bool,
+
+    /// Offer Opus discontinuous transmission (`usedtx=1`)
+    pub opus_dtx: bool,
+
+    /// Packet loss a client reports, in percent, at which the audio it
+    /// receives starts carrying FEC
+    pub fec_loss_threshold_percent: f64,
+}
+
+impl Default for LossRecoveryConfig {
+    fn default() -> Self {
+        Self {
+            video_nack: true,
+            opus_fec: true,
+            opus_dtx: false,
+            // Where the network quality drops to Poor
+            fec_loss_threshold_percent: 1.0,
+        }
+    }
+}
+
+impl LossRecoveryConfig {
+    /// Format parameters of the Opus codec offered
+    pub fn opus_fmtp_line(&self) -> String {
+        let mut fmtp = String::from("minptime=10");
+        if self.opus_fec {
+            fmtp.push_str(";useinbandfec=1");
+        }
+        if self.opus_dtx {
+            fmtp.push_str(";usedtx=1");
+        }
+        fmtp
+    }
+}
+
+/// Whether Opus format parameters ask for in-band FEC
+///
+/// In an answer, `useinbandfec=1` says the client can decode FEC and wants
+/// to receive it.
+pub fn fmtp_requests_fec(fmtp: &str) -> bool {
+    fmtp.split(';').any(|parameter| inband_fec_value(parameter) == Some("1"))
+}
+
+/// Value of a `useinbandfec` format parameter
+fn inband_fec_value(parameter: &str) -> Option<&str> {
+    let (key, value) = parameter.split_once('=')?;
+    key.trim().eq_ignore_ascii_case("useinbandfec").then(|| value.trim())
+}
+
+/// An answer's SDP with `useinbandfec=0` format parameters left out
+///
+/// Leaving the parameter out means the same, but the WebRTC stack takes an
+/// explicit `useinbandfec=0` to conflict with the `useinbandfec=1` offered
+/// and would then not negotiate Opus at all.
+pub fn omit_declined_fec(sdp: &str) -> String {
+    sdp.split_inclusive('\n')
+        .map(|line| {
+            let Some(fmtp) = line.strip_prefix("a=fmtp:") else {
+                return line.to_string();
+            };
+            let (content, ending) = fmtp.split_at(fmtp.trim_end_matches(['\r', '\n']).len());
+            let Some((payload_type, parameters)) = content.split_once(' ') else {
+                return line.to_string();
+            };
+            let kept: Vec<&str> = parameters
+                .split(';')
+                .filter(|parameter| inband_fec_value(parameter) != Some("0"))
+                .collect();
+            format!("a=fmtp:{} {}{}", payload_type, kept.join(";"), ending)
+        })
+        .collect()
+}
+
+/// Decides from a client's loss reports whether the audio it receives
+/// should carry FEC
+///
+/// FEC switches on with the first report reaching the threshold, and off
+/// only after `FEC_OFF_REPORTS` reports in a row below half of it, so loss
+/// hovering around the threshold does not flap it.
+#[derive(Debug, Clone)]
+pub struct FecController {
+    threshold_percent: f64,
+    active: bool,
+    quiet_reports: u32,
+    loss_percent: f64,
+}
+
+impl FecController {
+    pub fn new(threshold_percent: f64) -> Self {
+        Self {
+            threshold_percent,
+            active: false,
+            quiet_reports: 0,
+            loss_percent: 0.0,
+        }
+    }
+
+    /// Take in the loss of a report, returning whether FEC switched on or
+    /// off
+    pub fn update(&mut self, loss_percent: f64) -> bool {
+        self.loss_percent = loss_percent;
+        if loss_percent >= self.threshold_percent {
+            self.quiet_reports = 0;
+            if !self.active {
+                self.active = true;
+                return true;
+            }
+            return false;
+        }
+        if !self.active {
+            return false;
+        }
+        if loss_percent < self.threshold_percent / 2.0 {
+            self.quiet_reports += 1;
+        } else {
+            self.quiet_reports = 0;
+        }
+        if self.quiet_reports < FEC_OFF_REPORTS {
+            return false;
+        }
+        self.active = false;
+        self.quiet_reports = 0;
+        true
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Packet loss an encoder should expect, in whole percent, while FEC is
+    /// on
+    pub fn expected_loss(&self) -> Option<u8> {
+        self.active.then(|| self.loss_percent.ceil().clamp(1.0, 100.0) as u8)
+    }
+}
+
+/// FEC of a client's audio, for its statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FecStatus {
+    /// The client's answer accepted Opus with in-band FEC
+    pub negotiated: bool,
+
+    /// The client's loss has switched FEC on
+    pub active: bool,
+
+    /// Loss the encoders are told to expect for the client, in percent
+    pub expected_loss_percent: Option<u8>,
+}
+
+/// Loss the clients receiving each quality tier expect, for the tier
+/// encoders to protect their frames against
+///
+/// Each tier's encoder is shared by every client receiving the tier, so it
+/// follows the worst of them.
+#[derive(Debug, Default)]
+pub struct LossFeedback {
+    clients: parking_lot::Mutex<HashMap<Uuid, (QualityTier, u8)>>,
+}
+
+impl LossFeedback {
+    /// Record the tier a client receives and the loss it expects; none
+    /// once its FEC is off
+    pub fn set(&self, client_id: Uuid, tier: QualityTier, expected_loss: Option<u8>) {
+        let mut clients = self.clients.lock();
+        match expected_loss {
+            Some(loss) => clients.insert(client_id, (tier, loss)),
+            None => clients.remove(&client_id),
+        };
+    }
+
+    pub fn remove(&self, client_id: Uuid) {
+        self.clients.lock().remove(&client_id);
+    }
+
+    /// Loss the encoder of `tier` should expect; none without FEC
+    pub fn expected_loss(&self, tier: QualityTier) -> Option<u8> {
+        self.clients
+            .lock()
+            .values()
+            .filter(|(client_tier, _)| *client_tier == tier)
+            .map(|(_, loss)| *loss)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opus_fmtp_offers_and_detects_fec() {
+        let config = LossRecoveryConfig::default();
+        assert_eq!(config.opus_fmtp_line(), "minptime=10;useinbandfec=1");
+        let dtx = LossRecoveryConfig { opus_dtx: true, ..config };
+        assert_eq!(dtx.opus_fmtp_line(), "minptime=10;useinbandfec=1;usedtx=1");
+        let plain = LossRecoveryConfig { opus_fec: false, ..config };
+        assert_eq!(plain.opus_fmtp_line(), "minptime=10");
+
+        assert!(fmtp_requests_fec("minptime=10; useinbandfec=1"));
+        assert!(fmtp_requests_fec("UseInbandFec=1;stereo=1"));
+        assert!(!fmtp_requests_fec("minptime=10;useinbandfec=0"));
+        assert!(!fmtp_requests_fec("minptime=10"));
+        assert!(!fmtp_requests_fec(""));
+
+        let answer = "m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=fmtp:111 minptime=10;useinbandfec=0\r\na=fmtp:96 x=1\r\n";
+        assert_eq!(
+            omit_declined_fec(answer),
+            "m=audio 9 UDP/TLS/RTP/SAVPF 111\r\na=fmtp:111 minptime=10\r\na=fmtp:96 x=1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_fec_switches_on_at_the_threshold_and_off_after_quiet_reports() {
+        let mut fec = FecController::new(2.0);
+        assert!(!fec.update(1.9));
+        assert_eq!(fec.expected_loss(), None);
+
+        assert!(fec.update(2.3));
+        assert!(fec.is_active());
+        assert_eq!(fec.expected_loss(), Some(3));
+
+        // Loss between half the threshold and it keeps FEC on, and resets
+        // the quiet count
+        assert!(!fec.update(0.5) && !fec.update(0.5));
+        assert!(!fec.update(1.5));
+        assert_eq!(fec.expected_loss(), Some(2));
+        assert!(!fec.update(0.0) && !fec.update(0.0));
+        assert!(fec.is_active());
+        assert_eq!(fec.expected_loss(), Some(1));
+
+        assert!(fec.update(0.0));
+        assert!(!fec.is_active());
+        assert_eq!(fec.expected_loss(), None);
+        assert!(!fec.update(0.0));
+    }
+
+    #[test]
+    fn test_tier_encoders_follow_the_worst_client() {
+        let feedback = LossFeedback::default();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        feedback.set(a, QualityTier::Low, Some(4));
+        feedback.set(b, QualityTier::Low, Some(9));
+        assert_eq!(feedback.expected_loss(QualityTier::Low), Some(9));
+        assert_eq!(feedback.expected_loss(QualityTier::High), None);
+
+        // A client moving to another tier leaves the one it was on
+        feedback.set(b, QualityTier::Medium, Some(9));
+        assert_eq!(feedback.expected_loss(QualityTier::Low), Some(4));
+        feedback.set(a, QualityTier::Low, None);
+        feedback.remove(b);
+        assert_eq!(feedback.expected_loss(QualityTier::Low), None);
+        assert_eq!(feedback.expected_loss(QualityTier::Medium), None);
+    }
+}
@@ -0,0 +1,113 @@
+use super::WebRtcCodec;
+
+/// Sample rate of G.711
+pub const G711_SAMPLE_RATE: u32 = 8_000;
+
+/// Largest magnitude μ-law encodes before clipping
+const ULAW_CLIP: i32 = 32_635;
+
+/// Added to μ-law magnitudes so every segment starts on a power of two
+const ULAW_BIAS: i32 = 0x84;
+
+/// Encode a frame of 16-bit little-endian PCM as G.711 for `codec`
+///
+/// Channels are mixed down to mono and the frame resampled to 8kHz by
+/// averaging the samples each output sample covers. Frames are converted
+/// on their own, so any frame of `n` samples at `sample_rate` gives
+/// `n * 8000 / sample_rate` bytes, rounded. Codecs other than PCMU and
+/// PCMA give nothing.
+pub fn encode(codec: WebRtcCodec, pcm: &[u8], sample_rate: u32, channels: u8) -> Vec<u8> {
+    let compand: fn(i16) -> u8 = match codec {
+        WebRtcCodec::Pcmu => linear_to_ulaw,
+        WebRtcCodec::Pcma => linear_to_alaw,
+        _ => return Vec::new(),
+    };
+    let channels = channels.max(1) as usize;
+    let mono: Vec<i32> = pcm
+        .chunks_exact(2 * channels)
+        .map(|frame| {
+            let sum: i32 = frame
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as i32)
+                .sum();
+            sum / channels as i32
+        })
+        .collect();
+    if mono.is_empty() || sample_rate == 0 {
+        return Vec::new();
+    }
+
+    let ratio = sample_rate as f64 / G711_SAMPLE_RATE as f64;
+    let count = ((mono.len() as f64 / ratio).round() as usize).max(1);
+    (0..count)
+        .map(|i| {
+            let start = ((i as f64 * ratio) as usize).min(mono.len() - 1);
+            let end = (((i + 1) as f64 * ratio) as usize).clamp(start + 1, mono.len());
+            let window = &mono[start..end];
+            let average = window.iter().sum::<i32>() / window.len() as i32;
+            compand(average as i16)
+        })
+        .collect()
+}
+
+/// G.711 μ-law code of a sample
+fn linear_to_ulaw(sample: i16) -> u8 {
+    let mut magnitude = sample as i32;
+    let sign = if magnitude < 0 {
+        magnitude = -magnitude;
+        0x80
+    } else {
+        0
+    };
+    let magnitude = magnitude.min(ULAW_CLIP) + ULAW_BIAS;
+    let exponent = (31 - magnitude.leading_zeros()) as i32 - 7;
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+/// G.711 A-law code of a sample
+fn linear_to_alaw(sample: i16) -> u8 {
+    let mut magnitude = sample as i32 >> 3;
+    let mask = if magnitude >= 0 {
+        0xd5
+    } else {
+        magnitude = -magnitude - 1;
+        0x55
+    };
+    let segment = (32 - (magnitude as u32 >> 5).leading_zeros()) as u8;
+    if segment >= 8 {
+        return 0x7f ^ mask;
+    }
+    let shift = if segment < 2 { 1 } else { segment };
+    let code = (segment << 4) | ((magnitude >> shift) & 0x0f) as u8;
+    code ^ mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_g711_companding_and_resampling() {
+        assert_eq!(linear_to_ulaw(0), 0xff);
+        assert_eq!(linear_to_ulaw(-1), 0x7f);
+        assert_eq!(linear_to_ulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_ulaw(i16::MIN), 0x00);
+        assert_eq!(linear_to_alaw(0), 0xd5);
+        assert_eq!(linear_to_alaw(-8), 0x55);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xaa);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2a);
+
+        // 20ms of 48kHz stereo becomes 20ms of 8kHz mono, the channels
+        // mixed down: opposite channels cancel out
+        let stereo: Vec<i16> = (0..960).flat_map(|_| [1000, -1000]).collect();
+        let encoded = encode(WebRtcCodec::Pcmu, &pcm(&stereo), 48_000, 2);
+        assert_eq!(encoded, vec![0xff; 160]);
+        assert_eq!(encode(WebRtcCodec::Pcma, &pcm(&[0; 441]), 44_100, 1).len(), 80);
+        assert!(encode(WebRtcCodec::Opus, &pcm(&[0; 960]), 48_000, 1).is_empty());
+    }
+}
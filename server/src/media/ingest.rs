@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use tracing::{debug, warn};
+
+use super::buffer::{FrameType, MediaFrame};
+
+/// Number of chunks held back while waiting for a missing one
+pub const REORDER_WINDOW: usize = 8;
+
+/// Frame type for a chunk, based on its codec
+pub fn frame_type(codec: &str, is_keyframe: bool) -> FrameType {
+    match codec {
+        "opus" | "pcm16" | "aac" => FrameType::Audio,
+        _ if is_keyframe => FrameType::VideoKeyframe,
+        _ => FrameType::Video,
+    }
+}
+
+/// Restores chunk order for a live stream fed by a producer client
+///
+/// Frames are released in `sequence` order. A missing chunk is waited for
+/// until `window` later chunks have arrived; it is then skipped and the gap
+/// logged. Chunks older than the last released one are discarded.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    track_id: String,
+    window: usize,
+    next_sequence: Option<u64>,
+    pending: BTreeMap<u64, MediaFrame>,
+}
+
+impl ReorderBuffer {
+    pub fn new(track_id: impl Into<String>, window: usize) -> Self {
+        Self {
+            track_id: track_id.into(),
+            window,
+            next_sequence: None,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Add a frame and return the frames that are now ready, in order
+    pub fn push(&mut self, frame: MediaFrame) -> Vec<MediaFrame> {
+        let next = *self.next_sequence.get_or_insert(frame.sequence);
+        if frame.sequence < next {
+            debug!(
+                "Discarding late chunk {} for {} (expected {})",
+                frame.sequence, self.track_id, next
+            );
+            return Vec::new();
+        }
+        self.pending.insert(frame.sequence, frame);
+
+        let mut ready = Vec::new();
+        loop {
+            let next = self.next_sequence.unwrap_or_default();
+            if let Some(frame) = self.pending.remove(&next) {
+                self.next_sequence = Some(next + 1);
+                ready.push(frame);
+                continue;
+            }
+
+            // Give up on the missing chunk once the window is full
+            if self.pending.len() <= self.window {
+                break;
+            }
+            let Some(&first) = self.pending.keys().next() else {
+                break;
+            };
+            warn!(
+                "Live stream {} lost chunks {}..{}",
+                self.track_id,
+                next,
+                first - 1
+            );
+            self.next_sequence = Some(first);
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::{sync::Arc, time::Duration};
+
+    fn frame(sequence: u64) -> MediaFrame {
+        MediaFrame {
+            data: Bytes::new(),
+            timestamp: sequence as f64 * 0.02,
+            duration: Duration::from_millis(20),
+            frame_type: FrameType::Audio,
+            sequence,
+            renditions: Arc::default(),
+        }
+    }
+
+    fn sequences(frames: Vec<MediaFrame>) -> Vec<u64> {
+        frames.iter().map(|f| f.sequence).collect()
+    }
+
+    #[test]
+    fn test_reorders_within_window_and_skips_gaps() {
+        let mut buffer = ReorderBuffer::new("live", 2);
+
+        assert_eq!(sequences(buffer.push(frame(0))), vec![0]);
+        assert!(buffer.push(frame(2)).is_empty());
+        assert_eq!(sequences(buffer.push(frame(1))), vec![1, 2]);
+
+        // Chunk 3 never arrives: released once the window overflows
+        assert!(buffer.push(frame(4)).is_empty());
+        assert!(buffer.push(frame(5)).is_empty());
+        assert_eq!(sequences(buffer.push(frame(6))), vec![4, 5, 6]);
+
+        // Too late to be played
+        assert!(buffer.push(frame(3)).is_empty());
+        assert_eq!(sequences(buffer.push(frame(7))), vec![7]);
+    }
+}
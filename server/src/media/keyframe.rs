@@ -0,0 +1,86 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+use webrtc::rtcp::{
+    packet::Packet,
+    payload_feedbacks::{full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication},
+};
+
+use crate::protocol::KeyframeRequestReason;
+
+/// Shortest interval between keyframe requests for one stream
+///
+/// Every subscriber that lost the same packets sends a PLI of its own, and
+/// one keyframe answers all of them.
+pub const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Request for a keyframe of a live track, for the producer feeding it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyframeRequest {
+    pub producer_id: Uuid,
+    pub track_id: String,
+    pub reason: KeyframeRequestReason,
+}
+
+/// Keyframe asked for in RTCP packets a client sent about its video, if
+/// any; a FIR takes precedence over a PLI
+pub fn requested_keyframe(packets: &[Box<dyn Packet + Send + Sync>]) -> Option<KeyframeRequestReason> {
+    let mut reason = None;
+    for packet in packets {
+        let any = packet.as_any();
+        if any.is::<FullIntraRequest>() {
+            return Some(KeyframeRequestReason::Fir);
+        }
+        if any.is::<PictureLossIndication>() {
+            reason = Some(KeyframeRequestReason::Pli);
+        }
+    }
+    reason
+}
+
+/// Lets a stream's keyframe requests through at most once per
+/// `KEYFRAME_REQUEST_INTERVAL`
+#[derive(Debug, Default)]
+pub struct KeyframeThrottle {
+    last: Option<Instant>,
+}
+
+impl KeyframeThrottle {
+    /// Whether a request may be made at `now`, counting it if so
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if self.last.is_some_and(|last| now.duration_since(last) < KEYFRAME_REQUEST_INTERVAL) {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtcp::{payload_feedbacks::full_intra_request::FirEntry, receiver_report::ReceiverReport};
+
+    #[test]
+    fn test_pli_and_fir_request_throttled_keyframes() {
+        let report: Box<dyn Packet + Send + Sync> = Box::new(ReceiverReport::default());
+        let pli: Box<dyn Packet + Send + Sync> = Box::new(PictureLossIndication {
+            sender_ssrc: 1,
+            media_ssrc: 2,
+        });
+        let fir: Box<dyn Packet + Send + Sync> = Box::new(FullIntraRequest {
+            sender_ssrc: 1,
+            media_ssrc: 2,
+            fir: vec![FirEntry { ssrc: 2, sequence_number: 1 }],
+        });
+        assert_eq!(requested_keyframe(std::slice::from_ref(&report)), None);
+        assert_eq!(requested_keyframe(&[report, pli]), Some(KeyframeRequestReason::Pli));
+        assert_eq!(requested_keyframe(&[fir]), Some(KeyframeRequestReason::Fir));
+
+        let mut throttle = KeyframeThrottle::default();
+        let start = Instant::now();
+        assert!(throttle.allow(start));
+        assert!(!throttle.allow(start + KEYFRAME_REQUEST_INTERVAL / 2));
+        assert!(throttle.allow(start + KEYFRAME_REQUEST_INTERVAL));
+    }
+}
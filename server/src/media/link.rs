@@ -0,0 +1,229 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use webrtc::rtcp::{
+    packet::Packet, receiver_report::ReceiverReport, reception_report::ReceptionReport,
+    sender_report::SenderReport,
+};
+
+use crate::protocol::NetworkQuality;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Reports whose median loss and round trip decide the quality
+const REPORT_WINDOW: usize = 5;
+
+/// Consecutive reports that must agree on a new quality before it is
+/// applied
+const QUALITY_HOLD_REPORTS: u32 = 2;
+
+/// Link metrics from one RTCP reception report block
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LinkMetrics {
+    /// Packets lost since the client's previous report
+    pub loss_percent: f64,
+
+    /// Interarrival jitter
+    pub jitter_ms: f64,
+
+    /// Round trip from the last sender report the client acknowledged;
+    /// `None` until it has received one
+    pub rtt_ms: Option<f64>,
+}
+
+/// Link metrics in the reception report blocks of RTCP packets a client
+/// sent about our stream
+///
+/// Blocks of receiver and sender reports are both used. `now_ntp` is the
+/// middle 32 bits of the current NTP time, against which the round trip
+/// is measured, and `clock_rate` that of the stream's RTP timestamps.
+pub fn parse_reports(packets: &[Box<dyn Packet + Send + Sync>], now_ntp: u32, clock_rate: u32) -> Vec<LinkMetrics> {
+    packets
+        .iter()
+        .flat_map(|packet| {
+            let any = packet.as_any();
+            if let Some(rr) = any.downcast_ref::<ReceiverReport>() {
+                rr.reports.as_slice()
+            } else if let Some(sr) = any.downcast_ref::<SenderReport>() {
+                sr.reports.as_slice()
+            } else {
+                &[]
+            }
+        })
+        .map(|report| metrics(report, now_ntp, clock_rate))
+        .collect()
+}
+
+fn metrics(report: &ReceptionReport, now_ntp: u32, clock_rate: u32) -> LinkMetrics {
+    // RFC 3550 6.4.1: arrival minus LSR minus DLSR, in 1/65536 seconds.
+    // A negative result means the clocks disagree and is discarded.
+    let rtt_ms = (report.last_sender_report != 0)
+        .then(|| now_ntp.wrapping_sub(report.last_sender_report).wrapping_sub(report.delay))
+        .filter(|units| *units < 1 << 31)
+        .map(|units| units as f64 * 1000.0 / 65536.0);
+    LinkMetrics {
+        loss_percent: report.fraction_lost as f64 * 100.0 / 256.0,
+        jitter_ms: report.jitter as f64 * 1000.0 / clock_rate as f64,
+        rtt_ms,
+    }
+}
+
+/// Middle 32 bits of the NTP timestamp for `time`
+pub fn ntp_middle(time: SystemTime) -> u32 {
+    let since_unix = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_unix.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (since_unix.subsec_nanos() as u64 * 65536) / 1_000_000_000;
+    ((seconds << 16) | fraction) as u32
+}
+
+/// Smooths a client's reports into a network quality
+///
+/// The quality follows the median loss and round trip of the last
+/// `REPORT_WINDOW` reports, so one bad report cannot move it, and changes
+/// only once `QUALITY_HOLD_REPORTS` reports in a row have agreed.
+#[derive(Debug)]
+pub struct LinkEstimator {
+    recent: VecDeque<LinkMetrics>,
+    quality: NetworkQuality,
+    candidate: Option<(NetworkQuality, u32)>,
+}
+
+impl LinkEstimator {
+    pub fn new(quality: NetworkQuality) -> Self {
+        Self {
+            recent: VecDeque::with_capacity(REPORT_WINDOW),
+            quality,
+            candidate: None,
+        }
+    }
+
+    /// Take in a report, returning the new quality if it changed
+    pub fn update(&mut self, metrics: LinkMetrics) -> Option<NetworkQuality> {
+        if self.recent.len() == REPORT_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(metrics);
+
+        // Until a round trip is known, quality follows loss alone
+        let loss = median(self.recent.iter().map(|m| m.loss_percent)).unwrap_or(0.0);
+        let rtt = median(self.recent.iter().filter_map(|m| m.rtt_ms)).unwrap_or(0.0);
+        let wanted = NetworkQuality::from_metrics(rtt, loss);
+        if wanted == self.quality {
+            self.candidate = None;
+            return None;
+        }
+        let agreeing = match self.candidate {
+            Some((candidate, count)) if candidate == wanted => count + 1,
+            _ => 1,
+        };
+        if agreeing < QUALITY_HOLD_REPORTS {
+            self.candidate = Some((wanted, agreeing));
+            return None;
+        }
+        self.quality = wanted;
+        self.candidate = None;
+        Some(wanted)
+    }
+}
+
+fn median(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use webrtc::rtcp::goodbye::Goodbye;
+
+    fn receiver_report(fraction_lost: u8, jitter: u32, last_sender_report: u32, delay: u32) -> ReceiverReport {
+        ReceiverReport {
+            ssrc: 1,
+            reports: vec![ReceptionReport {
+                ssrc: 2,
+                fraction_lost,
+                total_lost: 0,
+                last_sequence_number: 100,
+                jitter,
+                last_sender_report,
+                delay,
+            }],
+            profile_extensions: Bytes::new(),
+        }
+    }
+
+    #[test]
+    fn test_receiver_reports_parse_into_link_metrics() {
+        // The client received our report 80ms ago and held it for 30ms
+        let now = 0x0010_0000;
+        let report = receiver_report(64, 480, now - 65536 * 80 / 1000, 65536 * 30 / 1000);
+
+        // Through the wire format, as the interceptor chain hands them over
+        let compound: Vec<Box<dyn Packet + Send + Sync>> = vec![
+            Box::new(report),
+            Box::new(Goodbye {
+                sources: vec![1],
+                reason: Bytes::new(),
+            }),
+        ];
+        let mut raw = webrtc::rtcp::packet::marshal(&compound).unwrap();
+        let packets = webrtc::rtcp::packet::unmarshal(&mut raw).unwrap();
+
+        let metrics = parse_reports(&packets, now, 48_000);
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].loss_percent, 25.0);
+        assert_eq!(metrics[0].jitter_ms, 10.0);
+        let rtt = metrics[0].rtt_ms.unwrap();
+        assert!((rtt - 50.0).abs() < 0.1, "rtt {}", rtt);
+
+        // No round trip before a sender report was acknowledged, nor when
+        // the clocks disagree
+        let unacknowledged: Vec<Box<dyn Packet + Send + Sync>> = vec![Box::new(receiver_report(0, 0, 0, 0))];
+        assert_eq!(parse_reports(&unacknowledged, now, 48_000)[0].rtt_ms, None);
+        let ahead: Vec<Box<dyn Packet + Send + Sync>> = vec![Box::new(receiver_report(0, 0, now + 100, 0))];
+        assert_eq!(parse_reports(&ahead, now, 48_000)[0].rtt_ms, None);
+    }
+
+    #[test]
+    fn test_one_bad_report_does_not_change_quality() {
+        let good = LinkMetrics {
+            loss_percent: 0.0,
+            jitter_ms: 1.0,
+            rtt_ms: Some(20.0),
+        };
+        let lossy = LinkMetrics {
+            loss_percent: 40.0,
+            ..good
+        };
+        let mut estimator = LinkEstimator::new(NetworkQuality::Good);
+        assert_eq!(estimator.update(good), None);
+
+        // A single burst of loss is outvoted
+        assert_eq!(estimator.update(lossy), None);
+        for _ in 0..REPORT_WINDOW {
+            assert_eq!(estimator.update(good), None);
+        }
+
+        // Sustained loss degrades the link once it is the median and a
+        // second report confirms it
+        let changes: Vec<_> = (0..4).map(|_| estimator.update(lossy)).collect();
+        assert_eq!(changes, [None, None, None, Some(NetworkQuality::Critical)]);
+
+        // And recovers the same way
+        let changes: Vec<_> = (0..4).map(|_| estimator.update(good)).collect();
+        assert_eq!(changes, [None, None, None, Some(NetworkQuality::Good)]);
+    }
+
+    #[test]
+    fn test_ntp_middle_bits() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1500);
+        let ntp = ntp_middle(time);
+        assert_eq!(ntp >> 16, (NTP_UNIX_OFFSET + 1) as u32 & 0xffff);
+        assert_eq!(ntp & 0xffff, 0x8000);
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+
+use super::source::FrameSource;
+
+/// Largest normalization gain applied to a quiet track, in dB
+pub const MAX_BOOST_DB: f64 = 20.0;
+
+/// Length of a gating block in 100ms sub-blocks
+const BLOCK_SUB_BLOCKS: usize = 4;
+
+/// Blocks quieter than this are silence and never counted
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Blocks this far below the ungated loudness are not counted
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Integrated loudness meter following ITU-R BS.1770 / EBU R128
+///
+/// Samples are K-weighted per channel and their mean square is taken over
+/// 400ms blocks overlapping by 75%. The integrated loudness averages the
+/// blocks above the absolute gate, then again over the blocks within 10 LU
+/// of that average.
+pub struct LoudnessMeter {
+    /// Shelf and high-pass stages for each channel
+    filters: Vec<[Biquad; 2]>,
+
+    /// Channel weights; surround channels of a 5.1 layout count more and
+    /// the LFE is ignored
+    weights: Vec<f64>,
+
+    sub_block_len: usize,
+
+    /// Weighted sum of squares in the current sub-block
+    energy: f64,
+    samples: usize,
+
+    /// Mean square of every completed sub-block
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u8) -> Self {
+        let channels = channels.max(1) as usize;
+        let weights = (0..channels)
+            .map(|channel| match (channels, channel) {
+                (6, 3) => 0.0,
+                (6, 4) | (6, 5) => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+        let rate = sample_rate as f64;
+
+        Self {
+            filters: vec![[Biquad::shelf(rate), Biquad::high_pass(rate)]; channels],
+            weights,
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            energy: 0.0,
+            samples: 0,
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    /// Add interleaved samples
+    pub fn add(&mut self, samples: &[i16]) {
+        let channels = self.filters.len();
+        for frame in samples.chunks_exact(channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample as f64 / 32768.0));
+                self.energy += self.weights[channel] * weighted * weighted;
+            }
+
+            self.samples += 1;
+            if self.samples == self.sub_block_len {
+                self.sub_blocks.push(self.energy / self.samples as f64);
+                self.energy = 0.0;
+                self.samples = 0;
+            }
+        }
+    }
+
+    /// Integrated loudness in LUFS, or `None` for silence
+    ///
+    /// Material shorter than one block is measured as a single block.
+    pub fn integrated(&self) -> Option<f64> {
+        let blocks: Vec<f64> = if self.sub_blocks.len() < BLOCK_SUB_BLOCKS {
+            let total = self.sub_blocks.len() * self.sub_block_len + self.samples;
+            if total == 0 {
+                return None;
+            }
+            let energy = self.energy
+                + self.sub_blocks.iter().sum::<f64>() * self.sub_block_len as f64;
+            vec![energy / total as f64]
+        } else {
+            self.sub_blocks
+                .windows(BLOCK_SUB_BLOCKS)
+                .map(|window| window.iter().sum::<f64>() / BLOCK_SUB_BLOCKS as f64)
+                .collect()
+        };
+
+        let gated_mean = |threshold: f64| {
+            let gated: Vec<f64> = blocks
+                .iter()
+                .copied()
+                .filter(|&energy| loudness(energy) > threshold)
+                .collect();
+            (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+        };
+        let ungated = gated_mean(ABSOLUTE_GATE_LUFS)?;
+        let relative_gate = (loudness(ungated) + RELATIVE_GATE_LU).max(ABSOLUTE_GATE_LUFS);
+
+        gated_mean(relative_gate).map(loudness)
+    }
+}
+
+/// Loudness in LUFS of a weighted mean square
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Measure the integrated loudness of a whole source from its current
+/// position
+///
+/// Returns `None` for silence and for sources that are not decoded to PCM.
+pub fn measure(source: &mut dyn FrameSource) -> Result<Option<f64>> {
+    if source.codec() != "pcm16" {
+        return Ok(None);
+    }
+
+    let mut meter = LoudnessMeter::new(source.sample_rate(), source.channels());
+    while let Some(frame) = source.next_frame()? {
+        let samples: Vec<i16> = frame
+            .data
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        meter.add(&samples);
+    }
+
+    Ok(meter.integrated())
+}
+
+/// Gain in dB bringing a track measured at `loudness` to `reference`
+///
+/// Boosts are capped at `MAX_BOOST_DB` so near-silent tracks are not
+/// amplified into noise.
+pub fn normalization_gain_db(loudness: f64, reference: f64) -> f64 {
+    (reference - loudness).min(MAX_BOOST_DB)
+}
+
+/// Second-order IIR filter section in direct form I
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// K-weighting stage 1: high shelf modelling the head's acoustics
+    fn shelf(sample_rate: f64) -> Self {
+        let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            ..Self::default()
+        }
+    }
+
+    /// K-weighting stage 2: RLB high-pass
+    fn high_pass(sample_rate: f64) -> Self {
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            ..Self::default()
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::source::SineSource;
+
+    #[test]
+    fn test_full_scale_sine_reads_near_reference_level() {
+        // A 1kHz sine at full scale on one channel reads about -3 LUFS
+        let mut source = SineSource::new(100, 1, 1000.0, 32767.0);
+        let loudness = measure(&mut source).unwrap().unwrap();
+        assert!((loudness + 3.0).abs() < 0.2, "measured {:.2} LUFS", loudness);
+
+        assert!(measure(&mut SineSource::new(100, 1, 1000.0, 0.0)).unwrap().is_none());
+        assert_eq!(normalization_gain_db(-60.0, -16.0), MAX_BOOST_DB);
+    }
+}
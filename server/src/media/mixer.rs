@@ -0,0 +1,161 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+
+use super::playback::SharedSource;
+
+/// Mixer overlapping the head of the next track with the tail of the
+/// current one
+///
+/// Over the overlap the current track ramps linearly from full gain to
+/// silence while the next ramps up in the opposite direction, so the two
+/// gains always sum to one. The next track's samples are converted to the
+/// current track's channel layout and scaled by its normalization gain
+/// before mixing.
+pub struct Mixer {
+    /// Source of the next track
+    source: SharedSource,
+
+    /// Position in the current track at which the overlap starts
+    start: f64,
+
+    /// Length of the overlap in seconds
+    length: f64,
+
+    /// Channel count of the current track
+    channels: u8,
+
+    /// Linear normalization gain of the next track
+    gain: f64,
+
+    /// Samples of the next track converted to `channels`, not yet mixed
+    pending: VecDeque<i16>,
+
+    /// Sample frames of the next track mixed so far
+    mixed: u64,
+
+    sample_rate: u32,
+}
+
+impl Mixer {
+    /// Rewind the next track's source and prepare to mix it in from
+    /// track position `start` of the current one
+    pub fn start(source: SharedSource, start: f64, length: f64, channels: u8, gain: f64) -> Result<Self> {
+        let sample_rate = {
+            let mut source = source.lock();
+            if source.codec() != "pcm16" {
+                anyhow::bail!("Cannot mix {} passthrough", source.codec());
+            }
+            source.seek(0.0)?;
+            source.sample_rate()
+        };
+
+        Ok(Self {
+            source,
+            start,
+            length,
+            channels: channels.max(1),
+            gain,
+            pending: VecDeque::new(),
+            mixed: 0,
+            sample_rate,
+        })
+    }
+
+    /// Mix the next track into a frame of interleaved little-endian i16
+    /// samples starting at track `position` of the current one
+    pub fn mix(&mut self, data: &mut [u8], position: f64, sample_rate: u32) -> Result<()> {
+        let frame_bytes = 2 * self.channels as usize;
+
+        for (index, samples) in data.chunks_exact_mut(frame_bytes).enumerate() {
+            let t = position + index as f64 / sample_rate as f64;
+            if t < self.start {
+                continue;
+            }
+
+            let gain = if self.length > 0.0 {
+                ((t - self.start) / self.length).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            self.fill(self.channels as usize)?;
+            self.mixed += 1;
+
+            for sample in samples.chunks_exact_mut(2) {
+                let current = i16::from_le_bytes([sample[0], sample[1]]) as f64;
+                let next = self.pending.pop_front().unwrap_or(0) as f64 * self.gain;
+                let value = current * (1.0 - gain) + next * gain;
+                let value = value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+                sample.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Position in the next track up to which it has been mixed
+    pub fn position(&self) -> f64 {
+        self.mixed as f64 / self.sample_rate as f64
+    }
+
+    /// Decode frames of the next track until `samples` are pending; past
+    /// its end the next track is silent
+    fn fill(&mut self, samples: usize) -> Result<()> {
+        while self.pending.len() < samples {
+            let mut source = self.source.lock();
+            let Some(frame) = source.next_frame()? else {
+                break;
+            };
+            let decoded: Vec<i16> = frame
+                .data
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+                .collect();
+            self.pending
+                .extend(convert_channels(&decoded, source.channels(), self.channels));
+        }
+        Ok(())
+    }
+}
+
+/// Convert interleaved samples between channel counts
+///
+/// Downmixing to mono averages all channels; otherwise each output channel
+/// averages the input channels that wrap onto it, and upmixing repeats the
+/// input channels in order.
+pub fn convert_channels(samples: &[i16], from: u8, to: u8) -> Vec<i16> {
+    let (from, to) = (from.max(1) as usize, to.max(1) as usize);
+    if from == to {
+        return samples.to_vec();
+    }
+
+    let mut converted = Vec::with_capacity(samples.len() / from * to);
+    for frame in samples.chunks_exact(from) {
+        for channel in 0..to {
+            if from < to {
+                converted.push(frame[channel % from]);
+            } else {
+                let inputs: Vec<i32> = frame
+                    .iter()
+                    .skip(channel)
+                    .step_by(to)
+                    .map(|&sample| sample as i32)
+                    .collect();
+                converted.push((inputs.iter().sum::<i32>() / inputs.len() as i32) as i16);
+            }
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_conversion() {
+        assert_eq!(convert_channels(&[1, 2], 1, 2), vec![1, 1, 2, 2]);
+        assert_eq!(convert_channels(&[10, 20, 30, 50], 2, 1), vec![15, 40]);
+        assert_eq!(convert_channels(&[1, 2, 3, 4, 5, 6], 6, 2), vec![3, 4]);
+        assert_eq!(convert_channels(&[1, 2], 2, 2), vec![1, 2]);
+    }
+}
@@ -1,31 +1,137 @@
 use anyhow::Result;
 use tokio::sync::RwLock;
 use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::Duration,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime},
 };
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
 use uuid::Uuid;
-use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::{
+    data_channel::RTCDataChannel,
+    ice_transport::ice_connection_state::RTCIceConnectionState,
+    peer_connection::{
+        peer_connection_state::RTCPeerConnectionState, sdp::session_description::RTCSessionDescription,
+        RTCPeerConnection,
+    },
+    rtp_transceiver::{rtp_codec::RTPCodecType, rtp_sender::RTCRtpSender},
+};
 
 mod buffer;
+mod capture;
+mod catalog;
+mod certificate;
+mod clock_channel;
+mod codec;
+mod compression;
+mod fairness;
+mod fec;
+mod g711;
+mod ingest;
+mod keyframe;
+mod link;
+mod loudness;
+mod mixer;
+#[cfg(feature = "opus")]
+mod opus;
+mod persist;
+mod playback;
+mod program;
+mod queue;
+mod recording;
+mod rendition;
+mod rtp_sender;
+mod simulcast;
+mod source;
+mod stats;
+mod sync_group;
+mod tone;
+mod tuning;
 mod webrtc_server;
+mod webrtc_stats;
+mod zone;
 
-pub use buffer::{DynamicFutureBuffer, MediaFrame};
-pub use webrtc_server::WebRtcServer;
+pub use buffer::{
+    BoundsSource, BufferPolicy, BufferStats, ConcealmentRequest, DynamicFutureBuffer, JitterMode,
+    LatencyBounds, MediaFrame, QueuedFrame,
+};
+pub use capture::{Capture, CaptureStatus, CapturedStream};
+pub use catalog::{CatalogError, TrackCatalog, TrackInfo};
+pub use clock_channel::CLOCK_CHANNEL_CAPABILITY;
+pub use codec::{can_transcode, channel_capacity, CodecSupport};
+pub use certificate::CertificateFile;
+pub use compression::{Compressors, FrameCompressor, ZstdCompressor};
+pub use fairness::{ForwarderClass, YieldBudget};
+pub use fec::{FecStatus, LossFeedback, LossRecoveryConfig};
+pub use ingest::{ReorderBuffer, REORDER_WINDOW};
+pub use keyframe::KeyframeRequest;
+pub use link::LinkMetrics;
+#[cfg(feature = "opus")]
+pub use opus::opus_encoder_factory;
+pub use persist::{PersistedState, StateFile};
+pub use playback::{Crossfade, Playback, PlaybackFinished, PlaybackParams, PlaybackTarget, SharedSource};
+pub use program::{Program, ProgramStatus};
+pub use queue::{PlayQueue, QueueItem, QueueStatus};
+pub use recording::{Recording, RecordingError, RecordingStatus};
+pub use rendition::{EncoderFactory, QualityTier, TierSelector};
+pub use rtp_sender::{MediaSender, PlayoutDelay, RtpClockMapping};
+pub use simulcast::{TieredVideo, VideoTierSwitch};
+pub use source::{FileSource, FrameSource};
+pub use stats::{ClientStats, DisconnectedClientStats, Egress, EgressCounter, MediaHealth, MediaStats, StreamCounters, StreamStats};
+pub use sync_group::{SyncGroup, SyncGroupStatus, DEFAULT_SYNC_SLACK};
+pub use tone::{ToneParams, ToneSource, Waveform};
+pub use tuning::TuningCache;
+pub use webrtc_server::{IceConfig, IceServerSummary, MediaCodecs, PortRange, UdpPortConfig, WebRtcCodec, WebRtcServer};
+pub use webrtc_stats::WebRtcStats;
+pub use zone::{stream_key, ZoneMap, ZoneStatus};
 
+use clock_channel::ClockEndpoint;
+use codec::is_video_codec;
+use fec::FecController;
+use keyframe::KeyframeThrottle;
+use link::LinkEstimator;
 use crate::{
     clock::ClockManager,
-    protocol::{MediaControlMessage, MediaDataMessage, NetworkQuality},
+    config::ServerConfig,
+    health::HealthState,
+    protocol::{
+        BufferReportMessage, Compression, MediaAction, MediaControlMessage, MediaDataMessage, MediaParams,
+        KeyframeRequestReason, MessageHeader, NetworkQuality, PlaybackProgressMessage, LOOP_FOREVER,
+    },
 };
 
+/// Lead time given to clients when a skip starts the next queue item
+const SKIP_LEAD_SECS: f64 = 0.1;
+
+/// How often frames held by sync groups are checked against their slack
+const SYNC_RELEASE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Longest a client's pacing task sleeps with nothing queued
+const PACER_IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// How often streams are checked for having gone idle
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often disconnected peer connections are checked against their grace
+const PEER_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the WebRTC statistics of every peer connection are collected
+const WEBRTC_STATS_INTERVAL: Duration = Duration::from_secs(2);
+
 /// Manages media streaming and synchronization
 pub struct MediaServer {
     /// Server ID
     server_id: Uuid,
     
+    /// Server configuration
+    config: Arc<ServerConfig>,
+    
     /// Clock manager reference
     clock_manager: Arc<ClockManager>,
     
@@ -35,47 +141,675 @@ pub struct MediaServer {
     /// Connected clients
     clients: Arc<RwLock<HashMap<Uuid, MediaClient>>>,
     
+    /// Registered tracks available for playback
+    catalog: TrackCatalog,
+    
+    /// Where the catalog and programs are saved on every change
+    state_file: Option<tokio::sync::Mutex<StateFile>>,
+    
+    /// Number of running frame forwarding tasks
+    active_forwarders: Arc<AtomicUsize>,
+    
+    /// Periodic stats snapshots taken by the run loop
+    stats_snapshots: AtomicU64,
+    
+    /// ICE restarts offered to clients since the server started
+    ice_restarts: AtomicU64,
+    
+    /// Control commands to announce to connected clients
+    control_events: broadcast::Sender<MediaControlMessage>,
+    
+    /// Periodic positions of playing streams, for connected clients
+    progress_events: broadcast::Sender<PlaybackProgressMessage>,
+    
+    /// Audio frames clients missed, for their decoders to conceal
+    concealment_requests: broadcast::Sender<(Uuid, ConcealmentRequest)>,
+    
+    /// Offers restarting ICE on degraded peer connections, to be signalled
+    /// to their clients
+    ice_restart_offers: broadcast::Sender<(Uuid, RTCSessionDescription)>,
+    
+    /// Keyframes subscribers need of live tracks, for their producers
+    keyframe_requests: broadcast::Sender<KeyframeRequest>,
+    
+    /// RTP timestamp mappings of clients' tracks as they start, to be
+    /// signalled to their clients
+    rtp_clocks: broadcast::Sender<(Uuid, WebRtcCodec, RtpClockMapping)>,
+    
+    /// Tracks played one after another
+    queue: parking_lot::Mutex<PlayQueue>,
+    
+    /// Zone each client belongs to
+    zones: parking_lot::RwLock<ZoneMap>,
+    
+    /// Live tracks released together, by group name
+    sync_groups: parking_lot::Mutex<HashMap<String, SyncGroup>>,
+    
+    /// Streams of generated test tones, deleted when they finish
+    test_tones: parking_lot::Mutex<HashSet<String>>,
+    
+    /// Audio and video tracks sharing a timeline, by program ID
+    programs: parking_lot::RwLock<HashMap<String, Program>>,
+    
+    /// Encodings of a video subscribed to as one stream, by stream ID;
+    /// shared with the clients' pacing tasks
+    video_tiers: Arc<parking_lot::RwLock<HashMap<String, TieredVideo>>>,
+    
+    /// Buffer tuning of recently disconnected devices
+    device_tuning: parking_lot::Mutex<TuningCache>,
+    
+    /// Encoders for quality tier renditions, shared with every stream
+    encoder_factory: Arc<parking_lot::RwLock<Option<EncoderFactory>>>,
+    
+    /// Loss the clients receiving each tier expect, from their reception
+    /// reports, shared with every stream's encoders
+    loss_feedback: Arc<LossFeedback>,
+    
+    /// Transport compression accepted on `media_data` chunks
+    compressors: parking_lot::RwLock<Compressors>,
+    
+    /// Playbacks that ran to the end; the receiver is taken by the run loop
+    finished_rx: parking_lot::Mutex<Option<mpsc::Receiver<PlaybackFinished>>>,
+    finished_tx: mpsc::Sender<PlaybackFinished>,
+    
+    /// Peer connection state changes; the receiver is taken by the run loop
+    peer_state_rx: parking_lot::Mutex<Option<mpsc::UnboundedReceiver<PeerStateEvent>>>,
+    peer_state_tx: mpsc::UnboundedSender<PeerStateEvent>,
+    
+    /// Clients torn down after their peer connection failed or closed, kept
+    /// for `detach_client` until their control connection goes too
+    failed_peers: parking_lot::Mutex<HashMap<Uuid, FailedPeer>>,
+    
+    /// Latest WebRTC statistics of each client's peer connection
+    webrtc_stats: parking_lot::Mutex<HashMap<Uuid, WebRtcStats>>,
+    
     /// WebRTC server
     webrtc_server: Arc<WebRtcServer>,
     
-    /// Control command channel
-    control_rx: Arc<RwLock<mpsc::Receiver<MediaControlMessage>>>,
+    /// Control command channel; the receiver is taken by the run loop
+    control_rx: parking_lot::Mutex<Option<mpsc::Receiver<MediaControlMessage>>>,
     control_tx: mpsc::Sender<MediaControlMessage>,
 }
 
+/// Playback state of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
 /// Active media stream
 struct MediaStream {
     track_id: String,
+    /// Zone the stream plays in, if any
+    zone: Option<String>,
     codec: String,
-    bitrate: u32,
     sample_rate: u32,
     channels: u8,
     /// Broadcast channel for media frames
     frame_tx: broadcast::Sender<MediaFrame>,
+    /// Frames `frame_tx` holds for subscribers that fall behind
+    channel_capacity: usize,
+    /// Loaded frame source, if any
+    source: Option<SharedSource>,
+    /// Active playback, if any
+    playback: Option<Playback>,
+    /// Requested playback state
+    state: PlaybackState,
+    /// Next frame sequence number
+    sequence: Arc<AtomicU64>,
+    /// Requested additional plays after the first
+    loop_count: u32,
+    /// Completed restarts of the current play
+    loop_iteration: Arc<AtomicU32>,
+    /// Fade-out applied on Stop/Pause when the command does not set one
+    fade_out_ms: Option<u32>,
+    /// Network time at which the current play stops on its own
+    stop_at: Option<f64>,
+    /// Chunk reordering for a stream fed by a producer client
+    reorder: Option<ReorderBuffer>,
+    /// Producer client that last fed the stream, asked for its keyframes
+    producer: Option<Uuid>,
+    /// Limits the keyframe requests subscribers make of the stream
+    keyframe_throttle: parking_lot::Mutex<KeyframeThrottle>,
+    /// Notified when playback reaches the end of the track
+    finished_tx: mpsc::Sender<PlaybackFinished>,
+    /// Emitted frame counters and track position
+    stats: Arc<StreamCounters>,
+    /// Encoders for quality tier renditions, read when playback starts
+    encoder_factory: Arc<parking_lot::RwLock<Option<EncoderFactory>>>,
+    /// Loss the clients of each tier expect, for its encoder
+    loss_feedback: Arc<LossFeedback>,
+    /// How far ahead of its presentation time playback emits media
+    prebuffer: Duration,
+    /// Linear loudness normalization gain of the loaded track
+    gain: f64,
+    /// Recording of the distributed frames, kept after it ends for stats
+    recording: Option<Recording>,
+    /// Frame capture of the stream, kept after it ends for stats
+    capture: Option<Capture>,
+    /// When the stream was last seen stopped without subscribers
+    idle_since: Option<tokio::time::Instant>,
+}
+
+impl MediaStream {
+    /// Key of the stream in the stream map
+    fn key(&self) -> String {
+        stream_key(&self.track_id, self.zone.as_deref())
+    }
+    
+    /// Current playback state, treating playback that ran to the end as stopped
+    fn state(&self) -> PlaybackState {
+        match self.state {
+            PlaybackState::Playing if !self.playback.as_ref().is_some_and(|p| p.is_running()) => {
+                PlaybackState::Stopped
+            }
+            state => state,
+        }
+    }
+    
+    /// Ask the source of a video stream for a keyframe next
+    ///
+    /// A playing file source is asked directly, and the producer of a live
+    /// stream through `requests`. Requests go through at most once per
+    /// `KEYFRAME_REQUEST_INTERVAL`; returns whether this one did.
+    fn request_keyframe(&self, reason: KeyframeRequestReason, requests: &broadcast::Sender<KeyframeRequest>) -> bool {
+        if !is_video_codec(&self.codec) {
+            return false;
+        }
+        let playing_source = self
+            .source
+            .as_ref()
+            .filter(|_| self.playback.as_ref().is_some_and(|p| p.is_running()));
+        if playing_source.is_none() && self.producer.is_none() {
+            return false;
+        }
+        if !self.keyframe_throttle.lock().allow(tokio::time::Instant::now()) {
+            return false;
+        }
+        
+        debug!("Requesting a keyframe of {} ({:?})", self.key(), reason);
+        match (playing_source, self.producer) {
+            (Some(source), _) => source.lock().request_keyframe(),
+            (None, Some(producer_id)) => requests
+                .send(KeyframeRequest {
+                    producer_id,
+                    track_id: self.track_id.clone(),
+                    reason,
+                })
+                .is_ok(),
+            (None, None) => false,
+        }
+    }
+    
+    /// Stop active playback, if any
+    async fn stop_playback(&mut self) {
+        if let Some(playback) = self.playback.take() {
+            playback.stop().await;
+        }
+    }
+    
+    /// Stop active playback, ramping down to silence first if a fade is set
+    ///
+    /// The fade starts at `at`, or at the first frame not yet emitted if that
+    /// is later, and this returns once it has completed.
+    async fn fade_out_playback(&mut self, clock: &ClockManager, at: f64, fade_out_ms: Option<u32>) {
+        let Some(playback) = self.playback.take() else {
+            return;
+        };
+        
+        match fade_out_ms.filter(|ms| *ms > 0) {
+            Some(ms) if playback.is_running() => {
+                let start = at.max(clock.now().await + self.prebuffer.as_secs_f64());
+                playback.fade_out(start, start + ms as f64 / 1000.0).await;
+            }
+            _ => playback.stop().await,
+        }
+    }
+    
+    /// Reject fades for sources whose frames are not decoded to PCM
+    fn check_fades(&self, params: &MediaParams) -> Result<()> {
+        let fades = [params.fade_in_ms, params.fade_out_ms];
+        if self.codec != "pcm16" && fades.iter().flatten().any(|ms| *ms > 0) {
+            anyhow::bail!(
+                "Fades require a decodable source; track {} is {} passthrough",
+                self.track_id,
+                self.codec
+            );
+        }
+        Ok(())
+    }
+    
+    /// Validate a seek, returning the loaded source and the target position
+    fn check_seek(&self, params: &MediaParams) -> Result<(SharedSource, f64)> {
+        let key = self.key();
+        let position = params
+            .seek_position
+            .ok_or_else(|| anyhow::anyhow!("Seek requires a seek_position"))?;
+        let source = self
+            .source
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Track not loaded: {}", key))?;
+        
+        if self.state() == PlaybackState::Stopped {
+            anyhow::bail!("Cannot seek stopped track {}", key);
+        }
+        self.check_fades(params)?;
+        let duration = source.lock().duration();
+        if position < 0.0 || duration.is_some_and(|d| position > d) {
+            anyhow::bail!(
+                "Seek position {:.3}s is outside track {} ({:?}s)",
+                position,
+                key,
+                duration
+            );
+        }
+        
+        Ok((source, position))
+    }
+    
+    /// (Re)start playback of the loaded source
+    async fn start_playback(&mut self, clock: Arc<ClockManager>, params: PlaybackParams) -> Result<()> {
+        let source = self
+            .source
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Track not loaded: {}", self.track_id))?;
+        
+        self.stop_playback().await;
+        let target = PlaybackTarget {
+            track_id: self.key(),
+            frame_tx: self.frame_tx.clone(),
+            sequence: self.sequence.clone(),
+            loop_iteration: self.loop_iteration.clone(),
+            finished_tx: Some(self.finished_tx.clone()),
+            stats: self.stats.clone(),
+            encoder_factory: self.encoder_factory.read().clone(),
+            loss_feedback: self.loss_feedback.clone(),
+        };
+        self.playback = Some(Playback::start(target, source, clock, params));
+        self.state = PlaybackState::Playing;
+        
+        Ok(())
+    }
+    
+    /// Playback parameters continuing this stream's loop settings
+    fn playback_params(
+        &self,
+        start_at: f64,
+        origin: Option<f64>,
+        fade_in_ms: Option<u32>,
+    ) -> PlaybackParams {
+        PlaybackParams {
+            start_at,
+            origin,
+            loop_count: self.loop_count,
+            fade_in: fade_in_ms
+                .filter(|ms| *ms > 0)
+                .map(|ms| Duration::from_millis(ms as u64)),
+            stop_at: self.stop_at,
+            fade_out: self
+                .fade_out_ms
+                .filter(|ms| *ms > 0)
+                .map(|ms| Duration::from_millis(ms as u64)),
+            prebuffer: self.prebuffer,
+            gain: self.gain,
+        }
+    }
+    
+    /// Seconds left to play with `loop_count` loops, if the length is known
+    fn remaining(&self, loop_count: u32) -> Option<f64> {
+        let duration = self.source.as_ref()?.lock().duration()?;
+        if loop_count == LOOP_FOREVER {
+            return None;
+        }
+        
+        let plays = loop_count as f64 + 1.0;
+        if self.state() == PlaybackState::Stopped {
+            return Some(duration * plays);
+        }
+        let done = self.loop_iteration.load(Ordering::Relaxed) as f64;
+        let position = self.stats.position().unwrap_or(0.0);
+        Some(duration * (plays - done) - position)
+    }
+    
+    fn stats(&self, now: f64) -> StreamStats {
+        StreamStats {
+            status: self.status(now),
+            frames_emitted: self.stats.frames_emitted(),
+            bytes_emitted: self.stats.bytes_emitted(),
+            position: self.stats.position(),
+            recording: self.recording.as_ref().map(Recording::status),
+            capture: self.capture.as_ref().map(Capture::status),
+        }
+    }
+    
+    fn status(&self, now: f64) -> StreamStatus {
+        StreamStatus {
+            track_id: self.track_id.clone(),
+            zone: self.zone.clone(),
+            codec: self.codec.clone(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            state: self.state(),
+            loaded: self.source.is_some(),
+            loop_count: self.loop_count,
+            loop_iteration: self.loop_iteration.load(Ordering::Relaxed),
+            subscribers: self.frame_tx.receiver_count(),
+            channel_capacity: self.channel_capacity,
+            playback_position: self.stats.position_at(now),
+        }
+    }
+}
+
+/// Stream status reported by the API
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StreamStatus {
+    pub track_id: String,
+    pub zone: Option<String>,
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub state: PlaybackState,
+    pub loaded: bool,
+    pub loop_count: u32,
+    pub loop_iteration: u32,
+    pub subscribers: usize,
+    
+    /// Frames the stream's channel holds for subscribers that fall behind
+    pub channel_capacity: usize,
+    
+    /// Track position in seconds being presented now
+    pub playback_position: Option<f64>,
 }
 
 /// Connected media client
 struct MediaClient {
     client_id: Uuid,
+    /// Device the client connects from, whose buffer tuning is cached
+    device_id: Option<Uuid>,
+    /// Codecs the client can decode, limiting what it may subscribe to
+    codecs: CodecSupport,
+    /// Codec each stream the client asked to have transcoded is to reach
+    /// it in
+    transcodes: HashMap<String, String>,
     peer_connection: Arc<RTCPeerConnection>,
+    /// Clock sync channel, kept open for the life of the connection and
+    /// closed before it
+    clock_channel: Arc<RTCDataChannel>,
+    /// Frames waiting to be paced out, shared with the forwarders and the
+    /// pacing task
+    queue: Arc<parking_lot::Mutex<ClientQueue>>,
+    network_quality: NetworkQuality,
+    /// Latest reception report the client sent about its audio track
+    link: Option<LinkMetrics>,
+    /// Whether the client's reported loss calls for FEC on its audio
+    fec: FecController,
+    /// Whether the client's answer accepted Opus with in-band FEC
+    fec_negotiated: bool,
+    /// Forwarding task cancellation handle for each subscribed track
+    subscriptions: HashMap<String, CancellationToken>,
+    /// Cancelled when the client is removed, stopping its forwarding tasks
+    shutdown: CancellationToken,
+    /// Wakes the pacing task when forwarders queue a frame
+    frames_ready: Arc<Notify>,
+    /// Frames forwarded to the client
+    frames_delivered: Arc<AtomicU64>,
+    /// Frames the client missed
+    frames_dropped: Arc<AtomicU64>,
+    /// Bytes sent over the peer connection since the client was added
+    egress: Arc<EgressCounter>,
+    /// `QualityTier` index of the most recently forwarded frame
+    quality_tier: Arc<AtomicU8>,
+    /// Keyframes requested for the client, by its PLIs and FIRs and its
+    /// video subscriptions
+    keyframe_requests: u64,
+    /// When the peer connection became disconnected, or was last offered
+    /// an ICE restart, unless it has connected again since; media is held
+    /// back meanwhile
+    disconnected_since: Option<tokio::time::Instant>,
+    /// ICE restarts offered since the peer connection was last connected
+    ice_restarts: u32,
+    /// Codecs the client's audio and video tracks carry
+    track_codecs: MediaCodecs,
+    /// Codecs the client negotiated, once its answer has been applied
+    negotiated_codecs: MediaCodecs,
+}
+
+impl MediaClient {
+    fn set_network_quality(&mut self, quality: NetworkQuality) {
+        self.network_quality = quality;
+        self.queue.lock().future_buffer.update_network_quality(quality);
+    }
+    
+    /// Whether the client can be sent a stream of `codec`, decoding it or
+    /// having asked for it to be transcoded
+    fn receives(&self, track_id: &str, codec: &str) -> bool {
+        self.codecs.decodes(codec) || self.transcodes.contains_key(track_id)
+    }
+    
+    fn subscribed_tracks(&self) -> Vec<String> {
+        let mut tracks: Vec<_> = self.subscriptions.keys().cloned().collect();
+        tracks.sort();
+        tracks
+    }
+    
+    fn fec_status(&self) -> FecStatus {
+        let active = self.fec_negotiated && self.fec.is_active();
+        FecStatus {
+            negotiated: self.fec_negotiated,
+            active,
+            expected_loss_percent: self.fec.expected_loss().filter(|_| active),
+        }
+    }
+    
+    /// Take in the loss of a reception report, switching FEC on the
+    /// client's audio with it and telling the encoders of the tier it
+    /// receives how much loss to expect
+    fn update_fec(&mut self, loss_percent: f64, loss_feedback: &LossFeedback) {
+        if self.fec.update(loss_percent) && self.fec_negotiated {
+            info!(
+                "Client {} audio FEC is now {} ({:.1}% loss)",
+                self.client_id,
+                if self.fec.is_active() { "on" } else { "off" },
+                loss_percent
+            );
+        }
+        let tier = QualityTier::from_index(self.quality_tier.load(Ordering::Relaxed));
+        loss_feedback.set(self.client_id, tier, self.fec_status().expected_loss_percent);
+    }
+}
+
+/// A client's future buffer and the rest of the state forwarding frames
+/// to it consults
+///
+/// Forwarders queue frames here and the pacing task releases them, each
+/// for every frame, so it has a lock of its own rather than sharing the
+/// client map's with every other client.
+struct ClientQueue {
+    future_buffer: DynamicFutureBuffer,
+    /// Latest reception report the client sent about its video track
+    video_link: Option<LinkMetrics>,
+    /// Tier forwarded of each tiered video subscribed to, by stream ID
+    video_tiers: HashMap<String, VideoTierSwitch>,
+    /// Whether the peer connection is recovering; frames keep their
+    /// schedule meanwhile, but are held back
+    recovering: bool,
+    /// Senders of tracks replaced to carry another codec, for the pacing
+    /// task to switch to
+    replaced_senders: Vec<MediaSender>,
+    /// Tracks removed since the pacing task last looked, for it to forget
+    removed_tracks: Vec<String>,
+}
+
+impl ClientQueue {
+    fn new(future_buffer: DynamicFutureBuffer) -> Self {
+        Self {
+            future_buffer,
+            video_link: None,
+            video_tiers: HashMap::new(),
+            recovering: false,
+            replaced_senders: Vec::new(),
+            removed_tracks: Vec::new(),
+        }
+    }
+    
+    /// Drop a track's queued frames and forwarding state
+    fn remove_track(&mut self, track_id: &str) {
+        self.future_buffer.remove_track(track_id);
+        self.video_tiers.remove(track_id);
+        self.removed_tracks.push(track_id.to_string());
+    }
+    
+    /// Tier of tiered videos the client's link calls for
+    fn wanted_video_tier(&self) -> QualityTier {
+        simulcast::wanted_tier(
+            self.future_buffer.effective_quality(),
+            self.video_link.map(|link| link.loss_percent),
+        )
+    }
+    
+    /// Whether to queue a frame of `tier` of the tiered video `stream_id`,
+    /// switching tiers at its keyframes as the client's link calls for
+    fn admit_video_frame(&mut self, client_id: Uuid, stream_id: &str, tier: QualityTier, frame: &MediaFrame) -> bool {
+        let wanted = self.wanted_video_tier();
+        let Some(switch) = self.video_tiers.get_mut(stream_id) else {
+            return false;
+        };
+        let previous = switch.forwarding();
+        let keyframe = frame.frame_type == buffer::FrameType::VideoKeyframe;
+        let admitted = switch.admit(tier, keyframe, wanted);
+        if let Some(previous) = previous.filter(|previous| admitted && *previous != tier) {
+            info!(
+                "Client {} switched {} from {:?} to {:?} at a keyframe",
+                client_id, stream_id, previous, tier
+            );
+        }
+        admitted
+    }
+}
+
+/// State change of a client's peer connection
+struct PeerStateEvent {
+    client_id: Uuid,
+    /// Connection the change happened on, which the client may since have
+    /// replaced
+    connection: Weak<RTCPeerConnection>,
+    state: RTCPeerConnectionState,
+}
+
+/// Client torn down when its peer connection failed or closed
+struct FailedPeer {
+    state: RTCPeerConnectionState,
+    detached: DetachedClient,
+}
+
+/// Media state of a disconnected client, kept so that it can resume
+pub struct DetachedClient {
+    device_id: Option<Uuid>,
+    codecs: CodecSupport,
+    transcodes: HashMap<String, String>,
+    subscriptions: Vec<String>,
+    zone: Option<String>,
     future_buffer: DynamicFutureBuffer,
     network_quality: NetworkQuality,
-    subscribed_tracks: Vec<String>,
+}
+
+impl DetachedClient {
+    /// Tracks the client was subscribed to, sorted
+    pub fn subscriptions(&self) -> &[String] {
+        &self.subscriptions
+    }
+}
+
+/// Keeps the forwarder count accurate for the lifetime of a forwarding task
+struct ForwarderGuard(Arc<AtomicUsize>);
+
+impl ForwarderGuard {
+    fn new(count: Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for ForwarderGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl MediaServer {
-    pub fn new() -> Self {
+    /// Test server loading media from the system temp directory
+    #[cfg(test)]
+    pub fn new(clock_manager: Arc<ClockManager>) -> Self {
+        let config = ServerConfig { media_dir: std::env::temp_dir(), ..ServerConfig::default() };
+        Self::with_config(clock_manager, Arc::new(config))
+    }
+    
+    #[cfg(test)]
+    pub fn with_config(clock_manager: Arc<ClockManager>, config: Arc<ServerConfig>) -> Self {
+        Self::try_with_config(clock_manager, config).expect("Failed to set up WebRTC")
+    }
+    
+    /// Create a server, failing if the WebRTC UDP ports cannot be set up
+    pub fn try_with_config(clock_manager: Arc<ClockManager>, config: Arc<ServerConfig>) -> Result<Self> {
         let (control_tx, control_rx) = mpsc::channel(100);
+        let (finished_tx, finished_rx) = mpsc::channel(100);
+        let (peer_state_tx, peer_state_rx) = mpsc::unbounded_channel();
+        let state_file = config
+            .state_file
+            .clone()
+            .map(|path| tokio::sync::Mutex::new(StateFile::new(path)));
+        let device_tuning = TuningCache::new(Duration::from_millis(config.device_tuning_max_age_ms));
+        let mut webrtc_server = WebRtcServer::with_network(
+            &config.ice,
+            &config.webrtc_codecs,
+            &config.webrtc_udp,
+            &config.loss_recovery,
+        )?;
+        if let Some(path) = &config.dtls_certificate {
+            webrtc_server = webrtc_server.with_certificate(CertificateFile::new(path).load_or_generate()?);
+        }
+        let webrtc_server = Arc::new(webrtc_server);
         
-        Self {
+        Ok(Self {
             server_id: Uuid::new_v4(),
-            clock_manager: Arc::new(ClockManager::new()),
+            config,
+            clock_manager,
             streams: Arc::new(RwLock::new(HashMap::new())),
             clients: Arc::new(RwLock::new(HashMap::new())),
-            webrtc_server: Arc::new(WebRtcServer::new()),
-            control_rx: Arc::new(RwLock::new(control_rx)),
+            catalog: TrackCatalog::new(),
+            state_file,
+            active_forwarders: Arc::new(AtomicUsize::new(0)),
+            stats_snapshots: AtomicU64::new(0),
+            ice_restarts: AtomicU64::new(0),
+            control_events: broadcast::channel(100).0,
+            progress_events: broadcast::channel(100).0,
+            concealment_requests: broadcast::channel(100).0,
+            ice_restart_offers: broadcast::channel(100).0,
+            keyframe_requests: broadcast::channel(100).0,
+            rtp_clocks: broadcast::channel(100).0,
+            queue: parking_lot::Mutex::new(PlayQueue::new()),
+            zones: parking_lot::RwLock::new(ZoneMap::new()),
+            sync_groups: parking_lot::Mutex::new(HashMap::new()),
+            test_tones: parking_lot::Mutex::new(HashSet::new()),
+            programs: parking_lot::RwLock::new(HashMap::new()),
+            video_tiers: Arc::new(parking_lot::RwLock::new(HashMap::new())),
+            device_tuning: parking_lot::Mutex::new(device_tuning),
+            encoder_factory: Arc::new(parking_lot::RwLock::new(None)),
+            loss_feedback: Arc::new(LossFeedback::default()),
+            compressors: parking_lot::RwLock::new(Compressors::default()),
+            finished_rx: parking_lot::Mutex::new(Some(finished_rx)),
+            finished_tx,
+            peer_state_rx: parking_lot::Mutex::new(Some(peer_state_rx)),
+            peer_state_tx,
+            failed_peers: parking_lot::Mutex::new(HashMap::new()),
+            webrtc_stats: parking_lot::Mutex::new(HashMap::new()),
+            webrtc_server,
+            control_rx: parking_lot::Mutex::new(Some(control_rx)),
             control_tx,
-        }
+        })
     }
     
     /// Get command sender for external control
@@ -83,166 +817,5614 @@ impl MediaServer {
         self.control_tx.clone()
     }
     
-    /// Create a new media stream
-    pub async fn create_stream(&self, track_id: String, codec: String) -> Result<()> {
-        let (frame_tx, _) = broadcast::channel(1000);
-        
-        let stream = MediaStream {
-            track_id: track_id.clone(),
-            codec,
-            bitrate: 128000,
-            sample_rate: 48000,
-            channels: 2,
-            frame_tx,
-        };
-        
-        self.streams.write().await.insert(track_id.clone(), stream);
-        info!("Created media stream: {}", track_id);
-        
-        Ok(())
+    /// Subscribe to control commands that clients must be told about
+    pub fn subscribe_control_events(&self) -> broadcast::Receiver<MediaControlMessage> {
+        self.control_events.subscribe()
     }
     
-    /// Add media client
-    pub async fn add_client(&self, client_id: Uuid) -> Result<()> {
-        let peer_connection = self.webrtc_server.create_peer_connection().await?;
+    /// Subscribe to the periodic progress reports of playing streams
+    pub fn subscribe_progress_events(&self) -> broadcast::Receiver<PlaybackProgressMessage> {
+        self.progress_events.subscribe()
+    }
+    
+    /// Subscribe to concealment requests for the audio frames each client
+    /// missed
+    pub fn subscribe_concealment_requests(&self) -> broadcast::Receiver<(Uuid, ConcealmentRequest)> {
+        self.concealment_requests.subscribe()
+    }
+    
+    /// Subscribe to the ICE restart offers made to clients whose peer
+    /// connection degraded
+    /// Keyframe requests for the producers of live video tracks
+    pub fn subscribe_keyframe_requests(&self) -> broadcast::Receiver<KeyframeRequest> {
+        self.keyframe_requests.subscribe()
+    }
+    
+    /// Where the RTP timestamps of each client track that starts lie on
+    /// the network clock
+    pub fn subscribe_rtp_clocks(&self) -> broadcast::Receiver<(Uuid, WebRtcCodec, RtpClockMapping)> {
+        self.rtp_clocks.subscribe()
+    }
+    
+    pub fn subscribe_ice_restart_offers(&self) -> broadcast::Receiver<(Uuid, RTCSessionDescription)> {
+        self.ice_restart_offers.subscribe()
+    }
+    
+    /// Track position in seconds a stream is presenting now
+    ///
+    /// Follows the network clock while playing, and holds still while
+    /// paused or before a scheduled start. `None` if the stream does not
+    /// exist or has not played.
+    #[cfg(test)]
+    pub async fn get_position(&self, track_id: &str) -> Option<f64> {
+        let now = self.clock_manager.now().await;
+        self.streams.read().await.get(track_id)?.stats.position_at(now)
+    }
+    
+    /// Report the position of every playing stream to progress subscribers
+    async fn publish_progress(&self) {
+        let now = self.clock_manager.now().await;
+        let streams = self.streams.read().await;
         
-        let client = MediaClient {
-            client_id,
-            peer_connection,
-            future_buffer: DynamicFutureBuffer::new(
-                Duration::from_millis(80),
-                NetworkQuality::Good,
-            ),
-            network_quality: NetworkQuality::Good,
-            subscribed_tracks: Vec::new(),
+        for stream in streams.values().filter(|s| s.state() == PlaybackState::Playing) {
+            let Some(position) = stream.stats.position_at(now) else {
+                continue;
+            };
+            // No subscribers just means nobody is listening yet
+            let _ = self.progress_events.send(PlaybackProgressMessage {
+                header: MessageHeader::new(self.server_id, 0),
+                track_id: stream.track_id.clone(),
+                zone: stream.zone.clone(),
+                position,
+                at: now,
+            });
+        }
+    }
+    
+    /// Announce a control command to connected clients
+    fn announce(&self, cmd: MediaControlMessage) {
+        // No subscribers just means nobody is listening yet
+        let _ = self.control_events.send(cmd);
+    }
+    
+    /// Status of all streams
+    pub async fn stream_statuses(&self) -> Vec<StreamStatus> {
+        let now = self.clock_manager.now().await;
+        let mut statuses: Vec<_> = self.streams.read().await.values().map(|s| s.status(now)).collect();
+        statuses.sort_by(|a, b| (&a.track_id, &a.zone).cmp(&(&b.track_id, &b.zone)));
+        statuses
+    }
+    
+    /// Get the track catalog
+    pub fn catalog(&self) -> &TrackCatalog {
+        &self.catalog
+    }
+    
+    /// Restore the catalog, programs, tiered videos and device zones saved
+    /// before the last shutdown
+    ///
+    /// Tracks whose files have disappeared are dropped.
+    pub async fn load_state(&self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        let state_file = state_file.lock().await.clone();
+        let state = match tokio::task::spawn_blocking(move || state_file.load()).await {
+            Ok(state) => state,
+            Err(e) => {
+                error!("Failed to load state: {}", e);
+                return;
+            }
         };
         
-        self.clients.write().await.insert(client_id, client);
-        info!("Added media client: {}", client_id);
+        let mut restored = 0;
+        for track in state.tracks {
+            if !track.path.exists() {
+                warn!("Dropping track {}: {} is missing", track.track_id, track.path.display());
+                continue;
+            }
+            self.catalog.insert(track).await;
+            restored += 1;
+        }
+        let programs = state.programs.len();
+        self.programs
+            .write()
+            .extend(state.programs.into_iter().map(|p| (p.program_id.clone(), p)));
+        let video_tiers = state.video_tiers.len();
+        self.video_tiers
+            .write()
+            .extend(state.video_tiers.into_iter().map(|v| (v.stream_id.clone(), v)));
+        let device_zones = state.zones.len();
+        {
+            let mut zones = self.zones.write();
+            for device in state.zones {
+                zones.assign_device(device.device_id, Some(device.zone));
+            }
+        }
         
-        Ok(())
+        info!(
+            "Restored {} tracks, {} programs, {} tiered videos and {} device zones",
+            restored, programs, video_tiers, device_zones
+        );
     }
     
-    /// Update client network quality
-    pub async fn update_client_quality(&self, client_id: Uuid, quality: NetworkQuality) {
-        if let Some(client) = self.clients.write().await.get_mut(&client_id) {
-            client.network_quality = quality;
-            client.future_buffer.update_network_quality(quality);
-            
-            debug!(
-                "Updated client {} network quality: {:?}, buffer: {}ms",
-                client_id,
-                quality,
-                quality.recommended_buffer_ms()
-            );
+    /// Save the catalog, programs, tiered videos and device zones, if a
+    /// state file is configured
+    async fn save_state(&self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        
+        // Held while the snapshot is taken, so saves land in order
+        let state_file = state_file.lock().await;
+        let state = PersistedState {
+            tracks: self.catalog.list().await,
+            programs: {
+                let mut programs: Vec<_> = self.programs.read().values().cloned().collect();
+                programs.sort_by(|a, b| a.program_id.cmp(&b.program_id));
+                programs
+            },
+            video_tiers: self.video_tiers(),
+            zones: self.zones.read().device_zones(),
+        };
+        let file = state_file.clone();
+        let saved = tokio::task::spawn_blocking(move || file.save(&state)).await;
+        if let Err(e) = saved.map_err(anyhow::Error::from).and_then(|saved| saved) {
+            error!("Failed to save state to {}: {:#}", state_file.path().display(), e);
         }
     }
     
-    /// Subscribe client to a track
-    pub async fn subscribe_client(&self, client_id: Uuid, track_id: String) -> Result<()> {
-        let streams = self.streams.read().await;
-        let stream = streams
+    /// Probe an uploaded file and register it in the catalog
+    ///
+    /// The file is moved from `temp_path` into `media_dir` under its content
+    /// hash. Files that cannot be decoded are deleted and rejected. The
+    /// track's loudness is measured once per content hash; re-uploads reuse
+    /// the catalog's measurement.
+    pub async fn register_upload(
+        &self,
+        temp_path: &Path,
+        media_dir: &Path,
+        content_hash: String,
+        size_bytes: u64,
+        extension: Option<String>,
+    ) -> std::result::Result<TrackInfo, CatalogError> {
+        let track_id = format!("trk_{}", &content_hash[..16]);
+        let cached = self
+            .catalog
             .get(&track_id)
-            .ok_or_else(|| anyhow::anyhow!("Track not found: {}", track_id))?;
-        
-        let mut frame_rx = stream.frame_tx.subscribe();
+            .await
+            .filter(|track| track.content_hash == content_hash)
+            .map(|track| track.loudness);
         
-        // Spawn task to forward frames to client
-        let clients = self.clients.clone();
-        let clock = self.clock_manager.clone();
+        let probe_path = temp_path.to_path_buf();
+        let probed = tokio::task::spawn_blocking(move || {
+            let mut source = FileSource::open(&probe_path)?;
+            let loudness = match cached {
+                Some(loudness) => loudness,
+                None => loudness::measure(&mut source).unwrap_or_else(|e| {
+                    warn!("Failed to measure loudness of {}: {}", probe_path.display(), e);
+                    None
+                }),
+            };
+            anyhow::Ok((source, loudness))
+        })
+        .await
+        .map_err(std::io::Error::other)?;
         
-        tokio::spawn(async move {
-            while let Ok(_frame) = frame_rx.recv().await {
-                if let Some(client) = clients.read().await.get(&client_id) {
-                    // Add frame to future buffer with synchronized timestamp
-                    let network_time = clock.now().await;
-                    let future_time = network_time + client.future_buffer.target_latency();
-                    
-                    // TODO: Send frame via WebRTC
-                    debug!(
-                        "Scheduling frame for client {} at {:.3}",
-                        client_id, future_time
-                    );
-                }
+        let (source, loudness) = match probed {
+            Ok(probed) => probed,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(temp_path).await;
+                return Err(CatalogError::UnsupportedMedia(e.to_string()));
             }
+        };
+        
+        let file_name = match &extension {
+            Some(ext) => format!("{}.{}", content_hash, ext),
+            None => content_hash.clone(),
+        };
+        let path = media_dir.join(file_name);
+        tokio::fs::rename(temp_path, &path).await?;
+        
+        let gain_db = loudness.map_or(0.0, |loudness| {
+            loudness::normalization_gain_db(loudness, self.config.loudness_reference_lufs)
         });
+        let track = TrackInfo {
+            track_id,
+            path,
+            content_hash,
+            codec: source.codec().to_string(),
+            sample_rate: source.sample_rate(),
+            channels: source.channels(),
+            duration: source.duration(),
+            size_bytes,
+            added_at: chrono::Utc::now(),
+            loudness,
+            gain_db,
+        };
         
-        if let Some(client) = self.clients.write().await.get_mut(&client_id) {
-            client.subscribed_tracks.push(track_id);
-        }
+        info!(
+            "Registered track {} ({} bytes, {:?} LUFS, {:+.1}dB gain)",
+            track.track_id, size_bytes, track.loudness, track.gain_db
+        );
+        self.catalog.insert(track.clone()).await;
+        self.save_state().await;
         
-        Ok(())
+        Ok(track)
     }
     
-    /// Process media control command
-    async fn process_control(&self, cmd: MediaControlMessage) -> Result<()> {
-        use crate::protocol::MediaAction;
+    /// Remove a track from the catalog and delete its file
+    pub async fn delete_track(&self, track_id: &str) -> std::result::Result<TrackInfo, CatalogError> {
+        let track = self
+            .catalog
+            .remove(track_id)
+            .await
+            .ok_or_else(|| CatalogError::NotFound(track_id.to_string()))?;
+        self.save_state().await;
         
-        match cmd.action {
-            MediaAction::Play => {
-                info!("Play track {} at {}", cmd.track_id, cmd.start_at);
-                // TODO: Schedule playback
-            }
-            MediaAction::Pause => {
-                info!("Pause track {}", cmd.track_id);
-                // TODO: Pause playback
-            }
-            MediaAction::Stop => {
-                info!("Stop track {}", cmd.track_id);
-                // TODO: Stop playback
-            }
-            _ => {
-                debug!("Unhandled media action: {:?}", cmd.action);
-            }
+        let keys: Vec<_> = self
+            .streams
+            .read()
+            .await
+            .values()
+            .filter(|s| s.track_id == track_id)
+            .map(MediaStream::key)
+            .collect();
+        for key in keys {
+            self.delete_stream(&key).await;
         }
         
-        Ok(())
+        tokio::fs::remove_file(&track.path).await?;
+        info!("Deleted track {}", track_id);
+        
+        Ok(track)
     }
     
-    /// Run the media server
-    pub async fn run(self: Arc<Self>) {
-        info!("Media server started");
-        
-        let mut stats_interval = tokio::time::interval(Duration::from_secs(5));
-        
+    /// Re-encode decoded frames into quality tier renditions
+    ///
+    /// Builds with the `opus` feature set `opus_encoder_factory` at
+    /// startup; until a factory is set every client is sent the source
+    /// frames whatever its tier. Takes effect for playbacks started
+    /// afterwards.
+    #[cfg(any(test, feature = "opus"))]
+    pub fn set_encoder_factory(&self, factory: EncoderFactory) {
+        *self.encoder_factory.write() = Some(factory);
+    }
+    
+    /// Accept `media_data` chunks compressed with `algorithm`
+    ///
+    /// Chunks are only accepted uncompressed until a compressor is
+    /// registered; startup registers `ZstdCompressor`.
+    pub fn set_compressor(&self, algorithm: Compression, compressor: Arc<dyn FrameCompressor>) {
+        self.compressors.write().register(algorithm, compressor);
+    }
+    
+    /// Capabilities announcing the transport compression clients may use
+    pub fn compression_capabilities(&self) -> Vec<String> {
+        self.compressors.read().capabilities()
+    }
+    
+    /// Fingerprint of the DTLS certificate peer connections present, for
+    /// clients to pin; none without a persistent certificate
+    pub fn dtls_fingerprint(&self) -> Option<String> {
+        self.webrtc_server.fingerprint()
+    }
+    
+    /// Create a new media stream, with the default channel capacity for
+    /// its codec
+    #[cfg(test)]
+    pub async fn create_stream(&self, track_id: String, codec: String) -> Result<()> {
+        let capacity = channel_capacity(&codec, None);
+        self.create_zone_stream(track_id, None, codec, capacity).await
+    }
+    
+    /// Create a new media stream whose broadcast channel holds `capacity`
+    /// frames
+    ///
+    /// A subscriber falling further behind than that skips the frames it
+    /// missed. Fails for a capacity of zero.
+    #[cfg(test)]
+    pub async fn create_stream_with_capacity(&self, track_id: String, codec: String, capacity: usize) -> Result<()> {
+        if capacity == 0 {
+            anyhow::bail!("Stream {} needs a channel capacity of at least one frame", track_id);
+        }
+        self.create_zone_stream(track_id, None, codec, capacity).await
+    }
+    
+    /// Create a media stream playing only in `zone`
+    async fn create_zone_stream(
+        &self,
+        track_id: String,
+        zone: Option<String>,
+        codec: String,
+        channel_capacity: usize,
+    ) -> Result<()> {
+        let (frame_tx, _) = broadcast::channel(channel_capacity);
+        let key = stream_key(&track_id, zone.as_deref());
+        
+        let stream = MediaStream {
+            track_id,
+            zone,
+            codec,
+            sample_rate: 48000,
+            channels: 2,
+            frame_tx,
+            channel_capacity,
+            source: None,
+            playback: None,
+            state: PlaybackState::Stopped,
+            sequence: Arc::new(AtomicU64::new(0)),
+            loop_count: 0,
+            loop_iteration: Arc::new(AtomicU32::new(0)),
+            fade_out_ms: None,
+            stop_at: None,
+            reorder: None,
+            producer: None,
+            keyframe_throttle: parking_lot::Mutex::default(),
+            finished_tx: self.finished_tx.clone(),
+            stats: Arc::new(StreamCounters::default()),
+            encoder_factory: self.encoder_factory.clone(),
+            loss_feedback: self.loss_feedback.clone(),
+            prebuffer: Duration::from_millis(self.config.prebuffer_ms),
+            gain: 1.0,
+            recording: None,
+            capture: None,
+            idle_since: None,
+        };
+        
+        self.streams.write().await.insert(key.clone(), stream);
+        info!("Created media stream: {} ({} frame channel)", key, channel_capacity);
+        
+        Ok(())
+    }
+    
+    /// Tear down a stream, releasing its channel and client subscriptions
+    ///
+    /// Active playback is stopped and a Stop is announced before the stream
+    /// is removed, so clients never wait on frames that will not come.
+    /// Catalog tracks stay registered and recreate their stream when played
+    /// again. Returns whether the stream existed.
+    pub async fn delete_stream(&self, key: &str) -> bool {
+        let Some(mut stream) = self.streams.write().await.remove(key) else {
+            return false;
+        };
+        self.test_tones.lock().remove(key);
+        
+        if stream.state() == PlaybackState::Playing {
+            let now = self.clock_manager.now().await;
+            let stop = MediaControlMessage {
+                zone: stream.zone.clone(),
+                ..self.command(MediaAction::Stop, stream.track_id.clone(), now, MediaParams::default())
+            };
+            self.announce(stop);
+        }
+        stream.stop_playback().await;
+        if let Some(recording) = &stream.recording {
+            recording.stop().await;
+        }
+        if let Some(capture) = &stream.capture {
+            capture.stop().await;
+        }
+        
+        for client in self.clients.write().await.values_mut() {
+            if let Some(cancel) = client.subscriptions.remove(key) {
+                cancel.cancel();
+                client.queue.lock().remove_track(key);
+            }
+        }
+        
+        // Dropping the last sender closes the channel for any other receivers
+        drop(stream);
+        info!("Deleted media stream: {}", key);
+        
+        true
+    }
+    
+    /// Tear down catalog streams that have been stopped without subscribers
+    /// for the idle grace period
+    ///
+    /// Catalog tracks are recreated when played or subscribed to again.
+    /// Other streams are kept, since their source or live producer could
+    /// not be restored.
+    async fn teardown_idle_streams(&self) {
+        let grace = Duration::from_millis(self.config.idle_stream_grace_ms);
+        let catalog: HashSet<String> = self.catalog.list().await.into_iter().map(|t| t.track_id).collect();
+        let now = tokio::time::Instant::now();
+        
+        let mut removed = Vec::new();
+        {
+            let mut streams = self.streams.write().await;
+            for stream in streams.values_mut() {
+                let idle = stream.frame_tx.receiver_count() == 0
+                    && stream.state() == PlaybackState::Stopped
+                    && catalog.contains(&stream.track_id);
+                stream.idle_since = if idle { stream.idle_since.or(Some(now)) } else { None };
+            }
+            
+            streams.retain(|key, stream| {
+                let expired = stream.idle_since.is_some_and(|since| now.duration_since(since) >= grace);
+                if expired {
+                    removed.push(key.clone());
+                }
+                !expired
+            });
+        }
+        if removed.is_empty() {
+            return;
+        }
+        
+        for client in self.clients.write().await.values_mut() {
+            for key in &removed {
+                if let Some(cancel) = client.subscriptions.remove(key) {
+                    cancel.cancel();
+                    client.queue.lock().remove_track(key);
+                }
+            }
+        }
+        for key in removed {
+            info!("Tore down idle stream {} after {}ms without subscribers", key, grace.as_millis());
+        }
+    }
+    
+    /// Record the frames a stream distributes to `path`
+    ///
+    /// The container's extension is added when `path` has none. A recording
+    /// that failed or was stopped can be replaced by starting another.
+    pub async fn start_recording(
+        &self,
+        track_id: &str,
+        path: &Path,
+    ) -> std::result::Result<RecordingStatus, RecordingError> {
+        let mut streams = self.streams.write().await;
+        let stream = streams
+            .get_mut(track_id)
+            .ok_or_else(|| RecordingError::NotFound(track_id.to_string()))?;
+        if stream.recording.as_ref().is_some_and(Recording::is_active) {
+            return Err(RecordingError::AlreadyRecording(track_id.to_string()));
+        }
+        
+        let path = match path.extension() {
+            Some(_) => path.to_path_buf(),
+            None => path.with_extension(recording::recording_extension(&stream.codec)),
+        };
+        let recording = Recording::start(
+            &path,
+            stream.frame_tx.subscribe(),
+            &stream.codec,
+            stream.sample_rate,
+            stream.channels,
+        )
+        .await?;
+        let status = recording.status();
+        stream.recording = Some(recording);
+        
+        Ok(status)
+    }
+    
+    /// Stop recording a stream, returning the finished recording's status
+    pub async fn stop_recording(&self, track_id: &str) -> std::result::Result<RecordingStatus, RecordingError> {
+        let streams = self.streams.read().await;
+        let recording = streams
+            .get(track_id)
+            .ok_or_else(|| RecordingError::NotFound(track_id.to_string()))?
+            .recording
+            .as_ref()
+            .filter(|recording| recording.is_active())
+            .ok_or_else(|| RecordingError::NotRecording(track_id.to_string()))?;
+        
+        Ok(recording.stop().await)
+    }
+    
+    /// Start capturing a stream's frames to `<capture_dir>/<name>.<n>.sscap`
+    ///
+    /// See [`Capture`] for the segment format. Like a recording, a capture
+    /// that failed or was stopped can be replaced by starting another.
+    pub async fn start_capture(&self, track_id: &str, name: &str) -> std::result::Result<CaptureStatus, RecordingError> {
+        let dir = &self.config.capture_dir;
+        tokio::fs::create_dir_all(dir).await?;
+        
+        let mut streams = self.streams.write().await;
+        let stream = streams
+            .get_mut(track_id)
+            .ok_or_else(|| RecordingError::NotFound(track_id.to_string()))?;
+        if stream.capture.as_ref().is_some_and(Capture::is_active) {
+            return Err(RecordingError::AlreadyRecording(track_id.to_string()));
+        }
+        
+        let capture = Capture::start(
+            dir,
+            name,
+            stream.frame_tx.subscribe(),
+            &stream.codec,
+            self.config.capture_segment_bytes,
+        )
+        .await?;
+        let status = capture.status();
+        stream.capture = Some(capture);
+        
+        Ok(status)
+    }
+    
+    /// Stop capturing a stream, returning the finished capture's status
+    pub async fn stop_capture(&self, track_id: &str) -> std::result::Result<CaptureStatus, RecordingError> {
+        let streams = self.streams.read().await;
+        let capture = streams
+            .get(track_id)
+            .ok_or_else(|| RecordingError::NotFound(track_id.to_string()))?
+            .capture
+            .as_ref()
+            .filter(|capture| capture.is_active())
+            .ok_or_else(|| RecordingError::NotRecording(track_id.to_string()))?;
+        
+        Ok(capture.stop().await)
+    }
+    
+    /// Read the capture `name` back from the capture directory
+    pub async fn load_capture(&self, name: &str) -> std::result::Result<CapturedStream, RecordingError> {
+        capture::read_capture(&self.config.capture_dir, name).await
+    }
+    
+    /// Re-ingest captured frames on a live stream, as if their producer
+    /// sent them again
+    ///
+    /// Frames keep their payload, type, sequence and spacing. Their
+    /// timestamps are shifted by one offset so the first frame presents a
+    /// prebuffer ahead of now, and each is ingested when its original gap
+    /// to the first has passed. Returns the number of frames replayed.
+    pub async fn replay_capture(&self, track_id: &str, captured: CapturedStream) -> Result<u64> {
+        let Some(first) = captured.frames.first() else {
+            return Ok(0);
+        };
+        let offset = self.clock_manager.now().await + self.config.prebuffer_ms as f64 / 1000.0 - first.timestamp;
+        let first_timestamp = first.timestamp;
+        let started = tokio::time::Instant::now();
+        info!(
+            "Replaying {} captured {} frames on {}",
+            captured.frames.len(),
+            captured.codec,
+            track_id
+        );
+        
+        let mut replayed = 0;
+        for frame in captured.frames {
+            let due = Duration::from_secs_f64((frame.timestamp - first_timestamp).max(0.0));
+            tokio::time::sleep_until(started + due).await;
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(self.server_id, frame.sequence),
+                track_id: track_id.to_string(),
+                chunk_index: frame.sequence,
+                timestamp: frame.timestamp + offset,
+                duration: frame.duration.as_secs_f64(),
+                data: frame.data.into(),
+                codec: captured.codec.clone(),
+                is_keyframe: frame.frame_type == buffer::FrameType::VideoKeyframe,
+                compression: None,
+            };
+            self.ingest_chunk(self.server_id, chunk).await?;
+            replayed += 1;
+        }
+        
+        Ok(replayed)
+    }
+    
+    /// Current queue contents
+    pub fn queue_status(&self) -> QueueStatus {
+        self.queue.lock().status(self.config.queue_gap_ms, self.config.queue_crossfade_ms)
+    }
+    
+    /// Append items to the play queue
+    ///
+    /// Every track must be in the catalog or have a stream.
+    pub async fn enqueue(&self, items: Vec<QueueItem>) -> std::result::Result<QueueStatus, CatalogError> {
+        for item in &items {
+            let known = self.streams.read().await.contains_key(&item.track_id)
+                || self.catalog.get(&item.track_id).await.is_some();
+            if !known {
+                return Err(CatalogError::NotFound(item.track_id.clone()));
+            }
+        }
+        
+        self.queue.lock().extend(items);
+        self.prepare_crossfade().await;
+        Ok(self.queue_status())
+    }
+    
+    /// Remove a waiting queue item by position
+    pub async fn remove_queue_item(&self, index: usize) -> Option<QueueItem> {
+        let removed = self.queue.lock().remove(index);
+        if index == 0 && removed.is_some() {
+            self.prepare_crossfade().await;
+        }
+        removed
+    }
+    
+    /// Stop the current queue item and start the next one shortly from now
+    ///
+    /// Returns the announced Play command, or `None` if the queue is empty.
+    pub async fn skip(&self) -> Result<Option<MediaControlMessage>> {
+        let current = self.queue.lock().current().map(|item| item.track_id.clone());
+        let now = self.clock_manager.now().await;
+        
+        if let Some(track_id) = current {
+            if let Some(stream) = self.streams.write().await.get_mut(&track_id) {
+                stream.stop_playback().await;
+                stream.state = PlaybackState::Stopped;
+            }
+            self.announce(self.command(MediaAction::Stop, track_id, now, MediaParams::default()));
+        }
+        
+        self.advance_queue(now + SKIP_LEAD_SECS, None).await
+    }
+    
+    /// Start the next playable queue item at `start_at` and announce it
+    ///
+    /// With a `handoff` position the item was already crossfaded into the
+    /// previous one and continues from there.
+    async fn advance_queue(
+        &self,
+        start_at: f64,
+        mut handoff: Option<f64>,
+    ) -> Result<Option<MediaControlMessage>> {
+        loop {
+            let Some(mut item) = self.queue.lock().advance() else {
+                info!("Play queue finished");
+                return Ok(None);
+            };
+            if let Some(position) = handoff.take() {
+                item.params.seek_position = Some(position);
+            }
+            
+            let cmd = self.command(MediaAction::Play, item.track_id, start_at, item.params);
+            match self.process_control(cmd.clone()).await {
+                Ok(()) => {
+                    self.announce(cmd.clone());
+                    self.prepare_crossfade().await;
+                    return Ok(Some(cmd));
+                }
+                // Keep the rotation going past tracks that cannot be played
+                Err(e) => error!("Skipping queued track {}: {}", cmd.track_id, e),
+            }
+        }
+    }
+    
+    /// Advance the queue when its current item has played to the end, and
+    /// tear down finished test tones
+    async fn handle_playback_finished(&self, finished: PlaybackFinished) -> Result<()> {
+        let next_sequence = self
+            .streams
+            .read()
+            .await
+            .get(&finished.track_id)
+            .map(|stream| stream.sequence.load(Ordering::Relaxed));
+        if let Some(next_sequence) = next_sequence {
+            self.end_client_playback(&finished.track_id, next_sequence).await;
+        }
+        
+        if self.test_tones.lock().contains(&finished.track_id) {
+            info!("Test tone {} finished", finished.track_id);
+            self.delete_stream(&finished.track_id).await;
+            return Ok(());
+        }
+        
+        let is_current = self
+            .queue
+            .lock()
+            .current()
+            .is_some_and(|item| item.track_id == finished.track_id);
+        
+        if is_current {
+            let gap = match finished.handoff {
+                Some(_) => 0.0,
+                None => self.config.queue_gap_ms as f64 / 1000.0,
+            };
+            self.advance_queue(finished.end_at + gap, finished.handoff).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Mix the next queue item into the end of the current one when a
+    /// crossfade applies, falling back to a gapless cut if it cannot
+    async fn prepare_crossfade(&self) {
+        let (current, next) = {
+            let queue = self.queue.lock();
+            (queue.current().cloned(), queue.next().cloned())
+        };
+        let Some(current) = current else {
+            return;
+        };
+        let crossfade_ms = next
+            .as_ref()
+            .map_or(0, |next| next.crossfade_ms.unwrap_or(self.config.queue_crossfade_ms));
+        
+        let crossfade = match next.filter(|_| crossfade_ms > 0) {
+            Some(next) => match self.crossfade_source(&current.track_id, &next.track_id).await {
+                Ok((source, gain)) => Some(Crossfade {
+                    source,
+                    duration: Duration::from_millis(crossfade_ms),
+                    gain,
+                }),
+                Err(e) => {
+                    warn!(
+                        "Cutting from {} to {} without a crossfade: {}",
+                        current.track_id, next.track_id, e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        
+        if let Some(playback) = self
+            .streams
+            .read()
+            .await
+            .get(&current.track_id)
+            .and_then(|stream| stream.playback.as_ref())
+        {
+            playback.set_crossfade(crossfade);
+        }
+    }
+    
+    /// Source and normalization gain of `next` for mixing into the end of
+    /// `current`
+    ///
+    /// Both tracks must decode to PCM at the same sample rate, and `next`
+    /// must not be playing on its own.
+    async fn crossfade_source(&self, current: &str, next: &str) -> Result<(SharedSource, f64)> {
+        if current == next {
+            anyhow::bail!("a track cannot crossfade into itself");
+        }
+        self.load_from_catalog(next, None).await?;
+        
+        let streams = self.streams.read().await;
+        let source_of = |track_id: &str| {
+            streams
+                .get(track_id)
+                .and_then(|stream| stream.source.clone())
+                .ok_or_else(|| anyhow::anyhow!("track {} is not loaded", track_id))
+        };
+        let (current_source, next_source) = (source_of(current)?, source_of(next)?);
+        if streams[next].state() != PlaybackState::Stopped {
+            anyhow::bail!("track {} is already playing", next);
+        }
+        
+        let (current_source, next_source) = (current_source.lock(), next_source.lock());
+        for source in [&current_source, &next_source] {
+            if source.codec() != "pcm16" {
+                anyhow::bail!("{} passthrough cannot be mixed", source.codec());
+            }
+        }
+        if current_source.sample_rate() != next_source.sample_rate() {
+            anyhow::bail!(
+                "sample rates differ ({}Hz and {}Hz)",
+                current_source.sample_rate(),
+                next_source.sample_rate()
+            );
+        }
+        drop((current_source, next_source));
+        
+        Ok((source_of(next)?, streams[next].gain))
+    }
+    
+    /// Build a control command issued by this server
+    fn command(
+        &self,
+        action: MediaAction,
+        track_id: String,
+        start_at: f64,
+        params: MediaParams,
+    ) -> MediaControlMessage {
+        MediaControlMessage {
+            header: MessageHeader::new(self.server_id, 0),
+            action,
+            track_id,
+            start_at,
+            params,
+            zone: None,
+        }
+    }
+    
+    /// Peer connection of a media client
+    pub async fn peer_connection(&self, client_id: Uuid) -> Option<Arc<RTCPeerConnection>> {
+        self.clients
+            .read()
+            .await
+            .get(&client_id)
+            .map(|client| client.peer_connection.clone())
+    }
+    
+    /// Apply a client's SDP answer to the latest offer on its peer
+    /// connection
+    pub async fn apply_answer(&self, client_id: Uuid, sdp: String) -> Result<()> {
+        let peer_connection = self
+            .peer_connection(client_id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No peer connection for client {}", client_id))?;
+        let answer = RTCSessionDescription::answer(fec::omit_declined_fec(&sdp))?;
+        let preferred = self.webrtc_server.answer_preference(&answer)?;
+        let accepts_fec = self.webrtc_server.answer_accepts_opus_fec(&answer)?;
+        let track_codecs = self.clients.read().await.get(&client_id).map(|client| client.track_codecs);
+        let track_codecs = track_codecs.unwrap_or_default();
+        
+        // Applying the answer binds the tracks, which have to carry a codec
+        // it accepts by then; the client's preferred one is chosen
+        let mut replaced = Vec::new();
+        for transceiver in peer_connection.get_transceivers().await {
+            let kind = transceiver.kind();
+            let Some(codec) = preferred.get(kind).filter(|codec| track_codecs.get(kind) != Some(*codec)) else {
+                continue;
+            };
+            let sender = MediaSender::new(codec, client_id.to_string());
+            transceiver.sender().await.replace_track(Some(sender.track())).await?;
+            info!("Switched {} track of {} to {}", kind, client_id, codec);
+            replaced.push(sender);
+        }
+        if !replaced.is_empty() {
+            let mut clients = self.clients.write().await;
+            if let Some(client) = clients.get_mut(&client_id) {
+                for sender in &replaced {
+                    client.track_codecs.set(sender.codec());
+                }
+                client.queue.lock().replaced_senders.extend(replaced);
+                client.frames_ready.notify_one();
+            }
+        }
+        
+        WebRtcServer::handle_answer(&peer_connection, answer).await?;
+        
+        // Only codecs the transceivers settled on count as negotiated
+        let mut clients = self.clients.write().await;
+        let Some(client) = clients.get_mut(&client_id) else {
+            return Ok(());
+        };
+        let mut negotiated = MediaCodecs::default();
+        for transceiver in peer_connection.get_transceivers().await {
+            let kind = transceiver.kind();
+            let Some(codec) = client.track_codecs.get(kind).filter(|_| preferred.get(kind).is_some()) else {
+                continue;
+            };
+            let parameters = transceiver.sender().await.get_parameters().await;
+            let settled = parameters
+                .rtp_parameters
+                .codecs
+                .iter()
+                .any(|c| WebRtcCodec::from_mime_type(&c.capability.mime_type) == Some(codec));
+            if settled {
+                negotiated.set(codec);
+            }
+        }
+        if negotiated != client.negotiated_codecs {
+            info!(
+                "Client {} negotiated audio {:?} and video {:?}",
+                client_id, negotiated.audio, negotiated.video
+            );
+        }
+        client.negotiated_codecs = negotiated;
+        // FEC is carried in the Opus frames themselves
+        client.fec_negotiated = accepts_fec && negotiated.audio == Some(WebRtcCodec::Opus);
+        if !client.fec_negotiated {
+            self.loss_feedback.remove(client_id);
+        }
+        Ok(())
+    }
+    
+    /// Bytes sent over a client's peer connection since it was added
+    pub async fn client_egress(&self, client_id: Uuid) -> Option<Egress> {
+        self.clients.read().await.get(&client_id).map(|client| client.egress.snapshot())
+    }
+    
+    /// Subscribe to the frames published on a stream
+    #[cfg(test)]
+    pub async fn subscribe_frames(&self, track_id: &str) -> Option<broadcast::Receiver<MediaFrame>> {
+        self.streams
+            .read()
+            .await
+            .get(track_id)
+            .map(|stream| stream.frame_tx.subscribe())
+    }
+    
+    /// Publish a chunk from a producer client to its live stream
+    ///
+    /// The stream is created on the first chunk. Chunks are reordered by
+    /// `chunk_index` within a small window before being broadcast.
+    pub async fn ingest_chunk(&self, producer_id: Uuid, mut chunk: MediaDataMessage) -> Result<()> {
+        // Compressed payloads are restored at the edge, bounded by the
+        // largest chunk accepted uncompressed
+        self.compressors
+            .read()
+            .decompress_chunk(&mut chunk, self.config.max_media_message_bytes)?;
+        let duration = Duration::try_from_secs_f64(chunk.duration.max(0.0))
+            .map_err(|_| anyhow::anyhow!("Invalid chunk duration: {}", chunk.duration))?;
+        
+        if !self.streams.read().await.contains_key(&chunk.track_id) {
+            info!("Producer {} started live stream {}", producer_id, chunk.track_id);
+            // The chunk's duration gives the stream's frame rate
+            let capacity = channel_capacity(&chunk.codec, Some(chunk.duration));
+            self.create_zone_stream(chunk.track_id.clone(), None, chunk.codec.clone(), capacity)
+                .await?;
+        }
+        
+        let mut streams = self.streams.write().await;
+        let stream = streams
+            .get_mut(&chunk.track_id)
+            .ok_or_else(|| anyhow::anyhow!("Track not found: {}", chunk.track_id))?;
+        
+        if stream.playback.as_ref().is_some_and(|p| p.is_running()) {
+            anyhow::bail!("Track {} is playing a file", chunk.track_id);
+        }
+        if stream.codec != chunk.codec {
+            anyhow::bail!(
+                "Chunk codec {} does not match stream {} ({})",
+                chunk.codec,
+                chunk.track_id,
+                stream.codec
+            );
+        }
+        stream.producer = Some(producer_id);
+        
+        let frame = MediaFrame {
+            data: chunk.data.into(),
+            timestamp: chunk.timestamp,
+            duration,
+            frame_type: ingest::frame_type(&chunk.codec, chunk.is_keyframe),
+            sequence: chunk.chunk_index,
+            renditions: Arc::default(),
+        };
+        let track_id = stream.track_id.clone();
+        let ready = stream
+            .reorder
+            .get_or_insert_with(|| ReorderBuffer::new(track_id, REORDER_WINDOW))
+            .push(frame);
+        
+        
+        let released = {
+            let mut groups = self.sync_groups.lock();
+            match groups.values_mut().find(|group| group.contains(&chunk.track_id)) {
+                Some(group) => {
+                    let now = std::time::Instant::now();
+                    let mut released = Vec::new();
+                    for frame in ready {
+                        released.extend(group.push(&chunk.track_id, frame, now));
+                    }
+                    released
+                }
+                None => ready.into_iter().map(|frame| (chunk.track_id.clone(), frame)).collect(),
+            }
+        };
+        publish_live(&streams, released);
+        
+        Ok(())
+    }
+    
+    /// Tie live tracks together so their frames are released in sync
+    ///
+    /// A track belongs to at most one group. The tracks' streams need not
+    /// exist yet; live streams are created by their first chunk.
+    pub fn create_sync_group(&self, name: String, tracks: Vec<String>, slack: Duration) -> Result<()> {
+        if tracks.len() < 2 {
+            anyhow::bail!("A sync group needs at least two tracks");
+        }
+        
+        let mut groups = self.sync_groups.lock();
+        if groups.contains_key(&name) {
+            anyhow::bail!("Sync group {} already exists", name);
+        }
+        if let Some((track_id, group)) = tracks.iter().find_map(|track_id| {
+            groups
+                .values()
+                .find(|group| group.contains(track_id))
+                .map(|group| (track_id, group))
+        }) {
+            anyhow::bail!("Track {} is already in sync group {}", track_id, group.name());
+        }
+        
+        info!("Created sync group {} for {:?}", name, tracks);
+        groups.insert(name.clone(), SyncGroup::new(name, tracks, slack));
+        Ok(())
+    }
+    
+    /// Dissolve a sync group, releasing the frames it holds
+    pub async fn remove_sync_group(&self, name: &str) -> bool {
+        let streams = self.streams.read().await;
+        let Some(mut group) = self.sync_groups.lock().remove(name) else {
+            return false;
+        };
+        
+        publish_live(&streams, group.drain());
+        info!("Removed sync group {}", name);
+        true
+    }
+    
+    /// Release frames held by sync groups for longer than their slack
+    async fn release_sync_groups(&self) {
+        let streams = self.streams.read().await;
+        let now = std::time::Instant::now();
+        let released: Vec<_> = self
+            .sync_groups
+            .lock()
+            .values_mut()
+            .flat_map(|group| group.release(now))
+            .collect();
+        
+        publish_live(&streams, released);
+    }
+    
+    /// State of every sync group, sorted by name
+    pub fn sync_groups(&self) -> Vec<SyncGroupStatus> {
+        let mut groups: Vec<_> = self.sync_groups.lock().values().map(SyncGroup::status).collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+    
+    /// Future buffer a client starts with
+    fn new_future_buffer(&self) -> DynamicFutureBuffer {
+        DynamicFutureBuffer::with_policy(
+            Duration::from_millis(80),
+            NetworkQuality::Good,
+            self.config.buffer_policy.clone(),
+        )
+    }
+    
+    /// Add media client
+    pub async fn add_client(&self, client_id: Uuid) -> Result<()> {
+        let peer_connection = self.webrtc_server.create_peer_connection().await?;
+        self.watch_peer_state(client_id, &peer_connection);
+        
+        // Tracks have to be on the connection before the offer; they start
+        // out with the preferred codecs and switch to the ones the client's
+        // answer prefers
+        let mut track_codecs = MediaCodecs::default();
+        let audio_codec = self
+            .webrtc_server
+            .default_codec(RTPCodecType::Audio)
+            .ok_or_else(|| anyhow::anyhow!("No audio codec is registered"))?;
+        let audio = MediaSender::new(audio_codec, client_id.to_string());
+        track_codecs.set(audio_codec);
+        let rtp_sender = peer_connection.add_track(audio.track()).await?;
+        self.spawn_link_monitor(client_id, rtp_sender);
+        let video = match self.webrtc_server.default_codec(RTPCodecType::Video) {
+            Some(codec) => {
+                let video = MediaSender::new(codec, client_id.to_string());
+                track_codecs.set(codec);
+                let rtp_sender = peer_connection.add_track(video.track()).await?;
+                self.spawn_video_link_monitor(client_id, rtp_sender, codec.clock_rate());
+                Some(video)
+            }
+            None => None,
+        };
+        let egress = Arc::new(EgressCounter::default());
+        let clock_endpoint = Arc::new(ClockEndpoint::new(client_id, self.clock_manager.clone(), egress.clone()));
+        let clock_channel = clock_channel::open(&peer_connection, clock_endpoint).await?;
+        
+        let client = MediaClient {
+            client_id,
+            device_id: None,
+            codecs: CodecSupport::default(),
+            transcodes: HashMap::new(),
+            peer_connection,
+            clock_channel,
+            queue: Arc::new(parking_lot::Mutex::new(ClientQueue::new(self.new_future_buffer()))),
+            network_quality: NetworkQuality::Good,
+            link: None,
+            fec: FecController::new(self.config.loss_recovery.fec_loss_threshold_percent),
+            fec_negotiated: false,
+            subscriptions: HashMap::new(),
+            shutdown: CancellationToken::new(),
+            frames_ready: Arc::new(Notify::new()),
+            frames_delivered: Arc::new(AtomicU64::new(0)),
+            frames_dropped: Arc::new(AtomicU64::new(0)),
+            egress,
+            quality_tier: Arc::new(AtomicU8::new(QualityTier::for_quality(NetworkQuality::Good) as u8)),
+            keyframe_requests: 0,
+            disconnected_since: None,
+            ice_restarts: 0,
+            track_codecs,
+            negotiated_codecs: MediaCodecs::default(),
+        };
+        self.spawn_client_pacer(&client, audio, video);
+        
+        self.failed_peers.lock().remove(&client_id);
+        self.clients.write().await.insert(client_id, client);
+        info!("Added media client: {}", client_id);
+        
+        Ok(())
+    }
+    
+    /// Report the state changes of a client's peer connection to the run
+    /// loop
+    ///
+    /// The connection state stays `new` after an ICE restart, so ICE
+    /// connecting again is reported as the connection doing so; the DTLS
+    /// session carries over the restart.
+    fn watch_peer_state(&self, client_id: Uuid, peer_connection: &Arc<RTCPeerConnection>) {
+        let send = {
+            let events = self.peer_state_tx.clone();
+            let connection = Arc::downgrade(peer_connection);
+            move |state| {
+                let _ = events.send(PeerStateEvent {
+                    client_id,
+                    connection: connection.clone(),
+                    state,
+                });
+            }
+        };
+        
+        let on_state = send.clone();
+        peer_connection.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+            info!("Peer connection of {} is {}", client_id, state);
+            on_state(state);
+            Box::pin(async {})
+        }));
+        peer_connection.on_ice_connection_state_change(Box::new(move |state: RTCIceConnectionState| {
+            if state == RTCIceConnectionState::Connected {
+                send(RTCPeerConnectionState::Connected);
+            }
+            Box::pin(async {})
+        }));
+    }
+    
+    /// Read the RTCP a client sends about its audio track, keeping the
+    /// metrics of its reception reports and moving its network quality
+    /// with them
+    ///
+    /// The RTCP has to be read in any case for interceptors such as NACK
+    /// responders to see it.
+    fn spawn_link_monitor(&self, client_id: Uuid, rtp_sender: Arc<RTCRtpSender>) {
+        let clients = self.clients.clone();
+        let policy = self.config.buffer_policy.clone();
+        let loss_feedback = self.loss_feedback.clone();
+        
+        tokio::spawn(async move {
+            let mut estimator = None;
+            let mut buf = vec![0u8; 1500];
+            while let Ok((packets, _)) = rtp_sender.read(&mut buf).await {
+                let mut clients = clients.write().await;
+                let Some(client) = clients.get_mut(&client_id) else {
+                    continue;
+                };
+                let now = link::ntp_middle(SystemTime::now());
+                let clock_rate = client.track_codecs.audio.map_or(48_000, WebRtcCodec::clock_rate);
+                let reports = link::parse_reports(&packets, now, clock_rate);
+                if reports.is_empty() {
+                    continue;
+                }
+                
+                let estimator = estimator.get_or_insert_with(|| LinkEstimator::new(client.network_quality));
+                for metrics in reports {
+                    client.link = Some(metrics);
+                    client.update_fec(metrics.loss_percent, &loss_feedback);
+                    if let Some(quality) = estimator.update(metrics) {
+                        client.set_network_quality(quality);
+                        info!(
+                            "Client {} link is now {:?} ({:.1}% loss, rtt {:?}ms), buffer: {}ms",
+                            client_id,
+                            quality,
+                            metrics.loss_percent,
+                            metrics.rtt_ms.map(|rtt| rtt.round()),
+                            policy.recommended(quality).as_millis()
+                        );
+                    }
+                }
+            }
+        });
+    }
+    
+    /// Read the RTCP a client sends about its video track, keeping the
+    /// metrics of its reception reports for choosing its video tiers and
+    /// passing on the keyframes its PLIs and FIRs ask for
+    ///
+    /// As with audio, interceptors only see the RTCP that is read.
+    fn spawn_video_link_monitor(&self, client_id: Uuid, rtp_sender: Arc<RTCRtpSender>, clock_rate: u32) {
+        let clients = self.clients.clone();
+        let streams = self.streams.clone();
+        let video_tiers = self.video_tiers.clone();
+        let keyframe_requests = self.keyframe_requests.clone();
+        
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            while let Ok((packets, _)) = rtp_sender.read(&mut buf).await {
+                let now = link::ntp_middle(SystemTime::now());
+                let metrics = link::parse_reports(&packets, now, clock_rate).pop();
+                let reason = keyframe::requested_keyframe(&packets);
+                if metrics.is_none() && reason.is_none() {
+                    continue;
+                }
+                
+                // The tracks whose frames the client's video track carries
+                let (reason, forwarded) = {
+                    let mut clients = clients.write().await;
+                    let Some(client) = clients.get_mut(&client_id) else {
+                        break;
+                    };
+                    let queue = client.queue.clone();
+                    let mut queue = queue.lock();
+                    if metrics.is_some() {
+                        queue.video_link = metrics;
+                    }
+                    let Some(reason) = reason else {
+                        continue;
+                    };
+                    client.keyframe_requests += 1;
+                    let video_tiers = video_tiers.read();
+                    let forwarded: Vec<_> = client
+                        .subscriptions
+                        .keys()
+                        .map(|id| match (video_tiers.get(id), queue.video_tiers.get(id)) {
+                            (Some(video), Some(switch)) => {
+                                let tier = switch.forwarding().unwrap_or(switch.status(id).target);
+                                video.tiers.get(&tier).cloned().unwrap_or_else(|| id.clone())
+                            }
+                            _ => id.clone(),
+                        })
+                        .collect();
+                    (reason, forwarded)
+                };
+                
+                debug!("Client {} asked for a keyframe ({:?})", client_id, reason);
+                let streams = streams.read().await;
+                for track_id in forwarded {
+                    if let Some(stream) = streams.get(&track_id) {
+                        stream.request_keyframe(reason, &keyframe_requests);
+                    }
+                }
+            }
+        });
+    }
+    
+    /// Release a client's queued frames one buffer depth ahead of their
+    /// presentation, smoothing bursts from the sources
+    ///
+    /// Forwarders queue frames in the client's future buffer and wake the
+    /// task, which otherwise sleeps until the next frame is due. The task
+    /// only takes the client's queue lock, never the client map's. Frames
+    /// are written to the client's tracks once the queue lock is released:
+    /// those of streams in the codec a track carries as they are, and PCM
+    /// encoded for a G.711 audio track. Other frames are not sent. Where a
+    /// track's RTP timestamps lie on the network clock is announced once
+    /// it writes its first frame.
+    fn spawn_client_pacer(&self, client: &MediaClient, mut audio: MediaSender, mut video: Option<MediaSender>) {
+        let client_id = client.client_id;
+        let shutdown = client.shutdown.clone();
+        let frames_ready = client.frames_ready.clone();
+        let frames_delivered = client.frames_delivered.clone();
+        let frames_dropped = client.frames_dropped.clone();
+        let egress = client.egress.clone();
+        let queue = client.queue.clone();
+        let quality_tier = client.quality_tier.clone();
+        let streams = self.streams.clone();
+        let video_tiers = self.video_tiers.clone();
+        let rtp_clocks = self.rtp_clocks.clone();
+        let clock = self.clock_manager.clone();
+        
+        tokio::spawn(async move {
+            let mut tier_selectors: HashMap<String, TierSelector> = HashMap::new();
+            // Codec, sample rate and channels of each subscribed stream
+            let mut stream_formats: HashMap<String, (String, u32, u8)> = HashMap::new();
+            
+            loop {
+                let now = clock.now().await;
+                let mut outgoing = Vec::new();
+                let next_due = {
+                    let mut queue = queue.lock();
+                    for sender in queue.replaced_senders.drain(..) {
+                        if sender.codec().is_audio() {
+                            audio = sender;
+                        } else {
+                            video = Some(sender);
+                        }
+                    }
+                    for track_id in queue.removed_tracks.drain(..) {
+                        tier_selectors.remove(&track_id);
+                        stream_formats.remove(&track_id);
+                    }
+                    // Frames keep their schedule while the connection
+                    // recovers; the senders hold the most recent of them
+                    let recovering = queue.recovering;
+                    // The client holds frames for as long as they are sent
+                    // ahead of their presentation time
+                    let delay = PlayoutDelay::fixed(Duration::from_secs_f64(queue.future_buffer.target_latency()));
+                    audio.set_paused(recovering);
+                    audio.set_playout_delay(delay);
+                    if let Some(video) = &mut video {
+                        video.set_paused(recovering);
+                        video.set_playout_delay(delay);
+                    }
+                    
+                    let wanted = QualityTier::for_quality(queue.future_buffer.effective_quality());
+                    for QueuedFrame { track_id, frame, late } in queue.future_buffer.pop_ready(now) {
+                        // Switch tiers only between frames
+                        let tier = tier_selectors
+                            .entry(track_id.clone())
+                            .or_insert_with(|| TierSelector::new(wanted))
+                            .select(wanted);
+                        quality_tier.store(tier as u8, Ordering::Relaxed);
+                        
+                        frames_delivered.fetch_add(1, Ordering::Relaxed);
+                        debug!(
+                            "Forwarding frame for client {} at {:.3} ({:.3}s ahead, late: {})",
+                            client_id,
+                            frame.timestamp,
+                            frame.timestamp - now,
+                            late
+                        );
+                        outgoing.push((track_id, frame, tier));
+                    }
+                    
+                    queue.future_buffer.next_due()
+                };
+                
+                // Packets held while the connection recovered go out as soon
+                // as it has, rather than with the next frame
+                if outgoing.is_empty() {
+                    for sender in std::iter::once(&mut audio).chain(video.as_mut()) {
+                        if sender.pending() == 0 {
+                            continue;
+                        }
+                        match sender.flush().await {
+                            Ok(outcome) => egress.record(outcome.sent, outcome.bytes),
+                            Err(e) => {
+                                frames_dropped.fetch_add(1, Ordering::Relaxed);
+                                debug!("Failed to send held frame to client {}: {}", client_id, e);
+                            }
+                        }
+                    }
+                }
+                for (track_id, frame, tier) in outgoing {
+                    let format = match stream_formats.get(&track_id) {
+                        Some(format) => format.clone(),
+                        None => {
+                            // Every tier of a tiered video has the same codec
+                            let source = video_tiers
+                                .read()
+                                .get(&track_id)
+                                .and_then(|video| video.tiers.values().next().cloned())
+                                .unwrap_or_else(|| track_id.clone());
+                            let format = streams
+                                .read()
+                                .await
+                                .get(&source)
+                                .map(|s| (s.codec.clone(), s.sample_rate, s.channels));
+                            let Some(format) = format else {
+                                continue;
+                            };
+                            stream_formats.insert(track_id.clone(), format.clone());
+                            format
+                        }
+                    };
+                    let (codec, sample_rate, channels) = format;
+                    let audio_codec = audio.codec();
+                    let (sender, payload) = if codec == audio_codec.name() {
+                        (&mut audio, frame.data_for(tier).clone())
+                    } else if let Some(encoded) = frame
+                        .rendition(tier)
+                        .filter(|_| codec == "pcm16" && audio_codec == WebRtcCodec::Opus)
+                    {
+                        // Played PCM goes out as its tier's Opus rendition
+                        (&mut audio, encoded.clone())
+                    } else if codec == "pcm16" && matches!(audio_codec, WebRtcCodec::Pcmu | WebRtcCodec::Pcma) {
+                        // Tiers are Opus encodings; G.711 has only one rate
+                        let encoded = g711::encode(audio_codec, &frame.data, sample_rate, channels);
+                        (&mut audio, encoded.into())
+                    } else if let Some(video) = video.as_mut().filter(|video| codec == video.codec().name()) {
+                        (video, frame.data_for(tier).clone())
+                    } else {
+                        continue;
+                    };
+                    // The first frame a sender writes anchors its RTP
+                    // timestamps, which the client needs to place its media
+                    let anchored = sender.clock_mapping().is_some();
+                    let written = sender.write(&frame, payload).await;
+                    if let Some(mapping) = sender.clock_mapping().filter(|_| !anchored) {
+                        let _ = rtp_clocks.send((client_id, sender.codec(), mapping));
+                    }
+                    match written {
+                        Ok(outcome) => {
+                            frames_dropped.fetch_add(outcome.expired as u64, Ordering::Relaxed);
+                            egress.record(outcome.sent, outcome.bytes);
+                        }
+                        Err(e) => {
+                            frames_dropped.fetch_add(1, Ordering::Relaxed);
+                            debug!("Failed to send {} frame to client {}: {}", track_id, client_id, e);
+                        }
+                    }
+                }
+                
+                let wait = next_due.map_or(PACER_IDLE_WAIT, |due| {
+                    Duration::from_secs_f64((due - now).clamp(0.0, PACER_IDLE_WAIT.as_secs_f64()))
+                });
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = frames_ready.notified() => {}
+                    _ = tokio::time::sleep(wait) => {}
+                }
+            }
+        });
+    }
+    
+    /// Remove a media client, closing its peer connection and stopping its forwarders
+    pub async fn remove_client(&self, client_id: Uuid) {
+        self.detach_client(client_id).await;
+    }
+    
+    /// Remove a media client, keeping its subscriptions, zone and buffer
+    /// state for `resume_client`
+    ///
+    /// Frames queued for the client are dropped; its buffer latency and
+    /// statistics are kept. A client already torn down because its peer
+    /// connection failed is detached with the state it had then.
+    pub async fn detach_client(&self, client_id: Uuid) -> Option<DetachedClient> {
+        match self.teardown_client(client_id, None).await {
+            Some(detached) => Some(detached),
+            None => self.failed_peers.lock().remove(&client_id).map(|peer| peer.detached),
+        }
+    }
+    
+    /// Remove a media client, closing its peer connection and stopping its
+    /// forwarders, provided it is still on `connection` when one is given
+    async fn teardown_client(
+        &self,
+        client_id: Uuid,
+        connection: Option<&Weak<RTCPeerConnection>>,
+    ) -> Option<DetachedClient> {
+        let client = {
+            let mut clients = self.clients.write().await;
+            let current = clients.get(&client_id).is_some_and(|client| {
+                connection.is_none_or(|connection| connection.as_ptr() == Arc::as_ptr(&client.peer_connection))
+            });
+            if !current {
+                return None;
+            }
+            clients.remove(&client_id)?
+        };
+        let zone = self.zones.write().leave(client_id);
+        self.loss_feedback.remove(client_id);
+        
+        client.shutdown.cancel();
+        if let Err(e) = client.clock_channel.close().await {
+            debug!("Failed to close clock channel for {}: {}", client_id, e);
+        }
+        if let Err(e) = client.peer_connection.close().await {
+            warn!("Failed to close peer connection for {}: {}", client_id, e);
+        }
+        
+        let subscriptions = client.subscribed_tracks();
+        let future_buffer = {
+            let mut queue = client.queue.lock();
+            for track_id in &subscriptions {
+                queue.remove_track(track_id);
+            }
+            std::mem::replace(&mut queue.future_buffer, self.new_future_buffer())
+        };
+        if let Some(device_id) = client.device_id {
+            self.device_tuning.lock().save(
+                device_id,
+                future_buffer.tuning(),
+                tokio::time::Instant::now(),
+            );
+        }
+        info!("Removed media client: {}", client_id);
+        
+        Some(DetachedClient {
+            device_id: client.device_id,
+            codecs: client.codecs,
+            transcodes: client.transcodes,
+            subscriptions,
+            zone,
+            future_buffer,
+            network_quality: client.network_quality,
+        })
+    }
+    
+    /// Add a client again with the state it had when it was detached
+    ///
+    /// A zone given on reconnect replaces the previous one. Tracks that no
+    /// longer exist are skipped.
+    pub async fn resume_client(
+        &self,
+        client_id: Uuid,
+        detached: DetachedClient,
+        zone: Option<String>,
+    ) -> Result<()> {
+        self.add_client(client_id).await?;
+        if let Some(client) = self.clients.write().await.get_mut(&client_id) {
+            client.device_id = detached.device_id;
+            client.codecs = detached.codecs;
+            client.transcodes = detached.transcodes;
+            client.queue.lock().future_buffer = detached.future_buffer;
+            client.network_quality = detached.network_quality;
+        }
+        
+        // Streams of a zone the client has left are not resumed
+        let left_zone_streams = match (&zone, &detached.zone) {
+            (Some(zone), Some(previous)) if zone != previous => self.zone_streams(previous, false).await,
+            _ => Vec::new(),
+        };
+        if let Some(zone) = zone.or(detached.zone) {
+            self.join_zone(client_id, zone).await?;
+        }
+        for track_id in detached.subscriptions {
+            if left_zone_streams.contains(&track_id) {
+                continue;
+            }
+            if let Err(e) = self.ensure_subscribed(client_id, track_id.clone()).await {
+                warn!("Client {} could not resume {}: {}", client_id, track_id, e);
+            }
+        }
+        
+        info!("Resumed media client: {}", client_id);
+        Ok(())
+    }
+    
+    /// Record the device a newly connected client uses
+    ///
+    /// Its buffer starts from the tuning cached when the device last
+    /// disconnected, if that was within the cache's age. Returns whether
+    /// it did.
+    pub async fn attach_device(&self, client_id: Uuid, device_id: Uuid) -> bool {
+        let mut clients = self.clients.write().await;
+        let Some(client) = clients.get_mut(&client_id) else {
+            return false;
+        };
+        client.device_id = Some(device_id);
+        
+        let Some(tuning) = self.device_tuning.lock().get(&device_id, tokio::time::Instant::now()) else {
+            return false;
+        };
+        client.queue.lock().future_buffer.seed(&tuning);
+        info!(
+            "Client {} starts from the tuning of device {}: {}ms target latency",
+            client_id,
+            device_id,
+            tuning.target_latency.as_millis()
+        );
+        true
+    }
+    
+    /// Record the codecs a client can decode; returns whether the client
+    /// exists
+    ///
+    /// Only later subscriptions are checked against them.
+    pub async fn set_client_codecs(&self, client_id: Uuid, codecs: CodecSupport) -> bool {
+        let mut clients = self.clients.write().await;
+        let Some(client) = clients.get_mut(&client_id) else {
+            return false;
+        };
+        client.codecs = codecs;
+        true
+    }
+    
+    /// Flag a stream to be transcoded to `target_codec` for a client that
+    /// cannot decode its own codec, so that the client may subscribe to it
+    ///
+    /// Fails unless the client decodes the target codec and the stream's
+    /// codec can be transcoded to it. Frames are transcoded as they are
+    /// sent on a track carrying the target codec.
+    pub async fn request_transcode(&self, client_id: Uuid, track_id: String, target_codec: String) -> Result<()> {
+        if !self.streams.read().await.contains_key(&track_id) {
+            self.load_from_catalog(&track_id, None).await?;
+        }
+        let source = self
+            .streams
+            .read()
+            .await
+            .get(&track_id)
+            .map(|stream| stream.codec.clone())
+            .ok_or_else(|| anyhow::anyhow!("Track not found: {}", track_id))?;
+        let target = target_codec.trim().to_ascii_lowercase();
+        
+        let mut clients = self.clients.write().await;
+        let client = clients
+            .get_mut(&client_id)
+            .ok_or_else(|| anyhow::anyhow!("Client not found: {}", client_id))?;
+        if !client.codecs.decodes(&target) {
+            anyhow::bail!("Client {} cannot decode {}", client_id, target);
+        }
+        if source.eq_ignore_ascii_case(&target) {
+            anyhow::bail!("{} is already {}", track_id, target);
+        }
+        if !can_transcode(&source, &target) {
+            anyhow::bail!("Transcoding {} from {} to {} is not supported", track_id, source, target);
+        }
+        info!("Transcoding {} from {} to {} for client {}", track_id, source, target, client_id);
+        client.transcodes.insert(track_id, target);
+        Ok(())
+    }
+    
+    /// Forget the buffer tuning cached for a device; returns whether there
+    /// was any
+    pub fn clear_device_tuning(&self, device_id: Uuid) -> bool {
+        self.device_tuning.lock().remove(&device_id)
+    }
+    
+    /// Tell subscribers of `key` that its playback ended on purpose, so the
+    /// gap after its last frame is not counted as an underrun
+    async fn end_client_playback(&self, key: &str, next_sequence: u64) {
+        for client in self.clients.write().await.values_mut() {
+            if client.subscriptions.contains_key(key) {
+                client.queue.lock().future_buffer.end_playback(key, next_sequence);
+            }
+        }
+    }
+    
+    /// How far ahead to schedule a Play that does not say when to start,
+    /// so every subscriber of the track can start together
+    ///
+    /// That is the deepest future buffer target among the clients
+    /// subscribed to the track in `zone`, or to either track of a program,
+    /// but at least the configured start lead, which is all there is
+    /// without subscribers.
+    pub async fn start_lead(&self, track_id: &str, zone: Option<&str>) -> Duration {
+        let mut keys = vec![stream_key(track_id, zone)];
+        if let Some(program) = self.programs.read().get(track_id) {
+            keys.extend([&program.audio_track, &program.video_track].map(|track| stream_key(track, zone)));
+        }
+        
+        let deepest = self
+            .clients
+            .read()
+            .await
+            .values()
+            .filter(|client| keys.iter().any(|key| client.subscriptions.contains_key(key)))
+            .map(|client| client.queue.lock().future_buffer.target_latency())
+            .fold(0.0, f64::max);
+        Duration::from_secs_f64(deepest).max(Duration::from_millis(self.config.start_lead_ms))
+    }
+    
+    /// Number of running frame forwarding tasks
+    pub fn active_forwarders(&self) -> usize {
+        self.active_forwarders.load(Ordering::Relaxed)
+    }
+    
+    /// Update client network quality
+    #[cfg(test)]
+    pub async fn update_client_quality(&self, client_id: Uuid, quality: NetworkQuality) {
+        if let Some(client) = self.clients.write().await.get_mut(&client_id) {
+            client.set_network_quality(quality);
+            
+            debug!(
+                "Updated client {} network quality: {:?}, buffer: {}ms",
+                client_id,
+                quality,
+                self.config.buffer_policy.recommended(quality).as_millis()
+            );
+        }
+    }
+    
+    /// Subscribe client to a track
+    ///
+    /// Fails if the client is already subscribed, so frames are never
+    /// forwarded twice, or cannot decode the track's codec. Subscribing to
+    /// a tiered video forwards the tier the client's link calls for.
+    pub async fn subscribe_client(&self, client_id: Uuid, track_id: String) -> Result<()> {
+        let tiered = self.video_tiers.read().get(&track_id).cloned();
+        if let Some(video) = tiered {
+            return self.subscribe_tiered_video(client_id, video).await;
+        }
+        
+        // Idle catalog streams are torn down; subscribing brings them back
+        if !self.streams.read().await.contains_key(&track_id) {
+            self.load_from_catalog(&track_id, None).await?;
+        }
+        
+        let streams = self.streams.read().await;
+        let stream = streams
+            .get(&track_id)
+            .ok_or_else(|| anyhow::anyhow!("Track not found: {}", track_id))?;
+        
+        let mut clients = self.clients.write().await;
+        let client = clients
+            .get_mut(&client_id)
+            .ok_or_else(|| anyhow::anyhow!("Client not found: {}", client_id))?;
+        if client.subscriptions.contains_key(&track_id) {
+            anyhow::bail!("Client {} is already subscribed to {}", client_id, track_id);
+        }
+        if !client.receives(&track_id, &stream.codec) {
+            anyhow::bail!("Client {} cannot decode {} ({})", client_id, track_id, stream.codec);
+        }
+        
+        // Cancelled on unsubscribe, or with every other subscription when
+        // the client is removed
+        let cancel = client.shutdown.child_token();
+        client.subscriptions.insert(track_id.clone(), cancel.clone());
+        client.queue.lock().remove_track(&track_id);
+        // Video is forwarded from a keyframe on, so have one sent soon
+        if is_video_codec(&stream.codec) {
+            client.keyframe_requests += 1;
+            stream.request_keyframe(KeyframeRequestReason::Subscribe, &self.keyframe_requests);
+        }
+        drop(clients);
+        
+        self.spawn_forwarder(client_id, stream, &track_id, None, cancel);
+        info!("Subscribed client {} to {}", client_id, track_id);
+        Ok(())
+    }
+    
+    /// Subscribe client to a tiered video, forwarding frames of every tier's
+    /// track through the client's tier switch under the video's stream ID
+    async fn subscribe_tiered_video(&self, client_id: Uuid, video: TieredVideo) -> Result<()> {
+        for track_id in video.tiers.values() {
+            if !self.streams.read().await.contains_key(track_id) {
+                self.load_from_catalog(track_id, None).await?;
+            }
+        }
+        
+        let streams = self.streams.read().await;
+        let mut tier_streams = Vec::new();
+        for (tier, track_id) in &video.tiers {
+            let stream = streams
+                .get(track_id)
+                .ok_or_else(|| anyhow::anyhow!("Track not found: {}", track_id))?;
+            tier_streams.push((*tier, stream));
+        }
+        let codec = &tier_streams[0].1.codec;
+        if tier_streams.iter().any(|(_, stream)| stream.codec != *codec) {
+            anyhow::bail!("Tiers of {} carry different codecs", video.stream_id);
+        }
+        
+        let stream_id = video.stream_id.clone();
+        let mut clients = self.clients.write().await;
+        let client = clients
+            .get_mut(&client_id)
+            .ok_or_else(|| anyhow::anyhow!("Client not found: {}", client_id))?;
+        if client.subscriptions.contains_key(&stream_id) {
+            anyhow::bail!("Client {} is already subscribed to {}", client_id, stream_id);
+        }
+        if !client.codecs.decodes(codec) {
+            anyhow::bail!("Client {} cannot decode {} ({})", client_id, stream_id, codec);
+        }
+        
+        let cancel = client.shutdown.child_token();
+        client.subscriptions.insert(stream_id.clone(), cancel.clone());
+        let target = {
+            let mut queue = client.queue.lock();
+            queue.remove_track(&stream_id);
+            let switch = VideoTierSwitch::new(video.tiers.keys().copied(), queue.wanted_video_tier());
+            let target = switch.status(&stream_id).target;
+            queue.video_tiers.insert(stream_id.clone(), switch);
+            target
+        };
+        client.keyframe_requests += 1;
+        drop(clients);
+        
+        // Forwarding starts at a keyframe of the tier the client starts at
+        if let Some((_, stream)) = tier_streams.iter().find(|(tier, _)| *tier == target) {
+            stream.request_keyframe(KeyframeRequestReason::Subscribe, &self.keyframe_requests);
+        }
+        for (tier, stream) in tier_streams {
+            self.spawn_forwarder(client_id, stream, &stream_id, Some(tier), cancel.clone());
+        }
+        info!("Subscribed client {} to {} starting at {:?}", client_id, stream_id, target);
+        Ok(())
+    }
+    
+    /// Forward a stream's frames to a client's future buffer, queued under
+    /// `queue_id`, until `cancel` fires
+    ///
+    /// The stream carrying `tier` of a tiered video only has the frames
+    /// the client's tier switch admits queued. Frames of any other video
+    /// are queued from its first keyframe on, as the client cannot decode
+    /// delta frames before it.
+    fn spawn_forwarder(
+        &self,
+        client_id: Uuid,
+        stream: &MediaStream,
+        queue_id: &str,
+        tier: Option<QualityTier>,
+        cancel: CancellationToken,
+    ) {
+        let mut frame_rx = stream.frame_tx.subscribe();
+        let mut budget = YieldBudget::new(ForwarderClass::for_codec(&stream.codec), self.config.video_forwarder_budget);
+        let track_id = stream.track_id.clone();
+        let queue_id = queue_id.to_string();
+        let mut awaiting_keyframe = tier.is_none() && is_video_codec(&stream.codec);
+        
+        let clients = self.clients.clone();
+        let clock = self.clock_manager.clone();
+        let concealment_requests = self.concealment_requests.clone();
+        let guard = ForwarderGuard::new(self.active_forwarders.clone());
+        
+        tokio::spawn(async move {
+            let _guard = guard;
+            // The client map is only read here; frames go through the
+            // client's own queue lock
+            let (queue, frames_ready, frames_dropped) = match clients.read().await.get(&client_id) {
+                Some(client) => (client.queue.clone(), client.frames_ready.clone(), client.frames_dropped.clone()),
+                None => return,
+            };
+            
+            loop {
+                let frame = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    frame = frame_rx.recv() => frame,
+                };
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // The receiver resumes at the oldest buffered frame
+                        warn!(
+                            "Client {} fell behind on {}, skipped {} frames",
+                            client_id, track_id, skipped
+                        );
+                        frames_dropped.fetch_add(skipped, Ordering::Relaxed);
+                        queue.lock().future_buffer.report_underrun();
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                
+                // Frames lost before this one are concealed by the client's
+                // decoder rather than played as a gap; the frame then waits
+                // in the client's future buffer for the pacing task
+                let arrival = clock.now().await;
+                let (request, dropped) = {
+                    let mut queue = queue.lock();
+                    if tier.is_some_and(|tier| !queue.admit_video_frame(client_id, &queue_id, tier, &frame)) {
+                        continue;
+                    }
+                    if awaiting_keyframe {
+                        if frame.frame_type != buffer::FrameType::VideoKeyframe {
+                            continue;
+                        }
+                        awaiting_keyframe = false;
+                    }
+                    queue.future_buffer.record_arrival(&queue_id, &frame, arrival);
+                    (
+                        queue.future_buffer.check_sequence(&queue_id, &frame),
+                        queue.future_buffer.push(&queue_id, frame),
+                    )
+                };
+                if let Some(request) = request {
+                    let _ = concealment_requests.send((client_id, request));
+                }
+                if let Some(dropped) = dropped {
+                    debug!(
+                        "Future buffer of client {} full, dropped frame at {:.3}",
+                        client_id, dropped.frame.timestamp
+                    );
+                    frames_dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                frames_ready.notify_one();
+                
+                // Let other forwarders run during a burst of video frames
+                if budget.spend(frame_rx.len()) {
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+    }
+    
+    /// Unsubscribe client from a track, stopping its forwarding task
+    ///
+    /// Returns whether the client was subscribed; unsubscribing twice is not
+    /// an error.
+    pub async fn unsubscribe_client(&self, client_id: Uuid, track_id: &str) -> Result<bool> {
+        let mut clients = self.clients.write().await;
+        let client = clients
+            .get_mut(&client_id)
+            .ok_or_else(|| anyhow::anyhow!("Client not found: {}", client_id))?;
+        
+        let Some(cancel) = client.subscriptions.remove(track_id) else {
+            return Ok(false);
+        };
+        cancel.cancel();
+        client.queue.lock().remove_track(track_id);
+        
+        info!("Unsubscribed client {} from {}", client_id, track_id);
+        Ok(true)
+    }
+    
+    /// Tracks a client is subscribed to, sorted by track ID
+    pub async fn client_subscriptions(&self, client_id: Uuid) -> Option<Vec<String>> {
+        self.clients
+            .read()
+            .await
+            .get(&client_id)
+            .map(MediaClient::subscribed_tracks)
+    }
+    
+    /// Statistics of a client's future buffer
+    pub async fn get_buffer_stats(&self, client_id: Uuid) -> Option<BufferStats> {
+        self.clients
+            .read()
+            .await
+            .get(&client_id)
+            .map(|client| client.queue.lock().future_buffer.stats())
+    }
+    
+    /// Zero the counters of a client's future buffer, such as after tuning
+    ///
+    /// Returns the statistics afterwards.
+    pub async fn reset_buffer_stats(&self, client_id: Uuid) -> Option<BufferStats> {
+        let mut clients = self.clients.write().await;
+        let client = clients.get_mut(&client_id)?;
+        let mut queue = client.queue.lock();
+        queue.future_buffer.reset_stats();
+        info!("Reset buffer statistics of client {}", client_id);
+        Some(queue.future_buffer.stats())
+    }
+    
+    /// Set the latency bounds a client requested from `source`
+    ///
+    /// Returns the statistics afterwards, or `None` for an unknown client;
+    /// bounds with the minimum over the maximum are an error.
+    pub async fn set_latency_bounds(
+        &self,
+        client_id: Uuid,
+        source: BoundsSource,
+        bounds: LatencyBounds,
+    ) -> Result<Option<BufferStats>> {
+        let mut clients = self.clients.write().await;
+        let Some(client) = clients.get_mut(&client_id) else {
+            return Ok(None);
+        };
+        let stats = {
+            let mut queue = client.queue.lock();
+            queue.future_buffer.set_latency_bounds(source, bounds)?;
+            queue.future_buffer.stats()
+        };
+        info!(
+            "Client {} latency bounds now {}-{}ms ({:?} requested {:?})",
+            client_id, stats.min_latency_ms, stats.max_latency_ms, source, bounds
+        );
+        Ok(Some(stats))
+    }
+    
+    /// Apply a buffer report from a client's audio output to its future
+    /// buffer
+    ///
+    /// Returns the statistics afterwards, with the target latency the
+    /// client should schedule playout with, and the new playback rate hint
+    /// if the report changed it.
+    pub async fn apply_buffer_report(
+        &self,
+        client_id: Uuid,
+        report: &BufferReportMessage,
+    ) -> Option<(BufferStats, Option<f64>)> {
+        let report_duration = |ms: f64| Duration::try_from_secs_f64(ms.max(0.0) / 1000.0).unwrap_or_default();
+        
+        let mut clients = self.clients.write().await;
+        let client = clients.get_mut(&client_id)?;
+        let mut queue = client.queue.lock();
+        let rate = queue.future_buffer.apply_client_report(
+            report.underruns,
+            report.overruns,
+            report_duration(report.occupancy_ms),
+            report_duration(report.playout_delay_ms),
+        );
+        if let Some(rate) = rate {
+            // The pacing task releases frames by the new rate from now on
+            client.frames_ready.notify_one();
+            info!("Client {} playback rate hint now {:.3}", client_id, rate);
+        }
+        if report.underruns > 0 {
+            debug!(
+                "Client {} reported {} underrun(s) on {}",
+                client_id, report.underruns, report.track_id
+            );
+        }
+        Some((queue.future_buffer.stats(), rate))
+    }
+    
+    /// Move a client into a zone
+    ///
+    /// The client leaves its previous zone's streams and is subscribed to
+    /// the streams currently playing in the new one. The zone is saved as
+    /// its device's.
+    pub async fn join_zone(&self, client_id: Uuid, zone: String) -> Result<()> {
+        let Some(device_id) = self.clients.read().await.get(&client_id).map(|client| client.device_id) else {
+            anyhow::bail!("Client not found: {}", client_id);
+        };
+        
+        let (previous, assigned) = {
+            let mut zones = self.zones.write();
+            let assigned = device_id.is_some_and(|device_id| zones.assign_device(device_id, Some(zone.clone())));
+            (zones.join(client_id, zone.clone()), assigned)
+        };
+        if assigned {
+            self.save_state().await;
+        }
+        if previous.as_deref() == Some(zone.as_str()) {
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            self.unsubscribe_from_zone(client_id, &previous).await?;
+        }
+        
+        info!("Client {} joined zone {}", client_id, zone);
+        for key in self.zone_streams(&zone, true).await {
+            self.ensure_subscribed(client_id, key).await?;
+        }
+        Ok(())
+    }
+    
+    /// Remove a client from its zone, returning the zone it left
+    ///
+    /// Its device is no longer put back in the zone either.
+    pub async fn leave_zone(&self, client_id: Uuid) -> Result<Option<String>> {
+        let device_id = self.clients.read().await.get(&client_id).and_then(|client| client.device_id);
+        let (zone, unassigned) = {
+            let mut zones = self.zones.write();
+            let unassigned = device_id.is_some_and(|device_id| zones.assign_device(device_id, None));
+            (zones.leave(client_id), unassigned)
+        };
+        if unassigned {
+            self.save_state().await;
+        }
+        let Some(zone) = zone else {
+            return Ok(None);
+        };
+        
+        info!("Client {} left zone {}", client_id, zone);
+        self.unsubscribe_from_zone(client_id, &zone).await?;
+        Ok(Some(zone))
+    }
+    
+    /// Zone a client belongs to
+    pub fn client_zone(&self, client_id: Uuid) -> Option<String> {
+        self.zones.read().zone_of(client_id).map(str::to_string)
+    }
+    
+    /// Zone a device was last put in, which it rejoins when it connects
+    /// without naming one
+    pub fn device_zone(&self, device_id: Uuid) -> Option<String> {
+        self.zones.read().device_zone(device_id).map(str::to_string)
+    }
+    
+    /// Clients in a zone, sorted
+    pub fn zone_members(&self, zone: &str) -> Vec<Uuid> {
+        self.zones.read().members(zone)
+    }
+    
+    pub fn zone_status(&self, zone: &str) -> ZoneStatus {
+        self.zones.read().status(zone)
+    }
+    
+    /// Keys of the streams in `zone`, optionally only those not stopped
+    async fn zone_streams(&self, zone: &str, active_only: bool) -> Vec<String> {
+        self.streams
+            .read()
+            .await
+            .values()
+            .filter(|s| s.zone.as_deref() == Some(zone))
+            .filter(|s| !active_only || s.state() != PlaybackState::Stopped)
+            .map(MediaStream::key)
+            .collect()
+    }
+    
+    async fn unsubscribe_from_zone(&self, client_id: Uuid, zone: &str) -> Result<()> {
+        for key in self.zone_streams(zone, false).await {
+            self.unsubscribe_client(client_id, &key).await?;
+        }
+        Ok(())
+    }
+    
+    /// Subscribe a client to a stream unless it already is, or cannot
+    /// decode it
+    async fn ensure_subscribed(&self, client_id: Uuid, key: String) -> Result<()> {
+        let codec = self.streams.read().await.get(&key).map(|s| s.codec.clone());
+        let (subscribed, decodes) = {
+            let clients = self.clients.read().await;
+            let client = clients.get(&client_id);
+            (
+                client.is_some_and(|c| c.subscriptions.contains_key(&key)),
+                client.zip(codec).is_none_or(|(c, codec)| c.receives(&key, &codec)),
+            )
+        };
+        if !decodes {
+            debug!("Not subscribing {} to {}, whose codec it cannot decode", client_id, key);
+            return Ok(());
+        }
+        if !subscribed {
+            self.subscribe_client(client_id, key).await?;
+        }
+        Ok(())
+    }
+    
+    /// Tie an audio and a video track into a program sharing one timeline
+    ///
+    /// Commands addressed to `program_id` then act on both tracks. Without
+    /// an offset the configured `av_offset_ms` is used.
+    pub async fn create_program(
+        &self,
+        program_id: String,
+        audio_track: String,
+        video_track: String,
+        av_offset_ms: Option<f64>,
+    ) -> Result<()> {
+        if audio_track == video_track {
+            anyhow::bail!("A program needs two different tracks");
+        }
+        if self.streams.read().await.values().any(|s| s.track_id == program_id)
+            || self.catalog.get(&program_id).await.is_some()
+        {
+            anyhow::bail!("Program ID {} is already a track", program_id);
+        }
+        
+        {
+            let mut programs = self.programs.write();
+            if programs.contains_key(&program_id) {
+                anyhow::bail!("Program {} already exists", program_id);
+            }
+            for track_id in [&audio_track, &video_track] {
+                if let Some(program) = programs.values().find(|p| p.contains(track_id)) {
+                    anyhow::bail!("Track {} is already in program {}", track_id, program.program_id);
+                }
+            }
+        
+            let program = Program {
+                program_id: program_id.clone(),
+                audio_track,
+                video_track,
+                av_offset: av_offset_ms.unwrap_or(self.config.av_offset_ms) / 1000.0,
+            };
+            info!(
+                "Created program {}: audio {}, video {} ({:+.0}ms)",
+                program_id,
+                program.audio_track,
+                program.video_track,
+                program.av_offset * 1000.0
+            );
+            programs.insert(program_id, program);
+        }
+        
+        self.save_state().await;
+        Ok(())
+    }
+    
+    /// Remove a program, leaving its tracks as they are
+    pub async fn remove_program(&self, program_id: &str) -> bool {
+        let removed = self.programs.write().remove(program_id).is_some();
+        if removed {
+            self.save_state().await;
+        }
+        removed
+    }
+    
+    /// Bind the tracks carrying encodings of one video at different
+    /// qualities into a stream subscribed to as one
+    ///
+    /// Two or three tiers are needed, each on its own track. The tracks
+    /// may be loaded or fed later, but must all carry the same codec by the
+    /// time a client subscribes.
+    pub async fn create_tiered_video(&self, video: TieredVideo) -> Result<()> {
+        let stream_id = &video.stream_id;
+        if stream_id.is_empty() {
+            anyhow::bail!("A tiered video needs a stream ID");
+        }
+        if video.tiers.len() < 2 {
+            anyhow::bail!("A tiered video needs at least two tiers");
+        }
+        let tracks: HashSet<_> = video.tiers.values().collect();
+        if tracks.len() < video.tiers.len() || tracks.contains(stream_id) {
+            anyhow::bail!("Each tier of {} needs a track of its own", stream_id);
+        }
+        if self.streams.read().await.values().any(|s| s.track_id == *stream_id)
+            || self.catalog.get(stream_id).await.is_some()
+            || self.programs.read().contains_key(stream_id)
+        {
+            anyhow::bail!("Stream ID {} is already a track or program", stream_id);
+        }
+        
+        {
+            let mut video_tiers = self.video_tiers.write();
+            if video_tiers.contains_key(stream_id) {
+                anyhow::bail!("Tiered video {} already exists", stream_id);
+            }
+            for track_id in video.tiers.values() {
+                if let Some(other) = video_tiers.values().find(|other| other.contains(track_id)) {
+                    anyhow::bail!("Track {} is already a tier of {}", track_id, other.stream_id);
+                }
+            }
+            info!("Created tiered video {}: {:?}", stream_id, video.tiers);
+            video_tiers.insert(stream_id.clone(), video);
+        }
+        
+        self.save_state().await;
+        Ok(())
+    }
+    
+    /// Remove a tiered video, leaving its tracks as they are
+    ///
+    /// Clients keep receiving the tier they are on until they unsubscribe.
+    pub async fn remove_tiered_video(&self, stream_id: &str) -> bool {
+        let removed = self.video_tiers.write().remove(stream_id).is_some();
+        if removed {
+            self.save_state().await;
+        }
+        removed
+    }
+    
+    /// Every tiered video, sorted by stream ID
+    pub fn video_tiers(&self) -> Vec<TieredVideo> {
+        let mut videos: Vec<_> = self.video_tiers.read().values().cloned().collect();
+        videos.sort_by(|a, b| a.stream_id.cmp(&b.stream_id));
+        videos
+    }
+    
+    /// State of every program, sorted by ID
+    pub async fn programs(&self) -> Vec<ProgramStatus> {
+        let programs: Vec<_> = self.programs.read().values().cloned().collect();
+        let streams = self.streams.read().await;
+        
+        let mut statuses: Vec<_> = programs
+            .into_iter()
+            .map(|program| {
+                let audio = streams.get(&program.audio_track);
+                ProgramStatus {
+                    state: audio.map_or(PlaybackState::Stopped, MediaStream::state),
+                    position: audio.and_then(|stream| stream.stats.position()),
+                    av_offset_ms: program.av_offset * 1000.0,
+                    program_id: program.program_id,
+                    audio_track: program.audio_track,
+                    video_track: program.video_track,
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.program_id.cmp(&b.program_id));
+        statuses
+    }
+    
+    /// Process media control command
+    ///
+    /// A command with a zone acts on that zone's own stream of the track and
+    /// leaves other zones untouched. A command addressed to a program acts
+    /// on both of its tracks, and one addressed to a tiered video on the
+    /// tracks of all its tiers.
+    ///
+    /// Everything logged while processing it, playback included, is in a
+    /// span carrying the ID of the HTTP request it came from, if any.
+    pub async fn process_control(&self, cmd: MediaControlMessage) -> Result<()> {
+        let span = tracing::info_span!(
+            "media_control",
+            action = ?cmd.action,
+            track_id = %cmd.track_id,
+            request_id = cmd.header.request_id.as_deref(),
+        );
+        let program = self.programs.read().get(&cmd.track_id).cloned();
+        if let Some(program) = program {
+            return self.process_program_control(&program, cmd).instrument(span).await;
+        }
+        let tiered = self.video_tiers.read().get(&cmd.track_id).cloned();
+        match tiered {
+            Some(video) => self.process_tiered_video_control(&video, cmd).instrument(span).await,
+            None => self.process_track_control(cmd).instrument(span).await,
+        }
+    }
+    
+    /// Apply a command to the tracks of every tier of a tiered video
+    ///
+    /// Tiers are switched at keyframes, so they only line up when played
+    /// on one timeline; a Play that fails on one tier stops the others.
+    async fn process_tiered_video_control(&self, video: &TieredVideo, cmd: MediaControlMessage) -> Result<()> {
+        if matches!(cmd.action, MediaAction::Load) {
+            anyhow::bail!("Load the tiers of {} individually", video.stream_id);
+        }
+        
+        let commands = video.track_commands(&cmd);
+        for (index, track_cmd) in commands.iter().enumerate() {
+            let Err(e) = self.process_track_control(track_cmd.clone()).await else {
+                continue;
+            };
+            if matches!(cmd.action, MediaAction::Play) {
+                for started in &commands[..index] {
+                    let stop = MediaControlMessage {
+                        action: MediaAction::Stop,
+                        params: MediaParams::default(),
+                        ..started.clone()
+                    };
+                    if let Err(e) = self.process_track_control(stop).await {
+                        warn!("Failed to stop {} of {}: {}", started.track_id, video.stream_id, e);
+                    }
+                }
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+    
+    /// Apply a command to both tracks of a program
+    ///
+    /// A seek is validated on both tracks before either moves, and a Play
+    /// whose video fails to start stops the audio again.
+    async fn process_program_control(&self, program: &Program, cmd: MediaControlMessage) -> Result<()> {
+        if matches!(cmd.action, MediaAction::Load) {
+            anyhow::bail!("Load the tracks of program {} individually", program.program_id);
+        }
+        
+        let [audio, video] = program.track_commands(&cmd);
+        if matches!(cmd.action, MediaAction::Seek) {
+            let streams = self.streams.read().await;
+            for track_cmd in [&audio, &video] {
+                let key = stream_key(&track_cmd.track_id, track_cmd.zone.as_deref());
+                streams
+                    .get(&key)
+                    .ok_or_else(|| anyhow::anyhow!("Track not found: {}", key))?
+                    .check_seek(&track_cmd.params)?;
+            }
+        }
+        
+        self.process_track_control(audio.clone()).await?;
+        if let Err(e) = self.process_track_control(video).await {
+            if matches!(cmd.action, MediaAction::Play) {
+                let stop = MediaControlMessage {
+                    action: MediaAction::Stop,
+                    params: MediaParams::default(),
+                    ..audio
+                };
+                if let Err(e) = self.process_track_control(stop).await {
+                    warn!("Failed to stop audio of program {}: {}", program.program_id, e);
+                }
+            }
+            return Err(e);
+        }
+        
+        Ok(())
+    }
+    
+    /// Process a media control command for a single track
+    async fn process_track_control(&self, cmd: MediaControlMessage) -> Result<()> {
+        let key = stream_key(&cmd.track_id, cmd.zone.as_deref());
+        match cmd.action {
+            MediaAction::Subscribe | MediaAction::Unsubscribe => {
+                anyhow::bail!("{:?} must be sent by a connected client", cmd.action);
+            }
+            MediaAction::Load => {
+                let source = cmd
+                    .params
+                    .source
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("Load requires a source path"))?;
+                let path = self.media_path(source)?;
+                self.load_track(&cmd.track_id, cmd.zone.clone(), path).await?;
+            }
+            MediaAction::Unload => {
+                info!("Unload track {}", key);
+                self.delete_stream(&key).await;
+            }
+            MediaAction::Play => {
+                info!("Play track {} at {}", key, cmd.start_at);
+                self.load_from_catalog(&cmd.track_id, cmd.zone.as_deref()).await?;
+                
+                let mut streams = self.streams.write().await;
+                let stream = streams
+                    .get_mut(&key)
+                    .ok_or_else(|| anyhow::anyhow!("Track not found: {}", key))?;
+                stream.check_fades(&cmd.params)?;
+                let loop_count = match (stream.state(), cmd.params.loop_count) {
+                    (_, Some(loop_count)) => loop_count,
+                    (PlaybackState::Stopped, None) => 0,
+                    (_, None) => stream.loop_count,
+                };
+                let stop_at =
+                    scheduled_stop(&cmd.params, cmd.start_at, stream.remaining(loop_count))?;
+                
+                // Playback that was stopped or ran to the end starts over, or
+                // from the requested position; resuming a paused stream keeps
+                // its loop progress
+                if stream.state() == PlaybackState::Stopped {
+                    if let Some(source) = &stream.source {
+                        let position = cmd.params.seek_position.unwrap_or(0.0);
+                        source.lock().seek(position)?;
+                        stream.stats.set_position(Some(position));
+                    }
+                    stream.loop_iteration.store(0, Ordering::Relaxed);
+                    stream.loop_count = cmd.params.loop_count.unwrap_or(0);
+                } else if let Some(loop_count) = cmd.params.loop_count {
+                    stream.loop_count = loop_count;
+                }
+                stream.fade_out_ms = cmd.params.fade_out_ms;
+                stream.stop_at = stop_at;
+                let params = stream.playback_params(cmd.start_at, None, cmd.params.fade_in_ms);
+                stream.start_playback(self.clock_manager.clone(), params).await?;
+                drop(streams);
+                
+                if let Some(zone) = &cmd.zone {
+                    for client_id in self.zone_members(zone) {
+                        self.ensure_subscribed(client_id, key.clone()).await?;
+                    }
+                }
+                
+                // Clients learn of the stop in advance and fade out locally
+                if let Some(stop_at) = stop_at {
+                    let fade_out_ms = cmd.params.fade_out_ms.unwrap_or(0);
+                    let params = MediaParams {
+                        fade_out_ms: cmd.params.fade_out_ms,
+                        ..Default::default()
+                    };
+                    let fade_start = stop_at - fade_out_ms as f64 / 1000.0;
+                    self.announce(MediaControlMessage {
+                        zone: cmd.zone,
+                        ..self.command(MediaAction::Stop, cmd.track_id, fade_start, params)
+                    });
+                }
+            }
+            MediaAction::Pause => {
+                info!("Pause track {}", key);
+                if let Some(stream) = self.streams.write().await.get_mut(&key) {
+                    stream.check_fades(&cmd.params)?;
+                    if stream.state() == PlaybackState::Playing {
+                        stream.state = PlaybackState::Paused;
+                    }
+                    let fade_out_ms = cmd.params.fade_out_ms.or(stream.fade_out_ms);
+                    // Frames already emitted are still presented, and playback
+                    // resumes after the last of them
+                    stream
+                        .fade_out_playback(&self.clock_manager, cmd.start_at, fade_out_ms)
+                        .await;
+                    self.end_client_playback(&key, stream.sequence.load(Ordering::Relaxed)).await;
+                }
+            }
+            MediaAction::Seek => {
+                let mut streams = self.streams.write().await;
+                let stream = streams
+                    .get_mut(&key)
+                    .ok_or_else(|| anyhow::anyhow!("Track not found: {}", key))?;
+                let (source, position) = stream.check_seek(&cmd.params)?;
+                info!("Seek track {} to {:.3}s at {}", key, position, cmd.start_at);
+                
+                // Presentation timestamps restart from the seek point, so every
+                // client resumes from the same sample at start_at
+                stream.stop_playback().await;
+                self.end_client_playback(&key, stream.sequence.load(Ordering::Relaxed)).await;
+                source.lock().seek(position)?;
+                stream.stats.set_position(Some(position));
+                let params =
+                    stream.playback_params(cmd.start_at, Some(position), cmd.params.fade_in_ms);
+                stream.start_playback(self.clock_manager.clone(), params).await?;
+                drop(streams);
+                
+                self.announce(cmd);
+            }
+            MediaAction::Stop => {
+                info!("Stop track {}", key);
+                if let Some(stream) = self.streams.write().await.get_mut(&key) {
+                    stream.check_fades(&cmd.params)?;
+                    let fade_out_ms = cmd.params.fade_out_ms.or(stream.fade_out_ms);
+                    stream
+                        .fade_out_playback(&self.clock_manager, cmd.start_at, fade_out_ms)
+                        .await;
+                    self.end_client_playback(&key, stream.sequence.load(Ordering::Relaxed)).await;
+                    stream.state = PlaybackState::Stopped;
+                    stream.loop_iteration.store(0, Ordering::Relaxed);
+                    if let Some(source) = &stream.source {
+                        source.lock().seek(0.0)?;
+                        stream.stats.set_position(Some(0.0));
+                    }
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Validate the stop a Play command schedules before it is queued
+    ///
+    /// Uses the loaded stream, or the catalog entry if nothing is loaded.
+    pub async fn check_scheduled_stop(&self, cmd: &MediaControlMessage) -> Result<()> {
+        // A program's tracks share the audio track's timeline
+        let program_audio = self.programs.read().get(&cmd.track_id).map(|p| p.audio_track.clone());
+        let track_id = program_audio.as_deref().unwrap_or(&cmd.track_id);
+        let key = stream_key(track_id, cmd.zone.as_deref());
+        let remaining = match self.streams.read().await.get(&key) {
+            Some(stream) if stream.source.is_some() => {
+                let loop_count = cmd.params.loop_count.unwrap_or(stream.loop_count);
+                stream.remaining(loop_count)
+            }
+            _ => {
+                let loop_count = cmd.params.loop_count.unwrap_or(0);
+                let duration = self.catalog.get(track_id).await.and_then(|t| t.duration);
+                duration.filter(|_| loop_count != LOOP_FOREVER).map(|d| d * (loop_count as f64 + 1.0))
+            }
+        };
+        
+        scheduled_stop(&cmd.params, cmd.start_at, remaining)?;
+        Ok(())
+    }
+    
+    /// Load a catalog track into its stream if nothing is loaded yet
+    async fn load_from_catalog(&self, track_id: &str, zone: Option<&str>) -> Result<()> {
+        let loaded = self
+            .streams
+            .read()
+            .await
+            .get(&stream_key(track_id, zone))
+            .is_some_and(|s| s.source.is_some());
+        
+        if !loaded {
+            if let Some(track) = self.catalog.get(track_id).await {
+                self.load_track(track_id, zone.map(str::to_string), track.path).await?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Resolve a Load command's source path, which must lie inside `media_dir`
+    ///
+    /// Both paths are canonicalized first, so `..` components and symlinks
+    /// cannot lead a client to files elsewhere on the server.
+    fn media_path(&self, source: &str) -> Result<PathBuf> {
+        let media_dir = self
+            .config
+            .media_dir
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Media directory {:?} is unavailable: {}", self.config.media_dir, e))?;
+        let path = Path::new(source)
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Cannot open {}: {}", source, e))?;
+        if !path.starts_with(&media_dir) {
+            anyhow::bail!("{} is outside the media directory", source);
+        }
+        Ok(path)
+    }
+    
+    /// Open a media file and attach it to a stream, creating the stream if needed
+    ///
+    /// Every zone opens the file separately, so each has its own read position.
+    /// Catalog tracks are played at their loudness normalization gain.
+    async fn load_track(&self, track_id: &str, zone: Option<String>, path: PathBuf) -> Result<()> {
+        let gain = self
+            .catalog
+            .get(track_id)
+            .await
+            .filter(|track| track.path == path)
+            .map_or(1.0, |track| track.gain());
+        let source = tokio::task::spawn_blocking(move || FileSource::open(path)).await??;
+        
+        info!(
+            "Loaded track {}: codec={}, {}Hz, {} channels, duration={:?}",
+            track_id,
+            source.codec(),
+            source.sample_rate(),
+            source.channels(),
+            source.duration()
+        );
+        
+        self.attach_source(track_id, zone, Box::new(source), gain).await
+    }
+    
+    /// Attach a frame source to a stream, creating the stream if needed
+    async fn attach_source(
+        &self,
+        track_id: &str,
+        zone: Option<String>,
+        source: Box<dyn FrameSource>,
+        gain: f64,
+    ) -> Result<()> {
+        let key = stream_key(track_id, zone.as_deref());
+        if !self.streams.read().await.contains_key(&key) {
+            let capacity = channel_capacity(source.codec(), None);
+            self.create_zone_stream(track_id.to_string(), zone, source.codec().to_string(), capacity)
+                .await?;
+        }
+        
+        let mut streams = self.streams.write().await;
+        let stream = streams
+            .get_mut(&key)
+            .ok_or_else(|| anyhow::anyhow!("Track not found: {}", key))?;
+        
+        stream.stop_playback().await;
+        stream.state = PlaybackState::Stopped;
+        stream.codec = source.codec().to_string();
+        stream.sample_rate = source.sample_rate();
+        stream.channels = source.channels();
+        stream.source = Some(Arc::new(parking_lot::Mutex::new(source)));
+        stream.gain = gain;
+        
+        Ok(())
+    }
+    
+    /// Play a generated test tone on every client, or every client in a zone
+    ///
+    /// The tone gets a stream of its own that is deleted once it has played.
+    /// Returns the announced Play command.
+    pub async fn play_test_tone(
+        &self,
+        params: ToneParams,
+        start_at: f64,
+        zone: Option<String>,
+    ) -> Result<MediaControlMessage> {
+        params.validate()?;
+        
+        let track_id = format!("tone_{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let key = stream_key(&track_id, zone.as_deref());
+        self.attach_source(&track_id, zone.clone(), Box::new(ToneSource::new(params)), 1.0)
+            .await?;
+        self.test_tones.lock().insert(key.clone());
+        info!("Playing {:?} test tone {} at {}", params.waveform, key, start_at);
+        
+        let clients = match &zone {
+            Some(zone) => self.zone_members(zone),
+            None => self.clients.read().await.keys().copied().collect(),
+        };
+        for client_id in clients {
+            self.ensure_subscribed(client_id, key.clone()).await?;
+        }
+        
+        let cmd = MediaControlMessage {
+            zone,
+            ..self.command(MediaAction::Play, track_id, start_at, MediaParams::default())
+        };
+        if let Err(e) = self.process_control(cmd.clone()).await {
+            self.delete_stream(&key).await;
+            return Err(e);
+        }
+        self.announce(cmd.clone());
+        
+        Ok(cmd)
+    }
+    
+    /// Run the media server
+    pub async fn run(self: Arc<Self>) {
+        info!("Media server started");
+        
+        let mut control_rx = self
+            .control_rx
+            .lock()
+            .take()
+            .expect("Media server is already running");
+        let mut finished_rx = self
+            .finished_rx
+            .lock()
+            .take()
+            .expect("Media server is already running");
+        let mut stats_interval = tokio::time::interval(Duration::from_secs(5));
+        let progress_ms = self.config.progress_interval_ms;
+        let mut progress_interval = tokio::time::interval(Duration::from_millis(progress_ms.max(1)));
+        let mut sync_interval = tokio::time::interval(SYNC_RELEASE_INTERVAL);
+        let idle_grace_ms = self.config.idle_stream_grace_ms;
+        let mut idle_interval = tokio::time::interval(IDLE_SWEEP_INTERVAL);
+        let mut peer_state_rx = self
+            .peer_state_rx
+            .lock()
+            .take()
+            .expect("Media server is already running");
+        let mut peer_interval = tokio::time::interval(PEER_SWEEP_INTERVAL);
+        let mut webrtc_stats_interval = tokio::time::interval(WEBRTC_STATS_INTERVAL);
+        
+        loop {
+            tokio::select! {
+                _ = sync_interval.tick() => {
+                    self.release_sync_groups().await;
+                }
+                
+                _ = stats_interval.tick() => {
+                    self.log_stats().await;
+                }
+                
+                _ = progress_interval.tick(), if progress_ms > 0 => {
+                    self.publish_progress().await;
+                }
+                
+                _ = idle_interval.tick(), if idle_grace_ms > 0 => {
+                    self.teardown_idle_streams().await;
+                }
+                
+                _ = peer_interval.tick() => {
+                    self.expire_disconnected_peers().await;
+                }
+                
+                _ = webrtc_stats_interval.tick() => {
+                    self.collect_webrtc_stats().await;
+                }
+                
+                Some(event) = peer_state_rx.recv() => {
+                    self.handle_peer_state(event).await;
+                }
+                
+                cmd = control_rx.recv() => {
+                    let Some(cmd) = cmd else {
+                        break;
+                    };
+                    let request_id = cmd.header.request_id.clone();
+                    if let Err(e) = self.process_control(cmd).await {
+                        error!(request_id = request_id.as_deref(), "Error processing control command: {}", e);
+                    }
+                }
+                
+                Some(finished) = finished_rx.recv() => {
+                    if let Err(e) = self.handle_playback_finished(finished).await {
+                        error!("Error advancing play queue: {}", e);
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Follow a change of a client's peer connection state
+    ///
+    /// A connection that stays disconnected past the configured grace, or
+    /// fails, is offered an ICE restart, since a client moving to another
+    /// network can usually carry on over a new candidate pair. Each restart
+    /// gets the same grace to connect. Once the configured number of
+    /// restarts has run out, or the connection closes, the client is torn
+    /// down. Changes of a connection the client no longer uses are ignored.
+    async fn handle_peer_state(&self, event: PeerStateEvent) {
+        let restart = {
+            let mut clients = self.clients.write().await;
+            let Some(client) = clients
+                .get_mut(&event.client_id)
+                .filter(|client| event.connection.as_ptr() == Arc::as_ptr(&client.peer_connection))
+            else {
+                return;
+            };
+            match event.state {
+                RTCPeerConnectionState::Disconnected => {
+                    client.disconnected_since.get_or_insert_with(tokio::time::Instant::now);
+                    client.queue.lock().recovering = true;
+                    None
+                }
+                RTCPeerConnectionState::Failed => self.take_ice_restart(client),
+                RTCPeerConnectionState::Connected => {
+                    if client.ice_restarts > 0 {
+                        info!(
+                            "Peer connection of {} recovered after {} ICE restart(s)",
+                            client.client_id, client.ice_restarts
+                        );
+                    }
+                    client.disconnected_since = None;
+                    client.queue.lock().recovering = false;
+                    client.ice_restarts = 0;
+                    client.frames_ready.notify_one();
+                    None
+                }
+                _ => None,
+            }
+        };
+        
+        match (event.state, restart) {
+            (_, Some(peer_connection)) => self.restart_ice(event.client_id, &peer_connection).await,
+            (RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed, None) => {
+                self.fail_peer(event.client_id, &event.connection, event.state).await;
+            }
+            _ => {}
+        }
+    }
+    
+    /// Restart ICE on the peer connections that stayed disconnected past
+    /// their grace, tearing down those out of restarts
+    async fn expire_disconnected_peers(&self) {
+        let grace = Duration::from_millis(self.config.peer_disconnect_grace_ms);
+        let mut restarts = Vec::new();
+        let mut expired = Vec::new();
+        for client in self.clients.write().await.values_mut() {
+            if client.disconnected_since.is_none_or(|since| since.elapsed() < grace) {
+                continue;
+            }
+            match self.take_ice_restart(client) {
+                Some(peer_connection) => restarts.push((client.client_id, peer_connection)),
+                None => expired.push((client.client_id, Arc::downgrade(&client.peer_connection))),
+            }
+        }
+        
+        for (client_id, peer_connection) in restarts {
+            self.restart_ice(client_id, &peer_connection).await;
+        }
+        for (client_id, connection) in expired {
+            warn!("Peer connection of {} did not recover, giving up on it", client_id);
+            self.fail_peer(client_id, &connection, RTCPeerConnectionState::Failed).await;
+        }
+    }
+    
+    /// Count an ICE restart for a client and start its grace over,
+    /// returning its peer connection unless it has used up its restarts
+    fn take_ice_restart(&self, client: &mut MediaClient) -> Option<Arc<RTCPeerConnection>> {
+        if client.ice_restarts >= self.config.max_ice_restarts {
+            return None;
+        }
+        client.ice_restarts += 1;
+        client.disconnected_since = Some(tokio::time::Instant::now());
+        client.queue.lock().recovering = true;
+        Some(client.peer_connection.clone())
+    }
+    
+    /// Offer a client an ICE restart of its peer connection
+    async fn restart_ice(&self, client_id: Uuid, peer_connection: &Arc<RTCPeerConnection>) {
+        match WebRtcServer::create_restart_offer(peer_connection).await {
+            Ok(offer) => {
+                info!("Restarting ICE for {}", client_id);
+                self.ice_restarts.fetch_add(1, Ordering::Relaxed);
+                // No subscribers just means nobody is listening yet
+                let _ = self.ice_restart_offers.send((client_id, offer));
+            }
+            Err(e) => warn!("Failed to restart ICE for {}: {}", client_id, e),
+        }
+    }
+    
+    /// Tear down a client whose peer connection failed or closed, keeping
+    /// its state for when its control connection detaches it
+    async fn fail_peer(&self, client_id: Uuid, connection: &Weak<RTCPeerConnection>, state: RTCPeerConnectionState) {
+        let Some(detached) = self.teardown_client(client_id, Some(connection)).await else {
+            return;
+        };
+        warn!("Tore down media client {} after its peer connection {}", client_id, state);
+        self.failed_peers.lock().insert(client_id, FailedPeer { state, detached });
+    }
+    
+    /// Log server statistics
+    async fn log_stats(&self) {
+        let stats = self.stats().await;
+        self.stats_snapshots.fetch_add(1, Ordering::Relaxed);
+        let frames_emitted: u64 = stats.streams.iter().map(|s| s.frames_emitted).sum();
+        
+        debug!(
+            "Media server stats: {} streams, {} clients, {} forwarders, {} frames emitted",
+            stats.streams.len(),
+            stats.clients.len(),
+            stats.active_forwarders,
+            frames_emitted
+        );
+    }
+    
+    /// Stream, client and command loop health
+    pub async fn health(&self) -> MediaHealth {
+        let running = self.control_rx.lock().is_none();
+        let (streams, playing_streams) = {
+            let streams = self.streams.read().await;
+            let playing = streams.values().filter(|s| s.state() == PlaybackState::Playing).count();
+            (streams.len(), playing)
+        };
+        
+        let clients = self.clients.read().await;
+        let failed_connections = clients
+            .values()
+            .filter(|c| c.peer_connection.connection_state() == RTCPeerConnectionState::Failed)
+            .count()
+            + self.failed_peers.lock().len();
+        
+        let state = if !running {
+            HealthState::Down
+        } else if failed_connections > 0 {
+            HealthState::Degraded
+        } else {
+            HealthState::Ok
+        };
+        
+        MediaHealth {
+            state,
+            streams,
+            playing_streams,
+            clients: clients.len(),
+            failed_connections,
+            underruns: clients.values().map(|c| c.queue.lock().future_buffer.stats().underrun_count).sum(),
+            frames_dropped: clients.values().map(|c| c.frames_dropped.load(Ordering::Relaxed)).sum(),
+        }
+    }
+    
+    /// Collect the WebRTC statistics of every client's peer connection,
+    /// replacing those of the previous collection
+    pub async fn collect_webrtc_stats(&self) {
+        let peers: Vec<_> = self
+            .clients
+            .read()
+            .await
+            .values()
+            .map(|client| (client.client_id, client.peer_connection.clone(), client.negotiated_codecs))
+            .collect();
+        
+        let mut collected = HashMap::with_capacity(peers.len());
+        for (client_id, peer_connection, negotiated_codecs) in peers {
+            let report = peer_connection.get_stats().await;
+            let stats = WebRtcStats::from_report(
+                client_id,
+                peer_connection.connection_state().to_string(),
+                negotiated_codecs,
+                &report,
+                crate::protocol::get_current_time(),
+            );
+            collected.insert(client_id, stats);
+        }
+        *self.webrtc_stats.lock() = collected;
+    }
+    
+    /// WebRTC statistics of each client's peer connection as last
+    /// collected, in client ID order
+    pub fn webrtc_stats(&self) -> Vec<WebRtcStats> {
+        let mut stats: Vec<_> = self.webrtc_stats.lock().values().cloned().collect();
+        stats.sort_by_key(|stats| stats.client_id);
+        stats
+    }
+    
+    /// Snapshot of stream and client statistics
+    ///
+    /// Values are copied out under the stream and client locks, which are
+    /// released before the snapshot is returned.
+    pub async fn stats(&self) -> MediaStats {
+        let now = self.clock_manager.now().await;
+        let mut streams: Vec<_> = self.streams.read().await.values().map(|s| s.stats(now)).collect();
+        streams.sort_by(|a, b| a.status.track_id.cmp(&b.status.track_id));
+        
+        let mut clients: Vec<_> = self
+            .clients
+            .read()
+            .await
+            .values()
+            .map(|client| (client, client.queue.lock()))
+            .map(|(client, queue)| ClientStats {
+                client_id: client.client_id,
+                device_id: client.device_id,
+                subscribed_tracks: client.subscribed_tracks(),
+                network_quality: client.network_quality,
+                quality_tier: QualityTier::from_index(client.quality_tier.load(Ordering::Relaxed)),
+                buffer: queue.future_buffer.stats(),
+                frames_delivered: client.frames_delivered.load(Ordering::Relaxed),
+                frames_dropped: client.frames_dropped.load(Ordering::Relaxed),
+                egress: client.egress.snapshot(),
+                link: client.link,
+                video_link: queue.video_link,
+                fec: client.fec_status(),
+                keyframe_requests: client.keyframe_requests,
+                video_tiers: {
+                    let mut tiers: Vec<_> = queue
+                        .video_tiers
+                        .iter()
+                        .map(|(stream_id, switch)| switch.status(stream_id))
+                        .collect();
+                    tiers.sort_by(|a, b| a.stream_id.cmp(&b.stream_id));
+                    tiers
+                },
+                connection_state: client.peer_connection.connection_state().to_string(),
+                negotiated_codecs: client.negotiated_codecs,
+                transcodes: client.transcodes.clone().into_iter().collect(),
+                clock: None,
+            })
+            .collect();
+        clients.sort_by_key(|c| c.client_id);
+        for client in &mut clients {
+            client.clock = self.clock_manager.get_peer_stats(&client.client_id).await;
+        }
+        
+        let mut disconnected_clients: Vec<_> = self
+            .failed_peers
+            .lock()
+            .iter()
+            .map(|(client_id, peer)| DisconnectedClientStats {
+                client_id: *client_id,
+                connection_state: peer.state.to_string(),
+                subscribed_tracks: peer.detached.subscriptions.clone(),
+            })
+            .collect();
+        disconnected_clients.sort_by_key(|c| c.client_id);
+        
+        MediaStats {
+            streams,
+            clients,
+            active_forwarders: self.active_forwarders(),
+            sync_groups: self.sync_groups(),
+            disconnected_clients,
+            ice_restarts: self.ice_restarts.load(Ordering::Relaxed),
+            stats_snapshots: self.stats_snapshots.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Broadcast released live frames on their tracks' streams
+fn publish_live(streams: &HashMap<String, MediaStream>, frames: Vec<(String, MediaFrame)>) {
+    for (track_id, frame) in frames {
+        let Some(stream) = streams.get(&track_id) else {
+            continue;
+        };
+        stream.stats.record_frame(frame.data.len());
+        // No subscribers is not an error for a live stream either
+        if stream.frame_tx.send(frame).is_err() {
+            debug!("No subscribers for {}", track_id);
+        }
+    }
+}
+
+/// Network time at which a Play command stops on its own, if any
+///
+/// The earlier of `stop_at` and `start_at + max_duration_ms` applies. It must
+/// fall after `start_at` and, when `remaining` seconds of track are known,
+/// within them.
+fn scheduled_stop(params: &MediaParams, start_at: f64, remaining: Option<f64>) -> Result<Option<f64>> {
+    let by_duration = params.max_duration_ms.map(|ms| start_at + ms as f64 / 1000.0);
+    let Some(stop_at) = [params.stop_at, by_duration].into_iter().flatten().reduce(f64::min) else {
+        return Ok(None);
+    };
+    
+    if stop_at <= start_at {
+        anyhow::bail!("stop_at {:.3} must be after start_at {:.3}", stop_at, start_at);
+    }
+    if let Some(remaining) = remaining {
+        // Allow for rounding in millisecond durations
+        if stop_at - start_at > remaining + 1e-3 {
+            anyhow::bail!(
+                "Scheduled stop after {:.3}s exceeds the {:.3}s left to play",
+                stop_at - start_at,
+                remaining
+            );
+        }
+    }
+    
+    Ok(Some(stop_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{MediaAction, MessageHeader};
+
+    fn control(action: MediaAction, track_id: &str, start_at: f64, source: Option<String>) -> MediaControlMessage {
+        MediaControlMessage {
+            header: MessageHeader::new(Uuid::new_v4(), 0),
+            action,
+            track_id: track_id.to_string(),
+            start_at,
+            params: MediaParams {
+                volume: None,
+                loop_count: None,
+                fade_in_ms: None,
+                fade_out_ms: None,
+                seek_position: None,
+                source,
+                stop_at: None,
+                max_duration_ms: None,
+            },
+            zone: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_and_play_wav() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        // 110ms of stereo audio
+        source::write_test_wav(&path, 48000, 2, 5280);
+
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let source = Some(path.to_string_lossy().into_owned());
+        server
+            .process_control(control(MediaAction::Load, "track", 0.0, source))
+            .await
+            .unwrap();
+
+        let mut frame_rx = server.streams.read().await["track"].frame_tx.subscribe();
+
+        let start_at = server.clock_manager.now().await + 0.05;
+        server
+            .process_control(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+
+        let mut frames = Vec::new();
+        while let Ok(Ok(frame)) =
+            tokio::time::timeout(Duration::from_millis(500), frame_rx.recv()).await
+        {
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 6);
+        let total: Duration = frames.iter().map(|f| f.duration).sum();
+        assert_eq!(total, Duration::from_millis(110));
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.sequence, i as u64);
+            assert!((frame.timestamp - (start_at + i as f64 * 0.02)).abs() < 1e-6);
+        }
+
+        server
+            .process_control(control(MediaAction::Unload, "track", 0.0, None))
+            .await
+            .unwrap();
+        assert!(!server.streams.read().await.contains_key("track"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_load_unsupported_format_fails() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.txt", Uuid::new_v4()));
+        std::fs::write(&path, b"not audio").unwrap();
+
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let source = Some(path.to_string_lossy().into_owned());
+        let result = server
+            .process_control(control(MediaAction::Load, "track", 0.0, source))
+            .await;
+
+        assert!(result.is_err());
+        assert!(!server.streams.read().await.contains_key("track"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_load_outside_media_dir_fails() {
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&media_dir).unwrap();
+        let outside = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        source::write_test_wav(&outside, 48000, 1, 960);
+        let inside = media_dir.join("track.wav");
+        source::write_test_wav(&inside, 48000, 1, 960);
+
+        let config = ServerConfig { media_dir: media_dir.clone(), ..ServerConfig::default() };
+        let server = MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config));
+        let escaping = media_dir.join("..").join(outside.file_name().unwrap());
+        for source in [&outside, &escaping] {
+            let source = Some(source.to_string_lossy().into_owned());
+            let result = server
+                .process_control(control(MediaAction::Load, "track", 0.0, source))
+                .await;
+            assert!(result.unwrap_err().to_string().contains("outside the media directory"));
+        }
+        assert!(!server.streams.read().await.contains_key("track"));
+
+        let source = Some(inside.to_string_lossy().into_owned());
+        server
+            .process_control(control(MediaAction::Load, "track", 0.0, source))
+            .await
+            .unwrap();
+
+        std::fs::remove_file(&outside).ok();
+        std::fs::remove_dir_all(&media_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_catalog_track_plays_by_id() {
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&media_dir).unwrap();
+        let temp_path = media_dir.join(".upload");
+        source::write_test_wav(&temp_path, 48000, 2, 960);
+
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let track = server
+            .register_upload(&temp_path, &media_dir, "ab".repeat(32), 3884, Some("wav".into()))
+            .await
+            .unwrap();
+        assert_eq!(track.codec, "pcm16");
+        assert!(track.path.exists());
+        assert!(!temp_path.exists());
+
+        let start_at = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, &track.track_id, start_at, None))
+            .await
+            .unwrap();
+        assert!(server.streams.read().await[&track.track_id].source.is_some());
+
+        server.delete_track(&track.track_id).await.unwrap();
+        assert!(server.catalog().get(&track.track_id).await.is_none());
+        assert!(!track.path.exists());
+
+        std::fs::remove_dir_all(&media_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_catalog_programs_and_device_zones_survive_restart() {
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&media_dir).unwrap();
+        let temp_path = media_dir.join(".upload");
+        source::write_test_wav(&temp_path, 48000, 2, 960);
+        let config = Arc::new(ServerConfig {
+            state_file: Some(media_dir.join("state.json")),
+            ..Default::default()
+        });
+
+        let server = MediaServer::with_config(Arc::new(ClockManager::new()), config.clone());
+        let track = server
+            .register_upload(&temp_path, &media_dir, "ef".repeat(32), 3884, Some("wav".into()))
+            .await
+            .unwrap();
+        server
+            .create_program("movie".into(), "movie_audio".into(), "movie_video".into(), Some(40.0))
+            .await
+            .unwrap();
+        let tiers = [(QualityTier::Low, "stage-360p"), (QualityTier::High, "stage-720p")];
+        let video = TieredVideo {
+            stream_id: "stage".into(),
+            tiers: tiers.into_iter().map(|(tier, track)| (tier, track.to_string())).collect(),
+        };
+        server.create_tiered_video(video).await.unwrap();
+        let (client_id, device_id) = (Uuid::new_v4(), Uuid::new_v4());
+        server.add_client(client_id).await.unwrap();
+        server.attach_device(client_id, device_id).await;
+        server.join_zone(client_id, "hall".into()).await.unwrap();
+
+        let restarted = MediaServer::with_config(Arc::new(ClockManager::new()), config.clone());
+        restarted.load_state().await;
+        assert_eq!(restarted.device_zone(device_id).as_deref(), Some("hall"));
+        assert!(restarted.zone_members("hall").is_empty());
+        let restored = restarted.catalog().get(&track.track_id).await.unwrap();
+        assert_eq!(restored.path, track.path);
+        assert_eq!(restored.gain_db, track.gain_db);
+        let programs = restarted.programs().await;
+        assert_eq!(programs.len(), 1);
+        assert_eq!(programs[0].av_offset_ms, 40.0);
+        let videos = restarted.video_tiers();
+        assert_eq!(videos.len(), 1);
+        assert_eq!(videos[0].tiers[&QualityTier::High], "stage-720p");
+
+        // Deletions are saved too
+        restarted.delete_track(&track.track_id).await.unwrap();
+        assert!(restarted.remove_program("movie").await);
+        restarted.add_client(client_id).await.unwrap();
+        restarted.attach_device(client_id, device_id).await;
+        restarted.join_zone(client_id, "hall".into()).await.unwrap();
+        restarted.leave_zone(client_id).await.unwrap();
+        let restarted = MediaServer::with_config(Arc::new(ClockManager::new()), config);
+        restarted.load_state().await;
+        assert!(restarted.catalog().list().await.is_empty());
+        assert!(restarted.programs().await.is_empty());
+        assert_eq!(restarted.device_zone(device_id), None);
+
+        std::fs::remove_dir_all(&media_dir).ok();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_stream_is_torn_down_after_grace_period() {
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&media_dir).unwrap();
+        let temp_path = media_dir.join(".upload");
+        source::write_test_wav(&temp_path, 48000, 2, 960);
+        let config = ServerConfig {
+            idle_stream_grace_ms: 3000,
+            ..Default::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let track = server
+            .register_upload(&temp_path, &media_dir, "12".repeat(32), 3884, Some("wav".into()))
+            .await
+            .unwrap();
+        tokio::spawn(server.clone().run());
+        let exists = || async { server.streams.read().await.contains_key(&track.track_id) };
+
+        // Subscribing creates the stream from the catalog
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, track.track_id.clone()).await.unwrap();
+        assert_eq!(server.streams.read().await[&track.track_id].frame_tx.receiver_count(), 1);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(exists().await);
+
+        // The last subscriber leaving starts the grace period
+        server.unsubscribe_client(client_id, &track.track_id).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(exists().await);
+
+        // Idleness is noticed by the next sweep, up to a second late
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(!exists().await);
+
+        server.subscribe_client(client_id, track.track_id.clone()).await.unwrap();
+        assert!(exists().await);
+
+        std::fs::remove_dir_all(&media_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_register_upload_rejects_non_audio() {
+        let media_dir = std::env::temp_dir().join(format!("solusync-media-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&media_dir).unwrap();
+        let temp_path = media_dir.join(".upload");
+        std::fs::write(&temp_path, b"<html></html>").unwrap();
+
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let result = server
+            .register_upload(&temp_path, &media_dir, "cd".repeat(32), 13, Some("html".into()))
+            .await;
+
+        assert!(matches!(result, Err(CatalogError::UnsupportedMedia(_))));
+        assert!(!temp_path.exists());
+
+        std::fs::remove_dir_all(&media_dir).ok();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commands_processed_after_stats_tick() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        source::write_test_wav(&path, 48000, 2, 960);
+
+        let server = Arc::new(MediaServer::new(Arc::new(ClockManager::new())));
+        let source = Some(path.to_string_lossy().into_owned());
+        server
+            .process_control(control(MediaAction::Load, "track", 0.0, source))
+            .await
+            .unwrap();
+        tokio::spawn(server.clone().run());
+
+        // Let the first stats tick fire before sending the command
+        tokio::time::sleep(Duration::from_secs(6)).await;
+
+        let start_at = server.clock_manager.now().await + 60.0;
+        server
+            .get_control_sender()
+            .send(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+
+        let mut playing = false;
+        for _ in 0..100 {
+            if server.streams.read().await["track"].playback.is_some() {
+                playing = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(playing, "Play command was not processed");
+        
+        // Commands did not starve the stats tick: one fires immediately
+        // and another after five seconds
+        assert!(server.stats().await.stats_snapshots >= 2);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_remove_client_stops_forwarders() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream("track".into(), "opus".into()).await.unwrap();
+        
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        assert_eq!(server.active_forwarders(), 1);
+
+        server.remove_client(client_id).await;
+        assert!(server.clients.read().await.is_empty());
+
+        for _ in 0..100 {
+            if server.active_forwarders() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.active_forwarders(), 0);
+        assert_eq!(server.streams.read().await["track"].frame_tx.receiver_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_peer_connections_are_torn_down() {
+        let config = ServerConfig {
+            max_ice_restarts: 0,
+            ..ServerConfig::default()
+        };
+        let server = MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config));
+        server.create_stream("track".into(), "opus".into()).await.unwrap();
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let connection = Arc::downgrade(&server.clients.read().await[&client_id].peer_connection);
+        let event = |state| PeerStateEvent {
+            client_id,
+            connection: connection.clone(),
+            state,
+        };
+        let grace = Duration::from_millis(server.config.peer_disconnect_grace_ms);
+
+        // A connection that recovers within the grace is kept
+        server.handle_peer_state(event(RTCPeerConnectionState::Disconnected)).await;
+        tokio::time::sleep(grace / 2).await;
+        server.handle_peer_state(event(RTCPeerConnectionState::Connected)).await;
+        tokio::time::sleep(grace).await;
+        server.expire_disconnected_peers().await;
+        assert!(server.clients.read().await.contains_key(&client_id));
+
+        // One that stays disconnected is given up on
+        server.handle_peer_state(event(RTCPeerConnectionState::Disconnected)).await;
+        tokio::time::sleep(grace / 2).await;
+        server.expire_disconnected_peers().await;
+        assert!(server.clients.read().await.contains_key(&client_id));
+        tokio::time::sleep(grace).await;
+        server.expire_disconnected_peers().await;
+        assert!(server.clients.read().await.is_empty());
+        for _ in 0..100 {
+            if server.active_forwarders() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.active_forwarders(), 0);
+
+        let stats = server.stats().await;
+        assert!(stats.clients.is_empty());
+        assert_eq!(stats.disconnected_clients.len(), 1);
+        assert_eq!(stats.disconnected_clients[0].connection_state, "failed");
+        assert_eq!(stats.disconnected_clients[0].subscribed_tracks, ["track"]);
+        assert_eq!(server.health().await.failed_connections, 1);
+
+        // The control connection still detaches it with its subscriptions
+        let detached = server.detach_client(client_id).await.unwrap();
+        assert_eq!(detached.subscriptions(), ["track"]);
+        assert!(server.stats().await.disconnected_clients.is_empty());
+
+        // A late close of the old connection leaves a resumed client alone
+        server.resume_client(client_id, detached, None).await.unwrap();
+        server.handle_peer_state(event(RTCPeerConnectionState::Closed)).await;
+        assert!(server.clients.read().await.contains_key(&client_id));
+        assert_eq!(server.active_forwarders(), 1);
+
+        // While a close of its current one tears it down
+        let current = Arc::downgrade(&server.clients.read().await[&client_id].peer_connection);
+        server
+            .handle_peer_state(PeerStateEvent {
+                client_id,
+                connection: current,
+                state: RTCPeerConnectionState::Closed,
+            })
+            .await;
+        assert!(server.clients.read().await.is_empty());
+        assert_eq!(server.stats().await.disconnected_clients[0].connection_state, "closed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_degraded_peer_connection_is_offered_ice_restart() {
+        let config = ServerConfig {
+            max_ice_restarts: 2,
+            ..ServerConfig::default()
+        };
+        let server = MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config));
+        let mut offers = server.subscribe_ice_restart_offers();
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        let ice_ufrag = |sdp: &str| sdp.lines().find_map(|l| l.strip_prefix("a=ice-ufrag:")).map(str::to_string);
+        let initial = WebRtcServer::create_offer(&server_pc).await.unwrap();
+        let event = |state| PeerStateEvent {
+            client_id,
+            connection: Arc::downgrade(&server_pc),
+            state,
+        };
+        let grace = Duration::from_millis(server.config.peer_disconnect_grace_ms);
+
+        // A brief disconnect is waited out
+        server.handle_peer_state(event(RTCPeerConnectionState::Disconnected)).await;
+        tokio::time::sleep(grace / 2).await;
+        server.expire_disconnected_peers().await;
+        assert!(offers.try_recv().is_err());
+
+        // A longer one is offered a restart with new credentials
+        tokio::time::sleep(grace / 2).await;
+        server.expire_disconnected_peers().await;
+        let (offered_to, offer) = offers.try_recv().unwrap();
+        assert_eq!(offered_to, client_id);
+        assert!(ice_ufrag(&offer.sdp).is_some());
+        assert_ne!(ice_ufrag(&offer.sdp), ice_ufrag(&initial.sdp));
+
+        // Failing takes the last restart at once, and after that the client
+        // is given up on
+        server.handle_peer_state(event(RTCPeerConnectionState::Failed)).await;
+        assert!(offers.try_recv().is_ok());
+        assert_eq!(server.stats().await.ice_restarts, 2);
+        tokio::time::sleep(grace / 2).await;
+        server.expire_disconnected_peers().await;
+        assert!(server.clients.read().await.contains_key(&client_id));
+        tokio::time::sleep(grace / 2).await;
+        server.expire_disconnected_peers().await;
+        assert!(offers.try_recv().is_err());
+        assert!(server.clients.read().await.is_empty());
+        assert_eq!(server.stats().await.disconnected_clients[0].connection_state, "failed");
+    }
+
+    #[tokio::test]
+    async fn test_buffer_stats_reset_and_survive_resume() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        let underrun = || async {
+            server.clients.write().await.get_mut(&client_id).unwrap().queue.lock().future_buffer.report_underrun();
+        };
+        let underruns = || async { server.get_buffer_stats(client_id).await.unwrap().underrun_count };
+
+        underrun().await;
+        assert_eq!(underruns().await, 1);
+        assert_eq!(server.reset_buffer_stats(client_id).await.unwrap().underrun_count, 0);
+
+        // Resuming keeps the counters, a fresh connection starts from zero
+        underrun().await;
+        let detached = server.detach_client(client_id).await.unwrap();
+        assert!(server.get_buffer_stats(client_id).await.is_none());
+        server.resume_client(client_id, detached, None).await.unwrap();
+        assert_eq!(underruns().await, 1);
+
+        server.remove_client(client_id).await;
+        server.add_client(client_id).await.unwrap();
+        assert_eq!(underruns().await, 0);
+        assert!(server.reset_buffer_stats(Uuid::new_v4()).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnecting_device_starts_from_cached_tuning() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let max_age = Duration::from_millis(server.config.device_tuning_max_age_ms);
+        let device_id = Uuid::new_v4();
+        let target = |client_id| {
+            let server = &server;
+            async move { server.get_buffer_stats(client_id).await.unwrap().target_latency_ms }
+        };
+        let connect = || async {
+            let client_id = Uuid::new_v4();
+            server.add_client(client_id).await.unwrap();
+            let seeded = server.attach_device(client_id, device_id).await;
+            (client_id, seeded)
+        };
+        
+        // The first connection learns a larger latency from underruns
+        let (first, seeded) = connect().await;
+        assert!(!seeded);
+        assert_eq!(target(first).await, 80);
+        let report = BufferReportMessage {
+            header: MessageHeader::new(device_id, 1),
+            track_id: "a".into(),
+            underruns: 5,
+            overruns: 0,
+            occupancy_ms: 40.0,
+            playout_delay_ms: 12.0,
+        };
+        server.apply_buffer_report(first, &report).await.unwrap();
+        let learned = target(first).await;
+        assert!(learned > 80, "{}", learned);
+        server.remove_client(first).await;
+        
+        // A new connection of the device within the age starts from it
+        tokio::time::advance(max_age / 2).await;
+        let (second, seeded) = connect().await;
+        assert!(seeded);
+        assert_eq!(target(second).await, learned);
+        assert_eq!(server.get_buffer_stats(second).await.unwrap().client_playout_delay_ms, Some(12));
+        
+        // After the age has passed it starts from the default
+        server.remove_client(second).await;
+        tokio::time::advance(max_age).await;
+        let (third, seeded) = connect().await;
+        assert!(!seeded);
+        assert_eq!(target(third).await, 80);
+        
+        // Clearing the cache does the same
+        server.remove_client(third).await;
+        assert!(server.clear_device_tuning(device_id));
+        assert!(!server.clear_device_tuning(device_id));
+        let (fourth, seeded) = connect().await;
+        assert!(!seeded);
+        assert_eq!(target(fourth).await, 80);
+    }
+    
+    #[tokio::test]
+    async fn test_unsubscribe_stops_delivery_and_allows_resubscribe() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_short_track(&server, "a").await;
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "a".into()).await.unwrap();
+        assert!(server.subscribe_client(client_id, "a".into()).await.is_err());
+        assert_eq!(server.active_forwarders(), 1);
+
+        let delivered = server.clients.read().await[&client_id].frames_delivered.clone();
+        let play = |start_at| control(MediaAction::Play, "a", start_at, None);
+        server.process_control(play(server.clock_manager.now().await)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(delivered.load(Ordering::Relaxed), 3);
+
+        // Frames already queued for the client are dropped with the subscription
+        server.process_control(play(server.clock_manager.now().await + 1.0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        let queued = server.clients.read().await[&client_id].queue.lock().future_buffer.stats().queued_frames;
+        assert_eq!(queued, 3);
+
+        assert!(server.unsubscribe_client(client_id, "a").await.unwrap());
+        assert!(!server.unsubscribe_client(client_id, "a").await.unwrap());
+        let queued = server.clients.read().await[&client_id].queue.lock().future_buffer.stats().queued_frames;
+        assert_eq!(queued, 0);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(delivered.load(Ordering::Relaxed), 3);
+        assert_eq!(server.client_subscriptions(client_id).await, Some(vec![]));
+        for _ in 0..100 {
+            if server.active_forwarders() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.active_forwarders(), 0);
+
+        server.process_control(play(server.clock_manager.now().await)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(delivered.load(Ordering::Relaxed), 3);
+
+        // Resubscribing picks up the next playback
+        server.subscribe_client(client_id, "a".into()).await.unwrap();
+        server.process_control(play(server.clock_manager.now().await)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(delivered.load(Ordering::Relaxed), 6);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_stream_channel_capacity_bounds_subscriber_lag() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        assert!(server.create_stream_with_capacity("none".into(), "opus".into(), 0).await.is_err());
+        server.create_stream_with_capacity("small".into(), "opus".into(), 4).await.unwrap();
+        server.create_stream("default".into(), "h264".into()).await.unwrap();
+        let capacities: Vec<_> = server
+            .stats()
+            .await
+            .streams
+            .iter()
+            .map(|s| (s.status.track_id.clone(), s.status.channel_capacity))
+            .collect();
+        assert_eq!(capacities, [("default".into(), channel_capacity("h264", None)), ("small".into(), 4)]);
+        
+        let frame_tx = server.streams.read().await["small"].frame_tx.clone();
+        let mut fast = frame_tx.subscribe();
+        let mut slow = frame_tx.subscribe();
+        let frame = |sequence| MediaFrame {
+            data: vec![0; 4].into(),
+            timestamp: sequence as f64 * 0.02,
+            duration: Duration::from_millis(20),
+            frame_type: buffer::FrameType::Audio,
+            sequence,
+            renditions: Arc::default(),
+        };
+        
+        // A subscriber keeping up sees every frame, one more than the
+        // channel's capacity behind misses the oldest
+        for sequence in 0..10 {
+            frame_tx.send(frame(sequence)).unwrap();
+            assert_eq!(fast.recv().await.unwrap().sequence, sequence);
+        }
+        assert!(matches!(slow.recv().await, Err(broadcast::error::RecvError::Lagged(6))));
+        for sequence in 6..10 {
+            assert_eq!(slow.recv().await.unwrap().sequence, sequence);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_audio_forwarding_stays_prompt_while_video_floods() {
+        let server = Arc::new(MediaServer::new(Arc::new(ClockManager::new())));
+        server.create_stream_with_capacity("cam".into(), "h264".into(), 4096).await.unwrap();
+        server.create_stream("voice".into(), "opus".into()).await.unwrap();
+        let (viewer, listener) = (Uuid::new_v4(), Uuid::new_v4());
+        server.add_client(viewer).await.unwrap();
+        server.add_client(listener).await.unwrap();
+        server.subscribe_client(viewer, "cam".into()).await.unwrap();
+        server.subscribe_client(listener, "voice".into()).await.unwrap();
+        
+        // Frames are due well after the test, so they stay queued
+        let timestamp = server.clock_manager.now().await + 60.0;
+        let frame = move |sequence, frame_type| MediaFrame {
+            data: vec![0; 4].into(),
+            timestamp: timestamp + sequence as f64 * 0.01,
+            duration: Duration::from_millis(10),
+            frame_type,
+            sequence,
+            renditions: Arc::default(),
+        };
+        let video_tx = server.streams.read().await["cam"].frame_tx.clone();
+        let audio_tx = server.streams.read().await["voice"].frame_tx.clone();
+        let flooding = CancellationToken::new();
+        let flood = tokio::spawn({
+            let flooding = flooding.clone();
+            async move {
+                let mut sequence = 0;
+                while !flooding.is_cancelled() {
+                    for _ in 0..512 {
+                        let _ = video_tx.send(frame(sequence, buffer::FrameType::Video));
+                        sequence += 1;
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+        
+        let queued = || async { server.clients.read().await[&listener].queue.lock().future_buffer.stats().queued_frames };
+        let mut worst = Duration::ZERO;
+        for sequence in 0..20 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let sent = std::time::Instant::now();
+            audio_tx.send(frame(sequence, buffer::FrameType::Audio)).unwrap();
+            while queued().await < sequence as usize + 1 {
+                tokio::task::yield_now().await;
+            }
+            worst = worst.max(sent.elapsed());
+        }
+        flooding.cancel();
+        flood.await.unwrap();
+        
+        assert!(worst < Duration::from_millis(50), "audio frame took {:?} to forward", worst);
+    }
+    
+    #[tokio::test]
+    async fn test_lagging_forwarder_counts_drops_and_keeps_running() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream_with_capacity("track".into(), "opus".into(), 2).await.unwrap();
+        let frame_tx = server.streams.read().await["track"].frame_tx.clone();
+
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let dropped = server.clients.read().await[&client_id].frames_dropped.clone();
+        let queued = || async { server.clients.read().await[&client_id].queue.lock().future_buffer.stats().queued_frames };
+
+        // Frames are due well after the test, so they stay queued
+        let timestamp = server.clock_manager.now().await + 60.0;
+        let frame = |sequence| MediaFrame {
+            data: vec![0; 4].into(),
+            timestamp,
+            duration: Duration::from_millis(20),
+            frame_type: buffer::FrameType::Audio,
+            sequence,
+            renditions: Arc::default(),
+        };
+        let settle = || async { tokio::time::sleep(Duration::from_millis(50)).await };
+
+        // The forwarder cannot run while frames are sent without yielding,
+        // so all but the last two of the burst are overwritten
+        for sequence in 0..10 {
+            frame_tx.send(frame(sequence)).unwrap();
+        }
+        settle().await;
+        assert_eq!(dropped.load(Ordering::Relaxed), 8);
+        assert_eq!(queued().await, 2);
+        let stats = server.clients.read().await[&client_id].queue.lock().future_buffer.stats();
+        assert_eq!(stats.underrun_count, 1);
+
+        frame_tx.send(frame(10)).unwrap();
+        settle().await;
+        assert_eq!(queued().await, 3);
+        assert_eq!(server.active_forwarders(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_missing_audio_frames_request_concealment_from_the_client() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream("track".into(), "opus".into()).await.unwrap();
+        let frame_tx = server.streams.read().await["track"].frame_tx.clone();
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let mut requests = server.subscribe_concealment_requests();
+
+        // Frames 2 and 3 are lost on the way; the rest are already due
+        let start = server.clock_manager.now().await - 1.0;
+        for sequence in [0, 1, 4] {
+            frame_tx
+                .send(MediaFrame {
+                    data: vec![0; 4].into(),
+                    timestamp: start + sequence as f64 * 0.02,
+                    duration: Duration::from_millis(20),
+                    frame_type: buffer::FrameType::Audio,
+                    sequence,
+                    renditions: Arc::default(),
+                })
+                .unwrap();
+        }
+        let (requested_for, request) = tokio::time::timeout(Duration::from_secs(1), requests.recv())
+            .await
+            .expect("concealment requested")
+            .unwrap();
+        assert_eq!(requested_for, client_id);
+        assert_eq!((request.track_id.as_str(), request.first_sequence, request.missing), ("track", 2, 2));
+        assert!((request.timestamp - (start + 0.04)).abs() < 1e-9);
+        assert_eq!(request.duration, Duration::from_millis(40));
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bursty_frames_are_paced_out_evenly_without_the_client_map() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream("track".into(), "opus".into()).await.unwrap();
+        let frame_tx = server.streams.read().await["track"].frame_tx.clone();
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let delivered = server.clients.read().await[&client_id].frames_delivered.clone();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Frames arrive in two bursts, far out of cadence, all due after
+        // both have arrived
+        let start = server.clock_manager.now().await + 0.3;
+        for sequence in 0..10u64 {
+            if sequence == 5 {
+                tokio::time::sleep(Duration::from_millis(130)).await;
+            }
+            frame_tx
+                .send(MediaFrame {
+                    data: vec![0; 4].into(),
+                    timestamp: start + sequence as f64 * 0.02,
+                    duration: Duration::from_millis(20),
+                    frame_type: buffer::FrameType::Audio,
+                    sequence,
+                    renditions: Arc::default(),
+                })
+                .unwrap();
+            tokio::task::yield_now().await;
+        }
+
+        // Forwarding and pacing carry on while the client map is locked,
+        // releasing the frames at the cadence of their timestamps
+        let _clients = server.clients.write().await;
+        let mut releases = Vec::new();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while releases.len() < 10 {
+                let count = delivered.load(Ordering::Relaxed) as usize;
+                releases.resize(count, std::time::Instant::now());
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("every frame is released");
+        for pair in releases.windows(2) {
+            let interval = pair[1].duration_since(pair[0]).as_secs_f64();
+            assert!((interval - 0.02).abs() < 0.01, "released {:.3}s apart", interval);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frame_payload_is_shared_by_all_subscribers() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream("live".into(), "opus".into()).await.unwrap();
+        let mut receivers = Vec::new();
+        for _ in 0..16 {
+            receivers.push(server.subscribe_frames("live").await.unwrap());
+        }
+        
+        // The chunk's buffer is taken over at the protocol edge and shared
+        // from there on, however many subscribers there are
+        let data = vec![7u8; 960];
+        let payload = data.as_ptr();
+        let chunk = MediaDataMessage {
+            header: MessageHeader::new(Uuid::new_v4(), 1),
+            track_id: "live".into(),
+            chunk_index: 0,
+            timestamp: 100.0,
+            duration: 0.02,
+            data,
+            codec: "opus".into(),
+            is_keyframe: false,
+            compression: None,
+        };
+        server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap();
+        
+        for receiver in &mut receivers {
+            let frame = receiver.try_recv().unwrap();
+            assert_eq!(frame.data.as_ptr(), payload);
+            assert_eq!(frame.data.len(), 960);
+        }
+        
+        // Renditions are shared the same way
+        let frame_tx = server.streams.read().await["live"].frame_tx.clone();
+        let renditions: Arc<[rendition::Rendition]> = Arc::from([rendition::Rendition {
+            tier: QualityTier::Low,
+            data: vec![1u8; 40].into(),
+        }]);
+        frame_tx
+            .send(MediaFrame {
+                data: vec![0; 4].into(),
+                timestamp: 100.02,
+                duration: Duration::from_millis(20),
+                frame_type: buffer::FrameType::Audio,
+                sequence: 1,
+                renditions: renditions.clone(),
+            })
+            .unwrap();
+        for receiver in &mut receivers {
+            let frame = receiver.try_recv().unwrap();
+            assert!(Arc::ptr_eq(&frame.renditions, &renditions));
+            assert_eq!(frame.data_for(QualityTier::Low).as_ptr(), renditions[0].data.as_ptr());
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_chunk_with_unrepresentable_duration_is_rejected() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        for duration in [f64::INFINITY, 1e30] {
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(Uuid::new_v4(), 1),
+                track_id: "live".into(),
+                chunk_index: 0,
+                timestamp: 100.0,
+                duration,
+                data: vec![0; 4],
+                codec: "opus".into(),
+                is_keyframe: false,
+                compression: None,
+            };
+            let error = server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap_err();
+            assert!(error.to_string().contains("Invalid chunk duration"));
+        }
+        assert!(server.subscribe_frames("live").await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_delete_stream_mid_playback_releases_resources() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_test_track(&server).await;
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let mut frame_rx = server.subscribe_frames("track").await.unwrap();
+        let mut events = server.subscribe_control_events();
+
+        let start_at = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+        frame_rx.recv().await.unwrap();
+
+        server
+            .process_control(control(MediaAction::Unload, "track", 0.0, None))
+            .await
+            .unwrap();
+
+        let stop = events.try_recv().unwrap();
+        assert!(matches!(stop.action, MediaAction::Stop));
+        assert_eq!(stop.track_id, "track");
+        assert!(!server.streams.read().await.contains_key("track"));
+        assert_eq!(server.client_subscriptions(client_id).await, Some(vec![]));
+
+        // Other receivers see the channel close once buffered frames drain
+        let closed = tokio::time::timeout(Duration::from_secs(1), async {
+            while frame_rx.recv().await.is_ok() {}
+        })
+        .await;
+        assert!(closed.is_ok());
+        for _ in 0..100 {
+            if server.active_forwarders() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.active_forwarders(), 0);
+        assert!(!server.delete_stream("track").await);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Encoder that replaces each frame with its tier's bitrate
+    struct TaggingEncoder(QualityTier);
+
+    impl rendition::TierEncoder for TaggingEncoder {
+        fn encode(&mut self, _pcm: &[u8]) -> Result<Vec<u8>> {
+            Ok(self.0.bitrate_kbps().to_le_bytes().to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_tier_follows_quality_downgrade() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.set_encoder_factory(Arc::new(|tier, _, _| {
+            Ok(Box::new(TaggingEncoder(tier)) as Box<dyn rendition::TierEncoder>)
+        }));
+        let path = load_test_track(&server).await;
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let mut frame_rx = server.subscribe_frames("track").await.unwrap();
+
+        let start_at = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+
+        let frame = frame_rx.recv().await.unwrap();
+        for tier in QualityTier::ALL {
+            assert_eq!(frame.data_for(tier)[..], tier.bitrate_kbps().to_le_bytes());
+        }
+        let tier = || async { server.stats().await.clients[0].quality_tier };
+        assert_eq!(tier().await, QualityTier::High);
+
+        server.update_client_quality(client_id, NetworkQuality::Critical).await;
+        // Less than the downgrade hysteresis has passed
+        frame_rx.recv().await.unwrap();
+        assert_eq!(tier().await, QualityTier::High);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(tier().await, QualityTier::Low);
+        let json = serde_json::to_value(server.stats().await).unwrap();
+        assert_eq!(json["clients"][0]["quality_tier"], "low");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Tags each frame with the loss it was last told to expect
+    struct LossTaggingEncoder(Option<u8>);
+    
+    impl rendition::TierEncoder for LossTaggingEncoder {
+        fn encode(&mut self, _pcm: &[u8]) -> Result<Vec<u8>> {
+            Ok(vec![self.0.unwrap_or(0)])
+        }
+        
+        fn set_expected_loss(&mut self, loss_percent: Option<u8>) {
+            self.0 = loss_percent;
+        }
+    }
+    
+    /// Add a client that answers the server's offer, asking for Opus FEC
+    /// or not
+    async fn add_answering_client(server: &MediaServer, accept_fec: bool) -> Uuid {
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        let offer = WebRtcServer::create_offer(&server_pc).await.unwrap();
+        
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let browser = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        browser.set_remote_description(offer).await.unwrap();
+        let answer = browser.create_answer(None).await.unwrap();
+        assert!(answer.sdp.contains("useinbandfec=1"));
+        let sdp = match accept_fec {
+            true => answer.sdp,
+            false => answer.sdp.replace("useinbandfec=1", "useinbandfec=0"),
+        };
+        server.apply_answer(client_id, sdp).await.unwrap();
+        client_id
+    }
+    
+    async fn fec_status(server: &MediaServer, client_id: Uuid) -> FecStatus {
+        let stats = server.stats().await;
+        stats.clients.into_iter().find(|c| c.client_id == client_id).unwrap().fec
+    }
+    
+    async fn report_loss(server: &MediaServer, client_id: Uuid, loss_percent: f64) {
+        let mut clients = server.clients.write().await;
+        clients.get_mut(&client_id).unwrap().update_fec(loss_percent, &server.loss_feedback);
+    }
+    
+    /// Wait for a frame whose high tier was encoded expecting `loss`
+    async fn expect_high_tier_loss(frame_rx: &mut broadcast::Receiver<MediaFrame>, loss: u8) {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                let frame = frame_rx.recv().await.unwrap();
+                if frame.data_for(QualityTier::High)[..] == [loss] {
+                    assert_eq!(frame.data_for(QualityTier::Low)[..], [0]);
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("high tier frames expect {}% loss", loss));
+    }
+    
+    #[tokio::test]
+    async fn test_lossy_clients_switch_on_fec_in_their_tier_encoder() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.set_encoder_factory(Arc::new(|_, _, _| {
+            Ok(Box::new(LossTaggingEncoder(None)) as Box<dyn rendition::TierEncoder>)
+        }));
+        let path = load_test_track(&server).await;
+        let with_fec = add_answering_client(&server, true).await;
+        let without_fec = add_answering_client(&server, false).await;
+        assert!(fec_status(&server, with_fec).await.negotiated);
+        assert!(!fec_status(&server, without_fec).await.negotiated);
+        
+        let mut frame_rx = server.subscribe_frames("track").await.unwrap();
+        let start_at = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+        expect_high_tier_loss(&mut frame_rx, 0).await;
+        
+        // Both clients receive the high tier, whose encoder follows the
+        // loss of the one that can decode FEC once it reaches the threshold
+        report_loss(&server, without_fec, 20.0).await;
+        report_loss(&server, with_fec, 0.5).await;
+        assert_eq!(fec_status(&server, with_fec).await.expected_loss_percent, None);
+        report_loss(&server, with_fec, 6.0).await;
+        expect_high_tier_loss(&mut frame_rx, 6).await;
+        let status = fec_status(&server, with_fec).await;
+        assert!(status.active && status.expected_loss_percent == Some(6));
+        assert!(!fec_status(&server, without_fec).await.active);
+        
+        for _ in 0..3 {
+            report_loss(&server, with_fec, 0.0).await;
+        }
+        assert!(!fec_status(&server, with_fec).await.active);
+        expect_high_tier_loss(&mut frame_rx, 0).await;
+        
+        let _ = std::fs::remove_file(path);
+    }
+    
+    #[tokio::test]
+    async fn test_play_with_max_duration_announces_and_stops() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_test_track(&server).await;
+        let mut frame_rx = server.subscribe_frames("track").await.unwrap();
+        let mut events = server.subscribe_control_events();
+        let start_at = server.clock_manager.now().await + 0.02;
+
+        let mut before_start = control(MediaAction::Play, "track", start_at, None);
+        before_start.params.stop_at = Some(start_at - 0.01);
+        let mut too_long = control(MediaAction::Play, "track", start_at, None);
+        too_long.params.max_duration_ms = Some(2000);
+        for cmd in [before_start, too_long] {
+            assert!(server.check_scheduled_stop(&cmd).await.is_err());
+            assert!(server.process_control(cmd).await.is_err());
+        }
+
+        let mut play = control(MediaAction::Play, "track", start_at, None);
+        play.params.max_duration_ms = Some(100);
+        play.params.fade_out_ms = Some(20);
+        server.check_scheduled_stop(&play).await.unwrap();
+        server.process_control(play).await.unwrap();
+
+        let stop = events.try_recv().unwrap();
+        assert!(matches!(stop.action, MediaAction::Stop));
+        assert!((stop.start_at - (start_at + 0.08)).abs() < 1e-6);
+        assert_eq!(stop.params.fade_out_ms, Some(20));
+
+        let mut last_end = 0.0;
+        while let Ok(Ok(frame)) =
+            tokio::time::timeout(Duration::from_millis(200), frame_rx.recv()).await
+        {
+            last_end = frame.timestamp + frame.duration.as_secs_f64();
+        }
+        let stop_at = start_at + 0.1;
+        assert!(last_end >= stop_at - 1e-6 && last_end - stop_at < 0.02);
+        assert_eq!(server.streams.read().await["track"].state(), PlaybackState::Stopped);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    fn seek(track_id: &str, start_at: f64, position: f64) -> MediaControlMessage {
+        let mut cmd = control(MediaAction::Seek, track_id, start_at, None);
+        cmd.params.seek_position = Some(position);
+        cmd
+    }
+
+    /// Load a one second mono test WAV as "track"
+    async fn load_test_track(server: &MediaServer) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        source::write_test_wav(&path, 48000, 1, 48000);
+        let source = Some(path.to_string_lossy().into_owned());
+        server
+            .process_control(control(MediaAction::Load, "track", 0.0, source))
+            .await
+            .unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_seek_aligns_timestamps_with_start_at() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_test_track(&server).await;
+        let mut events = server.subscribe_control_events();
+
+        // Playing, but not due to emit anything for a while
+        let now = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "track", now + 60.0, None))
+            .await
+            .unwrap();
+
+        let mut frame_rx = server.streams.read().await["track"].frame_tx.subscribe();
+        let start_at = server.clock_manager.now().await + 0.05;
+        server.process_control(seek("track", start_at, 0.51)).await.unwrap();
+
+        for i in 0..3 {
+            let frame = tokio::time::timeout(Duration::from_millis(500), frame_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!((frame.timestamp - (start_at + i as f64 * 0.02)).abs() < 1e-6);
+            if i == 0 {
+                // Test signal sample value at 0.51s (sample 24480)
+                assert_eq!(i16::from_le_bytes([frame.data[0], frame.data[1]]), 480);
+            }
+        }
+
+        let announced = events.try_recv().unwrap();
+        assert!(matches!(announced.action, MediaAction::Seek));
+        assert_eq!(announced.params.seek_position, Some(0.51));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_program_seek_keeps_audio_and_video_aligned() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        for track_id in ["audio", "video"] {
+            let source = Box::new(source::TestSource::new(100));
+            server.attach_source(track_id, None, source, 1.0).await.unwrap();
+        }
+        server
+            .create_program("show".into(), "audio".into(), "video".into(), Some(40.0))
+            .await
+            .unwrap();
+        assert!(server
+            .create_program("other".into(), "video".into(), "extra".into(), None)
+            .await
+            .is_err());
+
+        let now = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "show", now + 60.0, None))
+            .await
+            .unwrap();
+        // Out of range for both tracks, so neither moves
+        assert!(server.process_control(seek("show", now, 5.0)).await.is_err());
+
+        let mut audio_rx = server.subscribe_frames("audio").await.unwrap();
+        let mut video_rx = server.subscribe_frames("video").await.unwrap();
+        let start_at = server.clock_manager.now().await + 0.05;
+        server.process_control(seek("show", start_at, 0.5)).await.unwrap();
+
+        for i in 0..3 {
+            let (audio, video) = tokio::time::timeout(Duration::from_millis(500), async {
+                (audio_rx.recv().await.unwrap(), video_rx.recv().await.unwrap())
+            })
+            .await
+            .unwrap();
+            if i == 0 {
+                assert!((audio.timestamp - start_at).abs() < 1e-6);
+            }
+            // Video leads by the A/V offset and is otherwise aligned
+            let skew = audio.timestamp - (video.timestamp + 0.04);
+            assert!(skew.abs() < audio.duration.as_secs_f64(), "skewed by {:.4}s", skew);
+        }
+
+        // A pause applies to both tracks
+        let pause_at = server.clock_manager.now().await + 0.1;
+        server
+            .process_control(control(MediaAction::Pause, "show", pause_at, None))
+            .await
+            .unwrap();
+        let program = server.programs().await.remove(0);
+        assert_eq!(program.state, PlaybackState::Paused);
+        assert_eq!(server.streams.read().await["video"].state(), PlaybackState::Paused);
+    }
+
+    #[tokio::test]
+    async fn test_seek_rejects_stopped_track_and_out_of_range() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_test_track(&server).await;
+        let now = server.clock_manager.now().await;
+
+        assert!(server.process_control(seek("track", now, 0.5)).await.is_err());
+
+        server
+            .process_control(control(MediaAction::Play, "track", now + 60.0, None))
+            .await
+            .unwrap();
+        assert!(server.process_control(seek("track", now, 1.5)).await.is_err());
+        assert!(server.process_control(seek("track", now, 0.5)).await.is_ok());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    /// Load 60ms of mono audio as `track_id`
+    async fn load_short_track(server: &MediaServer, track_id: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        source::write_test_wav(&path, 48000, 1, 2880);
+        server.load_track(track_id, None, path.clone()).await.unwrap();
+        path
+    }
+
+    fn queue_item(track_id: &str) -> QueueItem {
+        QueueItem {
+            track_id: track_id.into(),
+            params: MediaParams::default(),
+            crossfade_ms: None,
+        }
+    }
+
+    async fn next_play(events: &mut broadcast::Receiver<MediaControlMessage>) -> MediaControlMessage {
         loop {
-            tokio::select! {
-                _ = stats_interval.tick() => {
-                    self.log_stats().await;
+            let cmd = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("No control event")
+                .unwrap();
+            if matches!(cmd.action, MediaAction::Play) {
+                return cmd;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_advances_after_gap() {
+        let config = ServerConfig {
+            queue_gap_ms: 30,
+            ..Default::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let paths = [
+            load_short_track(&server, "a").await,
+            load_short_track(&server, "b").await,
+        ];
+        tokio::spawn(server.clone().run());
+
+        let mut events = server.subscribe_control_events();
+        let mut frame_rx = server.subscribe_frames("b").await.unwrap();
+        server.enqueue(vec![queue_item("a"), queue_item("b")]).await.unwrap();
+        assert!(server.enqueue(vec![queue_item("missing")]).await.is_err());
+
+        let first = server.skip().await.unwrap().unwrap();
+        assert_eq!(first.track_id, "a");
+        assert_eq!(next_play(&mut events).await.track_id, "a");
+
+        // b starts where a's last frame ends, plus the configured gap
+        let second = next_play(&mut events).await;
+        assert_eq!(second.track_id, "b");
+        assert!((second.start_at - (first.start_at + 0.06 + 0.03)).abs() < 1e-6);
+
+        let frame = tokio::time::timeout(Duration::from_secs(1), frame_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((frame.timestamp - second.start_at).abs() < 1e-6);
+
+        let status = server.queue_status();
+        assert_eq!(status.current.unwrap().track_id, "b");
+        assert!(status.items.is_empty());
+
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_survives_pause_and_resume() {
+        let server = Arc::new(MediaServer::new(Arc::new(ClockManager::new())));
+        let paths = [
+            load_short_track(&server, "a").await,
+            load_short_track(&server, "b").await,
+            load_short_track(&server, "c").await,
+        ];
+        tokio::spawn(server.clone().run());
+
+        let mut events = server.subscribe_control_events();
+        server
+            .enqueue(vec![queue_item("a"), queue_item("b"), queue_item("c")])
+            .await
+            .unwrap();
+        assert_eq!(server.remove_queue_item(2).await.unwrap().track_id, "c");
+        assert!(server.remove_queue_item(5).await.is_none());
+
+        server.skip().await.unwrap();
+        assert_eq!(next_play(&mut events).await.track_id, "a");
+
+        let now = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Pause, "a", now, None))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let status = server.queue_status();
+        assert_eq!(status.current.unwrap().track_id, "a");
+        assert_eq!(status.items.len(), 1);
+
+        let now = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "a", now, None))
+            .await
+            .unwrap();
+        assert_eq!(next_play(&mut events).await.track_id, "b");
+
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_stream_and_client_counters() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = load_short_track(&server, "a").await;
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "a".into()).await.unwrap();
+
+        let start_at = server.clock_manager.now().await;
+        server
+            .process_control(control(MediaAction::Play, "a", start_at, None))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let stats = server.stats().await;
+        let stream = &stats.streams[0];
+        assert_eq!(stream.status.track_id, "a");
+        assert_eq!(stream.status.state, PlaybackState::Stopped);
+        assert_eq!(stream.frames_emitted, 3);
+        assert_eq!(stream.bytes_emitted, 3 * 960 * 2);
+        assert!((stream.position.unwrap() - 0.06).abs() < 1e-6);
+
+        let client = &stats.clients[0];
+        assert_eq!(client.client_id, client_id);
+        assert_eq!(client.subscribed_tracks, vec!["a".to_string()]);
+        assert_eq!(client.frames_delivered, 3);
+        assert_eq!(client.frames_dropped, 0);
+        assert_eq!(client.connection_state, "new");
+        assert_eq!(stats.active_forwarders, 1);
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["streams"][0]["track_id"], "a");
+        assert_eq!(json["streams"][0]["frames_emitted"], 3);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_playback_follows_shared_clock_offset() {
+        let clock = Arc::new(ClockManager::new());
+        clock.set_master_clock(1000.0, 0.0).await;
+        let server = MediaServer::new(clock.clone());
+        let path = load_short_track(&server, "a").await;
+        let mut frame_rx = server.subscribe_frames("a").await.unwrap();
+
+        // Scheduled on the shared clock, this is 50ms from now; on a private
+        // clock without the offset it would be 1000s away
+        let start_at = clock.now().await + 0.05;
+        assert!(start_at - crate::protocol::get_current_time() > 999.0);
+        server
+            .process_control(control(MediaAction::Play, "a", start_at, None))
+            .await
+            .unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_millis(500), frame_rx.recv())
+            .await
+            .expect("Playback did not use the shared clock")
+            .unwrap();
+        assert!((frame.timestamp - start_at).abs() < 1e-6);
+        let prebuffer = server.config.prebuffer_ms as f64 / 1000.0;
+        assert!(clock.now().await >= frame.timestamp - prebuffer);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_test_tone_plays_on_every_client_then_tears_down() {
+        let server = Arc::new(MediaServer::new(Arc::new(ClockManager::new())));
+        let clients = [Uuid::new_v4(), Uuid::new_v4()];
+        for client_id in clients {
+            server.add_client(client_id).await.unwrap();
+        }
+        tokio::spawn(server.clone().run());
+        let mut events = server.subscribe_control_events();
+
+        let params = ToneParams {
+            waveform: Waveform::Click,
+            frequency: 1000.0,
+            duration: Duration::from_millis(100),
+            interval: Some(Duration::from_millis(50)),
+        };
+        let start_at = server.clock_manager.now().await + 0.05;
+        let cmd = server.play_test_tone(params, start_at, None).await.unwrap();
+        assert_eq!(next_play(&mut events).await.track_id, cmd.track_id);
+        for client_id in clients {
+            assert_eq!(server.client_subscriptions(client_id).await, Some(vec![cmd.track_id.clone()]));
+        }
+
+        // The stream is removed once the tone has been presented
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !server.stream_statuses().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(server.clock_manager.now().await >= start_at + 0.1);
+        for client_id in clients {
+            assert_eq!(server.client_subscriptions(client_id).await, Some(vec![]));
+        }
+
+        let silent = ToneParams {
+            frequency: 0.0,
+            ..params
+        };
+        assert!(server.play_test_tone(silent, start_at, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_zones_play_independently() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let [hall_a, hall_b, foyer, lobby] = [(); 4].map(|_| Uuid::new_v4());
+        for client_id in [hall_a, hall_b, foyer, lobby] {
+            server.add_client(client_id).await.unwrap();
+        }
+        server.join_zone(hall_a, "hall".into()).await.unwrap();
+        server.join_zone(hall_b, "hall".into()).await.unwrap();
+        server.join_zone(foyer, "foyer".into()).await.unwrap();
+        assert_eq!(server.zone_members("hall").len(), 2);
+
+        // 300ms tracks, so both zones are still playing when checked
+        let paths: Vec<_> = (0..2)
+            .map(|_| {
+                let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+                source::write_test_wav(&path, 48000, 1, 14400);
+                path
+            })
+            .collect();
+        let zoned = |action, track_id, zone: &str, start_at, source: Option<String>| MediaControlMessage {
+            zone: Some(zone.to_string()),
+            ..control(action, track_id, start_at, source)
+        };
+        let loads = [("one", "hall", &paths[0]), ("two", "foyer", &paths[1]), ("one", "foyer", &paths[0])];
+        for (track_id, zone, path) in loads {
+            let source = Some(path.to_string_lossy().into_owned());
+            let load = zoned(MediaAction::Load, track_id, zone, 0.0, source);
+            server.process_control(load).await.unwrap();
+        }
+        let mut hall_rx = server.subscribe_frames("one@hall").await.unwrap();
+        let mut foyer_rx = server.subscribe_frames("one@foyer").await.unwrap();
+
+        let now = server.clock_manager.now().await;
+        server.process_control(zoned(MediaAction::Play, "one", "hall", now, None)).await.unwrap();
+        server.process_control(zoned(MediaAction::Play, "two", "foyer", now, None)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Only zone members are subscribed, each to its own zone's track
+        assert_eq!(server.client_subscriptions(hall_a).await, Some(vec!["one@hall".into()]));
+        assert_eq!(server.client_subscriptions(hall_b).await, Some(vec!["one@hall".into()]));
+        assert_eq!(server.client_subscriptions(foyer).await, Some(vec!["two@foyer".into()]));
+        assert_eq!(server.client_subscriptions(lobby).await, Some(vec![]));
+        let delivered = |client_id| {
+            let clients = &server.clients;
+            async move { clients.read().await[&client_id].frames_delivered.load(Ordering::Relaxed) }
+        };
+        for client_id in [hall_a, hall_b, foyer] {
+            assert!(delivered(client_id).await > 0);
+        }
+        assert_eq!(delivered(lobby).await, 0);
+
+        // The same track starting later in another zone runs its own driver
+        let later = server.clock_manager.now().await + 0.05;
+        server.process_control(zoned(MediaAction::Play, "one", "foyer", later, None)).await.unwrap();
+        let first_hall = hall_rx.recv().await.unwrap();
+        let first_foyer = foyer_rx.recv().await.unwrap();
+        assert_eq!((first_hall.sequence, first_foyer.sequence), (0, 0));
+        assert!((first_hall.timestamp - now).abs() < 1e-6);
+        assert!((first_foyer.timestamp - later).abs() < 1e-6);
+
+        // Stopping one zone leaves the other playing
+        server.process_control(zoned(MediaAction::Stop, "one", "hall", 0.0, None)).await.unwrap();
+        let states: HashMap<_, _> = server
+            .stream_statuses()
+            .await
+            .into_iter()
+            .map(|s| ((s.track_id, s.zone), s.state))
+            .collect();
+        assert_eq!(states[&("one".into(), Some("hall".into()))], PlaybackState::Stopped);
+        assert_eq!(states[&("two".into(), Some("foyer".into()))], PlaybackState::Playing);
+        assert_eq!(states[&("one".into(), Some("foyer".into()))], PlaybackState::Playing);
+
+        // Moving zones swaps the client onto the new zone's playing streams
+        server.join_zone(hall_a, "foyer".into()).await.unwrap();
+        assert_eq!(
+            server.client_subscriptions(hall_a).await,
+            Some(vec!["one@foyer".into(), "two@foyer".into()])
+        );
+
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_position_follows_clock_across_pause_resume_seek_and_stop() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", Uuid::new_v4()));
+        // One second of mono audio
+        source::write_test_wav(&path, 48000, 1, 48000);
+        server.load_track("track", None, path.clone()).await.unwrap();
+        assert_eq!(server.get_position("track").await, None);
+        assert_eq!(server.get_position("missing").await, None);
+
+        let clock = server.clock_manager.clone();
+        let near = |position: Option<f64>, expected: f64| {
+            let position = position.expect("position is known");
+            assert!((position - expected).abs() < 0.03, "at {} instead of {}", position, expected);
+        };
+
+        // Holds at the start until start_at, then follows the clock
+        let start_at = clock.now().await + 0.1;
+        server.process_control(control(MediaAction::Play, "track", start_at, None)).await.unwrap();
+        near(server.get_position("track").await, 0.0);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        near(server.get_position("track").await, clock.now().await - start_at);
+
+        // Pausing freezes it once the prebuffered frames have been presented
+        server.process_control(control(MediaAction::Pause, "track", clock.now().await, None)).await.unwrap();
+        near(server.get_position("track").await, clock.now().await - start_at);
+        tokio::time::sleep(Duration::from_millis(server.config.prebuffer_ms + 50)).await;
+        let paused = server.get_position("track").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.get_position("track").await, Some(paused));
+
+        // Resuming continues from the paused position at start_at
+        let resume_at = clock.now().await + 0.1;
+        server.process_control(control(MediaAction::Play, "track", resume_at, None)).await.unwrap();
+        assert_eq!(server.get_position("track").await, Some(paused));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        near(server.get_position("track").await, paused + clock.now().await - resume_at);
+
+        // A seek jumps to the new position and advances from its start_at
+        let seek_at = clock.now().await + 0.1;
+        let seek = MediaControlMessage {
+            params: MediaParams {
+                seek_position: Some(0.7),
+                ..Default::default()
+            },
+            ..control(MediaAction::Seek, "track", seek_at, None)
+        };
+        server.process_control(seek).await.unwrap();
+        assert_eq!(server.get_position("track").await, Some(0.7));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        near(server.get_position("track").await, 0.7 + clock.now().await - seek_at);
+
+        // The status and stats report the same position
+        let status = &server.stream_statuses().await[0];
+        near(status.playback_position, 0.7 + clock.now().await - seek_at);
+
+        server.process_control(control(MediaAction::Stop, "track", 0.0, None)).await.unwrap();
+        assert_eq!(server.get_position("track").await, Some(0.0));
+        let stats = server.stats().await;
+        assert_eq!(stats.streams[0].status.playback_position, Some(0.0));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_playing_streams_publish_progress() {
+        let config = ServerConfig {
+            progress_interval_ms: 50,
+            ..Default::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let path = load_short_track(&server, "track").await;
+        let mut progress = server.subscribe_progress_events();
+        tokio::spawn(server.clone().run());
+
+        // Nothing is reported while no stream plays
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert!(progress.try_recv().is_err());
+
+        let start_at = server.clock_manager.now().await;
+        server.process_control(control(MediaAction::Play, "track", start_at, None)).await.unwrap();
+        let report = tokio::time::timeout(Duration::from_secs(1), progress.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.track_id, "track");
+        assert!(report.position >= 0.0 && report.position <= 0.06);
+
+        let _ = std::fs::remove_file(path);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_captured_frames_replay_byte_for_byte() {
+        let capture_dir = std::env::temp_dir().join(format!("solusync-captures-{}", Uuid::new_v4()));
+        let config = ServerConfig {
+            capture_dir: capture_dir.clone(),
+            capture_segment_bytes: 512,
+            ..ServerConfig::default()
+        };
+        let server = MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config));
+        let chunk = |track_id: &str, index: u64| MediaDataMessage {
+            header: MessageHeader::new(Uuid::new_v4(), index),
+            track_id: track_id.into(),
+            chunk_index: index,
+            timestamp: 50.0 + index as f64 * 0.04,
+            duration: 0.04,
+            data: (0..200).map(|i| (i * 31 + index as usize * 17) as u8).collect(),
+            codec: "h264".into(),
+            is_keyframe: index == 0,
+            compression: None,
+        };
+        
+        server.ingest_chunk(Uuid::new_v4(), chunk("cam", 0)).await.unwrap();
+        server.start_capture("cam", "debug").await.unwrap();
+        let mut original = server.subscribe_frames("cam").await.unwrap();
+        for index in 1..6 {
+            server.ingest_chunk(Uuid::new_v4(), chunk("cam", index)).await.unwrap();
+        }
+        let status = server.stop_capture("cam").await.unwrap();
+        assert_eq!(status.frames, 5);
+        assert!(status.segments.len() > 1);
+        assert!(matches!(server.stop_capture("cam").await, Err(RecordingError::NotRecording(_))));
+        
+        // Replaying re-ingests the frames on another stream at their pace
+        let captured = server.load_capture("debug").await.unwrap();
+        assert_eq!(captured.codec, "h264");
+        server.create_stream("cam-replay".into(), "h264".into()).await.unwrap();
+        let mut replayed = server.subscribe_frames("cam-replay").await.unwrap();
+        let started = tokio::time::Instant::now();
+        assert_eq!(server.replay_capture("cam-replay", captured).await.unwrap(), 5);
+        assert_eq!(started.elapsed(), Duration::from_millis(160));
+        
+        let mut offset = None;
+        for _ in 0..5 {
+            let sent = original.try_recv().unwrap();
+            let replay = replayed.try_recv().unwrap();
+            assert_eq!(replay.data, sent.data);
+            assert_eq!(replay.sequence, sent.sequence);
+            assert_eq!(replay.frame_type, sent.frame_type);
+            assert_eq!(replay.duration, sent.duration);
+            let shift = replay.timestamp - sent.timestamp;
+            assert!((shift - *offset.get_or_insert(shift)).abs() < 1e-9);
+        }
+        assert!(replayed.try_recv().is_err());
+        
+        std::fs::remove_dir_all(&capture_dir).ok();
+    }
+    
+    #[tokio::test]
+    async fn test_client_receives_opus_frames_over_rtp() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let client_id = Uuid::new_v4();
+        server.create_stream("live".into(), "opus".into()).await.unwrap();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "live".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        // A second peer stands in for the browser, recording every packet
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            Box::pin(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
                 }
-                
-                _ = self.process_commands() => {}
+            })
+        }));
+        
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        let offer = gathered(server_pc.clone()).await;
+        assert!(offer.sdp.contains("opus/48000"));
+        peer.set_remote_description(offer).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        WebRtcServer::handle_answer(&server_pc, gathered(peer.clone()).await).await.unwrap();
+        
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
             }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        
+        // 20ms frames, the producer having skipped the sixth one's slot
+        let start = server.clock_manager.now().await + 0.2;
+        let slots: Vec<u64> = (0..10).filter(|i| *i != 5).collect();
+        for (index, &slot) in slots.iter().enumerate() {
+            let index = index as u64;
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(Uuid::new_v4(), index),
+                track_id: "live".into(),
+                chunk_index: index,
+                timestamp: start + slot as f64 * 0.02,
+                duration: 0.02,
+                data: vec![0xfc, index as u8, 0xaa],
+                codec: "opus".into(),
+                is_keyframe: false,
+                compression: None,
+            };
+            server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap();
+        }
+        
+        let mut packets = Vec::new();
+        while packets.len() < slots.len() {
+            let packet = tokio::time::timeout(Duration::from_secs(5), packet_rx.recv())
+                .await
+                .expect("every frame arrives as an RTP packet")
+                .unwrap();
+            packets.push(packet);
+        }
+        let base = packets[0].header.timestamp;
+        for (index, (packet, slot)) in packets.iter().zip(&slots).enumerate() {
+            assert_eq!(packet.payload[..], [0xfc, index as u8, 0xaa]);
+            assert_eq!(packet.header.timestamp.wrapping_sub(base), *slot as u32 * 960);
         }
+        let sequence_numbers: Vec<u16> = packets
+            .iter()
+            .map(|packet| packet.header.sequence_number.wrapping_sub(packets[0].header.sequence_number))
+            .collect();
+        assert_eq!(sequence_numbers, (0..slots.len() as u16).collect::<Vec<_>>());
+        
+        // Each packet is counted as egress: at least a 12 byte header and
+        // the payload, plus any header extensions the connection adds
+        let egress = server.client_egress(client_id).await.unwrap();
+        assert_eq!(egress.messages, slots.len() as u64);
+        assert!(egress.bytes >= slots.len() as u64 * 15);
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
     }
     
-    /// Process incoming commands
-    async fn process_commands(&self) {
-        loop {
-            let cmd = {
-                let mut rx = self.control_rx.write().await;
-                rx.recv().await
+    #[tokio::test]
+    async fn test_client_without_opus_or_h264_negotiates_fallback_codecs() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let client_id = Uuid::new_v4();
+        server.create_stream("voice".into(), "pcm16".into()).await.unwrap();
+        server.create_stream("cam".into(), "vp8".into()).await.unwrap();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "voice".into()).await.unwrap();
+        server.subscribe_client(client_id, "cam".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        // A peer that only decodes VP8 video and μ-law audio
+        let peer = WebRtcServer::with_codecs(&ice, &[WebRtcCodec::Pcmu, WebRtcCodec::Vp8])
+            .create_peer_connection()
+            .await
+            .unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        // Tracks are read on their own tasks, as the handler is awaited
+        // before the next track is announced
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            tokio::spawn(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
+                }
+            });
+            Box::pin(async {})
+        }));
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        let offer = gathered(server_pc.clone()).await;
+        assert!(offer.sdp.contains("opus/48000") && offer.sdp.contains("VP8/90000"));
+        peer.set_remote_description(offer).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        server.apply_answer(client_id, gathered(peer.clone()).await.sdp).await.unwrap();
+        
+        let stats = server.stats().await;
+        assert_eq!(
+            stats.clients[0].negotiated_codecs,
+            MediaCodecs {
+                audio: Some(WebRtcCodec::Pcmu),
+                video: Some(WebRtcCodec::Vp8),
+            }
+        );
+        
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        
+        // PCM is encoded as μ-law for the client, VP8 sent as it is
+        let mut rtp_clocks = server.subscribe_rtp_clocks();
+        let start = server.clock_manager.now().await + 0.2;
+        for index in 0..3u64 {
+            let chunk = |track_id: &str, codec: &str, data: Vec<u8>| MediaDataMessage {
+                header: MessageHeader::new(Uuid::new_v4(), index),
+                track_id: track_id.into(),
+                chunk_index: index,
+                timestamp: start + index as f64 * 0.02,
+                duration: 0.02,
+                data,
+                codec: codec.into(),
+                is_keyframe: index == 0,
+                compression: None,
             };
-            
-            if let Some(cmd) = cmd {
-                if let Err(e) = self.process_control(cmd).await {
-                    error!("Error processing control command: {}", e);
+            server.ingest_chunk(Uuid::new_v4(), chunk("voice", "pcm16", vec![0; 3840])).await.unwrap();
+            server.ingest_chunk(Uuid::new_v4(), chunk("cam", "vp8", vec![0x10; 100])).await.unwrap();
+        }
+        
+        let mut audio = Vec::new();
+        let mut video = Vec::new();
+        while audio.len() < 3 || video.len() < 3 {
+            let packet = tokio::time::timeout(Duration::from_secs(5), packet_rx.recv())
+                .await
+                .expect("every frame arrives as an RTP packet")
+                .unwrap();
+            match packet.header.payload_type {
+                0 => audio.push(packet),
+                _ => video.push(packet),
+            }
+        }
+        assert!(audio.iter().all(|packet| packet.payload[..] == [0xff; 160]));
+        assert_eq!(audio[1].header.timestamp.wrapping_sub(audio[0].header.timestamp), 160);
+        assert!(video.iter().all(|packet| packet.header.marker && packet.payload.ends_with(&[0x10; 100])));
+        assert_eq!(video[1].header.timestamp.wrapping_sub(video[0].header.timestamp), 1800);
+        
+        // Each track's announced mapping places its packets at the frames'
+        // presentation times
+        for _ in 0..2 {
+            let (announced_for, codec, mapping) = rtp_clocks.try_recv().unwrap();
+            assert_eq!(announced_for, client_id);
+            assert_eq!(mapping.clock_rate, codec.clock_rate());
+            let packets = if codec == WebRtcCodec::Pcmu { &audio } else { &video };
+            for (index, packet) in packets.iter().enumerate() {
+                let presented = mapping.network_time(packet.header.timestamp, start);
+                let expected = start + index as f64 * 0.02;
+                assert!((presented - expected).abs() <= 1.0 / mapping.clock_rate as f64, "{:?}", codec);
+            }
+        }
+        assert!(rtp_clocks.try_recv().is_err());
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
+    }
+    
+    #[tokio::test]
+    async fn test_played_pcm_reaches_opus_clients_as_tier_renditions() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            media_dir: std::env::temp_dir(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        server.set_encoder_factory(Arc::new(|tier, _, _| {
+            Ok(Box::new(TaggingEncoder(tier)) as Box<dyn rendition::TierEncoder>)
+        }));
+        let path = load_test_track(&server).await;
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "track".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        let peer = WebRtcServer::with_codecs(&ice, &[WebRtcCodec::Opus, WebRtcCodec::Vp8])
+            .create_peer_connection()
+            .await
+            .unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            tokio::spawn(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
+                }
+            });
+            Box::pin(async {})
+        }));
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        peer.set_remote_description(gathered(server_pc.clone()).await).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        server.apply_answer(client_id, gathered(peer.clone()).await.sdp).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        
+        // The client is on a good network, so gets the high tier encoding
+        // of each frame rather than the PCM
+        let start_at = server.clock_manager.now().await + 0.2;
+        server
+            .process_control(control(MediaAction::Play, "track", start_at, None))
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let packet = tokio::time::timeout(Duration::from_secs(5), packet_rx.recv())
+                .await
+                .expect("played frames arrive as RTP packets")
+                .unwrap();
+            assert_eq!(packet.payload[..], QualityTier::High.bitrate_kbps().to_le_bytes());
+        }
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
+        let _ = std::fs::remove_file(path);
+    }
+    
+    #[tokio::test]
+    async fn test_packets_carry_the_buffer_latency_as_playout_delay() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let client_id = Uuid::new_v4();
+        server.create_stream("live".into(), "opus".into()).await.unwrap();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "live".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            Box::pin(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
+                }
+            })
+        }));
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        let offer = gathered(server_pc.clone()).await;
+        assert!(offer.sdp.contains(rtp_sender::PLAYOUT_DELAY_URI));
+        peer.set_remote_description(offer).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        let answer = gathered(peer.clone()).await.sdp;
+        let extension_id: u8 = answer
+            .lines()
+            .find_map(|line| line.strip_prefix("a=extmap:")?.strip_suffix(rtp_sender::PLAYOUT_DELAY_URI))
+            .expect("the answer accepts the playout-delay extension")
+            .trim()
+            .parse()
+            .unwrap();
+        server.apply_answer(client_id, answer).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        
+        let send = |slot: u64, start: f64| {
+            let server = server.clone();
+            async move {
+                let chunk = MediaDataMessage {
+                    header: MessageHeader::new(Uuid::new_v4(), slot),
+                    track_id: "live".into(),
+                    chunk_index: slot,
+                    timestamp: start + slot as f64 * 0.02,
+                    duration: 0.02,
+                    data: vec![0xfc, slot as u8],
+                    codec: "opus".into(),
+                    is_keyframe: false,
+                    compression: None,
+                };
+                server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap();
+            }
+        };
+        async fn next_delay(
+            packets: &mut mpsc::UnboundedReceiver<webrtc::rtp::packet::Packet>,
+            extension_id: u8,
+        ) -> PlayoutDelay {
+            let packet = tokio::time::timeout(Duration::from_secs(5), packets.recv())
+                .await
+                .expect("frames arrive as RTP packets")
+                .unwrap();
+            let data = packet.header.get_extension(extension_id).expect("packets carry a playout delay");
+            PlayoutDelay::decode(&data).unwrap()
+        }
+        
+        // Frames are released one target latency ahead, which the client
+        // is told to hold them for
+        let target = server.get_buffer_stats(client_id).await.unwrap().target_latency_ms;
+        let start = server.clock_manager.now().await + 0.2;
+        send(0, start).await;
+        assert_eq!(next_delay(&mut packet_rx, extension_id).await, PlayoutDelay::fixed(Duration::from_millis(target as u64)));
+        
+        // A new target latency goes out with the next frame
+        let bounds = LatencyBounds {
+            min_latency_ms: Some(250),
+            max_latency_ms: Some(250),
+        };
+        server.set_latency_bounds(client_id, BoundsSource::Api, bounds).await.unwrap();
+        let start = server.clock_manager.now().await + 0.5;
+        send(1, start).await;
+        let delay = next_delay(&mut packet_rx, extension_id).await;
+        assert_eq!(delay, PlayoutDelay { min_ms: 250, max_ms: 250 });
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
+    }
+    
+    #[tokio::test]
+    async fn test_webrtc_stats_describe_the_connected_peer() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let client_id = Uuid::new_v4();
+        server.create_stream("live".into(), "opus".into()).await.unwrap();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "live".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        // Nothing is collected until the first collection
+        assert!(server.webrtc_stats().is_empty());
+        server.collect_webrtc_stats().await;
+        let stats = server.webrtc_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].connection_state, "new");
+        assert!(stats[0].selected_candidate_pair.is_none());
+        
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            Box::pin(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
+                }
+            })
+        }));
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        peer.set_remote_description(gathered(server_pc.clone()).await).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        server.apply_answer(client_id, gathered(peer.clone()).await.sdp).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        
+        let start = server.clock_manager.now().await + 0.1;
+        for slot in 0..3u64 {
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(Uuid::new_v4(), slot),
+                track_id: "live".into(),
+                chunk_index: slot,
+                timestamp: start + slot as f64 * 0.02,
+                duration: 0.02,
+                data: vec![0xfc, slot as u8],
+                codec: "opus".into(),
+                is_keyframe: false,
+                compression: None,
+            };
+            server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap();
+        }
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(5), packet_rx.recv())
+                .await
+                .expect("frames arrive as RTP packets")
+                .unwrap();
+        }
+        
+        server.collect_webrtc_stats().await;
+        let stats = server.webrtc_stats().remove(0);
+        assert_eq!(stats.client_id, client_id);
+        assert_eq!(stats.connection_state, "connected");
+        assert_eq!(stats.negotiated_codecs.audio, Some(WebRtcCodec::Opus));
+        let pair = stats.selected_candidate_pair.expect("ICE selected a candidate pair");
+        assert_eq!((pair.local.candidate_type.as_str(), pair.remote.candidate_type.as_str()), ("host", "host"));
+        assert_ne!(pair.local.port, 0);
+        assert_eq!(stats.packets_sent, 3);
+        assert!(stats.bytes_sent > 0 && stats.bytes_received > 0);
+        assert!(stats.current_rtt_ms.is_some());
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
+    }
+    
+    #[tokio::test]
+    async fn test_ice_restart_holds_media_until_reconnected() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let server = Arc::new(MediaServer::with_config(Arc::new(ClockManager::new()), Arc::new(config)));
+        let mut offers = server.subscribe_ice_restart_offers();
+        let mut peer_states = server.peer_state_rx.lock().take().unwrap();
+        let client_id = Uuid::new_v4();
+        server.create_stream("live".into(), "opus".into()).await.unwrap();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "live".into()).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.on_track(Box::new(move |track, _, _| {
+            let packet_tx = packet_tx.clone();
+            Box::pin(async move {
+                while let Ok((packet, _)) = track.read_rtp().await {
+                    let _ = packet_tx.send(packet);
                 }
-            } else {
+            })
+        }));
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        let answer = |offer: RTCSessionDescription| {
+            let peer = peer.clone();
+            async move {
+                peer.set_remote_description(offer).await.unwrap();
+                let answer = peer.create_answer(None).await.unwrap();
+                peer.set_local_description(answer).await.unwrap();
+                gathered(peer).await.sdp
+            }
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        let sdp = answer(gathered(server_pc.clone()).await).await;
+        server.apply_answer(client_id, sdp).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while [&server_pc, &peer]
+                .iter()
+                .any(|pc| pc.connection_state() != RTCPeerConnectionState::Connected)
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("peers connect over host candidates");
+        while let Ok(event) = peer_states.try_recv() {
+            server.handle_peer_state(event).await;
+        }
+        
+        let start = server.clock_manager.now().await + 0.2;
+        let send = |slot: u64| {
+            let server = server.clone();
+            async move {
+                let chunk = MediaDataMessage {
+                    header: MessageHeader::new(Uuid::new_v4(), slot),
+                    track_id: "live".into(),
+                    chunk_index: slot,
+                    timestamp: start + slot as f64 * 0.02,
+                    duration: 0.02,
+                    data: vec![0xfc, slot as u8],
+                    codec: "opus".into(),
+                    is_keyframe: false,
+                    compression: None,
+                };
+                server.ingest_chunk(Uuid::new_v4(), chunk).await.unwrap();
+            }
+        };
+        let packet_wait = Duration::from_secs(5);
+        send(0).await;
+        let mut packets = vec![tokio::time::timeout(packet_wait, packet_rx.recv()).await.unwrap().unwrap()];
+        
+        // Frames due while the restart is under way are held back
+        server
+            .handle_peer_state(PeerStateEvent {
+                client_id,
+                connection: Arc::downgrade(&server_pc),
+                state: RTCPeerConnectionState::Failed,
+            })
+            .await;
+        for slot in 1..4 {
+            send(slot).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(server.client_egress(client_id).await.unwrap().messages, 1);
+        assert_eq!(offers.try_recv().unwrap().0, client_id);
+        let offer = gathered(server_pc.clone()).await;
+        
+        // Once the client has answered and ICE is connected again, they
+        // follow in order and on the same timeline
+        let sdp = answer(offer).await;
+        server.apply_answer(client_id, sdp).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while server.clients.read().await[&client_id].disconnected_since.is_some() {
+                server.handle_peer_state(peer_states.recv().await.unwrap()).await;
+            }
+        })
+        .await
+        .expect("ICE connects again after the restart");
+        while packets.len() < 4 {
+            let packet = tokio::time::timeout(packet_wait, packet_rx.recv())
+                .await
+                .expect("held frames arrive as RTP packets");
+            packets.push(packet.unwrap());
+        }
+        for (slot, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.payload[..], [0xfc, slot as u8]);
+            assert_eq!(
+                packet.header.timestamp.wrapping_sub(packets[0].header.timestamp),
+                slot as u32 * 960
+            );
+        }
+        assert_eq!(server.stats().await.ice_restarts, 1);
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
+    }
+    
+    #[tokio::test]
+    async fn test_clock_channel_answers_requests_sent_as_it_opens() {
+        use clock_channel::{ClockPacket, SyncPath, CLOCK_CHANNEL_LABEL};
+        
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let config = ServerConfig {
+            ice: ice.clone(),
+            ..ServerConfig::default()
+        };
+        let clock = Arc::new(ClockManager::new());
+        tokio::spawn(clock.clone().run());
+        let server = Arc::new(MediaServer::with_config(clock.clone(), Arc::new(config)));
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        let server_pc = server.clients.read().await[&client_id].peer_connection.clone();
+        
+        // The browser side sends its first request the moment its end of
+        // the channel opens, which can be before the server's end has
+        let peer = WebRtcServer::new(&ice).create_peer_connection().await.unwrap();
+        let (channel_tx, mut channel_rx) = mpsc::unbounded_channel();
+        let (response_tx, mut response_rx) = mpsc::unbounded_channel();
+        peer.on_data_channel(Box::new(move |channel| {
+            assert_eq!(channel.label(), CLOCK_CHANNEL_LABEL);
+            let response_tx = response_tx.clone();
+            channel.on_message(Box::new(move |message| {
+                let t4 = crate::protocol::get_current_time();
+                let _ = response_tx.send((ClockPacket::decode(&message.data), t4));
+                Box::pin(async {})
+            }));
+            let opened = channel.clone();
+            let channel_tx = channel_tx.clone();
+            channel.on_open(Box::new(move || {
+                Box::pin(async move {
+                    let t1 = crate::protocol::get_current_time();
+                    let request = ClockPacket::Request { sequence: 1, t1 }.encode();
+                    opened.send(&request.into()).await.unwrap();
+                    let _ = channel_tx.send(opened);
+                })
+            }));
+            Box::pin(async {})
+        }));
+        
+        let gathered = |pc: Arc<RTCPeerConnection>| async move {
+            pc.gathering_complete_promise().await.recv().await;
+            pc.local_description().await.unwrap()
+        };
+        WebRtcServer::create_offer(&server_pc).await.unwrap();
+        peer.set_remote_description(gathered(server_pc.clone()).await).await.unwrap();
+        let answer = peer.create_answer(None).await.unwrap();
+        peer.set_local_description(answer).await.unwrap();
+        WebRtcServer::handle_answer(&server_pc, gathered(peer.clone()).await).await.unwrap();
+        
+        let channel = tokio::time::timeout(Duration::from_secs(10), channel_rx.recv())
+            .await
+            .expect("clock channel opens")
+            .unwrap();
+        let (response, t4) = tokio::time::timeout(Duration::from_secs(5), response_rx.recv())
+            .await
+            .expect("the first request is answered")
+            .unwrap();
+        let Some(ClockPacket::Response { sequence: 1, t1, t2, t3 }) = response else {
+            panic!("Unexpected clock packet: {:?}", response);
+        };
+        assert!(t1 <= t2 && t2 <= t3 && t3 <= t4);
+        
+        // The completed exchange is handed back and followed by the server
+        let report = ClockPacket::Report { path: SyncPath::DataChannel, t1, t2, t3, t4 };
+        channel.send(&report.encode().into()).await.unwrap();
+        let mut stats = None;
+        for _ in 0..200 {
+            stats = clock.get_peer_stats(&client_id).await;
+            if stats.is_some() {
                 break;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        let stats = stats.expect("the report reaches the clock manager");
+        assert_eq!(stats.sample_count, 1);
+        assert!((0.0..1.0).contains(&stats.rtt));
+        
+        peer.close().await.unwrap();
+        server.remove_client(client_id).await;
     }
     
-    /// Log server statistics
-    async fn log_stats(&self) {
-        let streams = self.streams.read().await;
-        let clients = self.clients.read().await;
+    /// Status of a client's subscription to a tiered video, once `ready`
+    /// holds for it
+    async fn video_tier_status(
+        server: &MediaServer,
+        client_id: Uuid,
+        ready: impl Fn(&simulcast::VideoTierStatus) -> bool,
+    ) -> simulcast::VideoTierStatus {
+        for _ in 0..100 {
+            let stats = server.stats().await;
+            let client = stats.clients.iter().find(|c| c.client_id == client_id).unwrap();
+            if let Some(status) = client.video_tiers.first().filter(|status| ready(status)) {
+                return status.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("video tier never settled");
+    }
+    
+    #[tokio::test]
+    async fn test_tiered_video_switches_tiers_at_keyframes() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let producer = Uuid::new_v4();
+        let mut next_index = HashMap::new();
+        let mut ingest = |track_id: &'static str, is_keyframe: bool| {
+            let index = next_index.entry(track_id).or_insert(0u64);
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(producer, *index),
+                track_id: track_id.into(),
+                chunk_index: *index,
+                timestamp: 100.0 + *index as f64 * 0.04,
+                duration: 0.04,
+                data: vec![0; 100],
+                codec: "h264".into(),
+                is_keyframe,
+                compression: None,
+            };
+            *index += 1;
+            server.ingest_chunk(producer, chunk)
+        };
+        ingest("stage-360p", true).await.unwrap();
+        ingest("stage-720p", true).await.unwrap();
         
-        debug!(
-            "Media server stats: {} streams, {} clients",
-            streams.len(),
-            clients.len()
+        let tiered = |tiers: &[(QualityTier, &str)]| TieredVideo {
+            stream_id: "stage".into(),
+            tiers: tiers.iter().map(|(tier, track)| (*tier, track.to_string())).collect(),
+        };
+        assert!(server.create_tiered_video(tiered(&[(QualityTier::Low, "stage-360p")])).await.is_err());
+        let same_track = [(QualityTier::Low, "stage-360p"), (QualityTier::High, "stage-360p")];
+        assert!(server.create_tiered_video(tiered(&same_track)).await.is_err());
+        let tiers = [(QualityTier::Low, "stage-360p"), (QualityTier::High, "stage-720p")];
+        server.create_tiered_video(tiered(&tiers)).await.unwrap();
+        assert!(server.create_tiered_video(tiered(&tiers)).await.is_err());
+        
+        let client_id = Uuid::new_v4();
+        server.add_client(client_id).await.unwrap();
+        server.subscribe_client(client_id, "stage".into()).await.unwrap();
+        assert_eq!(server.client_subscriptions(client_id).await.unwrap(), ["stage"]);
+        
+        // A Good link starts on the high tier at its next keyframe
+        ingest("stage-720p", false).await.unwrap();
+        ingest("stage-720p", true).await.unwrap();
+        let status = video_tier_status(&server, client_id, |status| status.tier.is_some()).await;
+        assert_eq!((status.tier, status.switches), (Some(QualityTier::High), 0));
+        
+        // A Poor link wants the low tier, which waits for its keyframe
+        server.update_client_quality(client_id, NetworkQuality::Poor).await;
+        for _ in 0..5 {
+            ingest("stage-720p", false).await.unwrap();
+            ingest("stage-360p", false).await.unwrap();
+        }
+        let status = video_tier_status(&server, client_id, |status| status.target == QualityTier::Low).await;
+        assert_eq!((status.tier, status.switches), (Some(QualityTier::High), 0));
+        
+        ingest("stage-360p", true).await.unwrap();
+        let status = video_tier_status(&server, client_id, |status| status.switches == 1).await;
+        assert_eq!(status.tier, Some(QualityTier::Low));
+        
+        assert!(server.unsubscribe_client(client_id, "stage").await.unwrap());
+        assert!(server.stats().await.clients[0].video_tiers.is_empty());
+        assert!(server.remove_tiered_video("stage").await);
+    }
+    
+    #[tokio::test]
+    async fn test_video_subscribers_start_at_a_requested_keyframe() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        let producer = Uuid::new_v4();
+        // Far enough ahead for the frames to wait in the future buffer
+        let start = server.clock_manager.now().await + 60.0;
+        let mut index = 0u64;
+        let mut ingest = |is_keyframe: bool| {
+            let chunk = MediaDataMessage {
+                header: MessageHeader::new(producer, index),
+                track_id: "cam".into(),
+                chunk_index: index,
+                timestamp: start + index as f64 * 0.04,
+                duration: 0.04,
+                data: vec![0; 100],
+                codec: "h264".into(),
+                is_keyframe,
+                compression: None,
+            };
+            index += 1;
+            server.ingest_chunk(producer, chunk)
+        };
+        ingest(true).await.unwrap();
+        
+        // Subscribing asks the producer for a keyframe, once per interval
+        let mut requests = server.subscribe_keyframe_requests();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        for client_id in [first, second] {
+            server.add_client(client_id).await.unwrap();
+            server.subscribe_client(client_id, "cam".into()).await.unwrap();
+        }
+        let request = requests.try_recv().unwrap();
+        assert_eq!(
+            request,
+            KeyframeRequest {
+                producer_id: producer,
+                track_id: "cam".into(),
+                reason: KeyframeRequestReason::Subscribe,
+            }
         );
+        assert!(requests.try_recv().is_err());
+        
+        // Delta frames before the keyframe cannot be decoded and are held back
+        ingest(false).await.unwrap();
+        ingest(false).await.unwrap();
+        ingest(true).await.unwrap();
+        ingest(false).await.unwrap();
+        let mut queued = Vec::new();
+        for _ in 0..100 {
+            if let Some(client) = server.clients.write().await.get_mut(&first) {
+                // Due as the keyframe is, so no frame is late
+                queued.extend(client.queue.lock().future_buffer.pop_ready(start + 0.12));
+            }
+            if queued.len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let frame_types: Vec<_> = queued.iter().map(|queued| queued.frame.frame_type).collect();
+        assert_eq!(frame_types, [buffer::FrameType::VideoKeyframe, buffer::FrameType::Video]);
+        
+        let stats = server.stats().await;
+        assert!(stats.clients.iter().all(|client| client.keyframe_requests == 1));
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_start_lead_covers_the_deepest_subscriber_buffer() {
+        let server = MediaServer::new(Arc::new(ClockManager::new()));
+        server.create_stream("track".into(), "opus".into()).await.unwrap();
+        assert_eq!(server.start_lead("track", None).await, Duration::from_millis(100));
+        
+        // Only subscribers count, however deep another client's buffer is
+        for (latency_ms, subscribes) in [(180, true), (120, true), (400, false)] {
+            let client_id = Uuid::new_v4();
+            server.add_client(client_id).await.unwrap();
+            let bounds = LatencyBounds {
+                min_latency_ms: Some(latency_ms),
+                max_latency_ms: Some(latency_ms),
+            };
+            server.set_latency_bounds(client_id, BoundsSource::Api, bounds).await.unwrap();
+            if subscribes {
+                server.subscribe_client(client_id, "track".into()).await.unwrap();
+            }
+        }
+        
+        let lead = server.start_lead("track", None).await;
+        assert!(lead >= Duration::from_millis(180), "{:?}", lead);
+        assert!(lead < Duration::from_millis(400), "{:?}", lead);
+        assert_eq!(server.start_lead("other", None).await, Duration::from_millis(100));
+    }
+}
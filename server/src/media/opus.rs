@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use audiopus::{coder::Encoder, Application, Bitrate, Channels, SampleRate};
+use std::sync::Arc;
+
+use super::rendition::{EncoderFactory, QualityTier, TierEncoder};
+
+/// Largest Opus packet libopus recommends allocating for
+const MAX_PACKET_BYTES: usize = 4000;
+
+/// Encodes `pcm16` frames to Opus at a tier's bitrate
+pub struct OpusTierEncoder {
+    encoder: Encoder,
+}
+
+impl OpusTierEncoder {
+    pub fn new(tier: QualityTier, sample_rate: u32, channels: u8) -> Result<Self> {
+        let sample_rate = SampleRate::try_from(sample_rate as i32)
+            .with_context(|| format!("Opus cannot encode {}Hz audio", sample_rate))?;
+        let channels = Channels::try_from(channels as i32)
+            .with_context(|| format!("Opus cannot encode {} channels", channels))?;
+        let mut encoder = Encoder::new(sample_rate, channels, Application::Audio)?;
+        encoder.set_bitrate(Bitrate::BitsPerSecond(tier.bitrate_kbps() as i32 * 1000))?;
+        Ok(Self { encoder })
+    }
+}
+
+impl TierEncoder for OpusTierEncoder {
+    fn encode(&mut self, pcm: &[u8]) -> Result<Vec<u8>> {
+        let samples: Vec<i16> = pcm
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        let mut packet = vec![0; MAX_PACKET_BYTES];
+        let len = self.encoder.encode(&samples, &mut packet)?;
+        packet.truncate(len);
+        Ok(packet)
+    }
+
+    fn set_expected_loss(&mut self, loss_percent: Option<u8>) {
+        let result = self
+            .encoder
+            .set_inband_fec(loss_percent.is_some())
+            .and_then(|()| self.encoder.set_packet_loss_perc(loss_percent.unwrap_or(0)));
+        if let Err(e) = result {
+            tracing::warn!("Failed to set Opus expected loss: {}", e);
+        }
+    }
+}
+
+/// Factory creating an `OpusTierEncoder` per tier
+pub fn opus_encoder_factory() -> EncoderFactory {
+    Arc::new(|tier, sample_rate, channels| {
+        Ok(Box::new(OpusTierEncoder::new(tier, sample_rate, channels)?) as Box<dyn TierEncoder>)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiers_encode_at_their_bitrate() {
+        // A 20ms stereo 48kHz frame of a loud square wave
+        let pcm: Vec<u8> = (0..960 * 2)
+            .flat_map(|i| if (i / 2) % 48 < 24 { 8000i16 } else { -8000 }.to_le_bytes())
+            .collect();
+        let factory = opus_encoder_factory();
+        let mut sizes = Vec::new();
+        for tier in QualityTier::ALL {
+            let mut encoder = factory(tier, 48000, 2).unwrap();
+            let packet = (0..10).map(|_| encoder.encode(&pcm).unwrap()).last().unwrap();
+            assert!(!packet.is_empty() && packet.len() < pcm.len());
+            sizes.push(packet.len());
+        }
+        assert!(sizes.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", sizes);
+
+        assert!(factory(QualityTier::High, 44100, 2).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{error, info};
+
+use super::{catalog::TrackInfo, program::Program, simulcast::TieredVideo, zone::DeviceZone};
+
+/// Server state kept across restarts
+///
+/// Only definitions the operator created are stored: the track catalog,
+/// with each track's loudness and normalization gain, programs with their
+/// A/V offsets, tiered videos and the zone each device was put in.
+/// Connected clients, clock offsets and playback are live state and start
+/// empty.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PersistedState {
+    #[serde(default)]
+    pub tracks: Vec<TrackInfo>,
+
+    #[serde(default)]
+    pub programs: Vec<Program>,
+
+    #[serde(default)]
+    pub video_tiers: Vec<TieredVideo>,
+
+    #[serde(default)]
+    pub zones: Vec<DeviceZone>,
+}
+
+/// JSON file holding the persisted state
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the saved state, or an empty state if there is none
+    ///
+    /// A file that cannot be parsed is moved aside with a `.corrupt`
+    /// suffix so the server still starts and the next save does not
+    /// overwrite it.
+    pub fn load(&self) -> PersistedState {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return PersistedState::default(),
+            Err(e) => {
+                error!("Failed to read state file {}: {}", self.path.display(), e);
+                return PersistedState::default();
+            }
+        };
+
+        match serde_json::from_slice(&data) {
+            Ok(state) => state,
+            Err(e) => {
+                let backup = self.sibling(&format!(".corrupt-{}", chrono::Utc::now().timestamp()));
+                error!(
+                    "State file {} is corrupt ({}); moving it to {} and starting empty",
+                    self.path.display(),
+                    e,
+                    backup.display()
+                );
+                if let Err(e) = std::fs::rename(&self.path, &backup) {
+                    error!("Failed to back up corrupt state file: {}", e);
+                }
+                PersistedState::default()
+            }
+        }
+    }
+
+    /// Replace the saved state
+    ///
+    /// Blocks on the file system; async callers run it on a blocking
+    /// thread.
+    ///
+    /// The state is written to a temporary file that is renamed over the
+    /// old one, so a crash mid-write leaves the previous state intact.
+    pub fn save(&self, state: &PersistedState) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        let temp_path = self.sibling(".tmp");
+        let mut file = std::fs::File::create(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        file.write_all(&serde_json::to_vec_pretty(state)?)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        info!(
+            "Saved {} tracks, {} programs and {} device zones to {}",
+            state.tracks.len(),
+            state.programs.len(),
+            state.zones.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Path of the state file with `suffix` appended
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_state_file() -> (PathBuf, StateFile) {
+        let dir = std::env::temp_dir().join(format!("solusync-state-{}", uuid::Uuid::new_v4()));
+        let file = StateFile::new(dir.join("state.json"));
+        (dir, file)
+    }
+
+    #[test]
+    fn test_tracks_programs_and_zones_round_trip() {
+        let (dir, file) = temp_state_file();
+        assert!(file.load().tracks.is_empty());
+
+        let track = TrackInfo {
+            track_id: "trk_0123456789abcdef".into(),
+            path: PathBuf::from("media/0123.wav"),
+            content_hash: "0123".repeat(16),
+            codec: "pcm16".into(),
+            sample_rate: 48000,
+            channels: 2,
+            duration: Some(12.5),
+            size_bytes: 4096,
+            added_at: chrono::Utc::now(),
+            loudness: Some(-23.4),
+            gain_db: 7.4,
+        };
+        let program = Program {
+            program_id: "movie".into(),
+            audio_track: "movie_audio".into(),
+            video_track: "movie_video".into(),
+            av_offset: 0.04,
+        };
+        let state = PersistedState {
+            tracks: vec![track.clone()],
+            programs: vec![program.clone()],
+            video_tiers: Vec::new(),
+            zones: vec![DeviceZone {
+                device_id: uuid::Uuid::new_v4(),
+                zone: "hall".into(),
+            }],
+        };
+        file.save(&state).unwrap();
+        assert!(!file.sibling(".tmp").exists());
+
+        let loaded = file.load();
+        let loaded_track = &loaded.tracks[0];
+        assert_eq!(loaded_track.track_id, track.track_id);
+        assert_eq!(loaded_track.path, track.path);
+        assert_eq!(loaded_track.content_hash, track.content_hash);
+        assert_eq!(loaded_track.duration, track.duration);
+        assert_eq!(loaded_track.added_at, track.added_at);
+        assert_eq!(loaded_track.loudness, track.loudness);
+        assert_eq!(loaded_track.gain_db, track.gain_db);
+
+        let loaded_program = &loaded.programs[0];
+        assert_eq!(loaded_program.program_id, program.program_id);
+        assert_eq!(loaded_program.audio_track, program.audio_track);
+        assert_eq!(loaded_program.video_track, program.video_track);
+        assert_eq!(loaded_program.av_offset, program.av_offset);
+        assert_eq!(loaded.zones, state.zones);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_corrupt_state_is_backed_up_and_ignored() {
+        let (dir, file) = temp_state_file();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(file.path(), b"{\"tracks\": [").unwrap();
+
+        let state = file.load();
+        assert!(state.tracks.is_empty() && state.programs.is_empty() && state.zones.is_empty());
+        assert!(!file.path().exists());
+
+        let backups: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(std::fs::read(&backups[0]).unwrap(), b"{\"tracks\": [");
+
+        // The next save starts a fresh file next to the backup
+        file.save(&PersistedState::default()).unwrap();
+        assert!(file.path().exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
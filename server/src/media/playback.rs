@@ -0,0 +1,827 @@
+use parking_lot::Mutex;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
+
+use super::{
+    buffer::MediaFrame,
+    fec::LossFeedback,
+    mixer::Mixer,
+    rendition::{EncoderFactory, QualityTier, Rendition, TierEncoder},
+    source::{FrameSource, SourceFrame},
+    stats::StreamCounters,
+};
+use crate::{clock::ClockManager, protocol::LOOP_FOREVER};
+
+/// Shared handle to a stream's frame source
+pub type SharedSource = Arc<Mutex<Box<dyn FrameSource>>>;
+
+/// Stream resources a playback writes into
+#[derive(Clone)]
+pub struct PlaybackTarget {
+    pub track_id: String,
+
+    /// Broadcast channel for emitted frames
+    pub frame_tx: broadcast::Sender<MediaFrame>,
+
+    /// Next frame sequence number
+    pub sequence: Arc<AtomicU64>,
+
+    /// Number of times the track has restarted for looping
+    pub loop_iteration: Arc<AtomicU32>,
+
+    /// Notified when playback reaches the end of the track
+    pub finished_tx: Option<mpsc::Sender<PlaybackFinished>>,
+
+    /// Emitted frame counters and track position
+    pub stats: Arc<StreamCounters>,
+
+    /// Encoders for the quality tier renditions of `pcm16` frames
+    pub encoder_factory: Option<EncoderFactory>,
+
+    /// Loss the clients of each tier expect, which its encoder is told of
+    pub loss_feedback: Arc<LossFeedback>,
+}
+
+/// Playback that ran to the end of its track
+#[derive(Debug, Clone)]
+pub struct PlaybackFinished {
+    pub track_id: String,
+
+    /// Network time at which the last frame ends
+    pub end_at: f64,
+
+    /// Position in the crossfaded next track at which it continues
+    pub handoff: Option<f64>,
+}
+
+/// Next track mixed into the end of a playback
+#[derive(Clone)]
+pub struct Crossfade {
+    /// Source of the next track, rewound when the overlap starts
+    pub source: SharedSource,
+
+    /// Length of the overlap
+    pub duration: Duration,
+
+    /// Linear loudness normalization gain of the next track
+    pub gain: f64,
+}
+
+/// Scheduling parameters for a playback
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackParams {
+    /// Network time at which `origin` is presented
+    pub start_at: f64,
+
+    /// Track position presented at `start_at` (defaults to the first frame's)
+    pub origin: Option<f64>,
+
+    /// Additional plays after the first (`LOOP_FOREVER` loops indefinitely)
+    pub loop_count: u32,
+
+    /// Gain ramp from silence starting at `start_at`
+    pub fade_in: Option<Duration>,
+
+    /// Network time at which playback stops on its own
+    pub stop_at: Option<f64>,
+
+    /// Gain ramp to silence ending at `stop_at`
+    pub fade_out: Option<Duration>,
+
+    /// How far ahead of its presentation time each frame is emitted
+    pub prebuffer: Duration,
+
+    /// Linear loudness normalization gain
+    pub gain: f64,
+}
+
+/// Gain ramp down to silence, after which playback ends
+#[derive(Debug, Clone, Copy)]
+struct FadeOut {
+    /// Network time at which the ramp starts
+    start: f64,
+
+    /// Network time at which the ramp reaches silence
+    end: f64,
+}
+
+/// Running playback of a frame source into a stream's broadcast channel
+///
+/// Frames are emitted `prebuffer` ahead of their presentation time on the
+/// network clock, so clients hold them before they are due: a frame at track
+/// position `p` is presented at `start_at + (p - origin)`. Without an
+/// explicit origin the first frame's position is used. A start scheduled in
+/// the future therefore begins emitting immediately. When the source runs
+/// out and loops remain, it is rewound and the next frame is presented
+/// exactly where the previous one ended. With `stop_at` set, no frame
+/// starting at or after it is emitted, so the last frame ends within one
+/// frame duration of the requested time.
+///
+/// Fades and the normalization gain are applied to `pcm16` frames only;
+/// callers must reject fades for sources that cannot be decoded. Likewise,
+/// only `pcm16` frames are re-encoded into quality tier renditions.
+///
+/// With a crossfade set, the final play of a track of known length mixes
+/// the next track into its last `duration`, and reports how far into the
+/// next track the mix got when it finishes.
+pub struct Playback {
+    cancel: CancellationToken,
+    fade_out_tx: watch::Sender<Option<FadeOut>>,
+    crossfade_tx: watch::Sender<Option<Crossfade>>,
+    task: JoinHandle<()>,
+}
+
+impl Playback {
+    /// Start emitting frames from `source`
+    pub fn start(
+        target: PlaybackTarget,
+        source: SharedSource,
+        clock: Arc<ClockManager>,
+        params: PlaybackParams,
+    ) -> Self {
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        let (fade_out_tx, mut fade_out_rx) = watch::channel(None::<FadeOut>);
+        let (crossfade_tx, crossfade_rx) = watch::channel(None::<Crossfade>);
+
+        // Logged in the span of the command that started playback
+        let task = tokio::spawn(async move {
+            let (sample_rate, channels, is_pcm, track_duration) = {
+                let source = source.lock();
+                (
+                    source.sample_rate(),
+                    source.channels(),
+                    source.codec() == "pcm16",
+                    source.duration(),
+                )
+            };
+            let mut encoders = match &target.encoder_factory {
+                Some(factory) if is_pcm => tier_encoders(factory, sample_rate, channels),
+                _ => Vec::new(),
+            };
+            let scheduled_stop = params.stop_at.map(|end| FadeOut {
+                start: end - params.fade_out.map_or(0.0, |fade| fade.as_secs_f64()),
+                end,
+            });
+            let mut segment_start = params.start_at;
+            let mut origin = params.origin;
+            let prebuffer = params.prebuffer.as_secs_f64();
+            let mut due = params.start_at;
+            let mut mixer: Option<Mixer> = None;
+
+            loop {
+                let requested = *fade_out_rx.borrow();
+                let fade_out = requested.or(scheduled_stop);
+                if fade_out.is_some_and(|fade| due >= fade.end) {
+                    if requested.is_some() {
+                        info!("Playback of {} faded out", target.track_id);
+                    } else if wait_until(&clock, &token, due).await {
+                        info!("Playback of {} reached its scheduled stop", target.track_id);
+                        notify_finished(&target, due, mixer.as_ref()).await;
+                    }
+                    return;
+                }
+
+                // Wait for the next frame to enter the prebuffer window before
+                // pulling it from the source, so that cancelling never
+                // discards a frame
+                let now = clock.now().await;
+                let wait = due - prebuffer - now;
+                if wait > 0.0 {
+                    let Ok(wait) = Duration::try_from_secs_f64(wait) else {
+                        error!("Playback of {} is due too far ahead to wait for ({}s)", target.track_id, wait);
+                        return;
+                    };
+                    tokio::select! {
+                        _ = token.cancelled() => return,
+                        _ = tokio::time::sleep(wait) => {}
+                        Ok(()) = fade_out_rx.changed() => continue,
+                    }
+                } else if token.is_cancelled() {
+                    return;
+                }
+
+                let frame = match next_frame(&source, &target, params.loop_count) {
+                    Ok(Some((frame, restarted))) => {
+                        if restarted {
+                            // Continue the timeline seamlessly from the previous frame
+                            segment_start = due;
+                            origin = None;
+                        }
+                        frame
+                    }
+                    Ok(None) => {
+                        if wait_until(&clock, &token, due).await {
+                            info!("Playback of {} finished", target.track_id);
+                            notify_finished(&target, due, mixer.as_ref()).await;
+                        }
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Error reading frame for {}: {}", target.track_id, e);
+                        return;
+                    }
+                };
+
+                let origin = *origin.get_or_insert(frame.position);
+                let timestamp = segment_start + (frame.position - origin);
+                due = timestamp + frame.duration.as_secs_f64();
+
+                let mut data = frame.data;
+                let final_play = params.loop_count != LOOP_FOREVER
+                    && target.loop_iteration.load(Ordering::Relaxed) >= params.loop_count;
+                if is_pcm && final_play && mixer.is_none() {
+                    let crossfade = crossfade_rx.borrow().clone();
+                    if let (Some(crossfade), Some(length)) = (crossfade, track_duration) {
+                        let overlap = crossfade.duration.as_secs_f64().min(length);
+                        let start = length - overlap;
+                        if frame.position + frame.duration.as_secs_f64() > start {
+                            match Mixer::start(crossfade.source, start, overlap, channels, crossfade.gain) {
+                                Ok(started) => mixer = Some(started),
+                                Err(e) => warn!("Cannot crossfade out of {}: {}", target.track_id, e),
+                            }
+                        }
+                    }
+                }
+                if let Some(mixer) = &mut mixer {
+                    if let Err(e) = mixer.mix(&mut data, frame.position, sample_rate) {
+                        error!("Error mixing into {}: {}", target.track_id, e);
+                    }
+                }
+                if is_pcm {
+                    apply_gain(&mut data, timestamp, sample_rate, channels, |t| {
+                        let fade_in = params.fade_in.map_or(1.0, |fade| {
+                            ((t - params.start_at) / fade.as_secs_f64()).clamp(0.0, 1.0)
+                        });
+                        let fade_out = fade_out.map_or(1.0, |fade| {
+                            if fade.end > fade.start {
+                                ((fade.end - t) / (fade.end - fade.start)).clamp(0.0, 1.0)
+                            } else if t < fade.end {
+                                1.0
+                            } else {
+                                0.0
+                            }
+                        });
+                        fade_in * fade_out * params.gain
+                    });
+                }
+
+                let renditions = encode_renditions(&mut encoders, &data, &target.track_id, &target.loss_feedback);
+                target.stats.record_frame(data.len());
+                target.stats.record_presented(
+                    now,
+                    timestamp,
+                    frame.position,
+                    frame.duration.as_secs_f64(),
+                );
+
+                let media_frame = MediaFrame {
+                    data: data.into(),
+                    timestamp,
+                    duration: frame.duration,
+                    frame_type: frame.frame_type,
+                    sequence: target.sequence.fetch_add(1, Ordering::Relaxed),
+                    renditions,
+                };
+
+                // No subscribers is not an error; playback keeps its schedule
+                if target.frame_tx.send(media_frame).is_err() {
+                    debug!("No subscribers for {}", target.track_id);
+                }
+            }
+        }.instrument(tracing::Span::current()));
+
+        Self {
+            cancel,
+            fade_out_tx,
+            crossfade_tx,
+            task,
+        }
+    }
+
+    /// Set the track mixed into the end of this one, or clear it
+    ///
+    /// Has no effect once the overlap has started.
+    pub fn set_crossfade(&self, crossfade: Option<Crossfade>) {
+        let _ = self.crossfade_tx.send(crossfade);
+    }
+
+    /// Whether the playback task is still running
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    /// Stop playback and wait for the task to exit
+    pub async fn stop(self) {
+        self.cancel.cancel();
+        let _ = self.task.await;
+    }
+
+    /// Ramp down to silence between the given network times, then stop
+    ///
+    /// Frames already emitted are not faded, so `start` should lie beyond
+    /// the prebuffer window. Waits until the last frame before `end` has
+    /// been emitted.
+    pub async fn fade_out(self, start: f64, end: f64) {
+        let _ = self.fade_out_tx.send(Some(FadeOut { start, end }));
+        let _ = self.task.await;
+    }
+}
+
+/// Wait until network time `at`, returning false if cancelled first or if
+/// `at` is too far ahead to wait for
+///
+/// Playback that ends on its own stays running until its prebuffered frames
+/// have been presented.
+async fn wait_until(clock: &ClockManager, token: &CancellationToken, at: f64) -> bool {
+    let wait = at - clock.now().await;
+    if wait > 0.0 {
+        let Ok(wait) = Duration::try_from_secs_f64(wait) else {
+            error!("Network time {} is too far ahead to wait for", at);
+            return false;
+        };
+        tokio::select! {
+            _ = token.cancelled() => return false,
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    !token.is_cancelled()
+}
+
+/// Tell the stream owner that playback ended without being stopped
+async fn notify_finished(target: &PlaybackTarget, end_at: f64, mixer: Option<&Mixer>) {
+    if let Some(finished_tx) = &target.finished_tx {
+        let finished = PlaybackFinished {
+            track_id: target.track_id.clone(),
+            end_at,
+            handoff: mixer.map(Mixer::position),
+        };
+        let _ = finished_tx.send(finished).await;
+    }
+}
+
+/// Scale interleaved little-endian i16 samples by a time-dependent gain
+///
+/// `gain_at` receives the network time of each sample frame. Samples
+/// boosted past full scale are clipped.
+fn apply_gain(
+    data: &mut [u8],
+    timestamp: f64,
+    sample_rate: u32,
+    channels: u8,
+    gain_at: impl Fn(f64) -> f64,
+) {
+    let frame_bytes = 2 * channels.max(1) as usize;
+
+    for (index, samples) in data.chunks_exact_mut(frame_bytes).enumerate() {
+        let gain = gain_at(timestamp + index as f64 / sample_rate as f64);
+        if gain == 1.0 {
+            continue;
+        }
+
+        for sample in samples.chunks_exact_mut(2) {
+            let value = i16::from_le_bytes([sample[0], sample[1]]) as f64 * gain;
+            let value = value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+            sample.copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Encoder of one tier, with the loss it was last told to expect
+struct TierEncoding {
+    tier: QualityTier,
+    encoder: Box<dyn TierEncoder>,
+    expected_loss: Option<u8>,
+}
+
+/// Create an encoder for every tier, leaving out tiers that fail
+fn tier_encoders(factory: &EncoderFactory, sample_rate: u32, channels: u8) -> Vec<TierEncoding> {
+    QualityTier::ALL
+        .into_iter()
+        .filter_map(|tier| match factory(tier, sample_rate, channels) {
+            Ok(encoder) => Some(TierEncoding {
+                tier,
+                encoder,
+                expected_loss: None,
+            }),
+            Err(e) => {
+                warn!("No {:?} encoder: {}", tier, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Encode a frame for each tier; tiers that fail fall back to the source data
+///
+/// Encoders are first told of any change in the loss their tier's clients
+/// expect.
+fn encode_renditions(
+    encoders: &mut [TierEncoding],
+    pcm: &[u8],
+    track_id: &str,
+    loss_feedback: &LossFeedback,
+) -> Arc<[Rendition]> {
+    encoders
+        .iter_mut()
+        .filter_map(|encoding| {
+            let expected_loss = loss_feedback.expected_loss(encoding.tier);
+            if expected_loss != encoding.expected_loss {
+                debug!("{:?} encoder of {} now expects {:?}% loss", encoding.tier, track_id, expected_loss);
+                encoding.encoder.set_expected_loss(expected_loss);
+                encoding.expected_loss = expected_loss;
+            }
+            match encoding.encoder.encode(pcm) {
+                Ok(data) => Some(Rendition {
+                    tier: encoding.tier,
+                    data: data.into(),
+                }),
+                Err(e) => {
+                    warn!("Failed to encode {:?} frame for {}: {}", encoding.tier, track_id, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Pull the next frame, rewinding the source if it ended and loops remain
+///
+/// Returns the frame and whether the source was rewound to produce it.
+fn next_frame(
+    source: &SharedSource,
+    target: &PlaybackTarget,
+    loop_count: u32,
+) -> anyhow::Result<Option<(SourceFrame, bool)>> {
+    let mut source = source.lock();
+
+    if let Some(frame) = source.next_frame()? {
+        return Ok(Some((frame, false)));
+    }
+
+    let iteration = target.loop_iteration.load(Ordering::Relaxed);
+    if loop_count != LOOP_FOREVER && iteration >= loop_count {
+        return Ok(None);
+    }
+
+    source.seek(0.0)?;
+    target.loop_iteration.store(iteration + 1, Ordering::Relaxed);
+    debug!("Looping {} (iteration {})", target.track_id, iteration + 1);
+
+    Ok(source.next_frame()?.map(|frame| (frame, true)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{
+        loudness,
+        source::{SineSource, TestSource},
+    };
+
+    #[tokio::test]
+    async fn test_loop_timestamps_are_contiguous() {
+        let (frame_tx, mut frame_rx) = broadcast::channel(100);
+        let target = PlaybackTarget {
+            track_id: "loop".into(),
+            frame_tx,
+            sequence: Arc::new(AtomicU64::new(0)),
+            loop_iteration: Arc::new(AtomicU32::new(0)),
+            finished_tx: None,
+            stats: Arc::new(StreamCounters::default()),
+            encoder_factory: None,
+            loss_feedback: Arc::default(),
+        };
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(3))));
+        let clock = Arc::new(ClockManager::new());
+        let start_at = clock.now().await + 0.02;
+
+        let params = PlaybackParams {
+            start_at,
+            origin: None,
+            loop_count: 2,
+            fade_in: None,
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::ZERO,
+            gain: 1.0,
+        };
+        let playback = Playback::start(target.clone(), source, clock, params);
+
+        let mut frames = Vec::new();
+        while let Ok(Ok(frame)) =
+            tokio::time::timeout(Duration::from_millis(300), frame_rx.recv()).await
+        {
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 9);
+        for (i, frame) in frames.iter().enumerate() {
+            assert!((frame.timestamp - (start_at + i as f64 * 0.02)).abs() < 1e-6);
+            assert_eq!(frame.sequence, i as u64);
+        }
+        assert_eq!(target.loop_iteration.load(Ordering::Relaxed), 2);
+        assert!(!playback.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_prebuffer_emits_frames_before_start() {
+        let (target, mut frame_rx) = test_target();
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(50))));
+        let clock = Arc::new(ClockManager::new());
+        let start_at = clock.now().await + 0.2;
+        let params = PlaybackParams {
+            start_at,
+            origin: None,
+            loop_count: 0,
+            fade_in: None,
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::from_millis(500),
+            gain: 1.0,
+        };
+        let _playback = Playback::start(target, source, clock.clone(), params);
+
+        // The first 500ms of media arrives before playback is due to start
+        for i in 0..25 {
+            let frame = frame_rx.recv().await.unwrap();
+            assert!((frame.timestamp - (start_at + i as f64 * 0.02)).abs() < 1e-6);
+        }
+        assert!(clock.now().await < start_at);
+
+        // Later frames wait until they enter the prebuffer window
+        let frame = frame_rx.recv().await.unwrap();
+        assert!(clock.now().await >= frame.timestamp - 0.5 - 1e-3);
+    }
+
+    #[tokio::test]
+    async fn test_start_too_far_ahead_ends_playback() {
+        let (target, mut frame_rx) = test_target();
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(3))));
+        let clock = Arc::new(ClockManager::new());
+        let params = PlaybackParams {
+            start_at: clock.now().await + 1e30,
+            origin: None,
+            loop_count: 0,
+            fade_in: None,
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::ZERO,
+            gain: 1.0,
+        };
+        let playback = Playback::start(target, source, clock, params);
+
+        // The task gives up rather than panicking on the wait
+        playback.task.await.unwrap();
+        assert!(frame_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_crossfade_mixes_complementary_ramps() {
+        let (mut target, mut frame_rx) = test_target();
+        let (finished_tx, mut finished_rx) = mpsc::channel(1);
+        target.finished_tx = Some(finished_tx);
+        let amplitude = TestSource::AMPLITUDE as f64;
+        // 200ms of mono 1kHz; the next track is the same tone inverted in
+        // stereo, so the mix falls silent halfway through a 100ms overlap
+        let current: SharedSource =
+            Arc::new(Mutex::new(Box::new(SineSource::new(10, 1, 1000.0, amplitude))));
+        let next: SharedSource =
+            Arc::new(Mutex::new(Box::new(SineSource::new(10, 2, 1000.0, -amplitude))));
+        let clock = Arc::new(ClockManager::new());
+        let start_at = clock.now().await;
+        let params = PlaybackParams {
+            start_at,
+            origin: None,
+            loop_count: 0,
+            fade_in: None,
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::from_millis(500),
+            gain: 1.0,
+        };
+        let playback = Playback::start(target, current, clock, params);
+        playback.set_crossfade(Some(Crossfade {
+            source: next,
+            duration: Duration::from_millis(100),
+            gain: 1.0,
+        }));
+
+        let finished = tokio::time::timeout(Duration::from_millis(500), finished_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut frames = Vec::new();
+        while let Ok(frame) = frame_rx.try_recv() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 10);
+
+        // Timestamps stay continuous across the overlap
+        for (i, frame) in frames.iter().enumerate() {
+            assert!((frame.timestamp - (start_at + i as f64 * 0.02)).abs() < 1e-6);
+        }
+
+        // Untouched before the overlap, then (1 - x) of the current track
+        // plus x of the inverted next track
+        for (k, frame) in frames.iter().enumerate() {
+            for i in (0..960).step_by(37) {
+                let t = k as f64 * 0.02 + i as f64 / 48000.0;
+                let x = ((t - 0.1) / 0.1).clamp(0.0, 1.0);
+                let tone = SineSource::sample_at(1000.0, amplitude, t);
+                assert_near(sample(frame, i), tone * (1.0 - 2.0 * x));
+            }
+        }
+
+        // The next track continues after the part that was mixed in
+        assert!((finished.handoff.unwrap() - 0.1).abs() < 1e-6);
+    }
+
+    fn test_target() -> (PlaybackTarget, broadcast::Receiver<MediaFrame>) {
+        let (frame_tx, frame_rx) = broadcast::channel(100);
+        let target = PlaybackTarget {
+            track_id: "fade".into(),
+            frame_tx,
+            sequence: Arc::new(AtomicU64::new(0)),
+            loop_iteration: Arc::new(AtomicU32::new(0)),
+            finished_tx: None,
+            stats: Arc::new(StreamCounters::default()),
+            encoder_factory: None,
+            loss_feedback: Arc::default(),
+        };
+        (target, frame_rx)
+    }
+
+    fn sample(frame: &MediaFrame, index: usize) -> i16 {
+        i16::from_le_bytes([frame.data[index * 2], frame.data[index * 2 + 1]])
+    }
+
+    fn assert_near(actual: i16, expected: f64) {
+        assert!(
+            (actual as f64 - expected).abs() < 2.0,
+            "sample {} differs from expected {}",
+            actual,
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fade_in_ramps_from_silence() {
+        let (target, mut frame_rx) = test_target();
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(3))));
+        let clock = Arc::new(ClockManager::new());
+        let params = PlaybackParams {
+            start_at: clock.now().await,
+            origin: None,
+            loop_count: 0,
+            fade_in: Some(Duration::from_millis(40)),
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::ZERO,
+            gain: 1.0,
+        };
+        let _playback = Playback::start(target, source, clock, params);
+
+        let amplitude = TestSource::AMPLITUDE as f64;
+        let first = frame_rx.recv().await.unwrap();
+        let second = frame_rx.recv().await.unwrap();
+        let third = frame_rx.recv().await.unwrap();
+
+        assert_eq!(sample(&first, 0), 0);
+        assert_near(sample(&first, 480), amplitude * 0.25);
+        assert_near(sample(&second, 0), amplitude * 0.5);
+        assert_near(sample(&second, 480), amplitude * 0.75);
+        assert_near(sample(&third, 0), amplitude);
+    }
+
+    #[tokio::test]
+    async fn test_fade_out_ramps_to_silence_and_stops() {
+        let (target, mut frame_rx) = test_target();
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(10))));
+        let clock = Arc::new(ClockManager::new());
+        let start_at = clock.now().await + 0.05;
+        let params = PlaybackParams {
+            start_at,
+            origin: None,
+            loop_count: 0,
+            fade_in: None,
+            stop_at: None,
+            fade_out: None,
+            prebuffer: Duration::ZERO,
+            gain: 1.0,
+        };
+        let playback = Playback::start(target, source, clock, params);
+
+        playback.fade_out(start_at + 0.04, start_at + 0.08).await;
+
+        let mut frames = Vec::new();
+        while let Ok(frame) = frame_rx.try_recv() {
+            frames.push(frame);
+        }
+
+        let amplitude = TestSource::AMPLITUDE as f64;
+        assert_eq!(frames.len(), 4);
+        assert_near(sample(&frames[1], 959), amplitude);
+        assert_near(sample(&frames[2], 0), amplitude);
+        assert_near(sample(&frames[2], 480), amplitude * 0.75);
+        assert_near(sample(&frames[3], 0), amplitude * 0.5);
+        assert_near(sample(&frames[3], 480), amplitude * 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_stop_ends_within_one_frame() {
+        let (mut target, mut frame_rx) = test_target();
+        let (finished_tx, mut finished_rx) = mpsc::channel(1);
+        target.finished_tx = Some(finished_tx);
+        let source: SharedSource = Arc::new(Mutex::new(Box::new(TestSource::new(50))));
+        let clock = Arc::new(ClockManager::new());
+        let start_at = clock.now().await + 0.02;
+        let stop_at = start_at + 0.05;
+        let params = PlaybackParams {
+            start_at,
+            origin: None,
+            loop_count: 0,
+            fade_in: None,
+            stop_at: Some(stop_at),
+            fade_out: Some(Duration::from_millis(20)),
+            prebuffer: Duration::ZERO,
+            gain: 1.0,
+        };
+        let _playback = Playback::start(target, source, clock, params);
+
+        let finished = tokio::time::timeout(Duration::from_millis(500), finished_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut frames = Vec::new();
+        while let Ok(frame) = frame_rx.try_recv() {
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 3);
+        let last = frames.last().unwrap();
+        let last_end = last.timestamp + last.duration.as_secs_f64();
+        assert!(last.timestamp < stop_at && last_end >= stop_at);
+        assert!(last_end - stop_at < last.duration.as_secs_f64());
+        assert!((finished.end_at - last_end).abs() < 1e-6);
+
+        // The fade reaches silence at stop_at
+        let amplitude = TestSource::AMPLITUDE as f64;
+        assert_near(sample(&frames[1], 480), amplitude);
+        assert_near(sample(&frames[2], 0), amplitude * 0.5);
+        assert_near(sample(&frames[2], 480), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_normalization_gain_matches_loudness_across_tracks() {
+        let clock = Arc::new(ClockManager::new());
+        let mut levels = Vec::new();
+
+        for amplitude in [16000.0, 2000.0] {
+            let loudness = loudness::measure(&mut SineSource::new(25, 2, 440.0, amplitude))
+                .unwrap()
+                .unwrap();
+            let gain_db = loudness::normalization_gain_db(loudness, -16.0);
+
+            let (target, mut frame_rx) = test_target();
+            let source: SharedSource =
+                Arc::new(Mutex::new(Box::new(SineSource::new(25, 2, 440.0, amplitude))));
+            let params = PlaybackParams {
+                start_at: clock.now().await,
+                origin: None,
+                loop_count: 0,
+                fade_in: None,
+                stop_at: None,
+                fade_out: None,
+                prebuffer: Duration::from_secs(1),
+                gain: 10f64.powf(gain_db / 20.0),
+            };
+            let _playback = Playback::start(target, source, clock.clone(), params);
+
+            let mut squares = 0.0;
+            let mut count = 0;
+            for _ in 0..25 {
+                let frame = frame_rx.recv().await.unwrap();
+                for index in 0..frame.data.len() / 2 {
+                    squares += (sample(&frame, index) as f64).powi(2);
+                    count += 1;
+                }
+            }
+            levels.push(20.0 * (squares / count as f64).sqrt().log10());
+        }
+
+        // Tracks 18dB apart play back at the same level
+        assert!((levels[0] - levels[1]).abs() < 0.1, "levels {:?}", levels);
+    }
+}
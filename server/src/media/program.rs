@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{MediaAction, MediaControlMessage};
+
+use super::PlaybackState;
+
+/// Audio and video track of the same content played on one timeline
+///
+/// Commands addressed to the program apply to both tracks with the same
+/// start time and position. Video is presented `av_offset` seconds ahead
+/// of the audio it belongs with, compensating for display latency.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Program {
+    pub program_id: String,
+    pub audio_track: String,
+    pub video_track: String,
+
+    /// Seconds by which video is presented ahead of its audio
+    pub av_offset: f64,
+}
+
+/// Program state for the API
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramStatus {
+    pub program_id: String,
+    pub audio_track: String,
+    pub video_track: String,
+    pub av_offset_ms: f64,
+    pub state: PlaybackState,
+
+    /// Shared position in seconds, read from the audio track
+    pub position: Option<f64>,
+}
+
+impl Program {
+    pub fn contains(&self, track_id: &str) -> bool {
+        self.audio_track == track_id || self.video_track == track_id
+    }
+
+    /// Split a command addressed to the program into the audio and video
+    /// tracks' commands
+    ///
+    /// Network times of the video command are moved earlier by the A/V
+    /// offset; track positions are shared unchanged.
+    pub fn track_commands(&self, cmd: &MediaControlMessage) -> [MediaControlMessage; 2] {
+        let audio = MediaControlMessage {
+            track_id: self.audio_track.clone(),
+            ..cmd.clone()
+        };
+
+        let mut video = MediaControlMessage {
+            track_id: self.video_track.clone(),
+            ..cmd.clone()
+        };
+        if !matches!(cmd.action, MediaAction::Load | MediaAction::Unload) {
+            video.start_at -= self.av_offset;
+            video.params.stop_at = video.params.stop_at.map(|stop_at| stop_at - self.av_offset);
+        }
+
+        [audio, video]
+    }
+}
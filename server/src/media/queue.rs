@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::protocol::MediaParams;
+
+/// Track waiting in the play queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub track_id: String,
+
+    /// Parameters for the Play command that starts the item
+    #[serde(default)]
+    pub params: MediaParams,
+
+    /// Overlap with the previous item, overriding the queue's crossfade
+    #[serde(default)]
+    pub crossfade_ms: Option<u64>,
+}
+
+/// Queue contents reported by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    /// Item started most recently by the queue
+    pub current: Option<QueueItem>,
+
+    /// Items still to be played, next first
+    pub items: Vec<QueueItem>,
+
+    /// Silence inserted between consecutive items
+    pub gap_ms: u64,
+
+    /// Overlap between consecutive items without their own crossfade
+    pub crossfade_ms: u64,
+}
+
+/// Ordered list of tracks played one after another
+#[derive(Debug, Default)]
+pub struct PlayQueue {
+    current: Option<QueueItem>,
+    items: VecDeque<QueueItem>,
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append items to the end of the queue
+    pub fn extend(&mut self, items: impl IntoIterator<Item = QueueItem>) {
+        self.items.extend(items);
+    }
+
+    /// Remove a waiting item by position
+    pub fn remove(&mut self, index: usize) -> Option<QueueItem> {
+        self.items.remove(index)
+    }
+
+    /// Make the next item current, or clear the current item if none is left
+    pub fn advance(&mut self) -> Option<QueueItem> {
+        self.current = self.items.pop_front();
+        self.current.clone()
+    }
+
+    /// Item started most recently by the queue
+    pub fn current(&self) -> Option<&QueueItem> {
+        self.current.as_ref()
+    }
+
+    /// Item that plays after the current one
+    pub fn next(&self) -> Option<&QueueItem> {
+        self.items.front()
+    }
+
+    pub fn status(&self, gap_ms: u64, crossfade_ms: u64) -> QueueStatus {
+        QueueStatus {
+            current: self.current.clone(),
+            items: self.items.iter().cloned().collect(),
+            gap_ms,
+            crossfade_ms,
+        }
+    }
+}
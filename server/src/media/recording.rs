@@ -0,0 +1,599 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::broadcast,
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::buffer::MediaFrame;
+
+/// Errors from starting or stopping a recording or capture, or reading
+/// a capture back
+#[derive(Debug, thiserror::Error)]
+pub enum RecordingError {
+    #[error("Stream not found: {0}")]
+    NotFound(String),
+
+    #[error("Stream {0} is already being recorded")]
+    AlreadyRecording(String),
+
+    #[error("Stream {0} is not being recorded")]
+    NotRecording(String),
+
+    #[error("Capture not found: {0}")]
+    CaptureNotFound(String),
+
+    #[error("Corrupt capture {0}: {1}")]
+    Corrupt(String, String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Whether a recording is still writing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingState {
+    Recording,
+    Stopped,
+    Failed,
+}
+
+/// Recording progress reported in stream stats
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStatus {
+    pub path: PathBuf,
+
+    /// Sidecar listing the timestamp of every recorded frame
+    pub index_path: PathBuf,
+
+    /// Container the frames are written in, e.g. `ogg_opus`
+    pub format: &'static str,
+    pub state: RecordingState,
+    pub frames: u64,
+    pub bytes: u64,
+
+    /// Frames missed because the recorder fell behind the stream
+    pub frames_dropped: u64,
+
+    /// Why the recording stopped early
+    pub error: Option<String>,
+}
+
+/// Recorded frame as listed in the sidecar
+#[derive(Debug, Clone, Serialize)]
+struct IndexEntry {
+    sequence: u64,
+    timestamp: f64,
+    duration: f64,
+
+    /// Byte range of the frame's payload in the recording
+    offset: u64,
+    bytes: usize,
+}
+
+/// Sidecar written next to a recording
+#[derive(Serialize)]
+struct RecordingIndex<'a> {
+    codec: &'a str,
+    format: &'static str,
+    sample_rate: u32,
+    channels: u8,
+    frames: &'a [IndexEntry],
+}
+
+/// Recording of the frames a stream distributes, as they are broadcast
+///
+/// The recorder is just another subscriber of the stream's broadcast
+/// channel, so a failing disk stops the recording without affecting
+/// delivery to clients. Opus is written as Ogg/Opus, PCM as WAV and H.264
+/// as an Annex B elementary stream; other codecs are written as the raw
+/// concatenation of frames. The sidecar at `<path>.json` lists every frame
+/// with its presentation timestamp and byte range, for comparison against
+/// client playback logs.
+pub struct Recording {
+    cancel: CancellationToken,
+    status: Arc<Mutex<RecordingStatus>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Recording {
+    /// Create the recording file and start writing frames from `frame_rx`
+    pub async fn start(
+        path: &Path,
+        frame_rx: broadcast::Receiver<MediaFrame>,
+        codec: &str,
+        sample_rate: u32,
+        channels: u8,
+    ) -> Result<Self, RecordingError> {
+        let container = Container::for_codec(codec);
+        let path = path.to_path_buf();
+        let mut index_path = path.clone().into_os_string();
+        index_path.push(".json");
+
+        let mut file = File::create(&path).await?;
+        let header = container.header(sample_rate, channels);
+        file.write_all(&header).await?;
+        file.flush().await?;
+        info!("Recording {} stream to {}", codec, path.display());
+
+        let status = Arc::new(Mutex::new(RecordingStatus {
+            path,
+            index_path: index_path.into(),
+            format: container.format(),
+            state: RecordingState::Recording,
+            frames: 0,
+            bytes: header.len() as u64,
+            frames_dropped: 0,
+            error: None,
+        }));
+        let cancel = CancellationToken::new();
+        let recorder = Recorder {
+            file,
+            container,
+            codec: codec.to_string(),
+            sample_rate,
+            channels,
+            index: Vec::new(),
+            status: status.clone(),
+        };
+        let task = tokio::spawn(recorder.run(frame_rx, cancel.clone()));
+
+        Ok(Self {
+            cancel,
+            status,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        self.status.lock().clone()
+    }
+
+    /// Whether frames are still being written
+    pub fn is_active(&self) -> bool {
+        self.status.lock().state == RecordingState::Recording
+    }
+
+    /// Stop recording, finishing the container and writing the sidecar
+    pub async fn stop(&self) -> RecordingStatus {
+        self.cancel.cancel();
+        let task = self.task.lock().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+        self.status()
+    }
+}
+
+/// Task writing broadcast frames to a recording
+struct Recorder {
+    file: File,
+    container: Container,
+    codec: String,
+    sample_rate: u32,
+    channels: u8,
+    index: Vec<IndexEntry>,
+    status: Arc<Mutex<RecordingStatus>>,
+}
+
+impl Recorder {
+    async fn run(mut self, mut frame_rx: broadcast::Receiver<MediaFrame>, cancel: CancellationToken) {
+        let result = loop {
+            // Frames broadcast before the stop are still written
+            let frame = tokio::select! {
+                _ = cancel.cancelled() => match frame_rx.try_recv() {
+                    Ok(frame) => Ok(frame),
+                    Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                        Err(broadcast::error::RecvError::Lagged(skipped))
+                    }
+                    Err(_) => break Ok(()),
+                },
+                frame = frame_rx.recv() => frame,
+            };
+            match frame {
+                Ok(frame) => {
+                    if let Err(e) = self.write_frame(&frame).await {
+                        break Err(e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Recorder fell behind, skipped {} frames", skipped);
+                    self.status.lock().frames_dropped += skipped;
+                }
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            }
+        };
+        let result = match result {
+            Ok(()) => self.finish().await,
+            Err(e) => Err(e),
+        };
+        self.write_index().await;
+
+        let mut status = self.status.lock();
+        match result {
+            Ok(()) => {
+                status.state = RecordingState::Stopped;
+                info!("Recording {} finished: {} frames", status.path.display(), status.frames);
+            }
+            Err(e) => {
+                warn!("Recording {} failed: {}", status.path.display(), e);
+                status.state = RecordingState::Failed;
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Append a frame, flushing so a full disk is noticed straight away
+    async fn write_frame(&mut self, frame: &MediaFrame) -> std::io::Result<()> {
+        let offset = self.status.lock().bytes;
+        let (data, payload_offset) = self.container.frame(frame);
+        self.file.write_all(&data).await?;
+        self.file.flush().await?;
+
+        self.index.push(IndexEntry {
+            sequence: frame.sequence,
+            timestamp: frame.timestamp,
+            duration: frame.duration.as_secs_f64(),
+            offset: offset + payload_offset as u64,
+            bytes: frame.data.len(),
+        });
+        let mut status = self.status.lock();
+        status.frames += 1;
+        status.bytes += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write the container trailer and fill in header lengths
+    async fn finish(&mut self) -> std::io::Result<()> {
+        let trailer = self.container.trailer();
+        self.file.write_all(&trailer).await?;
+
+        let bytes = {
+            let mut status = self.status.lock();
+            status.bytes += trailer.len() as u64;
+            status.bytes
+        };
+        if let Container::Wav = self.container {
+            let data_bytes = bytes.saturating_sub(WAV_HEADER_LEN as u64) as u32;
+            self.file.seek(SeekFrom::Start(4)).await?;
+            self.file.write_all(&(data_bytes + 36).to_le_bytes()).await?;
+            self.file.seek(SeekFrom::Start(40)).await?;
+            self.file.write_all(&data_bytes.to_le_bytes()).await?;
+        }
+        self.file.flush().await?;
+        self.file.sync_all().await
+    }
+
+    /// Write the sidecar; it describes whatever was recorded, even after a
+    /// failure
+    async fn write_index(&self) {
+        let index = RecordingIndex {
+            codec: &self.codec,
+            format: self.container.format(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            frames: &self.index,
+        };
+        let index_path = self.status.lock().index_path.clone();
+        let json = serde_json::to_vec_pretty(&index).expect("recording index always serializes");
+        if let Err(e) = tokio::fs::write(&index_path, json).await {
+            warn!("Failed to write recording index {}: {}", index_path.display(), e);
+        }
+    }
+}
+
+/// File extension of the container a codec is recorded in
+pub fn recording_extension(codec: &str) -> &'static str {
+    match codec {
+        "opus" => "opus",
+        "pcm16" => "wav",
+        "h264" => "h264",
+        _ => "bin",
+    }
+}
+
+/// Length of the WAV header written before PCM data
+const WAV_HEADER_LEN: usize = 44;
+
+/// Annex B start code prefixed to H.264 frames that lack one
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Container format of a recording
+enum Container {
+    OggOpus(OggWriter),
+    Wav,
+    AnnexB,
+    Raw,
+}
+
+impl Container {
+    fn for_codec(codec: &str) -> Self {
+        match codec {
+            "opus" => Self::OggOpus(OggWriter::new(rand::random())),
+            "pcm16" => Self::Wav,
+            "h264" => Self::AnnexB,
+            _ => Self::Raw,
+        }
+    }
+
+    fn format(&self) -> &'static str {
+        match self {
+            Self::OggOpus(_) => "ogg_opus",
+            Self::Wav => "wav",
+            Self::AnnexB => "h264_annexb",
+            Self::Raw => "raw",
+        }
+    }
+
+    fn header(&self, sample_rate: u32, channels: u8) -> Vec<u8> {
+        match self {
+            Self::OggOpus(ogg) => ogg.header(sample_rate, channels),
+            Self::Wav => wav_header(sample_rate, channels),
+            Self::AnnexB | Self::Raw => Vec::new(),
+        }
+    }
+
+    /// Bytes to append for a frame, and the offset of its payload in them
+    fn frame(&mut self, frame: &MediaFrame) -> (Vec<u8>, usize) {
+        match self {
+            Self::OggOpus(ogg) => ogg.packet(&frame.data, frame.duration),
+            Self::AnnexB if !has_start_code(&frame.data) => {
+                ([&START_CODE[..], &frame.data].concat(), START_CODE.len())
+            }
+            Self::Wav | Self::AnnexB | Self::Raw => (frame.data.to_vec(), 0),
+        }
+    }
+
+    fn trailer(&mut self) -> Vec<u8> {
+        match self {
+            Self::OggOpus(ogg) => ogg.end(),
+            Self::Wav | Self::AnnexB | Self::Raw => Vec::new(),
+        }
+    }
+}
+
+fn has_start_code(data: &[u8]) -> bool {
+    data.starts_with(&START_CODE) || data.starts_with(&START_CODE[1..])
+}
+
+/// WAV header for 16-bit PCM, with lengths filled in when recording ends
+fn wav_header(sample_rate: u32, channels: u8) -> Vec<u8> {
+    let channels = channels.max(1) as u16;
+    let mut header = Vec::with_capacity(WAV_HEADER_LEN);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&36u32.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    header.extend_from_slice(&(channels * 2).to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header
+}
+
+/// Ogg page header flag: first page of the stream
+const OGG_BOS: u8 = 0x02;
+
+/// Ogg page header flag: last page of the stream
+const OGG_EOS: u8 = 0x04;
+
+/// Length of an Ogg page header before its segment table
+const OGG_HEADER_LEN: usize = 27;
+
+/// Writer of a single Ogg/Opus logical stream, one packet per page
+struct OggWriter {
+    serial: u32,
+
+    /// Sequence number of the next page; 0 and 1 are the header pages
+    next_page: u32,
+
+    /// Samples at 48kHz up to the end of the last packet
+    granule: u64,
+}
+
+impl OggWriter {
+    fn new(serial: u32) -> Self {
+        Self {
+            serial,
+            next_page: 2,
+            granule: 0,
+        }
+    }
+
+    /// `OpusHead` and `OpusTags` pages
+    fn header(&self, sample_rate: u32, channels: u8) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels.max(1));
+        head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mapping family
+
+        let vendor = concat!("solusync-x ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut pages = page(self.serial, 0, OGG_BOS, 0, &head);
+        pages.extend(page(self.serial, 1, 0, 0, &tags));
+        pages
+    }
+
+    /// Page carrying one packet, and the offset of the packet in it
+    fn packet(&mut self, data: &[u8], duration: std::time::Duration) -> (Vec<u8>, usize) {
+        self.granule += (duration.as_secs_f64() * 48000.0).round() as u64;
+        let page = page(self.serial, self.next_sequence(), 0, self.granule, data);
+        let offset = page.len() - data.len();
+        (page, offset)
+    }
+
+    /// Empty page marking the end of the stream
+    fn end(&mut self) -> Vec<u8> {
+        page(self.serial, self.next_sequence(), OGG_EOS, self.granule, &[])
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.next_page += 1;
+        self.next_page - 1
+    }
+}
+
+/// Build an Ogg page holding `packet` in full
+fn page(serial: u32, sequence: u32, flags: u8, granule: u64, packet: &[u8]) -> Vec<u8> {
+    let mut lacing = vec![255u8; packet.len() / 255];
+    if !packet.is_empty() {
+        lacing.push((packet.len() % 255) as u8);
+    }
+
+    let mut page = Vec::with_capacity(OGG_HEADER_LEN + lacing.len() + packet.len());
+    page.extend_from_slice(b"OggS");
+    page.push(0);
+    page.push(flags);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&0u32.to_le_bytes());
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    page.extend_from_slice(packet);
+
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// CRC-32 of an Ogg page (polynomial 0x04c11db7, unreflected, no final xor)
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::buffer::FrameType;
+    use std::time::Duration;
+
+    fn opus_frame(sequence: u64) -> MediaFrame {
+        MediaFrame {
+            data: vec![0xfc; 60].into(),
+            timestamp: 100.0 + sequence as f64 * 0.02,
+            duration: Duration::from_millis(20),
+            frame_type: FrameType::Audio,
+            sequence,
+            renditions: Arc::default(),
+        }
+    }
+
+    /// Split a file into Ogg pages, checking each page's CRC
+    fn ogg_pages(data: &[u8]) -> Vec<(u8, u64, Vec<u8>)> {
+        let mut pages = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"OggS");
+            let segments = rest[26] as usize;
+            let body: usize = rest[27..27 + segments].iter().map(|&n| n as usize).sum();
+            let len = OGG_HEADER_LEN + segments + body;
+
+            let mut unsummed = rest[..len].to_vec();
+            unsummed[22..26].fill(0);
+            assert_eq!(ogg_crc(&unsummed).to_le_bytes(), rest[22..26]);
+
+            let granule = u64::from_le_bytes(rest[6..14].try_into().unwrap());
+            pages.push((rest[5], granule, rest[27 + segments..len].to_vec()));
+            rest = &rest[len..];
+        }
+        pages
+    }
+
+    #[tokio::test]
+    async fn test_records_opus_frames_to_ogg_with_index() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.opus", uuid::Uuid::new_v4()));
+        let (frame_tx, frame_rx) = broadcast::channel(16);
+        let recording = Recording::start(&path, frame_rx, "opus", 48000, 2).await.unwrap();
+
+        for sequence in 0..3 {
+            frame_tx.send(opus_frame(sequence)).unwrap();
+        }
+        drop(frame_tx);
+        let status = recording.stop().await;
+        assert_eq!(status.state, RecordingState::Stopped);
+        assert_eq!(status.frames, 3);
+
+        let data = std::fs::read(&status.path).unwrap();
+        assert_eq!(status.bytes, data.len() as u64);
+        let pages = ogg_pages(&data);
+        assert_eq!(pages.len(), 6);
+        assert_eq!(pages[0].0, OGG_BOS);
+        assert!(pages[0].2.starts_with(b"OpusHead"));
+        assert!(pages[1].2.starts_with(b"OpusTags"));
+        assert_eq!(pages[4].1, 3 * 960);
+        assert_eq!(pages[5].0, OGG_EOS);
+
+        // The sidecar locates each frame's payload in the recording
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&status.index_path).unwrap()).unwrap();
+        let frames = index["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!((frames[1]["timestamp"].as_f64().unwrap() - 100.02).abs() < 1e-9);
+        let offset = frames[2]["offset"].as_u64().unwrap() as usize;
+        assert_eq!(&data[offset..offset + 60], &[0xfc; 60][..]);
+
+        let _ = std::fs::remove_file(&status.path);
+        let _ = std::fs::remove_file(&status.index_path);
+    }
+
+    #[tokio::test]
+    async fn test_write_failure_stops_recording_cleanly() {
+        // Every write to /dev/full fails as if the disk were full
+        let path = Path::new("/dev/full");
+        if !path.exists() {
+            return;
+        }
+        let (frame_tx, frame_rx) = broadcast::channel(16);
+        let mut other_rx = frame_tx.subscribe();
+        let Ok(recording) = Recording::start(path, frame_rx, "h264", 0, 0).await else {
+            return;
+        };
+
+        frame_tx.send(opus_frame(0)).unwrap();
+        while recording.is_active() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let status = recording.status();
+        assert_eq!(status.state, RecordingState::Failed);
+        assert!(status.error.is_some());
+        assert_eq!(status.frames, 0);
+
+        // Other subscribers keep receiving frames
+        frame_tx.send(opus_frame(1)).unwrap();
+        assert_eq!(other_rx.recv().await.unwrap().sequence, 0);
+        assert_eq!(other_rx.recv().await.unwrap().sequence, 1);
+        let _ = std::fs::remove_file(&status.index_path);
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::protocol::NetworkQuality;
+
+/// Consecutive frames a lower tier must be wanted before switching down
+const DOWNGRADE_FRAMES: u32 = 5;
+
+/// Consecutive frames a higher tier must be wanted before switching up
+///
+/// Upgrades wait much longer than downgrades so a briefly recovering
+/// network does not flap between tiers.
+const UPGRADE_FRAMES: u32 = 100;
+
+/// Encoded quality level delivered to a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityTier {
+    Low,
+    Medium,
+    High,
+}
+
+impl QualityTier {
+    pub const ALL: [Self; 3] = [Self::Low, Self::Medium, Self::High];
+
+    /// Opus bitrate of the tier
+    #[cfg(any(test, feature = "opus"))]
+    pub fn bitrate_kbps(self) -> u32 {
+        match self {
+            Self::Low => 32,
+            Self::Medium => 64,
+            Self::High => 128,
+        }
+    }
+
+    /// Tier suited to a client's network quality
+    pub fn for_quality(quality: NetworkQuality) -> Self {
+        match quality {
+            NetworkQuality::Excellent | NetworkQuality::Good => Self::High,
+            NetworkQuality::Fair => Self::Medium,
+            NetworkQuality::Poor | NetworkQuality::Critical => Self::Low,
+        }
+    }
+
+    /// Next tier down, or Low itself
+    pub fn lower(self) -> Self {
+        match self {
+            Self::High => Self::Medium,
+            Self::Medium | Self::Low => Self::Low,
+        }
+    }
+
+    pub(super) fn from_index(index: u8) -> Self {
+        Self::ALL[(index as usize).min(Self::ALL.len() - 1)]
+    }
+}
+
+/// Frame data re-encoded for one tier
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    pub tier: QualityTier,
+    pub data: Bytes,
+}
+
+/// Re-encodes decoded `pcm16` frames for one tier
+pub trait TierEncoder: Send {
+    fn encode(&mut self, pcm: &[u8]) -> Result<Vec<u8>>;
+
+    /// Protect the following frames against `loss_percent` packet loss,
+    /// or stop with none
+    ///
+    /// An Opus encoder would enable in-band FEC and set its expected
+    /// packet loss to this; encoders without FEC ignore it.
+    fn set_expected_loss(&mut self, _loss_percent: Option<u8>) {}
+}
+
+/// Creates an encoder for a tier, given the source sample rate and channels
+pub type EncoderFactory =
+    Arc<dyn Fn(QualityTier, u32, u8) -> Result<Box<dyn TierEncoder>> + Send + Sync>;
+
+/// Chooses the tier a subscription forwards, switching with hysteresis
+///
+/// A switch only takes effect once a different tier has been wanted for
+/// several consecutive frames, and always between frames.
+#[derive(Debug)]
+pub struct TierSelector {
+    current: QualityTier,
+    candidate: QualityTier,
+    streak: u32,
+}
+
+impl TierSelector {
+    pub fn new(initial: QualityTier) -> Self {
+        Self {
+            current: initial,
+            candidate: initial,
+            streak: 0,
+        }
+    }
+
+    /// Tier to forward the next frame at, given the tier currently wanted
+    pub fn select(&mut self, wanted: QualityTier) -> QualityTier {
+        if wanted == self.current {
+            self.streak = 0;
+            return self.current;
+        }
+
+        if wanted == self.candidate {
+            self.streak += 1;
+        } else {
+            self.candidate = wanted;
+            self.streak = 1;
+        }
+
+        let required = if wanted < self.current {
+            DOWNGRADE_FRAMES
+        } else {
+            UPGRADE_FRAMES
+        };
+        if self.streak >= required {
+            self.current = wanted;
+            self.streak = 0;
+        }
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector_downgrades_quickly_and_upgrades_slowly() {
+        let mut selector = TierSelector::new(QualityTier::High);
+
+        for _ in 1..DOWNGRADE_FRAMES {
+            assert_eq!(selector.select(QualityTier::Low), QualityTier::High);
+        }
+        assert_eq!(selector.select(QualityTier::Low), QualityTier::Low);
+
+        // A brief recovery does not switch back up
+        for _ in 0..10 {
+            assert_eq!(selector.select(QualityTier::High), QualityTier::Low);
+        }
+        assert_eq!(selector.select(QualityTier::Low), QualityTier::Low);
+
+        for _ in 1..UPGRADE_FRAMES {
+            assert_eq!(selector.select(QualityTier::High), QualityTier::Low);
+        }
+        assert_eq!(selector.select(QualityTier::High), QualityTier::High);
+    }
+}
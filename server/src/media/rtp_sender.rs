@@ -0,0 +1,416 @@
+use anyhow::Result;
+use bytes::Bytes;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use webrtc::{
+    rtp::{extension::HeaderExtension, header::Header, packet::Packet, packetizer::Payloader},
+    track::track_local::track_local_static_rtp::TrackLocalStaticRTP,
+    util::{Marshal, MarshalSize},
+};
+
+use super::{buffer::MediaFrame, WebRtcCodec};
+
+/// Largest RTP payload, leaving room for headers and SRTP in a 1200 byte
+/// datagram
+const MAX_PAYLOAD_BYTES: usize = 1150;
+
+/// Media time written before the track is bound that is kept to be sent
+/// once it is, in seconds of presentation time
+const PENDING_MEDIA_SECS: f64 = 0.25;
+
+/// URI of the playout-delay RTP header extension
+pub const PLAYOUT_DELAY_URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay";
+
+/// Granularity of the playout-delay extension's delays, in milliseconds
+const PLAYOUT_DELAY_UNIT_MS: u32 = 10;
+
+/// Longest delay the extension's 12-bit fields carry, in milliseconds
+const MAX_PLAYOUT_DELAY_MS: u32 = 0xfff * PLAYOUT_DELAY_UNIT_MS;
+
+/// Value of the playout-delay header extension: the least and most time
+/// the receiver should hold a frame before rendering it
+///
+/// The receiver's own jitter buffer would otherwise pick a delay of its
+/// own, rendering frames sooner or later than the presentation time the
+/// server released them for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayoutDelay {
+    pub min_ms: u32,
+    pub max_ms: u32,
+}
+
+impl PlayoutDelay {
+    /// Hold frames for exactly `delay`, to the extension's 10ms
+    /// granularity
+    pub fn fixed(delay: Duration) -> Self {
+        let units = (delay.as_millis() as u32 + PLAYOUT_DELAY_UNIT_MS / 2) / PLAYOUT_DELAY_UNIT_MS;
+        let delay_ms = (units * PLAYOUT_DELAY_UNIT_MS).min(MAX_PLAYOUT_DELAY_MS);
+        Self { min_ms: delay_ms, max_ms: delay_ms }
+    }
+
+    /// Parse the extension's payload
+    #[cfg(test)]
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let [a, b, c] = *data else {
+            return None;
+        };
+        let min = (a as u32) << 4 | (b as u32) >> 4;
+        let max = (b as u32 & 0x0f) << 8 | c as u32;
+        Some(Self {
+            min_ms: min * PLAYOUT_DELAY_UNIT_MS,
+            max_ms: max * PLAYOUT_DELAY_UNIT_MS,
+        })
+    }
+}
+
+impl MarshalSize for PlayoutDelay {
+    fn marshal_size(&self) -> usize {
+        3
+    }
+}
+
+impl Marshal for PlayoutDelay {
+    fn marshal_to(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        if buf.len() < 3 {
+            return Err(webrtc::util::Error::ErrBufferShort);
+        }
+        let min = self.min_ms.min(MAX_PLAYOUT_DELAY_MS) / PLAYOUT_DELAY_UNIT_MS;
+        let max = self.max_ms.min(MAX_PLAYOUT_DELAY_MS) / PLAYOUT_DELAY_UNIT_MS;
+        buf[0] = (min >> 4) as u8;
+        buf[1] = ((min & 0x0f) << 4 | max >> 8) as u8;
+        buf[2] = max as u8;
+        Ok(3)
+    }
+}
+
+/// Correspondence between the RTP timestamps of a track and network time
+///
+/// RTP timestamp `rtp_anchor` is network time `network_anchor`, and each
+/// tick of the codec's clock rate after it one sample period later, the
+/// 32-bit timestamps wrapping around (every 24.9 hours at 48kHz, 13.3 at
+/// 90kHz). Clients map the timestamps of the media they receive onto the
+/// shared timeline through it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtpClockMapping {
+    pub rtp_anchor: u32,
+    pub network_anchor: f64,
+    pub clock_rate: u32,
+}
+
+impl RtpClockMapping {
+    /// RTP timestamp of network time `network_time`, to the nearest tick
+    pub fn rtp_timestamp(&self, network_time: f64) -> u32 {
+        self.rtp_anchor.wrapping_add(self.ticks(network_time) as u32)
+    }
+
+    /// Network time of RTP timestamp `rtp`, in the wrap of the timestamps
+    /// nearest network time `near`
+    ///
+    /// Any time within half a wrap of the timestamp's serves as `near`,
+    /// such as the time it is received at.
+    #[cfg(test)]
+    pub fn network_time(&self, rtp: u32, near: f64) -> f64 {
+        let near_ticks = self.ticks(near);
+        let from_near = rtp.wrapping_sub(self.rtp_anchor.wrapping_add(near_ticks as u32)) as i32;
+        self.network_anchor + (near_ticks + from_near as i64) as f64 / self.clock_rate as f64
+    }
+
+    /// Ticks from the anchor to network time `network_time`
+    fn ticks(&self, network_time: f64) -> i64 {
+        ((network_time - self.network_anchor) * self.clock_rate as f64).round() as i64
+    }
+}
+
+/// Outcome of writing a frame to the track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOutcome {
+    /// Packets sent, including ones held from before the track was bound
+    pub sent: usize,
+
+    /// Bytes of the packets sent, headers included
+    pub bytes: usize,
+
+    /// Held frames given up on because the track stayed unbound
+    pub expired: usize,
+}
+
+/// Server-generated track of a client's peer connection, sending frames
+/// already encoded in its codec
+///
+/// Each frame becomes as many RTP packets as the codec's packetization
+/// needs; audio frames fit one. Packet timestamps map the frames'
+/// presentation times through an [`RtpClockMapping`] anchored at the first
+/// frame written, so a frame missing from the client's stream leaves a gap
+/// the receiver conceals instead of shifting everything after it.
+///
+/// Until negotiation binds the track to the connection, writes have
+/// nowhere to go; frames are held and sent in order once it is bound, or
+/// given up on when they fall [`PENDING_MEDIA_SECS`] behind the newest.
+/// The same happens while the sender is paused, as while the connection
+/// restarts ICE.
+///
+/// Once a playout delay is set, every packet carries it in the
+/// playout-delay header extension, if the client negotiated it.
+pub struct MediaSender {
+    codec: WebRtcCodec,
+    track: Arc<TrackLocalStaticRTP>,
+    payloader: Box<dyn Payloader + Send + Sync>,
+    sequence_number: u16,
+    timestamp_base: u32,
+
+    /// Maps presentation times to RTP timestamps from `timestamp_base` on,
+    /// once a frame has been written
+    clock: Option<RtpClockMapping>,
+    /// Packets of each held frame
+    pending: VecDeque<(f64, Vec<Packet>)>,
+    paused: bool,
+    playout_delay: Option<PlayoutDelay>,
+}
+
+impl MediaSender {
+    pub fn new(codec: WebRtcCodec, stream_id: String) -> Self {
+        let capability = codec.capability();
+        let payloader = capability
+            .payloader_for_codec()
+            .expect("every WebRTC codec has a payloader");
+        let kind = if codec.is_audio() { "audio" } else { "video" };
+        let track = TrackLocalStaticRTP::new(capability, kind.to_string(), stream_id);
+        Self {
+            codec,
+            track: Arc::new(track),
+            payloader,
+            sequence_number: rand::random(),
+            timestamp_base: rand::random(),
+            clock: None,
+            pending: VecDeque::new(),
+            paused: false,
+            playout_delay: None,
+        }
+    }
+
+    pub fn codec(&self) -> WebRtcCodec {
+        self.codec
+    }
+
+    /// Track to add to the peer connection before the offer is created
+    pub fn track(&self) -> Arc<TrackLocalStaticRTP> {
+        self.track.clone()
+    }
+
+    /// Frames held until the track is bound
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Hold packets instead of sending them, or send the held ones with
+    /// the next write
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Stamp packets sent from now on with `delay`
+    pub fn set_playout_delay(&mut self, delay: PlayoutDelay) {
+        self.playout_delay = Some(delay);
+    }
+
+    /// Mapping of the track's RTP timestamps to network time; none until
+    /// the first frame is written
+    pub fn clock_mapping(&self) -> Option<RtpClockMapping> {
+        self.clock
+    }
+
+    /// RTP timestamp of a frame presented at `timestamp`
+    pub fn rtp_timestamp(&mut self, timestamp: f64) -> u32 {
+        let (rtp_anchor, clock_rate) = (self.timestamp_base, self.codec.clock_rate());
+        self.clock
+            .get_or_insert(RtpClockMapping {
+                rtp_anchor,
+                network_anchor: timestamp,
+                clock_rate,
+            })
+            .rtp_timestamp(timestamp)
+    }
+
+    /// Send a frame's payload, after any frames still held
+    ///
+    /// Fails when the connection refuses a packet; the rest of that frame
+    /// is dropped and later ones are still sent.
+    pub async fn write(&mut self, frame: &MediaFrame, payload: Bytes) -> Result<WriteOutcome> {
+        // Audio marks the first packet of a talkspurt, video the last
+        // packet of a frame
+        let first = self.clock.is_none();
+        let timestamp = self.rtp_timestamp(frame.timestamp);
+        let payloads = self.payloader.payload(MAX_PAYLOAD_BYTES, &payload)?;
+        let count = payloads.len();
+        let packets = payloads
+            .into_iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let marker = if self.codec.is_audio() { first } else { index + 1 == count };
+                let packet = Packet {
+                    header: Header {
+                        version: 2,
+                        marker,
+                        sequence_number: self.sequence_number,
+                        timestamp,
+                        ..Default::default()
+                    },
+                    payload,
+                };
+                self.sequence_number = self.sequence_number.wrapping_add(1);
+                packet
+            })
+            .collect();
+        self.pending.push_back((frame.timestamp, packets));
+
+        let mut outcome = self.flush().await?;
+        let oldest_kept = frame.timestamp - PENDING_MEDIA_SECS;
+        while self.pending.front().is_some_and(|(timestamp, _)| *timestamp < oldest_kept) {
+            self.pending.pop_front();
+            outcome.expired += 1;
+        }
+        Ok(outcome)
+    }
+
+    /// Send the frames still held, unless paused
+    ///
+    /// Fails like `write` when the connection refuses a packet.
+    pub async fn flush(&mut self) -> Result<WriteOutcome> {
+        let mut outcome = WriteOutcome { sent: 0, bytes: 0, expired: 0 };
+        let extensions: Vec<_> = self
+            .playout_delay
+            .map(|delay| HeaderExtension::Custom {
+                uri: PLAYOUT_DELAY_URI.into(),
+                extension: Box::new(delay),
+            })
+            .into_iter()
+            .collect();
+        while let Some((_, packets)) = self.pending.front_mut().filter(|_| !self.paused) {
+            while let Some(packet) = packets.first() {
+                // Nothing is written while no connection has bound the track
+                let written = self.track.write_rtp_with_extensions(packet, &extensions).await;
+                match written {
+                    Ok(0) => return Ok(outcome),
+                    Ok(bytes) => {
+                        outcome.sent += 1;
+                        outcome.bytes += bytes;
+                        packets.remove(0);
+                    }
+                    Err(e) => {
+                        self.pending.pop_front();
+                        return Err(e.into());
+                    }
+                }
+            }
+            self.pending.pop_front();
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::buffer::FrameType;
+    use std::time::Duration;
+
+    fn frame(timestamp: f64) -> MediaFrame {
+        MediaFrame {
+            data: vec![0xf8, 0xff, 0xfe].into(),
+            timestamp,
+            duration: Duration::from_millis(20),
+            frame_type: FrameType::Audio,
+            sequence: 0,
+            renditions: Arc::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unbound_track_holds_packets_briefly() {
+        let mut sender = MediaSender::new(WebRtcCodec::Opus, "client".into());
+        for i in 0..10 {
+            let frame = frame(100.0 + i as f64 * 0.02);
+            let outcome = sender.write(&frame, frame.data.clone()).await.unwrap();
+            assert_eq!(outcome.sent, 0);
+        }
+        assert_eq!(sender.pending(), 10);
+
+        // Packets more than a quarter second behind the newest are given up
+        let late = frame(100.26);
+        let outcome = sender.write(&late, late.data.clone()).await.unwrap();
+        assert_eq!(outcome, WriteOutcome { sent: 0, bytes: 0, expired: 1 });
+        assert_eq!(sender.pending(), 10);
+    }
+
+    #[test]
+    fn test_rtp_timestamps_follow_presentation_time() {
+        let mut sender = MediaSender::new(WebRtcCodec::Opus, "client".into());
+        let base = sender.rtp_timestamp(100.0);
+        assert_eq!(sender.rtp_timestamp(100.02).wrapping_sub(base), 960);
+        // A missing frame leaves a gap
+        assert_eq!(sender.rtp_timestamp(100.06).wrapping_sub(base), 2880);
+
+        let mut sender = MediaSender::new(WebRtcCodec::Pcmu, "client".into());
+        let base = sender.rtp_timestamp(100.0);
+        assert_eq!(sender.rtp_timestamp(100.02).wrapping_sub(base), 160);
+        let clock = sender.clock_mapping().unwrap();
+        assert_eq!((clock.rtp_anchor, clock.network_anchor, clock.clock_rate), (base, 100.0, 8_000));
+    }
+
+    #[test]
+    fn test_rtp_timestamps_map_back_to_network_time_across_wraps() {
+        for clock_rate in [48_000, 90_000] {
+            let clock = RtpClockMapping {
+                rtp_anchor: u32::MAX - 1000,
+                network_anchor: 1_700_000_000.0,
+                clock_rate,
+            };
+            let sample_period = 1.0 / clock_rate as f64;
+            let wrap = (1u64 << 32) as f64 / clock_rate as f64;
+
+            // Across the wrap right after the anchor, and two wraps later
+            // with the time received a few seconds off
+            for (elapsed, received_after) in [(0.0, 0.0), (0.5, 0.1), (2.0 * wrap + 0.123_456, -3.0), (-0.25, 5.0)] {
+                let presented = clock.network_anchor + elapsed;
+                let rtp = clock.rtp_timestamp(presented);
+                let recovered = clock.network_time(rtp, presented + received_after);
+                assert!(
+                    (recovered - presented).abs() <= sample_period,
+                    "{}Hz: {} recovered as {}",
+                    clock_rate,
+                    presented,
+                    recovered
+                );
+            }
+            assert!(clock.rtp_timestamp(clock.network_anchor + 0.5) < clock.rtp_anchor);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_video_frames_are_split_into_packets() {
+        let mut sender = MediaSender::new(WebRtcCodec::Vp8, "client".into());
+        let keyframe = MediaFrame {
+            data: vec![0x10; 3000].into(),
+            frame_type: FrameType::VideoKeyframe,
+            ..frame(100.0)
+        };
+        sender.write(&keyframe, keyframe.data.clone()).await.unwrap();
+        let (_, packets) = &sender.pending[0];
+        assert_eq!(packets.len(), 3);
+        let markers: Vec<bool> = packets.iter().map(|packet| packet.header.marker).collect();
+        assert_eq!(markers, [false, false, true]);
+        assert!(packets.iter().all(|packet| packet.header.timestamp == packets[0].header.timestamp));
+    }
+
+    #[test]
+    fn test_playout_delay_is_packed_in_10ms_units() {
+        assert_eq!(PlayoutDelay::fixed(Duration::from_millis(123)), PlayoutDelay { min_ms: 120, max_ms: 120 });
+        assert_eq!(PlayoutDelay::fixed(Duration::from_secs(60)).max_ms, MAX_PLAYOUT_DELAY_MS);
+
+        let delay = PlayoutDelay { min_ms: 100, max_ms: MAX_PLAYOUT_DELAY_MS };
+        let mut buf = [0; 3];
+        assert_eq!(delay.marshal_to(&mut buf).unwrap(), 3);
+        assert_eq!(buf, [0x00, 0xaf, 0xff]);
+        assert_eq!(PlayoutDelay::decode(&buf), Some(delay));
+        assert_eq!(PlayoutDelay::decode(&buf[..2]), None);
+    }
+}
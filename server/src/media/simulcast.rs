@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::protocol::{MediaControlMessage, NetworkQuality};
+
+use super::rendition::{QualityTier, TierSelector};
+
+/// Loss a client reports about its video track, in percent, at which it
+/// is sent the tier below the one its network quality calls for
+pub const VIDEO_LOSS_DOWNGRADE_PERCENT: f64 = 5.0;
+
+/// Encodings of one video at different resolutions and bitrates, each fed
+/// as a track of its own and subscribed to as one stream
+///
+/// A subscriber is sent one tier at a time, picked for its link, and
+/// switched between tiers only at keyframes so its decoder never receives
+/// delta frames of an encoding it has no keyframe of.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TieredVideo {
+    pub stream_id: String,
+
+    /// Track carrying the encoding of each tier
+    pub tiers: BTreeMap<QualityTier, String>,
+}
+
+impl TieredVideo {
+    pub fn contains(&self, track_id: &str) -> bool {
+        self.tiers.values().any(|track| track == track_id)
+    }
+
+    /// Split a command addressed to the stream into one for each tier's
+    /// track, so all tiers play on the same timeline
+    pub fn track_commands(&self, cmd: &MediaControlMessage) -> Vec<MediaControlMessage> {
+        self.tiers
+            .values()
+            .map(|track_id| MediaControlMessage {
+                track_id: track_id.clone(),
+                ..cmd.clone()
+            })
+            .collect()
+    }
+}
+
+/// Tier of a tiered video a client's link calls for
+///
+/// Loss on the client's video track takes it one tier further down than its
+/// network quality, which follows its audio, would.
+pub fn wanted_tier(quality: NetworkQuality, video_loss_percent: Option<f64>) -> QualityTier {
+    let tier = QualityTier::for_quality(quality);
+    match video_loss_percent {
+        Some(loss) if loss >= VIDEO_LOSS_DOWNGRADE_PERCENT => tier.lower(),
+        _ => tier,
+    }
+}
+
+/// Tier of a tiered video a subscription forwards
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoTierStatus {
+    pub stream_id: String,
+
+    /// Tier being forwarded; none until the first keyframe
+    pub tier: Option<QualityTier>,
+
+    /// Tier being switched to at its next keyframe, or the one forwarded
+    pub target: QualityTier,
+
+    /// Switches between tiers since the client subscribed
+    pub switches: u64,
+}
+
+/// Picks which tier's frames a subscription to a tiered video forwards
+///
+/// The tier wanted passes through a `TierSelector`, counted in forwarded
+/// frames, so a switch down waits a few frames and one up many more. The
+/// switch itself happens at the next keyframe of the new tier; frames of
+/// the old tier are forwarded until then.
+#[derive(Debug)]
+pub struct VideoTierSwitch {
+    /// Tiers the video is encoded at
+    available: Vec<QualityTier>,
+    selector: TierSelector,
+    target: QualityTier,
+    forwarding: Option<QualityTier>,
+    switches: u64,
+}
+
+impl VideoTierSwitch {
+    pub fn new(available: impl IntoIterator<Item = QualityTier>, wanted: QualityTier) -> Self {
+        let mut available: Vec<_> = available.into_iter().collect();
+        available.sort();
+        let target = serving_tier(&available, wanted);
+        Self {
+            available,
+            selector: TierSelector::new(target),
+            target,
+            forwarding: None,
+            switches: 0,
+        }
+    }
+
+    /// Whether to forward a frame of `tier`, given the tier the client's
+    /// link calls for
+    pub fn admit(&mut self, tier: QualityTier, keyframe: bool, wanted: QualityTier) -> bool {
+        let wanted = serving_tier(&self.available, wanted);
+        let Some(forwarding) = self.forwarding else {
+            // Nothing is shown yet, so the first keyframe can be of any tier
+            self.target = wanted;
+            self.selector = TierSelector::new(wanted);
+            if tier != wanted || !keyframe {
+                return false;
+            }
+            self.forwarding = Some(tier);
+            return true;
+        };
+
+        if tier == forwarding {
+            self.target = self.selector.select(wanted);
+            return true;
+        }
+        if tier != self.target || !keyframe {
+            return false;
+        }
+        self.forwarding = Some(tier);
+        self.switches += 1;
+        true
+    }
+
+    /// Tier being forwarded; none until the first keyframe
+    pub fn forwarding(&self) -> Option<QualityTier> {
+        self.forwarding
+    }
+
+    pub fn status(&self, stream_id: &str) -> VideoTierStatus {
+        VideoTierStatus {
+            stream_id: stream_id.to_string(),
+            tier: self.forwarding,
+            target: self.target,
+            switches: self.switches,
+        }
+    }
+}
+
+/// Highest available tier not above `wanted`, or the lowest one if all are
+fn serving_tier(available: &[QualityTier], wanted: QualityTier) -> QualityTier {
+    available
+        .iter()
+        .rev()
+        .find(|tier| **tier <= wanted)
+        .or(available.first())
+        .copied()
+        .unwrap_or(wanted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switches_wait_for_a_keyframe_of_the_new_tier() {
+        let mut switch = VideoTierSwitch::new([QualityTier::Low, QualityTier::High], QualityTier::High);
+
+        // Forwarding starts at a keyframe of the wanted tier
+        assert!(!switch.admit(QualityTier::High, false, QualityTier::High));
+        assert!(!switch.admit(QualityTier::Low, true, QualityTier::High));
+        assert!(switch.admit(QualityTier::High, true, QualityTier::High));
+
+        // Medium is not encoded, so a Fair link gets Low, after the hold
+        for _ in 0..5 {
+            assert!(switch.admit(QualityTier::High, false, QualityTier::Medium));
+            assert!(!switch.admit(QualityTier::Low, false, QualityTier::Medium));
+        }
+        let status = switch.status("stage");
+        assert_eq!((status.tier, status.target), (Some(QualityTier::High), QualityTier::Low));
+
+        // High keeps flowing until Low has a keyframe
+        assert!(switch.admit(QualityTier::High, true, QualityTier::Medium));
+        assert!(switch.admit(QualityTier::Low, true, QualityTier::Medium));
+        assert!(!switch.admit(QualityTier::High, false, QualityTier::Medium));
+        assert!(switch.admit(QualityTier::Low, false, QualityTier::Medium));
+        assert_eq!(switch.forwarding(), Some(QualityTier::Low));
+        assert_eq!(switch.status("stage").switches, 1);
+    }
+
+    #[test]
+    fn test_video_loss_lowers_the_wanted_tier() {
+        assert_eq!(wanted_tier(NetworkQuality::Good, None), QualityTier::High);
+        assert_eq!(wanted_tier(NetworkQuality::Good, Some(1.0)), QualityTier::High);
+        assert_eq!(wanted_tier(NetworkQuality::Good, Some(8.0)), QualityTier::Medium);
+        assert_eq!(wanted_tier(NetworkQuality::Poor, Some(8.0)), QualityTier::Low);
+        assert_eq!(serving_tier(&[QualityTier::Medium, QualityTier::High], QualityTier::Low), QualityTier::Medium);
+    }
+}
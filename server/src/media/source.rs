@@ -0,0 +1,510 @@
+use anyhow::{anyhow, Result};
+use std::{path::Path, time::Duration};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::{Time, TimeBase},
+};
+
+use super::buffer::FrameType;
+
+/// Duration of the frames produced when re-chunking decoded PCM
+pub const PCM_FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Frame produced by a source, positioned on the track timeline
+#[derive(Debug, Clone)]
+pub struct SourceFrame {
+    /// Encoded frame data
+    pub data: Vec<u8>,
+
+    /// Offset from the start of the track in seconds
+    pub position: f64,
+
+    /// Frame duration
+    pub duration: Duration,
+
+    /// Frame type
+    pub frame_type: FrameType,
+}
+
+/// Producer of media frames for a stream
+pub trait FrameSource: Send {
+    /// Codec of the produced frames (e.g. "opus", "pcm16")
+    fn codec(&self) -> &str;
+
+    /// Sample rate in Hz
+    fn sample_rate(&self) -> u32;
+
+    /// Number of audio channels
+    fn channels(&self) -> u8;
+
+    /// Total track duration in seconds, if known
+    fn duration(&self) -> Option<f64>;
+
+    /// Produce the next frame, or `None` at the end of the track
+    fn next_frame(&mut self) -> Result<Option<SourceFrame>>;
+
+    /// Reposition the source to `position` seconds into the track
+    fn seek(&mut self, position: f64) -> Result<()>;
+
+    /// Make the next frame a keyframe, returning whether the source can
+    ///
+    /// Only sources encoding video can; those passing encoded frames
+    /// through, as file sources do, keep to the keyframes they have.
+    fn request_keyframe(&mut self) -> bool {
+        false
+    }
+}
+
+/// Audio file source backed by symphonia
+///
+/// PCM-decodable formats (WAV) are decoded and re-chunked into
+/// `PCM_FRAME_DURATION` frames of interleaved little-endian i16 samples.
+/// Opus packets are passed through without decoding.
+pub struct FileSource {
+    format: Box<dyn FormatReader>,
+
+    /// Decoder for PCM output (`None` for passthrough codecs)
+    decoder: Option<Box<dyn Decoder>>,
+
+    track_id: u32,
+    codec: String,
+    sample_rate: u32,
+    channels: u8,
+    time_base: TimeBase,
+    duration: Option<f64>,
+
+    /// Decoded interleaved samples not yet emitted
+    pending: Vec<i16>,
+
+    /// Per-channel samples to discard after an accurate seek
+    skip_samples: usize,
+
+    /// Position of the next PCM frame in seconds
+    position: f64,
+
+    /// End of the underlying stream reached
+    eof: bool,
+}
+
+impl FileSource {
+    /// Open and probe an audio file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| anyhow!("Unsupported media format {}: {}", path.display(), e))?;
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("No audio track in {}", path.display()))?;
+        let params = track.codec_params.clone();
+        let track_id = track.id;
+
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| anyhow!("Unknown sample rate in {}", path.display()))?;
+        let channels = params.channels.map(|c| c.count() as u8).unwrap_or(2);
+        let time_base = params.time_base.unwrap_or_else(|| TimeBase::new(1, sample_rate));
+        let duration = params.n_frames.map(|n| {
+            let time = time_base.calc_time(n);
+            time.seconds as f64 + time.frac
+        });
+
+        let (codec, decoder) = if params.codec == CODEC_TYPE_OPUS {
+            ("opus".to_string(), None)
+        } else {
+            let decoder = symphonia::default::get_codecs()
+                .make(&params, &DecoderOptions::default())
+                .map_err(|e| anyhow!("Unsupported codec in {}: {}", path.display(), e))?;
+            ("pcm16".to_string(), Some(decoder))
+        };
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            codec,
+            sample_rate,
+            channels,
+            time_base,
+            duration,
+            pending: Vec::new(),
+            skip_samples: 0,
+            position: 0.0,
+            eof: false,
+        })
+    }
+
+    /// Samples per channel in one PCM frame
+    fn samples_per_frame(&self) -> usize {
+        (self.sample_rate as f64 * PCM_FRAME_DURATION.as_secs_f64()) as usize
+    }
+
+    /// Convert a timestamp in the track time base to seconds
+    fn ts_to_secs(&self, ts: u64) -> f64 {
+        let time = self.time_base.calc_time(ts);
+        time.seconds as f64 + time.frac
+    }
+
+    /// Read the next packet of our track, or `None` at end of stream
+    fn next_packet(&mut self) -> Result<Option<symphonia::core::formats::Packet>> {
+        loop {
+            match self.format.next_packet() {
+                Ok(packet) if packet.track_id() == self.track_id => return Ok(Some(packet)),
+                Ok(_) => continue,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Decode packets until a full PCM frame is pending or the stream ends
+    fn fill_pending(&mut self) -> Result<()> {
+        let frame_len = self.samples_per_frame() * self.channels as usize;
+
+        while self.pending.len() < frame_len && !self.eof {
+            let Some(packet) = self.next_packet()? else {
+                self.eof = true;
+                break;
+            };
+
+            let Some(decoder) = self.decoder.as_mut() else {
+                break;
+            };
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(e)) => {
+                    tracing::warn!("Skipping undecodable packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let spec = *decoded.spec();
+            let mut samples = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+            samples.copy_interleaved_ref(decoded);
+
+            let skip = (self.skip_samples * self.channels as usize).min(samples.samples().len());
+            self.skip_samples -= skip / self.channels as usize;
+            self.pending.extend_from_slice(&samples.samples()[skip..]);
+        }
+
+        Ok(())
+    }
+
+    fn next_pcm_frame(&mut self) -> Result<Option<SourceFrame>> {
+        self.fill_pending()?;
+
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+
+        let frame_len = (self.samples_per_frame() * self.channels as usize).min(self.pending.len());
+        let data = self
+            .pending
+            .drain(..frame_len)
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        let samples = frame_len / self.channels as usize;
+        let duration = Duration::from_secs_f64(samples as f64 / self.sample_rate as f64);
+        let position = self.position;
+        self.position += duration.as_secs_f64();
+
+        Ok(Some(SourceFrame {
+            data,
+            position,
+            duration,
+            frame_type: FrameType::Audio,
+        }))
+    }
+
+    fn next_passthrough_frame(&mut self) -> Result<Option<SourceFrame>> {
+        let Some(packet) = self.next_packet()? else {
+            self.eof = true;
+            return Ok(None);
+        };
+
+        Ok(Some(SourceFrame {
+            position: self.ts_to_secs(packet.ts),
+            duration: Duration::from_secs_f64(self.ts_to_secs(packet.dur)),
+            data: packet.data.into_vec(),
+            frame_type: FrameType::Audio,
+        }))
+    }
+}
+
+impl FrameSource for FileSource {
+    fn codec(&self) -> &str {
+        &self.codec
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    fn next_frame(&mut self) -> Result<Option<SourceFrame>> {
+        if self.decoder.is_some() {
+            self.next_pcm_frame()
+        } else {
+            self.next_passthrough_frame()
+        }
+    }
+
+    fn seek(&mut self, position: f64) -> Result<()> {
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(position.max(0.0)),
+                track_id: Some(self.track_id),
+            },
+        )?;
+
+        if let Some(decoder) = self.decoder.as_mut() {
+            decoder.reset();
+        }
+
+        self.pending.clear();
+        self.skip_samples = if self.decoder.is_some() {
+            seeked.required_ts.saturating_sub(seeked.actual_ts) as usize
+        } else {
+            0
+        };
+        self.position = self.ts_to_secs(seeked.required_ts);
+        self.eof = false;
+
+        Ok(())
+    }
+}
+
+/// Write a 16-bit PCM WAV file containing a ramp signal
+#[cfg(test)]
+pub(crate) fn write_test_wav(path: &Path, sample_rate: u32, channels: u16, samples: usize) {
+    let data_len = (samples * channels as usize * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    wav.extend_from_slice(&(channels * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+
+    for i in 0..samples {
+        let sample = (i % 1000) as i16;
+        for _ in 0..channels {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+
+    std::fs::write(path, wav).expect("Failed to write test WAV");
+}
+
+/// Synthetic mono PCM source of constant-amplitude 20ms frames
+#[cfg(test)]
+pub(crate) struct TestSource {
+    frames: usize,
+    next: usize,
+}
+
+#[cfg(test)]
+impl TestSource {
+    /// Amplitude of every sample
+    pub const AMPLITUDE: i16 = 10000;
+
+    pub fn new(frames: usize) -> Self {
+        Self { frames, next: 0 }
+    }
+}
+
+#[cfg(test)]
+impl FrameSource for TestSource {
+    fn codec(&self) -> &str {
+        "pcm16"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    fn channels(&self) -> u8 {
+        1
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(self.frames as f64 * PCM_FRAME_DURATION.as_secs_f64())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<SourceFrame>> {
+        if self.next >= self.frames {
+            return Ok(None);
+        }
+
+        let frame = SourceFrame {
+            data: Self::AMPLITUDE.to_le_bytes().repeat(960),
+            position: self.next as f64 * PCM_FRAME_DURATION.as_secs_f64(),
+            duration: PCM_FRAME_DURATION,
+            frame_type: FrameType::Audio,
+        };
+        self.next += 1;
+
+        Ok(Some(frame))
+    }
+
+    fn seek(&mut self, position: f64) -> Result<()> {
+        self.next = (position / PCM_FRAME_DURATION.as_secs_f64()).round() as usize;
+        Ok(())
+    }
+}
+
+/// Synthetic PCM source of a sine tone in 20ms frames, identical on every
+/// channel
+#[cfg(test)]
+pub(crate) struct SineSource {
+    frames: usize,
+    channels: u8,
+    frequency: f64,
+    amplitude: f64,
+    next: usize,
+}
+
+#[cfg(test)]
+impl SineSource {
+    const SAMPLE_RATE: u32 = 48000;
+
+    pub fn new(frames: usize, channels: u8, frequency: f64, amplitude: f64) -> Self {
+        Self {
+            frames,
+            channels,
+            frequency,
+            amplitude,
+            next: 0,
+        }
+    }
+
+    /// Sample at `t` seconds into the track
+    pub fn sample_at(frequency: f64, amplitude: f64, t: f64) -> f64 {
+        amplitude * (2.0 * std::f64::consts::PI * frequency * t).sin()
+    }
+}
+
+#[cfg(test)]
+impl FrameSource for SineSource {
+    fn codec(&self) -> &str {
+        "pcm16"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        Self::SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(self.frames as f64 * PCM_FRAME_DURATION.as_secs_f64())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<SourceFrame>> {
+        if self.next >= self.frames {
+            return Ok(None);
+        }
+
+        let position = self.next as f64 * PCM_FRAME_DURATION.as_secs_f64();
+        let samples = (Self::SAMPLE_RATE as f64 * PCM_FRAME_DURATION.as_secs_f64()) as usize;
+        let mut data = Vec::with_capacity(samples * self.channels as usize * 2);
+        for i in 0..samples {
+            let t = position + i as f64 / Self::SAMPLE_RATE as f64;
+            let sample = Self::sample_at(self.frequency, self.amplitude, t).round() as i16;
+            for _ in 0..self.channels {
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        self.next += 1;
+
+        Ok(Some(SourceFrame {
+            data,
+            position,
+            duration: PCM_FRAME_DURATION,
+            frame_type: FrameType::Audio,
+        }))
+    }
+
+    fn seek(&mut self, position: f64) -> Result<()> {
+        self.next = (position / PCM_FRAME_DURATION.as_secs_f64()).round() as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_source_framing() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.wav", uuid::Uuid::new_v4()));
+        // 110ms of mono audio: five full 20ms frames and one 10ms frame
+        write_test_wav(&path, 48000, 1, 5280);
+
+        let mut source = FileSource::open(&path).unwrap();
+        assert_eq!(source.codec(), "pcm16");
+        assert_eq!(source.sample_rate(), 48000);
+        assert_eq!(source.channels(), 1);
+
+        let mut frames = Vec::new();
+        while let Some(frame) = source.next_frame().unwrap() {
+            frames.push(frame);
+        }
+
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[0].data.len(), 960 * 2);
+        assert_eq!(frames[5].duration, Duration::from_millis(10));
+        assert!((frames[5].position - 0.1).abs() < 1e-9);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_unsupported_format_is_rejected() {
+        let path = std::env::temp_dir().join(format!("solusync-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"definitely not audio").unwrap();
+
+        assert!(FileSource::open(&path).is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,262 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use uuid::Uuid;
+
+use crate::clock::PeerClockStats;
+
+use super::{
+    buffer::BufferStats, capture::CaptureStatus, fec::FecStatus, link::LinkMetrics, recording::RecordingStatus,
+    rendition::QualityTier, simulcast::VideoTierStatus, sync_group::SyncGroupStatus, MediaCodecs, StreamStatus,
+};
+use crate::{health::HealthState, protocol::NetworkQuality};
+
+/// Bytes and messages sent to one client over one path, kept for the
+/// life of its connection
+#[derive(Debug, Default)]
+pub struct EgressCounter {
+    bytes: AtomicU64,
+    messages: AtomicU64,
+}
+
+impl EgressCounter {
+    /// Count `messages` messages totalling `bytes` bytes as sent
+    pub fn record(&self, messages: usize, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.messages.fetch_add(messages as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Egress {
+        Egress {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            messages: self.messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of an `EgressCounter`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Egress {
+    pub bytes: u64,
+    pub messages: u64,
+}
+
+/// Counters updated as a stream emits frames
+#[derive(Debug)]
+pub struct StreamCounters {
+    frames_emitted: AtomicU64,
+    bytes_emitted: AtomicU64,
+    /// Track position in seconds as `f64` bits; NaN when unknown
+    position: AtomicU64,
+    /// Emitted frames not yet superseded by a frame being presented, from
+    /// which the playback position at any network time is derived
+    presented: Mutex<VecDeque<PresentedFrame>>,
+}
+
+/// Track span presented from a network time
+#[derive(Debug, Clone, Copy)]
+struct PresentedFrame {
+    /// Network time at which `position` is presented
+    at: f64,
+
+    /// Track position in seconds
+    position: f64,
+
+    /// Seconds of track presented from `at`; zero when not advancing
+    duration: f64,
+}
+
+impl Default for StreamCounters {
+    fn default() -> Self {
+        Self {
+            frames_emitted: AtomicU64::new(0),
+            bytes_emitted: AtomicU64::new(0),
+            position: AtomicU64::new(f64::NAN.to_bits()),
+            presented: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl StreamCounters {
+    /// Count an emitted frame
+    pub fn record_frame(&self, bytes: usize) {
+        self.frames_emitted.fetch_add(1, Ordering::Relaxed);
+        self.bytes_emitted.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Set the current track position, or clear it with `None`
+    ///
+    /// The playback position holds at `position` until the next frame is
+    /// presented.
+    pub fn set_position(&self, position: Option<f64>) {
+        let bits = position.unwrap_or(f64::NAN).to_bits();
+        self.position.store(bits, Ordering::Relaxed);
+        let mut presented = self.presented.lock();
+        presented.clear();
+        presented.extend(position.map(|position| PresentedFrame {
+            at: 0.0,
+            position,
+            duration: 0.0,
+        }));
+    }
+
+    /// Record a frame at track `position` emitted at network time `now` for
+    /// presentation at `at`
+    ///
+    /// Frames may be emitted ahead of their presentation time; they only
+    /// count towards the playback position once it arrives.
+    pub fn record_presented(&self, now: f64, at: f64, position: f64, duration: f64) {
+        let end = position + duration;
+        self.position.store(end.to_bits(), Ordering::Relaxed);
+
+        let mut presented = self.presented.lock();
+        presented.push_back(PresentedFrame { at, position, duration });
+        while presented.get(1).is_some_and(|next| next.at <= now) {
+            presented.pop_front();
+        }
+    }
+
+    /// Track position being presented at network time `now`
+    ///
+    /// Advances with the clock through the emitted frames and holds at the
+    /// end of the last one, so it stands still while paused or waiting to
+    /// start.
+    pub fn position_at(&self, now: f64) -> Option<f64> {
+        let presented = self.presented.lock();
+        let frame = presented
+            .iter()
+            .rev()
+            .find(|frame| frame.at <= now)
+            .or(presented.front())?;
+        Some(frame.position + (now - frame.at).clamp(0.0, frame.duration))
+    }
+
+    pub fn frames_emitted(&self) -> u64 {
+        self.frames_emitted.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_emitted(&self) -> u64 {
+        self.bytes_emitted.load(Ordering::Relaxed)
+    }
+
+    pub fn position(&self) -> Option<f64> {
+        let position = f64::from_bits(self.position.load(Ordering::Relaxed));
+        (!position.is_nan()).then_some(position)
+    }
+}
+
+/// Snapshot of all media statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaStats {
+    pub streams: Vec<StreamStats>,
+    pub clients: Vec<ClientStats>,
+    pub active_forwarders: usize,
+    pub sync_groups: Vec<SyncGroupStatus>,
+
+    /// Clients whose media was torn down when their peer connection failed
+    /// or closed, until their control connection goes too
+    pub disconnected_clients: Vec<DisconnectedClientStats>,
+
+    /// ICE restarts offered to clients since the server started
+    pub ice_restarts: u64,
+
+    /// Stats snapshots the run loop has taken since the server started
+    pub stats_snapshots: u64,
+}
+
+/// Client whose peer connection failed or closed
+#[derive(Debug, Clone, Serialize)]
+pub struct DisconnectedClientStats {
+    pub client_id: Uuid,
+    pub connection_state: String,
+    pub subscribed_tracks: Vec<String>,
+}
+
+/// Media subsystem health
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaHealth {
+    /// `down` until the command loop runs, `degraded` while any client's
+    /// peer connection has failed
+    pub state: HealthState,
+    pub streams: usize,
+    pub playing_streams: usize,
+    pub clients: usize,
+    pub failed_connections: usize,
+
+    /// Buffer underruns across connected clients
+    pub underruns: u64,
+
+    /// Frames missed across connected clients
+    pub frames_dropped: u64,
+}
+
+/// Statistics for one stream
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamStats {
+    #[serde(flatten)]
+    pub status: StreamStatus,
+    pub frames_emitted: u64,
+    pub bytes_emitted: u64,
+
+    /// Track position in seconds of the last emitted frame's end
+    pub position: Option<f64>,
+
+    /// Latest recording of the stream, if any
+    pub recording: Option<RecordingStatus>,
+
+    /// Latest frame capture of the stream, if any
+    pub capture: Option<CaptureStatus>,
+}
+
+/// Statistics for one media client
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientStats {
+    pub client_id: Uuid,
+
+    /// Node ID from the client's hello, under which its buffer tuning is
+    /// cached across reconnects
+    pub device_id: Option<Uuid>,
+    pub subscribed_tracks: Vec<String>,
+    pub network_quality: NetworkQuality,
+
+    /// Tier of the most recently forwarded frame
+    pub quality_tier: QualityTier,
+    pub buffer: BufferStats,
+    pub frames_delivered: u64,
+    pub frames_dropped: u64,
+
+    /// RTP packets and clock sync replies sent over the peer connection
+    pub egress: Egress,
+
+    /// Latest RTCP reception report about the client's audio track
+    pub link: Option<LinkMetrics>,
+
+    /// Latest RTCP reception report about the client's video track
+    pub video_link: Option<LinkMetrics>,
+
+    /// In-band FEC of the client's Opus audio
+    pub fec: FecStatus,
+
+    /// Keyframes requested for the client, by its PLIs and FIRs and its
+    /// video subscriptions
+    pub keyframe_requests: u64,
+
+    /// Tier forwarded of each tiered video the client subscribes to, with
+    /// the switches made between tiers
+    pub video_tiers: Vec<VideoTierStatus>,
+
+    /// WebRTC peer connection state, e.g. `connected`
+    pub connection_state: String,
+
+    /// Codecs the client's answer settled on; none before it is applied
+    pub negotiated_codecs: MediaCodecs,
+
+    /// Target codec of each stream transcoded for the client
+    pub transcodes: BTreeMap<String, String>,
+
+    /// Clock synchronization of the client, once it has sent a sample
+    pub clock: Option<PeerClockStats>,
+}
@@ -0,0 +1,248 @@
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+use super::buffer::MediaFrame;
+
+/// Longest a frame is held for a stalled track unless configured otherwise
+pub const DEFAULT_SYNC_SLACK: Duration = Duration::from_millis(200);
+
+/// Tracks released together so that they stay in sync, such as the audio
+/// and video of one source
+///
+/// Every track's frames share the network clock, so the group's anchor is
+/// the earliest point up to which all tracks have delivered media. A frame
+/// is held until the anchor passes its presentation timestamp, so a track
+/// that runs ahead of the others waits for them and matching frames leave
+/// together. A track that delivers nothing for `slack` stops holding the
+/// others, and no frame is held longer than `slack`.
+pub struct SyncGroup {
+    name: String,
+    slack: Duration,
+    created: Instant,
+    tracks: BTreeMap<String, GroupTrack>,
+}
+
+/// Frames of one track of a sync group
+#[derive(Default)]
+struct GroupTrack {
+    /// Network time at which the latest frame received ends
+    received_until: Option<f64>,
+
+    last_arrival: Option<Instant>,
+
+    /// Frames not yet released and when they arrived, in arrival order
+    pending: VecDeque<(Instant, MediaFrame)>,
+
+    /// Frames released after waiting `slack` for a lagging track
+    forced_releases: u64,
+}
+
+/// Sync group state for the stats API
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncGroupStatus {
+    pub name: String,
+    pub tracks: Vec<String>,
+    pub slack_ms: u64,
+
+    /// Frames waiting for the other tracks
+    pub held_frames: usize,
+
+    /// Frames released after waiting `slack` for a lagging track
+    pub forced_releases: u64,
+}
+
+impl SyncGroup {
+    pub fn new(name: impl Into<String>, tracks: impl IntoIterator<Item = String>, slack: Duration) -> Self {
+        Self {
+            name: name.into(),
+            slack,
+            created: Instant::now(),
+            tracks: tracks
+                .into_iter()
+                .map(|track_id| (track_id, GroupTrack::default()))
+                .collect(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn contains(&self, track_id: &str) -> bool {
+        self.tracks.contains_key(track_id)
+    }
+
+    /// Add a frame of one of the group's tracks, returning the frames
+    /// released as a result in presentation order
+    pub fn push(&mut self, track_id: &str, frame: MediaFrame, now: Instant) -> Vec<(String, MediaFrame)> {
+        let Some(track) = self.tracks.get_mut(track_id) else {
+            return vec![(track_id.to_string(), frame)];
+        };
+
+        let end = frame.timestamp + frame.duration.as_secs_f64();
+        track.received_until = Some(track.received_until.map_or(end, |until| until.max(end)));
+        track.last_arrival = Some(now);
+        track.pending.push_back((now, frame));
+
+        self.release(now)
+    }
+
+    /// Release the frames that are aligned or have been held for `slack`
+    pub fn release(&mut self, now: Instant) -> Vec<(String, MediaFrame)> {
+        let anchor = self.anchor(now);
+        let mut released = Vec::new();
+
+        for (track_id, track) in &mut self.tracks {
+            while let Some((arrived, frame)) = track.pending.front() {
+                let aligned = anchor.is_some_and(|anchor| frame.timestamp < anchor);
+                if !aligned {
+                    if now.duration_since(*arrived) < self.slack {
+                        break;
+                    }
+                    debug!(
+                        "Releasing {} frame at {:.3} of sync group {} unaligned",
+                        track_id, frame.timestamp, self.name
+                    );
+                    track.forced_releases += 1;
+                }
+                if let Some((_, frame)) = track.pending.pop_front() {
+                    released.push((track_id.clone(), frame));
+                }
+            }
+        }
+
+        released.sort_by(|(_, a), (_, b)| a.timestamp.total_cmp(&b.timestamp));
+        released
+    }
+
+    /// Release every held frame, such as when the group is dissolved
+    pub fn drain(&mut self) -> Vec<(String, MediaFrame)> {
+        let mut released: Vec<_> = self
+            .tracks
+            .iter_mut()
+            .flat_map(|(track_id, track)| {
+                track
+                    .pending
+                    .drain(..)
+                    .map(|(_, frame)| (track_id.clone(), frame))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        released.sort_by(|(_, a), (_, b)| a.timestamp.total_cmp(&b.timestamp));
+        released
+    }
+
+    /// Network time up to which every active track has delivered media
+    ///
+    /// A track is active while it has delivered within `slack`, or has not
+    /// started yet while the group is younger than `slack`; no anchor
+    /// exists while an active track has delivered nothing.
+    fn anchor(&self, now: Instant) -> Option<f64> {
+        let mut anchor: Option<f64> = None;
+
+        for track in self.tracks.values() {
+            let since = now.duration_since(track.last_arrival.unwrap_or(self.created));
+            if since >= self.slack {
+                continue;
+            }
+
+            let until = track.received_until?;
+            anchor = Some(anchor.map_or(until, |anchor| anchor.min(until)));
+        }
+
+        anchor
+    }
+
+    pub fn status(&self) -> SyncGroupStatus {
+        SyncGroupStatus {
+            name: self.name.clone(),
+            tracks: self.tracks.keys().cloned().collect(),
+            slack_ms: self.slack.as_millis() as u64,
+            held_frames: self.tracks.values().map(|track| track.pending.len()).sum(),
+            forced_releases: self.tracks.values().map(|track| track.forced_releases).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::buffer::FrameType;
+    use bytes::Bytes;
+    use std::{collections::HashMap, sync::Arc};
+
+    fn frame(timestamp: f64, frame_type: FrameType) -> MediaFrame {
+        MediaFrame {
+            data: Bytes::new(),
+            timestamp,
+            duration: Duration::from_millis(20),
+            frame_type,
+            sequence: 0,
+            renditions: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_video_lead_is_aligned_with_audio() {
+        let mut group = SyncGroup::new("av", ["audio".into(), "video".into()], DEFAULT_SYNC_SLACK);
+        let start = Instant::now();
+        let mut released_at: HashMap<(String, u64), Instant> = HashMap::new();
+
+        // Video arrives 40ms before the audio it belongs with
+        let lead = Duration::from_millis(40);
+        let mut events = Vec::new();
+        for i in 0..20u64 {
+            let arrival = start + Duration::from_millis(20 * i);
+            events.push((arrival, "video", frame(100.0 + i as f64 * 0.02, FrameType::Video)));
+            events.push((arrival + lead, "audio", frame(100.0 + i as f64 * 0.02, FrameType::Audio)));
+        }
+        events.sort_by_key(|(arrival, _, _)| *arrival);
+
+        for (arrival, track_id, frame) in events {
+            for (track_id, frame) in group.push(track_id, frame, arrival) {
+                let index = ((frame.timestamp - 100.0) / 0.02).round() as u64;
+                released_at.insert((track_id, index), arrival);
+            }
+        }
+
+        for i in 0..20u64 {
+            let video = released_at[&("video".to_string(), i)];
+            let audio = released_at[&("audio".to_string(), i)];
+            let skew = if video > audio { video - audio } else { audio - video };
+            assert!(skew <= Duration::from_millis(2), "frame {} skewed by {:?}", i, skew);
+        }
+        assert_eq!(group.status().forced_releases, 0);
+    }
+
+    #[test]
+    fn test_stalled_track_holds_group_for_slack_only() {
+        let slack = Duration::from_millis(100);
+        let mut group = SyncGroup::new("av", ["audio".into(), "video".into()], slack);
+        let start = Instant::now();
+
+        assert_eq!(group.push("audio", frame(100.0, FrameType::Audio), start).len(), 0);
+        assert_eq!(group.push("video", frame(100.0, FrameType::Video), start).len(), 2);
+
+        // Audio stops; video is held until audio has been silent for the slack
+        let arrival = start + Duration::from_millis(20);
+        assert!(group.push("video", frame(100.02, FrameType::Video), arrival).is_empty());
+        assert!(group.release(arrival + slack / 2).is_empty());
+        assert_eq!(group.release(start + slack).len(), 1);
+
+        // With audio stalled, video flows without waiting
+        let later = start + slack * 2;
+        assert_eq!(group.push("video", frame(100.04, FrameType::Video), later).len(), 1);
+
+        // Audio lagging behind video by more than the slack releases video
+        // unaligned
+        let resumed = later + Duration::from_millis(10);
+        assert_eq!(group.push("audio", frame(100.02, FrameType::Audio), resumed).len(), 1);
+        assert!(group.push("video", frame(100.2, FrameType::Video), resumed).is_empty());
+        assert_eq!(group.release(resumed + slack).len(), 1);
+        assert_eq!(group.status().forced_releases, 1);
+    }
+}
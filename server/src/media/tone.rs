@@ -0,0 +1,214 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::{
+    buffer::FrameType,
+    source::{FrameSource, SourceFrame, PCM_FRAME_DURATION},
+};
+
+/// Sample rate of generated tones
+pub const TONE_SAMPLE_RATE: u32 = 48000;
+
+/// Peak level of generated tones, -6dBFS
+const TONE_AMPLITUDE: f64 = 16384.0;
+
+/// Longest tone that can be generated
+pub const MAX_TONE_DURATION: Duration = Duration::from_secs(600);
+
+/// Longest beep of a gated sine
+const MAX_BEEP: Duration = Duration::from_millis(100);
+
+/// Spacing of clicks when no interval is given
+const DEFAULT_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Shape of a test tone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Waveform {
+    /// Sine at the tone frequency, continuous or gated into beeps
+    #[default]
+    Sine,
+
+    /// One cycle of the sine at the start of every interval
+    Click,
+}
+
+/// Parameters of a generated test tone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneParams {
+    pub waveform: Waveform,
+    pub frequency: f64,
+
+    /// Total length of the tone
+    pub duration: Duration,
+
+    /// Spacing between beep or click onsets; a sine without one is continuous
+    pub interval: Option<Duration>,
+}
+
+impl ToneParams {
+    /// Reject tones that cannot be generated
+    pub fn validate(&self) -> Result<()> {
+        let nyquist = TONE_SAMPLE_RATE as f64 / 2.0;
+        if !(self.frequency > 0.0 && self.frequency < nyquist) {
+            anyhow::bail!("Tone frequency must be between 0 and {}Hz", nyquist);
+        }
+        if self.duration.is_zero() || self.duration > MAX_TONE_DURATION {
+            anyhow::bail!("Tone duration must be between 0 and {}s", MAX_TONE_DURATION.as_secs());
+        }
+        if let Some(interval) = self.interval {
+            let cycle = Duration::from_secs_f64(1.0 / self.frequency);
+            if interval < cycle * 2 {
+                anyhow::bail!("Tone interval must be at least two cycles of the frequency");
+            }
+        }
+        Ok(())
+    }
+
+    /// Sample `n` of the tone
+    ///
+    /// Every beep or click starts at phase zero, so all of them are
+    /// identical, and samples depend only on the parameters and `n`.
+    fn sample(&self, n: u64) -> i16 {
+        let rate = TONE_SAMPLE_RATE as u64;
+        let period = match (self.waveform, self.interval) {
+            (_, Some(interval)) => Some(interval),
+            (Waveform::Click, None) => Some(DEFAULT_CLICK_INTERVAL),
+            (Waveform::Sine, None) => None,
+        };
+
+        let n = match period {
+            Some(period) => {
+                let period_samples = (period.as_secs_f64() * rate as f64).round() as u64;
+                let onset = n % period_samples.max(1);
+                let length = match self.waveform {
+                    Waveform::Sine => MAX_BEEP.min(period / 2).as_secs_f64(),
+                    Waveform::Click => 1.0 / self.frequency,
+                };
+                if onset >= (length * rate as f64).round() as u64 {
+                    return 0;
+                }
+                onset
+            }
+            None => n,
+        };
+
+        let phase = 2.0 * std::f64::consts::PI * self.frequency * n as f64 / rate as f64;
+        (TONE_AMPLITUDE * phase.sin()).round() as i16
+    }
+}
+
+/// Mono `pcm16` source generating a test tone on the fly
+///
+/// Frames are deterministic: the same parameters always produce the same
+/// bytes, so recordings made on different clients can be cross-correlated.
+pub struct ToneSource {
+    params: ToneParams,
+    total_samples: u64,
+    next_sample: u64,
+}
+
+impl ToneSource {
+    pub fn new(params: ToneParams) -> Self {
+        Self {
+            params,
+            total_samples: (params.duration.as_secs_f64() * TONE_SAMPLE_RATE as f64).round() as u64,
+            next_sample: 0,
+        }
+    }
+}
+
+impl FrameSource for ToneSource {
+    fn codec(&self) -> &str {
+        "pcm16"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        TONE_SAMPLE_RATE
+    }
+
+    fn channels(&self) -> u8 {
+        1
+    }
+
+    fn duration(&self) -> Option<f64> {
+        Some(self.params.duration.as_secs_f64())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<SourceFrame>> {
+        if self.next_sample >= self.total_samples {
+            return Ok(None);
+        }
+
+        let frame_samples = (PCM_FRAME_DURATION.as_secs_f64() * TONE_SAMPLE_RATE as f64) as u64;
+        let end = (self.next_sample + frame_samples).min(self.total_samples);
+        let data = (self.next_sample..end)
+            .flat_map(|n| self.params.sample(n).to_le_bytes())
+            .collect();
+
+        let frame = SourceFrame {
+            data,
+            position: self.next_sample as f64 / TONE_SAMPLE_RATE as f64,
+            duration: Duration::from_secs_f64((end - self.next_sample) as f64 / TONE_SAMPLE_RATE as f64),
+            frame_type: FrameType::Audio,
+        };
+        self.next_sample = end;
+        Ok(Some(frame))
+    }
+
+    fn seek(&mut self, position: f64) -> Result<()> {
+        let sample = (position.max(0.0) * TONE_SAMPLE_RATE as f64).round() as u64;
+        self.next_sample = sample.min(self.total_samples);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(source: &mut ToneSource) -> Vec<i16> {
+        let mut samples = Vec::new();
+        while let Some(frame) = source.next_frame().unwrap() {
+            samples.extend(frame.data.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_clicks_are_identical_and_deterministic() {
+        let params = ToneParams {
+            waveform: Waveform::Click,
+            frequency: 1000.0,
+            duration: Duration::from_millis(1010),
+            interval: Some(Duration::from_millis(250)),
+        };
+        assert!(params.validate().is_ok());
+
+        let first = samples(&mut ToneSource::new(params));
+        assert_eq!(first, samples(&mut ToneSource::new(params)));
+        assert_eq!(first.len(), 48480);
+
+        // A 48-sample click starts every 12000 samples, silence in between
+        let click = &first[..48];
+        assert!(click.iter().any(|&s| s != 0));
+        for (n, &sample) in first.iter().enumerate() {
+            match n % 12000 {
+                offset if offset < 48 => assert_eq!(sample, click[offset]),
+                _ => assert_eq!(sample, 0, "sample {} not silent", n),
+            }
+        }
+
+        // Seeking reproduces the same samples
+        let mut source = ToneSource::new(params);
+        source.seek(0.5).unwrap();
+        assert_eq!(samples(&mut source), first[24000..]);
+
+        let invalid = ToneParams {
+            frequency: 30000.0,
+            ..params
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use super::buffer::BufferTuning;
+
+/// Buffer tuning of recently disconnected devices, by device ID
+///
+/// A device is identified by the node ID in its hello header, which a
+/// client keeps across reconnects while the server assigns a new client ID
+/// to each connection that does not resume its session. Entries older than
+/// `max_age` are forgotten; a zero age disables the cache.
+pub struct TuningCache {
+    entries: HashMap<Uuid, (BufferTuning, Instant)>,
+    max_age: Duration,
+}
+
+impl TuningCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_age,
+        }
+    }
+
+    /// Remember the tuning a device's buffer had when it disconnected
+    pub fn save(&mut self, device_id: Uuid, tuning: BufferTuning, now: Instant) {
+        if self.max_age.is_zero() {
+            return;
+        }
+        self.expire(now);
+        self.entries.insert(device_id, (tuning, now));
+    }
+
+    /// Tuning saved for a device within the last `max_age`
+    pub fn get(&mut self, device_id: &Uuid, now: Instant) -> Option<BufferTuning> {
+        self.expire(now);
+        self.entries.get(device_id).map(|(tuning, _)| *tuning)
+    }
+
+    /// Forget a device's tuning; returns whether there was any
+    pub fn remove(&mut self, device_id: &Uuid) -> bool {
+        self.entries.remove(device_id).is_some()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let max_age = self.max_age;
+        self.entries
+            .retain(|_, (_, saved_at)| now.duration_since(*saved_at) < max_age);
+    }
+}
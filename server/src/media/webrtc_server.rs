@@ -1,90 +1,573 @@
-use anyhow::Result;
-use std::sync::Arc;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, sync::Arc};
 use webrtc::{
     api::{
-        interceptor_registry::register_default_interceptors,
-        media_engine::MediaEngine,
+        interceptor_registry::{configure_nack, configure_rtcp_reports, configure_twcc_receiver_only},
+        media_engine::{
+            MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS, MIME_TYPE_PCMA, MIME_TYPE_PCMU, MIME_TYPE_VP8,
+            MIME_TYPE_VP9,
+        },
+        setting_engine::SettingEngine,
         APIBuilder,
     },
+    data_channel::{data_channel_init::RTCDataChannelInit, RTCDataChannel},
+    ice::{
+        udp_mux::{UDPMuxDefault, UDPMuxParams},
+        udp_network::{EphemeralUDP, UDPNetwork},
+    },
     ice_transport::{ice_credential_type::RTCIceCredentialType, ice_server::RTCIceServer},
     interceptor::registry::Registry,
     peer_connection::{
-        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+        certificate::RTCCertificate, configuration::RTCConfiguration, offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState, sdp::session_description::RTCSessionDescription,
         RTCPeerConnection,
     },
-    rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType},
+    rtp_transceiver::{
+        rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability, RTPCodecType},
+        RTCPFeedback,
+    },
+};
+
+use super::{
+    fec::{fmtp_requests_fec, LossRecoveryConfig},
+    rtp_sender::PLAYOUT_DELAY_URI,
 };
 
+/// Public STUN server used when none is configured
+const DEFAULT_STUN_URL: &str = "stun:stun.l.google.com:19302";
+
+/// How a TURN server's credential is to be used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IceCredentialType {
+    #[default]
+    Password,
+    Oauth,
+}
+
+/// One STUN or TURN server offered to ICE
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct IceServerConfig {
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub credential: Option<String>,
+    #[serde(default)]
+    pub credential_type: IceCredentialType,
+}
+
+/// An ICE server as reported by the API, without its credential
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IceServerSummary {
+    pub urls: Vec<String>,
+    pub username: Option<String>,
+    pub credential_type: IceCredentialType,
+    pub has_credential: bool,
+}
+
+/// ICE servers used by peer connections
+#[derive(Debug, Clone, PartialEq)]
+pub struct IceConfig {
+    pub servers: Vec<IceServerConfig>,
+    
+    /// Gather host candidates only, contacting no STUN or TURN server, for
+    /// LAN-only deployments
+    pub host_only: bool,
+}
+
+impl Default for IceConfig {
+    fn default() -> Self {
+        Self {
+            servers: vec![IceServerConfig {
+                urls: vec![DEFAULT_STUN_URL.to_string()],
+                username: None,
+                credential: None,
+                credential_type: IceCredentialType::Password,
+            }],
+            host_only: false,
+        }
+    }
+}
+
+impl IceConfig {
+    /// Check every server URL, and that TURN servers have credentials
+    pub fn validate(&self) -> Result<()> {
+        for server in &self.servers {
+            if server.urls.is_empty() {
+                bail!("ICE server entry has no URLs");
+            }
+            for url in &server.urls {
+                let parsed = webrtc::ice::url::Url::parse_url(url)
+                    .map_err(|e| anyhow::anyhow!("Invalid ICE server URL {:?}: {}", url, e))?;
+                let turn = matches!(
+                    parsed.scheme,
+                    webrtc::ice::url::SchemeType::Turn | webrtc::ice::url::SchemeType::Turns
+                );
+                let has_credentials = server.username.as_deref().is_some_and(|u| !u.is_empty())
+                    && server.credential.as_deref().is_some_and(|c| !c.is_empty());
+                if turn && !has_credentials {
+                    bail!("TURN server {} needs a username and credential", url);
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    /// Parse the JSON list of servers given in `SOLUSYNC_ICE_SERVERS`
+    pub fn parse_servers(json: &str) -> Result<Vec<IceServerConfig>> {
+        serde_json::from_str(json).context("SOLUSYNC_ICE_SERVERS must be a JSON list of ICE servers")
+    }
+    
+    /// Servers peer connections use; none when gathering host candidates
+    /// only
+    pub fn effective_servers(&self) -> &[IceServerConfig] {
+        if self.host_only {
+            &[]
+        } else {
+            &self.servers
+        }
+    }
+    
+    /// Servers in use, for the API
+    pub fn summary(&self) -> Vec<IceServerSummary> {
+        self.effective_servers()
+            .iter()
+            .map(|server| IceServerSummary {
+                urls: server.urls.clone(),
+                username: server.username.clone(),
+                credential_type: server.credential_type,
+                has_credential: server.credential.is_some(),
+            })
+            .collect()
+    }
+    
+    fn rtc_servers(&self) -> Vec<RTCIceServer> {
+        self.effective_servers()
+            .iter()
+            .map(|server| RTCIceServer {
+                urls: server.urls.clone(),
+                username: server.username.clone().unwrap_or_default(),
+                credential: server.credential.clone().unwrap_or_default(),
+                credential_type: match server.credential_type {
+                    IceCredentialType::Password => RTCIceCredentialType::Password,
+                    IceCredentialType::Oauth => RTCIceCredentialType::Oauth,
+                },
+            })
+            .collect()
+    }
+}
+
+/// Inclusive range of UDP ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PortRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl PortRange {
+    /// Parse a range written as `min-max`, such as `50000-50100`
+    pub fn parse(range: &str) -> Result<Self> {
+        let (min, max) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("UDP port range {:?} must be written as min-max", range))?;
+        let port = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .with_context(|| format!("Invalid port {:?} in UDP port range", port))
+        };
+        Ok(Self {
+            min: port(min)?,
+            max: port(max)?,
+        })
+    }
+}
+
+/// UDP ports peer connections use, so venue firewalls need only open
+/// those
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UdpPortConfig {
+    /// Ports ICE candidates are gathered on; any ephemeral port when unset
+    ///
+    /// Each peer connection takes a port per network interface, so the
+    /// range bounds how many clients can connect at once.
+    pub port_range: Option<PortRange>,
+    
+    /// Single port every peer connection shares, told apart by their ICE
+    /// credentials; replaces the range
+    pub mux_port: Option<u16>,
+}
+
+impl UdpPortConfig {
+    /// Check that ports are nonzero, the range is not reversed, and a
+    /// range and shared port are not both set
+    pub fn validate(&self) -> Result<()> {
+        if let Some(range) = self.port_range {
+            if range.min == 0 {
+                bail!("UDP port range cannot start at port 0");
+            }
+            if range.min > range.max {
+                bail!("UDP port range {}-{} is reversed", range.min, range.max);
+            }
+        }
+        match self.mux_port {
+            Some(0) => bail!("The shared UDP port cannot be port 0"),
+            Some(_) if self.port_range.is_some() => bail!("Set either a UDP port range or a shared UDP port, not both"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for UdpPortConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.mux_port, self.port_range) {
+            (Some(port), _) => write!(f, "UDP port {} (shared)", port),
+            (None, Some(range)) => write!(f, "UDP ports {}-{}", range.min, range.max),
+            (None, None) => f.write_str("any UDP port"),
+        }
+    }
+}
+
+/// Codec a peer connection can negotiate
+///
+/// Names match the stream codecs they carry, so an `h264` stream is sent
+/// as is to a client that negotiated H264.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebRtcCodec {
+    Opus,
+    /// G.711 μ-law, for clients that cannot decode Opus
+    Pcmu,
+    /// G.711 A-law
+    Pcma,
+    H264,
+    Vp8,
+    Vp9,
+}
+
+impl WebRtcCodec {
+    /// Every codec, the preferred one of each kind first
+    pub const ALL: [Self; 6] = [Self::Opus, Self::Pcmu, Self::Pcma, Self::H264, Self::Vp8, Self::Vp9];
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Opus => "opus",
+            Self::Pcmu => "pcmu",
+            Self::Pcma => "pcma",
+            Self::H264 => "h264",
+            Self::Vp8 => "vp8",
+            Self::Vp9 => "vp9",
+        }
+    }
+    
+    pub fn is_audio(self) -> bool {
+        matches!(self, Self::Opus | Self::Pcmu | Self::Pcma)
+    }
+    
+    pub fn kind(self) -> RTPCodecType {
+        if self.is_audio() {
+            RTPCodecType::Audio
+        } else {
+            RTPCodecType::Video
+        }
+    }
+    
+    /// Rate of the codec's RTP timestamps
+    pub fn clock_rate(self) -> u32 {
+        match self {
+            Self::Opus => 48_000,
+            Self::Pcmu | Self::Pcma => 8_000,
+            Self::H264 | Self::Vp8 | Self::Vp9 => 90_000,
+        }
+    }
+    
+    fn mime_type(self) -> &'static str {
+        match self {
+            Self::Opus => MIME_TYPE_OPUS,
+            Self::Pcmu => MIME_TYPE_PCMU,
+            Self::Pcma => MIME_TYPE_PCMA,
+            Self::H264 => MIME_TYPE_H264,
+            Self::Vp8 => MIME_TYPE_VP8,
+            Self::Vp9 => MIME_TYPE_VP9,
+        }
+    }
+    
+    /// Codec with a MIME type such as `video/VP8`, ignoring case
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.mime_type().eq_ignore_ascii_case(mime_type))
+    }
+    
+    /// Capability of tracks sending the codec
+    ///
+    /// Video offers FIR feedback, so clients can ask for a keyframe; PLI
+    /// comes with NACK, when that is enabled.
+    pub fn capability(self) -> RTCRtpCodecCapability {
+        let (channels, sdp_fmtp_line) = match self {
+            Self::Opus => (2, ""),
+            Self::H264 => (0, "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f"),
+            Self::Vp9 => (0, "profile-id=0"),
+            Self::Pcmu | Self::Pcma | Self::Vp8 => (0, ""),
+        };
+        RTCRtpCodecCapability {
+            mime_type: self.mime_type().to_string(),
+            clock_rate: self.clock_rate(),
+            channels,
+            sdp_fmtp_line: sdp_fmtp_line.to_string(),
+            rtcp_feedback: match self.kind() {
+                RTPCodecType::Video => vec![RTCPFeedback {
+                    typ: "ccm".to_string(),
+                    parameter: "fir".to_string(),
+                }],
+                _ => vec![],
+            },
+        }
+    }
+    
+    /// Parse a comma-separated list of codec names, such as
+    /// `opus,pcmu,vp8`, which needs an audio codec for the clock and
+    /// media track
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        let mut codecs = Vec::new();
+        for name in list.split(',').filter(|name| !name.trim().is_empty()) {
+            let codec: Self = name.parse()?;
+            if !codecs.contains(&codec) {
+                codecs.push(codec);
+            }
+        }
+        if !codecs.iter().any(|codec| codec.is_audio()) {
+            bail!("At least one audio codec is needed");
+        }
+        Ok(codecs)
+    }
+    
+    fn payload_type(self) -> u8 {
+        match self {
+            Self::Opus => 111,
+            Self::Pcmu => 0,
+            Self::Pcma => 8,
+            Self::H264 => 102,
+            Self::Vp8 => 96,
+            Self::Vp9 => 98,
+        }
+    }
+}
+
+impl fmt::Display for WebRtcCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for WebRtcCodec {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim();
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("Unknown WebRTC codec {:?}", name))
+    }
+}
+
+/// Audio and video codec of a client's peer connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MediaCodecs {
+    pub audio: Option<WebRtcCodec>,
+    pub video: Option<WebRtcCodec>,
+}
+
+impl MediaCodecs {
+    pub fn get(&self, kind: RTPCodecType) -> Option<WebRtcCodec> {
+        match kind {
+            RTPCodecType::Video => self.video,
+            _ => self.audio,
+        }
+    }
+    
+    pub fn set(&mut self, codec: WebRtcCodec) {
+        match codec.kind() {
+            RTPCodecType::Video => self.video = Some(codec),
+            _ => self.audio = Some(codec),
+        }
+    }
+}
+
 /// WebRTC server for media streaming
 pub struct WebRtcServer {
     api: webrtc::api::API,
     config: RTCConfiguration,
+    codecs: Vec<WebRtcCodec>,
+    recovery: LossRecoveryConfig,
 }
 
 impl WebRtcServer {
-    pub fn new() -> Self {
-        // Create media engine with audio/video codecs
-        let mut media_engine = MediaEngine::default();
+    /// Create a server whose peer connections use the ICE servers in `ice`,
+    /// which should have been validated, and offer every codec
+    #[cfg(test)]
+    pub fn new(ice: &IceConfig) -> Self {
+        Self::with_codecs(ice, &WebRtcCodec::ALL)
+    }
+    
+    /// Create a server whose peer connections offer only `codecs`, each
+    /// kind in the order given
+    #[cfg(test)]
+    pub fn with_codecs(ice: &IceConfig, codecs: &[WebRtcCodec]) -> Self {
+        Self::with_network(ice, codecs, &UdpPortConfig::default(), &LossRecoveryConfig::default())
+            .expect("ephemeral UDP ports need no setup")
+    }
+    
+    /// Create a server whose peer connections offer only `codecs`, with
+    /// the NACK and FEC of `recovery`, and gather candidates on the UDP
+    /// ports of `udp`, which should have been validated
+    ///
+    /// A shared port is bound here, failing if it is taken; this needs a
+    /// Tokio runtime.
+    pub fn with_network(
+        ice: &IceConfig,
+        codecs: &[WebRtcCodec],
+        udp: &UdpPortConfig,
+        recovery: &LossRecoveryConfig,
+    ) -> Result<Self> {
+        let mut setting_engine = SettingEngine::default();
+        if let Some(port) = udp.mux_port {
+            let socket = std::net::UdpSocket::bind(("0.0.0.0", port))
+                .with_context(|| format!("Failed to bind shared UDP port {}", port))?;
+            socket.set_nonblocking(true)?;
+            let socket = tokio::net::UdpSocket::from_std(socket)?;
+            setting_engine.set_udp_network(UDPNetwork::Muxed(UDPMuxDefault::new(UDPMuxParams::new(socket))));
+        } else if let Some(range) = udp.port_range {
+            let ports = EphemeralUDP::new(range.min, range.max)
+                .map_err(|e| anyhow::anyhow!("Invalid UDP port range: {}", e))?;
+            setting_engine.set_udp_network(UDPNetwork::Ephemeral(ports));
+        }
         
-        // Register audio codecs
-        media_engine
-            .register_codec(
-                RTCRtpCodecParameters {
-                    capability: RTCRtpCodecCapability {
-                        mime_type: "audio/opus".to_string(),
-                        clock_rate: 48000,
-                        channels: 2,
-                        sdp_fmtp_line: "".to_string(),
-                        rtcp_feedback: vec![],
-                    },
-                    payload_type: 111,
-                    ..Default::default()
-                },
-                RTPCodecType::Audio,
-            )
-            .expect("Failed to register Opus codec");
-        
-        // Register video codecs
-        media_engine
-            .register_codec(
-                RTCRtpCodecParameters {
-                    capability: RTCRtpCodecCapability {
-                        mime_type: "video/H264".to_string(),
-                        clock_rate: 90000,
-                        channels: 0,
-                        sdp_fmtp_line: "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42001f".to_string(),
-                        rtcp_feedback: vec![],
+        let mut media_engine = MediaEngine::default();
+        for &codec in codecs {
+            let mut capability = codec.capability();
+            if codec == WebRtcCodec::Opus {
+                capability.sdp_fmtp_line = recovery.opus_fmtp_line();
+            }
+            media_engine
+                .register_codec(
+                    RTCRtpCodecParameters {
+                        capability,
+                        payload_type: codec.payload_type(),
+                        ..Default::default()
                     },
-                    payload_type: 102,
-                    ..Default::default()
-                },
-                RTPCodecType::Video,
-            )
-            .expect("Failed to register H264 codec");
+                    codec.kind(),
+                )
+                .unwrap_or_else(|e| panic!("Failed to register {} codec: {}", codec, e));
+        }
+        // Lets clients hold frames for the server's buffer latency instead
+        // of their own jitter buffer's
+        for kind in [RTPCodecType::Audio, RTPCodecType::Video] {
+            media_engine
+                .register_header_extension(
+                    RTCRtpHeaderExtensionCapability { uri: PLAYOUT_DELAY_URI.to_string() },
+                    kind,
+                    None,
+                )
+                .expect("Failed to register playout-delay extension");
+        }
         
-        // Create interceptor registry
+        // Video may ask for lost packets again, which the NACK responder
+        // resends from its history; a resent audio packet would mostly
+        // arrive too late to play, so audio relies on FEC instead
         let mut registry = Registry::new();
-        registry = register_default_interceptors(registry, &mut media_engine)
+        if recovery.video_nack {
+            registry = configure_nack(registry, &mut media_engine);
+        }
+        registry = configure_rtcp_reports(registry);
+        registry = configure_twcc_receiver_only(registry, &mut media_engine)
             .expect("Failed to register interceptors");
         
         // Create API
         let api = APIBuilder::new()
             .with_media_engine(media_engine)
             .with_interceptor_registry(registry)
+            .with_setting_engine(setting_engine)
             .build();
         
-        // ICE configuration
         let config = RTCConfiguration {
-            ice_servers: vec![RTCIceServer {
-                urls: vec!["stun:stun.l.google.com:19302".to_string()],
-                username: String::new(),
-                credential: String::new(),
-                credential_type: RTCIceCredentialType::Unspecified,
-            }],
+            ice_servers: ice.rtc_servers(),
             ..Default::default()
         };
         
-        Self { api, config }
+        Ok(Self {
+            api,
+            config,
+            codecs: codecs.to_vec(),
+            recovery: *recovery,
+        })
+    }
+    
+    /// Present `certificate` on every peer connection instead of one
+    /// generated for each
+    pub fn with_certificate(mut self, certificate: RTCCertificate) -> Self {
+        self.config.certificates = vec![certificate];
+        self
+    }
+    
+    /// SHA-256 fingerprint of the certificate all peer connections
+    /// present, as in SDP's `a=fingerprint`; none when each generates its
+    /// own
+    pub fn fingerprint(&self) -> Option<String> {
+        let fingerprint = self.config.certificates.first()?.get_fingerprints().into_iter().next()?;
+        Some(format!("{} {}", fingerprint.algorithm, fingerprint.value.to_uppercase()))
+    }
+    
+    /// Codec tracks of `kind` start out with, before a client's answer
+    /// says which one it prefers
+    pub fn default_codec(&self, kind: RTPCodecType) -> Option<WebRtcCodec> {
+        self.codecs.iter().copied().find(|codec| codec.kind() == kind)
+    }
+    
+    /// The codec of each kind a client's answer prefers among those
+    /// offered
+    ///
+    /// Rejected media sections, with port 0, prefer none.
+    pub fn answer_preference(&self, answer: &RTCSessionDescription) -> Result<MediaCodecs> {
+        let parsed = answer.unmarshal()?;
+        let mut preferred = MediaCodecs::default();
+        for media in &parsed.media_descriptions {
+            if media.media_name.port.value == 0 {
+                continue;
+            }
+            let codec = media
+                .media_name
+                .formats
+                .iter()
+                .filter_map(|format| format.parse::<u8>().ok())
+                .filter_map(|payload_type| parsed.get_codec_for_payload_type(payload_type).ok())
+                .filter_map(|codec| WebRtcCodec::from_str(&codec.name).ok())
+                .find(|codec| self.codecs.contains(codec) && codec.kind().to_string() == media.media_name.media);
+            if let Some(codec) = codec {
+                preferred.set(codec);
+            }
+        }
+        Ok(preferred)
+    }
+    
+    /// Whether a client's answer accepted Opus audio with in-band FEC,
+    /// which has to have been offered
+    pub fn answer_accepts_opus_fec(&self, answer: &RTCSessionDescription) -> Result<bool> {
+        if !self.recovery.opus_fec {
+            return Ok(false);
+        }
+        let parsed = answer.unmarshal()?;
+        let accepted = parsed
+            .media_descriptions
+            .iter()
+            .filter(|media| media.media_name.media == "audio" && media.media_name.port.value != 0)
+            .flat_map(|media| &media.media_name.formats)
+            .filter_map(|format| format.parse::<u8>().ok())
+            .filter_map(|payload_type| parsed.get_codec_for_payload_type(payload_type).ok())
+            .any(|codec| codec.name.eq_ignore_ascii_case("opus") && fmtp_requests_fec(&codec.fmtp));
+        Ok(accepted)
     }
     
     /// Create a new peer connection
@@ -96,7 +579,6 @@ impl WebRtcServer {
         );
         
         // Set up event handlers
-        let pc = peer_connection.clone();
         peer_connection.on_peer_connection_state_change(Box::new(
             move |state: RTCPeerConnectionState| {
                 tracing::info!("Peer connection state changed: {:?}", state);
@@ -114,11 +596,52 @@ impl WebRtcServer {
         Ok(peer_connection)
     }
     
+    /// Create a data channel announced in the next offer
+    ///
+    /// Unordered channels are also unreliable: a lost message is never
+    /// retransmitted.
+    pub async fn create_data_channel(
+        peer_connection: &Arc<RTCPeerConnection>,
+        label: &str,
+        ordered: bool,
+    ) -> Result<Arc<RTCDataChannel>> {
+        let init = RTCDataChannelInit {
+            ordered: Some(ordered),
+            max_retransmits: (!ordered).then_some(0),
+            ..Default::default()
+        };
+        Ok(peer_connection.create_data_channel(label, Some(init)).await?)
+    }
+    
     /// Create SDP offer
+    ///
+    /// Candidates are not trickled, so the offer is returned once ICE
+    /// gathering has finished and carries all of them.
     pub async fn create_offer(
         peer_connection: &Arc<RTCPeerConnection>,
     ) -> Result<webrtc::peer_connection::sdp::session_description::RTCSessionDescription> {
         let offer = peer_connection.create_offer(None).await?;
+        let mut gathered = peer_connection.gathering_complete_promise().await;
+        peer_connection.set_local_description(offer).await?;
+        let _ = gathered.recv().await;
+        peer_connection
+            .local_description()
+            .await
+            .context("Peer connection has no local description")
+    }
+    
+    /// Create an SDP offer that restarts ICE
+    ///
+    /// The offer carries new ICE credentials, so the client gathers
+    /// candidates afresh, as after moving to another network.
+    pub async fn create_restart_offer(
+        peer_connection: &Arc<RTCPeerConnection>,
+    ) -> Result<webrtc::peer_connection::sdp::session_description::RTCSessionDescription> {
+        let options = RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        };
+        let offer = peer_connection.create_offer(Some(options)).await?;
         peer_connection.set_local_description(offer.clone()).await?;
         Ok(offer)
     }
@@ -131,13 +654,238 @@ impl WebRtcServer {
         peer_connection.set_remote_description(answer).await?;
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     
-    /// Add ICE candidate
-    pub async fn add_ice_candidate(
-        peer_connection: &Arc<RTCPeerConnection>,
-        candidate: webrtc::ice_transport::ice_candidate::RTCIceCandidateInit,
-    ) -> Result<()> {
-        peer_connection.add_ice_candidate(candidate).await?;
-        Ok(())
+    fn turn(username: Option<&str>, credential: Option<&str>) -> IceServerConfig {
+        IceServerConfig {
+            urls: vec!["turn:turn.example.com:3478?transport=udp".into()],
+            username: username.map(String::from),
+            credential: credential.map(String::from),
+            credential_type: IceCredentialType::Password,
+        }
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_ice_servers_are_validated_and_reported_without_credentials() {
+        let servers = IceConfig::parse_servers(
+            r#"[
+                {"urls": ["stun:stun.example.com:3478"]},
+                {"urls": ["turn:turn.example.com:3478?transport=udp"], "username": "venue", "credential": "secret"}
+            ]"#,
+        )
+        .unwrap();
+        let config = IceConfig {
+            servers,
+            host_only: false,
+        };
+        config.validate().unwrap();
+        assert_eq!(config.rtc_servers()[1].credential, "secret");
+        
+        let summary = config.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[1].username.as_deref(), Some("venue"));
+        assert!(summary[1].has_credential && !summary[0].has_credential);
+        assert!(!serde_json::to_string(&summary).unwrap().contains("secret"));
+        
+        // LAN-only deployments contact no server
+        let host_only = IceConfig {
+            host_only: true,
+            ..config
+        };
+        assert!(host_only.rtc_servers().is_empty() && host_only.summary().is_empty());
+        
+        let invalid = |servers| IceConfig { servers, host_only: false }.validate().is_err();
+        assert!(invalid(vec![IceServerConfig {
+            urls: vec!["http://stun.example.com".into()],
+            ..turn(None, None)
+        }]));
+        assert!(invalid(vec![turn(Some("venue"), None)]));
+        assert!(invalid(vec![IceServerConfig { urls: vec![], ..turn(Some("u"), Some("p")) }]));
+        assert!(IceConfig::parse_servers(r#"{"urls": "stun:x"}"#).is_err());
+        IceConfig::default().validate().unwrap();
+    }
+    
+    /// Ports of the UDP candidates a new peer connection gathers
+    async fn candidate_ports(server: &WebRtcServer) -> Vec<u16> {
+        let pc = server.create_peer_connection().await.unwrap();
+        WebRtcServer::create_data_channel(&pc, "control", true).await.unwrap();
+        let mut gathered = pc.gathering_complete_promise().await;
+        WebRtcServer::create_offer(&pc).await.unwrap();
+        gathered.recv().await;
+        let sdp = pc.local_description().await.unwrap().sdp;
+        pc.close().await.unwrap();
+        // a=candidate:<foundation> <component> udp <priority> <address> <port> typ host
+        sdp.lines()
+            .filter_map(|line| line.strip_prefix("a=candidate:"))
+            .map(|candidate| candidate.split_whitespace().collect::<Vec<_>>())
+            .filter(|fields| fields[2].eq_ignore_ascii_case("udp"))
+            .map(|fields| fields[5].parse().unwrap())
+            .collect()
+    }
+    
+    #[tokio::test]
+    async fn test_peer_connections_gather_on_configured_udp_ports() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let free_port = || std::net::UdpSocket::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port();
+        
+        let base = free_port().clamp(1024, 65_000);
+        let range = PortRange { min: base, max: base + 20 };
+        let udp = UdpPortConfig {
+            port_range: Some(range),
+            mux_port: None,
+        };
+        udp.validate().unwrap();
+        let server = WebRtcServer::with_network(&ice, &WebRtcCodec::ALL, &udp, &LossRecoveryConfig::default()).unwrap();
+        let ports = candidate_ports(&server).await;
+        assert!(!ports.is_empty());
+        assert!(ports.iter().all(|port| (range.min..=range.max).contains(port)), "{:?} outside {:?}", ports, range);
+        
+        // Every connection shares the one muxed port
+        let port = free_port();
+        let udp = UdpPortConfig {
+            port_range: None,
+            mux_port: Some(port),
+        };
+        let server = WebRtcServer::with_network(&ice, &WebRtcCodec::ALL, &udp, &LossRecoveryConfig::default()).unwrap();
+        for _ in 0..2 {
+            let ports = candidate_ports(&server).await;
+            assert!(!ports.is_empty() && ports.iter().all(|p| *p == port), "{:?}", ports);
+        }
+        assert_eq!(udp.to_string(), format!("UDP port {} (shared)", port));
+    }
+    
+    /// Fingerprint a new peer connection's offer carries
+    async fn offered_fingerprint(server: &WebRtcServer) -> String {
+        let pc = server.create_peer_connection().await.unwrap();
+        WebRtcServer::create_data_channel(&pc, "control", true).await.unwrap();
+        let offer = WebRtcServer::create_offer(&pc).await.unwrap();
+        pc.close().await.unwrap();
+        offer
+            .sdp
+            .lines()
+            .find_map(|line| line.strip_prefix("a=fingerprint:"))
+            .unwrap()
+            .to_string()
+    }
+    
+    #[tokio::test]
+    async fn test_persistent_certificate_keeps_the_fingerprint_across_restarts() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let dir = std::env::temp_dir().join(format!("solusync-dtls-{}", uuid::Uuid::new_v4()));
+        let file = crate::media::CertificateFile::new(dir.join("dtls.pem"));
+        let start = || {
+            WebRtcServer::with_codecs(&ice, &WebRtcCodec::ALL).with_certificate(file.load_or_generate().unwrap())
+        };
+        
+        let first = start();
+        let fingerprint = first.fingerprint().unwrap();
+        assert!(fingerprint.starts_with("sha-256 "));
+        assert_eq!(offered_fingerprint(&first).await, fingerprint);
+        assert_eq!(offered_fingerprint(&first).await, fingerprint);
+        
+        // A restart reads the same certificate back
+        let restarted = start();
+        assert_eq!(restarted.fingerprint().unwrap(), fingerprint);
+        assert_eq!(offered_fingerprint(&restarted).await, fingerprint);
+        
+        file.regenerate().unwrap();
+        assert_ne!(start().fingerprint().unwrap(), fingerprint);
+        assert!(WebRtcServer::with_codecs(&ice, &WebRtcCodec::ALL).fingerprint().is_none());
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+    
+    /// Offer of a peer connection with an audio and a video transceiver
+    async fn media_offer(server: &WebRtcServer) -> RTCSessionDescription {
+        let pc = server.create_peer_connection().await.unwrap();
+        for kind in [RTPCodecType::Audio, RTPCodecType::Video] {
+            pc.add_transceiver_from_kind(kind, None).await.unwrap();
+        }
+        let offer = WebRtcServer::create_offer(&pc).await.unwrap();
+        pc.close().await.unwrap();
+        offer
+    }
+    
+    #[tokio::test]
+    async fn test_offers_negotiate_video_nack_and_opus_fec() {
+        let ice = IceConfig {
+            servers: Vec::new(),
+            host_only: true,
+        };
+        let udp = UdpPortConfig::default();
+        let with_recovery = |recovery| WebRtcServer::with_network(&ice, &WebRtcCodec::ALL, &udp, &recovery).unwrap();
+        
+        let server = with_recovery(LossRecoveryConfig::default());
+        let offer = media_offer(&server).await;
+        assert!(offer.sdp.contains("a=fmtp:111 minptime=10;useinbandfec=1\r\n"));
+        let vp8_feedback = format!("a=rtcp-fb:{} ", WebRtcCodec::Vp8.payload_type());
+        let feedback: Vec<&str> = offer
+            .sdp
+            .lines()
+            .filter_map(|line| line.strip_prefix(vp8_feedback.as_str()))
+            .map(str::trim)
+            .collect();
+        assert!(feedback.contains(&"nack") && feedback.contains(&"nack pli"), "{:?}", feedback);
+        assert!(feedback.contains(&"ccm fir"), "{:?}", feedback);
+        
+        // A client answering with Opus and useinbandfec=1 wants FEC
+        let browser = server.create_peer_connection().await.unwrap();
+        browser.set_remote_description(offer).await.unwrap();
+        let answer = browser.create_answer(None).await.unwrap();
+        browser.close().await.unwrap();
+        assert!(server.answer_accepts_opus_fec(&answer).unwrap());
+        let declined = RTCSessionDescription::answer(answer.sdp.replace(";useinbandfec=1", "")).unwrap();
+        assert!(!server.answer_accepts_opus_fec(&declined).unwrap());
+        
+        let plain = with_recovery(LossRecoveryConfig {
+            video_nack: false,
+            opus_fec: false,
+            ..Default::default()
+        });
+        let offer = media_offer(&plain).await;
+        assert!(offer.sdp.contains("a=fmtp:111 minptime=10\r\n"));
+        assert!(!offer.sdp.contains(" nack"));
+        assert!(!plain.answer_accepts_opus_fec(&answer).unwrap());
+    }
+    
+    #[test]
+    fn test_udp_port_ranges_are_validated() {
+        assert_eq!(PortRange::parse(" 50000 - 50100").unwrap(), PortRange { min: 50000, max: 50100 });
+        assert!(PortRange::parse("50000").is_err());
+        assert!(PortRange::parse("50000-70000").is_err());
+        
+        let config = |port_range, mux_port| UdpPortConfig { port_range, mux_port };
+        let range = |min, max| Some(PortRange { min, max });
+        assert!(config(range(50000, 50100), None).validate().is_ok());
+        assert!(config(range(50100, 50000), None).validate().is_err());
+        assert!(config(range(0, 100), None).validate().is_err());
+        assert!(config(None, Some(0)).validate().is_err());
+        assert!(config(range(50000, 50100), Some(3478)).validate().is_err());
+        assert_eq!(config(range(50000, 50100), None).to_string(), "UDP ports 50000-50100");
+        assert_eq!(UdpPortConfig::default().to_string(), "any UDP port");
+    }
+    
+    #[test]
+    fn test_codec_lists_need_an_audio_codec() {
+        let codecs = WebRtcCodec::parse_list(" PCMU, vp8,pcmu ,").unwrap();
+        assert_eq!(codecs, [WebRtcCodec::Pcmu, WebRtcCodec::Vp8]);
+        assert!(WebRtcCodec::parse_list("h264,vp8").is_err());
+        assert!(WebRtcCodec::parse_list("opus,av1").is_err());
+        
+        assert_eq!(WebRtcCodec::from_mime_type("video/vp8"), Some(WebRtcCodec::Vp8));
+        assert_eq!(WebRtcCodec::from_mime_type("audio/PCMA"), Some(WebRtcCodec::Pcma));
+        let server = WebRtcServer::with_codecs(&IceConfig::default(), &codecs);
+        assert_eq!(server.default_codec(RTPCodecType::Audio), Some(WebRtcCodec::Pcmu));
+        assert_eq!(server.default_codec(RTPCodecType::Video), Some(WebRtcCodec::Vp8));
+    }
+}
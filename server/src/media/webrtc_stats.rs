@@ -0,0 +1,139 @@
+use serde::Serialize;
+use uuid::Uuid;
+use webrtc::{
+    ice::candidate::CandidatePairState,
+    stats::{ICECandidateStats, StatsReport, StatsReportType},
+};
+
+use super::MediaCodecs;
+
+/// One end of an ICE candidate pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CandidateSummary {
+    /// `host`, `srflx`, `prflx` or `relay`
+    pub candidate_type: String,
+    pub address: String,
+    pub port: u16,
+
+    /// Network of the candidate, e.g. `udp4`
+    pub network: String,
+}
+
+impl From<&ICECandidateStats> for CandidateSummary {
+    fn from(stats: &ICECandidateStats) -> Self {
+        Self {
+            candidate_type: stats.candidate_type.to_string(),
+            address: stats.ip.clone(),
+            port: stats.port,
+            network: stats.network_type.to_string(),
+        }
+    }
+}
+
+/// Candidate pair ICE selected to carry a peer connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelectedCandidatePair {
+    pub local: CandidateSummary,
+    pub remote: CandidateSummary,
+}
+
+/// Snapshot of a client's peer connection from WebRTC's statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct WebRtcStats {
+    pub client_id: Uuid,
+
+    /// WebRTC peer connection state, e.g. `connected`
+    pub connection_state: String,
+
+    /// Codecs the client's answer settled on; none before it is applied
+    pub negotiated_codecs: MediaCodecs,
+
+    /// None until ICE has succeeded on a pair
+    pub selected_candidate_pair: Option<SelectedCandidatePair>,
+
+    /// Bytes over the connection's transport, DTLS and SCTP included
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+
+    /// RTP packets sent on the client's tracks and received from it
+    pub packets_sent: u64,
+    pub packets_received: u64,
+
+    /// Packets the client reported lost in RTCP receiver reports
+    pub packets_lost: i64,
+
+    /// Fraction of packets lost in the latest receiver reports, the worst
+    /// of the client's tracks
+    pub fraction_lost: Option<f64>,
+
+    /// Round trip of the latest ICE check on the selected pair
+    pub current_rtt_ms: Option<f64>,
+
+    /// Estimated outgoing bitrate the selected pair allows, in bits per
+    /// second
+    pub available_outgoing_bitrate: Option<f64>,
+
+    /// Time the statistics were taken, in seconds since the Unix epoch
+    pub collected_at: f64,
+}
+
+impl WebRtcStats {
+    /// Summarize a peer connection's statistics report
+    pub fn from_report(
+        client_id: Uuid,
+        connection_state: String,
+        negotiated_codecs: MediaCodecs,
+        report: &StatsReport,
+        collected_at: f64,
+    ) -> Self {
+        let mut stats = Self {
+            client_id,
+            connection_state,
+            negotiated_codecs,
+            selected_candidate_pair: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            packets_sent: 0,
+            packets_received: 0,
+            packets_lost: 0,
+            fraction_lost: None,
+            current_rtt_ms: None,
+            available_outgoing_bitrate: None,
+            collected_at,
+        };
+
+        let candidate = |id: &str| match report.reports.get(id) {
+            Some(StatsReportType::LocalCandidate(candidate) | StatsReportType::RemoteCandidate(candidate)) => {
+                Some(CandidateSummary::from(candidate))
+            }
+            _ => None,
+        };
+        for entry in report.reports.values() {
+            match entry {
+                // A nominated pair that succeeded is the one in use
+                StatsReportType::CandidatePair(pair)
+                    if pair.nominated && pair.state == CandidatePairState::Succeeded =>
+                {
+                    stats.selected_candidate_pair = candidate(&pair.local_candidate_id)
+                        .zip(candidate(&pair.remote_candidate_id))
+                        .map(|(local, remote)| SelectedCandidatePair { local, remote });
+                    stats.current_rtt_ms = Some(pair.current_round_trip_time * 1000.0);
+                    stats.available_outgoing_bitrate =
+                        (pair.available_outgoing_bitrate > 0.0).then_some(pair.available_outgoing_bitrate);
+                }
+                StatsReportType::Transport(transport) => {
+                    stats.bytes_sent += transport.bytes_sent as u64;
+                    stats.bytes_received += transport.bytes_received as u64;
+                }
+                StatsReportType::OutboundRTP(outbound) => stats.packets_sent += outbound.packets_sent,
+                StatsReportType::InboundRTP(inbound) => stats.packets_received += inbound.packets_received,
+                StatsReportType::RemoteInboundRTP(remote) => {
+                    stats.packets_lost += remote.packets_lost;
+                    stats.fraction_lost = Some(stats.fraction_lost.unwrap_or(0.0).max(remote.fraction_lost));
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Key of the stream playing `track_id` in `zone`
+///
+/// Each zone plays a track on its own stream, so zones playing the same
+/// track at different times never share a source or playback driver.
+pub fn stream_key(track_id: &str, zone: Option<&str>) -> String {
+    match zone {
+        Some(zone) => format!("{}@{}", track_id, zone),
+        None => track_id.to_string(),
+    }
+}
+
+/// Zone membership reported by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct ZoneStatus {
+    pub zone: String,
+    pub clients: Vec<Uuid>,
+}
+
+/// Zone a device was last put in, saved across restarts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceZone {
+    pub device_id: Uuid,
+    pub zone: String,
+}
+
+/// Assignment of clients to zones
+///
+/// A client is in at most one zone; joining another moves it. The zone of
+/// each client's device is remembered separately, so that the device is
+/// put back in it when it connects again without naming a zone.
+#[derive(Debug, Default)]
+pub struct ZoneMap {
+    zones: HashMap<Uuid, String>,
+    devices: HashMap<Uuid, String>,
+}
+
+impl ZoneMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move a client into `zone`, returning the zone it left
+    pub fn join(&mut self, client_id: Uuid, zone: String) -> Option<String> {
+        self.zones.insert(client_id, zone)
+    }
+
+    /// Remove a client from its zone, returning the zone it left
+    pub fn leave(&mut self, client_id: Uuid) -> Option<String> {
+        self.zones.remove(&client_id)
+    }
+
+    /// Remember `zone` as a device's zone, or forget it on `None`;
+    /// returns whether that changed anything
+    pub fn assign_device(&mut self, device_id: Uuid, zone: Option<String>) -> bool {
+        match zone {
+            Some(zone) => self.devices.insert(device_id, zone.clone()) != Some(zone),
+            None => self.devices.remove(&device_id).is_some(),
+        }
+    }
+
+    pub fn device_zone(&self, device_id: Uuid) -> Option<&str> {
+        self.devices.get(&device_id).map(String::as_str)
+    }
+
+    /// Zones of all devices, sorted by device
+    pub fn device_zones(&self) -> Vec<DeviceZone> {
+        let mut devices: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(device_id, zone)| DeviceZone {
+                device_id: *device_id,
+                zone: zone.clone(),
+            })
+            .collect();
+        devices.sort_by_key(|device| device.device_id);
+        devices
+    }
+
+    pub fn zone_of(&self, client_id: Uuid) -> Option<&str> {
+        self.zones.get(&client_id).map(String::as_str)
+    }
+
+    /// Clients in `zone`, sorted
+    pub fn members(&self, zone: &str) -> Vec<Uuid> {
+        let mut members: Vec<_> = self
+            .zones
+            .iter()
+            .filter(|(_, z)| *z == zone)
+            .map(|(id, _)| *id)
+            .collect();
+        members.sort();
+        members
+    }
+
+    pub fn status(&self, zone: &str) -> ZoneStatus {
+        ZoneStatus {
+            zone: zone.to_string(),
+            clients: self.members(zone),
+        }
+    }
+}
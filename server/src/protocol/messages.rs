@@ -10,22 +10,68 @@ pub enum Message {
     // Clock synchronization
     ClockSync(ClockSyncMessage),
     ClockSyncResponse(ClockSyncResponse),
+    ClockSynced(ClockSyncedMessage),
     
     // Media control
     MediaControl(MediaControlMessage),
     MediaData(MediaDataMessage),
+    TranscodeRequest(TranscodeRequestMessage),
+    KeyframeRequest(KeyframeRequestMessage),
+    Concealment(ConcealmentMessage),
+    PlaybackProgress(PlaybackProgressMessage),
+    BufferReport(BufferReportMessage),
+    BufferReportAck(BufferReportAckMessage),
+    RateAdjust(RateAdjustMessage),
     
     // Cluster management
     NodeAnnounce(NodeAnnounceMessage),
+    NodeChallenge(NodeChallengeMessage),
+    NodeChallengeResponse(NodeChallengeResponseMessage),
     NodeStatus(NodeStatusMessage),
     MasterElection(MasterElectionMessage),
     
+    // WebRTC signaling
+    SdpOffer(SdpOfferMessage),
+    SdpAnswer(SdpAnswerMessage),
+    RtpClock(RtpClockMessage),
+    
     // Connection
     Hello(HelloMessage),
     Heartbeat(HeartbeatMessage),
     Error(ErrorMessage),
 }
 
+impl Message {
+    /// Header common to every message
+    pub fn header(&self) -> &MessageHeader {
+        match self {
+            Self::ClockSync(m) => &m.header,
+            Self::ClockSyncResponse(m) => &m.header,
+            Self::ClockSynced(m) => &m.header,
+            Self::MediaControl(m) => &m.header,
+            Self::MediaData(m) => &m.header,
+            Self::TranscodeRequest(m) => &m.header,
+            Self::KeyframeRequest(m) => &m.header,
+            Self::Concealment(m) => &m.header,
+            Self::PlaybackProgress(m) => &m.header,
+            Self::BufferReport(m) => &m.header,
+            Self::BufferReportAck(m) => &m.header,
+            Self::RateAdjust(m) => &m.header,
+            Self::NodeAnnounce(m) => &m.header,
+            Self::NodeChallenge(m) => &m.header,
+            Self::NodeChallengeResponse(m) => &m.header,
+            Self::NodeStatus(m) => &m.header,
+            Self::MasterElection(m) => &m.header,
+            Self::SdpOffer(m) => &m.header,
+            Self::SdpAnswer(m) => &m.header,
+            Self::RtpClock(m) => &m.header,
+            Self::Hello(m) => &m.header,
+            Self::Heartbeat(m) => &m.header,
+            Self::Error(m) => &m.header,
+        }
+    }
+}
+
 /// Initial handshake message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HelloMessage {
@@ -34,6 +80,16 @@ pub struct HelloMessage {
     pub capabilities: Vec<String>,
     pub node_type: NodeType,
     pub auth_token: Option<String>,
+    #[serde(default)]
+    pub zone: Option<String>, // Zone to join on connect
+    #[serde(default)]
+    pub session_token: Option<String>, // Issued in the server's hello; sent back to resume after a reconnect
+    #[serde(default)]
+    pub min_latency_ms: Option<u64>, // Overrides the server's minimum buffer latency for this client
+    #[serde(default)]
+    pub max_acceptable_latency_ms: Option<u64>, // Overrides the server's maximum buffer latency
+    #[serde(default)]
+    pub ingest_token: Option<String>, // Grants the publisher role when the server requires ingest auth
 }
 
 /// Clock synchronization request
@@ -52,6 +108,16 @@ pub struct ClockSyncResponse {
     pub t3: f64, // Server timestamp when sending response
 }
 
+/// Sent when the client's clock offset converges, and again with `synced`
+/// false if it diverges so the client can hold synchronized playback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSyncedMessage {
+    pub header: MessageHeader,
+    pub synced: bool,
+    pub offset_ms: f64, // Client clock minus server clock
+    pub offset_stddev_ms: f64, // Spread of the recent offset samples
+}
+
 /// Media control commands
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MediaControlMessage {
@@ -60,6 +126,8 @@ pub struct MediaControlMessage {
     pub track_id: String,
     pub start_at: f64, // Network clock time to start
     pub params: MediaParams,
+    #[serde(default)]
+    pub zone: Option<String>, // Applies only to clients in this zone
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,15 +139,95 @@ pub enum MediaAction {
     Seek,
     Load,
     Unload,
+    Subscribe,
+    Unsubscribe,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// `loop_count` value that repeats a track until it is stopped
+pub const LOOP_FOREVER: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MediaParams {
     pub volume: Option<f32>,
-    pub loop_count: Option<u32>,
+    pub loop_count: Option<u32>, // Additional plays after the first; LOOP_FOREVER repeats indefinitely
     pub fade_in_ms: Option<u32>,
     pub fade_out_ms: Option<u32>,
-    pub seek_position: Option<f64>,
+    pub seek_position: Option<f64>, // Seek: target; Play: where a stopped track starts
+    pub source: Option<String>, // File path to open for Load
+    #[serde(default)]
+    pub stop_at: Option<f64>, // Play: network time at which to stop
+    #[serde(default)]
+    pub max_duration_ms: Option<u64>, // Play: stop after playing this long
+}
+
+/// Periodic report of where a playing track is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackProgressMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub zone: Option<String>,
+    pub position: f64, // Track position in seconds at `at`
+    pub at: f64,       // Network clock time of the report
+}
+
+/// Periodic report of a client's audio output buffer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferReportMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub underruns: u32,         // Output underruns since the previous report
+    pub overruns: u32,          // Output overruns since the previous report
+    pub occupancy_ms: f64,      // Audio queued for output
+    pub playout_delay_ms: f64,  // Delay from presentation time to the speaker
+}
+
+/// Buffer state after a client's buffer report was applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferReportAckMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub target_latency_ms: u32, // Latency to schedule playout with
+    pub jitter_buffer_ms: u32,
+}
+
+/// Playback rate a client should play at to bring its buffer back to the
+/// target latency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateAdjustMessage {
+    pub header: MessageHeader,
+    pub playback_rate: f64, // 1.0 clears an earlier adjustment
+    pub target_latency_ms: u32,
+}
+
+/// SDP offer the server makes on a client's peer connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdpOfferMessage {
+    pub header: MessageHeader,
+    pub sdp: String,
+    #[serde(default)]
+    pub ice_restart: bool, // New ICE credentials; the client gathers candidates afresh
+}
+
+/// Client's SDP answer to the server's latest offer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdpAnswerMessage {
+    pub header: MessageHeader,
+    pub sdp: String,
+}
+
+/// Anchors the RTP timestamps of one of a client's media tracks to network
+/// time, sent when the track starts or its sender is replaced
+///
+/// RTP timestamp `rtp_timestamp` is presented at `network_time`, and each
+/// tick of `clock_rate` after it, wrapping at 2^32, one sample later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RtpClockMessage {
+    pub header: MessageHeader,
+    pub kind: String, // Track of the peer connection, "audio" or "video"
+    pub codec: String,
+    pub clock_rate: u32,
+    pub rtp_timestamp: u32,
+    pub network_time: f64,
 }
 
 /// Media data chunk
@@ -93,6 +241,70 @@ pub struct MediaDataMessage {
     pub data: Vec<u8>,     // Encoded media data
     pub codec: String,     // e.g., "opus", "pcm16", "h264"
     pub is_keyframe: bool,
+    #[serde(default)]
+    pub compression: Option<Compression>, // Transport compression applied to `data`
+}
+
+/// Request to have a stream the client cannot decode transcoded for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscodeRequestMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub target_codec: String, // A codec the client decodes, e.g. "pcmu"
+}
+
+/// Asks the producer of a live video track to send a keyframe next, so
+/// subscribers that joined mid-GOP or lost packets can decode again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyframeRequestMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub reason: KeyframeRequestReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyframeRequestReason {
+    Pli,       // A subscriber lost packets it cannot decode without
+    Fir,       // A subscriber asked for a full intra refresh
+    Subscribe, // A subscriber joined and has no keyframe yet
+}
+
+/// Transport compression of `media_data` payloads, negotiated through
+/// `compression:<name>` capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    /// Algorithms in order of preference
+    #[cfg(test)]
+    pub const ALL: [Self; 1] = [Self::Zstd];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Capability a peer advertises in its hello to accept the algorithm
+    pub fn capability(self) -> String {
+        format!("compression:{}", self.name())
+    }
+}
+
+/// Tells a client that audio frames of a track never arrived, so its
+/// decoder conceals them instead of leaving a gap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcealmentMessage {
+    pub header: MessageHeader,
+    pub track_id: String,
+    pub first_sequence: u64, // Sequence number of the first missing frame
+    pub missing: u64,        // Consecutive frames missing
+    pub timestamp: f64,      // Presentation time of the first missing frame
+    pub duration: f64,       // Seconds of audio to conceal
 }
 
 /// Node announcement for cluster discovery
@@ -105,6 +317,20 @@ pub struct NodeAnnounceMessage {
     pub public_key: Option<Vec<u8>>,
 }
 
+/// Nonce a node announcing itself as Master or Replica must sign
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeChallengeMessage {
+    pub header: MessageHeader,
+    pub nonce: Vec<u8>,
+}
+
+/// Ed25519 signature over a challenge nonce with the announced key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeChallengeResponseMessage {
+    pub header: MessageHeader,
+    pub signature: Vec<u8>,
+}
+
 /// Periodic node status update
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeStatusMessage {
@@ -118,6 +344,10 @@ pub struct NodeStatusMessage {
     pub avg_rtt_ms: f64,
     pub packet_loss_percent: f64,
     pub uptime_seconds: u64,
+    #[serde(default)]
+    pub drift_ppm: Option<f64>, // Drift of the node's clock against the network clock
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>, // Ed25519 signature by the sending node
 }
 
 /// Master election message
@@ -125,8 +355,14 @@ pub struct NodeStatusMessage {
 pub struct MasterElectionMessage {
     pub header: MessageHeader,
     pub election_id: Uuid,
+    #[serde(default)]
+    pub epoch: u64, // Raised each time the election restarts, e.g. after a split brain
     pub candidate_score: f64,
     pub current_master: Option<Uuid>,
+    #[serde(default)]
+    pub demoted: Option<Uuid>, // Master stepping down, which may not stand again for now
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>, // Ed25519 signature by the sending node
 }
 
 /// Heartbeat to keep connection alive
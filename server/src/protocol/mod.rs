@@ -2,8 +2,10 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 pub mod messages;
+pub mod time;
 
 pub use messages::*;
+pub use time::get_current_time;
 
 /// Node types in the SOLUSync-X cluster
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -36,6 +38,26 @@ impl NetworkQuality {
         }
     }
 
+    /// Next quality level down, saturating at `Critical`
+    pub fn downgrade(self) -> Self {
+        match self {
+            Self::Excellent => Self::Good,
+            Self::Good => Self::Fair,
+            Self::Fair => Self::Poor,
+            Self::Poor | Self::Critical => Self::Critical,
+        }
+    }
+
+    /// Next quality level up, saturating at `Excellent`
+    pub fn upgrade(self) -> Self {
+        match self {
+            Self::Critical => Self::Poor,
+            Self::Poor => Self::Fair,
+            Self::Fair => Self::Good,
+            Self::Good | Self::Excellent => Self::Excellent,
+        }
+    }
+
     /// Get recommended future buffer size for this quality
     pub fn recommended_buffer_ms(&self) -> u64 {
         match self {
@@ -55,6 +77,11 @@ pub struct MessageHeader {
     pub timestamp: f64,
     pub node_id: Uuid,
     pub sequence: u64,
+    
+    /// ID of the HTTP request the message results from, carried into the
+    /// logs of whatever it leads to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl MessageHeader {
@@ -64,16 +91,13 @@ impl MessageHeader {
             timestamp: get_current_time(),
             node_id,
             sequence,
+            request_id: None,
         }
     }
-}
-
-/// Get current time in seconds with microsecond precision
-pub fn get_current_time() -> f64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
     
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs_f64()
-}
\ No newline at end of file
+    /// Mark the message as resulting from an HTTP request
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+}
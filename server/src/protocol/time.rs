@@ -0,0 +1,113 @@
+use std::{
+    sync::OnceLock,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Seconds since the Unix epoch that only ever move forward
+///
+/// The wall clock is read once, when the clock is anchored, and the time
+/// since then is taken from the monotonic clock. NTP steps and VM pauses
+/// that move the wall clock backward or forward later do not show; only
+/// the slewing both clocks share does.
+#[derive(Debug, Clone, Copy)]
+pub struct EpochClock {
+    anchor: Instant,
+    anchor_epoch: f64,
+}
+
+impl EpochClock {
+    /// Clock anchored to the wall clock now
+    pub fn new() -> Self {
+        Self::anchored(Instant::now(), SystemTime::now())
+    }
+
+    /// Clock reading `wall` at monotonic time `anchor`
+    pub fn anchored(anchor: Instant, wall: SystemTime) -> Self {
+        Self {
+            anchor,
+            anchor_epoch: epoch_seconds(wall),
+        }
+    }
+
+    pub fn now(&self) -> f64 {
+        self.at(Instant::now())
+    }
+
+    /// Epoch time of monotonic time `instant`; instants before the anchor
+    /// read as the anchor
+    pub fn at(&self, instant: Instant) -> f64 {
+        self.anchor_epoch + instant.saturating_duration_since(self.anchor).as_secs_f64()
+    }
+}
+
+impl Default for EpochClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Seconds since the Unix epoch of a wall clock time, negative before it
+pub fn epoch_seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+/// The wall clock, in seconds since the Unix epoch
+///
+/// For comparing with other hosts' clocks; it can jump backward, so time
+/// kept by this server comes from `get_current_time`.
+pub fn wall_clock_time() -> f64 {
+    epoch_seconds(SystemTime::now())
+}
+
+/// Clock of the process, anchored at its first reading
+fn process_clock() -> &'static EpochClock {
+    static CLOCK: OnceLock<EpochClock> = OnceLock::new();
+    CLOCK.get_or_init(EpochClock::new)
+}
+
+/// Get current time in seconds with microsecond precision
+///
+/// Epoch time that never goes backward, whatever the wall clock does; see
+/// `EpochClock`.
+pub fn get_current_time() -> f64 {
+    process_clock().now()
+}
+
+/// Anchor the process clock to the wall clock, if nothing has read it yet
+pub fn init() {
+    process_clock();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wall_clock_steps_do_not_move_the_clock() {
+        let start = Instant::now();
+        let wall = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = EpochClock::anchored(start, wall);
+        assert_eq!(clock.at(start), 1_700_000_000.0);
+
+        // The wall clock steps back 30s a second later, and then before
+        // the epoch altogether
+        let later = start + Duration::from_secs(1);
+        let stepped = wall + Duration::from_secs(1) - Duration::from_secs(30);
+        assert_eq!(epoch_seconds(stepped) - clock.at(later), -30.0);
+        assert_eq!(epoch_seconds(UNIX_EPOCH - Duration::from_millis(1500)), -1.5);
+
+        let readings: Vec<f64> = (0..5).map(|i| clock.at(start + Duration::from_millis(i * 10))).collect();
+        assert!(readings.windows(2).all(|pair| pair[1] > pair[0]), "{:?}", readings);
+        assert_eq!(clock.at(later), 1_700_000_001.0);
+        if let Some(earlier) = start.checked_sub(Duration::from_secs(1)) {
+            assert_eq!(clock.at(earlier), 1_700_000_000.0);
+        }
+
+        let first = get_current_time();
+        assert!(get_current_time() >= first);
+    }
+}
@@ -0,0 +1,117 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{convert::Infallible, fmt};
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header a request's ID is read from and echoed in
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// ID correlating an HTTP request with the log lines and media commands it
+/// leads to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The ID a client sent in `X-Request-Id`, or a new one
+    ///
+    /// Only printable ASCII without spaces is taken from the client, so an
+    /// ID cannot forge log lines.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let sent = headers
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .filter(|id| id.bytes().all(|b| b.is_ascii_graphic()));
+        match sent {
+            Some(id) => Self(id.to_string()),
+            None => Self(Uuid::new_v4().to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<RequestId> for String {
+    fn from(id: RequestId) -> Self {
+        id.0
+    }
+}
+
+/// The ID `propagate_request_id` gave the request, or one of its own when
+/// the middleware is not in front of the handler
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<RequestId>() {
+            Some(id) => Ok(id.clone()),
+            None => Ok(Self::from_headers(&parts.headers)),
+        }
+    }
+}
+
+/// Give every request an ID, logging its handling in a span carrying the
+/// ID and echoing it in the response's `X-Request-Id`
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = RequestId::from_headers(request.headers());
+    request.extensions_mut().insert(id.clone());
+
+    let span = tracing::info_span!("request", request_id = %id);
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn request(id: Option<&str>) -> Request {
+        let mut builder = Request::builder().uri("/api/status");
+        if let Some(id) = id {
+            builder = builder.header(&REQUEST_ID_HEADER, id);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_are_echoed_or_generated() {
+        let app = Router::new()
+            .route("/api/status", get(|id: RequestId| async move { id.to_string() }))
+            .layer(middleware::from_fn(propagate_request_id));
+        let response = app.clone().oneshot(request(Some("play-42"))).await.unwrap();
+        assert_eq!(response.headers()[&REQUEST_ID_HEADER], "play-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"play-42");
+
+        // IDs that could garble logs are replaced
+        let long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for sent in [None, Some(""), Some("two words"), Some(long.as_str())] {
+            let response = app.clone().oneshot(request(sent)).await.unwrap();
+            let id = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+            assert!(Uuid::parse_str(id).is_ok(), "{:?} gave {}", sent, id);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use axum::{
+    extract::Request,
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::path::Path;
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Path prefixes owned by the server, never answered with the client app
+const RESERVED_PREFIXES: [&str; 2] = ["/api", "/ws"];
+
+/// Web client files, served for every request no route matched
+///
+/// Paths naming no file get `index.html`, so client-side routes of a
+/// single-page app load the app instead of a 404. API paths and requests
+/// other than `GET`/`HEAD` are left as 404s.
+#[derive(Clone)]
+pub struct StaticFiles {
+    /// `None` when the directory does not exist
+    files: Option<ServeDir<ServeFile>>,
+}
+
+impl StaticFiles {
+    /// Serve files from `dir`, warning if it cannot serve the client app
+    pub fn new(dir: &Path) -> Self {
+        if !dir.is_dir() {
+            tracing::warn!(
+                "Static directory {} not found; only the API is served (set SOLUSYNC_STATIC_DIR)",
+                dir.display()
+            );
+            return Self { files: None };
+        }
+
+        let index = dir.join("index.html");
+        if !index.is_file() {
+            tracing::warn!("{} not found; client routes will return 404", index.display());
+        }
+        Self {
+            files: Some(ServeDir::new(dir).fallback(ServeFile::new(index))),
+        }
+    }
+
+    /// Answer a request no route matched
+    pub async fn serve(self, request: Request) -> Response {
+        let Some(files) = self.files else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if !matches!(*request.method(), Method::GET | Method::HEAD) || is_reserved(request.uri().path()) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+
+        match files.oneshot(request).await {
+            Ok(response) => response.into_response(),
+            Err(never) => match never {},
+        }
+    }
+}
+
+/// Whether `path` belongs to the API or WebSocket endpoints
+fn is_reserved(path: &str) -> bool {
+    RESERVED_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+
+    fn app(static_files: StaticFiles) -> Router {
+        Router::new()
+            .route("/api/status", get(|| async { "OK" }))
+            .fallback(move |request: Request| static_files.clone().serve(request))
+    }
+
+    async fn get_body(app: Router, method: Method, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_client_route_serves_index() {
+        let dir = std::env::temp_dir().join(format!("solusync-public-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>app</html>").unwrap();
+        std::fs::write(dir.join("monitor.html"), "<html>monitor</html>").unwrap();
+        let static_files = StaticFiles::new(&dir);
+
+        let cases = [
+            (Method::GET, "/zones/hall", StatusCode::OK, "<html>app</html>"),
+            (Method::GET, "/", StatusCode::OK, "<html>app</html>"),
+            (Method::GET, "/monitor.html", StatusCode::OK, "<html>monitor</html>"),
+            (Method::GET, "/api/status", StatusCode::OK, "OK"),
+            (Method::GET, "/api/unknown", StatusCode::NOT_FOUND, ""),
+            (Method::GET, "/ws/other", StatusCode::NOT_FOUND, ""),
+            (Method::POST, "/zones/hall", StatusCode::NOT_FOUND, ""),
+        ];
+        for (method, uri, status, body) in cases {
+            let response = get_body(app(static_files.clone()), method, uri).await;
+            assert_eq!(response, (status, body.to_string()), "{}", uri);
+        }
+
+        // Client routes next to an API-like name still load the app
+        assert!(!is_reserved("/apis"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let missing = StaticFiles::new(&dir);
+        let (status, _) = get_body(app(missing), Method::GET, "/zones/hall").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use std::{net::SocketAddr, path::PathBuf};
+use tracing::info;
+
+/// Certificate and private key for serving HTTPS/WSS
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain
+    pub cert_path: PathBuf,
+
+    /// PEM file with the private key
+    pub key_path: PathBuf,
+}
+
+/// Serve `app` over TLS on an already bound listener
+pub async fn serve(listener: std::net::TcpListener, app: Router, tls: &TlsConfig) -> Result<()> {
+    let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {:?} / key {:?}",
+                tls.cert_path, tls.key_path
+            )
+        })?;
+
+    listener.set_nonblocking(true)?;
+    info!("Serving HTTPS/WSS on {}", listener.local_addr()?);
+
+    axum_server::from_tcp_rustls(listener, rustls_config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+
+    #[tokio::test]
+    async fn test_tls_handshake_with_self_signed_cert() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("solusync-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+        std::fs::write(&tls.cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&tls.key_path, cert.key_pair.serialize_pem()).unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/health", get(|| async { "OK" }));
+        let server_tls = tls.clone();
+        tokio::spawn(async move { serve(listener, app, &server_tls).await });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, tcp).await.unwrap();
+
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("OK"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}